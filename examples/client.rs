@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use tokio_stream::StreamExt;
 
 // Include the proto modules
+#[allow(clippy::enum_variant_names)]
 pub mod proto {
     pub mod common {
         tonic::include_proto!("datasink.common");
@@ -20,7 +21,7 @@ pub mod proto {
 use proto::data_sink_client::DataSinkClient;
 use proto::admin::{CreateTableRequest};
 use proto::crud::{
-    BatchInsertRequest, DeleteRequest, InsertRequest, InsertRow, 
+    BatchInsertRequest, DeleteRequest, InsertRequest, InsertRow, OnConflict,
     QueryRequest, QueryResponse, UpdateRequest, query_response,
 };
 use proto::common::{ColumnDefinition, DataType, Value, value};
@@ -35,6 +36,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let create_table_req = CreateTableRequest {
         table_name: "users".to_string(),
         database: String::new(),  // Use default database
+        unique: vec![],
         columns: vec![
             ColumnDefinition {
                 name: "id".to_string(),
@@ -43,6 +45,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 primary_key: true,
                 unique: true,
                 default_value: String::new(),
+                generated: String::new(),
+                generated_stored: false,
+                collation: String::new(),
+                allowed_values: vec![],
             },
             ColumnDefinition {
                 name: "name".to_string(),
@@ -51,6 +57,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 primary_key: false,
                 unique: false,
                 default_value: String::new(),
+                generated: String::new(),
+                generated_stored: false,
+                collation: String::new(),
+                allowed_values: vec![],
             },
             ColumnDefinition {
                 name: "email".to_string(),
@@ -59,6 +69,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 primary_key: false,
                 unique: true,
                 default_value: String::new(),
+                generated: String::new(),
+                generated_stored: false,
+                collation: String::new(),
+                allowed_values: vec![],
             },
             ColumnDefinition {
                 name: "created_at".to_string(),
@@ -67,6 +81,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 primary_key: false,
                 unique: false,
                 default_value: String::new(),
+                generated: String::new(),
+                generated_stored: false,
+                collation: String::new(),
+                allowed_values: vec![],
             },
         ],
     };
@@ -106,6 +124,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         table_name: "users".to_string(),
         values,
         database: String::new(),  // Use default database
+        on_conflict: OnConflict::Abort as i32,
     };
 
     let response = client.insert(insert_req).await?;
@@ -116,6 +135,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let batch_req = BatchInsertRequest {
         table_name: "users".to_string(),
         database: String::new(),  // Use default database
+        on_conflict: OnConflict::Abort as i32,
         rows: vec![
             InsertRow {
                 values: {
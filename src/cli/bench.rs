@@ -0,0 +1,370 @@
+//! Built-in load generator: `datasink bench`
+//!
+//! Generates synthetic rows matching a table's schema, drives inserts and
+//! point queries against a running server from several concurrent clients,
+//! and reports throughput and latency percentiles.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tabled::{builder::Builder as TableBuilder, settings::Style};
+use tokio_stream::StreamExt;
+
+use super::generate::{fetch_columns, max_rowid, FakeRng, TableColumn};
+use crate::proto::common::{value, Value};
+use crate::proto::crud::{query_response, BatchInsertRequest, InsertRequest, InsertRow, OnConflict, QueryRequest};
+use super::client::{self, Client};
+use super::error::{CliError, ErrorKind};
+use super::output;
+
+/// Settings for a benchmark run
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub table: String,
+    pub rows: usize,
+    pub concurrency: usize,
+    /// Rows per request; 1 uses Insert, larger values use BatchInsert
+    pub batch_size: usize,
+    /// Point queries to run after the inserts (0 skips the query phase)
+    pub queries: usize,
+    pub database: Option<String>,
+}
+
+/// Latencies and counts collected by one phase
+#[derive(Debug, Default)]
+struct PhaseResult {
+    latencies: Vec<Duration>,
+    rows: usize,
+    errors: usize,
+    first_error: Option<String>,
+}
+
+impl PhaseResult {
+    fn merge(&mut self, other: PhaseResult) {
+        self.latencies.extend(other.latencies);
+        self.rows += other.rows;
+        self.errors += other.errors;
+        if self.first_error.is_none() {
+            self.first_error = other.first_error;
+        }
+    }
+
+    fn record_error(&mut self, error: impl ToString) {
+        self.errors += 1;
+        if self.first_error.is_none() {
+            self.first_error = Some(error.to_string());
+        }
+    }
+}
+
+pub async fn run_bench(server_address: String, options: BenchOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.concurrency == 0 || options.batch_size == 0 {
+        return Err(CliError::new(ErrorKind::InvalidInput, "--concurrency and --batch-size must be at least 1").into());
+    }
+
+    let mut client = client::connect(server_address).await?;
+    let database = options.database.clone().unwrap_or_default();
+
+    let columns = fetch_columns(&mut client, &options.table, &database).await?;
+    if columns.is_empty() {
+        let message = format!("Table '{}' has no columns to fill (or does not exist)", options.table);
+        return Err(CliError::new(ErrorKind::NotFound, message).into());
+    }
+    // Start past existing rows so generated values don't collide with UNIQUE columns
+    let first_index = max_rowid(&mut client, &options.table, &database).await? + 1;
+
+    output::progress(&format!(
+        "Benchmarking '{}': {} rows, concurrency {}, batch size {}",
+        options.table, options.rows, options.concurrency, options.batch_size
+    ));
+
+    // Insert phase: rows are split into contiguous ranges, one per worker
+    let started = Instant::now();
+    let mut workers = Vec::new();
+    let per_worker = options.rows.div_ceil(options.concurrency);
+    for worker in 0..options.concurrency {
+        let start = first_index + (worker * per_worker) as i64;
+        let count = per_worker.min(options.rows.saturating_sub(worker * per_worker));
+        let client = client.clone();
+        let columns = columns.clone();
+        let options = options.clone();
+        let database = database.clone();
+        workers.push(tokio::spawn(async move {
+            insert_worker(client, &columns, &options, &database, start, count).await
+        }));
+    }
+    let mut inserts = PhaseResult::default();
+    for worker in workers {
+        inserts.merge(worker.await?);
+    }
+    let insert_elapsed = started.elapsed();
+
+    // Query phase: point lookups by rowid over the rows just inserted
+    let mut queries = PhaseResult::default();
+    let mut query_elapsed = Duration::ZERO;
+    if options.queries > 0 && inserts.rows > 0 {
+        let started = Instant::now();
+        let mut workers = Vec::new();
+        let per_worker = options.queries.div_ceil(options.concurrency);
+        for worker in 0..options.concurrency {
+            let count = per_worker.min(options.queries.saturating_sub(worker * per_worker));
+            let client = client.clone();
+            let table = options.table.clone();
+            let database = database.clone();
+            let range = (first_index, options.rows as i64);
+            workers.push(tokio::spawn(async move {
+                query_worker(client, &table, &database, range, count, worker as u64 + 1).await
+            }));
+        }
+        for worker in workers {
+            queries.merge(worker.await?);
+        }
+        query_elapsed = started.elapsed();
+    }
+
+    if output::enabled() {
+        let mut phases = vec![phase_json("insert", &mut inserts, insert_elapsed)];
+        if options.queries > 0 {
+            phases.push(phase_json("query", &mut queries, query_elapsed));
+        }
+        output::print(&serde_json::json!({
+            "table": options.table,
+            "rows": options.rows,
+            "concurrency": options.concurrency,
+            "batch_size": options.batch_size,
+            "phases": phases,
+        }));
+        return Ok(());
+    }
+
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Phase", "Requests", "Rows", "Errors", "Elapsed", "Throughput", "p50", "p90", "p99", "Max"]);
+    builder.push_record(phase_row("insert", &mut inserts, insert_elapsed, "rows/s"));
+    if options.queries > 0 {
+        builder.push_record(phase_row("query", &mut queries, query_elapsed, "queries/s"));
+    }
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+
+    for (phase, result) in [("insert", &inserts), ("query", &queries)] {
+        if let Some(error) = &result.first_error {
+            eprintln!("First {} error: {}", phase, error);
+        }
+    }
+
+    Ok(())
+}
+
+async fn insert_worker(
+    mut client: Client,
+    columns: &[TableColumn],
+    options: &BenchOptions,
+    database: &str,
+    start: i64,
+    count: usize,
+) -> PhaseResult {
+    let mut result = PhaseResult::default();
+    let mut index = start;
+    let end = start + count as i64;
+
+    while index < end {
+        let batch_end = (index + options.batch_size as i64).min(end);
+        let rows: Vec<HashMap<String, Value>> = (index..batch_end).map(|i| generate_row(columns, i)).collect();
+        let row_count = rows.len();
+
+        let request_started = Instant::now();
+        let outcome = if options.batch_size == 1 {
+            client
+                .insert(InsertRequest {
+                    table_name: options.table.clone(),
+                    values: rows.into_iter().next().unwrap_or_default(),
+                    database: database.to_string(),
+                    on_conflict: OnConflict::Abort as i32,
+                })
+                .await
+                .map(|_| ())
+        } else {
+            client
+                .batch_insert(BatchInsertRequest {
+                    table_name: options.table.clone(),
+                    rows: rows.into_iter().map(|values| InsertRow { values }).collect(),
+                    database: database.to_string(),
+                    on_conflict: OnConflict::Abort as i32,
+                })
+                .await
+                .map(|_| ())
+        };
+        result.latencies.push(request_started.elapsed());
+
+        match outcome {
+            Ok(()) => result.rows += row_count,
+            Err(status) => result.record_error(status.message()),
+        }
+        index = batch_end;
+    }
+
+    result
+}
+
+async fn query_worker(
+    mut client: Client,
+    table: &str,
+    database: &str,
+    (first_index, row_count): (i64, i64),
+    count: usize,
+    seed: u64,
+) -> PhaseResult {
+    let mut result = PhaseResult::default();
+    let mut rng = FakeRng::new(seed);
+
+    for _ in 0..count {
+        let rowid = rng.range(first_index, first_index + row_count.max(1) - 1);
+
+        let request = QueryRequest {
+            sql: format!("SELECT * FROM {} WHERE rowid = ?", table),
+            parameters: HashMap::from([(
+                "rowid".to_string(),
+                Value { value: Some(value::Value::IntValue(rowid)) },
+            )]),
+            database: database.to_string(),
+        };
+
+        let request_started = Instant::now();
+        match client.query(request).await {
+            Ok(response) => {
+                let mut stream = response.into_inner();
+                while let Some(message) = stream.next().await {
+                    match message {
+                        Ok(message) => {
+                            if let Some(query_response::Response::Error(error)) = message.response {
+                                result.record_error(error.message);
+                            }
+                        }
+                        Err(status) => {
+                            result.record_error(status.message());
+                            break;
+                        }
+                    }
+                }
+                result.rows += 1;
+            }
+            Err(status) => result.record_error(status.message()),
+        }
+        result.latencies.push(request_started.elapsed());
+    }
+
+    result
+}
+
+/// A synthetic row; `index` makes every value unique so UNIQUE columns are satisfied
+fn generate_row(columns: &[TableColumn], index: i64) -> HashMap<String, Value> {
+    columns
+        .iter()
+        .map(|column| (column.name.clone(), Value { value: Some(generate_value(column, index)) }))
+        .collect()
+}
+
+fn generate_value(column: &TableColumn, index: i64) -> value::Value {
+    // Type affinity rules, as SQLite applies them to declared column types
+    let sql_type = column.sql_type.as_str();
+    if sql_type.contains("INT") {
+        value::Value::IntValue(index)
+    } else if sql_type.contains("CHAR") || sql_type.contains("CLOB") || sql_type.contains("TEXT") {
+        value::Value::TextValue(format!("{}-{}", column.name, index))
+    } else if sql_type.contains("BLOB") {
+        value::Value::BlobValue(index.to_le_bytes().to_vec())
+    } else if sql_type.contains("REAL") || sql_type.contains("FLOA") || sql_type.contains("DOUB") {
+        value::Value::RealValue(index as f64 + 0.5)
+    } else {
+        value::Value::TextValue(format!("{}-{}", column.name, index))
+    }
+}
+
+fn phase_row(name: &str, result: &mut PhaseResult, elapsed: Duration, unit: &str) -> Vec<String> {
+    result.latencies.sort();
+    let throughput = if elapsed.as_secs_f64() > 0.0 {
+        result.rows as f64 / elapsed.as_secs_f64()
+    } else {
+        0.0
+    };
+    let latency = |pct: f64| format_latency(percentile(&result.latencies, pct));
+
+    vec![
+        name.to_string(),
+        result.latencies.len().to_string(),
+        result.rows.to_string(),
+        result.errors.to_string(),
+        format!("{:.2}s", elapsed.as_secs_f64()),
+        format!("{:.0} {}", throughput, unit),
+        latency(50.0),
+        latency(90.0),
+        latency(99.0),
+        latency(100.0),
+    ]
+}
+
+fn phase_json(name: &str, result: &mut PhaseResult, elapsed: Duration) -> serde_json::Value {
+    result.latencies.sort();
+    let throughput = if elapsed.as_secs_f64() > 0.0 {
+        result.rows as f64 / elapsed.as_secs_f64()
+    } else {
+        0.0
+    };
+    let latency_ms = |pct: f64| percentile(&result.latencies, pct).as_secs_f64() * 1000.0;
+
+    serde_json::json!({
+        "phase": name,
+        "requests": result.latencies.len(),
+        "rows": result.rows,
+        "errors": result.errors,
+        "elapsed_seconds": elapsed.as_secs_f64(),
+        "throughput_per_second": throughput,
+        "p50_ms": latency_ms(50.0),
+        "p90_ms": latency_ms(90.0),
+        "p99_ms": latency_ms(99.0),
+        "max_ms": latency_ms(100.0),
+        "first_error": result.first_error,
+    })
+}
+
+/// Nearest-rank percentile of an ascending list of latencies
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn format_latency(latency: Duration) -> String {
+    format!("{:.2}ms", latency.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&latencies, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_generate_row_matches_column_types() {
+        let columns = vec![
+            TableColumn { name: "count".to_string(), sql_type: "INTEGER".to_string() },
+            TableColumn { name: "email".to_string(), sql_type: "VARCHAR(255)".to_string() },
+            TableColumn { name: "score".to_string(), sql_type: "REAL".to_string() },
+        ];
+
+        let row = generate_row(&columns, 7);
+        assert_eq!(row["count"].value, Some(value::Value::IntValue(7)));
+        assert_eq!(row["email"].value, Some(value::Value::TextValue("email-7".to_string())));
+        assert_eq!(row["score"].value, Some(value::Value::RealValue(7.5)));
+    }
+}
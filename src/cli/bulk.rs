@@ -0,0 +1,635 @@
+//! `datasink insert --file`: bulk insert rows from a JSON array or NDJSON file
+//! (or stdin).
+//!
+//! Every row is checked against the table's columns before anything is sent,
+//! then the rows go to BatchInsert in chunks, one transaction per chunk.
+//!
+//! With `--jobs N` up to N chunks are in flight at once, capped at the
+//! database's connection pool size since each chunk holds a pooled
+//! connection for its transaction. Chunks may then commit in any order, so
+//! rows are not stored in file order (auto-increment ids interleave). Each
+//! chunk commits or rolls back as a whole: after a chunk fails no further
+//! chunks are sent, those already in flight finish, and the error lists which
+//! rows failed and which were never sent.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::stream::{self, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::cli::client::{self, Client};
+use crate::cli::error::{CliError, ErrorKind};
+use crate::cli::{generate, output, read_input, sink};
+use crate::db::traits::DbValue;
+use crate::db::error::UnknownColumn;
+use crate::db::{partition, SqliteDatabase};
+use crate::proto::admin::ServerStatusRequest;
+use crate::proto::common::{value, DataType};
+use crate::proto::crud::{BatchInsertRequest, InsertRow, OnConflict};
+
+/// Validation errors listed before the rest are summarized
+const MAX_REPORTED_ERRORS: usize = 10;
+
+/// What a bulk insert reads and how it sends it
+#[derive(Debug, Clone)]
+pub struct BulkInsertOptions {
+    pub table: String,
+    pub path: String,
+    pub batch_size: usize,
+    /// Chunks sent concurrently
+    pub jobs: usize,
+    pub on_conflict: OnConflict,
+    /// Convert text values to their columns' types before checking them
+    pub coerce: bool,
+    pub database: String,
+}
+
+/// A table column as reported by `PRAGMA table_info`
+#[derive(Debug, Clone)]
+pub struct SchemaColumn {
+    pub name: String,
+    /// Declared type, uppercased (e.g. "INTEGER", "VARCHAR(255)")
+    pub sql_type: String,
+    pub not_null: bool,
+    pub has_default: bool,
+    pub primary_key: bool,
+    /// Values allowed by an enum-style CHECK constraint
+    pub allowed_values: Option<Vec<String>>,
+}
+
+impl SchemaColumn {
+    /// Whether SQLite fills the column when a row leaves it out
+    fn optional(&self) -> bool {
+        !self.not_null || self.has_default || (self.primary_key && self.sql_type == "INTEGER")
+    }
+
+    /// Whether the column holds numbers, booleans or timestamps rather than text
+    pub fn typed(&self) -> bool {
+        ["INT", "REAL", "FLOA", "DOUB", "BOOL", "TIMESTAMP", "DATE"].iter().any(|t| self.sql_type.contains(t))
+    }
+
+    /// The closest API type to the declared type, as the server reports it in results
+    pub fn data_type(&self) -> DataType {
+        let sql_type = self.sql_type.as_str();
+        if sql_type.contains("BOOL") {
+            DataType::Boolean
+        } else if sql_type.contains("TIMESTAMP") || sql_type.contains("DATE") {
+            DataType::Timestamp
+        } else if sql_type.contains("INT") {
+            DataType::Integer
+        } else if ["REAL", "FLOA", "DOUB"].iter().any(|t| sql_type.contains(t)) {
+            DataType::Real
+        } else if sql_type.contains("BLOB") {
+            DataType::Blob
+        } else {
+            DataType::Text
+        }
+    }
+
+    /// A text value converted to the column's type, for `--coerce`: numeric
+    /// text to a number, true/false/yes/no/1/0 to a boolean, and an ISO-8601
+    /// date or date-time to Unix seconds for INTEGER and TIMESTAMP columns.
+    /// None if the value is not text or does not convert.
+    pub fn coerce(&self, value: &JsonValue) -> Option<JsonValue> {
+        let JsonValue::String(text) = value else {
+            return None;
+        };
+        let text = text.trim();
+        let sql_type = self.sql_type.as_str();
+        let integer = || {
+            text.parse::<i64>()
+                .ok()
+                .or_else(|| text.parse::<f64>().ok().filter(|n| n.is_finite() && n.fract() == 0.0).map(|n| n as i64))
+                .map(JsonValue::from)
+        };
+        let timestamp = || {
+            let time = partition::value_time(&DbValue::Text(text.to_string())).ok().flatten()?;
+            Some(JsonValue::from(time.timestamp()))
+        };
+        if sql_type.contains("BOOL") {
+            match text.to_lowercase().as_str() {
+                "true" | "yes" | "1" => Some(JsonValue::Bool(true)),
+                "false" | "no" | "0" => Some(JsonValue::Bool(false)),
+                _ => None,
+            }
+        } else if sql_type.contains("INT") || sql_type.contains("TIMESTAMP") || sql_type.contains("DATE") {
+            integer().or_else(timestamp)
+        } else if ["REAL", "FLOA", "DOUB"].iter().any(|t| sql_type.contains(t)) {
+            text.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(JsonValue::Number)
+        } else {
+            None
+        }
+    }
+
+    /// Check a JSON value against the column's type affinity. Text is only
+    /// accepted by text columns: converting it is up to `--coerce`.
+    fn check(&self, value: &JsonValue) -> Result<(), String> {
+        let sql_type = self.sql_type.as_str();
+        let integral = |n: f64| n.is_finite() && n.fract() == 0.0;
+        let ok = match value {
+            JsonValue::Null => !self.not_null,
+            // Objects and arrays are stored as JSON text
+            JsonValue::Object(_) | JsonValue::Array(_) => {
+                sql_type.is_empty() || ["CHAR", "CLOB", "TEXT", "JSON"].iter().any(|t| sql_type.contains(t))
+            }
+            JsonValue::Number(n) if sql_type.contains("INT") => n.as_f64().is_some_and(integral),
+            JsonValue::String(_) => !self.typed(),
+            _ => true,
+        };
+        let allowed = match (&self.allowed_values, value) {
+            (Some(values), JsonValue::String(s)) => values.contains(s),
+            (Some(_), JsonValue::Null) | (None, _) => true,
+            (Some(_), _) => false,
+        };
+        if ok && !allowed {
+            let values = self.allowed_values.as_deref().unwrap_or_default();
+            return Err(format!(
+                "{} is not an allowed value for column '{}' (expected one of: {})",
+                value,
+                self.name,
+                values.join(", ")
+            ));
+        }
+        if ok {
+            Ok(())
+        } else if value.is_null() {
+            Err(format!("column '{}' is NOT NULL", self.name))
+        } else {
+            let hint = if self.coerce(value).is_some() { " (--coerce converts it)" } else { "" };
+            Err(format!("column '{}' expects {}, got {} {}{}", self.name, self.sql_type, json_type(value), value, hint))
+        }
+    }
+}
+
+/// Convert the text values of rows to their columns' types where they
+/// convert (see [`SchemaColumn::coerce`]); the rest are left for validation
+/// to report
+pub fn coerce_rows(columns: &[SchemaColumn], rows: &mut [JsonValue]) {
+    for fields in rows.iter_mut().filter_map(JsonValue::as_object_mut) {
+        for (name, value) in fields.iter_mut() {
+            let column = columns.iter().find(|column| column.name.eq_ignore_ascii_case(name));
+            if let Some(converted) = column.and_then(|column| column.coerce(value)) {
+                *value = converted;
+            }
+        }
+    }
+}
+
+/// The JSON type of a value, for error messages
+fn json_type(value: &JsonValue) -> &'static str {
+    match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(number) if number.is_f64() => "real",
+        JsonValue::Number(_) => "integer",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object",
+    }
+}
+
+/// What is wrong with one row's values for the table: type and NULL
+/// mismatches and unknown columns, plus missing NOT NULL columns unless the
+/// row is `partial` (the values of an update)
+pub fn row_problems(table: &str, columns: &[SchemaColumn], row: &JsonValue, partial: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    match row.as_object() {
+        None => problems.push("expected a JSON object".to_string()),
+        Some(fields) if fields.is_empty() => problems.push("row has no values".to_string()),
+        Some(fields) => {
+            for (name, value) in fields {
+                match columns.iter().find(|column| column.name.eq_ignore_ascii_case(name)) {
+                    Some(column) => problems.extend(column.check(value).err()),
+                    None => {
+                        let column = UnknownColumn::new(name, columns.iter().map(|column| column.name.as_str()));
+                        problems.push(format!("table '{}' has no column {}", table, column));
+                    }
+                }
+            }
+            for column in columns.iter().filter(|column| !partial && !column.optional()) {
+                if !fields.keys().any(|name| name.eq_ignore_ascii_case(&column.name)) {
+                    problems.push(format!("missing NOT NULL column '{}'", column.name));
+                }
+            }
+        }
+    }
+    problems
+}
+
+/// Every JSON value in `text`: the items of a single top-level array, or
+/// each value of a sequence such as NDJSON
+pub fn json_documents(text: &str) -> Result<Vec<JsonValue>, String> {
+    let documents: Vec<JsonValue> = serde_json::Deserializer::from_str(text)
+        .into_iter::<JsonValue>()
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Invalid JSON: {}", e))?;
+    Ok(match <[JsonValue; 1]>::try_from(documents) {
+        Ok([JsonValue::Array(items)]) => items,
+        Ok([single]) => vec![single],
+        Err(documents) => documents,
+    })
+}
+
+/// Check every row against the table's columns, reporting the first few
+/// problems by row number (1-based)
+pub fn validate_rows(table: &str, columns: &[SchemaColumn], rows: &[JsonValue]) -> Result<(), String> {
+    let mut errors = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        let problems = row_problems(table, columns, row, false);
+        errors.extend(problems.into_iter().map(|problem| format!("Row {}: {}", i + 1, problem)));
+    }
+
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let mut message = errors.iter().take(MAX_REPORTED_ERRORS).cloned().collect::<Vec<_>>().join("\n");
+    if errors.len() > MAX_REPORTED_ERRORS {
+        message.push_str(&format!("\n... and {} more", errors.len() - MAX_REPORTED_ERRORS));
+    }
+    Err(message)
+}
+
+/// Allowed values of a table's enum-style columns, read from its CREATE
+/// TABLE statement and keyed by lowercased column name
+pub async fn allowed_values(
+    client: &mut Client,
+    table: &str,
+    database: &str,
+) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
+    let sql = format!(
+        "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = '{}'",
+        table.replace('\'', "''")
+    );
+    let rows = generate::query_rows(client, &sql, database).await?;
+    Ok(match rows.first().and_then(|row| row.first()).and_then(|v| v.value.as_ref()) {
+        Some(value::Value::TextValue(sql)) => SqliteDatabase::parse_allowed_values(sql)
+            .into_iter()
+            .map(|(column, values)| (column.to_lowercase(), values))
+            .collect(),
+        _ => HashMap::new(),
+    })
+}
+
+/// Columns of a table from `PRAGMA table_info`; empty if it does not exist
+pub async fn fetch_schema(
+    client: &mut Client,
+    table: &str,
+    database: &str,
+) -> Result<Vec<SchemaColumn>, Box<dyn std::error::Error>> {
+    let rows = generate::query_rows(client, &format!("PRAGMA table_info({})", table), database).await?;
+    let mut allowed = allowed_values(client, table, database).await?;
+
+    // Row layout: cid, name, type, notnull, dflt_value, pk
+    let int = |value: Option<&crate::proto::common::Value>| match value.and_then(|v| v.value.as_ref()) {
+        Some(value::Value::IntValue(i)) => *i,
+        _ => 0,
+    };
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            let text = |i: usize| match row.get(i)?.value.as_ref()? {
+                value::Value::TextValue(s) => Some(s.clone()),
+                _ => None,
+            };
+            Some(SchemaColumn {
+                name: text(1)?,
+                sql_type: text(2).unwrap_or_default().to_uppercase(),
+                not_null: int(row.get(3)) != 0,
+                has_default: row.get(4).is_some_and(|v| {
+                    !matches!(v.value, None | Some(value::Value::NullValue(_)))
+                }),
+                primary_key: int(row.get(5)) > 0,
+                allowed_values: text(1).and_then(|name| allowed.remove(&name.to_lowercase())),
+            })
+        })
+        .collect())
+}
+
+/// What became of one chunk of rows
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkOutcome {
+    Committed { inserted: i64, skipped: i64 },
+    Failed(String),
+    /// Not sent because an earlier chunk failed
+    NotSent,
+}
+
+/// Totals of a load whose chunks all committed, or an error naming the
+/// rows (1-based) of each failed chunk and how many rows were not sent
+pub fn summarize(outcomes: &[(usize, ChunkOutcome)], batch_size: usize, total_rows: usize) -> Result<(i64, i64), String> {
+    let mut outcomes: Vec<&(usize, ChunkOutcome)> = outcomes.iter().collect();
+    outcomes.sort_by_key(|(index, _)| *index);
+
+    let (mut inserted, mut skipped, mut not_sent) = (0, 0, 0);
+    let mut failures = Vec::new();
+    for (index, outcome) in outcomes {
+        let first = index * batch_size + 1;
+        let last = ((index + 1) * batch_size).min(total_rows);
+        match outcome {
+            ChunkOutcome::Committed { inserted: i, skipped: s } => {
+                inserted += i;
+                skipped += s;
+            }
+            ChunkOutcome::Failed(message) => failures.push(format!("rows {}-{} failed: {}", first, last, message)),
+            ChunkOutcome::NotSent => not_sent += last + 1 - first,
+        }
+    }
+    if failures.is_empty() {
+        return Ok((inserted, skipped));
+    }
+    let mut message = format!("Bulk insert stopped after {} rows were inserted; {}", inserted, failures.join("; "));
+    if not_sent > 0 {
+        message.push_str(&format!("; {} rows were not sent", not_sent));
+    }
+    Err(message)
+}
+
+/// Cap `jobs` at the pool size the server reports for `database`
+async fn pool_limit(client: &mut Client, database: &str, jobs: usize) -> Result<usize, Box<dyn std::error::Error>> {
+    if jobs <= 1 {
+        return Ok(jobs);
+    }
+    let databases = client.get_server_status(ServerStatusRequest {}).await?.into_inner().databases;
+    let status = databases
+        .iter()
+        .find(|db| db.name == database)
+        .or_else(|| databases.iter().find(|db| database.is_empty() && db.name == "default"));
+    match status.map(|db| db.max_connections as usize) {
+        Some(max) if max > 0 && max < jobs => {
+            eprintln!("Using {} jobs, the size of the database's connection pool", max);
+            Ok(max)
+        }
+        _ => Ok(jobs),
+    }
+}
+
+pub async fn insert_file(server_address: String, options: BulkInsertOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let text = read_input(&options.path)?;
+    let mut rows = json_documents(&text)?;
+    if rows.is_empty() {
+        output::success(
+            &format!("No rows in {}", options.path),
+            serde_json::json!({ "table": options.table, "inserted": 0, "skipped": 0 }),
+        );
+        return Ok(());
+    }
+
+    let mut client = client::connect(server_address).await?;
+    let columns = fetch_schema(&mut client, &options.table, &options.database).await?;
+    if columns.is_empty() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", options.table)).into());
+    }
+    if options.coerce {
+        coerce_rows(&columns, &mut rows);
+    }
+    if let Err(problems) = validate_rows(&options.table, &columns, &rows) {
+        eprintln!("{}", problems);
+        let message = format!("Rows in {} do not match table '{}'; nothing was inserted", options.path, options.table);
+        return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+    }
+
+    let jobs = pool_limit(&mut client, &options.database, options.jobs.max(1)).await?;
+
+    let progress = ProgressBar::new(rows.len() as u64);
+    progress.set_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} rows ({per_sec}, {eta} left)")
+            .unwrap_or_else(|_| ProgressStyle::default_bar()),
+    );
+
+    let batch_size = options.batch_size.max(1);
+    let failed = Arc::new(AtomicBool::new(false));
+    let outcomes: Vec<(usize, ChunkOutcome)> = stream::iter(rows.chunks(batch_size).enumerate())
+        .map(|(index, chunk)| {
+            let mut client = client.clone();
+            let failed = failed.clone();
+            let progress = progress.clone();
+            let request = batch_request(&options, chunk);
+            async move {
+                if failed.load(Ordering::SeqCst) {
+                    return (index, ChunkOutcome::NotSent);
+                }
+                match client.batch_insert(request).await {
+                    Ok(response) => {
+                        let response = response.into_inner();
+                        progress.inc(chunk.len() as u64);
+                        let outcome = ChunkOutcome::Committed {
+                            inserted: response.inserted_count,
+                            skipped: response.skipped_count,
+                        };
+                        (index, outcome)
+                    }
+                    Err(status) => {
+                        failed.store(true, Ordering::SeqCst);
+                        (index, ChunkOutcome::Failed(status.message().to_string()))
+                    }
+                }
+            }
+        })
+        .buffer_unordered(jobs)
+        .collect()
+        .await;
+
+    let (inserted, skipped) = match summarize(&outcomes, batch_size, rows.len()) {
+        Ok(totals) => totals,
+        Err(message) => {
+            progress.abandon();
+            return Err(message.into());
+        }
+    };
+    progress.finish_and_clear();
+
+    let text = if skipped > 0 {
+        format!("✅ Inserted {} rows into '{}' ({} skipped)", inserted, options.table, skipped)
+    } else {
+        format!("✅ Inserted {} rows into '{}'", inserted, options.table)
+    };
+    output::success(
+        &text,
+        serde_json::json!({ "table": options.table, "inserted": inserted, "skipped": skipped }),
+    );
+    Ok(())
+}
+
+pub fn batch_request(options: &BulkInsertOptions, chunk: &[JsonValue]) -> BatchInsertRequest {
+    let rows = chunk
+        .iter()
+        .map(|row| InsertRow {
+            values: row
+                .as_object()
+                .map(Map::iter)
+                .into_iter()
+                .flatten()
+                .map(|(column, value)| (column.clone(), sink::json_to_value(value)))
+                .collect(),
+        })
+        .collect();
+    BatchInsertRequest {
+        table_name: options.table.clone(),
+        rows,
+        database: options.database.clone(),
+        on_conflict: options.on_conflict as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn column(name: &str, sql_type: &str, not_null: bool, primary_key: bool) -> SchemaColumn {
+        SchemaColumn {
+            name: name.to_string(),
+            sql_type: sql_type.to_string(),
+            not_null,
+            has_default: false,
+            primary_key,
+            allowed_values: None,
+        }
+    }
+
+    #[test]
+    fn test_json_documents() {
+        assert_eq!(json_documents(r#"[{"a": 1}, {"a": 2}]"#).unwrap().len(), 2);
+        assert_eq!(json_documents("{\"a\": 1}\n{\"a\": 2}\n\n{\"a\": 3}\n").unwrap().len(), 3);
+        assert_eq!(json_documents(r#"{"a": 1}"#).unwrap().len(), 1);
+        assert!(json_documents("").unwrap().is_empty());
+        assert!(json_documents("{\"a\": 1}\n{\"a\": ").is_err());
+    }
+
+    #[test]
+    fn test_validate_rows() {
+        let columns = vec![
+            column("id", "INTEGER", false, true),
+            column("name", "TEXT", true, false),
+            column("age", "INTEGER", false, false),
+            column("score", "REAL", false, false),
+            column("active", "BOOLEAN", false, false),
+        ];
+        let good = vec![
+            json!({"id": 1, "name": "Alice", "age": 30, "score": 1.5, "active": true}),
+            json!({"NAME": "Bob", "age": 41, "score": null, "active": false}),
+        ];
+        assert!(validate_rows("users", &columns, &good).is_ok());
+
+        let bad = vec![
+            json!({"name": "Carol", "age": "old"}),
+            json!({"age": 5}),
+            json!({"name": null, "nickname": "x"}),
+            json!(["not", "an", "object"]),
+            json!({"name": "Dan", "score": {"nested": true}}),
+        ];
+        let message = validate_rows("users", &columns, &bad).unwrap_err();
+        let lines: Vec<&str> = message.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "Row 1: column 'age' expects INTEGER, got string \"old\"",
+                "Row 2: missing NOT NULL column 'name'",
+                "Row 3: column 'name' is NOT NULL",
+                "Row 3: table 'users' has no column 'nickname'",
+                "Row 4: expected a JSON object",
+                "Row 5: column 'score' expects REAL, got object {\"nested\":true}",
+            ]
+        );
+
+        let many: Vec<JsonValue> = (0..15).map(|_| json!({"age": 1})).collect();
+        let message = validate_rows("users", &columns, &many).unwrap_err();
+        assert_eq!(message.lines().count(), MAX_REPORTED_ERRORS + 1);
+        assert!(message.ends_with("... and 5 more"));
+    }
+
+    #[test]
+    fn test_data_type() {
+        let data_type = |sql_type: &str| column("c", sql_type, false, false).data_type();
+        assert_eq!(data_type("BIGINT"), DataType::Integer);
+        assert_eq!(data_type("DOUBLE PRECISION"), DataType::Real);
+        assert_eq!(data_type("BOOLEAN"), DataType::Boolean);
+        assert_eq!(data_type("DATETIME"), DataType::Timestamp);
+        assert_eq!(data_type("VARCHAR(255)"), DataType::Text);
+        assert_eq!(data_type(""), DataType::Text);
+    }
+
+    #[test]
+    fn test_coerce_rows() {
+        let columns = vec![
+            column("age", "INTEGER", false, false),
+            column("score", "REAL", false, false),
+            column("active", "BOOLEAN", false, false),
+            column("seen", "TIMESTAMP", false, false),
+            column("name", "TEXT", false, false),
+        ];
+        let mut rows = vec![
+            json!({"age": " 41", "score": "1.5", "active": "yes", "seen": "2024-03-15T12:00:00Z", "name": "7"}),
+            json!({"AGE": "2024-03-15", "score": "x", "active": "maybe", "seen": "1710504000"}),
+        ];
+        // Strict by default: text in a typed column is rejected, with a hint when it converts
+        let message = validate_rows("users", &columns, &rows).unwrap_err();
+        assert!(message.contains("Row 1: column 'age' expects INTEGER, got string \" 41\" (--coerce converts it)"));
+
+        coerce_rows(&columns, &mut rows);
+        assert_eq!(rows[0], json!({"age": 41, "score": 1.5, "active": true, "seen": 1710504000, "name": "7"}));
+        assert_eq!(rows[1], json!({"AGE": 1710460800, "score": "x", "active": "maybe", "seen": 1710504000}));
+        assert_eq!(
+            validate_rows("users", &columns, &rows).unwrap_err().lines().collect::<Vec<_>>(),
+            [
+                "Row 2: column 'active' expects BOOLEAN, got string \"maybe\"",
+                "Row 2: column 'score' expects REAL, got string \"x\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_row_problems() {
+        let columns = vec![column("id", "INTEGER", false, true), column("name", "TEXT", true, false)];
+        // An update need not set every NOT NULL column, but cannot clear one
+        assert!(row_problems("users", &columns, &json!({"id": 2}), true).is_empty());
+        assert_eq!(row_problems("users", &columns, &json!({"id": 2}), false), ["missing NOT NULL column 'name'"]);
+        assert_eq!(
+            row_problems("users", &columns, &json!({"name": null, "id": 1.5}), true),
+            ["column 'id' expects INTEGER, got real 1.5", "column 'name' is NOT NULL"]
+        );
+        assert_eq!(
+            row_problems("users", &columns, &json!({"nmae": "Ann"}), true),
+            ["table 'users' has no column 'nmae' (did you mean 'name'?)"]
+        );
+    }
+
+    #[test]
+    fn test_validate_rows_allowed_values() {
+        let mut priority = column("priority", "TEXT", false, false);
+        priority.allowed_values = Some(vec!["low".to_string(), "high".to_string()]);
+        let columns = vec![priority];
+        let rows = vec![json!({"priority": "low"}), json!({"priority": null}), json!({"priority": "urgent"}), json!({"priority": 1})];
+        assert_eq!(
+            validate_rows("tasks", &columns, &rows).unwrap_err().lines().collect::<Vec<_>>(),
+            vec![
+                "Row 3: \"urgent\" is not an allowed value for column 'priority' (expected one of: low, high)",
+                "Row 4: 1 is not an allowed value for column 'priority' (expected one of: low, high)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let committed = |inserted| ChunkOutcome::Committed { inserted, skipped: 0 };
+        // Chunks finish out of order with --jobs
+        let outcomes = vec![(1, committed(10)), (0, committed(10)), (2, ChunkOutcome::Committed { inserted: 3, skipped: 2 })];
+        assert_eq!(summarize(&outcomes, 10, 25), Ok((23, 2)));
+
+        let outcomes = vec![
+            (0, committed(10)),
+            (2, ChunkOutcome::Failed("UNIQUE constraint failed: t.id".to_string())),
+            (1, committed(10)),
+            (3, ChunkOutcome::NotSent),
+            (4, ChunkOutcome::NotSent),
+        ];
+        assert_eq!(
+            summarize(&outcomes, 10, 45).unwrap_err(),
+            "Bulk insert stopped after 20 rows were inserted; rows 21-30 failed: UNIQUE constraint failed: t.id; \
+             15 rows were not sent"
+        );
+    }
+}
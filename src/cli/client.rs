@@ -0,0 +1,257 @@
+//! gRPC connections for client commands
+//!
+//! Every request carries `authorization: Bearer <token>` when the
+//! `DATASINK_TOKEN` environment variable is set, for servers that have
+//! identities configured, and runs in the session named by
+//! `DATASINK_SESSION` when that is set.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Response, Status};
+
+use crate::cli::error::{CliError, ErrorKind};
+use crate::grpc::service::{CONSISTENCY_HEADER, EXECUTED_SQL_HEADER, SHOW_SQL_HEADER};
+use crate::grpc::session::SESSION_HEADER;
+use crate::proto::data_sink_client::DataSinkClient;
+
+/// Environment variable holding the client's bearer token
+pub const TOKEN_ENV: &str = "DATASINK_TOKEN";
+
+/// Environment variable holding the ID of the session to run queries in
+pub const SESSION_ENV: &str = "DATASINK_SESSION";
+
+/// First pause between connection attempts; doubled after each failure
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Longest pause between connection attempts
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Timeouts, retries, HTTP/2 keepalive and TCP settings for client
+/// connections; unset fields keep tonic's defaults
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionSettings {
+    /// Give up on a connection attempt after this long
+    pub connect_timeout: Option<Duration>,
+    /// Fail a request whose response has not arrived after this long
+    pub request_timeout: Option<Duration>,
+    /// Connection attempts made after the first one fails
+    pub retries: u32,
+    /// Ping the server after this long without traffic, so NAT and load
+    /// balancer idle timeouts do not cut long-running streams
+    pub keepalive_interval: Option<Duration>,
+    /// Drop the connection when a ping is not answered within this long
+    pub keepalive_timeout: Option<Duration>,
+    pub tcp_nodelay: Option<bool>,
+    /// Ask the server for the statements Insert, Update, Delete and
+    /// CreateTable executed, and print them (--show-sql)
+    pub show_sql: bool,
+    /// Where reads may be served from (--consistency)
+    pub consistency: Option<String>,
+}
+
+impl ConnectionSettings {
+    fn apply(&self, mut endpoint: Endpoint) -> Endpoint {
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.request_timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(interval) = self.keepalive_interval {
+            endpoint = endpoint.http2_keep_alive_interval(interval).keep_alive_while_idle(true);
+        }
+        if let Some(timeout) = self.keepalive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+        if let Some(nodelay) = self.tcp_nodelay {
+            endpoint = endpoint.tcp_nodelay(nodelay);
+        }
+        endpoint
+    }
+}
+
+static SETTINGS: OnceLock<ConnectionSettings> = OnceLock::new();
+
+/// Use these settings for every connection made by `connect`
+pub fn configure(settings: ConnectionSettings) {
+    let _ = SETTINGS.set(settings);
+}
+
+/// A connected client
+pub type Client = DataSinkClient<InterceptedService<Channel, TokenInterceptor>>;
+
+/// Adds the bearer token and session, if any, and the --show-sql and
+/// --consistency headers to each request
+#[derive(Debug, Clone, Default)]
+pub struct TokenInterceptor {
+    authorization: Option<MetadataValue<Ascii>>,
+    session: Option<MetadataValue<Ascii>>,
+    show_sql: bool,
+    consistency: Option<MetadataValue<Ascii>>,
+}
+
+impl TokenInterceptor {
+    fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+        let authorization = match std::env::var(TOKEN_ENV) {
+            Ok(token) if !token.is_empty() => Some(
+                format!("Bearer {}", token)
+                    .parse()
+                    .map_err(|_| format!("{} contains characters not allowed in metadata", TOKEN_ENV))?,
+            ),
+            _ => None,
+        };
+        let session = match std::env::var(SESSION_ENV) {
+            Ok(id) if !id.is_empty() => Some(
+                id.trim()
+                    .parse()
+                    .map_err(|_| format!("{} contains characters not allowed in metadata", SESSION_ENV))?,
+            ),
+            _ => None,
+        };
+        Ok(Self { authorization, session, show_sql: false, consistency: None })
+    }
+}
+
+impl Interceptor for TokenInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(value) = &self.authorization {
+            request.metadata_mut().insert("authorization", value.clone());
+        }
+        if let Some(value) = &self.session {
+            request.metadata_mut().insert(SESSION_HEADER, value.clone());
+        }
+        if self.show_sql {
+            request.metadata_mut().insert(SHOW_SQL_HEADER, MetadataValue::from_static("1"));
+        }
+        if let Some(value) = &self.consistency {
+            request.metadata_mut().insert(CONSISTENCY_HEADER, value.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Connect to a server, retrying with backoff as configured
+pub async fn connect(server_address: String) -> Result<Client, Box<dyn std::error::Error>> {
+    connect_with(server_address, &SETTINGS.get().cloned().unwrap_or_default()).await
+}
+
+/// Connect to a server with the given settings rather than the configured ones
+async fn connect_with(server_address: String, settings: &ConnectionSettings) -> Result<Client, Box<dyn std::error::Error>> {
+    let consistency = settings.consistency.as_deref().map(MetadataValue::try_from).transpose().map_err(|_| {
+        CliError::new(ErrorKind::InvalidInput, "--consistency contains characters not allowed in metadata")
+    })?;
+    let interceptor = TokenInterceptor { show_sql: settings.show_sql, consistency, ..TokenInterceptor::from_env()? };
+    let endpoint = Endpoint::from_shared(server_address.clone()).map_err(|e| {
+        CliError::new(ErrorKind::InvalidInput, format!("Invalid server address '{}': {}", server_address, describe(&e)))
+    })?;
+    let endpoint = settings.apply(endpoint);
+
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 0;
+    let channel = loop {
+        match endpoint.connect().await {
+            Ok(channel) => break channel,
+            Err(e) if attempt < settings.retries => {
+                attempt += 1;
+                tracing::debug!("Connecting to {} failed ({}), retry {} in {:?}", server_address, describe(&e), attempt, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+            }
+            Err(e) => {
+                let attempts = if attempt > 0 { format!(" after {} attempts", attempt + 1) } else { String::new() };
+                let message = format!("Failed to connect to {}{}: {}", server_address, attempts, describe(&e));
+                return Err(CliError::new(ErrorKind::Connection, message).into());
+            }
+        }
+    };
+    Ok(DataSinkClient::with_interceptor(channel, interceptor))
+}
+
+/// Print the statements the server reports executing for a request, on
+/// stderr so --json output stays parseable. Does nothing without --show-sql.
+pub fn echo_sql<T>(result: &Result<Response<T>, Status>) {
+    let metadata = match result {
+        Ok(response) => response.metadata(),
+        Err(status) => status.metadata(),
+    };
+    for value in metadata.get_all_bin(EXECUTED_SQL_HEADER) {
+        if let Ok(bytes) = value.to_bytes() {
+            eprintln!("SQL: {}", String::from_utf8_lossy(&bytes));
+        }
+    }
+}
+
+/// An error and its causes, e.g. "transport error: Connection refused (os error 111)"
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut text = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        // Some errors already include their cause's message
+        let message = cause.to_string();
+        if !text.contains(&message) {
+            text.push_str(&format!(": {}", message));
+        }
+        source = cause.source();
+    }
+    text
+}
+
+/// Serve `manager`'s databases on a free local port for tests of client
+/// commands, returning the address to connect to
+#[cfg(test)]
+pub async fn test_server(manager: std::sync::Arc<crate::db::DatabaseManager>) -> String {
+    use crate::grpc::DataSinkService;
+    use crate::proto::data_sink_server::DataSinkServer;
+    use tonic::transport::server::TcpIncoming;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = format!("http://{}", listener.local_addr().unwrap());
+    let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+    let service = DataSinkServer::new(DataSinkService::new_with_manager(manager));
+    tokio::spawn(tonic::transport::Server::builder().add_service(service).serve_with_incoming(incoming));
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DatabaseManager;
+    use crate::proto::admin::PingRequest;
+    use std::sync::Arc;
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_connect_retries_then_reports_attempts() {
+        // Nothing listens on port 1, so every attempt is refused
+        let settings = ConnectionSettings { retries: 1, connect_timeout: Some(Duration::from_secs(2)), ..Default::default() };
+        let error = connect_with("http://127.0.0.1:1".to_string(), &settings).await.unwrap_err();
+        assert!(error.to_string().contains("after 2 attempts"), "{}", error);
+
+        let error = connect_with("http://127.0.0.1:1".to_string(), &ConnectionSettings::default()).await.unwrap_err();
+        assert!(!error.to_string().contains("attempts"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_timeouts_and_keepalive() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let manager = Arc::new(DatabaseManager::new());
+        let url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+        manager.add_database("default".to_string(), url).await.unwrap();
+
+        let settings = ConnectionSettings {
+            connect_timeout: Some(Duration::from_secs(2)),
+            request_timeout: Some(Duration::from_secs(5)),
+            keepalive_interval: Some(Duration::from_secs(30)),
+            keepalive_timeout: Some(Duration::from_secs(5)),
+            tcp_nodelay: Some(true),
+            ..Default::default()
+        };
+        let mut client = connect_with(test_server(manager).await, &settings).await.unwrap();
+        client.ping(PingRequest {}).await.unwrap();
+    }
+}
@@ -1,70 +1,279 @@
-use crate::db::{Database, SqliteDatabase, DatabaseManager};
-use crate::grpc::DataSinkService;
+use crate::config::{JobConfig, ServerConfig};
+use crate::db::scheduler::JobScheduler;
+use crate::db::secrets;
+use crate::db::{ColumnCipher, Database, DatabaseManager, DatabaseOptions, QueryCache, SqliteDatabase};
+use crate::db::error::UnknownColumn;
+use crate::db::sqlite::DESCRIPTIONS_TABLE;
+use crate::db::traits::KeepRow;
+use crate::grpc::policy::{AuthInterceptor, RowPolicies};
+use crate::grpc::{DataSinkService, RequestIdLayer};
 use crate::proto::data_sink_server::DataSinkServer;
-use crate::proto::data_sink_client::DataSinkClient;
-use crate::proto::admin::{CreateTableRequest, ServerStatusRequest, AddDatabaseRequest};
+use crate::cli::error::{CliError, ErrorKind};
+use crate::cli::{bulk, client, generate, output, read_input, render, template};
+use crate::proto::admin::{
+    CreateTableRequest, UniqueConstraint, ServerStatusRequest, ServerStatusResponse, DatabaseInfoRequest, DatabaseStatsRequest, GetPragmaRequest, SetPragmaRequest, AddDatabaseRequest, RemoveDatabaseRequest, ShutdownRequest, ProvisionDatabaseRequest, provision_database_request, CloneDatabaseRequest, CopyTableRequest, DiffTableRequest, RowChange, MetricsRequest, PingRequest, TruncateTableRequest,
+    CreateSearchIndexRequest, CreateSpatialIndexRequest, DiskUsage,
+    AddJobRequest, RemoveJobRequest, ListJobsRequest, ListJobRunsRequest, RefreshMaterializedViewRequest,
+    CheckForeignKeysRequest, SaveQueryRequest, RemoveSavedQueryRequest, ListSavedQueriesRequest, AdviseIndexesRequest,
+    ListChangesRequest, UndoChangesRequest, HistoryChange,
+};
 use crate::proto::crud::{
-    DeleteRequest, InsertRequest, QueryRequest, UpdateRequest, 
-    query_response, QueryResponse,
+    DeleteRequest, GetRowRequest, InsertRequest, OnConflict, QueryRequest, SearchRequest, UpdateRequest, 
+    BoundingBox, SpatialQueryRequest, SampleRequest, OpenSessionRequest, CloseSessionRequest, DedupeKeep, DedupeRequest, RunSavedQueryRequest,
+    query_response, QueryResponse, QuerySummary,
 };
-use crate::proto::common::{ColumnDefinition, DataType, Value, value};
-use crate::schema::parser;
-use std::collections::HashMap;
-use std::path::Path;
+use crate::proto::common::{Column, ColumnDefinition, DataType, Value, value};
+use crate::schema::{apply, ddl, parser, plan};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use tokio_stream::StreamExt;
-use tonic::transport::Server;
+use tonic::codegen::InterceptedService;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 use tracing::info;
 use tabled::{builder::Builder as TableBuilder, settings::{Style, object::Segment, Alignment, Modify}};
 
 pub async fn start_server(
-    database_url: String,
-    bind_address: String,
+    database_url: Option<String>,
+    databases: Vec<(String, String)>,
+    bind_address: Option<String>,
+    registry_path: Option<PathBuf>,
+    idle_timeout_minutes: Option<u64>,
+    query_cache_entries: Option<usize>,
+    config: ServerConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    // Collect the databases to register: config file entries first, then the
+    // primary database, then any --database flags (later entries win)
+    let mut startup_databases: BTreeMap<String, (String, DatabaseOptions)> = BTreeMap::new();
+    for (name, db_config) in &config.databases {
+        let url = db_config.connection_url().map_err(|e| format!("Database '{}': {}", name, e))?;
+        startup_databases.insert(name.clone(), (url, config.database_options(Some(db_config))));
+    }
+    if let Some(database_url) = database_url {
+        startup_databases.insert("default".to_string(), (database_url, config.database_options(None)));
+    }
+    for (name, url) in databases {
+        let options = config.database_options(config.databases.get(&name));
+        startup_databases.insert(name, (url, options));
+    }
+
+    // Create database manager and register the databases
+    let db_manager = std::sync::Arc::new(match &registry_path {
+        Some(path) => DatabaseManager::with_registry(path),
+        None => DatabaseManager::new(),
+    });
+    for (name, (url, options)) in startup_databases {
+        let db_url = normalize_server_database_url(&url)?;
+        info!("Connecting to database '{}': {}", name, secrets::redact_url(&db_url));
+        db_manager
+            .add_database_with_options(name.clone(), db_url, options)
+            .await
+            .map_err(|e| format!("Failed to connect to database '{}': {}", name, e))?;
+    }
+
+    // Restore databases that were added at runtime before the last restart
+    if let Some(path) = &registry_path {
+        let restored = db_manager.load_registry().await?;
+        if restored > 0 {
+            info!("Restored {} database(s) from {}", restored, path.display());
+        }
+    }
+
+    // Close pools of databases that sit unused; they reconnect on next access
+    let idle_timeout_minutes = idle_timeout_minutes.or(config.server.idle_timeout_minutes);
+    if let Some(minutes) = idle_timeout_minutes.filter(|m| *m > 0) {
+        info!("Closing idle database connections after {} minute(s)", minutes);
+        db_manager.start_idle_eviction(std::time::Duration::from_secs(minutes * 60));
+    }
+
+    let bind_address = bind_address
+        .or_else(|| config.server.bind_address.clone())
+        .unwrap_or_else(|| "127.0.0.1:50051".to_string());
+    info!("Starting DataSink gRPC server on {}", bind_address);
+    let addr = bind_address.parse()?;
+
+    let query_cache = query_cache_entries
+        .or(config.server.query_cache_entries)
+        .filter(|e| *e > 0)
+        .map(|entries| {
+            info!("Query result cache enabled ({} entries)", entries);
+            std::sync::Arc::new(QueryCache::new(entries))
+        });
+
+    // Refresh materialized views as their intervals elapse
+    db_manager.start_view_refresher(query_cache.clone());
+
+    // Delete rows of tables with a TTL column once they expire
+    let ttl_interval_seconds = config.server.ttl_interval_seconds.unwrap_or(60);
+    if ttl_interval_seconds > 0 {
+        db_manager.start_ttl_reaper(std::time::Duration::from_secs(ttl_interval_seconds), query_cache.clone());
+    }
+
+    // Run recurring SQL jobs from the config file and those added at runtime
+    let scheduler = std::sync::Arc::new(JobScheduler::new(db_manager.clone(), query_cache.clone()));
+    let jobs = scheduler
+        .load(config.jobs.iter().map(JobConfig::to_job).collect())
+        .await
+        .map_err(|e| format!("Failed to load jobs: {}", e))?;
+    if jobs > 0 {
+        info!("Scheduled {} job(s)", jobs);
+    }
+    scheduler.start();
+
+    let mut service = DataSinkService::new_with_manager(db_manager.clone()).with_scheduler(scheduler);
+    if let Some(cache) = query_cache {
+        service = service.with_query_cache(cache);
+    }
+    if let Some(rows) = config.server.query_batch_rows {
+        service = service.with_query_batch_rows(rows);
+    }
+    if let Some(rows) = config.server.max_query_rows {
+        service = service.with_max_query_rows(rows);
+    }
+    if let Some(rows) = config.server.default_query_limit {
+        service = service.with_default_query_limit(rows);
+    }
+    if let Some(dir) = &config.server.data_dir {
+        service = service.with_data_dir(dir.clone());
+    }
+    if let Some(dir) = &config.server.schema_dir {
+        service = service.with_schema_dir(dir.clone());
+    }
+    if let Some(slow_query_ms) = config.server.slow_query_ms {
+        service = service.with_slow_query_threshold(std::time::Duration::from_millis(slow_query_ms));
+    }
+
+    let cipher = match ColumnCipher::from_env()? {
+        Some(cipher) => Some(cipher),
+        None => config
+            .server
+            .encryption_key_file
+            .as_deref()
+            .map(ColumnCipher::from_key_file)
+            .transpose()?,
+    };
+    if let Some(cipher) = cipher {
+        info!("Column encryption enabled");
+        service = service.with_cipher(std::sync::Arc::new(cipher));
+    }
+
+    let mut policies = RowPolicies::from_config(&config.identities)?;
+    if !policies.is_empty() {
+        info!("Token authentication enabled ({} identities)", config.identities.len());
+    }
+    if let Some(token) = &config.server.admin_token {
+        policies = policies.with_admin_token(token)?;
+        info!("Admin operations require the admin token");
+    }
+
+    let section = &config.server;
+    let mut builder = Server::builder()
+        .http2_keepalive_interval(section.keepalive_interval_seconds.map(std::time::Duration::from_secs))
+        .tcp_nodelay(section.tcp_nodelay.unwrap_or(false))
+        .max_concurrent_streams(section.max_concurrent_streams);
+    if let Some(timeout) = section.keepalive_timeout_seconds {
+        builder = builder.http2_keepalive_timeout(Some(std::time::Duration::from_secs(timeout)));
+    }
+    if let Some(limit) = section.concurrency_limit_per_connection {
+        builder = builder.concurrency_limit_per_connection(limit);
+    }
+    if let Some(tls) = &config.tls {
+        info!("TLS enabled (certificate: {})", tls.cert.display());
+        let cert = tokio::fs::read(&tls.cert).await
+            .map_err(|e| format!("Failed to read TLS certificate {}: {}", tls.cert.display(), e))?;
+        let key = tokio::fs::read(&tls.key).await
+            .map_err(|e| format!("Failed to read TLS key {}: {}", tls.key.display(), e))?;
+        let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        if let Some(client_ca) = &tls.client_ca {
+            let ca = tokio::fs::read(client_ca).await
+                .map_err(|e| format!("Failed to read client CA {}: {}", client_ca.display(), e))?;
+            tls_config = tls_config.client_ca_root(Certificate::from_pem(ca));
+        }
+        builder = builder.tls_config(tls_config)?;
+    }
+
+    let shutdown = service.shutdown_signal();
+    let service = std::sync::Arc::new(service);
+    let policies = std::sync::Arc::new(policies);
+    if let Some(ingest_address) = &config.server.ingest_address {
+        let ingest_addr = ingest_address.parse()?;
+        info!("HTTP ingestion endpoint listening on {}", ingest_addr);
+        let router = crate::ingest::router(service.clone(), db_manager.clone(), policies.clone());
+        tokio::spawn(async move {
+            if let Err(e) = crate::ingest::serve(ingest_addr, router).await {
+                tracing::error!("HTTP ingestion endpoint failed: {}", e);
+            }
+        });
+    }
+
+    builder
+        .layer(RequestIdLayer)
+        .add_service(InterceptedService::new(
+            DataSinkServer::from_arc(service),
+            AuthInterceptor::new(policies),
+        ))
+        .serve_with_shutdown(addr, shutdown)
+        .await?;
+
+    Ok(())
+}
+
+/// Validate a database URL for the server and enable create mode for SQLite
+fn normalize_server_database_url(database_url: &str) -> Result<String, Box<dyn std::error::Error>> {
     use crate::cli::validation::validate_database_url;
-    
+
     // Validate and normalize the database URL
-    let validated_url = validate_database_url(&database_url)?;
-    
+    let validated_url = validate_database_url(database_url)?;
+
     // Add create mode if not already present in the URL
-    let db_url = if validated_url.contains("?") {
+    Ok(if validated_url.contains('?') {
         validated_url
     } else {
         format!("{}?mode=rwc", validated_url)
-    };
-    
-    info!("Connecting to database: {}", db_url);
-    
-    // Create database manager and add the primary database
-    let db_manager = std::sync::Arc::new(DatabaseManager::new());
-    db_manager.add_database("default".to_string(), db_url.clone()).await?;
-    
-    info!("Starting DataSink gRPC server on {}", bind_address);
-    let addr = bind_address.parse()?;
-
-    let service = DataSinkService::new_with_manager(db_manager);
-
-    Server::builder()
-        .add_service(DataSinkServer::new(service))
-        .serve(addr)
-        .await?;
+    })
+}
 
+pub fn generate_key() -> Result<(), Box<dyn std::error::Error>> {
+    let key = ColumnCipher::generate_key()?;
+    if output::enabled() {
+        output::print(&serde_json::json!({ "key": key }));
+    } else {
+        println!("{}", key);
+    }
+    eprintln!("Store this key safely: data in encrypted columns cannot be read without it.");
+    eprintln!("Use it via DATASINK_ENCRYPTION_KEY or [server] encryption_key_file.");
     Ok(())
 }
 
-pub async fn stop_server(_server_address: String) -> Result<(), Box<dyn std::error::Error>> {
-    // TODO: Implement graceful shutdown
-    // This would require the server to expose a shutdown endpoint
-    // or use a signal handler
-    eprintln!("Server stop not yet implemented. Use Ctrl+C to stop the server.");
+pub async fn stop_server(server_address: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let response = client.shutdown(ShutdownRequest {}).await?;
+    let result = response.into_inner();
+    output::success(&format!("✅ {}", result.message), serde_json::json!({ "message": result.message }));
     Ok(())
 }
 
-pub async fn server_status(server_address: String) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+pub async fn server_status(server_address: String, detailed: bool, format: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
     
     let request = ServerStatusRequest {};
     let response = client.get_server_status(request).await?;
     let status = response.into_inner();
+
+    match if output::enabled() { "json" } else { format } {
+        "json" => {
+            output::print(&status_json(&status));
+            return Ok(());
+        }
+        "yaml" => {
+            output::print_yaml(&status_json(&status))?;
+            return Ok(());
+        }
+        "table" => {
+            print!("{}", status_table(&status, detailed));
+            return Ok(());
+        }
+        _ => {}
+    }
     
     println!("🚀 DataSink Server Status");
     println!("========================");
@@ -80,7 +289,7 @@ pub async fn server_status(server_address: String) -> Result<(), Box<dyn std::er
         for db in status.databases {
             let connection_time = if db.connection_time > 0 {
                 let dt = chrono::DateTime::from_timestamp(db.connection_time, 0)
-                    .unwrap_or_else(|| chrono::Utc::now());
+                    .unwrap_or_else(chrono::Utc::now);
                 dt.format("%Y-%m-%d %H:%M:%S UTC").to_string()
             } else {
                 "Unknown".to_string()
@@ -89,8 +298,21 @@ pub async fn server_status(server_address: String) -> Result<(), Box<dyn std::er
             println!("  📊 Database: {}", db.name);
             println!("     URL: {}", db.url);
             println!("     Status: {}", if db.connected { "🟢 Connected" } else { "🔴 Disconnected" });
+            for problem in &db.problems {
+                println!("     ⚠️  Degraded: {}", problem);
+            }
             println!("     Connected: {}", connection_time);
             println!("     Active Connections: {}", db.active_connections);
+            if let Some(usage) = &db.disk_usage {
+                println!("     Disk Usage: {}", format_disk_usage(usage));
+            }
+            if detailed {
+                println!("     Requests: {} ({} active queries)", db.requests_total, db.active_queries);
+                println!("     Queries: {}", db.queries_total);
+                println!("     Rows Written: {}", db.rows_written_total);
+                println!("     Last Write: {}", format_last_write(db.last_write_time));
+                println!("     Errors: {}", db.errors_total);
+            }
             println!();
         }
     }
@@ -98,391 +320,2042 @@ pub async fn server_status(server_address: String) -> Result<(), Box<dyn std::er
     Ok(())
 }
 
-pub async fn add_database(
-    server_address: String,
-    name: String,
-    url: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
-    
-    let request = AddDatabaseRequest { name: name.clone(), url };
-    let response = client.add_database(request).await?;
-    let result = response.into_inner();
-    
-    if result.success {
-        println!("✅ {}", result.message);
+/// A database's last write time, or "Never" if it has not been written to
+/// since the server started
+fn format_last_write(timestamp: i64) -> String {
+    match chrono::DateTime::from_timestamp(timestamp, 0).filter(|_| timestamp > 0) {
+        Some(dt) => dt.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => "Never".to_string(),
+    }
+}
+
+/// `server status --format table`: one row per database, with its activity
+/// counters under --detailed
+fn status_table(status: &ServerStatusResponse, detailed: bool) -> String {
+    let mut text = format!(
+        "Server: {}, up {} seconds\n",
+        if status.server_running { "running" } else { "stopped" },
+        status.uptime_seconds
+    );
+    if status.databases.is_empty() {
+        text.push_str("No databases connected\n");
+        return text;
+    }
+
+    let mut header = vec!["Database", "Status", "Connections", "Disk Size"];
+    if detailed {
+        header.extend(["Requests", "Queries", "Rows Written", "Last Write", "Errors"]);
+    }
+    let mut builder = TableBuilder::default();
+    builder.push_record(header);
+    for db in &status.databases {
+        let state = match (db.connected, db.problems.is_empty()) {
+            (false, _) => "disconnected",
+            (true, true) => "connected",
+            (true, false) => "degraded",
+        };
+        let size = db
+            .disk_usage
+            .as_ref()
+            .map(|usage| format_bytes(usage.file_bytes.max(usage.page_count * usage.page_size)))
+            .unwrap_or_else(|| "-".to_string());
+        let mut row = vec![
+            db.name.clone(),
+            state.to_string(),
+            format!("{}/{}", db.active_connections, db.max_connections),
+            size,
+        ];
+        if detailed {
+            row.extend([
+                db.requests_total.to_string(),
+                db.queries_total.to_string(),
+                db.rows_written_total.to_string(),
+                format_last_write(db.last_write_time),
+                db.errors_total.to_string(),
+            ]);
+        }
+        builder.push_record(row);
+    }
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    text.push_str(&format!("{}\n", table));
+    text
+}
+
+fn status_json(status: &ServerStatusResponse) -> serde_json::Value {
+    let databases: Vec<serde_json::Value> = status
+        .databases
+        .iter()
+        .map(|db| {
+            serde_json::json!({
+                "name": db.name,
+                "url": db.url,
+                "connected": db.connected,
+                "connection_time": db.connection_time,
+                "active_connections": db.active_connections,
+                "idle_connections": db.idle_connections,
+                "max_connections": db.max_connections,
+                "requests_total": db.requests_total,
+                "active_queries": db.active_queries,
+                "queries_total": db.queries_total,
+                "rows_written_total": db.rows_written_total,
+                "last_write_time": db.last_write_time,
+                "errors_total": db.errors_total,
+                "problems": db.problems,
+                "disk_usage": db.disk_usage.as_ref().map(disk_usage_json),
+            })
+        })
+        .collect();
+    let slow_queries: Vec<serde_json::Value> = status
+        .slow_queries
+        .iter()
+        .map(|query| {
+            serde_json::json!({
+                "database": query.database,
+                "sql": query.sql,
+                "duration_ms": query.duration_ms,
+                "finished_at": query.finished_at,
+                "request_id": query.request_id,
+                "plan": query.plan,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "server_running": status.server_running,
+        "uptime_seconds": status.uptime_seconds,
+        "databases": databases,
+        "slow_queries": slow_queries,
+    })
+}
+
+pub async fn database_info(server_address: String, database: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let info = client
+        .get_database_info(DatabaseInfoRequest { database: database.unwrap_or_default() })
+        .await?
+        .into_inner();
+
+    if output::enabled() {
+        output::print(&serde_json::json!({
+            "name": info.name,
+            "backend": info.backend,
+            "url": info.url,
+            "replicas": info.replicas,
+            "problems": info.problems,
+            "file_path": Some(&info.file_path).filter(|path| !path.is_empty()),
+            "disk_usage": info.disk_usage.as_ref().map(disk_usage_json),
+            "journal_mode": info.journal_mode,
+            "schema_name": Some(&info.schema_name).filter(|name| !name.is_empty()),
+            "schema_version": Some(&info.schema_version).filter(|version| !version.is_empty()),
+            "table_count": info.table_count,
+            "connection_time": info.connection_time,
+            "connection_uptime_seconds": info.connection_uptime_seconds,
+        }));
+        return Ok(());
+    }
+
+    println!("📊 Database: {}", info.name);
+    println!("   Backend: {}", info.backend);
+    println!("   URL: {}", info.url);
+    for replica in &info.replicas {
+        println!("   Replica: {}", replica);
+    }
+    for problem in &info.problems {
+        println!("   ⚠️  Degraded: {}", problem);
+    }
+    println!("   File: {}", if info.file_path.is_empty() { "(in memory)" } else { &info.file_path });
+    if let Some(usage) = &info.disk_usage {
+        println!("   Disk Usage: {}", format_disk_usage(usage));
+    }
+    println!("   Journal Mode: {}", info.journal_mode);
+    if info.schema_name.is_empty() {
+        println!("   Schema: (none applied)");
     } else {
-        eprintln!("❌ {}", result.message);
-        std::process::exit(1);
+        println!("   Schema: {} version {}", info.schema_name, info.schema_version);
     }
-    
+    println!("   Tables: {}", info.table_count);
+    if info.connection_time > 0 {
+        let connected = chrono::DateTime::from_timestamp(info.connection_time, 0).unwrap_or_else(chrono::Utc::now);
+        println!(
+            "   Connected: {} (up {} seconds)",
+            connected.format("%Y-%m-%d %H:%M:%S UTC"),
+            info.connection_uptime_seconds
+        );
+    }
+
     Ok(())
 }
 
-pub async fn create_table(
-    server_address: String,
-    table_name: String,
-    columns_json: String,
-    database: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+/// Show a PRAGMA, or change it with NAME=VALUE
+pub async fn pragma(server_address: String, setting: String, database: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let database = database.unwrap_or_default();
+
+    match setting.split_once('=') {
+        Some((name, value)) => {
+            let response = client
+                .set_pragma(SetPragmaRequest { database, name: name.trim().to_string(), value: value.trim().to_string() })
+                .await?
+                .into_inner();
+            output::success(
+                &format!("✅ {} = {}", response.name, response.value),
+                serde_json::json!({ "name": response.name, "value": response.value }),
+            );
+        }
+        None => {
+            let response = client
+                .get_pragma(GetPragmaRequest { database, name: setting.trim().to_string() })
+                .await?
+                .into_inner();
+            if output::enabled() {
+                output::print(&serde_json::json!({ "name": response.name, "value": response.value }));
+            } else {
+                println!("{} = {}", response.name, response.value);
+            }
+        }
+    }
+    Ok(())
+}
 
-    // Parse column definitions from JSON
-    let column_defs: Vec<serde_json::Value> = serde_json::from_str(&columns_json)?;
+fn disk_usage_json(usage: &DiskUsage) -> serde_json::Value {
+    serde_json::json!({
+        "file_bytes": usage.file_bytes,
+        "wal_bytes": usage.wal_bytes,
+        "page_size": usage.page_size,
+        "page_count": usage.page_count,
+        "free_pages": usage.free_pages,
+    })
+}
 
-    let mut columns = Vec::new();
-    for col in column_defs {
-        let name = col["name"].as_str().ok_or("Missing column name")?;
-        let type_str = col["type"].as_str().ok_or("Missing column type")?;
+/// "1.2 MB (300 pages x 4.0 KB, 12 free), WAL 32.0 KB"
+fn format_disk_usage(usage: &DiskUsage) -> String {
+    let mut text = format!(
+        "{} ({} pages x {}, {} free)",
+        format_bytes(usage.file_bytes.max(usage.page_count * usage.page_size)),
+        usage.page_count,
+        format_bytes(usage.page_size),
+        usage.free_pages
+    );
+    if usage.wal_bytes > 0 {
+        text.push_str(&format!(", WAL {}", format_bytes(usage.wal_bytes)));
+    }
+    text
+}
 
-        let data_type = match type_str.to_uppercase().as_str() {
-            "INTEGER" => DataType::Integer,
-            "REAL" => DataType::Real,
-            "TEXT" => DataType::Text,
-            "BLOB" => DataType::Blob,
-            "BOOLEAN" => DataType::Boolean,
-            "TIMESTAMP" => DataType::Timestamp,
-            _ => return Err(format!("Unknown data type: {}", type_str).into()),
-        };
+/// Human-readable byte count using binary units
+pub fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
 
-        columns.push(ColumnDefinition {
-            name: name.to_string(),
-            r#type: data_type as i32,
-            nullable: col["nullable"].as_bool().unwrap_or(true),
-            primary_key: col["primary_key"].as_bool().unwrap_or(false),
-            unique: col["unique"].as_bool().unwrap_or(false),
-            default_value: col["default_value"].as_str().unwrap_or("").to_string(),
-        });
+pub async fn ping(server_address: String, count: u32, interval: std::time::Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let started = std::time::Instant::now();
+    let mut client = client::connect(server_address.clone()).await?;
+    let connect_ms = started.elapsed().as_secs_f64() * 1000.0;
+    output::progress(&format!("Connected to {} in {:.2} ms", server_address, connect_ms));
+
+    let mut times = Vec::new();
+    let (mut version, mut uptime_seconds) = (String::new(), 0);
+    for i in 0..count {
+        if i > 0 {
+            tokio::time::sleep(interval).await;
+        }
+        let sent = std::time::Instant::now();
+        let response = client.ping(PingRequest {}).await?.into_inner();
+        let millis = sent.elapsed().as_secs_f64() * 1000.0;
+        output::progress(&format!(
+            "Pong from {} (version {}, up {}s): time={:.2} ms",
+            server_address, response.version, response.uptime_seconds, millis
+        ));
+        version = response.version;
+        uptime_seconds = response.uptime_seconds;
+        times.push(millis);
     }
 
-    let request = CreateTableRequest {
-        table_name,
-        columns,
+    let min = times.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = times.iter().cloned().fold(0.0, f64::max);
+    let avg = times.iter().sum::<f64>() / times.len() as f64;
+    if output::enabled() {
+        output::print(&serde_json::json!({
+            "server": server_address,
+            "version": version,
+            "uptime_seconds": uptime_seconds,
+            "connect_ms": connect_ms,
+            "times_ms": times,
+            "min_ms": min,
+            "avg_ms": avg,
+            "max_ms": max,
+        }));
+    } else if times.len() > 1 {
+        println!("{} pings: min/avg/max = {:.2}/{:.2}/{:.2} ms", times.len(), min, avg, max);
+    }
+
+    Ok(())
+}
+
+pub async fn server_metrics(server_address: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let response = client.get_metrics(MetricsRequest {}).await?;
+    let metrics = response.into_inner().metrics;
+
+    if output::enabled() {
+        let metrics: Vec<serde_json::Value> = metrics
+            .iter()
+            .map(|metric| serde_json::json!({ "name": metric.name, "labels": metric.labels, "value": metric.value }))
+            .collect();
+        output::print(&serde_json::Value::Array(metrics));
+        return Ok(());
+    }
+
+    if metrics.is_empty() {
+        println!("No metrics reported");
+        return Ok(());
+    }
+
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Metric", "Labels", "Value"]);
+    for metric in metrics {
+        let mut labels: Vec<String> = metric
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        labels.sort();
+        builder.push_record([metric.name, labels.join(","), metric.value.to_string()]);
+    }
+
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+
+    Ok(())
+}
+
+pub async fn list_jobs(server_address: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let jobs = client.list_jobs(ListJobsRequest {}).await?.into_inner().jobs;
+    if output::enabled() {
+        let jobs: Vec<serde_json::Value> = jobs
+            .iter()
+            .map(|job| {
+                serde_json::json!({
+                    "name": job.name,
+                    "schedule": job.schedule,
+                    "sql": job.sql,
+                    "database": job.database,
+                    "next_run": job.next_run,
+                    "from_config": job.from_config,
+                })
+            })
+            .collect();
+        output::print(&serde_json::Value::Array(jobs));
+        return Ok(());
+    }
+    if jobs.is_empty() {
+        println!("No jobs registered");
+        return Ok(());
+    }
+
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Name", "Schedule", "Database", "Next Run", "Source", "SQL"]);
+    for job in jobs {
+        let source = if job.from_config { "config" } else { "runtime" };
+        builder.push_record([
+            job.name,
+            job.schedule,
+            job.database,
+            format_timestamp(job.next_run),
+            source.to_string(),
+            job.sql,
+        ]);
+    }
+
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+
+    Ok(())
+}
+
+pub async fn add_job(
+    server_address: String,
+    name: String,
+    schedule: String,
+    sql: String,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = AddJobRequest {
+        name,
+        schedule,
+        sql,
         database: database.unwrap_or_default(),
     };
+    let result = client.add_job(request).await?.into_inner();
 
-    let response = client.create_table(request).await?;
-    let inner = response.into_inner();
+    output::success(
+        &format!("✅ {}\n   Next run: {}", result.message, format_timestamp(result.next_run)),
+        serde_json::json!({ "message": result.message, "next_run": result.next_run }),
+    );
 
-    if inner.success {
-        println!("{}", inner.message);
-    } else {
-        eprintln!("Failed to create table: {}", inner.message);
-    }
+    Ok(())
+}
+
+pub async fn remove_job(server_address: String, name: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let result = client.remove_job(RemoveJobRequest { name }).await?.into_inner();
+    output::success(&format!("✅ {}", result.message), serde_json::json!({ "message": result.message }));
 
     Ok(())
 }
 
-pub async fn create_database(name: String) -> Result<(), Box<dyn std::error::Error>> {
-    // Check if a database with the same name (case-insensitive) already exists
-    let db_file = if name.ends_with(".db") {
-        name.clone()
-    } else {
-        format!("{}.db", name)
+pub async fn list_job_runs(
+    server_address: String,
+    job: Option<String>,
+    limit: u32,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = ListJobRunsRequest {
+        job_name: job.unwrap_or_default(),
+        database: database.unwrap_or_default(),
+        limit,
     };
-    
-    if let Ok(entries) = std::fs::read_dir(".") {
-        for entry in entries.flatten() {
-            if let Ok(file_name) = entry.file_name().into_string() {
-                if file_name.to_lowercase() == db_file.to_lowercase() {
-                    return Err(format!(
-                        "Database already exists: {} (case-insensitive match)",
-                        file_name
-                    ).into());
-                }
-            }
-        }
+    let runs = client.list_job_runs(request).await?.into_inner().runs;
+    if output::enabled() {
+        let runs: Vec<serde_json::Value> = runs
+            .iter()
+            .map(|run| {
+                serde_json::json!({
+                    "job_name": run.job_name,
+                    "started_at": run.started_at,
+                    "duration_ms": run.duration_ms,
+                    "success": run.success,
+                    "rows_affected": run.rows_affected,
+                    "message": run.message,
+                })
+            })
+            .collect();
+        output::print(&serde_json::Value::Array(runs));
+        return Ok(());
     }
-    
-    // For SQLite, we just need to ensure the directory exists
-    if let Some(parent) = Path::new(&db_file).parent() {
-        if !parent.as_os_str().is_empty() {
-            std::fs::create_dir_all(parent)?;
-        }
+    if runs.is_empty() {
+        println!("No job runs recorded");
+        return Ok(());
     }
 
-    // Connect to create the database file with create mode
-    let db_url = if name.starts_with("sqlite://") {
-        format!("{}?mode=rwc", name)
-    } else {
-        format!("sqlite://{}?mode=rwc", db_file)
-    };
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Job", "Started", "Duration", "Status", "Rows", "Message"]);
+    for run in runs {
+        let status = if run.success { "ok" } else { "failed" };
+        builder.push_record([
+            run.job_name,
+            format_timestamp(run.started_at),
+            format!("{} ms", run.duration_ms),
+            status.to_string(),
+            run.rows_affected.to_string(),
+            run.message,
+        ]);
+    }
 
-    let _db = SqliteDatabase::connect(&db_url).await?;
-    println!("Database created: {}", db_file);
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+
+    Ok(())
+}
+
+/// Format a Unix timestamp for display, "-" when unset
+fn format_timestamp(timestamp: i64) -> String {
+    if timestamp <= 0 {
+        return "-".to_string();
+    }
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub async fn save_query(
+    server_address: String,
+    name: String,
+    sql: String,
+    description: Option<String>,
+    replace: bool,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = SaveQueryRequest {
+        name,
+        sql,
+        description: description.unwrap_or_default(),
+        replace,
+        database: database.unwrap_or_default(),
+    };
+    let response = client.save_query(request).await?.into_inner();
+    output::success(&response.message, serde_json::json!({ "message": response.message }));
+
+    Ok(())
+}
+
+pub async fn list_saved_queries(
+    server_address: String,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = ListSavedQueriesRequest {
+        database: database.unwrap_or_default(),
+    };
+    let queries = client.list_saved_queries(request).await?.into_inner().queries;
+    if output::enabled() {
+        let queries: Vec<serde_json::Value> = queries
+            .iter()
+            .map(|query| {
+                serde_json::json!({
+                    "name": query.name,
+                    "sql": query.sql,
+                    "description": query.description,
+                    "parameters": query.parameters,
+                })
+            })
+            .collect();
+        output::print(&serde_json::Value::Array(queries));
+        return Ok(());
+    }
+    if queries.is_empty() {
+        println!("No saved queries");
+        return Ok(());
+    }
+
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Name", "Parameters", "Description", "SQL"]);
+    for query in queries {
+        builder.push_record([query.name, query.parameters.join(", "), query.description, query.sql]);
+    }
+
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+
+    Ok(())
+}
+
+pub async fn run_saved_query(
+    server_address: String,
+    name: String,
+    params: Vec<(String, String)>,
+    format: String,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = RunSavedQueryRequest {
+        name,
+        parameters: params
+            .into_iter()
+            .map(|(name, value)| (name, parse_key_value(&value)))
+            .collect(),
+        database: database.unwrap_or_default(),
+    };
+    let stream = client.run_saved_query(request).await?.into_inner();
+    print_query_results(stream, &format, false, &render::ColumnView::default()).await
+}
+
+pub async fn remove_saved_query(
+    server_address: String,
+    name: String,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = RemoveSavedQueryRequest {
+        name,
+        database: database.unwrap_or_default(),
+    };
+    let response = client.remove_saved_query(request).await?.into_inner();
+    output::success(&response.message, serde_json::json!({ "message": response.message }));
+
+    Ok(())
+}
+
+pub async fn open_session(server_address: String, database: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = OpenSessionRequest { database: database.unwrap_or_default() };
+    let response = client.open_session(request).await?.into_inner();
+    if output::enabled() {
+        output::print(&serde_json::json!({
+            "session_id": response.session_id,
+            "database": response.database,
+            "idle_timeout_seconds": response.idle_timeout_seconds,
+        }));
+    } else {
+        // Just the ID, for `export DATASINK_SESSION=$(datasink session open)`
+        println!("{}", response.session_id);
+    }
+    Ok(())
+}
+
+pub async fn close_session(server_address: String, id: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let session_id = match id.or_else(|| std::env::var(client::SESSION_ENV).ok().filter(|id| !id.is_empty())) {
+        Some(id) => id,
+        None => {
+            let message = format!("No session ID given and {} is not set", client::SESSION_ENV);
+            return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+        }
+    };
+    let mut client = client::connect(server_address).await?;
+
+    let response = client.close_session(CloseSessionRequest { session_id: session_id.clone() }).await?.into_inner();
+    if !response.closed {
+        let message = format!("Session '{}' not found or already closed", session_id);
+        return Err(CliError::new(ErrorKind::NotFound, message).into());
+    }
+    output::success(&format!("Session '{}' closed", session_id), serde_json::json!({ "session_id": session_id, "closed": true }));
+    Ok(())
+}
+
+pub async fn add_database(
+    server_address: String,
+    name: String,
+    url: String,
+    replicas: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    
+    let request = AddDatabaseRequest { name: name.clone(), url, replicas };
+    let response = client.add_database(request).await?;
+    let result = response.into_inner();
+    
+    if result.success {
+        output::success(&format!("✅ {}", result.message), serde_json::json!({ "message": result.message }));
+    } else {
+        return Err(CliError::new(ErrorKind::Other, format!("❌ {}", result.message)).into());
+    }
+    
+    Ok(())
+}
+
+pub async fn remove_database(server_address: String, name: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let response = client.remove_database(RemoveDatabaseRequest { name }).await?;
+    let result = response.into_inner();
+    output::success(&format!("✅ {}", result.message), serde_json::json!({ "message": result.message }));
+    Ok(())
+}
+
+pub async fn provision_database(
+    server_address: String,
+    name: String,
+    template: Option<String>,
+    schema_file: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = match (template, schema_file) {
+        (Some(template), _) => provision_database_request::Schema::Template(template),
+        (None, Some(path)) => provision_database_request::Schema::SchemaToml(
+            std::fs::read_to_string(&path).map_err(|e| CliError::new(ErrorKind::NotFound, format!("{}: {}", path, e)))?,
+        ),
+        (None, None) => return Err(CliError::new(ErrorKind::InvalidInput, "--template or --schema is required").into()),
+    };
+
+    let mut client = client::connect(server_address).await?;
+    let result = client
+        .provision_database(ProvisionDatabaseRequest { name, schema: Some(schema) })
+        .await?
+        .into_inner();
+
+    output::success(
+        &format!(
+            "✅ Database '{}' provisioned with schema '{}' version {}\n   File: {}",
+            result.name, result.schema_name, result.schema_version, result.file_path
+        ),
+        serde_json::json!({
+            "message": format!("Database '{}' provisioned", result.name),
+            "database": result.name,
+            "file": result.file_path,
+            "schema_name": result.schema_name,
+            "schema_version": result.schema_version,
+        }),
+    );
+    Ok(())
+}
+
+pub async fn clone_database(
+    server_address: String,
+    source: String,
+    name: String,
+    schema_only: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let result = client
+        .clone_database(CloneDatabaseRequest { source: source.clone(), name, schema_only })
+        .await?
+        .into_inner();
+
+    let what = if schema_only { "schema" } else { "data" };
+    output::success(
+        &format!(
+            "✅ Database '{}' cloned from '{}' ({})
+   File: {} ({})",
+            result.name, source, what, result.file_path, format_bytes(result.file_bytes)
+        ),
+        serde_json::json!({
+            "message": format!("Database '{}' cloned from '{}'", result.name, source),
+            "database": result.name,
+            "source": source,
+            "schema_only": schema_only,
+            "file": result.file_path,
+            "file_bytes": result.file_bytes,
+        }),
+    );
+    Ok(())
+}
+
+pub async fn copy_table(
+    server_address: String,
+    (source_database, source_table): (String, String),
+    (target_database, target_table): (String, String),
+    where_clause: Option<String>,
+    create: bool,
+    batch_size: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let request = CopyTableRequest {
+        source_database: source_database.clone(),
+        source_table: source_table.clone(),
+        target_database: target_database.clone(),
+        target_table: target_table.clone(),
+        where_clause: where_clause.unwrap_or_default(),
+        create,
+        batch_size,
+    };
+    let result = client.copy_table(request).await?.into_inner();
+
+    let qualified = |database: &str, table: &str| {
+        if database.is_empty() { table.to_string() } else { format!("{}.{}", database, table) }
+    };
+    let source = qualified(&source_database, &source_table);
+    let target = qualified(&target_database, &target_table);
+    let created = if result.created { " (created)" } else { "" };
+    output::success(
+        &format!("✅ Copied {} row(s) from {} to {}{}", result.rows_copied, source, target, created),
+        serde_json::json!({
+            "message": format!("Copied {} row(s) from {} to {}", result.rows_copied, source, target),
+            "source": source,
+            "target": target,
+            "rows_copied": result.rows_copied,
+            "created": result.created,
+        }),
+    );
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn diff_table(
+    server_address: String,
+    source: String,
+    target: String,
+    table_name: String,
+    key_columns: Vec<String>,
+    where_clause: Option<String>,
+    include_rows: bool,
+    limit: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let request = DiffTableRequest {
+        source_database: source.clone(),
+        target_database: target.clone(),
+        table_name: table_name.clone(),
+        key_columns,
+        where_clause: where_clause.unwrap_or_default(),
+        include_rows,
+        limit,
+    };
+    let result = client.diff_table(request).await?.into_inner();
+    let columns: Vec<String> = result.columns.iter().map(|c| c.name.clone()).collect();
+    let change_name = |change: i32| match RowChange::try_from(change).unwrap_or(RowChange::Changed) {
+        RowChange::Added => "added",
+        RowChange::Removed => "removed",
+        RowChange::Changed => "changed",
+    };
+
+    if output::enabled() {
+        let row_json = |row: Option<crate::proto::common::Row>| {
+            row.map(|row| {
+                let values = columns.iter().cloned().zip(row.values.into_iter().map(proto_value_to_json));
+                serde_json::Value::Object(values.collect())
+            })
+        };
+        let rows: Vec<serde_json::Value> = result
+            .rows
+            .into_iter()
+            .map(|row| {
+                let key = result.key_columns.iter().cloned().zip(row.key.into_iter().map(proto_value_to_json));
+                serde_json::json!({
+                    "change": change_name(row.change),
+                    "key": serde_json::Value::Object(key.collect()),
+                    "source": row_json(row.source_row),
+                    "target": row_json(row.target_row),
+                    "changed_columns": row.changed_columns,
+                })
+            })
+            .collect();
+        output::print(&serde_json::json!({
+            "table": table_name,
+            "key_columns": result.key_columns,
+            "added": result.added,
+            "removed": result.removed,
+            "changed": result.changed,
+            "unchanged": result.unchanged,
+            "rows": rows,
+            "truncated": result.truncated,
+        }));
+        return Ok(());
+    }
+
+    println!("{} (key: {}) in '{}' compared with '{}'", table_name, result.key_columns.join(", "), source, target);
+    println!(
+        "  {} added, {} removed, {} changed, {} unchanged",
+        result.added, result.removed, result.changed, result.unchanged
+    );
+    if result.rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Change", "Key", "Values"]);
+    for row in result.rows {
+        let key = row.key.into_iter().map(proto_value_to_string).collect::<Vec<_>>().join(", ");
+        let values = match (row.source_row, row.target_row) {
+            (Some(source_row), Some(target_row)) => {
+                let pairs = columns.iter().zip(source_row.values.into_iter().zip(target_row.values));
+                pairs
+                    .filter(|(name, _)| row.changed_columns.contains(name))
+                    .map(|(name, (old, new))| format!("{}: {} → {}", name, proto_value_to_string(old), proto_value_to_string(new)))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            (Some(only), None) | (None, Some(only)) => columns
+                .iter()
+                .zip(only.values)
+                .map(|(name, value)| format!("{}: {}", name, proto_value_to_string(value)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            (None, None) => String::new(),
+        };
+        builder.push_record([change_name(row.change).to_string(), key, values]);
+    }
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+    if result.truncated {
+        println!("(more differing rows than --limit; only the first are listed)");
+    }
+    Ok(())
+}
+
+pub async fn create_table(
+    server_address: String,
+    table_name: String,
+    columns_json: String,
+    unique: Vec<String>,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    // Parse column definitions from JSON
+    let column_defs: Vec<serde_json::Value> = serde_json::from_str(&columns_json)?;
+
+    let mut columns = Vec::new();
+    for col in column_defs {
+        let name = col["name"].as_str().ok_or("Missing column name")?;
+        let type_str = col["type"].as_str().ok_or("Missing column type")?;
+
+        let data_type = match type_str.to_uppercase().as_str() {
+            "INTEGER" => DataType::Integer,
+            "REAL" => DataType::Real,
+            "TEXT" => DataType::Text,
+            "BLOB" => DataType::Blob,
+            "BOOLEAN" => DataType::Boolean,
+            "TIMESTAMP" => DataType::Timestamp,
+            _ => return Err(CliError::new(ErrorKind::InvalidInput, format!("Unknown data type: {}", type_str)).into()),
+        };
+
+        columns.push(ColumnDefinition {
+            name: name.to_string(),
+            r#type: data_type as i32,
+            nullable: col["nullable"].as_bool().unwrap_or(true),
+            primary_key: col["primary_key"].as_bool().unwrap_or(false),
+            unique: col["unique"].as_bool().unwrap_or(false),
+            default_value: col["default_value"].as_str().unwrap_or("").to_string(),
+            generated: col["generated"].as_str().unwrap_or("").to_string(),
+            generated_stored: col["generated_stored"].as_bool().unwrap_or(false),
+            collation: col["collation"].as_str().unwrap_or("").to_string(),
+            allowed_values: col["allowed_values"]
+                .as_array()
+                .map(|values| values.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                .unwrap_or_default(),
+        });
+    }
+
+    let unique = unique
+        .iter()
+        .map(|set| UniqueConstraint {
+            columns: set.split(',').map(|column| column.trim().to_string()).filter(|c| !c.is_empty()).collect(),
+        })
+        .collect();
+
+    let request = CreateTableRequest {
+        table_name,
+        columns,
+        database: database.unwrap_or_default(),
+        unique,
+    };
+
+    let response = client.create_table(request).await;
+    client::echo_sql(&response);
+    let response = response?;
+    let inner = response.into_inner();
+
+    if inner.success {
+        output::success(&inner.message, serde_json::json!({ "message": inner.message }));
+    } else {
+        return Err(CliError::new(ErrorKind::Other, format!("Failed to create table: {}", inner.message)).into());
+    }
+
+    Ok(())
+}
+
+pub async fn create_database(name: String) -> Result<(), Box<dyn std::error::Error>> {
+    // Check if a database with the same name (case-insensitive) already exists
+    let db_file = if name.ends_with(".db") {
+        name.clone()
+    } else {
+        format!("{}.db", name)
+    };
+    
+    if let Ok(entries) = std::fs::read_dir(".") {
+        for entry in entries.flatten() {
+            if let Ok(file_name) = entry.file_name().into_string() {
+                if file_name.to_lowercase() == db_file.to_lowercase() {
+                    return Err(CliError::new(
+                        ErrorKind::ConstraintViolation,
+                        format!("Database already exists: {} (case-insensitive match)", file_name),
+                    ).into());
+                }
+            }
+        }
+    }
+    
+    // For SQLite, we just need to ensure the directory exists
+    if let Some(parent) = Path::new(&db_file).parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    // Connect to create the database file with create mode
+    let db_url = if name.starts_with("sqlite://") {
+        format!("{}?mode=rwc", name)
+    } else {
+        format!("sqlite://{}?mode=rwc", db_file)
+    };
+
+    let _db = SqliteDatabase::connect(&db_url).await?;
+    output::success(
+        &format!("Database created: {}", db_file),
+        serde_json::json!({ "message": format!("Database created: {}", db_file), "file": db_file }),
+    );
+
+    Ok(())
+}
+
+/// Print the statements a schema file would run, without a database
+pub async fn schema_ddl(schema_file: String) -> Result<(), Box<dyn std::error::Error>> {
+    let schema_path = Path::new(&schema_file);
+    if !schema_path.exists() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Schema file not found: {}", schema_file)).into());
+    }
+    let schema = parser::load_schema(schema_path).await?;
+    let statements = ddl::statements(&schema, chrono::Utc::now())
+        .map_err(|e| CliError::new(ErrorKind::InvalidInput, e.to_string()))?;
+
+    if output::enabled() {
+        output::print(&serde_json::json!(statements));
+    } else {
+        print!("{}", ddl::script(&schema, &statements));
+    }
+    Ok(())
+}
+
+pub async fn schema_plan(
+    old_file: String,
+    new_file: String,
+    output_file: Option<String>,
+    reverse_file: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut schemas = Vec::new();
+    for file in [&old_file, &new_file] {
+        if !Path::new(file).exists() {
+            return Err(CliError::new(ErrorKind::NotFound, format!("Schema file not found: {}", file)).into());
+        }
+        schemas.push(parser::load_schema(Path::new(file)).await?);
+    }
+    let (old, new) = (&schemas[0], &schemas[1]);
+    let plan = plan::plan(old, new).map_err(|e| CliError::new(ErrorKind::InvalidInput, e.to_string()))?;
+
+    if !output::enabled() {
+        for warning in &plan.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+
+    let write = |path: &str, script: String| {
+        std::fs::write(path, script).map_err(|e| CliError::new(ErrorKind::Other, format!("{}: {}", path, e)))
+    };
+    if let Some(path) = &reverse_file {
+        write(path, plan::script(new, old, &plan.reverse, &plan.reverse_warnings))?;
+    }
+    let Some(path) = output_file else {
+        if output::enabled() {
+            output::print(&serde_json::json!({
+                "forward": plan.forward,
+                "reverse": plan.reverse,
+                "warnings": plan.warnings,
+            }));
+        } else {
+            print!("{}", plan::script(old, new, &plan.forward, &plan.warnings));
+        }
+        return Ok(());
+    };
+    write(&path, plan::script(old, new, &plan.forward, &plan.warnings))?;
+    output::success(
+        &format!("✅ Wrote {} statements to {}", plan.forward.len(), path),
+        serde_json::json!({
+            "message": format!("Wrote {} statements to {}", plan.forward.len(), path),
+            "file": path,
+            "reverse_file": reverse_file,
+            "statements": plan.forward.len(),
+            "warnings": plan.warnings,
+        }),
+    );
+    Ok(())
+}
+
+pub async fn create_from_schema(
+    schema_file: String,
+    database_name: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Load the schema file
+    let schema_path = Path::new(&schema_file);
+    if !schema_path.exists() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Schema file not found: {}", schema_file)).into());
+    }
+    
+    output::progress(&format!("Loading schema from: {}", schema_file));
+    let (schema, checksum) = parser::load_schema_with_checksum(schema_path).await?;
+    
+    // Determine database name
+    let db_name = database_name.unwrap_or_else(|| schema.database.name.clone());
+    let db_file = format!("{}.db", db_name);
+    let db_url = format!("sqlite://{}?mode=rwc", db_file);
+    
+    output::progress(&format!("Creating database: {}", db_file));
+    
+    // Create database directly without server
+    let db = SqliteDatabase::connect(&db_url).await?;
+
+    if let Some(applied) = db.applied_schema().await? {
+        if applied.name != schema.database.name || applied.version != schema.database.version {
+            eprintln!(
+                "Warning: {} already has schema '{}' version {} applied; applying '{}' version {}",
+                db_file, applied.name, applied.version, schema.database.name, schema.database.version
+            );
+        } else if applied.checksum != checksum {
+            eprintln!(
+                "Warning: {} was changed since version {} was applied to {} without a version bump",
+                schema_file, applied.version, db_file
+            );
+        }
+    }
+    
+    let cipher = ColumnCipher::from_env()?;
+    let mut report = |event: apply::Event| match event {
+        apply::Event::Progress(text) => output::progress(&text),
+        apply::Event::Warning(text) => eprintln!("Warning: {}", text),
+    };
+    apply::apply(&db, &schema, &checksum, cipher.as_ref(), &mut report).await?;
+
+    output::success(
+        &format!(
+            "\nDatabase '{}' created successfully from schema!\nDatabase file: {}\nSchema version: {}",
+            db_name, db_file, schema.database.version
+        ),
+        serde_json::json!({
+            "message": format!("Database '{}' created successfully from schema", db_name),
+            "database": db_name,
+            "file": db_file,
+            "schema_version": schema.database.version,
+        }),
+    );
+    
+    Ok(())
+}
+
+/// `query --watch`: how often to run the query again, and how many times
+pub struct Watch {
+    pub interval: std::time::Duration,
+    pub count: Option<u64>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn query(
+    server_address: String,
+    sql: String,
+    vars: Vec<(String, String)>,
+    format: String,
+    stats: bool,
+    view: render::ColumnView,
+    watch: Option<Watch>,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sql = if sql == "-" { read_input("-")? } else { sql };
+    let rendered = template::render(&sql, &vars).map_err(|e| CliError::new(ErrorKind::InvalidInput, e))?;
+    let mut client = client::connect(server_address).await?;
+
+    let request = QueryRequest {
+        sql: rendered.sql,
+        parameters: rendered.parameters,
+        database: database.unwrap_or_default(),
+    };
+
+    if let Some(watch) = watch {
+        return watch_query(client, request, &sql, &format, stats, &view, watch).await;
+    }
+    let stream = client.query(request).await?.into_inner();
+    print_query_results(stream, &format, stats, &view).await
+}
+
+/// Run a query every `watch.interval` until interrupted, or `watch.count`
+/// times. Tables are redrawn under a line with the interval, the query and
+/// the time, with the cells that changed since the last run highlighted;
+/// the other formats print each run's results after the previous ones, JSON
+/// as one array per line.
+async fn watch_query(
+    mut client: client::Client,
+    request: QueryRequest,
+    sql: &str,
+    format: &str,
+    stats: bool,
+    view: &render::ColumnView,
+    watch: Watch,
+) -> Result<(), Box<dyn std::error::Error>> {
+    const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+    let format = if output::enabled() { "json" } else { format };
+    let sql = sql.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut previous: Option<Vec<Vec<String>>> = None;
+    let mut runs = 0;
+
+    loop {
+        let stream = client.query(request.clone()).await?.into_inner();
+        let (columns, rows, summary) = read_query_results(stream).await?;
+        let (columns, rows) = view.select(columns, rows).map_err(|e| CliError::new(ErrorKind::InvalidInput, e))?;
+
+        match format {
+            "json" => println!("{}", serde_json::to_string(&json_rows(&columns, rows))?),
+            "csv" => print!("{}", csv_rows(&columns, rows)),
+            _ => {
+                print!(
+                    "{}Every {}s: {}    {}\n\n",
+                    CLEAR_SCREEN,
+                    watch.interval.as_secs_f64(),
+                    sql,
+                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+                );
+                if format == "vertical" && !rows.is_empty() {
+                    print!("{}", render::vertical(&columns, rows, view));
+                } else {
+                    let cells = table_cells(&columns, rows, view);
+                    let changed = previous
+                        .as_ref()
+                        .map(|before| render::changed_cells(before, &cells))
+                        .unwrap_or_default();
+                    print_table(&columns, &cells, &changed);
+                    previous = Some(cells);
+                }
+            }
+        }
+        print_query_summary(summary, stats);
+
+        runs += 1;
+        if watch.count.is_some_and(|count| runs >= count) {
+            return Ok(());
+        }
+        tokio::time::sleep(watch.interval).await;
+    }
+}
+
+/// Print a Query response stream as a table, JSON, CSV or vertically, with
+/// the columns `view` selects, followed on stderr by the stream's summary
+/// with `stats`
+async fn print_query_results(
+    stream: tonic::Streaming<QueryResponse>,
+    format: &str,
+    stats: bool,
+    view: &render::ColumnView,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let format = if output::enabled() { "json" } else { format };
+    let (columns, rows, summary) = read_query_results(stream).await?;
+    let (columns, rows) = view.select(columns, rows).map_err(|e| CliError::new(ErrorKind::InvalidInput, e))?;
+
+    // Format output
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&json_rows(&columns, rows))?),
+        "csv" => print!("{}", csv_rows(&columns, rows)),
+        "vertical" if !rows.is_empty() => print!("{}", render::vertical(&columns, rows, view)),
+        // Table format (default)
+        _ => print_table(&columns, &table_cells(&columns, rows, view), &Default::default()),
+    }
+    print_query_summary(summary, stats);
+
+    Ok(())
+}
+
+/// The columns, rows and summary of a Query response stream
+async fn read_query_results(
+    mut stream: tonic::Streaming<QueryResponse>,
+) -> Result<(Vec<Column>, Vec<Vec<Value>>, Option<QuerySummary>), Box<dyn std::error::Error>> {
+    let mut columns = Vec::new();
+    let mut rows = Vec::new();
+    let mut summary = None;
+
+    while let Some(response) = stream.next().await {
+        match response? {
+            QueryResponse {
+                response: Some(query_response::Response::ResultSet(result_set)),
+            } => {
+                if !result_set.columns.is_empty() {
+                    columns = result_set.columns;
+                }
+                for row in result_set.rows {
+                    rows.push(row.values);
+                }
+            }
+            QueryResponse {
+                response: Some(query_response::Response::Error(error)),
+            } => {
+                return Err(CliError::query(&error).into());
+            }
+            QueryResponse {
+                response: Some(query_response::Response::Summary(s)),
+            } => summary = Some(s),
+            _ => {}
+        }
+    }
+
+    Ok((columns, rows, summary))
+}
+
+/// Rows as JSON objects keyed by column name
+fn json_rows(columns: &[Column], rows: Vec<Vec<Value>>) -> serde_json::Value {
+    let mut json_rows = Vec::new();
+    for row in rows {
+        let mut json_row = serde_json::Map::new();
+        for (i, value) in row.into_iter().enumerate() {
+            if let Some(col) = columns.get(i) {
+                json_row.insert(col.name.clone(), render::json(value, Some(col)));
+            }
+        }
+        json_rows.push(serde_json::Value::Object(json_row));
+    }
+    serde_json::Value::Array(json_rows)
+}
+
+/// A header line of column names, then a line per row
+fn csv_rows(columns: &[Column], rows: Vec<Vec<Value>>) -> String {
+    let mut text = columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(",");
+    text.push('\n');
+    for row in rows {
+        let values: Vec<String> = row
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| render::text(value, columns.get(i), render::Format::Csv))
+            .collect();
+        text.push_str(&values.join(","));
+        text.push('\n');
+    }
+    text
+}
+
+/// Rows as the text of table cells
+pub fn table_cells(columns: &[Column], rows: Vec<Vec<Value>>, view: &render::ColumnView) -> Vec<Vec<String>> {
+    rows.into_iter()
+        .map(|row| {
+            row.into_iter()
+                .enumerate()
+                .map(|(i, value)| view.fit(render::text(value, columns.get(i), render::Format::Table)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Print rows as a table using tabled, with the `changed` (row, column)
+/// cells highlighted
+pub fn print_table(columns: &[Column], rows: &[Vec<String>], changed: &HashSet<(usize, usize)>) {
+    if !columns.is_empty() && !rows.is_empty() {
+        let mut table_builder = TableBuilder::default();
+        table_builder.push_record(columns.iter().map(|c| c.name.clone()));
+        for row in rows {
+            table_builder.push_record(row.clone());
+        }
+
+        let mut table = table_builder.build();
+        table.with(Style::rounded())
+            .with(Modify::new(Segment::all()).with(Alignment::left()));
+
+        if changed.is_empty() {
+            println!("{}", table);
+        } else {
+            print!("{}", render::highlight_cells(&table.to_string(), rows, changed));
+        }
+    } else if columns.is_empty() {
+        println!("No results returned");
+    } else {
+        println!("Empty result set");
+    }
+}
+
+/// On stderr, the summary with `stats`, or otherwise a warning if the
+/// server cut the result short
+fn print_query_summary(summary: Option<QuerySummary>, stats: bool) {
+    match summary {
+        Some(summary) if stats => eprintln!("{}", format_query_summary(&summary)),
+        Some(summary) if summary.truncated => eprintln!(
+            "Warning: only the first {} rows were returned (server row limit)",
+            summary.rows_returned
+        ),
+        _ => {}
+    }
+}
+
+/// "3 rows in 1.25 ms", noting a cached or truncated result
+fn format_query_summary(summary: &QuerySummary) -> String {
+    let mut text = format!(
+        "{} row{} in {:.2} ms",
+        summary.rows_returned,
+        if summary.rows_returned == 1 { "" } else { "s" },
+        summary.execution_time_us as f64 / 1000.0
+    );
+    if summary.cached {
+        text.push_str(" (cached)");
+    }
+    if summary.truncated {
+        text.push_str(" (truncated by the server's row limit)");
+    }
+    text
+}
+
+pub async fn get_row(
+    server_address: String,
+    table_name: String,
+    key: Vec<String>,
+    format: String,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = GetRowRequest {
+        table_name,
+        key: key.iter().map(|k| parse_key_value(k)).collect(),
+        database: database.unwrap_or_default(),
+    };
+
+    let response = client.get_row(request).await?.into_inner();
+    let values = response.row.map(|row| row.values).unwrap_or_default();
+
+    let format = if output::enabled() { "json" } else { format.as_str() };
+    match format {
+        "json" => {
+            let mut json_row = serde_json::Map::new();
+            for (col, value) in response.columns.iter().zip(values) {
+                json_row.insert(col.name.clone(), render::json(value, Some(col)));
+            }
+            println!("{}", serde_json::to_string_pretty(&serde_json::Value::Object(json_row))?);
+        }
+        _ => {
+            // One line per column reads better than a single wide row
+            let mut table_builder = TableBuilder::default();
+            table_builder.push_record(vec!["Column".to_string(), "Value".to_string()]);
+            for (col, value) in response.columns.iter().zip(values) {
+                table_builder.push_record(vec![col.name.clone(), render::text(value, Some(col), render::Format::Table)]);
+            }
+
+            let mut table = table_builder.build();
+            table.with(Style::rounded())
+                .with(Modify::new(Segment::all()).with(Alignment::left()));
+            println!("{}", table);
+        }
+    }
+
+    Ok(())
+}
+
+/// How `datasink insert` writes a row given as an argument
+pub struct InsertOptions {
+    /// "abort" or "ignore"
+    pub on_conflict: String,
+    pub returning: Option<Returning>,
+    /// Output format of the `returning` row
+    pub format: String,
+    /// Convert text values to their columns' types
+    pub coerce: bool,
+}
+
+pub async fn insert(
+    server_address: String,
+    table_name: String,
+    data_json: String,
+    options: InsertOptions,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let mut data: serde_json::Value = serde_json::from_str(&data_json)?;
+    let on_conflict = match options.on_conflict.as_str() {
+        "ignore" => OnConflict::Ignore,
+        _ => OnConflict::Abort,
+    };
+    let returning = options.returning;
+    if let serde_json::Value::Array(rows) = data {
+        if returning.is_some() {
+            return Err(CliError::new(ErrorKind::InvalidInput, "--returning works with a single row, not an array").into());
+        }
+        return insert_rows(&mut client, table_name, rows, on_conflict, options.coerce, database).await;
+    }
+    let database = database.unwrap_or_default();
+    let schema = check_values(&mut client, &table_name, &database, &mut data, false, options.coerce).await?;
+    let values = json_to_proto_values(data)?;
+
+    // Check the columns first: SQLite reads an unknown quoted name as a string
+    if let Some(Returning::Columns(columns)) = &returning {
+        if let Some(missing) = columns.iter().find(|c| !schema.iter().any(|s| s.name.eq_ignore_ascii_case(c))) {
+            let message = format!("Table '{}' has no column '{}'", table_name, missing);
+            return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+        }
+    }
+    let request = InsertRequest {
+        table_name: table_name.clone(),
+        values,
+        database: database.clone(),
+        on_conflict: on_conflict as i32,
+    };
+
+    let response = client.insert(request).await;
+    client::echo_sql(&response);
+    let response = response?;
+    let inner = response.into_inner();
+
+    let fields = serde_json::json!({
+        "inserted": inner.inserted,
+        "inserted_id": inner.inserted_id,
+        "message": inner.message,
+    });
+    if inner.success && !inner.inserted {
+        output::success("Row already exists; insert skipped.", fields);
+    } else if let (true, Some(columns)) = (inner.success, returning) {
+        // Read the row back so defaults and generated values show as stored
+        let request = QueryRequest {
+            sql: returning_query(&table_name, &columns),
+            parameters: HashMap::from([("id".to_string(), Value { value: Some(value::Value::IntValue(inner.inserted_id)) })]),
+            database,
+        };
+        let stream = client.query(request).await?.into_inner();
+        print_query_results(stream, &options.format, false, &render::ColumnView::default()).await?;
+    } else if inner.success {
+        output::success(&format!("Insert successful. ID: {}", inner.inserted_id), fields);
+    } else {
+        return Err(CliError::new(ErrorKind::Other, format!("Insert failed: {}", inner.message)).into());
+    }
+
+    Ok(())
+}
+
+/// Check a row's values against the table's column types and NOT NULL
+/// constraints before sending them, so a mismatch is reported by column
+/// rather than as a SQLite error, converting text values first with
+/// `coerce`. `partial` values (an update's) need not set every NOT NULL
+/// column. Returns the table's columns.
+async fn check_values(
+    client: &mut client::Client,
+    table: &str,
+    database: &str,
+    row: &mut serde_json::Value,
+    partial: bool,
+    coerce: bool,
+) -> Result<Vec<bulk::SchemaColumn>, Box<dyn std::error::Error>> {
+    let columns = bulk::fetch_schema(client, table, database).await?;
+    if columns.is_empty() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", table)).into());
+    }
+    if coerce {
+        bulk::coerce_rows(&columns, std::slice::from_mut(row));
+    }
+    let problems = bulk::row_problems(table, &columns, row, partial);
+    if !problems.is_empty() {
+        let message = format!("Invalid values for table '{}': {}", table, problems.join("; "));
+        return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+    }
+    Ok(columns)
+}
+
+/// Columns of an inserted row to print with `insert --returning`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Returning {
+    All,
+    Columns(Vec<String>),
+}
+
+/// Query for the `--returning` columns of the row with rowid `:id`
+fn returning_query(table: &str, returning: &Returning) -> String {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let select = match returning {
+        Returning::All => "*".to_string(),
+        Returning::Columns(columns) => columns.iter().map(|column| quote(column)).collect::<Vec<_>>().join(", "),
+    };
+    format!("SELECT {} FROM {} WHERE rowid = :id", select, quote(table))
+}
+
+/// Insert the rows of a JSON array argument in one BatchInsert, so they
+/// commit or fail together
+async fn insert_rows(
+    client: &mut client::Client,
+    table: String,
+    mut rows: Vec<serde_json::Value>,
+    on_conflict: OnConflict,
+    coerce: bool,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if rows.is_empty() {
+        return Err(CliError::new(ErrorKind::InvalidInput, "The JSON array has no rows to insert").into());
+    }
+    let options = bulk::BulkInsertOptions {
+        table,
+        path: String::new(),
+        batch_size: rows.len(),
+        jobs: 1,
+        on_conflict,
+        coerce,
+        database: database.unwrap_or_default(),
+    };
+    let columns = bulk::fetch_schema(client, &options.table, &options.database).await?;
+    if columns.is_empty() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", options.table)).into());
+    }
+    if coerce {
+        bulk::coerce_rows(&columns, &mut rows);
+    }
+    if let Err(problems) = bulk::validate_rows(&options.table, &columns, &rows) {
+        eprintln!("{}", problems);
+        let message = format!("Rows do not match table '{}'; nothing was inserted", options.table);
+        return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+    }
+
+    let response = client.batch_insert(bulk::batch_request(&options, &rows)).await;
+    client::echo_sql(&response);
+    let inner = response?.into_inner();
+    output::success(
+        &insert_rows_summary(inner.inserted_count, inner.skipped_count, inner.first_inserted_id, inner.last_inserted_id),
+        serde_json::json!({
+            "inserted_count": inner.inserted_count,
+            "skipped_count": inner.skipped_count,
+            "first_id": inner.first_inserted_id,
+            "last_id": inner.last_inserted_id,
+            "message": inner.message,
+        }),
+    );
+    Ok(())
+}
+
+/// "Inserted 3 rows (1 skipped). First ID: 4, last ID: 6"
+fn insert_rows_summary(inserted: i64, skipped: i64, first_id: i64, last_id: i64) -> String {
+    let mut text = format!("Inserted {} row{}", inserted, if inserted == 1 { "" } else { "s" });
+    if skipped > 0 {
+        text.push_str(&format!(" ({} skipped; already exist)", skipped));
+    }
+    match inserted {
+        0 => {}
+        1 => text.push_str(&format!(". ID: {}", first_id)),
+        _ => text.push_str(&format!(". First ID: {}, last ID: {}", first_id, last_id)),
+    }
+    text
+}
+
+pub async fn update(
+    server_address: String,
+    table_name: String,
+    data_json: String,
+    where_clause: String,
+    coerce: bool,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let mut data: serde_json::Value = serde_json::from_str(&data_json)?;
+    let database = database.unwrap_or_default();
+    check_values(&mut client, &table_name, &database, &mut data, true, coerce).await?;
+    let values = json_to_proto_values(data)?;
+
+    let request = UpdateRequest {
+        table_name,
+        values,
+        where_clause,
+        database,
+    };
+
+    let response = client.update(request).await;
+    client::echo_sql(&response);
+    let response = response?;
+    let inner = response.into_inner();
+
+    if inner.success {
+        output::success(
+            &inner.message,
+            serde_json::json!({ "message": inner.message, "affected_rows": inner.affected_rows }),
+        );
+    } else {
+        return Err(CliError::new(ErrorKind::Other, format!("Update failed: {}", inner.message)).into());
+    }
+
+    Ok(())
+}
+
+pub async fn delete(
+    server_address: String,
+    table_name: String,
+    where_clause: String,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = DeleteRequest {
+        table_name,
+        where_clause,
+        database: database.unwrap_or_default(),
+    };
+
+    let response = client.delete(request).await;
+    client::echo_sql(&response);
+    let response = response?;
+    let inner = response.into_inner();
+
+    if inner.success {
+        output::success(
+            &inner.message,
+            serde_json::json!({ "message": inner.message, "affected_rows": inner.affected_rows }),
+        );
+    } else {
+        return Err(CliError::new(ErrorKind::Other, format!("Delete failed: {}", inner.message)).into());
+    }
+
+    Ok(())
+}
+
+pub async fn truncate(
+    server_address: String,
+    table_name: String,
+    database: Option<String>,
+    force: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !force && !confirm(&format!("Delete ALL rows from table '{}'?", table_name))? {
+        return Err(CliError::new(ErrorKind::Other, "Aborted").into());
+    }
+
+    let mut client = client::connect(server_address).await?;
+
+    let request = TruncateTableRequest {
+        table_name,
+        database: database.unwrap_or_default(),
+    };
+
+    let response = client.truncate_table(request).await?;
+    let inner = response.into_inner();
+
+    if inner.success {
+        output::success(&inner.message, serde_json::json!({ "message": inner.message }));
+    } else {
+        return Err(CliError::new(ErrorKind::Other, format!("Truncate failed: {}", inner.message)).into());
+    }
+
+    Ok(())
+}
+
+pub async fn dedupe(
+    server_address: String,
+    table_name: String,
+    columns: Vec<String>,
+    keep: KeepRow,
+    dry_run: bool,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let (keep, keep_column) = match keep {
+        KeepRow::First => (DedupeKeep::First, String::new()),
+        KeepRow::Last => (DedupeKeep::Last, String::new()),
+        KeepRow::Newest(column) => (DedupeKeep::Newest, column),
+        KeepRow::Oldest(column) => (DedupeKeep::Oldest, column),
+    };
+    let request = DedupeRequest {
+        table_name: table_name.clone(),
+        columns,
+        keep: keep as i32,
+        keep_column,
+        dry_run,
+        database: database.unwrap_or_default(),
+    };
 
+    let response = client.dedupe(request).await?.into_inner();
+    let message = if response.applied {
+        format!(
+            "Removed {} duplicate rows from '{}' ({} groups)",
+            response.rows_removed, table_name, response.groups
+        )
+    } else {
+        format!(
+            "Would remove {} duplicate rows from '{}' ({} groups)",
+            response.rows_removed, table_name, response.groups
+        )
+    };
+    output::success(
+        &message,
+        serde_json::json!({
+            "table": table_name,
+            "groups": response.groups,
+            "rows_removed": response.rows_removed,
+            "applied": response.applied,
+        }),
+    );
     Ok(())
 }
 
-pub async fn create_from_schema(
-    schema_file: String,
-    database_name: Option<String>,
+pub async fn create_search_index(
+    server_address: String,
+    table_name: String,
+    columns: Vec<String>,
+    database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Load the schema file
-    let schema_path = Path::new(&schema_file);
-    if !schema_path.exists() {
-        return Err(format!("Schema file not found: {}", schema_file).into());
-    }
-    
-    println!("Loading schema from: {}", schema_file);
-    let schema = parser::load_schema(schema_path).await?;
-    
-    // Determine database name
-    let db_name = database_name.unwrap_or_else(|| schema.database.name.clone());
-    let db_file = format!("{}.db", db_name);
-    let db_url = format!("sqlite://{}?mode=rwc", db_file);
-    
-    println!("Creating database: {}", db_file);
-    
-    // Create database directly without server
-    let db = SqliteDatabase::connect(&db_url).await?;
-    
-    // Create tables
-    for table in &schema.tables {
-        println!("Creating table: {}", table.name);
-        
-        let mut db_columns = Vec::new();
-        for col in &table.columns {
-            let db_col = parser::column_def_to_db(col)?;
-            db_columns.push(db_col);
-        }
-        
-        if let Err(e) = db.create_table(&table.name, db_columns).await {
-            eprintln!("Warning: Failed to create table {}: {}", table.name, e);
-        }
-    }
-    
-    // Insert initial data
-    for (table_name, rows) in &schema.data {
-        if rows.is_empty() {
-            continue;
-        }
-        
-        println!("Inserting data into table: {}", table_name);
-        
-        // Find table definition
-        let table_def = schema.tables.iter()
-            .find(|t| t.name == *table_name)
-            .ok_or_else(|| format!("Table {} not found in schema", table_name))?;
-        
-        // Insert rows using batch insert
-        let mut db_rows = Vec::new();
-        for row_data in rows {
-            let values = parser::prepare_insert_data_db(table_def, row_data)?;
-            db_rows.push(values);
-        }
-        
-        match db.batch_insert(table_name, db_rows).await {
-            Ok(count) => println!("  Inserted {} rows", count),
-            Err(e) => eprintln!("  Warning: Failed to insert data: {}", e),
-        }
-    }
-    
-    // Create indexes (if supported in future)
-    if !schema.indexes.is_empty() {
-        println!("Note: Index creation from schema files will be supported in a future version");
+    let mut client = client::connect(server_address).await?;
+
+    let request = CreateSearchIndexRequest {
+        table_name,
+        columns,
+        database: database.unwrap_or_default(),
+    };
+
+    let response = client.create_search_index(request).await?;
+    let inner = response.into_inner();
+
+    if inner.success {
+        output::success(&inner.message, serde_json::json!({ "message": inner.message }));
+    } else {
+        return Err(CliError::new(ErrorKind::Other, format!("Failed to create search index: {}", inner.message)).into());
     }
-    
-    println!("\nDatabase '{}' created successfully from schema!", db_name);
-    println!("Database file: {}", db_file);
-    println!("Schema version: {}", schema.database.version);
-    
+
     Ok(())
 }
 
-pub async fn query(
+pub async fn search(
     server_address: String,
-    sql: String,
+    table_name: String,
+    query: String,
+    limit: u32,
     format: String,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+    let mut client = client::connect(server_address).await?;
 
-    let request = QueryRequest {
-        sql,
-        parameters: HashMap::new(),
+    let request = SearchRequest {
+        table_name,
+        query,
+        limit,
         database: database.unwrap_or_default(),
     };
 
-    let mut stream = client.query(request).await?.into_inner();
-    let mut columns = Vec::new();
-    let mut rows = Vec::new();
-
-    while let Some(response) = stream.next().await {
-        match response? {
-            QueryResponse {
-                response: Some(query_response::Response::ResultSet(result_set)),
-            } => {
-                if !result_set.columns.is_empty() {
-                    columns = result_set.columns;
-                }
-                for row in result_set.rows {
-                    rows.push(row.values);
-                }
-            }
-            QueryResponse {
-                response: Some(query_response::Response::Error(error)),
-            } => {
-                eprintln!("Query error: {} - {}", error.code, error.message);
-                return Ok(());
-            }
-            _ => {}
-        }
-    }
+    let response = client.search(request).await?.into_inner();
 
-    // Format output
-    match format.as_str() {
+    let format = if output::enabled() { "json" } else { format.as_str() };
+    match format {
         "json" => {
-            let mut json_rows = Vec::new();
-            for row in rows {
-                let mut json_row = serde_json::Map::new();
-                for (i, value) in row.into_iter().enumerate() {
-                    if let Some(col) = columns.get(i) {
+            let matches: Vec<serde_json::Value> = response
+                .matches
+                .into_iter()
+                .map(|m| {
+                    let mut json_row = serde_json::Map::new();
+                    let values = m.row.map(|row| row.values).unwrap_or_default();
+                    for (col, value) in response.columns.iter().zip(values) {
                         json_row.insert(col.name.clone(), proto_value_to_json(value));
                     }
-                }
-                json_rows.push(serde_json::Value::Object(json_row));
-            }
-            println!("{}", serde_json::to_string_pretty(&json_rows)?);
-        }
-        "csv" => {
-            // Print header
-            println!(
-                "{}",
-                columns
-                    .iter()
-                    .map(|c| &c.name)
-                    .cloned()
-                    .collect::<Vec<_>>()
-                    .join(",")
-            );
-            // Print rows
-            for row in rows {
-                let values: Vec<String> =
-                    row.into_iter().map(|v| proto_value_to_string(v)).collect();
-                println!("{}", values.join(","));
-            }
+                    serde_json::json!({ "score": m.score, "snippet": m.snippet, "row": json_row })
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&matches)?);
         }
         _ => {
-            // Table format (default) using tabled
-            if !columns.is_empty() && !rows.is_empty() {
-                let mut table_builder = TableBuilder::default();
-                
-                // Add header
-                let header: Vec<String> = columns.iter().map(|c| c.name.clone()).collect();
-                table_builder.push_record(header);
-                
-                // Add rows
-                for row in rows {
-                    let values: Vec<String> = row.into_iter()
-                        .map(|v| proto_value_to_string(v))
-                        .collect();
-                    table_builder.push_record(values);
-                }
-                
-                let mut table = table_builder.build();
-                table.with(Style::rounded())
-                    .with(Modify::new(Segment::all()).with(Alignment::left()));
-                
-                println!("{}", table);
-            } else if columns.is_empty() {
-                println!("No results returned");
-            } else {
-                println!("Empty result set");
+            if response.matches.is_empty() {
+                println!("No matches");
+                return Ok(());
+            }
+
+            // The first column (usually the id) identifies the row; -f json shows the rest
+            let key_column = response.columns.first().map(|c| c.name.clone()).unwrap_or_default();
+            let mut table_builder = TableBuilder::default();
+            table_builder.push_record(vec!["#".to_string(), "Score".to_string(), key_column, "Snippet".to_string()]);
+            let count = response.matches.len();
+            for (rank, m) in response.matches.into_iter().enumerate() {
+                let key = m
+                    .row
+                    .and_then(|row| row.values.into_iter().next())
+                    .map(proto_value_to_string)
+                    .unwrap_or_default();
+                table_builder.push_record(vec![
+                    (rank + 1).to_string(),
+                    format!("{:.4}", m.score),
+                    key,
+                    m.snippet,
+                ]);
             }
+
+            let mut table = table_builder.build();
+            table.with(Style::rounded())
+                .with(Modify::new(Segment::all()).with(Alignment::left()));
+            println!("{}", table);
+            println!("\n{} match(es)", count);
         }
     }
 
     Ok(())
 }
 
-pub async fn insert(
+pub async fn create_spatial_index(
     server_address: String,
     table_name: String,
-    data_json: String,
+    columns: Vec<String>,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
-
-    let data: serde_json::Value = serde_json::from_str(&data_json)?;
-    let values = json_to_proto_values(data)?;
+    let mut client = client::connect(server_address).await?;
 
-    let request = InsertRequest { 
-        table_name, 
-        values,
+    let request = CreateSpatialIndexRequest {
+        table_name,
+        columns,
         database: database.unwrap_or_default(),
     };
 
-    let response = client.insert(request).await?;
+    let response = client.create_spatial_index(request).await?;
     let inner = response.into_inner();
 
     if inner.success {
-        println!("Insert successful. ID: {}", inner.inserted_id);
+        output::success(&inner.message, serde_json::json!({ "message": inner.message }));
     } else {
-        eprintln!("Insert failed: {}", inner.message);
+        return Err(CliError::new(ErrorKind::Other, format!("Failed to create spatial index: {}", inner.message)).into());
     }
 
     Ok(())
 }
 
-pub async fn update(
+pub async fn refresh_view(
     server_address: String,
-    table_name: String,
-    data_json: String,
-    where_clause: String,
+    name: String,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+    let mut client = client::connect(server_address).await?;
 
-    let data: serde_json::Value = serde_json::from_str(&data_json)?;
-    let values = json_to_proto_values(data)?;
-
-    let request = UpdateRequest {
-        table_name,
-        values,
-        where_clause,
+    let request = RefreshMaterializedViewRequest {
+        name,
         database: database.unwrap_or_default(),
     };
 
-    let response = client.update(request).await?;
+    let response = client.refresh_materialized_view(request).await?;
     let inner = response.into_inner();
 
     if inner.success {
-        println!("{}", inner.message);
+        output::success(&inner.message, serde_json::json!({ "message": inner.message }));
     } else {
-        eprintln!("Update failed: {}", inner.message);
+        return Err(CliError::new(ErrorKind::Other, format!("Failed to refresh materialized view: {}", inner.message)).into());
     }
 
     Ok(())
 }
 
-pub async fn delete(
+pub async fn within(
     server_address: String,
     table_name: String,
-    where_clause: String,
+    bbox: BoundingBox,
+    limit: u32,
+    format: String,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+    let mut client = client::connect(server_address).await?;
 
-    let request = DeleteRequest {
+    let request = SpatialQueryRequest {
         table_name,
-        where_clause,
+        bbox: Some(bbox),
+        limit,
         database: database.unwrap_or_default(),
     };
 
-    let response = client.delete(request).await?;
-    let inner = response.into_inner();
+    let response = client.spatial_query(request).await?.into_inner();
+    let columns = response.columns;
+    let rows: Vec<Vec<Value>> = response.rows.into_iter().map(|row| row.values).collect();
 
-    if inner.success {
-        println!("{}", inner.message);
-    } else {
-        eprintln!("Delete failed: {}", inner.message);
+    let format = if output::enabled() { "json" } else { format.as_str() };
+    match format {
+        "json" => {
+            let json_rows: Vec<serde_json::Value> = rows
+                .into_iter()
+                .map(|values| {
+                    let mut json_row = serde_json::Map::new();
+                    for (col, value) in columns.iter().zip(values) {
+                        json_row.insert(col.name.clone(), render::json(value, Some(col)));
+                    }
+                    serde_json::Value::Object(json_row)
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&json_rows)?);
+        }
+        _ => {
+            if rows.is_empty() {
+                println!("No rows in the bounding box");
+                return Ok(());
+            }
+
+            let count = rows.len();
+            let mut table_builder = TableBuilder::default();
+            table_builder.push_record(columns.iter().map(|c| c.name.clone()));
+            for values in rows {
+                let cells = columns.iter().zip(values).map(|(col, value)| render::text(value, Some(col), render::Format::Table));
+                table_builder.push_record(cells);
+            }
+
+            let mut table = table_builder.build();
+            table.with(Style::rounded());
+            println!("{}", table);
+            println!("\n{} row(s)", count);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn sample(
+    server_address: String,
+    table_name: String,
+    count: u32,
+    format: String,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = SampleRequest {
+        table_name,
+        count,
+        database: database.unwrap_or_default(),
+    };
+
+    let response = client.sample(request).await?.into_inner();
+    let columns = response.columns;
+    let rows: Vec<Vec<Value>> = response.rows.into_iter().map(|row| row.values).collect();
+
+    let format = if output::enabled() { "json" } else { format.as_str() };
+    match format {
+        "json" => {
+            let json_rows: Vec<serde_json::Value> = rows
+                .into_iter()
+                .map(|values| {
+                    let mut json_row = serde_json::Map::new();
+                    for (col, value) in columns.iter().zip(values) {
+                        json_row.insert(col.name.clone(), render::json(value, Some(col)));
+                    }
+                    serde_json::Value::Object(json_row)
+                })
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&json_rows)?);
+        }
+        _ => {
+            if rows.is_empty() {
+                println!("The table is empty");
+                return Ok(());
+            }
+            if format == "vertical" {
+                print!("{}", render::vertical(&columns, rows, &render::ColumnView::default()));
+                return Ok(());
+            }
+
+            let count = rows.len();
+            let mut table_builder = TableBuilder::default();
+            table_builder.push_record(columns.iter().map(|c| c.name.clone()));
+            for values in rows {
+                let cells = columns.iter().zip(values).map(|(col, value)| render::text(value, Some(col), render::Format::Table));
+                table_builder.push_record(cells);
+            }
+
+            let mut table = table_builder.build();
+            table.with(Style::rounded());
+            println!("{}", table);
+            println!("\n{} row(s)", count);
+        }
     }
 
     Ok(())
 }
 
 // Helper functions
+
+/// Interpret a key given on the command line as an integer, a number, or text
+pub fn parse_key_value(key: &str) -> Value {
+    let value = if let Ok(v) = key.parse::<i64>() {
+        value::Value::IntValue(v)
+    } else if let Ok(v) = key.parse::<f64>() {
+        value::Value::RealValue(v)
+    } else {
+        value::Value::TextValue(key.to_string())
+    };
+    Value { value: Some(value) }
+}
+
+/// Ask a yes/no question on the terminal; anything but "y"/"yes" declines
+fn confirm(prompt: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    // The prompt stays off stdout with --json
+    if output::enabled() {
+        eprint!("{} [y/N] ", prompt);
+        std::io::stderr().flush()?;
+    } else {
+        print!("{} [y/N] ", prompt);
+        std::io::stdout().flush()?;
+    }
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
 fn json_to_proto_values(
     json: serde_json::Value,
 ) -> Result<HashMap<String, Value>, Box<dyn std::error::Error>> {
@@ -501,7 +2374,7 @@ fn json_to_proto_values(
                         value: Some(value::Value::RealValue(f)),
                     }
                 } else {
-                    return Err("Invalid number".into());
+                    return Err(CliError::new(ErrorKind::InvalidInput, "Invalid number").into());
                 }
             }
             serde_json::Value::String(s) => Value {
@@ -513,7 +2386,7 @@ fn json_to_proto_values(
             serde_json::Value::Null => Value {
                 value: Some(value::Value::NullValue(true)),
             },
-            _ => return Err("Unsupported JSON value type".into()),
+            _ => return Err(CliError::new(ErrorKind::InvalidInput, "Unsupported JSON value type").into()),
         };
 
         values.insert(key.clone(), proto_value);
@@ -522,7 +2395,7 @@ fn json_to_proto_values(
     Ok(values)
 }
 
-fn proto_value_to_string(value: Value) -> String {
+pub fn proto_value_to_string(value: Value) -> String {
     match value.value {
         Some(value::Value::IntValue(i)) => i.to_string(),
         Some(value::Value::RealValue(f)) => f.to_string(),
@@ -534,7 +2407,7 @@ fn proto_value_to_string(value: Value) -> String {
     }
 }
 
-fn proto_value_to_json(value: Value) -> serde_json::Value {
+pub fn proto_value_to_json(value: Value) -> serde_json::Value {
     match value.value {
         Some(value::Value::IntValue(i)) => serde_json::Value::Number(i.into()),
         Some(value::Value::RealValue(f)) => serde_json::Number::from_f64(f)
@@ -553,14 +2426,94 @@ fn proto_value_to_json(value: Value) -> serde_json::Value {
 
 pub async fn list_tables(
     server_address: String,
+    like: Option<String>,
+    with_counts: bool,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+    let mut client = client::connect(server_address).await?;
+    let db_name = database.clone().unwrap_or_default();
+    let tables = table_names(&mut client, like.as_deref(), &db_name).await?;
+
+    if output::enabled() {
+        let counts = if with_counts && !tables.is_empty() {
+            Some(row_counts(&mut client, &tables, &db_name).await?)
+        } else {
+            None
+        };
+        let tables: Vec<serde_json::Value> = tables
+            .iter()
+            .map(|table| match &counts {
+                Some(counts) => serde_json::json!({ "name": table, "rows": counts.get(table) }),
+                None => serde_json::json!({ "name": table }),
+            })
+            .collect();
+        output::print(&serde_json::Value::Array(tables));
+        return Ok(());
+    }
+
+    let db_info = match &database {
+        Some(name) => format!(" '{}'", name),
+        None => " (default)".to_string(),
+    };
+    if tables.is_empty() {
+        match &like {
+            Some(pattern) => println!("No tables matching '{}' in database{}", pattern, db_info),
+            None => {
+                println!("No tables found in database{}", db_info);
+                println!("Tip: Use 'datasink server status' to see available databases");
+            }
+        }
+        return Ok(());
+    }
+
+    if !with_counts {
+        println!("Tables in database{}:", db_info);
+        for table in tables {
+            println!("  {}", table);
+        }
+        return Ok(());
+    }
+
+    let counts = row_counts(&mut client, &tables, &db_name).await?;
+    println!("Tables in database{}:", db_info);
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Table", "Rows"]);
+    for table in tables {
+        let count = counts.get(&table).map(|c| c.to_string()).unwrap_or_else(|| "-".to_string());
+        builder.push_record([table, count]);
+    }
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    table.with(Modify::new(Segment::new(1.., 1..2)).with(Alignment::right()));
+    println!("{}", table);
+
+    Ok(())
+}
+
+/// Names of the database's tables, sorted, only those matching a LIKE
+/// pattern if given
+async fn table_names(
+    client: &mut client::Client,
+    like: Option<&str>,
+    database: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut sql = "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%'".to_string();
+    let mut parameters = HashMap::new();
+    if let Some(pattern) = like {
+        sql.push_str(" AND name LIKE ?1");
+        parameters.insert(
+            "pattern".to_string(),
+            Value {
+                value: Some(value::Value::TextValue(pattern.to_string())),
+            },
+        );
+    }
+    sql.push_str(" ORDER BY name");
 
     let request = QueryRequest {
-        sql: "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' ORDER BY name".to_string(),
-        parameters: HashMap::new(),
-        database: database.clone().unwrap_or_default(),
+        sql,
+        parameters,
+        database: database.to_string(),
     };
 
     let mut stream = client.query(request).await?.into_inner();
@@ -582,114 +2535,177 @@ pub async fn list_tables(
             QueryResponse {
                 response: Some(query_response::Response::Error(error)),
             } => {
-                eprintln!("Query error: {} - {}", error.code, error.message);
-                return Ok(());
+                return Err(CliError::query(&error).into());
             }
             _ => {}
         }
     }
 
-    if tables.is_empty() {
-        let db_info = if database.is_some() {
-            format!(" '{}'", database.as_ref().unwrap())
-        } else {
-            " (default)".to_string()
-        };
-        println!("No tables found in database{}", db_info);
-        println!("Tip: Use 'datasink server status' to see available databases");
-    } else {
-        let db_info = if database.is_some() {
-            format!(" '{}'", database.as_ref().unwrap())
-        } else {
-            " (default)".to_string()
-        };
-        println!("Tables in database{}:", db_info);
-        for table in tables {
-            println!("  {}", table);
+    Ok(tables)
+}
+
+/// Row counts of several tables, fetched with a single query
+async fn row_counts(
+    client: &mut client::Client,
+    tables: &[String],
+    database: &str,
+) -> Result<HashMap<String, i64>, Box<dyn std::error::Error>> {
+    // A multi-row VALUES clause is not subject to SQLite's limit on compound
+    // SELECTs, so this works for any number of tables
+    let rows: Vec<String> = tables
+        .iter()
+        .map(|table| {
+            format!(
+                "('{}', (SELECT COUNT(*) FROM \"{}\"))",
+                table.replace('\'', "''"),
+                table.replace('"', "\"\"")
+            )
+        })
+        .collect();
+    let sql = format!("VALUES {}", rows.join(", "));
+
+    let mut counts = HashMap::new();
+    for row in generate::query_rows(client, &sql, database).await? {
+        if let [Value { value: Some(value::Value::TextValue(table)) }, Value { value: Some(value::Value::IntValue(count)) }] = &row[..] {
+            counts.insert(table.clone(), *count);
         }
     }
-
-    Ok(())
+    Ok(counts)
 }
 
 pub async fn describe_tables(
     server_address: String,
     table_names: Vec<String>,
+    verbose: bool,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // If no tables specified, describe all tables
-    let tables_to_describe = if table_names.is_empty() {
-        // Get all tables first
-        let mut client = DataSinkClient::connect(server_address.clone()).await?;
-        let request = QueryRequest {
-            sql: "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' ORDER BY name".to_string(),
-            parameters: HashMap::new(),
-            database: database.clone().unwrap_or_default(),
+    let mut client = client::connect(server_address.clone()).await?;
+    let db_name = database.clone().unwrap_or_default();
+
+    // No names describes every table
+    let describe_all = table_names.is_empty();
+    let patterns = if describe_all { vec!["*".to_string()] } else { table_names };
+
+    // Glob patterns are resolved against the server's table list; plain
+    // names are described as given
+    let mut tables_to_describe: Vec<String> = Vec::new();
+    for pattern in &patterns {
+        let matched = if is_glob_pattern(pattern) {
+            tables_matching(&mut client, pattern, &db_name).await?
+        } else {
+            vec![pattern.clone()]
         };
+        if matched.is_empty() && !describe_all {
+            eprintln!("No tables match '{}'", pattern);
+        }
+        for table in matched {
+            if !tables_to_describe.contains(&table) {
+                tables_to_describe.push(table);
+            }
+        }
+    }
 
-        let mut stream = client.query(request).await?.into_inner();
-        let mut all_tables = Vec::new();
-
-        while let Some(response) = stream.next().await {
-            match response? {
-                QueryResponse {
-                    response: Some(query_response::Response::ResultSet(result_set)),
-                } => {
-                    for row in result_set.rows {
-                        if let Some(value) = row.values.first() {
-                            if let Some(value::Value::TextValue(table_name)) = &value.value {
-                                all_tables.push(table_name.clone());
-                            }
-                        }
-                    }
-                }
-                QueryResponse {
-                    response: Some(query_response::Response::Error(error)),
-                } => {
-                    eprintln!("Query error: {} - {}", error.code, error.message);
-                    return Ok(());
+    if output::enabled() {
+        let mut described = Vec::new();
+        for table_name in &tables_to_describe {
+            let columns = table_info(&mut client, table_name, &db_name).await?;
+            let unique = unique_constraints(&mut client, table_name, &db_name).await?;
+            let allowed = bulk::allowed_values(&mut client, table_name, &db_name).await?;
+            let mut table = serde_json::json!({
+                "table": table_name,
+                "columns": columns_json(columns, &allowed),
+                "unique": unique,
+            });
+            if verbose {
+                let mut descriptions = table_descriptions(&mut client, table_name, &db_name).await?;
+                for column in table["columns"].as_array_mut().into_iter().flatten() {
+                    let name = column["name"].as_str().unwrap_or_default().to_string();
+                    column["description"] = descriptions.remove(&name).into();
                 }
-                _ => {}
+                table["description"] = descriptions.remove("").into();
             }
+            described.push(table);
         }
+        output::print(&serde_json::Value::Array(described));
+        return Ok(());
+    }
 
-        if all_tables.is_empty() {
-            let db_info = if database.is_some() {
-                format!(" '{}'", database.as_ref().unwrap())
-            } else {
-                " (default)".to_string()
+    if tables_to_describe.is_empty() {
+        if describe_all {
+            let db_info = match &database {
+                Some(name) => format!(" '{}'", name),
+                None => " (default)".to_string(),
             };
             println!("No tables found in database{}", db_info);
-            return Ok(());
         }
-
-        all_tables
-    } else {
-        table_names
-    };
+        return Ok(());
+    }
 
     // Describe each table
     for (i, table_name) in tables_to_describe.iter().enumerate() {
         if i > 0 {
             println!(); // Add spacing between tables
         }
-        describe_table(server_address.clone(), table_name.clone(), database.clone()).await?;
+        describe_table(&mut client, table_name, verbose, &db_name).await?;
     }
 
     Ok(())
 }
 
-pub async fn describe_table(
-    server_address: String,
-    table_name: String,
-    database: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+/// Whether a table argument is a GLOB pattern rather than a name
+fn is_glob_pattern(name: &str) -> bool {
+    name.contains(['*', '?', '['])
+}
+
+/// Names of the tables matching a GLOB pattern, sorted
+async fn tables_matching(
+    client: &mut client::Client,
+    pattern: &str,
+    database: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let request = QueryRequest {
+        sql: "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' AND name GLOB ?1 ORDER BY name".to_string(),
+        parameters: HashMap::from([(
+            "pattern".to_string(),
+            Value {
+                value: Some(value::Value::TextValue(pattern.to_string())),
+            },
+        )]),
+        database: database.to_string(),
+    };
+
+    let mut stream = client.query(request).await?.into_inner();
+    let mut tables = Vec::new();
+    while let Some(response) = stream.next().await {
+        match response?.response {
+            Some(query_response::Response::ResultSet(result_set)) => {
+                for row in result_set.rows {
+                    if let Some(Value { value: Some(value::Value::TextValue(name)) }) = row.values.into_iter().next() {
+                        tables.push(name);
+                    }
+                }
+            }
+            Some(query_response::Response::Error(error)) => {
+                return Err(CliError::query(&error).into());
+            }
+            Some(query_response::Response::Summary(_)) | None => {}
+        }
+    }
+    Ok(tables)
+}
 
+/// PRAGMA table_xinfo rows of a table (table_info plus generated columns and
+/// a trailing `hidden` value, without the hidden columns of virtual tables);
+/// a NotFound error if it does not exist
+async fn table_info(
+    client: &mut client::Client,
+    table_name: &str,
+    database: &str,
+) -> Result<Vec<Vec<Value>>, Box<dyn std::error::Error>> {
     let request = QueryRequest {
-        sql: format!("PRAGMA table_info({})", table_name),
+        sql: format!("PRAGMA table_xinfo({})", table_name),
         parameters: HashMap::new(),
-        database: database.unwrap_or_default(),
+        database: database.to_string(),
     };
 
     let mut stream = client.query(request).await?.into_inner();
@@ -700,29 +2716,149 @@ pub async fn describe_table(
             QueryResponse {
                 response: Some(query_response::Response::ResultSet(result_set)),
             } => {
-                for row in result_set.rows {
-                    rows.push(row.values);
-                }
+                rows.extend(
+                    result_set
+                        .rows
+                        .into_iter()
+                        .map(|row| row.values)
+                        .filter(|row| row.get(6).map(|hidden| proto_value_to_string(hidden.clone())).as_deref() != Some("1")),
+                );
             }
             QueryResponse {
                 response: Some(query_response::Response::Error(error)),
             } => {
-                eprintln!("Query error: {} - {}", error.code, error.message);
-                return Ok(());
+                return Err(CliError::query(&error).into());
             }
             _ => {}
         }
     }
-
     if rows.is_empty() {
-        println!("Table '{}' not found", table_name);
-        return Ok(());
+        return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", table_name)).into());
+    }
+    Ok(rows)
+}
+
+/// Column lists of a table's UNIQUE constraints (single-column and composite)
+/// Descriptions recorded for a table (key "") and its columns by
+/// `create-from-schema`; empty if none were
+async fn table_descriptions(
+    client: &mut client::Client,
+    table_name: &str,
+    database: &str,
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    if tables_matching(client, DESCRIPTIONS_TABLE, database).await?.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let sql = format!(
+        "SELECT column_name, description FROM {} WHERE table_name = '{}'",
+        DESCRIPTIONS_TABLE,
+        table_name.replace('\'', "''")
+    );
+    Ok(generate::query_rows(client, &sql, database)
+        .await?
+        .into_iter()
+        .filter(|row| row.len() == 2)
+        .map(|row| (proto_value_to_string(row[0].clone()), proto_value_to_string(row[1].clone())))
+        .collect())
+}
+
+async fn unique_constraints(
+    client: &mut client::Client,
+    table_name: &str,
+    database: &str,
+) -> Result<Vec<Vec<String>>, Box<dyn std::error::Error>> {
+    // origin 'u' marks indexes created for UNIQUE constraints, as opposed to
+    // CREATE INDEX ('c') and primary keys ('pk')
+    let request = QueryRequest {
+        sql: "SELECT il.name, ii.name FROM pragma_index_list(?1) il JOIN pragma_index_info(il.name) ii \
+              WHERE il.origin = 'u' ORDER BY il.seq DESC, ii.seqno"
+            .to_string(),
+        parameters: HashMap::from([(
+            "table".to_string(),
+            Value {
+                value: Some(value::Value::TextValue(table_name.to_string())),
+            },
+        )]),
+        database: database.to_string(),
+    };
+
+    let mut stream = client.query(request).await?.into_inner();
+    let mut constraints: Vec<(String, Vec<String>)> = Vec::new();
+    while let Some(response) = stream.next().await {
+        match response?.response {
+            Some(query_response::Response::ResultSet(result_set)) => {
+                for row in result_set.rows {
+                    let mut values = row.values.into_iter().map(proto_value_to_string);
+                    let (Some(index), Some(column)) = (values.next(), values.next()) else {
+                        continue;
+                    };
+                    match constraints.last_mut() {
+                        Some((name, columns)) if *name == index => columns.push(column),
+                        _ => constraints.push((index, vec![column])),
+                    }
+                }
+            }
+            Some(query_response::Response::Error(error)) => {
+                return Err(CliError::query(&error).into());
+            }
+            Some(query_response::Response::Summary(_)) | None => {}
+        }
+    }
+    Ok(constraints.into_iter().map(|(_, columns)| columns).collect())
+}
+
+/// How a table_xinfo row's column is generated: "virtual", "stored" or None
+fn generated_kind(row: &[Value]) -> Option<&'static str> {
+    match row.get(6).map(|hidden| proto_value_to_string(hidden.clone())).as_deref() {
+        Some("2") => Some("virtual"),
+        Some("3") => Some("stored"),
+        _ => None,
     }
+}
+
+/// table_xinfo rows as `{"name", "type", "nullable", "primary_key", "default", "generated", "values"}`
+/// objects, `values` being the column's allowed values from `bulk::allowed_values`
+fn columns_json(rows: Vec<Vec<Value>>, allowed: &HashMap<String, Vec<String>>) -> Vec<serde_json::Value> {
+    rows.into_iter()
+        .filter(|row| row.len() >= 6)
+        .map(|row| {
+            serde_json::json!({
+                "name": proto_value_to_string(row[1].clone()),
+                "type": proto_value_to_string(row[2].clone()),
+                "nullable": proto_value_to_string(row[3].clone()) == "0",
+                "primary_key": proto_value_to_string(row[5].clone()) != "0",
+                "default": proto_value_to_json(row[4].clone()),
+                "generated": generated_kind(&row),
+                "values": allowed.get(&proto_value_to_string(row[1].clone()).to_lowercase()),
+            })
+        })
+        .collect()
+}
+
+async fn describe_table(
+    client: &mut client::Client,
+    table_name: &str,
+    verbose: bool,
+    database: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rows = table_info(client, table_name, database).await?;
+    let mut descriptions = if verbose {
+        table_descriptions(client, table_name, database).await?
+    } else {
+        HashMap::new()
+    };
 
     println!("Table: {}", table_name);
+    if let Some(description) = descriptions.remove("") {
+        println!("Description: {}", description);
+    }
     
     let mut table_builder = TableBuilder::default();
-    table_builder.push_record(vec!["Name", "Type", "Nullable", "Primary Key", "Default"]);
+    let mut header = vec!["Name", "Type", "Nullable", "Primary Key", "Default"];
+    if verbose {
+        header.push("Description");
+    }
+    table_builder.push_record(header);
     
     for row in rows {
         if row.len() >= 6 {
@@ -731,9 +2867,18 @@ pub async fn describe_table(
             let nullable = if proto_value_to_string(row[3].clone()) == "0" { "YES" } else { "NO" };
             let pk = if proto_value_to_string(row[5].clone()) == "0" { "NO" } else { "YES" };
             let default = proto_value_to_string(row[4].clone());
-            let default_display = if default == "NULL" { "-".to_string() } else { default };
+            let default_display = match generated_kind(&row) {
+                Some(kind) => format!("generated ({})", kind),
+                None if default == "NULL" => "-".to_string(),
+                None => default,
+            };
+            let description = descriptions.remove(&name);
+            let mut record = vec![name, type_name, nullable.to_string(), pk.to_string(), default_display];
+            if verbose {
+                record.push(description.unwrap_or_else(|| "-".to_string()));
+            }
             
-            table_builder.push_record(vec![name, type_name, nullable.to_string(), pk.to_string(), default_display]);
+            table_builder.push_record(record);
         }
     }
     
@@ -742,115 +2887,97 @@ pub async fn describe_table(
     
     println!("{}", table);
 
+    for columns in unique_constraints(client, table_name, database).await? {
+        println!("UNIQUE ({})", columns.join(", "));
+    }
+    let mut allowed: Vec<_> = bulk::allowed_values(client, table_name, database).await?.into_iter().collect();
+    allowed.sort();
+    for (column, values) in allowed {
+        println!("{}", SqliteDatabase::allowed_values_sql(&column, &values));
+    }
+
     Ok(())
 }
 
 pub async fn show_stats(
     server_address: String,
     detailed: bool,
+    table: Option<String>,
+    columns: Option<Vec<String>>,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address.clone()).await?;
+    let mut client = client::connect(server_address).await?;
+    let db_name = database.unwrap_or_default();
 
-    // First get all tables
-    let tables_request = QueryRequest {
-        sql: "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' ORDER BY name".to_string(),
-        parameters: HashMap::new(),
-        database: database.clone().unwrap_or_default(),
-    };
+    if let (Some(table), Some(columns)) = (&table, columns) {
+        return show_column_stats(&mut client, table, columns, &db_name).await;
+    }
 
-    let mut stream = client.query(tables_request).await?.into_inner();
-    let mut tables = Vec::new();
+    // Every table's row count and size, computed by the server in one call
+    let stats = client.get_database_stats(DatabaseStatsRequest { database: db_name.clone() }).await?.into_inner();
+    let disk_usage = stats.disk_usage;
+    let mut tables = stats.tables;
 
-    while let Some(response) = stream.next().await {
-        match response? {
-            QueryResponse {
-                response: Some(query_response::Response::ResultSet(result_set)),
-            } => {
-                for row in result_set.rows {
-                    if let Some(value) = row.values.first() {
-                        if let Some(value::Value::TextValue(table_name)) = &value.value {
-                            tables.push(table_name.clone());
-                        }
-                    }
-                }
-            }
-            QueryResponse {
-                response: Some(query_response::Response::Error(error)),
-            } => {
-                eprintln!("Query error: {} - {}", error.code, error.message);
-                return Ok(());
-            }
-            _ => {}
+    if let Some(table) = &table {
+        tables.retain(|stats| stats.name.eq_ignore_ascii_case(table));
+        if tables.is_empty() {
+            return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", table)).into());
         }
     }
 
-    if tables.is_empty() {
+    if tables.is_empty() && !output::enabled() {
         println!("No tables found in database");
         return Ok(());
     }
 
+    let total_rows: i64 = tables.iter().map(|stats| stats.rows).sum();
+    let total_size: i64 = tables.iter().map(|stats| stats.size_bytes).sum();
+
+    if output::enabled() {
+        let mut table_stats = Vec::new();
+        for stats in &tables {
+            let mut json = serde_json::json!({ "name": stats.name, "rows": stats.rows, "size_bytes": stats.size_bytes });
+            if detailed {
+                let columns = table_info(&mut client, &stats.name, &db_name).await?;
+                let allowed = bulk::allowed_values(&mut client, &stats.name, &db_name).await?;
+                json["columns"] = serde_json::Value::Array(columns_json(columns, &allowed));
+            }
+            table_stats.push(json);
+        }
+        output::print(&serde_json::json!({
+            "tables": tables.len(),
+            "disk_usage": disk_usage.as_ref().map(disk_usage_json),
+            "table_stats": table_stats,
+            "total_rows": total_rows,
+            "total_size_bytes": total_size,
+        }));
+        return Ok(());
+    }
+
     println!("Database Statistics:");
     println!("  Total tables: {}", tables.len());
+    if let Some(usage) = &disk_usage {
+        println!("  Disk usage: {}", format_disk_usage(usage));
+    }
     println!();
-    
-    let mut table_builder = TableBuilder::default();
-    table_builder.push_record(vec!["Table", "Rows"]);
-    
-    let mut total_rows = 0;
-    for table in &tables {
-        // Reconnect for each query to avoid stream issues
-        let mut client = DataSinkClient::connect(server_address.clone()).await?;
-        
-        let count_request = QueryRequest {
-            sql: format!("SELECT COUNT(*) FROM {}", table),
-            parameters: HashMap::new(),
-            database: database.clone().unwrap_or_default(),
-        };
-
-        let mut stream = client.query(count_request).await?.into_inner();
-        let mut count = 0;
-
-        while let Some(response) = stream.next().await {
-            match response? {
-                QueryResponse {
-                    response: Some(query_response::Response::ResultSet(result_set)),
-                } => {
-                    for row in result_set.rows {
-                        if let Some(value) = row.values.first() {
-                            if let Some(value::Value::IntValue(row_count)) = &value.value {
-                                count = *row_count;
-                            }
-                        }
-                    }
-                }
-                QueryResponse {
-                    response: Some(query_response::Response::Error(error)),
-                } => {
-                    eprintln!("Error counting {}: {} - {}", table, error.code, error.message);
-                    continue;
-                }
-                _ => {}
-            }
-        }
 
-        table_builder.push_record(vec![table.clone(), count.to_string()]);
-        total_rows += count;
+    let mut table_builder = TableBuilder::default();
+    table_builder.push_record(vec!["Table", "Rows", "Size"]);
+    for stats in &tables {
+        table_builder.push_record(vec![stats.name.clone(), stats.rows.to_string(), format_bytes(stats.size_bytes)]);
     }
-    
-    // Add total row
-    table_builder.push_record(vec!["Total".to_string(), total_rows.to_string()]);
-    
+    table_builder.push_record(vec!["Total".to_string(), total_rows.to_string(), format_bytes(total_size)]);
+
     let mut table = table_builder.build();
     table.with(Style::rounded());
-    
+
     println!("{}", table);
 
     if detailed {
         println!();
         println!("Detailed table information:");
-        for table in &tables {
-            describe_table(server_address.clone(), table.clone(), database.clone()).await?;
+        for stats in &tables {
+            describe_table(&mut client, &stats.name, false, &db_name).await?;
             println!();
         }
     }
@@ -858,12 +2985,463 @@ pub async fn show_stats(
     Ok(())
 }
 
+/// The query that profiles `columns` of `table` in one pass: the row count,
+/// then per column its NULL count, distinct count, minimum, maximum and
+/// average (NULL for text and blob columns, where it means nothing)
+fn column_stats_query(table: &str, columns: &[bulk::SchemaColumn]) -> String {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let mut aggregates = vec!["COUNT(*)".to_string()];
+    for column in columns {
+        let name = quote(&column.name);
+        aggregates.push(format!("COUNT(*) - COUNT({})", name));
+        aggregates.push(format!("COUNT(DISTINCT {})", name));
+        aggregates.push(format!("MIN({})", name));
+        aggregates.push(format!("MAX({})", name));
+        aggregates.push(if column.typed() { format!("AVG({})", name) } else { "NULL".to_string() });
+    }
+    format!("SELECT {} FROM {}", aggregates.join(", "), quote(table))
+}
+
+/// Print the NULL count, distinct count, minimum, maximum and average of
+/// `columns` of `table` (all of them if empty), computed by the server in a
+/// single query
+async fn show_column_stats(
+    client: &mut client::Client,
+    table: &str,
+    columns: Vec<String>,
+    database: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = bulk::fetch_schema(client, table, database).await?;
+    if schema.is_empty() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", table)).into());
+    }
+    let profiled: Vec<bulk::SchemaColumn> = if columns.is_empty() {
+        schema
+    } else {
+        columns
+            .iter()
+            .map(|wanted| {
+                schema.iter().find(|column| column.name.eq_ignore_ascii_case(wanted)).cloned().ok_or_else(|| {
+                    let names = schema.iter().map(|column| column.name.as_str());
+                    CliError::new(
+                        ErrorKind::InvalidInput,
+                        format!("Table '{}' has no column {}", table, UnknownColumn::new(wanted, names)),
+                    )
+                })
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let rows = generate::query_rows(client, &column_stats_query(table, &profiled), database).await?;
+    let values = rows.into_iter().next().unwrap_or_default();
+    let (rows_total, stats) = split_column_stats(&profiled, &values)?;
+    let int = |value: &Value| match value.value {
+        Some(value::Value::IntValue(i)) => i,
+        _ => 0,
+    };
+
+    if output::enabled() {
+        let columns: Vec<serde_json::Value> = stats
+            .iter()
+            .map(|(column, aggregates)| {
+                let (shown, average) = stats_columns(column);
+                serde_json::json!({
+                    "name": column.name,
+                    "type": column.sql_type,
+                    "null_count": int(&aggregates[0]),
+                    "distinct_count": int(&aggregates[1]),
+                    "min": render::json(aggregates[2].clone(), Some(&shown)),
+                    "max": render::json(aggregates[3].clone(), Some(&shown)),
+                    "avg": render::json(aggregates[4].clone(), Some(&average)),
+                })
+            })
+            .collect();
+        output::print(&serde_json::json!({ "table": table, "rows": rows_total, "columns": columns }));
+        return Ok(());
+    }
+
+    println!("Table {}: {} row{}", table, rows_total, if rows_total == 1 { "" } else { "s" });
+    let mut table_builder = TableBuilder::default();
+    table_builder.push_record(["Column", "Type", "Nulls", "Distinct", "Min", "Max", "Avg"].map(String::from));
+    // Long text would stretch the table past the terminal
+    let view = render::ColumnView { max_width: Some(40), ..Default::default() };
+    for (column, aggregates) in &stats {
+        let (shown, average) = stats_columns(column);
+        let cell = |value: &Value, shown: &Column| view.fit(render::text(value.clone(), Some(shown), render::Format::Table));
+        table_builder.push_record(vec![
+            column.name.clone(),
+            column.sql_type.clone(),
+            int(&aggregates[0]).to_string(),
+            int(&aggregates[1]).to_string(),
+            cell(&aggregates[2], &shown),
+            cell(&aggregates[3], &shown),
+            cell(&aggregates[4], &average),
+        ]);
+    }
+    let mut table = table_builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+    Ok(())
+}
+
+/// A profiled column and its null count, distinct count, MIN, MAX and AVG
+type ColumnStats<'a> = (&'a bulk::SchemaColumn, &'a [Value]);
+
+/// Split the row of `column_stats_query` into the row count and each column's
+/// five aggregates, which follow it in order
+fn split_column_stats<'a>(
+    profiled: &'a [bulk::SchemaColumn],
+    values: &'a [Value],
+) -> Result<(i64, Vec<ColumnStats<'a>>), CliError> {
+    let expected = 1 + 5 * profiled.len();
+    if values.len() != expected {
+        return Err(CliError::new(
+            ErrorKind::Other,
+            format!("Expected {} column statistics from the server, got {}", expected, values.len()),
+        ));
+    }
+    let rows_total = match values[0].value {
+        Some(value::Value::IntValue(count)) => count,
+        _ => 0,
+    };
+    Ok((rows_total, profiled.iter().zip(values[1..].chunks(5)).collect()))
+}
+
+/// How a column's MIN and MAX, and its AVG, are shown: AVG is a REAL
+/// whatever the column's type, so a BOOLEAN's 0.5 is not shown as a bool
+/// nor a TIMESTAMP's as a date
+fn stats_columns(column: &bulk::SchemaColumn) -> (Column, Column) {
+    (
+        Column { name: column.name.clone(), r#type: column.data_type() as i32 },
+        Column { name: column.name.clone(), r#type: DataType::Real as i32 },
+    )
+}
+
+pub async fn check_foreign_keys(
+    server_address: String,
+    table: Option<String>,
+    limit: u32,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = CheckForeignKeysRequest {
+        table_name: table.unwrap_or_default(),
+        limit,
+        database: database.unwrap_or_default(),
+    };
+    let response = client.check_foreign_keys(request).await?.into_inner();
+
+    if output::enabled() {
+        let violations: Vec<serde_json::Value> = response
+            .violations
+            .iter()
+            .map(|violation| {
+                serde_json::json!({
+                    "table": violation.table_name,
+                    "rowid": violation.rowid,
+                    "parent_table": violation.parent_table,
+                    "constraint_index": violation.constraint_index,
+                })
+            })
+            .collect();
+        output::print(&serde_json::json!({
+            "enforced": response.enforced,
+            "violations": violations,
+            "truncated": response.truncated,
+        }));
+        return Ok(());
+    }
+
+    let enforcement = if response.enforced { "enabled" } else { "disabled" };
+    println!("Foreign key enforcement: {}", enforcement);
+    if response.violations.is_empty() {
+        println!("No foreign key violations found");
+        return Ok(());
+    }
+
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Table", "Rowid", "References", "Constraint"]);
+    for violation in &response.violations {
+        builder.push_record([
+            violation.table_name.clone(),
+            violation.rowid.to_string(),
+            violation.parent_table.clone(),
+            violation.constraint_index.to_string(),
+        ]);
+    }
+
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+    if response.truncated {
+        println!("Showing the first {} violations; use --limit to see more", response.violations.len());
+    }
+
+    Ok(())
+}
+
+/// Suggest indexes for the frequent queries that scan whole tables, optionally creating them
+pub async fn advise_indexes(
+    server_address: String,
+    database: Option<String>,
+    apply: bool,
+    min_count: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = AdviseIndexesRequest {
+        database: database.unwrap_or_default(),
+        min_count,
+        apply,
+    };
+    let response = client.advise_indexes(request).await?.into_inner();
+
+    if output::enabled() {
+        let suggestions: Vec<serde_json::Value> = response
+            .suggestions
+            .iter()
+            .map(|suggestion| {
+                serde_json::json!({
+                    "table": suggestion.table_name,
+                    "columns": suggestion.columns,
+                    "sql": suggestion.sql,
+                    "query_count": suggestion.query_count,
+                    "example_sql": suggestion.example_sql,
+                    "plan_before": suggestion.plan_before,
+                    "plan_after": suggestion.plan_after,
+                    "applied": suggestion.applied,
+                })
+            })
+            .collect();
+        output::print(&serde_json::json!({
+            "patterns_analyzed": response.patterns_analyzed,
+            "suggestions": suggestions,
+        }));
+        return Ok(());
+    }
+
+    println!("Analyzed {} query patterns", response.patterns_analyzed);
+    if response.suggestions.is_empty() {
+        println!("No index suggestions");
+        return Ok(());
+    }
+
+    for suggestion in &response.suggestions {
+        println!();
+        println!(
+            "{}({}) - used by {} queries",
+            suggestion.table_name,
+            suggestion.columns.join(", "),
+            suggestion.query_count
+        );
+        println!("  {}", suggestion.sql);
+        if suggestion.applied {
+            println!("  ✅ Created");
+        }
+        println!("  Example: {}", suggestion.example_sql);
+        println!("  Before:");
+        for line in &suggestion.plan_before {
+            println!("    {}", line);
+        }
+        println!("  After:");
+        for line in &suggestion.plan_after {
+            println!("    {}", line);
+        }
+    }
+    if !apply {
+        println!();
+        println!("Run with --apply to create these indexes");
+    }
+    Ok(())
+}
+
+/// List the most recent changes recorded in table history
+pub async fn list_changes(
+    server_address: String,
+    database: Option<String>,
+    table: Option<String>,
+    limit: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = ListChangesRequest {
+        database: database.unwrap_or_default(),
+        table_name: table.unwrap_or_default(),
+        limit,
+    };
+    let changes = client.list_changes(request).await?.into_inner().changes;
+
+    if output::enabled() {
+        output::print(&serde_json::Value::Array(changes.iter().map(change_json).collect()));
+        return Ok(());
+    }
+    if changes.is_empty() {
+        println!("No changes recorded");
+        return Ok(());
+    }
+    print_changes(&changes);
+    Ok(())
+}
+
+/// Revert recent changes, or show the statements that would
+pub async fn undo_changes(
+    server_address: String,
+    database: Option<String>,
+    last: u32,
+    change_ids: Vec<String>,
+    table: Option<String>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+
+    let request = UndoChangesRequest {
+        database: database.unwrap_or_default(),
+        last,
+        change_ids,
+        table_name: table.unwrap_or_default(),
+        dry_run,
+    };
+    let response = client.undo_changes(request).await?.into_inner();
+
+    if output::enabled() {
+        output::print(&serde_json::json!({
+            "changes": response.changes.iter().map(change_json).collect::<Vec<_>>(),
+            "statements": response.statements,
+            "applied": response.applied,
+        }));
+        return Ok(());
+    }
+
+    print_changes(&response.changes);
+    println!();
+    for statement in &response.statements {
+        println!("{};", statement);
+    }
+    if response.applied {
+        println!();
+        println!("✅ Undid {} change(s)", response.changes.len());
+    } else {
+        println!();
+        println!("Dry run: nothing was changed");
+    }
+    Ok(())
+}
+
+fn change_json(change: &HistoryChange) -> serde_json::Value {
+    serde_json::json!({
+        "id": change.id,
+        "table": change.table_name,
+        "time": change.time,
+        "inserted": change.inserted,
+        "updated": change.updated,
+        "deleted": change.deleted,
+    })
+}
+
+fn print_changes(changes: &[HistoryChange]) {
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Id", "Time", "Inserted", "Updated", "Deleted"]);
+    for change in changes {
+        let time = chrono::DateTime::from_timestamp_millis((change.time * 1000.0).round() as i64)
+            .map(|dt| dt.format("%Y-%m-%d %H:%M:%S%.3f UTC").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        builder.push_record([
+            change.id.clone(),
+            time,
+            change.inserted.to_string(),
+            change.updated.to_string(),
+            change.deleted.to_string(),
+        ]);
+    }
+
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    println!("{}", table);
+}
+
+/// Show the schema file last applied to a database with create-from-schema
+pub async fn schema_version(server_address: String, database: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let database = database.unwrap_or_default();
+    let shown_name = if database.is_empty() { "default" } else { database.as_str() };
+
+    let Some((name, version, checksum, applied_at)) = applied_schema(&mut client, &database).await? else {
+        return Err(CliError::new(
+            ErrorKind::NotFound,
+            format!("No schema has been applied to database '{}'", shown_name),
+        )
+        .into());
+    };
+
+    if output::enabled() {
+        output::print(&serde_json::json!({
+            "database": shown_name,
+            "name": name,
+            "version": version,
+            "checksum": checksum,
+            "applied_at": applied_at,
+        }));
+    } else {
+        println!("Database:   {}", shown_name);
+        println!("Schema:     {}", name);
+        println!("Version:    {}", version);
+        println!("Checksum:   {}", checksum);
+        println!("Applied at: {}", format_timestamp(applied_at));
+    }
+    Ok(())
+}
+
+/// The schema file last applied to a database: name, version, checksum and
+/// when it was applied (Unix seconds); `None` if no schema has been applied
+pub async fn applied_schema(
+    client: &mut client::Client,
+    database: &str,
+) -> Result<Option<(String, String, String, i64)>, Box<dyn std::error::Error>> {
+    let table = crate::db::sqlite::APPLIED_SCHEMA_TABLE;
+    if tables_matching(client, table, database).await?.is_empty() {
+        return Ok(None);
+    }
+
+    let request = QueryRequest {
+        sql: format!("SELECT name, version, checksum, applied_at FROM {} ORDER BY id DESC LIMIT 1", table),
+        parameters: HashMap::new(),
+        database: database.to_string(),
+    };
+    let mut stream = client.query(request).await?.into_inner();
+    let mut row = Vec::new();
+    while let Some(response) = stream.next().await {
+        match response?.response {
+            Some(query_response::Response::ResultSet(result_set)) => {
+                if let Some(first) = result_set.rows.into_iter().next() {
+                    row = first.values;
+                }
+            }
+            Some(query_response::Response::Error(error)) => {
+                return Err(CliError::query(&error).into());
+            }
+            Some(query_response::Response::Summary(_)) | None => {}
+        }
+    }
+    let mut values = row.into_iter();
+    let (Some(name), Some(version), Some(checksum), Some(applied_at)) =
+        (values.next(), values.next(), values.next(), values.next())
+    else {
+        return Ok(None);
+    };
+    let applied_at = match applied_at.value {
+        Some(value::Value::IntValue(t)) | Some(value::Value::TimestampValue(t)) => t,
+        _ => 0,
+    };
+    Ok(Some((proto_value_to_string(name), proto_value_to_string(version), proto_value_to_string(checksum), applied_at)))
+}
+
 pub async fn show_schema(
     server_address: String,
     format: String,
     database: Option<String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = DataSinkClient::connect(server_address).await?;
+    let mut client = client::connect(server_address).await?;
 
     let request = QueryRequest {
         sql: "SELECT sql FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' ORDER BY name".to_string(),
@@ -890,19 +3468,19 @@ pub async fn show_schema(
             QueryResponse {
                 response: Some(query_response::Response::Error(error)),
             } => {
-                eprintln!("Query error: {} - {}", error.code, error.message);
-                return Ok(());
+                return Err(CliError::query(&error).into());
             }
             _ => {}
         }
     }
 
-    if schemas.is_empty() {
+    let format = if output::enabled() { "json" } else { format.as_str() };
+    if schemas.is_empty() && format != "json" {
         println!("No tables found in database");
         return Ok(());
     }
 
-    match format.as_str() {
+    match format {
         "sql" => {
             println!("-- Database Schema");
             for schema in schemas {
@@ -913,7 +3491,7 @@ pub async fn show_schema(
         "json" => {
             let json_schemas: Vec<serde_json::Value> = schemas
                 .into_iter()
-                .map(|s| serde_json::Value::String(s))
+                .map(serde_json::Value::String)
                 .collect();
             println!("{}", serde_json::to_string_pretty(&json_schemas)?);
         }
@@ -929,3 +3507,186 @@ pub async fn show_schema(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tempfile::NamedTempFile;
+
+    /// A server with one database holding the given tables, and a client for it
+    async fn serve_tables(temp_file: &NamedTempFile, statements: &[&str]) -> client::Client {
+        let url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+        let manager = Arc::new(DatabaseManager::new());
+        manager.add_database("default".to_string(), url).await.unwrap();
+        let db = manager.get_database("default").await.unwrap();
+        for sql in statements {
+            db.read().await.execute(sql).await.unwrap();
+        }
+        client::connect(client::test_server(manager).await).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tables_matching_glob() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut client = serve_tables(&temp_file, &[
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY)",
+            "CREATE TABLE order_items (id INTEGER PRIMARY KEY)",
+            "CREATE TABLE customers (id INTEGER PRIMARY KEY)",
+        ])
+        .await;
+
+        assert!(is_glob_pattern("order*") && is_glob_pattern("log_20[0-9][0-9]") && !is_glob_pattern("orders"));
+        assert_eq!(tables_matching(&mut client, "order*", "").await.unwrap(), vec!["order_items", "orders"]);
+        assert_eq!(tables_matching(&mut client, "*ers", "").await.unwrap(), vec!["customers", "orders"]);
+        // GLOB is case sensitive, unlike LIKE
+        assert!(tables_matching(&mut client, "Order*", "").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_tables_like_and_counts() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut client = serve_tables(&temp_file, &[
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY)",
+            "CREATE TABLE order_items (id INTEGER PRIMARY KEY)",
+            "CREATE TABLE \"odd'name\" (id INTEGER PRIMARY KEY)",
+            "INSERT INTO orders (id) VALUES (1), (2), (3)",
+            "INSERT INTO \"odd'name\" (id) VALUES (1)",
+        ])
+        .await;
+
+        assert_eq!(table_names(&mut client, None, "").await.unwrap(), vec!["odd'name", "order_items", "orders"]);
+        // LIKE is case insensitive and `_` matches any one character
+        assert_eq!(table_names(&mut client, Some("%ITEMS"), "").await.unwrap(), vec!["order_items"]);
+        assert_eq!(table_names(&mut client, Some("order_%"), "").await.unwrap(), vec!["order_items", "orders"]);
+        assert!(table_names(&mut client, Some("missing%"), "").await.unwrap().is_empty());
+
+        let tables = table_names(&mut client, None, "").await.unwrap();
+        let counts = row_counts(&mut client, &tables, "").await.unwrap();
+        assert_eq!(counts, HashMap::from([
+            ("odd'name".to_string(), 1),
+            ("order_items".to_string(), 0),
+            ("orders".to_string(), 3),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_insert_json_array() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut client = serve_tables(&temp_file, &["CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT NOT NULL)"]).await;
+        let rows = |json: &str| match serde_json::from_str(json).unwrap() {
+            serde_json::Value::Array(rows) => rows,
+            _ => unreachable!(),
+        };
+        let db = SqliteDatabase::connect(&format!("sqlite://{}", temp_file.path().display())).await.unwrap();
+        let count = || async {
+            match db.query("SELECT COUNT(*) FROM events", HashMap::new()).await.unwrap().rows[0][0] {
+                crate::db::traits::DbValue::Integer(count) => count,
+                ref other => panic!("unexpected count {:?}", other),
+            }
+        };
+
+        // A row that fails validation stops the whole array
+        let invalid = rows(r#"[{"id": 1, "name": "a"}, {"id": 2}]"#);
+        assert!(insert_rows(&mut client, "events".to_string(), invalid, OnConflict::Abort, false, None).await.is_err());
+        assert_eq!(count().await, 0);
+
+        let valid = rows(r#"[{"id": 1, "name": "a"}, {"id": 2, "name": "b"}]"#);
+        insert_rows(&mut client, "events".to_string(), valid, OnConflict::Abort, false, None).await.unwrap();
+        assert_eq!(count().await, 2);
+        assert!(insert_rows(&mut client, "events".to_string(), Vec::new(), OnConflict::Abort, false, None).await.is_err());
+    }
+
+    #[test]
+    fn test_insert_rows_summary() {
+        assert_eq!(insert_rows_summary(0, 2, 0, 0), "Inserted 0 rows (2 skipped; already exist)");
+        assert_eq!(insert_rows_summary(1, 0, 7, 7), "Inserted 1 row. ID: 7");
+        assert_eq!(insert_rows_summary(3, 1, 4, 6), "Inserted 3 rows (1 skipped; already exist). First ID: 4, last ID: 6");
+    }
+
+    #[test]
+    fn test_status_formats() {
+        use crate::proto::admin::DatabaseStatus;
+
+        let database = |name: &str, connected: bool, problems: Vec<String>| DatabaseStatus {
+            name: name.to_string(),
+            connected,
+            problems,
+            max_connections: 10,
+            active_connections: 2,
+            queries_total: 42,
+            ..Default::default()
+        };
+        let status = ServerStatusResponse {
+            server_running: true,
+            uptime_seconds: 90,
+            databases: vec![
+                database("main", true, Vec::new()),
+                database("logs", true, vec!["WAL file is large".to_string()]),
+                database("archive", false, Vec::new()),
+            ],
+            ..Default::default()
+        };
+
+        let table = status_table(&status, false);
+        assert!(table.starts_with("Server: running, up 90 seconds\n"));
+        for state in ["connected", "degraded", "disconnected", "2/10"] {
+            assert!(table.contains(state), "{}", table);
+        }
+        assert!(!table.contains("Queries"));
+        let detailed = status_table(&status, true);
+        assert!(detailed.contains("Queries") && detailed.contains("42"), "{}", detailed);
+        assert_eq!(status_table(&ServerStatusResponse::default(), true), "Server: stopped, up 0 seconds\nNo databases connected\n");
+
+        // YAML carries the same document as JSON
+        let json = status_json(&status);
+        assert_eq!(json["databases"][1]["problems"][0], "WAL file is large");
+        assert_eq!(json["databases"][2]["connected"], false);
+        let yaml: serde_json::Value = serde_yaml::from_str(&serde_yaml::to_string(&json).unwrap()).unwrap();
+        assert_eq!(yaml, json);
+    }
+
+    #[test]
+    fn test_stats_average_is_shown_as_real() {
+        let column = |sql_type: &str| bulk::SchemaColumn {
+            name: "c".to_string(),
+            sql_type: sql_type.to_string(),
+            not_null: false,
+            has_default: false,
+            primary_key: false,
+            allowed_values: None,
+        };
+        let real = |v: f64| Value { value: Some(value::Value::RealValue(v)) };
+        for sql_type in ["BOOLEAN", "TIMESTAMP", "INTEGER"] {
+            let (_, average) = stats_columns(&column(sql_type));
+            assert_eq!(render::text(real(0.5), Some(&average), render::Format::Table), "0.5", "{}", sql_type);
+            assert_eq!(render::json(real(0.5), Some(&average)), serde_json::json!(0.5), "{}", sql_type);
+        }
+        let (shown, _) = stats_columns(&column("BOOLEAN"));
+        assert_eq!(shown.r#type, DataType::Boolean as i32);
+    }
+
+    #[test]
+    fn test_split_column_stats_checks_row_width() {
+        let column = |name: &str| bulk::SchemaColumn {
+            name: name.to_string(),
+            sql_type: "INTEGER".to_string(),
+            not_null: false,
+            has_default: false,
+            primary_key: false,
+            allowed_values: None,
+        };
+        let profiled = [column("a"), column("b")];
+        let int = |i: i64| Value { value: Some(value::Value::IntValue(i)) };
+        let values: Vec<Value> = (0..11).map(int).collect();
+
+        let (rows, stats) = split_column_stats(&profiled, &values).unwrap();
+        assert_eq!(rows, 0);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[1].0.name, "b");
+        assert_eq!(stats[1].1, &values[6..11]);
+
+        assert!(split_column_stats(&profiled, &values[..10]).is_err());
+        assert!(split_column_stats(&profiled, &[]).is_err());
+    }
+}
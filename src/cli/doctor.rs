@@ -0,0 +1,499 @@
+//! `datasink doctor`: one command to triage a server and its databases.
+//!
+//! Checks that the server answers, then for every database (or the one given
+//! with -D) that it opens, whether it passed the server's integrity check
+//! at startup, its journal mode and WAL size, and with --schema
+//! whether the schema applied to it matches the file. A database file on the
+//! host the doctor runs on is also checked for permissions, free disk space
+//! and WAL or shared-memory files left behind, so run it on the server host
+//! for the full picture. Each problem comes with the step that fixes it.
+
+use std::path::Path;
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::cli::commands::{applied_schema, format_bytes};
+use crate::cli::{client, output};
+use crate::proto::admin::{DatabaseInfoRequest, DatabaseInfoResponse, PingRequest, ServerStatusRequest};
+use crate::schema::{parser, Schema};
+
+/// A WAL this large means checkpoints are not keeping up
+const LARGE_WAL_BYTES: i64 = 64 * 1024 * 1024;
+
+/// Less free space than this share of the disk is worth a warning
+const LOW_FREE_SPACE_PERCENT: u64 = 10;
+
+/// How bad a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Ok,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Ok => "ok",
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Severity::Ok => "✅",
+            Severity::Info => "ℹ️ ",
+            Severity::Warning => "⚠️ ",
+            Severity::Error => "❌",
+        }
+    }
+}
+
+/// The result of one check
+pub struct Finding {
+    pub severity: Severity,
+    /// Short name of the check, e.g. "journal_mode"
+    pub check: &'static str,
+    /// The database checked, if the check is about one
+    pub database: Option<String>,
+    pub message: String,
+    /// What to do about it
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn new(severity: Severity, check: &'static str, database: Option<&str>, message: String) -> Self {
+        Finding { severity, check, database: database.map(str::to_string), message, fix: None }
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+/// Run the checks and print the findings; returns false if any is an error
+pub async fn run(
+    server_address: String,
+    database: Option<String>,
+    schema_file: Option<String>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let schema = match &schema_file {
+        Some(path) => Some(parser::load_schema_with_checksum(Path::new(path)).await?),
+        None => None,
+    };
+    let findings = diagnose(&server_address, database.as_deref(), schema.as_ref()).await;
+    print_findings(&server_address, &findings);
+    Ok(findings.iter().all(|f| f.severity != Severity::Error))
+}
+
+async fn diagnose(server_address: &str, database: Option<&str>, schema: Option<&(Schema, String)>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let started = Instant::now();
+    let ping = match client::connect(server_address.to_string()).await {
+        Ok(mut client) => client.ping(PingRequest {}).await.map(|r| (client, r.into_inner())).map_err(|e| e.message().to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let (mut client, pong) = match ping {
+        Ok(ping) => ping,
+        Err(e) => {
+            findings.push(
+                Finding::new(Severity::Error, "server", None, format!("Cannot reach the server at {}: {}", server_address, e))
+                    .fix("Start it with `datasink server start`, or pass the right address with -s"),
+            );
+            return findings;
+        }
+    };
+    findings.push(Finding::new(
+        Severity::Ok,
+        "server",
+        None,
+        format!(
+            "Server {} answered in {:.2} ms (version {}, up {}s)",
+            server_address,
+            started.elapsed().as_secs_f64() * 1000.0,
+            pong.version,
+            pong.uptime_seconds
+        ),
+    ));
+
+    let names = match database {
+        Some(name) => vec![name.to_string()],
+        None => match client.get_server_status(ServerStatusRequest {}).await {
+            Ok(status) => status.into_inner().databases.into_iter().map(|d| d.name).collect(),
+            Err(e) => {
+                findings.push(Finding::new(Severity::Error, "server", None, format!("Cannot list databases: {}", e.message())));
+                return findings;
+            }
+        },
+    };
+
+    let mut schema_checked = false;
+    let mut directories = std::collections::BTreeSet::new();
+    for name in &names {
+        let info = match client.get_database_info(DatabaseInfoRequest { database: name.clone() }).await {
+            Ok(info) => info.into_inner(),
+            Err(e) => {
+                findings.push(
+                    Finding::new(Severity::Error, "open", Some(name), format!("Cannot open database: {}", e.message()))
+                        .fix(format!("Check the server log, then `datasink server info -D {}`", name)),
+                );
+                continue;
+            }
+        };
+        findings.extend(check_integrity(&info));
+        findings.extend(check_journal(&info));
+        if !info.file_path.is_empty() {
+            let path = Path::new(&info.file_path);
+            findings.extend(check_files(&info, path));
+            if path.exists() {
+                directories.insert(database_directory(path).to_path_buf());
+            }
+        }
+
+        if let Some((schema, checksum)) = schema {
+            match applied_schema(&mut client, name).await {
+                Ok(applied) => {
+                    let finding = check_schema(name, applied, schema, checksum, database.is_some());
+                    schema_checked |= finding.is_some();
+                    findings.extend(finding);
+                }
+                Err(e) => findings.push(Finding::new(
+                    Severity::Error,
+                    "schema",
+                    Some(name),
+                    format!("Cannot read the applied schema: {}", e),
+                )),
+            }
+        }
+    }
+    for directory in directories {
+        findings.extend(orphaned_journal_files(&directory));
+    }
+    if let Some((schema, _)) = schema.filter(|_| !schema_checked && database.is_none()) {
+        findings.push(Finding::new(
+            Severity::Info,
+            "schema",
+            None,
+            format!("No database has schema '{}' applied", schema.database.name),
+        ));
+    }
+
+    findings
+}
+
+/// Problems the server's quick_check found when it opened the database
+fn check_integrity(info: &DatabaseInfoResponse) -> Option<Finding> {
+    if info.problems.is_empty() {
+        return None;
+    }
+    Some(
+        Finding::new(
+            Severity::Error,
+            "integrity",
+            Some(&info.name),
+            format!("Database is degraded: {}", info.problems.join("; ")),
+        )
+        .fix("Restore it from a backup, or stop the server and try `sqlite3 <file> .recover`"),
+    )
+}
+
+/// Journal mode and the size of the write-ahead log
+fn check_journal(info: &DatabaseInfoResponse) -> Vec<Finding> {
+    let name = Some(info.name.as_str());
+    let mut findings = Vec::new();
+    match info.journal_mode.as_str() {
+        // In-memory databases have no journal to choose
+        _ if info.file_path.is_empty() => {}
+        "wal" => findings.push(Finding::new(Severity::Ok, "journal_mode", name, "Journal mode is WAL".to_string())),
+        mode => findings.push(
+            Finding::new(
+                Severity::Warning,
+                "journal_mode",
+                name,
+                format!("Journal mode is {}; writers block readers", mode.to_uppercase()),
+            )
+            .fix(format!("datasink server pragma journal_mode=wal -D {}", info.name)),
+        ),
+    }
+
+    let wal_bytes = info.disk_usage.as_ref().map_or(0, |usage| usage.wal_bytes);
+    if wal_bytes > LARGE_WAL_BYTES {
+        findings.push(
+            Finding::new(
+                Severity::Warning,
+                "wal",
+                name,
+                format!("The WAL has grown to {}; a long-running reader may be holding back checkpoints", format_bytes(wal_bytes)),
+            )
+            .fix(format!("datasink query \"PRAGMA wal_checkpoint(TRUNCATE)\" -D {}", info.name)),
+        );
+    }
+    findings
+}
+
+/// Permissions, free space and left-over journal files of a database file,
+/// if it is on this host
+fn check_files(info: &DatabaseInfoResponse, path: &Path) -> Vec<Finding> {
+    let name = Some(info.name.as_str());
+    if !path.exists() {
+        return vec![Finding::new(
+            Severity::Info,
+            "files",
+            name,
+            format!("{} is not on this host; run doctor on the server host to check its files", path.display()),
+        )];
+    }
+    let mut findings = Vec::new();
+
+    let writable = std::fs::OpenOptions::new().read(true).write(true).open(path).is_ok();
+    let directory = database_directory(path);
+    let directory_writable = std::fs::metadata(directory).map(|m| !m.permissions().readonly()).unwrap_or(false);
+    if !writable {
+        findings.push(
+            Finding::new(Severity::Error, "permissions", name, format!("{} is not writable by this user", path.display()))
+                .fix("Give the user the server runs as write access to the file"),
+        );
+    } else if !directory_writable {
+        findings.push(
+            Finding::new(
+                Severity::Error,
+                "permissions",
+                name,
+                format!("{} is read-only; SQLite cannot create its journal files", directory.display()),
+            )
+            .fix("Give the user the server runs as write access to the directory"),
+        );
+    } else {
+        findings.push(Finding::new(Severity::Ok, "permissions", name, format!("{} is writable", path.display())));
+    }
+
+    if let Some((available, total)) = free_space(directory) {
+        let file_bytes = info.disk_usage.as_ref().map_or(0, |usage| usage.file_bytes);
+        // VACUUM writes a full copy of the database before replacing it
+        if available < file_bytes as u64 {
+            findings.push(
+                Finding::new(
+                    Severity::Error,
+                    "disk_space",
+                    name,
+                    format!("Only {} free, less than the database's size", format_bytes(available as i64)),
+                )
+                .fix("Free up disk space or move the database to a larger disk"),
+            );
+        } else if available * 100 < total * LOW_FREE_SPACE_PERCENT {
+            findings.push(
+                Finding::new(
+                    Severity::Warning,
+                    "disk_space",
+                    name,
+                    format!("Only {} of {} free on the database's disk", format_bytes(available as i64), format_bytes(total as i64)),
+                )
+                .fix("Free up disk space before the database fills it"),
+            );
+        } else {
+            findings.push(Finding::new(Severity::Ok, "disk_space", name, format!("{} free", format_bytes(available as i64))));
+        }
+    }
+
+    // WAL and shared-memory files only belong next to a database in WAL mode
+    if info.journal_mode != "wal" {
+        for suffix in ["-wal", "-shm"] {
+            let file = format!("{}{}", path.display(), suffix);
+            if Path::new(&file).exists() {
+                findings.push(
+                    Finding::new(
+                        Severity::Warning,
+                        "dangling_wal",
+                        name,
+                        format!("{} is left over from WAL mode or a crash", file),
+                    )
+                    .fix("Remove it once no process has the database open"),
+                );
+            }
+        }
+    }
+    findings
+}
+
+fn database_directory(path: &Path) -> &Path {
+    path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."))
+}
+
+/// WAL and shared-memory files in a directory whose database file is gone
+fn orphaned_journal_files(directory: &Path) -> Vec<Finding> {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.to_string_lossy();
+            let database = name.strip_suffix("-wal").or_else(|| name.strip_suffix("-shm"));
+            database.is_some_and(|database| !Path::new(database).exists())
+        })
+        .collect();
+    files.sort();
+    files
+        .into_iter()
+        .map(|path| {
+            Finding::new(Severity::Warning, "dangling_wal", None, format!("{} has no database file", path.display()))
+                .fix("Remove it, or restore the database file it belongs to")
+        })
+        .collect()
+}
+
+/// Available and total bytes on the disk holding `path`
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid statvfs to fill in
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block_size = stat.f_frsize as u64;
+    Some((stat.f_bavail as u64 * block_size, stat.f_blocks as u64 * block_size))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Compare the schema applied to a database with the schema file. Databases
+/// with another schema applied are only reported when named with -D.
+fn check_schema(
+    name: &str,
+    applied: Option<(String, String, String, i64)>,
+    schema: &Schema,
+    checksum: &str,
+    named: bool,
+) -> Option<Finding> {
+    let expected = &schema.database;
+    let Some((applied_name, version, applied_checksum, _)) = applied else {
+        return named.then(|| {
+            Finding::new(Severity::Warning, "schema", Some(name), "No schema has been applied".to_string())
+                .fix("Create it with `datasink server create-from-schema`, or pick the database with -D")
+        });
+    };
+    if applied_name != expected.name {
+        return named.then(|| {
+            Finding::new(
+                Severity::Warning,
+                "schema",
+                Some(name),
+                format!("Has schema '{}' applied, not '{}'", applied_name, expected.name),
+            )
+        });
+    }
+
+    let plan = "Generate the migration with `datasink schema plan <applied schema file> <schema file>`";
+    Some(if version != expected.version {
+        Finding::new(
+            Severity::Warning,
+            "schema",
+            Some(name),
+            format!("Schema '{}' is at version {}; the file is version {}", applied_name, version, expected.version),
+        )
+        .fix(plan)
+    } else if applied_checksum != checksum {
+        Finding::new(
+            Severity::Warning,
+            "schema",
+            Some(name),
+            format!("The schema file changed since version {} was applied, without a version bump", version),
+        )
+        .fix(plan)
+    } else {
+        Finding::new(Severity::Ok, "schema", Some(name), format!("Schema '{}' version {} is up to date", applied_name, version))
+    })
+}
+
+fn print_findings(server_address: &str, findings: &[Finding]) {
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+
+    if output::enabled() {
+        let findings: Vec<_> = findings
+            .iter()
+            .map(|f| {
+                json!({
+                    "severity": f.severity.name(),
+                    "check": f.check,
+                    "database": f.database,
+                    "message": f.message,
+                    "fix": f.fix,
+                })
+            })
+            .collect();
+        output::print(&json!({
+            "server": server_address,
+            "healthy": errors == 0,
+            "errors": errors,
+            "warnings": warnings,
+            "findings": findings,
+        }));
+        return;
+    }
+
+    for finding in findings {
+        match &finding.database {
+            Some(database) => println!("{} [{}] {}", finding.severity.icon(), database, finding.message),
+            None => println!("{} {}", finding.severity.icon(), finding.message),
+        }
+        if let Some(fix) = &finding.fix {
+            println!("   Fix: {}", fix);
+        }
+    }
+    println!();
+    println!("{} errors, {} warnings", errors, warnings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema(version: &str) -> Schema {
+        toml::from_str(&format!("[database]\nname = \"shop\"\ndescription = \"\"\nversion = \"{}\"\n", version)).unwrap()
+    }
+
+    #[test]
+    fn test_check_schema() {
+        let applied = || Some(("shop".to_string(), "1.0.0".to_string(), "abc".to_string(), 0));
+
+        let finding = check_schema("main", applied(), &schema("1.0.0"), "abc", false).unwrap();
+        assert_eq!(finding.severity, Severity::Ok);
+        let finding = check_schema("main", applied(), &schema("1.1.0"), "def", false).unwrap();
+        assert_eq!(finding.message, "Schema 'shop' is at version 1.0.0; the file is version 1.1.0");
+        let finding = check_schema("main", applied(), &schema("1.0.0"), "def", false).unwrap();
+        assert_eq!(finding.severity, Severity::Warning);
+        assert!(finding.fix.unwrap().contains("schema plan"));
+
+        // Other databases are only reported when asked for
+        assert!(check_schema("logs", None, &schema("1.0.0"), "abc", false).is_none());
+        assert_eq!(check_schema("logs", None, &schema("1.0.0"), "abc", true).unwrap().severity, Severity::Warning);
+        let other = Some(("logs".to_string(), "2.0.0".to_string(), "x".to_string(), 0));
+        assert!(check_schema("logs", other, &schema("1.0.0"), "abc", false).is_none());
+    }
+
+    #[test]
+    fn test_orphaned_journal_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["a.db", "a.db-wal", "b.db-wal", "b.db-shm"] {
+            std::fs::write(dir.path().join(file), b"").unwrap();
+        }
+        let findings = orphaned_journal_files(dir.path());
+        assert_eq!(findings.len(), 2);
+        assert!(findings[0].message.ends_with("b.db-shm has no database file"));
+        assert!(findings[1].message.ends_with("b.db-wal has no database file"));
+    }
+}
@@ -0,0 +1,151 @@
+//! Error categories and exit codes.
+//!
+//! A command that fails exits with the code of its error's category, so
+//! scripts can tell a server that is down from a row that does not exist:
+//!
+//! | Code | Category               | Examples                                                  |
+//! |------|------------------------|-----------------------------------------------------------|
+//! | 0    | success                |                                                           |
+//! | 1    | other                  | internal server errors, aborted prompts                   |
+//! | 2    | invalid input          | malformed JSON or SQL, bad flag values, missing variables |
+//! | 3    | connection             | server unreachable or gone away                           |
+//! | 4    | not found              | missing table, row, file or database                      |
+//! | 5    | constraint violation   | UNIQUE, NOT NULL, CHECK or foreign key, existing table    |
+//!
+//! Command line usage errors reported by clap also exit with 2. Errors from
+//! the server are categorised by their gRPC status code.
+
+use std::fmt;
+
+use crate::proto::common::Error as QueryError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Other,
+    InvalidInput,
+    Connection,
+    NotFound,
+    ConstraintViolation,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::InvalidInput => 2,
+            ErrorKind::Connection => 3,
+            ErrorKind::NotFound => 4,
+            ErrorKind::ConstraintViolation => 5,
+        }
+    }
+
+    /// Name used for the category in --json error documents
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::InvalidInput => "invalid_input",
+            ErrorKind::Connection => "connection",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::ConstraintViolation => "constraint_violation",
+        }
+    }
+
+    pub fn from_status(code: tonic::Code) -> Self {
+        match code {
+            tonic::Code::InvalidArgument | tonic::Code::OutOfRange => ErrorKind::InvalidInput,
+            tonic::Code::Unavailable => ErrorKind::Connection,
+            tonic::Code::NotFound => ErrorKind::NotFound,
+            tonic::Code::AlreadyExists | tonic::Code::FailedPrecondition => ErrorKind::ConstraintViolation,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// An error raised by the CLI itself, with its category
+#[derive(Debug)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into() }
+    }
+
+    /// The error ending a Query response stream
+    pub fn query(error: &QueryError) -> Self {
+        let kind = match error.code.as_str() {
+            "CONSTRAINT_VIOLATION" => ErrorKind::ConstraintViolation,
+            _ => ErrorKind::InvalidInput,
+        };
+        Self::new(kind, format!("Query error: {} - {}", error.code, error.message))
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Category of an error that ended a command
+pub fn kind_of(error: &(dyn std::error::Error + 'static)) -> ErrorKind {
+    if let Some(error) = error.downcast_ref::<CliError>() {
+        error.kind
+    } else if let Some(status) = error.downcast_ref::<tonic::Status>() {
+        ErrorKind::from_status(status.code())
+    } else if error.is::<serde_json::Error>() {
+        ErrorKind::InvalidInput
+    } else {
+        ErrorKind::Other
+    }
+}
+
+/// Text of an error for the terminal; a gRPC status shows just its message
+pub fn message(error: &(dyn std::error::Error + 'static)) -> String {
+    match error.downcast_ref::<tonic::Status>() {
+        Some(status) => status.message().to_string(),
+        None => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_of() {
+        let cases: Vec<(Box<dyn std::error::Error>, ErrorKind)> = vec![
+            (Box::new(tonic::Status::not_found("no row")), ErrorKind::NotFound),
+            (Box::new(tonic::Status::already_exists("UNIQUE constraint failed")), ErrorKind::ConstraintViolation),
+            (Box::new(tonic::Status::unavailable("connection reset")), ErrorKind::Connection),
+            (Box::new(tonic::Status::internal("disk I/O error")), ErrorKind::Other),
+            (Box::new(CliError::new(ErrorKind::Connection, "Failed to connect")), ErrorKind::Connection),
+            (serde_json::from_str::<serde_json::Value>("{").unwrap_err().into(), ErrorKind::InvalidInput),
+            ("Aborted".into(), ErrorKind::Other),
+        ];
+        for (error, kind) in cases {
+            assert_eq!(kind_of(error.as_ref()), kind, "{}", error);
+        }
+        assert_eq!(ErrorKind::ConstraintViolation.exit_code(), 5);
+    }
+
+    #[test]
+    fn test_query_error_kind() {
+        let error = QueryError {
+            code: "CONSTRAINT_VIOLATION".to_string(),
+            message: "UNIQUE constraint failed".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(CliError::query(&error).kind, ErrorKind::ConstraintViolation);
+        let error = QueryError {
+            code: "QUERY_ERROR".to_string(),
+            message: "near \"SELEC\": syntax error".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(CliError::query(&error).kind, ErrorKind::InvalidInput);
+    }
+}
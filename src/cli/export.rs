@@ -0,0 +1,362 @@
+//! `datasink export`: write a table's rows to a file (or stdout) as NDJSON,
+//! a JSON array or CSV.
+//!
+//! Rows are read through a Query stream and written as they arrive, so the
+//! export is never held in memory. `--columns`/`--exclude` choose the columns
+//! written and `--filter COLUMN OP VALUE` narrows the rows. Both are checked
+//! against the table's schema before the query is sent, and filter values are
+//! bound as query parameters, so a partial export (the last 30 days, only the
+//! non-PII columns) needs no hand-written SQL.
+//!
+//! With `--compress gzip|zstd`, or an output file ending in `.gz` or `.zst`,
+//! the output is compressed as it is written.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+
+use crate::cli::bulk::{self, SchemaColumn};
+use crate::cli::client;
+use crate::cli::commands::{self, proto_value_to_json};
+use crate::cli::error::{CliError, ErrorKind};
+use crate::cli::output;
+use crate::compression::{self, Compression, Encoder};
+use crate::proto::common::{value, Value};
+use crate::proto::crud::{query_response, QueryRequest, QueryResponse};
+
+/// What an export reads and where it writes it
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub table: String,
+    /// File to write; stdout when `None` or `-`
+    pub path: Option<String>,
+    pub format: ExportFormat,
+    pub compression: Compression,
+    /// Columns to write, in this order; all columns when empty
+    pub columns: Vec<String>,
+    /// Columns to leave out
+    pub exclude: Vec<String>,
+    /// Conditions a row must meet, all of them
+    pub filters: Vec<Filter>,
+    /// Extra SQL condition, ANDed with the filters
+    pub where_clause: Option<String>,
+    pub database: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line
+    Ndjson,
+    /// A single JSON array of objects
+    Json,
+    /// A header line, then one line per row (RFC 4180 quoting)
+    Csv,
+}
+
+impl ExportFormat {
+    /// The format a file name implies: `.csv` and `.json` by extension
+    /// (before any compression extension), NDJSON for anything else
+    pub fn for_path(path: &str) -> Self {
+        let lower = compression::strip_extension(path).to_lowercase();
+        if lower.ends_with(".csv") {
+            ExportFormat::Csv
+        } else if lower.ends_with(".json") {
+            ExportFormat::Json
+        } else {
+            ExportFormat::Ndjson
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("Unknown export format '{}' (expected ndjson, json or csv)", s)),
+        }
+    }
+}
+
+/// Comparison operators a filter can use, longest first for parsing
+const OPERATORS: [&str; 6] = ["!=", "<=", ">=", "=", "<", ">"];
+
+/// One `COLUMN OP VALUE` condition of `--filter`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub column: String,
+    /// One of `=`, `!=`, `<`, `<=`, `>`, `>=`
+    pub operator: &'static str,
+    pub value: FilterValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    /// `null`, only with `=` and `!=` (IS NULL / IS NOT NULL)
+    Null,
+    Literal(Value),
+    /// `now` or `now-30d`: the Unix time this many seconds before the export
+    /// starts (units s, m, h, d)
+    SecondsAgo(i64),
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid filter '{}': expected COLUMN OP VALUE with OP one of {}", spec, OPERATORS.join(" "));
+        let start = spec.find(['=', '!', '<', '>']).ok_or_else(invalid)?;
+        let operator = OPERATORS
+            .into_iter()
+            .find(|op| spec[start..].starts_with(op))
+            .ok_or_else(invalid)?;
+        let column = spec[..start].trim();
+        if column.is_empty() || !column.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid());
+        }
+        let raw = spec[start + operator.len()..].trim();
+        let value = parse_filter_value(raw).map_err(|e| format!("Invalid filter '{}': {}", spec, e))?;
+        if value == FilterValue::Null && !matches!(operator, "=" | "!=") {
+            return Err(format!("Invalid filter '{}': null can only be compared with = or !=", spec));
+        }
+        Ok(Filter { column: column.to_string(), operator, value })
+    }
+}
+
+/// A filter value: `null`, a relative time, a quoted string (kept as text),
+/// or a bare integer, number or text
+fn parse_filter_value(raw: &str) -> Result<FilterValue, String> {
+    if raw.is_empty() {
+        return Err("missing value".to_string());
+    }
+    if raw.eq_ignore_ascii_case("null") {
+        return Ok(FilterValue::Null);
+    }
+    if let Some(rest) = raw.strip_prefix("now") {
+        if rest.is_empty() {
+            return Ok(FilterValue::SecondsAgo(0));
+        }
+        let amount = rest.strip_prefix('-').ok_or("relative times look like now-30d")?;
+        let unit = amount.chars().last().ok_or_else(|| format!("invalid relative time '{}'", raw))?;
+        let number = &amount[..amount.len() - unit.len_utf8()];
+        let unit_seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(format!("unknown time unit in '{}' (expected s, m, h or d)", raw)),
+        };
+        let number: i64 = number.parse().map_err(|_| format!("invalid relative time '{}'", raw))?;
+        return Ok(FilterValue::SecondsAgo(number * unit_seconds));
+    }
+    for quote in ['\'', '"'] {
+        if raw.len() >= 2 && raw.starts_with(quote) && raw.ends_with(quote) {
+            let text = raw[1..raw.len() - 1].to_string();
+            return Ok(FilterValue::Literal(Value { value: Some(value::Value::TextValue(text)) }));
+        }
+    }
+    Ok(FilterValue::Literal(commands::parse_key_value(raw)))
+}
+
+/// The SELECT for an export of `columns` from `table`, with its parameters.
+/// Filter values are bound as `:filter_N`; relative times are resolved
+/// against `now` (Unix seconds).
+pub fn build_query(
+    table: &str,
+    columns: &[String],
+    filters: &[Filter],
+    where_clause: Option<&str>,
+    now: i64,
+) -> (String, HashMap<String, Value>) {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let mut sql = format!(
+        "SELECT {} FROM {}",
+        columns.iter().map(|name| quote(name)).collect::<Vec<_>>().join(", "),
+        quote(table)
+    );
+    let mut conditions = Vec::new();
+    let mut parameters = HashMap::new();
+    for (i, filter) in filters.iter().enumerate() {
+        let column = quote(&filter.column);
+        let value = match &filter.value {
+            FilterValue::Null => {
+                let test = if filter.operator == "=" { "IS NULL" } else { "IS NOT NULL" };
+                conditions.push(format!("{} {}", column, test));
+                continue;
+            }
+            FilterValue::Literal(value) => value.clone(),
+            FilterValue::SecondsAgo(seconds) => Value { value: Some(value::Value::IntValue(now - seconds)) },
+        };
+        let parameter = format!("filter_{}", i);
+        conditions.push(format!("{} {} :{}", column, filter.operator, parameter));
+        parameters.insert(parameter, value);
+    }
+    if let Some(where_clause) = where_clause {
+        conditions.push(format!("({})", where_clause));
+    }
+    if !conditions.is_empty() {
+        sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+    }
+    (sql, parameters)
+}
+
+/// The columns to export, in order: `--columns` if given, else every
+/// column of the table, less `--exclude`
+fn select_columns(schema: &[SchemaColumn], options: &ExportOptions) -> Result<Vec<String>, CliError> {
+    let names: Vec<&str> = schema.iter().map(|column| column.name.as_str()).collect();
+    let resolve = |wanted: &String| {
+        names
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case(wanted))
+            .map(|name| name.to_string())
+            .ok_or_else(|| {
+                CliError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Table '{}' has no column '{}' (columns: {})", options.table, wanted, names.join(", ")),
+                )
+            })
+    };
+    for filter in &options.filters {
+        resolve(&filter.column)?;
+    }
+    let excluded = options.exclude.iter().map(resolve).collect::<Result<Vec<_>, _>>()?;
+    let chosen = if options.columns.is_empty() {
+        names.iter().map(|name| name.to_string()).collect()
+    } else {
+        options.columns.iter().map(resolve).collect::<Result<Vec<_>, _>>()?
+    };
+    let columns: Vec<String> = chosen.into_iter().filter(|name| !excluded.contains(name)).collect();
+    if columns.is_empty() {
+        return Err(CliError::new(ErrorKind::InvalidInput, "No columns left to export"));
+    }
+    Ok(columns)
+}
+
+pub async fn run_export(server_address: String, options: ExportOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let schema = bulk::fetch_schema(&mut client, &options.table, &options.database).await?;
+    if schema.is_empty() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", options.table)).into());
+    }
+    let columns = select_columns(&schema, &options)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default();
+    let (sql, parameters) =
+        build_query(&options.table, &columns, &options.filters, options.where_clause.as_deref(), now);
+
+    let request = QueryRequest { sql, parameters, database: options.database.clone() };
+    let mut stream = client.query(request).await?.into_inner();
+
+    let to_file = options.path.as_deref().filter(|path| *path != "-").map(|path| output_path(path, options.compression));
+    let sink: Box<dyn Write> = match &to_file {
+        Some(path) => Box::new(std::fs::File::create(path).map_err(|e| {
+            CliError::new(ErrorKind::Other, format!("Failed to create {}: {}", path, e))
+        })?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut writer = Encoder::new(std::io::BufWriter::new(sink), options.compression)?;
+
+    match options.format {
+        ExportFormat::Csv => writeln!(writer, "{}", columns.iter().map(|c| csv_field(c)).collect::<Vec<_>>().join(","))?,
+        ExportFormat::Json => write!(writer, "[")?,
+        ExportFormat::Ndjson => {}
+    }
+    let mut rows: u64 = 0;
+    let mut truncated = false;
+    while let Some(response) = stream.next().await {
+        match response? {
+            QueryResponse { response: Some(query_response::Response::ResultSet(result_set)) } => {
+                for row in result_set.rows {
+                    write_row(&mut writer, options.format, &columns, row.values, rows == 0)?;
+                    rows += 1;
+                }
+            }
+            QueryResponse { response: Some(query_response::Response::Error(error)) } => {
+                return Err(CliError::query(&error).into());
+            }
+            QueryResponse { response: Some(query_response::Response::Summary(summary)) } => {
+                truncated = summary.truncated;
+            }
+            _ => {}
+        }
+    }
+    if options.format == ExportFormat::Json {
+        writeln!(writer, "{}]", if rows > 0 { "\n" } else { "" })?;
+    }
+    writer.finish()?;
+
+    if truncated {
+        return Err(CliError::new(
+            ErrorKind::Other,
+            format!(
+                "Export stopped at the server row limit after {} rows; narrow it with --filter or raise the database's max_query_rows",
+                rows
+            ),
+        )
+        .into());
+    }
+    if let Some(path) = to_file {
+        output::success(
+            &format!("Exported {} rows ({} columns) from '{}' to {}", rows, columns.len(), options.table, path),
+            serde_json::json!({ "table": options.table, "path": path, "rows": rows, "columns": columns }),
+        );
+    }
+    Ok(())
+}
+
+fn write_row(
+    writer: &mut impl Write,
+    format: ExportFormat,
+    columns: &[String],
+    values: Vec<Value>,
+    first: bool,
+) -> std::io::Result<()> {
+    match format {
+        ExportFormat::Csv => {
+            let fields: Vec<String> = values.into_iter().map(csv_value).collect();
+            writeln!(writer, "{}", fields.join(","))
+        }
+        ExportFormat::Json | ExportFormat::Ndjson => {
+            let object: serde_json::Map<String, serde_json::Value> =
+                columns.iter().cloned().zip(values.into_iter().map(proto_value_to_json)).collect();
+            let line = serde_json::Value::Object(object).to_string();
+            match format {
+                ExportFormat::Json => write!(writer, "{}\n  {}", if first { "" } else { "," }, line),
+                _ => writeln!(writer, "{}", line),
+            }
+        }
+    }
+}
+
+/// The file to write: `path`, with the compression's extension added if it
+/// lacks it so that imports know to decompress it
+pub fn output_path(path: &str, compression: Compression) -> String {
+    if Compression::for_path(path) == compression {
+        path.to_string()
+    } else {
+        format!("{}{}", path, compression.extension())
+    }
+}
+
+/// A value as a CSV field: NULL is empty, blobs are base64
+fn csv_value(value: Value) -> String {
+    match proto_value_to_json(value) {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => csv_field(&s),
+        other => other.to_string(),
+    }
+}
+
+/// Quote a CSV field if it holds a comma, quote or line break
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,475 @@
+//! Fake data generation: `datasink generate`
+//!
+//! Inspects a table's schema and fills it with plausible values (names,
+//! emails, timestamps, numbers in range), choosing a generator per column from
+//! its name and type unless overridden with `--set column=generator`.
+
+use std::collections::HashMap;
+use tokio_stream::StreamExt;
+
+use crate::proto::common::{value, Value};
+use crate::proto::crud::{query_response, BatchInsertRequest, InsertRow, OnConflict, QueryRequest};
+use super::client::{self, Client};
+use super::error::{CliError, ErrorKind};
+use super::output;
+
+/// Rows sent per BatchInsert request
+const BATCH_SIZE: usize = 500;
+
+const FIRST_NAMES: &[&str] = &[
+    "Alice", "Bob", "Carol", "David", "Emma", "Frank", "Grace", "Henry", "Isabel", "James",
+    "Karen", "Liam", "Maria", "Noah", "Olivia", "Peter", "Quinn", "Rosa", "Samuel", "Tara",
+];
+const LAST_NAMES: &[&str] = &[
+    "Anderson", "Brown", "Clark", "Davis", "Evans", "Garcia", "Harris", "Jackson", "Johnson",
+    "Lee", "Martin", "Miller", "Moore", "Nguyen", "Patel", "Robinson", "Smith", "Taylor",
+    "Thomas", "Wilson",
+];
+const WORDS: &[&str] = &[
+    "alpha", "bright", "cloud", "delta", "early", "field", "green", "harbor", "island", "jolly",
+    "kernel", "lunar", "maple", "north", "ocean", "prime", "quiet", "river", "stone", "tiger",
+];
+const DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+/// A table column as reported by `PRAGMA table_info`
+#[derive(Debug, Clone)]
+pub struct TableColumn {
+    pub name: String,
+    /// Declared type, uppercased (e.g. "INTEGER", "VARCHAR(255)")
+    pub sql_type: String,
+}
+
+/// How values for one column are produced
+#[derive(Debug, Clone, PartialEq)]
+pub enum Generator {
+    FirstName,
+    LastName,
+    FullName,
+    Email,
+    Url,
+    Phone,
+    /// Unix timestamp within the last `days` days
+    Timestamp { days: i64 },
+    Int { min: i64, max: i64 },
+    Real { min: f64, max: f64 },
+    Bool,
+    /// A few random words
+    Text,
+    Uuid,
+    Choice(Vec<String>),
+    /// Row number, continuing after the rows already in the table
+    Sequence,
+    Blob,
+    Null,
+}
+
+impl std::str::FromStr for Generator {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = match spec.split_once(':') {
+            Some((kind, arg)) => (kind.trim().to_lowercase(), Some(arg.trim())),
+            None => (spec.trim().to_lowercase(), None),
+        };
+
+        let range = |arg: Option<&str>| -> Result<(String, String), String> {
+            let arg = arg.ok_or_else(|| format!("Generator '{}' needs a range, e.g. {}:1..100", kind, kind))?;
+            let (min, max) = arg
+                .split_once("..")
+                .ok_or_else(|| format!("Invalid range '{}': expected MIN..MAX", arg))?;
+            Ok((min.trim().to_string(), max.trim().to_string()))
+        };
+
+        let generator = match kind.as_str() {
+            "first_name" => Generator::FirstName,
+            "last_name" => Generator::LastName,
+            "name" | "full_name" => Generator::FullName,
+            "email" => Generator::Email,
+            "url" => Generator::Url,
+            "phone" => Generator::Phone,
+            "timestamp" => Generator::Timestamp {
+                days: arg.map(|d| d.parse().map_err(|_| format!("Invalid day count '{}'", d))).transpose()?.unwrap_or(365),
+            },
+            "int" => {
+                let (min, max) = range(arg)?;
+                let min = min.parse().map_err(|_| format!("Invalid integer '{}'", min))?;
+                let max = max.parse().map_err(|_| format!("Invalid integer '{}'", max))?;
+                if min > max {
+                    return Err(format!("Invalid range {}..{}: MIN is greater than MAX", min, max));
+                }
+                Generator::Int { min, max }
+            }
+            "real" => {
+                let (min, max) = range(arg)?;
+                let min = min.parse().map_err(|_| format!("Invalid number '{}'", min))?;
+                let max = max.parse().map_err(|_| format!("Invalid number '{}'", max))?;
+                if min > max {
+                    return Err(format!("Invalid range {}..{}: MIN is greater than MAX", min, max));
+                }
+                Generator::Real { min, max }
+            }
+            "bool" => Generator::Bool,
+            "text" => Generator::Text,
+            "uuid" => Generator::Uuid,
+            "choice" => {
+                let choices: Vec<String> = arg
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|c| c.trim().to_string())
+                    .filter(|c| !c.is_empty())
+                    .collect();
+                if choices.is_empty() {
+                    return Err("Generator 'choice' needs values, e.g. choice:active,inactive".to_string());
+                }
+                Generator::Choice(choices)
+            }
+            "sequence" => Generator::Sequence,
+            "blob" => Generator::Blob,
+            "null" => Generator::Null,
+            _ => {
+                return Err(format!(
+                    "Unknown generator '{}' (expected first_name, last_name, name, email, url, phone, \
+                     timestamp[:DAYS], int:MIN..MAX, real:MIN..MAX, bool, text, uuid, choice:A,B,..., \
+                     sequence, blob or null)",
+                    kind
+                ))
+            }
+        };
+        Ok(generator)
+    }
+}
+
+impl Generator {
+    /// Pick a generator from a column's name, falling back to its declared type
+    pub fn infer(column: &TableColumn) -> Generator {
+        let name = column.name.to_lowercase();
+        let sql_type = column.sql_type.as_str();
+        let is_int = sql_type.contains("INT");
+        let is_real = sql_type.contains("REAL") || sql_type.contains("FLOA") || sql_type.contains("DOUB");
+        let is_numeric = is_int || is_real;
+
+        if name.contains("email") {
+            Generator::Email
+        } else if name == "first_name" || name == "firstname" {
+            Generator::FirstName
+        } else if name == "last_name" || name == "lastname" || name == "surname" {
+            Generator::LastName
+        } else if !is_numeric && (name == "name" || name.ends_with("_name") || name == "author" || name.ends_with("_by")) {
+            Generator::FullName
+        } else if !is_numeric && (name.contains("url") || name.contains("website")) {
+            Generator::Url
+        } else if !is_numeric && name.contains("phone") {
+            Generator::Phone
+        } else if !is_numeric && (name == "uuid" || name == "guid") {
+            Generator::Uuid
+        } else if !is_numeric && name == "status" {
+            Generator::Choice(vec!["active".to_string(), "inactive".to_string(), "pending".to_string()])
+        } else if sql_type.contains("TIMESTAMP")
+            || sql_type.contains("DATE")
+            || name.ends_with("_at")
+            || name.ends_with("_time")
+            || name.ends_with("_date")
+        {
+            Generator::Timestamp { days: 365 }
+        } else if sql_type.contains("BOOL") || name.starts_with("is_") || name.starts_with("has_") {
+            Generator::Bool
+        } else if name == "age" {
+            Generator::Int { min: 18, max: 90 }
+        } else if name.contains("price") || name.contains("amount") || name.contains("cost") || name.contains("total") {
+            Generator::Real { min: 1.0, max: 1000.0 }
+        } else if is_int {
+            Generator::Int { min: 0, max: 1000 }
+        } else if is_real {
+            Generator::Real { min: 0.0, max: 1000.0 }
+        } else if sql_type.contains("BLOB") {
+            Generator::Blob
+        } else {
+            Generator::Text
+        }
+    }
+
+    /// Produce the value for row `index` (0-based)
+    pub fn generate(&self, rng: &mut FakeRng, index: usize) -> value::Value {
+        match self {
+            Generator::FirstName => value::Value::TextValue(rng.pick(FIRST_NAMES).to_string()),
+            Generator::LastName => value::Value::TextValue(rng.pick(LAST_NAMES).to_string()),
+            Generator::FullName => {
+                value::Value::TextValue(format!("{} {}", rng.pick(FIRST_NAMES), rng.pick(LAST_NAMES)))
+            }
+            // The row number keeps emails unique
+            Generator::Email => value::Value::TextValue(format!(
+                "{}.{}{}@{}",
+                rng.pick(FIRST_NAMES).to_lowercase(),
+                rng.pick(LAST_NAMES).to_lowercase(),
+                index + 1,
+                rng.pick(DOMAINS)
+            )),
+            Generator::Url => value::Value::TextValue(format!(
+                "https://{}/{}/{}",
+                rng.pick(DOMAINS),
+                rng.pick(WORDS),
+                index + 1
+            )),
+            Generator::Phone => value::Value::TextValue(format!(
+                "+1-555-{:03}-{:04}",
+                rng.range(0, 999),
+                rng.range(0, 9999)
+            )),
+            Generator::Timestamp { days } => {
+                let now = chrono::Utc::now().timestamp();
+                value::Value::IntValue(now - rng.range(0, days * 24 * 60 * 60))
+            }
+            Generator::Int { min, max } => value::Value::IntValue(rng.range(*min, *max)),
+            Generator::Real { min, max } => {
+                // Two decimal places read more naturally than full-precision noise
+                let value = min + rng.fraction() * (max - min);
+                value::Value::RealValue((value * 100.0).round() / 100.0)
+            }
+            Generator::Bool => value::Value::BoolValue(rng.range(0, 1) == 1),
+            Generator::Text => {
+                let count = rng.range(2, 5) as usize;
+                let words: Vec<&str> = (0..count).map(|_| *rng.pick(WORDS)).collect();
+                value::Value::TextValue(words.join(" "))
+            }
+            Generator::Uuid => value::Value::TextValue(uuid::Uuid::new_v4().to_string()),
+            Generator::Choice(choices) => value::Value::TextValue(rng.pick(choices).clone()),
+            Generator::Sequence => value::Value::IntValue(index as i64 + 1),
+            Generator::Blob => value::Value::BlobValue((0..16).map(|_| rng.range(0, 255) as u8).collect()),
+            Generator::Null => value::Value::NullValue(true),
+        }
+    }
+}
+
+/// Small seedable xorshift generator, so `--seed` reproduces a data set
+pub struct FakeRng(u64);
+
+impl FakeRng {
+    pub fn new(seed: u64) -> Self {
+        FakeRng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform integer in `min..=max`
+    pub fn range(&mut self, min: i64, max: i64) -> i64 {
+        let span = (max as i128 - min as i128 + 1) as u128;
+        (min as i128 + (self.next_u64() as u128 % span) as i128) as i64
+    }
+
+    /// Uniform float in `[0, 1)`
+    pub fn fraction(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.next_u64() as usize % items.len()]
+    }
+}
+
+pub async fn generate_data(
+    server_address: String,
+    table: String,
+    rows: usize,
+    overrides: Vec<(String, String)>,
+    seed: Option<u64>,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let database = database.unwrap_or_default();
+
+    let columns = fetch_columns(&mut client, &table, &database).await?;
+    if columns.is_empty() {
+        let message = format!("Table '{}' has no columns to fill (or does not exist)", table);
+        return Err(CliError::new(ErrorKind::NotFound, message).into());
+    }
+
+    // Resolve a generator for every column, applying --set overrides
+    let mut overrides: HashMap<String, Generator> = overrides
+        .into_iter()
+        .map(|(column, spec)| {
+            spec.parse::<Generator>()
+                .map(|generator| (column.to_lowercase(), generator))
+                .map_err(|e| format!("Invalid generator for column '{}': {}", column, e))
+        })
+        .collect::<Result<_, _>>()?;
+    let generators: Vec<(String, Generator)> = columns
+        .iter()
+        .map(|column| {
+            let generator = overrides
+                .remove(&column.name.to_lowercase())
+                .unwrap_or_else(|| Generator::infer(column));
+            (column.name.clone(), generator)
+        })
+        .collect();
+    if let Some(column) = overrides.keys().next() {
+        return Err(CliError::new(ErrorKind::InvalidInput, format!("Table '{}' has no column '{}'", table, column)).into());
+    }
+
+    // Continue numbering after existing rows so sequences and emails stay unique
+    let first_index = max_rowid(&mut client, &table, &database).await? as usize;
+
+    let seed = seed.unwrap_or_else(|| chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64);
+    let mut rng = FakeRng::new(seed);
+
+    let mut inserted = 0;
+    for batch_start in (first_index..first_index + rows).step_by(BATCH_SIZE) {
+        let batch_end = (batch_start + BATCH_SIZE).min(first_index + rows);
+        let batch: Vec<InsertRow> = (batch_start..batch_end)
+            .map(|index| InsertRow {
+                values: generators
+                    .iter()
+                    .map(|(name, generator)| {
+                        (name.clone(), Value { value: Some(generator.generate(&mut rng, index)) })
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let response = client
+            .batch_insert(BatchInsertRequest {
+                table_name: table.clone(),
+                rows: batch,
+                database: database.clone(),
+                on_conflict: OnConflict::Abort as i32,
+            })
+            .await?;
+        inserted += response.into_inner().inserted_count;
+    }
+
+    output::success(
+        &format!("✅ Generated {} rows in '{}' (seed {})", inserted, table, seed),
+        serde_json::json!({ "table": table, "inserted": inserted, "seed": seed }),
+    );
+    Ok(())
+}
+
+/// Columns to generate values for, from `PRAGMA table_info`.
+///
+/// An INTEGER PRIMARY KEY is the rowid and is left for SQLite to assign.
+pub async fn fetch_columns(
+    client: &mut Client,
+    table: &str,
+    database: &str,
+) -> Result<Vec<TableColumn>, Box<dyn std::error::Error>> {
+    let rows = query_rows(client, &format!("PRAGMA table_info({})", table), database).await?;
+
+    // Row layout: cid, name, type, notnull, dflt_value, pk
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let name = text(row.get(1)?)?;
+            let sql_type = text(row.get(2)?).unwrap_or_default().to_uppercase();
+            let primary_key = matches!(row.get(5)?.value, Some(value::Value::IntValue(pk)) if pk > 0);
+            if primary_key && sql_type == "INTEGER" {
+                return None;
+            }
+            Some(TableColumn { name, sql_type })
+        })
+        .collect())
+}
+
+/// Largest rowid in a table (0 when empty)
+pub async fn max_rowid(
+    client: &mut Client,
+    table: &str,
+    database: &str,
+) -> Result<i64, Box<dyn std::error::Error>> {
+    let rows = query_rows(client, &format!("SELECT COALESCE(MAX(rowid), 0) FROM {}", table), database).await?;
+    Ok(rows
+        .first()
+        .and_then(|row| row.first())
+        .and_then(|value| match value.value {
+            Some(value::Value::IntValue(v)) => Some(v),
+            _ => None,
+        })
+        .unwrap_or(0))
+}
+
+/// Run a query and collect all rows
+pub async fn query_rows(
+    client: &mut Client,
+    sql: &str,
+    database: &str,
+) -> Result<Vec<Vec<Value>>, Box<dyn std::error::Error>> {
+    let request = QueryRequest {
+        sql: sql.to_string(),
+        parameters: HashMap::new(),
+        database: database.to_string(),
+    };
+
+    let mut stream = client.query(request).await?.into_inner();
+    let mut rows = Vec::new();
+    while let Some(message) = stream.next().await {
+        match message?.response {
+            Some(query_response::Response::ResultSet(result_set)) => {
+                rows.extend(result_set.rows.into_iter().map(|row| row.values));
+            }
+            Some(query_response::Response::Error(error)) => {
+                return Err(CliError::query(&error).into());
+            }
+            Some(query_response::Response::Summary(_)) | None => {}
+        }
+    }
+    Ok(rows)
+}
+
+fn text(value: &Value) -> Option<String> {
+    match &value.value {
+        Some(value::Value::TextValue(s)) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, sql_type: &str) -> TableColumn {
+        TableColumn { name: name.to_string(), sql_type: sql_type.to_string() }
+    }
+
+    #[test]
+    fn test_parse_generator_specs() {
+        assert_eq!("email".parse::<Generator>().unwrap(), Generator::Email);
+        assert_eq!("int:18..90".parse::<Generator>().unwrap(), Generator::Int { min: 18, max: 90 });
+        assert_eq!("timestamp:30".parse::<Generator>().unwrap(), Generator::Timestamp { days: 30 });
+        assert_eq!(
+            "choice:open, closed".parse::<Generator>().unwrap(),
+            Generator::Choice(vec!["open".to_string(), "closed".to_string()])
+        );
+        assert!("int".parse::<Generator>().is_err());
+        assert!("int:9..1".parse::<Generator>().is_err());
+        assert!("zipcode".parse::<Generator>().is_err());
+    }
+
+    #[test]
+    fn test_infer_generator_from_column() {
+        assert_eq!(Generator::infer(&column("email", "TEXT")), Generator::Email);
+        assert_eq!(Generator::infer(&column("author_name", "TEXT")), Generator::FullName);
+        assert_eq!(Generator::infer(&column("created_at", "INTEGER")), Generator::Timestamp { days: 365 });
+        assert_eq!(Generator::infer(&column("age", "INTEGER")), Generator::Int { min: 18, max: 90 });
+        assert_eq!(Generator::infer(&column("price", "REAL")), Generator::Real { min: 1.0, max: 1000.0 });
+        assert_eq!(Generator::infer(&column("notes", "TEXT")), Generator::Text);
+    }
+
+    #[test]
+    fn test_generated_values_respect_ranges_and_seed() {
+        let generator = Generator::Int { min: 5, max: 10 };
+        let mut rng = FakeRng::new(42);
+        for index in 0..1000 {
+            match generator.generate(&mut rng, index) {
+                value::Value::IntValue(v) => assert!((5..=10).contains(&v)),
+                other => panic!("unexpected value {:?}", other),
+            }
+        }
+
+        let mut a = FakeRng::new(7);
+        let mut b = FakeRng::new(7);
+        assert_eq!(Generator::FullName.generate(&mut a, 0), Generator::FullName.generate(&mut b, 0));
+    }
+}
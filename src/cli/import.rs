@@ -0,0 +1,483 @@
+//! `datasink import`: load a CSV, JSON array or NDJSON file (optionally
+//! `.gz`/`.zst` compressed) into a table, resumably.
+//!
+//! Rows are checked against the table like `insert --file`, then sent in
+//! BatchInsert chunks one after another. After each chunk commits, the number
+//! of rows committed is written to a progress file next to the source
+//! (`FILE.progress`) together with the SHA-256 of the source file. If the
+//! import is interrupted, `--resume` skips the rows already committed, after
+//! checking that the file is unchanged; the progress file is removed once the
+//! import completes. A crash between a chunk committing and the progress file
+//! being written re-sends that one chunk on resume.
+//!
+//! `--on-conflict` decides what happens to a row that violates a UNIQUE or
+//! PRIMARY KEY constraint: `fail` stops the import at its chunk, `ignore`
+//! skips it, `replace` deletes the existing row and inserts the new one, and
+//! `update` updates the existing row with the columns the new row sets.
+//!
+//! `--mapping FILE` renames, transforms and adds fields before rows are
+//! checked; see [`crate::cli::mapping`]. `--create` creates a missing table
+//! with column types inferred from the file; see [`crate::cli::infer`].
+
+use std::path::{Path, PathBuf};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::cli::bulk::{self, BulkInsertOptions, SchemaColumn};
+use crate::cli::client;
+use crate::cli::error::{CliError, ErrorKind};
+use crate::cli::infer::{self, SAMPLE_ROWS};
+use crate::cli::mapping::Mapping;
+use crate::cli::output;
+use crate::compression::{self, Compression};
+use crate::proto::admin::CreateTableRequest;
+use crate::proto::crud::OnConflict;
+use crate::schema::data::next_csv_record;
+
+/// What an import reads and how it sends it
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub table: String,
+    pub path: String,
+    pub batch_size: usize,
+    pub on_conflict: OnConflict,
+    /// Continue an interrupted import of the same file
+    pub resume: bool,
+    /// Ignore an interrupted import's progress and load the whole file
+    pub restart: bool,
+    /// Mapping file applied to each row before it is checked
+    pub mapping: Option<String>,
+    /// Create the table from the file if it does not exist
+    pub create: bool,
+    /// Convert text values of JSON rows to their columns' types
+    pub coerce: bool,
+    pub database: String,
+}
+
+/// Contents of a progress file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub table: String,
+    pub database: String,
+    /// SHA-256 of the source file as stored (compressed or not), hex encoded
+    pub sha256: String,
+    pub total_rows: usize,
+    pub rows_committed: usize,
+}
+
+/// Where the progress of importing `path` is kept
+pub fn progress_path(path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.progress", path))
+}
+
+/// The progress file's contents, if there is one
+fn load_progress(path: &Path) -> Result<Option<Progress>, CliError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(CliError::new(ErrorKind::Other, format!("Failed to read {}: {}", path.display(), e))),
+    };
+    serde_json::from_str(&text).map(Some).map_err(|e| {
+        CliError::new(ErrorKind::InvalidInput, format!("{} is not a valid progress file: {}", path.display(), e))
+    })
+}
+
+/// Write the progress file via a temporary sibling and rename, so a crash
+/// never leaves it half-written
+fn save_progress(path: &Path, progress: &Progress) -> Result<(), CliError> {
+    let tmp_path = path.with_extension("progress.tmp");
+    let write = || -> std::io::Result<()> {
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(progress)?)?;
+        std::fs::rename(&tmp_path, path)
+    };
+    write().map_err(|e| CliError::new(ErrorKind::Other, format!("Failed to write {}: {}", path.display(), e)))
+}
+
+/// Rows to skip: those an interrupted import of the same file into the same
+/// table committed, or none
+pub fn resume_point(saved: Option<&Progress>, current: &Progress, options: &ImportOptions) -> Result<usize, String> {
+    let Some(saved) = saved.filter(|_| !options.restart) else {
+        return Ok(0);
+    };
+    if !options.resume {
+        return Err(format!(
+            "An import of {} into '{}' was interrupted after {} of {} rows; pass --resume to continue it \
+             or --restart to load the whole file again",
+            options.path, saved.table, saved.rows_committed, saved.total_rows
+        ));
+    }
+    if saved.table != current.table || saved.database != current.database {
+        return Err(format!(
+            "The interrupted import of {} was into '{}'{}; pass --restart to ignore it",
+            options.path,
+            saved.table,
+            if saved.database.is_empty() { String::new() } else { format!(" in database '{}'", saved.database) }
+        ));
+    }
+    if saved.sha256 != current.sha256 || saved.total_rows != current.total_rows {
+        return Err(format!(
+            "{} has changed since the interrupted import ({} rows were committed from the old file); \
+             pass --restart to load it from the start",
+            options.path, saved.rows_committed
+        ));
+    }
+    Ok(saved.rows_committed)
+}
+
+/// Rows of a CSV file as JSON objects, with `mapping` applied. CSV fields
+/// are all text, so they are always converted to their column's type where
+/// they convert (as `--coerce` does for JSON); empty fields are left out of
+/// the row, and anything else is kept as text for validation to report.
+pub fn csv_rows(text: &str, columns: &[SchemaColumn], mapping: &Mapping) -> Result<Vec<JsonValue>, String> {
+    let mut lines = text.lines().map(|line| Ok(line.to_string()));
+    let Some(header) = next_csv_record(&mut lines)? else {
+        return Ok(Vec::new());
+    };
+    let header: Vec<String> = header.into_iter().map(|name| name.trim().to_string()).collect();
+
+    let mut rows = Vec::new();
+    while let Some(record) = next_csv_record(&mut lines)? {
+        if record.len() == 1 && record[0].is_empty() {
+            continue;
+        }
+        let number = rows.len() + 1;
+        if record.len() != header.len() {
+            return Err(format!("Row {}: expected {} fields, found {}", number, header.len(), record.len()));
+        }
+        let fields: Map<String, JsonValue> = header
+            .iter()
+            .zip(record)
+            .filter(|(_, field)| !field.is_empty())
+            .map(|(name, field)| (name.clone(), JsonValue::String(field)))
+            .collect();
+        let row: Map<String, JsonValue> = mapping
+            .apply(fields)
+            .map_err(|e| format!("Row {}: {}", number, e))?
+            .into_iter()
+            .filter(|(_, value)| value.as_str() != Some(""))
+            .collect();
+        rows.push(JsonValue::Object(row));
+    }
+    bulk::coerce_rows(columns, &mut rows);
+    Ok(rows)
+}
+
+/// JSON documents with `mapping` applied to those that are objects; others
+/// are left for validation to report
+fn map_documents(documents: Vec<JsonValue>, mapping: &Mapping) -> Result<Vec<JsonValue>, String> {
+    documents
+        .into_iter()
+        .enumerate()
+        .map(|(i, document)| match document {
+            JsonValue::Object(row) => {
+                mapping.apply(row).map(JsonValue::Object).map_err(|e| format!("Row {}: {}", i + 1, e))
+            }
+            document => Ok(document),
+        })
+        .collect()
+}
+
+/// Columns of a table created from the file: the CSV header's, mapped, then
+/// any other fields of the sampled rows in the order they first appear
+fn field_names(text: &str, is_csv: bool, mapping: &Mapping, rows: &[JsonValue]) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    if is_csv {
+        let mut lines = text.lines().map(|line| Ok(line.to_string()));
+        if let Some(header) = next_csv_record(&mut lines)? {
+            let header: Vec<String> = header.into_iter().map(|name| name.trim().to_string()).collect();
+            names = mapping.columns(&header);
+        }
+    }
+    for row in rows.iter().take(SAMPLE_ROWS).filter_map(JsonValue::as_object) {
+        for name in row.keys() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// "✅ Imported 90 rows into 'users' (8 updated, 2 skipped)"
+fn summary(options: &ImportOptions, inserted: i64, replaced: i64, skipped: i64, start: usize) -> String {
+    let mut text = format!("✅ Imported {} rows into '{}'", inserted, options.table);
+    let mut counts = Vec::new();
+    if replaced > 0 {
+        let verb = if options.on_conflict == OnConflict::Update { "updated" } else { "replaced" };
+        counts.push(format!("{} {}", replaced, verb));
+    }
+    if skipped > 0 {
+        counts.push(format!("{} skipped", skipped));
+    }
+    if !counts.is_empty() {
+        text.push_str(&format!(" ({})", counts.join(", ")));
+    }
+    if start > 0 {
+        text.push_str(&format!(", resuming after {} rows", start));
+    }
+    text
+}
+
+/// SHA-256 of a file's bytes, hex encoded
+fn sha256(bytes: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub async fn run_import(server_address: String, options: ImportOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.path == "-" {
+        let message = "import reads a file so it can be resumed; use 'datasink insert TABLE -' for stdin";
+        return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+    }
+    let mapping = match &options.mapping {
+        Some(path) => Mapping::load(path).map_err(|message| CliError::new(ErrorKind::InvalidInput, message))?,
+        None => Mapping::default(),
+    };
+    let bytes = std::fs::read(&options.path)
+        .map_err(|e| CliError::new(ErrorKind::NotFound, format!("Failed to read {}: {}", options.path, e)))?;
+    let checksum = sha256(&bytes);
+    let mut text = String::new();
+    std::io::Read::read_to_string(
+        &mut compression::decoder(std::io::Cursor::new(bytes), Compression::for_path(&options.path))?,
+        &mut text,
+    )
+    .map_err(|e| CliError::new(ErrorKind::InvalidInput, format!("Failed to read {}: {}", options.path, e)))?;
+
+    let mut client = client::connect(server_address).await?;
+    let mut columns = bulk::fetch_schema(&mut client, &options.table, &options.database).await?;
+    if columns.is_empty() && !options.create {
+        let message = format!("Table '{}' not found; pass --create to create it from the file", options.table);
+        return Err(CliError::new(ErrorKind::NotFound, message).into());
+    }
+    let is_csv = compression::strip_extension(&options.path).to_lowercase().ends_with(".csv");
+    let invalid = |message: String| CliError::new(ErrorKind::InvalidInput, message);
+    let mut rows = if is_csv {
+        csv_rows(&text, &columns, &mapping)
+    } else {
+        map_documents(bulk::json_documents(&text)?, &mapping)
+    }
+    .map_err(invalid)?;
+
+    // Infer a missing table's columns now, but create it only once the rows check out
+    let mut created = None;
+    if columns.is_empty() {
+        let definitions = infer::infer_columns(&field_names(&text, is_csv, &mapping, &rows).map_err(invalid)?, &rows);
+        if definitions.is_empty() {
+            return Err(invalid(format!("{} has no fields to create table '{}' from", options.path, options.table)).into());
+        }
+        infer::convert_rows(&mut rows, &definitions);
+        columns = infer::schema_columns(&definitions);
+        created = Some(definitions);
+    } else if options.coerce {
+        bulk::coerce_rows(&columns, &mut rows);
+    }
+    drop(text);
+    if let Err(problems) = bulk::validate_rows(&options.table, &columns, &rows) {
+        eprintln!("{}", problems);
+        let message = format!("Rows in {} do not match table '{}'; nothing was inserted", options.path, options.table);
+        return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+    }
+
+    let progress_file = progress_path(&options.path);
+    let mut progress = Progress {
+        table: options.table.clone(),
+        database: options.database.clone(),
+        sha256: checksum,
+        total_rows: rows.len(),
+        rows_committed: 0,
+    };
+    let start = resume_point(load_progress(&progress_file)?.as_ref(), &progress, &options)
+        .map_err(|message| CliError::new(ErrorKind::InvalidInput, message))?;
+    progress.rows_committed = start;
+    if start > 0 {
+        eprintln!("Resuming after {} of {} rows", start, rows.len());
+    }
+    if let Some(definitions) = &created {
+        let request = CreateTableRequest {
+            table_name: options.table.clone(),
+            columns: definitions.clone(),
+            database: options.database.clone(),
+            unique: Vec::new(),
+        };
+        let response = client.create_table(request).await?.into_inner();
+        if !response.success {
+            let message = format!("Failed to create table '{}': {}", options.table, response.message);
+            return Err(CliError::new(ErrorKind::Other, message).into());
+        }
+        eprintln!("Created table '{}' ({})", options.table, infer::describe(definitions));
+    }
+
+    let bar = ProgressBar::new(rows.len() as u64);
+    bar.set_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} rows ({per_sec}, {eta} left)")
+            .unwrap_or_else(|_| ProgressStyle::default_bar()),
+    );
+    bar.set_position(start as u64);
+
+    let batch_options = BulkInsertOptions {
+        table: options.table.clone(),
+        path: options.path.clone(),
+        batch_size: options.batch_size.max(1),
+        jobs: 1,
+        on_conflict: options.on_conflict,
+        coerce: options.coerce,
+        database: options.database.clone(),
+    };
+    let (mut inserted, mut replaced, mut skipped) = (0, 0, 0);
+    for chunk in rows[start..].chunks(batch_options.batch_size) {
+        match client.batch_insert(bulk::batch_request(&batch_options, chunk)).await {
+            Ok(response) => {
+                let response = response.into_inner();
+                inserted += response.inserted_count;
+                replaced += response.replaced_count;
+                skipped += response.skipped_count;
+                progress.rows_committed += chunk.len();
+                save_progress(&progress_file, &progress)?;
+                bar.inc(chunk.len() as u64);
+            }
+            Err(status) => {
+                bar.abandon();
+                // Nothing committed yet means there is nothing to resume
+                if progress.rows_committed == 0 {
+                    let _ = std::fs::remove_file(&progress_file);
+                }
+                let first = progress.rows_committed + 1;
+                let mut message = format!(
+                    "Import stopped after {} of {} rows; rows {}-{} failed: {}",
+                    progress.rows_committed,
+                    rows.len(),
+                    first,
+                    progress.rows_committed + chunk.len(),
+                    status.message()
+                );
+                if progress.rows_committed > 0 {
+                    message.push_str(&format!(". Fix the problem and rerun with --resume to continue from row {}", first));
+                }
+                return Err(CliError::new(ErrorKind::from_status(status.code()), message).into());
+            }
+        }
+    }
+    bar.finish_and_clear();
+    if let Err(e) = std::fs::remove_file(&progress_file) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Warning: failed to remove {}: {}", progress_file.display(), e);
+        }
+    }
+
+    output::success(
+        &summary(&options, inserted, replaced, skipped, start),
+        serde_json::json!({
+            "table": options.table,
+            "inserted": inserted,
+            "replaced": replaced,
+            "skipped": skipped,
+            "resumed_at": start,
+            "created": created.is_some(),
+        }),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(resume: bool, restart: bool) -> ImportOptions {
+        ImportOptions {
+            table: "events".to_string(),
+            path: "events.csv".to_string(),
+            batch_size: 100,
+            on_conflict: OnConflict::Abort,
+            resume,
+            restart,
+            mapping: None,
+            create: false,
+            coerce: false,
+            database: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_resume_point() {
+        let current = Progress {
+            table: "events".to_string(),
+            database: String::new(),
+            sha256: "abc".to_string(),
+            total_rows: 1000,
+            rows_committed: 0,
+        };
+        let saved = Progress { rows_committed: 300, ..current.clone() };
+
+        assert_eq!(resume_point(None, &current, &options(false, false)), Ok(0));
+        assert_eq!(resume_point(None, &current, &options(true, false)), Ok(0));
+        assert_eq!(resume_point(Some(&saved), &current, &options(true, false)), Ok(300));
+        assert_eq!(resume_point(Some(&saved), &current, &options(false, true)), Ok(0));
+        assert!(resume_point(Some(&saved), &current, &options(false, false)).unwrap_err().contains("--resume"));
+
+        let changed = Progress { sha256: "def".to_string(), ..saved.clone() };
+        assert!(resume_point(Some(&changed), &current, &options(true, false)).unwrap_err().contains("has changed"));
+        let other_table = Progress { table: "users".to_string(), ..saved };
+        assert!(resume_point(Some(&other_table), &current, &options(true, false)).is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut upsert = options(false, false);
+        assert_eq!(summary(&upsert, 10, 0, 0, 0), "✅ Imported 10 rows into 'events'");
+        upsert.on_conflict = OnConflict::Update;
+        assert_eq!(summary(&upsert, 90, 8, 2, 0), "✅ Imported 90 rows into 'events' (8 updated, 2 skipped)");
+        upsert.on_conflict = OnConflict::Replace;
+        assert_eq!(summary(&upsert, 5, 3, 0, 400), "✅ Imported 5 rows into 'events' (3 replaced), resuming after 400 rows");
+    }
+
+    #[test]
+    fn test_field_names() {
+        let mapping: Mapping = toml::from_str("[fields.Id]\ncolumn = \"id\"\n[constants]\nsource = \"x\"").unwrap();
+        let csv = "Id, Name ,empty\n1,Al,\n";
+        let rows = csv_rows(csv, &[], &mapping).unwrap();
+        assert_eq!(field_names(csv, true, &mapping, &rows).unwrap(), ["id", "Name", "empty", "source"]);
+        let rows = vec![serde_json::json!({"b": 1, "a": 2}), serde_json::json!({"c": 3, "a": 4})];
+        assert_eq!(field_names("", false, &Mapping::default(), &rows).unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let column = |name: &str, sql_type: &str| SchemaColumn {
+            name: name.to_string(),
+            sql_type: sql_type.to_string(),
+            not_null: false,
+            has_default: false,
+            primary_key: false,
+            allowed_values: None,
+        };
+        let columns = vec![column("id", "INTEGER"), column("name", "TEXT"), column("score", "REAL"), column("active", "BOOLEAN")];
+        let csv = "id,Name,score,active\r\n1,\"Smith, Jo\",1.5,true\n\n2,007,,0\nx,Al,,maybe\n";
+        let rows = csv_rows(csv, &columns, &Mapping::default()).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                serde_json::json!({"id": 1, "Name": "Smith, Jo", "score": 1.5, "active": true}),
+                serde_json::json!({"id": 2, "Name": "007", "active": false}),
+                serde_json::json!({"id": "x", "Name": "Al", "active": "maybe"}),
+            ]
+        );
+        assert!(csv_rows("id,name\n1\n", &columns, &Mapping::default()).is_err());
+        assert!(csv_rows("", &columns, &Mapping::default()).unwrap().is_empty());
+
+        let mapping: Mapping = toml::from_str(
+            "[fields.ID]\ncolumn = \"id\"\n[fields.Label]\ncolumn = \"name\"\ntransform = [\"trim\"]\n\
+             [constants]\nscore = \"2.5\"",
+        )
+        .unwrap();
+        let rows = csv_rows("ID,Label\n7, Jo \n8,  \n", &columns, &mapping).unwrap();
+        assert_eq!(
+            rows,
+            vec![serde_json::json!({"id": 7, "name": "Jo", "score": 2.5}), serde_json::json!({"id": 8, "score": 2.5})]
+        );
+    }
+}
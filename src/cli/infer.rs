@@ -0,0 +1,191 @@
+//! Column type inference for `datasink import --create`.
+//!
+//! The first [`SAMPLE_ROWS`] rows are sampled. A column is INTEGER, REAL or
+//! BOOLEAN when every value sampled is one, TIMESTAMP when every value is an
+//! ISO-8601 date or date-time, and TEXT otherwise; a mix of integers and
+//! reals is REAL. Text with a leading zero ("007", zip codes) stays TEXT so
+//! the zeros survive. Columns are nullable and the table gets no declared
+//! primary key, since a sample cannot show a value is always present.
+
+use serde_json::Value as JsonValue;
+
+use crate::cli::bulk::SchemaColumn;
+use crate::db::partition;
+use crate::db::traits::DbValue;
+use crate::proto::common::{ColumnDefinition, DataType};
+
+/// Rows looked at to infer column types
+pub const SAMPLE_ROWS: usize = 1000;
+
+/// The type a value suggests for its column, if it suggests one
+fn value_type(value: &JsonValue) -> Option<DataType> {
+    match value {
+        JsonValue::Null => None,
+        JsonValue::Bool(_) => Some(DataType::Boolean),
+        JsonValue::Number(number) if number.is_i64() || number.is_u64() => Some(DataType::Integer),
+        JsonValue::Number(_) => Some(DataType::Real),
+        JsonValue::Object(_) | JsonValue::Array(_) => Some(DataType::Text),
+        JsonValue::String(text) => text_type(text.trim()),
+    }
+}
+
+fn text_type(text: &str) -> Option<DataType> {
+    if text.is_empty() {
+        return None;
+    }
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.") {
+        return Some(DataType::Text);
+    }
+    if text.parse::<i64>().is_ok() {
+        Some(DataType::Integer)
+    } else if text.parse::<f64>().is_ok_and(|n| n.is_finite()) && text.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b)) {
+        Some(DataType::Real)
+    } else if text.eq_ignore_ascii_case("true") || text.eq_ignore_ascii_case("false") {
+        Some(DataType::Boolean)
+    } else if timestamp(text).is_some() {
+        Some(DataType::Timestamp)
+    } else {
+        Some(DataType::Text)
+    }
+}
+
+/// Unix seconds of an ISO-8601 date or date-time
+fn timestamp(text: &str) -> Option<i64> {
+    partition::value_time(&DbValue::Text(text.to_string())).ok().flatten().map(|time| time.timestamp())
+}
+
+/// The type of a column holding values of both types
+fn merge(a: DataType, b: DataType) -> DataType {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (DataType::Integer, DataType::Real) | (DataType::Real, DataType::Integer) => DataType::Real,
+        _ => DataType::Text,
+    }
+}
+
+/// Column definitions for `names`, typed by the values of the sampled rows.
+/// A column with no values sampled is TEXT.
+pub fn infer_columns(names: &[String], rows: &[JsonValue]) -> Vec<ColumnDefinition> {
+    names
+        .iter()
+        .map(|name| {
+            let data_type = rows
+                .iter()
+                .take(SAMPLE_ROWS)
+                .filter_map(|row| value_type(row.get(name)?))
+                .reduce(merge)
+                .unwrap_or(DataType::Text);
+            ColumnDefinition { name: name.clone(), r#type: data_type as i32, nullable: true, ..Default::default() }
+        })
+        .collect()
+}
+
+/// Convert text values to their inferred column's type. Values that do not
+/// parse as it are left as text for validation to report.
+pub fn convert_rows(rows: &mut [JsonValue], columns: &[ColumnDefinition]) {
+    for row in rows.iter_mut().filter_map(JsonValue::as_object_mut) {
+        for column in columns {
+            let Some(value) = row.get_mut(&column.name) else {
+                continue;
+            };
+            let JsonValue::String(text) = value else {
+                continue;
+            };
+            let text = text.trim();
+            let converted = match DataType::try_from(column.r#type).unwrap_or(DataType::Text) {
+                DataType::Integer => text.parse::<i64>().ok().map(JsonValue::from),
+                DataType::Real => text.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(JsonValue::Number),
+                DataType::Boolean => match text.to_lowercase().as_str() {
+                    "true" => Some(JsonValue::Bool(true)),
+                    "false" => Some(JsonValue::Bool(false)),
+                    _ => None,
+                },
+                DataType::Timestamp => timestamp(text).map(JsonValue::from),
+                DataType::Text | DataType::Blob => None,
+            };
+            if let Some(converted) = converted {
+                *value = converted;
+            }
+        }
+    }
+}
+
+/// How the columns will look to validation once the table is created
+pub fn schema_columns(columns: &[ColumnDefinition]) -> Vec<SchemaColumn> {
+    columns
+        .iter()
+        .map(|column| SchemaColumn {
+            name: column.name.clone(),
+            sql_type: match DataType::try_from(column.r#type).unwrap_or(DataType::Text) {
+                DataType::Integer => "INTEGER",
+                DataType::Timestamp => "TIMESTAMP",
+                DataType::Real => "REAL",
+                DataType::Boolean => "BOOLEAN",
+                DataType::Text => "TEXT",
+                DataType::Blob => "BLOB",
+            }
+            .to_string(),
+            not_null: false,
+            has_default: false,
+            primary_key: false,
+            allowed_values: None,
+        })
+        .collect()
+}
+
+/// "id INTEGER, name TEXT, ..." for reporting what was created
+pub fn describe(columns: &[ColumnDefinition]) -> String {
+    columns
+        .iter()
+        .map(|column| {
+            let data_type = DataType::try_from(column.r#type).unwrap_or(DataType::Text);
+            format!("{} {}", column.name, data_type.as_str_name())
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_infer_columns() {
+        let mut rows = vec![
+            json!({"id": "1", "price": "2", "zip": "02134", "active": "true", "seen": "2024-03-15", "note": "x", "n": 3}),
+            json!({"id": "2", "price": "2.5", "zip": "10001", "active": "FALSE", "seen": "2024-03-15T12:00:00Z", "n": 4.5}),
+            json!({"id": "3", "price": "", "zip": "94110", "active": "false", "seen": "yesterday", "note": "1e5"}),
+        ];
+        let names: Vec<String> =
+            ["id", "price", "zip", "active", "seen", "note", "n", "empty"].iter().map(|s| s.to_string()).collect();
+        let columns = infer_columns(&names, &rows[..2]);
+        assert_eq!(
+            describe(&columns),
+            "id INTEGER, price REAL, zip TEXT, active BOOLEAN, seen TIMESTAMP, note TEXT, n REAL, empty TEXT"
+        );
+        assert_eq!(describe(&infer_columns(&names[4..5], &rows)), "seen TEXT");
+
+        convert_rows(&mut rows, &columns);
+        assert_eq!(
+            rows[1],
+            json!({"id": 2, "price": 2.5, "zip": "10001", "active": false, "seen": 1710504000, "n": 4.5})
+        );
+        // Left as text for validation to report
+        assert_eq!(rows[2]["seen"], json!("yesterday"));
+        assert_eq!(schema_columns(&columns)[4].sql_type, "TIMESTAMP");
+    }
+
+    #[test]
+    fn test_text_type() {
+        assert_eq!(text_type("-12"), Some(DataType::Integer));
+        assert_eq!(text_type("0"), Some(DataType::Integer));
+        assert_eq!(text_type("0.5"), Some(DataType::Real));
+        assert_eq!(text_type("-007"), Some(DataType::Text));
+        assert_eq!(text_type("NaN"), Some(DataType::Text));
+        assert_eq!(text_type("inf"), Some(DataType::Text));
+        assert_eq!(text_type("2024-03-15 08:00:00"), Some(DataType::Timestamp));
+        assert_eq!(text_type(""), None);
+    }
+}
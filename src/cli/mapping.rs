@@ -0,0 +1,231 @@
+//! Import mapping files for `datasink import --mapping`.
+//!
+//! A mapping renames a source file's fields to table columns, transforms
+//! their values, drops fields the table does not need and adds constant
+//! columns, so a messy export can be loaded without a preprocessing script:
+//!
+//! ```toml
+//! [fields."E-Mail"]
+//! column = "email"
+//! transform = ["trim", "lowercase"]
+//!
+//! [fields."Signup Date"]
+//! column = "created_at"
+//! transform = ["date:%m/%d/%Y"]
+//!
+//! [fields."Internal Notes"]
+//! skip = true
+//!
+//! [constants]
+//! source = "crm-export"
+//! ```
+//!
+//! Fields the mapping does not name keep their name and value. Transforms
+//! run in order on text values; constants are set on every row, replacing
+//! any value from the file.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+
+use crate::db::partition;
+use crate::db::traits::DbValue;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    /// Source field name to what becomes of it
+    #[serde(default)]
+    pub fields: HashMap<String, FieldMapping>,
+    /// Column to the value it gets in every row
+    #[serde(default)]
+    pub constants: HashMap<String, toml::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldMapping {
+    /// Column the field is loaded into; the field's own name if not set
+    pub column: Option<String>,
+    #[serde(default)]
+    pub transform: Vec<Transform>,
+    /// Leave the field out of the rows
+    #[serde(default)]
+    pub skip: bool,
+}
+
+/// A change made to a text value before it is inserted
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Transform {
+    Trim,
+    Lowercase,
+    Uppercase,
+    /// Parse a date or date-time into Unix seconds (UTC), with a chrono
+    /// format such as `%d/%m/%Y`, or as ISO-8601 without one
+    Date(Option<String>),
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "trim" => Ok(Transform::Trim),
+            None if s == "lowercase" => Ok(Transform::Lowercase),
+            None if s == "uppercase" => Ok(Transform::Uppercase),
+            None if s == "date" => Ok(Transform::Date(None)),
+            Some(("date", format)) if !format.is_empty() => Ok(Transform::Date(Some(format.to_string()))),
+            _ => Err(format!(
+                "Unknown transform '{}' (expected trim, lowercase, uppercase, date or date:FORMAT)",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Transform {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Transform {
+    /// The transformed value; values other than text are left as they are
+    pub fn apply(&self, value: JsonValue) -> Result<JsonValue, String> {
+        let JsonValue::String(text) = value else {
+            return Ok(value);
+        };
+        Ok(match self {
+            Transform::Trim => JsonValue::String(text.trim().to_string()),
+            Transform::Lowercase => JsonValue::String(text.to_lowercase()),
+            Transform::Uppercase => JsonValue::String(text.to_uppercase()),
+            Transform::Date(format) => JsonValue::from(parse_date(&text, format.as_deref())?),
+        })
+    }
+}
+
+/// Unix seconds of a date or date-time, read with `format` or as ISO-8601
+fn parse_date(text: &str, format: Option<&str>) -> Result<i64, String> {
+    let time = match format {
+        Some(format) => NaiveDateTime::parse_from_str(text, format)
+            .ok()
+            .or_else(|| NaiveDate::parse_from_str(text, format).ok()?.and_hms_opt(0, 0, 0))
+            .map(|time| time.and_utc()),
+        None => partition::value_time(&DbValue::Text(text.to_string())).ok().flatten(),
+    };
+    time.map(|time| time.timestamp()).ok_or_else(|| match format {
+        Some(format) => format!("'{}' does not match date format '{}'", text, format),
+        None => format!("'{}' is not an ISO-8601 date", text),
+    })
+}
+
+impl Mapping {
+    /// Read a mapping file
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid mapping file {}: {}", path, e))
+    }
+
+    /// The columns a file with fields `names` is loaded into, in field order
+    /// followed by the constants
+    pub fn columns(&self, names: &[String]) -> Vec<String> {
+        let mut columns: Vec<String> = names
+            .iter()
+            .filter_map(|name| match self.fields.get(name) {
+                Some(field) if field.skip => None,
+                Some(field) => Some(field.column.clone().unwrap_or_else(|| name.clone())),
+                None => Some(name.clone()),
+            })
+            .collect();
+        let mut constants: Vec<&String> = self.constants.keys().filter(|name| !columns.contains(name)).collect();
+        constants.sort();
+        columns.extend(constants.into_iter().cloned());
+        columns
+    }
+
+    /// A row with the mapping applied
+    pub fn apply(&self, row: Map<String, JsonValue>) -> Result<Map<String, JsonValue>, String> {
+        let mut mapped = Map::new();
+        for (name, value) in row {
+            let Some(field) = self.fields.get(&name) else {
+                mapped.insert(name, value);
+                continue;
+            };
+            if field.skip {
+                continue;
+            }
+            let value = field
+                .transform
+                .iter()
+                .try_fold(value, |value, transform| transform.apply(value))
+                .map_err(|e| format!("field '{}': {}", name, e))?;
+            mapped.insert(field.column.clone().unwrap_or(name), value);
+        }
+        for (column, value) in &self.constants {
+            let value = serde_json::to_value(value).map_err(|e| format!("constant '{}': {}", column, e))?;
+            mapped.insert(column.clone(), value);
+        }
+        Ok(mapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const MAPPING: &str = r#"
+[fields."E-Mail"]
+column = "email"
+transform = ["trim", "lowercase"]
+
+[fields."Signup Date"]
+column = "created_at"
+transform = ["date:%m/%d/%Y"]
+
+[fields.notes]
+skip = true
+
+[constants]
+source = "crm"
+active = true
+"#;
+
+    #[test]
+    fn test_apply_mapping() {
+        let mapping: Mapping = toml::from_str(MAPPING).unwrap();
+        let row = json!({"E-Mail": "  Ann@Example.COM ", "Signup Date": "03/15/2024", "notes": "x", "name": "Ann", "source": "file"});
+        let JsonValue::Object(row) = row else { unreachable!() };
+        assert_eq!(
+            JsonValue::Object(mapping.apply(row).unwrap()),
+            json!({"email": "ann@example.com", "created_at": 1710460800, "name": "Ann", "source": "crm", "active": true})
+        );
+
+        let names = ["E-Mail", "notes", "name", "source"].map(String::from);
+        assert_eq!(mapping.columns(&names), ["email", "name", "source", "active"]);
+
+        let JsonValue::Object(bad) = json!({"Signup Date": "2024-03-15"}) else { unreachable!() };
+        assert_eq!(
+            mapping.apply(bad).unwrap_err(),
+            "field 'Signup Date': '2024-03-15' does not match date format '%m/%d/%Y'"
+        );
+    }
+
+    #[test]
+    fn test_transforms() {
+        assert_eq!(Transform::Date(None).apply(json!("2024-03-15T12:00:00Z")).unwrap(), json!(1710504000));
+        let with_time = Transform::Date(Some("%d.%m.%Y %H:%M".to_string()));
+        assert_eq!(with_time.apply(json!("15.03.2024 12:00")).unwrap(), json!(1710504000));
+        assert_eq!(Transform::Uppercase.apply(json!(5)).unwrap(), json!(5));
+        assert!("date:".parse::<Transform>().is_err());
+        assert!("reverse".parse::<Transform>().is_err());
+        assert!(toml::from_str::<Mapping>("[fields.a]\nrename = \"b\"").is_err());
+        assert!(toml::from_str::<Mapping>("[fields.a]\ntransform = [\"strip\"]").is_err());
+    }
+}
@@ -1,8 +1,31 @@
+pub mod bench;
+pub mod bulk;
+pub mod client;
 pub mod commands;
+pub mod doctor;
+pub mod error;
+pub mod export;
+pub mod import;
+pub mod infer;
+pub mod generate;
+pub mod mapping;
+pub mod output;
+pub mod render;
+pub mod sink;
+pub mod tail;
+pub mod template;
+pub mod top;
+pub mod tx;
 pub mod validation;
 
 use clap::{Parser, Subcommand};
 
+use crate::cli::commands::Returning;
+use crate::cli::export::{ExportFormat, Filter};
+use crate::compression::Compression;
+use crate::db::traits::KeepRow;
+use crate::proto::crud::{BoundingBox, OnConflict};
+
 #[derive(Parser)]
 #[command(name = "datasink")]
 #[command(about = "A gRPC-based database service", long_about = None)]
@@ -26,6 +49,67 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub verbose: bool,
 
+    /// Print results and errors as JSON with stable field names, for scripts
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Show NULL values as this text in table and CSV results [default: NULL in tables,
+    /// an empty field in CSV]
+    #[arg(long, global = true, value_name = "TEXT")]
+    pub null_as: Option<String>,
+
+    /// Show TIMESTAMP columns as the Unix seconds stored rather than RFC 3339 date-times
+    #[arg(long, global = true)]
+    pub raw_timestamps: bool,
+
+    /// Show TIMESTAMP columns in the local time zone rather than UTC
+    #[arg(long, global = true, conflicts_with = "raw_timestamps")]
+    pub local_time: bool,
+
+    /// Show REAL values in tables with this many decimal places [default: up to 15 significant digits]
+    #[arg(long, global = true, value_name = "N")]
+    pub decimals: Option<usize>,
+
+    /// Group the digits of numbers in tables, with the separators of the current locale
+    #[arg(long, global = true)]
+    pub thousands: bool,
+
+    /// Give up connecting to the server after this many seconds
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_interval)]
+    pub connect_timeout: Option<f64>,
+
+    /// Fail a request whose response has not arrived after this many seconds
+    #[arg(long, global = true, value_name = "SECS", value_parser = parse_interval)]
+    pub request_timeout: Option<f64>,
+
+    /// Retry connecting this many times, with increasing pauses, while the server is unavailable
+    #[arg(long, global = true, default_value_t = 0)]
+    pub retries: u32,
+
+    /// Send HTTP/2 keepalive pings after this many idle seconds, keeping long-running
+    /// streams alive through NAT and proxy idle timeouts (client commands and `server start`)
+    #[arg(long, global = true, value_name = "SECS")]
+    pub keepalive_interval: Option<u64>,
+
+    /// Close the connection if a keepalive ping is not answered within this many seconds [default: 20]
+    #[arg(long, global = true, value_name = "SECS")]
+    pub keepalive_timeout: Option<u64>,
+
+    /// Set TCP_NODELAY on connections [client default: true, server default: false]
+    #[arg(long, global = true, value_name = "BOOL")]
+    pub tcp_nodelay: Option<bool>,
+
+    /// Print the SQL and bound parameters the server executed for insert, update,
+    /// delete and create-table (on stderr)
+    #[arg(long, global = true)]
+    pub show_sql: bool,
+
+    /// Where reads of a database with replicas go: "strong" reads from the database
+    /// itself and sees every earlier write, "eventual" may read from a lagging replica
+    /// [server default: eventual]
+    #[arg(long, global = true, value_parser = ["strong", "eventual"])]
+    pub consistency: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -73,6 +157,14 @@ impl Cli {
         }
     }
     
+    /// Whether a database was given explicitly via flags or environment variables
+    pub fn has_explicit_database(&self) -> bool {
+        self.database_url.is_some()
+            || self.database_name.is_some()
+            || std::env::var("DATABASE_URL").is_ok()
+            || std::env::var("DATABASE_NAME").is_ok()
+    }
+
     /// Find existing database file (case-insensitive) or return path for new one
     fn find_or_create_db_url(&self, name: &str) -> Result<String, String> {
         self.find_or_create_db_url_in_dir(name, ".")
@@ -101,6 +193,148 @@ impl Cli {
     }
 }
 
+/// Parse a `NAME=URL` database specification
+pub fn parse_database_spec(spec: &str) -> Result<(String, String), String> {
+    let (name, url) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid database '{}': expected NAME=URL", spec))?;
+    let name = name.trim();
+    let url = url.trim();
+    if name.is_empty() || url.is_empty() {
+        return Err(format!("Invalid database '{}': expected NAME=URL", spec));
+    }
+    Ok((name.to_string(), url.to_string()))
+}
+
+/// Parse a positive number of seconds, such as an interval or timeout, or a
+/// duration with a unit (`500ms`, `5s`, `2m`, `1h`)
+pub fn parse_interval(value: &str) -> Result<f64, String> {
+    let (number, scale) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = value.strip_suffix('h') {
+        (number, 3600.0)
+    } else {
+        (value, 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n.is_finite() && n > 0.0 => Ok(n * scale),
+        _ => Err(format!(
+            "Invalid value '{}': expected a positive number of seconds, or a duration such as 500ms, 5s, 2m or 1h",
+            value
+        )),
+    }
+}
+
+/// Read a command's input from a file, or from stdin when the path is `-`.
+/// `.gz` and `.zst` files are decompressed.
+pub fn read_input(path: &str) -> Result<String, String> {
+    use std::io::Read;
+
+    if path == "-" {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Failed to read stdin: {}", e))?;
+        Ok(text)
+    } else {
+        crate::compression::read_to_string(std::path::Path::new(path))
+            .map_err(|e| format!("Failed to read {}: {}", path, e))
+    }
+}
+
+/// Parse a `NAME=VALUE` query parameter
+fn parse_param(spec: &str) -> Result<(String, String), String> {
+    let (name, value) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid parameter '{}': expected NAME=VALUE", spec))?;
+    let name = name.trim().trim_start_matches(':');
+    if name.is_empty() {
+        return Err(format!("Invalid parameter '{}': expected NAME=VALUE", spec));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Parse a `COLUMN=GENERATOR` override for `datasink generate`
+fn parse_generator_override(spec: &str) -> Result<(String, String), String> {
+    let (column, generator) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid override '{}': expected COLUMN=GENERATOR", spec))?;
+    generator.parse::<generate::Generator>()?;
+    Ok((column.trim().to_string(), generator.trim().to_string()))
+}
+
+/// Parse a `MIN_X,MIN_Y,MAX_X,MAX_Y` bounding box for `datasink within`
+fn parse_bbox(spec: &str) -> Result<BoundingBox, String> {
+    let parts: Vec<f64> = spec
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Invalid bounding box '{}': {}", spec, e))?;
+    let [min_x, min_y, max_x, max_y] = parts[..] else {
+        return Err(format!("Invalid bounding box '{}': expected MIN_X,MIN_Y,MAX_X,MAX_Y", spec));
+    };
+    if min_x > max_x || min_y > max_y {
+        return Err(format!("Invalid bounding box '{}': minimums must not exceed maximums", spec));
+    }
+    Ok(BoundingBox { min_x, min_y, max_x, max_y })
+}
+
+/// Parse a `DATABASE.TABLE` or `TABLE` reference for `datasink copy-table`;
+/// the database is empty (the default) when not given
+fn parse_table_ref(spec: &str) -> Result<(String, String), String> {
+    let (database, table) = spec.split_once('.').unwrap_or(("", spec));
+    if table.is_empty() || (spec.contains('.') && database.is_empty()) {
+        return Err(format!("Invalid table '{}': expected DATABASE.TABLE or TABLE", spec));
+    }
+    Ok((database.to_string(), table.to_string()))
+}
+
+/// Parse the row `datasink dedupe` keeps: `first`, `last`, `newest:COLUMN`
+/// or `oldest:COLUMN`
+fn parse_keep(spec: &str) -> Result<KeepRow, String> {
+    spec.parse::<KeepRow>().map_err(|e| e.to_string())
+}
+
+/// Parse a `--filter COLUMN OP VALUE` condition for `datasink export`
+fn parse_filter(spec: &str) -> Result<Filter, String> {
+    spec.parse::<Filter>()
+}
+
+fn parse_export_format(spec: &str) -> Result<ExportFormat, String> {
+    spec.parse::<ExportFormat>()
+}
+
+/// Parse `--on-conflict` for `datasink import`
+fn parse_import_conflict(spec: &str) -> Result<OnConflict, String> {
+    match spec.to_lowercase().as_str() {
+        "fail" | "abort" => Ok(OnConflict::Abort),
+        "ignore" => Ok(OnConflict::Ignore),
+        "replace" => Ok(OnConflict::Replace),
+        "update" => Ok(OnConflict::Update),
+        _ => Err(format!("Unknown conflict strategy '{}' (expected fail, ignore, replace or update)", spec)),
+    }
+}
+
+/// Parse `--returning`: `*` or a comma-separated list of columns
+fn parse_returning(spec: &str) -> Result<Returning, String> {
+    if spec.trim() == "*" {
+        return Ok(Returning::All);
+    }
+    let columns: Vec<String> = spec.split(',').map(|column| column.trim().to_string()).collect();
+    if columns.iter().any(|column| column.is_empty() || column == "*") {
+        return Err(format!("Invalid column list '{}' (expected * or COLUMN,COLUMN)", spec));
+    }
+    Ok(Returning::Columns(columns))
+}
+
+fn parse_compression(spec: &str) -> Result<Compression, String> {
+    spec.parse::<Compression>()
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Server management commands
@@ -116,11 +350,56 @@ pub enum Commands {
     #[command(after_help = "Examples:
   datasink query \"SELECT * FROM users\"
   datasink query \"SELECT * FROM users WHERE age > 18\" -f json
-  datasink query \"SELECT name, email FROM users\" -f csv -D mydb")]
+  datasink query \"SELECT name, email FROM users\" -f csv -D mydb
+  datasink query \"SELECT * FROM users WHERE org = {{org}}\" --var org=acme
+  datasink query \"SELECT * FROM users WHERE id IN ({{ids}})\" --var 'ids=[1,2,3]'
+  datasink query \"SELECT * FROM events\" --stats
+  datasink query \"SELECT * FROM orders WHERE id = 7\" -f vertical
+  datasink query \"SELECT * FROM articles\" --columns id,title,body --max-col-width 40
+  datasink query \"SELECT count(*) AS rows, max(id) FROM events\" --watch 5s
+  datasink query - < report.sql")]
     Query {
-        /// SQL query to execute
+        /// SQL query to execute ('-' reads it from stdin); {{name}} marks a template variable
         sql: String,
-        /// Output format (json, table, csv)
+        /// Template variable as NAME=VALUE, bound as a query parameter; a
+        /// [a,b,c] value expands to a list for IN clauses (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_param)]
+        vars: Vec<(String, String)>,
+        /// Output format (table, json, csv, vertical)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+        /// Print the row count and execution time to stderr afterwards
+        #[arg(long)]
+        stats: bool,
+        /// Columns of the result to show, in order (comma-separated; defaults to all)
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+        /// Cut table and vertical cells longer than this many characters, ending them in "…"
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        max_col_width: Option<u32>,
+        /// Run the query again at this interval (e.g. 5, 500ms, 5s, 2m), redrawing
+        /// the table with the cells that changed highlighted, until Ctrl-C
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_interval)]
+        watch: Option<f64>,
+        /// With --watch, exit after this many runs
+        #[arg(long, value_name = "N", requires = "watch")]
+        count: Option<u64>,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Fetch a single row by primary key
+    #[command(after_help = "Examples:
+  datasink get users 42
+  datasink get users 42 -f json
+  datasink get order_items 1001 3 -D shop   (composite key, in key column order)")]
+    Get {
+        /// Table name
+        table: String,
+        /// Primary key value(s), in key column order (rowid for tables without a key)
+        #[arg(required = true)]
+        key: Vec<String>,
+        /// Output format (table, json)
         #[arg(short, long, default_value = "table")]
         format: String,
         /// Target database (defaults to "default")
@@ -131,12 +410,161 @@ pub enum Commands {
     #[command(after_help = "Examples:
   datasink insert users '{\"name\": \"Alice\", \"email\": \"alice@example.com\"}'
   datasink insert products '{\"name\": \"Laptop\", \"price\": 999.99, \"stock\": 10}'
-  datasink insert notes '{\"title\": \"Meeting\", \"priority\": \"high\"}' -D postit")]
+  datasink insert notes '{\"title\": \"Meeting\", \"priority\": \"high\"}' -D postit
+  datasink insert users '{\"id\": 1, \"name\": \"Alice\"}' --on-conflict ignore
+  datasink insert users '[{\"name\": \"Bob\"}, {\"name\": \"Cy\"}]'   (one transaction; prints first/last ID)
+  datasink insert users '{\"name\": \"Dee\"}' --returning '*'      (print the row with its defaults and ID)
+  id=$(datasink insert users '{\"name\": \"Eve\"}' --returning id -f csv | tail -n 1)
+  datasink insert users '{\"age\": \"41\", \"active\": \"yes\"}' --coerce   (stores 41 and true)
+  datasink insert users --file users.json              (JSON array or NDJSON)
+  datasink insert events --file events.ndjson --batch-size 2000
+  datasink insert events --file events.ndjson --jobs 4  (4 batches in flight; row order not kept)
+  cat users.ndjson | datasink insert users -")]
     Insert {
         /// Table name
         table: String,
-        /// JSON data to insert (e.g., '{"id": 1, "name": "Alice"}')
-        data: String,
+        /// JSON data to insert (e.g., '{"id": 1, "name": "Alice"}'), or an array of
+        /// rows inserted together; '-' reads rows from stdin like --file
+        #[arg(required_unless_present = "file")]
+        data: Option<String>,
+        /// Insert every row of a JSON array or NDJSON file ('-' for stdin)
+        /// instead; rows are checked against the table's columns before any are sent
+        #[arg(long, conflicts_with = "data")]
+        file: Option<String>,
+        /// Rows per BatchInsert call (one transaction each) with --file or -
+        #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u32).range(1..))]
+        batch_size: u32,
+        /// Send up to this many batches at once (capped at the database's pool size). With more
+        /// than one, batches may commit out of order; a failed batch stops new ones from being sent
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        jobs: u32,
+        /// What to do if the row violates a UNIQUE or PRIMARY KEY constraint
+        #[arg(long, default_value = "abort", value_parser = ["abort", "ignore"])]
+        on_conflict: String,
+        /// Print the inserted row's columns ('*' or COLUMN,COLUMN) as stored, with
+        /// defaults and the generated ID filled in, instead of the insert message
+        #[arg(long, value_name = "COLUMNS", value_parser = parse_returning, conflicts_with = "file")]
+        returning: Option<Returning>,
+        /// Output format for --returning (table, json, csv, vertical)
+        #[arg(short, long, default_value = "table", requires = "returning")]
+        format: String,
+        /// Convert text values to their columns' types (numeric text to numbers, true/false/yes/no
+        /// to booleans, ISO-8601 dates to Unix seconds) instead of rejecting them
+        #[arg(long)]
+        coerce: bool,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Write a table's rows to a file as NDJSON, JSON or CSV
+    #[command(after_help = "Examples:
+  datasink export users -o users.ndjson
+  datasink export users -o users.csv --exclude email,phone
+  datasink export events -o recent.json --filter \"created_at >= now-30d\"
+  datasink export orders --columns id,total,status --filter \"status != 'cancelled'\" -D shop
+  datasink export logs --filter \"level = error\" --where \"message LIKE '%timeout%'\" -o errors.ndjson.gz
+  datasink export events -o events.csv --compress zstd   (writes events.csv.zst)
+
+Filters are COLUMN OP VALUE with OP one of = != < <= > >=; all of them must match. VALUE is
+a number, text (quote it to keep digits as text), null (with = or !=), or a time relative to
+now such as now-30d, now-12h or now-15m, compared as Unix seconds. Values are bound as
+parameters, never spliced into the SQL.")]
+    Export {
+        /// Table name
+        table: String,
+        /// File to write ('-' or omitted for stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+        /// ndjson, json or csv (defaults from the output file's extension, else ndjson)
+        #[arg(short, long, value_parser = parse_export_format)]
+        format: Option<ExportFormat>,
+        /// Compress the output with gzip or zstd, adding .gz or .zst to the file name if it
+        /// lacks it (defaults from the output file's extension, else none)
+        #[arg(long, value_parser = parse_compression)]
+        compress: Option<Compression>,
+        /// Columns to export, in order (comma-separated; defaults to all)
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+        /// Columns to leave out (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        exclude: Vec<String>,
+        /// Row condition as COLUMN OP VALUE (repeatable; all must match)
+        #[arg(long = "filter", value_name = "COLUMN OP VALUE", value_parser = parse_filter)]
+        filters: Vec<Filter>,
+        /// Extra SQL condition for rows, ANDed with the filters
+        #[arg(short, long = "where")]
+        where_clause: Option<String>,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Load a CSV, JSON array or NDJSON file into a table, resumably
+    #[command(after_help = "Examples:
+  datasink import events events.csv
+  datasink import users users.ndjson.zst --batch-size 2000 -D shop
+  datasink import events events.csv --resume    (continue an interrupted import)
+  datasink import users users.csv --on-conflict update   (upsert by primary key or unique column)
+  datasink import contacts crm.csv --mapping crm.toml   (rename, transform and add fields)
+  datasink import trips trips.csv --create    (create the table with types inferred from the file)
+
+Rows are checked against the table before any are sent, then sent in chunks of --batch-size
+rows, each its own transaction. Progress is kept in FILE.progress with the file's SHA-256;
+after an interruption, --resume skips the rows already committed if the file is unchanged.
+CSV files start with a header row of column names; empty fields are left out of the row.
+
+A mapping file renames fields to columns, transforms them and adds constant columns:
+  [fields.\"E-Mail\"]
+  column = \"email\"
+  transform = [\"trim\", \"lowercase\"]    (also uppercase, date, date:%m/%d/%Y)
+  [fields.notes]
+  skip = true
+  [constants]
+  source = \"crm\"")]
+    Import {
+        /// Table name
+        table: String,
+        /// CSV, JSON or NDJSON file (by extension; .gz and .zst are decompressed)
+        file: String,
+        /// Rows per BatchInsert call (one transaction each)
+        #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u32).range(1..))]
+        batch_size: u32,
+        /// What to do with a row that violates a UNIQUE or PRIMARY KEY constraint: fail the
+        /// import, ignore (skip) the row, replace the existing row, or update it (upsert)
+        #[arg(long, default_value = "fail", value_parser = parse_import_conflict)]
+        on_conflict: OnConflict,
+        /// Continue an interrupted import of the file from its last committed chunk
+        #[arg(long, conflicts_with = "restart")]
+        resume: bool,
+        /// Discard an interrupted import's progress and load the whole file
+        #[arg(long)]
+        restart: bool,
+        /// TOML file mapping the file's fields to columns, with transforms and constants
+        #[arg(long, value_name = "FILE")]
+        mapping: Option<String>,
+        /// Create the table if it does not exist, with column types inferred from the file
+        #[arg(long)]
+        create: bool,
+        /// Convert text values of JSON rows to their columns' types (CSV fields always are)
+        #[arg(long)]
+        coerce: bool,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Run inserts, updates and deletes from a JSON document in one transaction
+    #[command(after_help = "Operations are a JSON array or one JSON object per line (NDJSON):
+  {\"op\": \"insert\", \"table\": \"accounts\", \"values\": {\"id\": 3, \"balance\": 0}}
+  {\"op\": \"update\", \"table\": \"accounts\", \"values\": {\"balance\": 50}, \"where\": \"id = 1\"}
+  {\"op\": \"delete\", \"table\": \"pending\", \"where\": \"account_id = 3\"}
+If any operation fails, none of them take effect.
+
+Examples:
+  datasink tx transfer.ndjson
+  datasink tx ops.json -D shop
+  generate-ops | datasink tx")]
+    Tx {
+        /// JSON or NDJSON file of operations (reads stdin if omitted or '-')
+        file: Option<String>,
         /// Target database (defaults to "default")
         #[arg(short = 'D', long)]
         database: Option<String>,
@@ -154,6 +582,10 @@ pub enum Commands {
         /// WHERE clause (e.g., "id = 1")
         #[arg(short, long)]
         where_clause: String,
+        /// Convert text values to their columns' types (numeric text to numbers, true/false/yes/no
+        /// to booleans, ISO-8601 dates to Unix seconds) instead of rejecting them
+        #[arg(long)]
+        coerce: bool,
         /// Target database (defaults to "default")
         #[arg(short = 'D', long)]
         database: Option<String>,
@@ -173,6 +605,328 @@ pub enum Commands {
         #[arg(short = 'D', long)]
         database: Option<String>,
     },
+    /// Full-text search a table that has a search index
+    #[command(after_help = "Examples:
+  datasink search posts \"rust async\"
+  datasink search posts \"title:rust OR body:tokio\" --limit 5
+  datasink search notes \"\\\"release plan\\\"\" -f json -D postit
+
+Create the index first with: datasink server create-search-index <table> -c <column>...")]
+    Search {
+        /// Table name
+        table: String,
+        /// FTS5 query terms (supports AND/OR/NOT, \"phrases\", prefix* and column:term)
+        query: String,
+        /// Maximum number of matches
+        #[arg(short, long, default_value = "20")]
+        limit: u32,
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Find rows inside a bounding box using a table's spatial index
+    #[command(after_help = "Examples:
+  datasink within places --bbox -74.05,40.68,-73.90,40.82
+  datasink within parcels --bbox 0,0,100,100 --limit 50 -f json -D gis
+
+Create the index first with: datasink server create-spatial-index <table> -c <x> -c <y>")]
+    Within {
+        /// Table name
+        table: String,
+        /// Area to search as MIN_X,MIN_Y,MAX_X,MAX_Y
+        #[arg(long, allow_hyphen_values = true, value_parser = parse_bbox)]
+        bbox: BoundingBox,
+        /// Maximum number of rows
+        #[arg(short, long, default_value = "1000")]
+        limit: u32,
+        /// Output format (table, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Show rows picked at random from a table
+    #[command(after_help = "Examples:
+  datasink sample users
+  datasink sample events -c 20 -f json -D analytics
+
+Large tables are sampled by seeking to random rowids rather than scanning them; rows that
+follow a gap in the rowids are somewhat more likely to be picked.")]
+    Sample {
+        /// Table name
+        table: String,
+        /// Number of rows
+        #[arg(short, long, default_value = "100")]
+        count: u32,
+        /// Output format (table, json, vertical)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Print the rows added to a table as they arrive, like `tail -f`
+    #[command(after_help = "Examples:
+  datasink tail events
+  datasink tail events --since-column created_at --lines 0
+  datasink tail orders -f json --interval 500ms -D shop | jq .total
+
+The table is polled for rows whose --since-column value is greater than the last row shown,
+so pick a column that only grows, such as an INTEGER PRIMARY KEY or an insertion time. Rows
+that arrive later with the same value as the last row shown are skipped. Ctrl-C stops.")]
+    Tail {
+        /// Table name
+        table: String,
+        /// Column whose growing values mark new rows
+        #[arg(long, default_value = "id")]
+        since_column: String,
+        /// Rows already in the table to print first
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        lines: u32,
+        /// Time between polls (e.g. 2, 500ms, 5s)
+        #[arg(short, long, default_value_t = 1.0, value_parser = parse_interval)]
+        interval: f64,
+        /// Output format (table, csv, json)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Delete duplicate rows, keeping one row per group of equal values
+    #[command(after_help = "Examples:
+  datasink dedupe users --by email --dry-run
+  datasink dedupe events --by user_id,kind --keep newest:updated_at
+  datasink dedupe orders --by order_ref --keep last -D shop
+
+--keep picks the row kept in each group: first or last by insertion (rowid), or the row with
+the greatest (newest) or smallest (oldest) value in a column. NULLs count as equal, so rows
+that are NULL in all --by columns form a group. All deletes run in one transaction.")]
+    Dedupe {
+        /// Table name
+        table: String,
+        /// Columns whose values make rows duplicates (comma-separated)
+        #[arg(long, required = true, value_delimiter = ',')]
+        by: Vec<String>,
+        /// Row to keep per group: first, last, newest:COLUMN or oldest:COLUMN
+        #[arg(long, default_value = "first", value_parser = parse_keep)]
+        keep: KeepRow,
+        /// Only report how many rows would be removed
+        #[arg(long)]
+        dry_run: bool,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Remove all rows from a table, keeping the table
+    #[command(after_help = "Examples:
+  datasink truncate logs
+  datasink truncate events --force -D analytics")]
+    Truncate {
+        /// Table name
+        table: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+        /// Skip the confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Copy the rows of a table into a table of another database on the server
+    #[command(after_help = "Examples:
+  datasink copy-table main.users staging.users --create
+  datasink copy-table main.orders archive.orders --where \"created_at < '2024-01-01'\"
+  datasink copy-table users users_backup --create   (both in the default database)
+
+Rows are streamed on the server and inserted in batches, each its own transaction, so a
+failed copy may leave the rows of earlier batches behind. Only columns both tables have are
+copied. --create makes the target table from the source table's definition (with its
+indexes when the name is unchanged); the target must not exist yet.")]
+    CopyTable {
+        /// Table to copy, as DATABASE.TABLE or TABLE
+        #[arg(value_parser = parse_table_ref)]
+        source: (String, String),
+        /// Table to copy into, as DATABASE.TABLE or TABLE
+        #[arg(value_parser = parse_table_ref)]
+        target: (String, String),
+        /// Only copy rows matching this WHERE clause
+        #[arg(short, long = "where")]
+        where_clause: Option<String>,
+        /// Create the target table from the source table's definition
+        #[arg(long)]
+        create: bool,
+        /// Rows inserted per transaction
+        #[arg(long, default_value = "1000")]
+        batch_size: u32,
+    },
+    /// Compare a table's rows in two databases on the server
+    #[command(after_help = "Examples:
+  datasink diff main replica --table users
+  datasink diff main migrated --table order_items --key order_id --key line --rows
+  datasink diff main staging --table events --where \"day = '2024-06-01'\" --rows --limit 50 --json
+
+Rows are matched by key (the table's primary key unless --key is given) and reported as
+added (only in the second database), removed (only in the first) or changed. Only columns
+both tables have are compared.")]
+    Diff {
+        /// Database with the expected rows
+        source: String,
+        /// Database to compare with it
+        target: String,
+        /// Table to compare
+        #[arg(short, long)]
+        table: String,
+        /// Key column identifying a row (repeatable; defaults to the primary key)
+        #[arg(short, long)]
+        key: Vec<String>,
+        /// Only compare rows matching this WHERE clause
+        #[arg(short, long = "where")]
+        where_clause: Option<String>,
+        /// List the differing rows, not only the counts
+        #[arg(long)]
+        rows: bool,
+        /// Most differing rows to list
+        #[arg(long, default_value = "1000")]
+        limit: u32,
+    },
+    /// Check that the server is reachable and report round-trip latency and its version
+    #[command(after_help = "Examples:
+  datasink ping
+  datasink ping --count 5
+  datasink -s http://db.internal:50051 ping --count 10 --interval 0.2
+  datasink ping && datasink query \"SELECT count(*) FROM events\"")]
+    Ping {
+        /// Number of pings to send
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+        /// Seconds between pings
+        #[arg(short, long, default_value_t = 1.0, value_parser = parse_interval)]
+        interval: f64,
+    },
+    /// Check the server and its databases for common problems
+    #[command(after_help = "Examples:
+  datasink doctor
+  datasink doctor -D analytics
+  datasink doctor -D shop --schema schemas/shop.schema
+  datasink --json doctor
+
+Checks that the server answers, each database's journal mode and WAL size, and with
+--schema whether the schema applied to a database matches the file. Database files on
+this host are also checked for permissions, free disk space and WAL files left behind,
+so run it on the server host for the full picture. Exits with code 1 if any check fails.")]
+    Doctor {
+        /// Only check this database (checks every database if omitted)
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+        /// Schema file the databases should be at
+        #[arg(long)]
+        schema: Option<String>,
+    },
+    /// Suggest indexes for frequent queries that scan whole tables
+    #[command(after_help = "Examples:
+  datasink advise -D shop
+  datasink advise -D shop --min-count 10
+  datasink advise -D shop --apply
+
+Looks at the query shapes the server has seen since it started and, for columns used in
+WHERE and JOIN conditions that no index covers, suggests an index along with the query plan
+of the most frequent matching query before and after it. With --apply the suggested indexes
+are created (requires the admin token when the server has one).")]
+    Advise {
+        /// Database to analyze (uses the default database if omitted)
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+        /// Create the suggested indexes
+        #[arg(long)]
+        apply: bool,
+        /// Only consider query shapes run at least this many times
+        #[arg(long, default_value_t = 2)]
+        min_count: u32,
+    },
+    /// List or revert recent changes recorded in table history
+    #[command(after_help = "Examples:
+  datasink undo -D shop --list
+  datasink undo -D shop --last 1 --dry-run
+  datasink undo -D shop --last 3 --table orders
+  datasink undo -D shop --id orders@1718000000.123
+
+Only tables with history (`history = true` in the schema) record changes. Each change is
+reverted by compensating statements, the most recent change first and all in one
+transaction; if a row a change wrote has been changed again since, nothing is undone.
+Undoing requires the admin token when the server has one.")]
+    Undo {
+        /// Database the changes were made in (uses the default database if omitted)
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+        /// Undo this many of the most recent changes
+        #[arg(long, conflicts_with_all = ["id", "list"])]
+        last: Option<u32>,
+        /// Undo a change by its id, as --list shows it (can be repeated)
+        #[arg(long, conflicts_with = "list")]
+        id: Vec<String>,
+        /// Only consider changes to this table
+        #[arg(short, long)]
+        table: Option<String>,
+        /// List recent changes instead of undoing any
+        #[arg(long)]
+        list: bool,
+        /// Print the compensating statements without running them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Generate load against a running server and report throughput and latency
+    #[command(after_help = "Examples:
+  datasink bench --table events --rows 100000 --concurrency 8
+  datasink bench --table events --rows 100000 --batch-size 500
+  datasink bench --table users --rows 1000 --queries 10000 -D analytics")]
+    Bench {
+        /// Table to insert synthetic rows into (must already exist)
+        #[arg(short, long)]
+        table: String,
+        /// Number of rows to insert
+        #[arg(short, long, default_value = "10000")]
+        rows: usize,
+        /// Number of concurrent clients
+        #[arg(short, long, default_value = "4")]
+        concurrency: usize,
+        /// Rows per request (1 uses Insert, more uses BatchInsert)
+        #[arg(short, long, default_value = "1")]
+        batch_size: usize,
+        /// Point queries to run after the inserts (0 to skip)
+        #[arg(short, long, default_value = "1000")]
+        queries: usize,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Fill a table with plausible fake data for demos and tests
+    #[command(after_help = "Examples:
+  datasink generate users --rows 1000
+  datasink generate users --rows 500 --set age=int:21..65 --set status=choice:free,pro -D mydb
+  datasink generate events --rows 10000 --set created_at=timestamp:30 --seed 42
+
+Generators: first_name, last_name, name, email, url, phone, timestamp[:DAYS],
+int:MIN..MAX, real:MIN..MAX, bool, text, uuid, choice:A,B,..., sequence, blob, null")]
+    Generate {
+        /// Table to fill (must already exist)
+        table: String,
+        /// Number of rows to insert
+        #[arg(short, long, default_value = "100")]
+        rows: usize,
+        /// Override the generator for a column as COLUMN=GENERATOR (repeatable)
+        #[arg(long = "set", value_name = "COLUMN=GENERATOR", value_parser = parse_generator_override)]
+        overrides: Vec<(String, String)>,
+        /// Random seed, to reproduce the same data
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
     /// Schema information and statistics
     #[command(after_help = "Examples:
   datasink schema list-tables
@@ -182,6 +936,41 @@ pub enum Commands {
         #[command(subcommand)]
         command: SchemaCommands,
     },
+    /// Manage recurring SQL jobs run by the server
+    #[command(after_help = "Examples:
+  datasink jobs list
+  datasink jobs add nightly-cleanup --schedule \"0 3 * * *\" --sql \"DELETE FROM sessions WHERE expired = 1\"
+  datasink jobs runs nightly-cleanup")]
+    Jobs {
+        #[command(subcommand)]
+        command: JobCommands,
+    },
+    /// Store named queries in a database and run them with parameters
+    #[command(after_help = "Examples:
+  datasink saved add big-orders \"SELECT * FROM orders WHERE total > :min\"
+  datasink saved run big-orders --param min=100
+  datasink saved list")]
+    Saved {
+        #[command(subcommand)]
+        command: SavedCommands,
+    },
+    /// Open and close sessions whose queries share a connection and its TEMP tables
+    #[command(after_help = "Examples:
+  export DATASINK_SESSION=$(datasink session open -D analytics)
+  datasink query \"CREATE TEMP TABLE big AS SELECT * FROM orders WHERE total > 100\"
+  datasink query \"SELECT customer_id, count(*) FROM big GROUP BY 1\"
+  datasink session close")]
+    Session {
+        #[command(subcommand)]
+        command: SessionCommands,
+    },
+    /// Stream records from an external system into a table
+    #[command(after_help = "Examples:
+  datasink sink kafka --brokers localhost:9092 --topic readings --table readings")]
+    Sink {
+        #[command(subcommand)]
+        command: SinkCommands,
+    },
 }
 
 #[derive(Subcommand)]
@@ -190,40 +979,241 @@ pub enum ServerCommands {
     #[command(after_help = "Examples:
   datasink server start
   datasink server start -b 0.0.0.0:8080
-  datasink server start -b 127.0.0.1:9000 -d sqlite://myapp.db")]
+  datasink server start -b 127.0.0.1:9000 -d sqlite://myapp.db
+  datasink server start --config datasink.toml
+  datasink server start -D analytics=sqlite://analytics.db -D logs=sqlite://logs.db
+  datasink server start --no-persist
+  datasink server start --idle-timeout 15
+  datasink server start --query-cache 1000
+  datasink server start --ingest-addr 127.0.0.1:8080
+  datasink server start --keepalive-interval 30 --concurrency-limit 64
+  datasink server start --log-format json --log-file /var/log/datasink.log")]
     Start {
-        /// Server bind address
-        #[arg(short, long, default_value = "127.0.0.1:50051")]
-        bind_address: String,
+        /// Server bind address [default: 127.0.0.1:50051]
+        #[arg(short, long)]
+        bind_address: Option<String>,
+        /// Path to a TOML configuration file (command line flags take precedence)
+        #[arg(short, long)]
+        config: Option<String>,
+        /// Additional database to register at startup as NAME=URL (repeatable)
+        #[arg(short = 'D', long = "database", value_name = "NAME=URL", value_parser = parse_database_spec)]
+        databases: Vec<(String, String)>,
+        /// File used to remember databases added at runtime [default: datasink.registry.json]
+        #[arg(long)]
+        state_file: Option<String>,
+        /// Do not persist databases added at runtime across restarts
+        #[arg(long)]
+        no_persist: bool,
+        /// Close connection pools of databases unused for this many minutes (reopened on next access)
+        #[arg(long, value_name = "MINUTES")]
+        idle_timeout: Option<u64>,
+        /// Cache up to this many SELECT results, invalidated when their tables are written
+        #[arg(long, value_name = "ENTRIES")]
+        query_cache: Option<usize>,
+        /// Also accept rows as JSON over HTTP on this address (POST /ingest/DATABASE/TABLE)
+        #[arg(long, value_name = "ADDR")]
+        ingest_addr: Option<String>,
+        /// Handle at most this many requests at once on each client connection
+        #[arg(long, value_name = "N")]
+        concurrency_limit: Option<usize>,
+        /// Allow at most this many concurrent HTTP/2 streams per client connection
+        #[arg(long, value_name = "N")]
+        max_concurrent_streams: Option<u32>,
+        /// Log output format (text, json)
+        #[arg(long)]
+        log_format: Option<String>,
+        /// Write logs to this file instead of stdout
+        #[arg(long)]
+        log_file: Option<String>,
+        /// Rotate the log file after it reaches this size in megabytes [default: 100]
+        #[arg(long, value_name = "MB")]
+        log_max_size: Option<u64>,
     },
-    /// Stop the gRPC server (requires server to implement shutdown endpoint)
+    /// Stop the gRPC server once requests in progress have finished
     #[command(after_help = "Examples:
-  datasink server stop")]
+  datasink server stop
+
+When the server has an admin_token, DATASINK_TOKEN must be set to it.")]
     Stop,
     /// Show server status and connected databases
     #[command(after_help = "Examples:
-  datasink server status")]
-    Status,
+  datasink server status
+  datasink server status --detailed
+  datasink server status --format table --detailed
+  datasink server status --format yaml")]
+    Status {
+        /// Output format (text, table, json, yaml)
+        #[arg(short, long, default_value = "text", value_parser = ["text", "table", "json", "yaml"])]
+        format: String,
+        /// Also show each database's activity since the server started:
+        /// requests, queries, rows written, last write and errors
+        #[arg(long)]
+        detailed: bool,
+    },
+    /// Show storage details of one database: file, size, journal mode, schema and tables
+    #[command(after_help = "Examples:
+  datasink server info
+  datasink server info -D analytics
+  datasink --json server info -D analytics")]
+    Info {
+        /// Database to describe (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Show or change a PRAGMA of a running database
+    #[command(after_help = "Examples:
+  datasink server pragma journal_mode
+  datasink server pragma -D mydb journal_mode=WAL
+  datasink server pragma -D mydb cache_size=-20000
+
+Settable PRAGMAs: journal_mode, synchronous, temp_store, cache_size, mmap_size,
+wal_autocheckpoint. A change applies to every connection of the database and is
+kept across restarts for databases added with add-database.")]
+    Pragma {
+        /// PRAGMA name to show, or NAME=VALUE to change it
+        setting: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Show server metrics such as query cache hits and misses
+    #[command(after_help = "Examples:
+  datasink server metrics")]
+    Metrics,
+    /// Continuously show per-database request rates, active queries, connections and slow queries
+    #[command(after_help = "Examples:
+  datasink server top
+  datasink server top --interval 5
+  datasink server top --count 1")]
+    Top {
+        /// Seconds between refreshes
+        #[arg(short, long, default_value_t = 2.0, value_parser = parse_interval)]
+        interval: f64,
+        /// Exit after this many refreshes instead of running until Ctrl-C
+        #[arg(long, value_name = "N")]
+        count: Option<u64>,
+    },
+    /// Print a new random key for encrypted columns
+    #[command(after_help = "Examples:
+  datasink server generate-key > datasink.key
+  DATASINK_ENCRYPTION_KEY=$(datasink server generate-key) datasink server start")]
+    GenerateKey,
     /// Add a database to the running server
     #[command(after_help = "Examples:
   datasink server add-database analytics sqlite://analytics.db
-  datasink server add-database logs sqlite:///var/data/logs.db")]
+  datasink server add-database logs sqlite:///var/data/logs.db
+  datasink server add-database shop sqlite:///data/shop.db --replica sqlite:///replica/shop.db
+
+SELECTs, get, search and within are spread over the --replica databases, which another
+process (e.g. Litestream or LiteFS) keeps up to date; writes go to URL. Use --consistency
+strong on a read that must see earlier writes.")]
     AddDatabase {
         /// Database name/identifier
         name: String,
         /// Database URL
         url: String,
+        /// URL of a read replica of the database (repeatable)
+        #[arg(long = "replica", value_name = "URL")]
+        replicas: Vec<String>,
+    },
+    /// Disconnect a database from the server (its file is kept)
+    #[command(after_help = "Examples:
+  datasink server remove-database analytics")]
+    RemoveDatabase {
+        /// Database name/identifier
+        name: String,
+    },
+    /// Create a new database on the server from a schema and add it
+    #[command(after_help = "Examples:
+  datasink server provision acme --template tenant
+  datasink server provision acme --schema tenant.toml
+
+--template names a <name>.toml file in the server's schema_dir; --schema uploads
+a local schema file, which cannot include other files or load data from files.
+The database file is <name>.db in the server's data_dir.")]
+    Provision {
+        /// Name of the new database (letters, digits, '_' and '-')
+        name: String,
+        /// Schema template on the server
+        #[arg(long, required_unless_present = "schema", conflicts_with = "schema")]
+        template: Option<String>,
+        /// Local schema file to upload
+        #[arg(long)]
+        schema: Option<String>,
+    },
+    /// Copy a database on the server and add the copy
+    #[command(after_help = "Examples:
+  datasink server clone main staging
+  datasink server clone main pre_migration_snapshot
+  datasink server clone main empty_copy --schema-only
+
+The copy is <new_name>.db in the server's data_dir and opens with the source's
+connection options. --schema-only copies tables, indexes, views and triggers
+and DataSink's metadata (TTL columns, descriptions, applied schemas) without rows.")]
+    Clone {
+        /// Database to copy
+        source: String,
+        /// Name of the copy (letters, digits, '_' and '-')
+        new_name: String,
+        /// Copy the schema without the data
+        #[arg(long)]
+        schema_only: bool,
     },
     /// Create a new table
     #[command(after_help = "Examples:
   datasink server create-table users '[{\"name\":\"id\",\"type\":\"INTEGER\",\"primary_key\":true}]'
-  datasink server create-table products '[{\"name\":\"id\",\"type\":\"INTEGER\",\"primary_key\":true},{\"name\":\"name\",\"type\":\"TEXT\",\"nullable\":false},{\"name\":\"price\",\"type\":\"REAL\"}]'")]
+  datasink server create-table products '[{\"name\":\"id\",\"type\":\"INTEGER\",\"primary_key\":true},{\"name\":\"name\",\"type\":\"TEXT\",\"nullable\":false},{\"name\":\"price\",\"type\":\"REAL\"}]'
+  datasink server create-table members '[{\"name\":\"org_id\",\"type\":\"INTEGER\"},{\"name\":\"email\",\"type\":\"TEXT\"}]' --unique org_id,email")]
     CreateTable {
         /// Table name
         name: String,
         /// Column definitions as JSON array
         /// Example: '[{"name":"id","type":"INTEGER","primary_key":true},{"name":"name","type":"TEXT"}]'
         columns: String,
+        /// Comma-separated columns whose values must be unique together (repeatable)
+        #[arg(long, value_name = "COLUMNS")]
+        unique: Vec<String>,
+    },
+    /// Create a full-text search index over text columns of a table
+    #[command(after_help = "Examples:
+  datasink server create-search-index posts -c title -c body
+  datasink server create-search-index notes -c title -c description -D postit")]
+    CreateSearchIndex {
+        /// Table name
+        table: String,
+        /// Text column to index (repeatable)
+        #[arg(short, long = "column", required = true)]
+        columns: Vec<String>,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Create a spatial (R*Tree) index over coordinate columns of a table
+    #[command(after_help = "Examples:
+  datasink server create-spatial-index places -c lon -c lat
+  datasink server create-spatial-index parcels -c min_x -c min_y -c max_x -c max_y -D gis
+
+Give two columns (x, y) for points or four (min_x, min_y, max_x, max_y) for boxes.")]
+    CreateSpatialIndex {
+        /// Table name
+        table: String,
+        /// Coordinate column, in x, y or min_x, min_y, max_x, max_y order (repeatable)
+        #[arg(short, long = "column", required = true)]
+        columns: Vec<String>,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Rebuild a materialized view from its query now
+    #[command(after_help = "Examples:
+  datasink server refresh-view daily_sales
+  datasink server refresh-view top_authors -D blog")]
+    RefreshView {
+        /// Materialized view name
+        name: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
     },
     /// Create a new database (SQLite: creates new file)
     #[command(after_help = "Examples:
@@ -253,8 +1243,15 @@ pub enum SchemaCommands {
     /// List all tables in the database
     #[command(name = "list-tables", after_help = "Examples:
   datasink schema list-tables
-  datasink schema list-tables -D mydb")]
+  datasink schema list-tables -D mydb
+  datasink schema list-tables --like 'user%' --with-counts")]
     ListTables {
+        /// Only list tables whose name matches this SQL LIKE pattern (% and _ wildcards, case-insensitive)
+        #[arg(long, value_name = "PATTERN")]
+        like: Option<String>,
+        /// Show each table's row count
+        #[arg(long)]
+        with_counts: bool,
         /// Target database (defaults to "default")
         #[arg(short = 'D', long)]
         database: Option<String>,
@@ -263,9 +1260,14 @@ pub enum SchemaCommands {
     #[command(name = "describe", after_help = "Examples:
   datasink schema describe users
   datasink schema describe users products tags
-  datasink schema describe -D mydb")]
+  datasink schema describe 'user*' orders
+  datasink schema describe -D mydb
+  datasink schema describe users --verbose
+
+Names containing *, ? or [...] are glob patterns matched against the table list.
+With --verbose the table and column descriptions from the schema file are shown.")]
     Describe {
-        /// Table names to describe (describe all tables if none provided)
+        /// Table names or glob patterns to describe (describe all tables if none provided)
         tables: Vec<String>,
         /// Target database (defaults to "default")
         #[arg(short = 'D', long)]
@@ -275,7 +1277,14 @@ pub enum SchemaCommands {
     #[command(name = "stats", after_help = "Examples:
   datasink schema stats
   datasink schema stats -D mydb
-  datasink schema stats --detailed")]
+  datasink schema stats --detailed
+  datasink schema stats --table users
+  datasink schema stats --table users --columns
+  datasink schema stats --table orders --columns total,created_at --json
+
+--columns profiles the table's columns: NULL and distinct counts, minimum, maximum and average
+(for numeric, boolean and timestamp columns), computed by the server in one pass over the
+table.")]
     Stats {
         /// Target database (defaults to "default")
         #[arg(short = 'D', long)]
@@ -283,6 +1292,12 @@ pub enum SchemaCommands {
         /// Show detailed statistics
         #[arg(long)]
         detailed: bool,
+        /// Only show this table
+        #[arg(long)]
+        table: Option<String>,
+        /// With --table, profile these columns (comma-separated; all columns if none are given)
+        #[arg(long, requires = "table", num_args = 0.., value_delimiter = ',', value_name = "COLUMNS")]
+        columns: Option<Vec<String>>,
     },
     /// Display full database schema
     #[command(name = "show", after_help = "Examples:
@@ -297,6 +1312,255 @@ pub enum SchemaCommands {
         #[arg(short, long, default_value = "text")]
         format: String,
     },
+    /// Show whether foreign keys are enforced and list rows that violate them
+    #[command(name = "foreign-keys", after_help = "Examples:
+  datasink schema foreign-keys
+  datasink schema foreign-keys orders
+  datasink schema foreign-keys --limit 1000 -D mydb")]
+    ForeignKeys {
+        /// Only check this table (checks every table if omitted)
+        table: Option<String>,
+        /// Maximum number of violations to list
+        #[arg(short, long, default_value = "100")]
+        limit: u32,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Show the schema file version last applied to a database
+    #[command(name = "version", after_help = "Examples:
+  datasink schema version
+  datasink schema version -D mydb
+  datasink --json schema version -D mydb
+
+Databases created with create-from-schema record the schema's name, version and a
+checksum of the file. Exits with code 4 if no schema has been applied.")]
+    Version {
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Print the statements a schema file would run, without touching any database
+    #[command(name = "ddl", after_help = "Examples:
+  datasink schema ddl schemas/blog.schema
+  datasink schema ddl schemas/blog.schema > blog.sql
+  datasink --json schema ddl schemas/blog.schema
+
+Statements are listed in the order create-from-schema runs them. A partitioned
+table is shown as its current partition and the view over it; seed data is left out.")]
+    Ddl {
+        /// Path to the schema file
+        schema_file: String,
+        /// SQL dialect to generate
+        #[arg(long, default_value = "sqlite", value_parser = ["sqlite"])]
+        dialect: String,
+    },
+    /// Generate migration SQL between two versions of a schema file
+    #[command(name = "plan", after_help = "Examples:
+  datasink schema plan old/blog.schema blog.schema
+  datasink schema plan old/blog.schema blog.schema --output 0007_change.sql
+  datasink schema plan old/blog.schema blog.schema -o 0007_change.sql --reverse 0007_change.down.sql
+
+Tables, indexes and views are matched by name. Columns added at the end or dropped
+use ALTER TABLE; other table changes rebuild the table (create it under a new name,
+copy the rows, drop the old table and rename). A renamed column is seen as dropped
+and added. Statements that delete data are listed as warnings, and partitioned
+tables, seed data and DataSink's metadata (TTL, descriptions) are not migrated.
+The reverse SQL is best-effort: it cannot bring back dropped rows or columns.")]
+    Plan {
+        /// The schema file the database was created from
+        old_schema: String,
+        /// The schema file to migrate to
+        new_schema: String,
+        /// Write the migration SQL to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Also write SQL undoing the migration to this file
+        #[arg(long, value_name = "FILE")]
+        reverse: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SinkCommands {
+    /// Consume JSON records from a Kafka topic and insert them in batches
+    #[command(after_help = "Examples:
+  datasink sink kafka --brokers localhost:9092 --topic readings --table readings
+  datasink sink kafka --brokers kafka1:9092,kafka2:9092 --topic events --table events --from-beginning
+  datasink sink kafka --brokers localhost:9092 --topic readings --table readings \\
+    --map sensor=device.id --map temp=data.temp --map 'kafka_partition=$partition' --map 'kafka_offset=$offset' \\
+    --on-conflict ignore --batch-size 1000 -D metrics
+
+Without --map, each top-level field of a record is written to the column of the same
+name. PATH is a dotted path into the record (data.temp, tags.0) or one of $key,
+$partition, $offset and $timestamp. Offsets are committed after their batch is inserted.")]
+    Kafka {
+        /// Comma-separated Kafka bootstrap servers
+        #[arg(long)]
+        brokers: String,
+        /// Topic to consume
+        #[arg(long)]
+        topic: String,
+        /// Table to insert into (must already exist)
+        #[arg(short, long)]
+        table: String,
+        /// Consumer group whose committed offsets track progress
+        #[arg(long, default_value = "datasink")]
+        group_id: String,
+        /// Read a column from the record as COLUMN=PATH (repeatable)
+        #[arg(long = "map", value_name = "COLUMN=PATH", value_parser = sink::parse_mapping)]
+        mapping: Vec<(String, String)>,
+        /// Most records inserted per transaction
+        #[arg(short, long, default_value = "500")]
+        batch_size: usize,
+        /// Milliseconds a partial batch waits for more records before it is inserted
+        #[arg(long, value_name = "MS", default_value = "1000")]
+        batch_timeout: u64,
+        /// What to do with rows that violate a UNIQUE or PRIMARY KEY constraint
+        #[arg(long, default_value = "abort", value_parser = ["abort", "ignore"])]
+        on_conflict: String,
+        /// Start from the oldest retained record when the group has no committed offset
+        #[arg(long)]
+        from_beginning: bool,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum JobCommands {
+    /// List registered jobs and their next run
+    #[command(name = "list", after_help = "Examples:
+  datasink jobs list")]
+    List,
+    /// Register a job
+    #[command(name = "add", after_help = "Examples:
+  datasink jobs add nightly-cleanup --schedule \"0 3 * * *\" --sql \"DELETE FROM sessions WHERE expired = 1\"
+  datasink jobs add rollup --schedule \"*/5 * * * *\" --sql \"INSERT INTO hourly SELECT ...\" -D analytics")]
+    Add {
+        /// Unique job name
+        name: String,
+        /// Cron expression in UTC (min hour day month weekday, with an optional leading seconds field)
+        #[arg(long)]
+        schedule: String,
+        /// Statement to run
+        #[arg(long)]
+        sql: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Show recent runs, most recent first
+    #[command(name = "runs", after_help = "Examples:
+  datasink jobs runs
+  datasink jobs runs nightly-cleanup --limit 50
+  datasink jobs runs -D analytics")]
+    Runs {
+        /// Only show runs of this job
+        job: Option<String>,
+        /// Maximum number of runs to show
+        #[arg(short, long, default_value = "20")]
+        limit: u32,
+        /// Database whose history to show when no job is given (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Remove a job added at runtime
+    #[command(name = "remove", after_help = "Examples:
+  datasink jobs remove nightly-cleanup")]
+    Remove {
+        /// Job name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SessionCommands {
+    /// Open a session and print its ID
+    #[command(name = "open", after_help = "Examples:
+  datasink session open
+  export DATASINK_SESSION=$(datasink session open -D analytics)
+
+Queries sent while DATASINK_SESSION holds the ID run in the session, on a connection of
+its own: TEMP tables they create are visible to later queries of the session and to no
+one else. A session unused for 30 minutes is closed.")]
+    Open {
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Close a session, dropping its TEMP tables
+    #[command(name = "close", after_help = "Examples:
+  datasink session close
+  datasink session close 0b6f3c1e-...")]
+    Close {
+        /// Session ID (defaults to DATASINK_SESSION)
+        id: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SavedCommands {
+    /// Save a query under a name
+    #[command(name = "add", after_help = "Examples:
+  datasink saved add active-users \"SELECT * FROM users WHERE active = 1\"
+  datasink saved add orders-by-customer \"SELECT * FROM orders WHERE customer_id = :customer\" --description \"Orders of one customer\"
+  datasink saved add big-orders \"SELECT * FROM orders WHERE total > :min\" --replace -D shop
+
+Placeholders are written :name and given values with `saved run --param name=VALUE`.")]
+    Add {
+        /// Unique query name
+        name: String,
+        /// SQL to save
+        sql: String,
+        /// What the query is for
+        #[arg(long)]
+        description: Option<String>,
+        /// Replace a saved query with the same name
+        #[arg(long)]
+        replace: bool,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// List saved queries and their parameters
+    #[command(name = "list", after_help = "Examples:
+  datasink saved list
+  datasink saved list -D shop")]
+    List {
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Run a saved query
+    #[command(name = "run", after_help = "Examples:
+  datasink saved run active-users
+  datasink saved run orders-by-customer --param customer=42 -f json
+  datasink saved run big-orders --param min=100 -D shop")]
+    Run {
+        /// Saved query name
+        name: String,
+        /// Value for a :name placeholder as NAME=VALUE (repeatable)
+        #[arg(short, long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+        params: Vec<(String, String)>,
+        /// Output format (table, json, csv, vertical)
+        #[arg(short, long, default_value = "table")]
+        format: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
+    /// Delete a saved query
+    #[command(name = "remove", after_help = "Examples:
+  datasink saved remove big-orders")]
+    Remove {
+        /// Saved query name
+        name: String,
+        /// Target database (defaults to "default")
+        #[arg(short = 'D', long)]
+        database: Option<String>,
+    },
 }
 
 #[cfg(test)]
@@ -319,6 +1583,20 @@ mod tests {
             database_name: None,
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -340,6 +1618,20 @@ mod tests {
             database_name: Some("testdb".to_string()),
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -356,6 +1648,20 @@ mod tests {
             database_name: None,
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -372,6 +1678,20 @@ mod tests {
             database_name: Some("myapp".to_string()),
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -388,6 +1708,20 @@ mod tests {
             database_name: Some("myapp".to_string()),
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -417,6 +1751,20 @@ mod tests {
             database_name: None,
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -441,6 +1789,20 @@ mod tests {
             database_name: None,
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -450,6 +1812,31 @@ mod tests {
         assert_eq!(result, "sqlite://testdb.db"); // Should return the name as-is when no file exists
     }
 
+    #[test]
+    fn test_parse_database_spec() {
+        assert_eq!(
+            parse_database_spec("analytics=sqlite://analytics.db?mode=rwc").unwrap(),
+            ("analytics".to_string(), "sqlite://analytics.db?mode=rwc".to_string())
+        );
+        assert!(parse_database_spec("analytics").is_err());
+        assert!(parse_database_spec("=sqlite://x.db").is_err());
+        assert!(parse_database_spec("name=").is_err());
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("5").unwrap(), 5.0);
+        assert_eq!(parse_interval("0.5").unwrap(), 0.5);
+        assert_eq!(parse_interval("5s").unwrap(), 5.0);
+        assert_eq!(parse_interval("250ms").unwrap(), 0.25);
+        assert_eq!(parse_interval("2m").unwrap(), 120.0);
+        assert_eq!(parse_interval("1h").unwrap(), 3600.0);
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("-1s").is_err());
+        assert!(parse_interval("5d").is_err());
+        assert!(parse_interval("s").is_err());
+    }
+
     #[test]
     #[serial]
     fn test_environment_variable_database_url() {
@@ -464,6 +1851,20 @@ mod tests {
             database_name: None,
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -497,6 +1898,20 @@ mod tests {
             database_name: None,
             server_address: "http://127.0.0.1:50051".to_string(),
             verbose: false,
+            json: false,
+            null_as: None,
+            raw_timestamps: false,
+            local_time: false,
+            decimals: None,
+            thousands: false,
+            connect_timeout: None,
+            request_timeout: None,
+            retries: 0,
+            keepalive_interval: None,
+            keepalive_timeout: None,
+            tcp_nodelay: None,
+            show_sql: false,
+            consistency: None,
             command: Commands::Server {
                 command: ServerCommands::Stop,
             },
@@ -510,4 +1925,134 @@ mod tests {
         env::remove_var("DATABASE_NAME");
         env::set_current_dir(&_original_dir).unwrap();
     }
+
+    #[test]
+    fn test_cli_definition() {
+        use clap::CommandFactory;
+        // Catches clashing flags, which clap otherwise only reports when the command runs
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_connection_flags() {
+        use clap::Parser;
+
+        // The client flags are global, so they may follow the subcommand
+        let cli = Cli::try_parse_from([
+            "datasink", "server", "start", "--keepalive-interval", "30", "--keepalive-timeout", "5",
+            "--tcp-nodelay", "false", "--concurrency-limit", "64", "--max-concurrent-streams", "100",
+        ])
+        .unwrap();
+        assert_eq!((cli.keepalive_interval, cli.keepalive_timeout, cli.tcp_nodelay), (Some(30), Some(5), Some(false)));
+        match cli.command {
+            Commands::Server { command: ServerCommands::Start { concurrency_limit, max_concurrent_streams, .. } } => {
+                assert_eq!((concurrency_limit, max_concurrent_streams), (Some(64), Some(100)));
+            }
+            _ => panic!("expected server start"),
+        }
+
+        let cli = Cli::try_parse_from(["datasink", "--keepalive-interval", "10", "ping"]).unwrap();
+        assert_eq!((cli.keepalive_interval, cli.tcp_nodelay), (Some(10), None));
+        assert!(Cli::try_parse_from(["datasink", "--tcp-nodelay", "maybe", "ping"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_bbox() {
+        let bbox = parse_bbox("-74.05, 40.68,-73.9,40.82").unwrap();
+        assert_eq!((bbox.min_x, bbox.min_y, bbox.max_x, bbox.max_y), (-74.05, 40.68, -73.9, 40.82));
+        assert!(parse_bbox("1,2,3").is_err());
+        assert!(parse_bbox("1,2,x,4").is_err());
+        assert!(parse_bbox("5,0,1,1").is_err());
+    }
+
+    #[test]
+    fn test_parse_table_ref() {
+        assert_eq!(parse_table_ref("main.users").unwrap(), ("main".to_string(), "users".to_string()));
+        assert_eq!(parse_table_ref("users").unwrap(), (String::new(), "users".to_string()));
+        assert!(parse_table_ref(".users").is_err());
+        assert!(parse_table_ref("main.").is_err());
+    }
+
+    #[test]
+    fn test_parse_keep() {
+        assert_eq!(parse_keep("first").unwrap(), KeepRow::First);
+        assert_eq!(parse_keep("newest:updated_at").unwrap(), KeepRow::Newest("updated_at".to_string()));
+        assert_eq!(parse_keep("oldest:created_at").unwrap(), KeepRow::Oldest("created_at".to_string()));
+        assert!(parse_keep("newest").is_err());
+        assert!(parse_keep("newest:").is_err());
+        assert!(parse_keep("middle").is_err());
+    }
+
+    #[test]
+    fn test_parse_returning() {
+        assert_eq!(parse_returning("*").unwrap(), Returning::All);
+        assert_eq!(
+            parse_returning("id, created_at").unwrap(),
+            Returning::Columns(vec!["id".to_string(), "created_at".to_string()])
+        );
+        assert!(parse_returning("id,").is_err());
+        assert!(parse_returning("id,*").is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        use crate::cli::export::FilterValue;
+        use crate::proto::common::{value, Value};
+
+        let filter = parse_filter("created_at >= now-30d").unwrap();
+        assert_eq!((filter.column.as_str(), filter.operator), ("created_at", ">="));
+        assert_eq!(filter.value, FilterValue::SecondsAgo(30 * 86400));
+        let filter = parse_filter("status!='42'").unwrap();
+        assert_eq!(filter.operator, "!=");
+        assert_eq!(filter.value, FilterValue::Literal(Value { value: Some(value::Value::TextValue("42".to_string())) }));
+        assert_eq!(parse_filter("age<18").unwrap().value, FilterValue::Literal(Value { value: Some(value::Value::IntValue(18)) }));
+        assert_eq!(parse_filter("deleted_at = null").unwrap().value, FilterValue::Null);
+        assert!(parse_filter("deleted_at > null").is_err());
+        assert!(parse_filter("created_at > now+1d").is_err());
+        assert!(parse_filter("created_at > now-3w").is_err());
+        assert!(parse_filter("created_at > now-").is_err());
+        assert!(parse_filter("name").is_err());
+        assert!(parse_filter("= 1").is_err());
+        assert!(parse_filter("a; DROP TABLE x = 1").is_err());
+        assert!(parse_filter("age =").is_err());
+    }
+
+    #[test]
+    fn test_export_query() {
+        use crate::cli::export::{build_query, csv_field, output_path, ExportFormat};
+
+        let filters = vec![
+            parse_filter("created_at >= now-1h").unwrap(),
+            parse_filter("deleted_at != null").unwrap(),
+        ];
+        let columns = vec!["id".to_string(), "name".to_string()];
+        let (sql, parameters) = build_query("users", &columns, &filters, Some("id % 2 = 0"), 10_000);
+        assert_eq!(
+            sql,
+            "SELECT \"id\", \"name\" FROM \"users\" WHERE \"created_at\" >= :filter_0 AND \"deleted_at\" IS NOT NULL AND (id % 2 = 0)"
+        );
+        assert_eq!(parameters.len(), 1);
+        assert!(matches!(parameters["filter_0"].value, Some(crate::proto::common::value::Value::IntValue(6400))));
+
+        assert_eq!(ExportFormat::for_path("out/Users.CSV"), ExportFormat::Csv);
+        assert_eq!(ExportFormat::for_path("users.json"), ExportFormat::Json);
+        assert_eq!(ExportFormat::for_path("users.jsonl"), ExportFormat::Ndjson);
+        assert_eq!(ExportFormat::for_path("users.csv.gz"), ExportFormat::Csv);
+        assert_eq!(output_path("users.csv", Compression::Zstd), "users.csv.zst");
+        assert_eq!(output_path("users.csv.gz", Compression::Gzip), "users.csv.gz");
+        assert_eq!(output_path("users.csv", Compression::None), "users.csv");
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn test_read_input_from_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("rows.ndjson");
+        fs::write(&path, "{\"id\": 1}\n").unwrap();
+        assert_eq!(read_input(path.to_str().unwrap()).unwrap(), "{\"id\": 1}\n");
+
+        let missing = dir.path().join("missing.json");
+        assert!(read_input(missing.to_str().unwrap()).unwrap_err().starts_with("Failed to read"));
+    }
 }
@@ -0,0 +1,174 @@
+//! `--json`: machine-readable output for scripts.
+//!
+//! With the global `--json` flag every command prints one JSON document to
+//! stdout in place of its tables and messages, with field names that do not
+//! change between releases:
+//!
+//! - listings print an array of objects, or an object holding the listing and
+//!   its context (`server status`, `schema stats`)
+//! - commands that change something print `{"success": true, "message": ...}`
+//!   plus fields such as `inserted_id` or `affected_rows`
+//! - a command that fails prints `{"success": false, "error": ..., "kind": ...}`,
+//!   plus the gRPC `code` for errors from the server (and, for database
+//!   errors, `constraint`, `constraint_name`, `column`, `sqlite_code` and
+//!   `retryable` where known), and exits with the category's exit code (see
+//!   `cli::error`)
+//!
+//! Progress lines, prompts and warnings go to stderr so stdout stays parseable.
+//!
+//! Commands with a `--format yaml` print the same documents as YAML.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{json, Value as JsonValue};
+
+use crate::cli::error;
+use crate::proto::common::Error as QueryError;
+use prost::Message;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Switch every command to JSON output
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Print a JSON document
+pub fn print(document: &JsonValue) {
+    println!("{}", serde_json::to_string_pretty(document).unwrap_or_default());
+}
+
+/// Print a document as YAML
+pub fn print_yaml(document: &JsonValue) -> Result<(), serde_yaml::Error> {
+    print!("{}", serde_yaml::to_string(document)?);
+    Ok(())
+}
+
+/// Report a successful change: `text` normally, or with --json
+/// `{"success": true}` merged with `fields` (an object)
+pub fn success(text: &str, fields: JsonValue) {
+    if !enabled() {
+        println!("{}", text);
+        return;
+    }
+    let mut document = json!({ "success": true });
+    if let (Some(document), JsonValue::Object(fields)) = (document.as_object_mut(), fields) {
+        document.extend(fields);
+    }
+    print(&document);
+}
+
+/// A progress or informational line; kept off stdout with --json
+pub fn progress(text: &str) {
+    if enabled() {
+        eprintln!("{}", text);
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// The document printed for an error that ended a command
+pub fn error_document(error: &(dyn std::error::Error + 'static)) -> JsonValue {
+    let kind = error::kind_of(error).name();
+    let Some(status) = error.downcast_ref::<tonic::Status>() else {
+        return json!({ "success": false, "error": error.to_string(), "kind": kind });
+    };
+    let mut document = json!({
+        "success": false,
+        "error": status.message(),
+        "kind": kind,
+        "code": format!("{:?}", status.code()),
+    });
+    if let Some(details) = Some(status.details()).filter(|d| !d.is_empty()).and_then(|d| QueryError::decode(d).ok()) {
+        let fields = [
+            ("constraint", json!(details.constraint)),
+            ("constraint_name", json!(details.constraint_name)),
+            ("column", json!(details.column)),
+        ];
+        for (name, value) in fields.into_iter().filter(|(_, value)| value != "") {
+            document[name] = value;
+        }
+        if details.sqlite_code != 0 {
+            document["sqlite_code"] = json!(details.sqlite_code);
+        }
+        if !details.unknown_columns.is_empty() {
+            let columns: Vec<JsonValue> = details
+                .unknown_columns
+                .iter()
+                .map(|column| json!({"name": column.name, "close_matches": column.close_matches}))
+                .collect();
+            document["unknown_columns"] = json!(columns);
+        }
+        document["retryable"] = json!(details.retryable);
+    }
+    document
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::UnknownColumn;
+
+    #[test]
+    fn test_error_document() {
+        let status: Box<dyn std::error::Error> = Box::new(tonic::Status::not_found("Table 'x' not found"));
+        assert_eq!(
+            error_document(status.as_ref()),
+            json!({"success": false, "error": "Table 'x' not found", "kind": "not_found", "code": "NotFound"})
+        );
+
+        let details = QueryError {
+            code: "CONSTRAINT_VIOLATION".to_string(),
+            constraint: "UNIQUE".to_string(),
+            column: "users.email".to_string(),
+            sqlite_code: 2067,
+            ..Default::default()
+        };
+        let status = tonic::Status::with_details(
+            tonic::Code::AlreadyExists,
+            "UNIQUE constraint failed: users.email",
+            details.encode_to_vec().into(),
+        );
+        let status: Box<dyn std::error::Error> = Box::new(status);
+        assert_eq!(
+            error_document(status.as_ref()),
+            json!({
+                "success": false,
+                "error": "UNIQUE constraint failed: users.email",
+                "kind": "constraint_violation",
+                "code": "AlreadyExists",
+                "constraint": "UNIQUE",
+                "column": "users.email",
+                "sqlite_code": 2067,
+                "retryable": false,
+            })
+        );
+
+        let details = QueryError {
+            code: "UNKNOWN_COLUMN".to_string(),
+            unknown_columns: vec![UnknownColumn { name: "nmae".to_string(), close_matches: vec!["name".to_string()] }],
+            ..Default::default()
+        };
+        let message = "Table 'users' has no column 'nmae' (did you mean 'name'?)";
+        let status = tonic::Status::with_details(tonic::Code::InvalidArgument, message, details.encode_to_vec().into());
+        let status: Box<dyn std::error::Error> = Box::new(status);
+        assert_eq!(
+            error_document(status.as_ref()),
+            json!({
+                "success": false,
+                "error": message,
+                "kind": "invalid_input",
+                "code": "InvalidArgument",
+                "unknown_columns": [{"name": "nmae", "close_matches": ["name"]}],
+                "retryable": false,
+            })
+        );
+
+        let error: Box<dyn std::error::Error> = "Aborted".into();
+        assert_eq!(error_document(error.as_ref()), json!({"success": false, "error": "Aborted", "kind": "other"}));
+    }
+}
@@ -0,0 +1,529 @@
+//! How result values are shown by `query`, `get`, `sample` and `within`.
+//!
+//! NULL is `NULL` in tables and an empty field in CSV; the global
+//! `--null-as` flag replaces both, e.g. `--null-as '\N'` for tools that
+//! expect it. JSON output keeps `null`. Values of BOOLEAN columns, which
+//! SQLite stores as 0 and 1, are shown as `true` and `false` in every format.
+//!
+//! Unix seconds in TIMESTAMP (and DATETIME or DATE) columns are shown as
+//! RFC 3339 date-times, in UTC or with `--local-time` in the local time zone;
+//! `--raw-timestamps` shows the numbers as stored.
+//!
+//! Tables show REAL values to 15 significant digits, as SQLite's shell does,
+//! so `0.1 + 0.2` is `0.3`, or with `--decimals N` to N decimal places;
+//! neither tables nor CSV use scientific notation. `--thousands` groups the digits of
+//! numbers in tables with the separators of the locale in `LC_ALL`,
+//! `LC_NUMERIC` or `LANG` (`1,234.5`, or `1.234,5` for `de_DE`). CSV and JSON
+//! keep numbers exact for other programs to read.
+//!
+//! `-f vertical` prints each row as `column | value` lines under a numbered
+//! header, like psql's expanded display (`\x`), for rows too wide for a table.
+//!
+//! `query --columns a,b` shows only those columns of the result, in that
+//! order, in every format; `--max-col-width N` cuts longer table and vertical
+//! cells to N characters ending in `…`, so a `SELECT *` with long text stays
+//! readable.
+//!
+//! `query --watch` redraws the table on each run and shows the cells that
+//! differ from the previous run, at the same row and column, in reverse video.
+
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde_json::Value as JsonValue;
+
+use crate::cli::commands::{proto_value_to_json, proto_value_to_string};
+use crate::db::error::UnknownColumn;
+use crate::proto::common::{value, Column, DataType, Value};
+
+/// Significant digits of REAL values in tables, as SQLite's shell shows them
+const SIGNIFICANT_DIGITS: i32 = 15;
+
+/// The text formats a value can be shown in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Csv,
+}
+
+impl Format {
+    /// How NULL is shown when `--null-as` is not given
+    fn default_null(self) -> &'static str {
+        match self {
+            Format::Table => "NULL",
+            Format::Csv => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RenderSettings {
+    /// Text shown for NULL in tables and CSV, in place of each format's default
+    pub null_as: Option<String>,
+    /// Show TIMESTAMP values as the Unix seconds stored
+    pub raw_timestamps: bool,
+    /// Show TIMESTAMP values in the local time zone rather than UTC
+    pub local_time: bool,
+    /// Decimal places of REAL values in tables
+    pub decimals: Option<usize>,
+    /// Group the digits of numbers in tables with these separators
+    pub thousands: Option<Separators>,
+}
+
+/// A locale's digit group separator and decimal mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Separators {
+    pub group: char,
+    pub decimal: char,
+}
+
+impl Separators {
+    /// The separators of the locale named by `LC_ALL`, `LC_NUMERIC` or
+    /// `LANG`, the first that is set
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        Self::for_locale(&locale)
+    }
+
+    /// The separators of a locale such as `de_DE.UTF-8`, by its language;
+    /// English ones for languages not listed and the C locale
+    pub fn for_locale(locale: &str) -> Self {
+        let language = locale.split(['_', '-', '.', '@']).next().unwrap_or_default().to_lowercase();
+        match language.as_str() {
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "el" | "id" | "tr" => Separators { group: '.', decimal: ',' },
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" | "uk" | "hu" => {
+                Separators { group: ' ', decimal: ',' }
+            }
+            _ => Separators { group: ',', decimal: '.' },
+        }
+    }
+}
+
+static SETTINGS: OnceLock<RenderSettings> = OnceLock::new();
+
+/// Use these settings for every value shown
+pub fn configure(settings: RenderSettings) {
+    let _ = SETTINGS.set(settings);
+}
+
+fn settings() -> &'static RenderSettings {
+    SETTINGS.get_or_init(RenderSettings::default)
+}
+
+/// A value as it appears in a table cell or CSV field
+pub fn text(value: Value, column: Option<&Column>, format: Format) -> String {
+    render_text(value, column, format, settings())
+}
+
+fn render_text(value: Value, column: Option<&Column>, format: Format, settings: &RenderSettings) -> String {
+    if let Some(boolean) = boolean(&value, column) {
+        return boolean.to_string();
+    }
+    if let Some(time) = timestamp(&value, column, settings) {
+        return time;
+    }
+    match value.value {
+        None | Some(value::Value::NullValue(_)) => {
+            settings.null_as.clone().unwrap_or_else(|| format.default_null().to_string())
+        }
+        Some(value::Value::IntValue(i)) if format == Format::Table => group_digits(&i.to_string(), settings),
+        Some(value::Value::RealValue(f)) if format == Format::Table && f.is_finite() => {
+            let text = match settings.decimals {
+                Some(decimals) => format!("{:.*}", decimals, f),
+                None => significant(f),
+            };
+            group_digits(&text, settings)
+        }
+        _ => proto_value_to_string(value),
+    }
+}
+
+/// The columns of a result that are shown, and how wide their cells may be
+#[derive(Debug, Clone, Default)]
+pub struct ColumnView {
+    /// Names of the columns to show, in this order; every column if empty
+    pub columns: Vec<String>,
+    /// Most characters a table or vertical cell shows
+    pub max_width: Option<usize>,
+}
+
+impl ColumnView {
+    /// The selected columns, matched by name ignoring case, and their values
+    /// in each row. Fails naming columns the result does not have, unless
+    /// it has no columns at all (no rows were returned).
+    pub fn select(&self, columns: Vec<Column>, rows: Vec<Vec<Value>>) -> Result<(Vec<Column>, Vec<Vec<Value>>), String> {
+        if self.columns.is_empty() || columns.is_empty() {
+            return Ok((columns, rows));
+        }
+        let mut indexes = Vec::new();
+        let mut unknown = Vec::new();
+        for name in &self.columns {
+            match columns.iter().position(|column| column.name.eq_ignore_ascii_case(name)) {
+                Some(i) => indexes.push(i),
+                None => unknown.push(UnknownColumn::new(name, columns.iter().map(|column| column.name.as_str()))),
+            }
+        }
+        if !unknown.is_empty() {
+            let names: Vec<String> = unknown.iter().map(ToString::to_string).collect();
+            return Err(format!("The result has no column {}", names.join(", ")));
+        }
+        let selected = indexes.iter().map(|&i| columns[i].clone()).collect();
+        let rows = rows
+            .into_iter()
+            .map(|row| indexes.iter().map(|&i| row.get(i).cloned().unwrap_or_default()).collect())
+            .collect();
+        Ok((selected, rows))
+    }
+
+    /// A cell cut to the maximum width: its first line, ending in `…` if
+    /// anything was left out
+    pub fn fit(&self, cell: String) -> String {
+        let Some(max_width) = self.max_width else {
+            return cell;
+        };
+        let first_line = cell.lines().next().unwrap_or_default();
+        if first_line.len() == cell.len() && cell.chars().count() <= max_width {
+            return cell;
+        }
+        let mut cut: String = first_line.chars().take(max_width.saturating_sub(1)).collect();
+        cut.push('…');
+        cut
+    }
+}
+
+/// Rows in the vertical format: a `-[ RECORD n ]` header per row, then one
+/// line per column with the names padded to line the values up
+pub fn vertical(columns: &[Column], rows: Vec<Vec<Value>>, view: &ColumnView) -> String {
+    render_vertical(columns, rows, view, settings())
+}
+
+fn render_vertical(columns: &[Column], rows: Vec<Vec<Value>>, view: &ColumnView, settings: &RenderSettings) -> String {
+    let name_width = columns.iter().map(|column| column.name.chars().count()).max().unwrap_or(0);
+    let mut text = String::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        let cells: Vec<(&str, String)> = columns
+            .iter()
+            .zip(row)
+            .map(|(column, value)| {
+                (column.name.as_str(), view.fit(render_text(value, Some(column), Format::Table, settings)))
+            })
+            .collect();
+        let value_width = cells.iter().flat_map(|(_, cell)| cell.lines()).map(|line| line.chars().count()).max();
+        let header = format!("-[ RECORD {} ]", i + 1);
+        let width = name_width + 3 + value_width.unwrap_or(0);
+        text.push_str(&format!("{:-<width$}\n", header, width = width));
+        for (name, cell) in cells {
+            // Lines after the first of a multi-line value stay in the value column
+            let mut lines = cell.lines();
+            text.push_str(&format!("{:<width$} | {}\n", name, lines.next().unwrap_or_default(), width = name_width));
+            for line in lines {
+                text.push_str(&format!("{:<width$} | {}\n", "", line, width = name_width));
+            }
+        }
+    }
+    text
+}
+
+/// The (row, column) positions of the cells of `after` that differ from the
+/// cell at the same position in `before`, or have none there
+pub fn changed_cells(before: &[Vec<String>], after: &[Vec<String>]) -> HashSet<(usize, usize)> {
+    let mut changed = HashSet::new();
+    for (i, row) in after.iter().enumerate() {
+        for (j, cell) in row.iter().enumerate() {
+            if before.get(i).and_then(|row| row.get(j)) != Some(cell) {
+                changed.insert((i, j));
+            }
+        }
+    }
+    changed
+}
+
+/// A table tabled drew from a header and `rows`, with the `changed` cells in
+/// reverse video. The escapes are added after drawing because tabled would
+/// count them towards the column widths.
+pub fn highlight_cells(table: &str, rows: &[Vec<String>], changed: &HashSet<(usize, usize)>) -> String {
+    const REVERSE: &str = "\x1b[7m";
+    const RESET: &str = "\x1b[0m";
+
+    // Rows start after the top border, the header and the line under it
+    let mut row_of_line = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        let height = row.iter().map(|cell| cell.lines().count()).max().unwrap_or(0).max(1);
+        row_of_line.extend(std::iter::repeat_n(i, height));
+    }
+
+    let mut text = String::new();
+    for (n, line) in table.lines().enumerate() {
+        let row = n.checked_sub(3).and_then(|n| row_of_line.get(n));
+        let parts: Vec<&str> = line.split('│').collect();
+        match row {
+            // A line split into other than its cells, e.g. by a `│` in a
+            // value, is left as it is
+            Some(&row) if parts.len() == rows[row].len() + 2 => {
+                let cells = parts[1..parts.len() - 1].iter().enumerate().map(|(j, part)| {
+                    if changed.contains(&(row, j)) {
+                        format!("{}{}{}", REVERSE, part, RESET)
+                    } else {
+                        part.to_string()
+                    }
+                });
+                text.push('│');
+                text.push_str(&cells.collect::<Vec<_>>().join("│"));
+                text.push('│');
+            }
+            _ => text.push_str(line),
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// `f` rounded to [`SIGNIFICANT_DIGITS`], without trailing zeros or an exponent
+fn significant(f: f64) -> String {
+    if f == 0.0 {
+        return "0".to_string();
+    }
+    let magnitude = f.abs().log10().floor() as i32;
+    let decimals = (SIGNIFICANT_DIGITS - 1 - magnitude).max(0) as usize;
+    let text = format!("{:.*}", decimals, f);
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    }
+}
+
+/// A number written with `.` for its decimal point, with its digits grouped
+/// and its decimal mark replaced under `--thousands`
+fn group_digits(number: &str, settings: &RenderSettings) -> String {
+    let Some(separators) = settings.thousands else {
+        return number.to_string();
+    };
+    let (sign, unsigned) = number.strip_prefix('-').map_or(("", number), |rest| ("-", rest));
+    let (integer, fraction) = unsigned.split_once('.').map_or((unsigned, None), |(i, f)| (i, Some(f)));
+    let mut grouped = String::from(sign);
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(separators.group);
+        }
+        grouped.push(digit);
+    }
+    if let Some(fraction) = fraction {
+        grouped.push(separators.decimal);
+        grouped.push_str(fraction);
+    }
+    grouped
+}
+
+/// A value as it appears in JSON output
+pub fn json(value: Value, column: Option<&Column>) -> JsonValue {
+    render_json(value, column, settings())
+}
+
+fn render_json(value: Value, column: Option<&Column>, settings: &RenderSettings) -> JsonValue {
+    if let Some(boolean) = boolean(&value, column) {
+        return JsonValue::Bool(boolean);
+    }
+    match timestamp(&value, column, settings) {
+        Some(time) => JsonValue::String(time),
+        None => proto_value_to_json(value),
+    }
+}
+
+/// What a 0 or 1 in a BOOLEAN column stands for
+fn boolean(value: &Value, column: Option<&Column>) -> Option<bool> {
+    if column?.r#type != DataType::Boolean as i32 {
+        return None;
+    }
+    match value.value {
+        Some(value::Value::IntValue(0)) => Some(false),
+        Some(value::Value::IntValue(1)) => Some(true),
+        _ => None,
+    }
+}
+
+/// The RFC 3339 date-time of Unix seconds in a TIMESTAMP column; None for
+/// other columns, text values and times out of range
+fn timestamp(value: &Value, column: Option<&Column>, settings: &RenderSettings) -> Option<String> {
+    if settings.raw_timestamps || column?.r#type != DataType::Timestamp as i32 {
+        return None;
+    }
+    let time = match value.value {
+        Some(value::Value::IntValue(seconds) | value::Value::TimestampValue(seconds)) => {
+            DateTime::from_timestamp(seconds, 0)?
+        }
+        // Fractional seconds, as history tables record
+        Some(value::Value::RealValue(seconds)) if seconds.is_finite() => {
+            DateTime::from_timestamp(seconds.floor() as i64, (seconds.fract() * 1e9).round().min(999_999_999.0) as u32)?
+        }
+        _ => return None,
+    };
+    Some(if settings.local_time {
+        time.with_timezone(&Local).to_rfc3339_opts(SecondsFormat::AutoSi, false)
+    } else {
+        time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn column(data_type: DataType) -> Column {
+        Column { name: "c".to_string(), r#type: data_type as i32 }
+    }
+
+    fn int(i: i64) -> Value {
+        Value { value: Some(value::Value::IntValue(i)) }
+    }
+
+    #[test]
+    fn test_render_values() {
+        let null = Value { value: Some(value::Value::NullValue(true)) };
+        let defaults = RenderSettings::default();
+        assert_eq!(render_text(null.clone(), None, Format::Table, &defaults), "NULL");
+        assert_eq!(render_text(null.clone(), None, Format::Csv, &defaults), "");
+        let settings = RenderSettings { null_as: Some("\\N".to_string()), ..Default::default() };
+        assert_eq!(render_text(null.clone(), None, Format::Csv, &settings), "\\N");
+        assert_eq!(render_text(null.clone(), None, Format::Table, &settings), "\\N");
+        assert_eq!(render_json(null, None, &defaults), JsonValue::Null);
+
+        let flag = column(DataType::Boolean);
+        assert_eq!(render_text(int(1), Some(&flag), Format::Table, &defaults), "true");
+        assert_eq!(render_text(int(0), Some(&flag), Format::Csv, &defaults), "false");
+        assert_eq!(render_json(int(0), Some(&flag), &defaults), json!(false));
+        // Only 0 and 1 are booleans; anything else is shown as stored
+        assert_eq!(render_text(int(2), Some(&flag), Format::Table, &defaults), "2");
+        assert_eq!(render_json(int(1), Some(&column(DataType::Integer)), &defaults), json!(1));
+    }
+
+    #[test]
+    fn test_render_numbers() {
+        let real = |f: f64| Value { value: Some(value::Value::RealValue(f)) };
+        let defaults = RenderSettings::default();
+        assert_eq!(render_text(real(0.1 + 0.2), None, Format::Table, &defaults), "0.3");
+        assert_eq!(render_text(real(0.1 + 0.2), None, Format::Csv, &defaults), "0.30000000000000004");
+        assert_eq!(render_text(real(1e18), None, Format::Table, &defaults), "1000000000000000000");
+        assert_eq!(render_text(real(-2.5e-7), None, Format::Table, &defaults), "-0.00000025");
+        assert_eq!(render_text(real(100.0), None, Format::Table, &defaults), "100");
+
+        let fixed = RenderSettings { decimals: Some(2), ..Default::default() };
+        assert_eq!(render_text(real(2.0 / 3.0), None, Format::Table, &fixed), "0.67");
+        assert_eq!(render_text(int(7), None, Format::Table, &fixed), "7");
+
+        let grouped = RenderSettings { thousands: Some(Separators::for_locale("en_US.UTF-8")), ..Default::default() };
+        assert_eq!(render_text(int(-1234567), None, Format::Table, &grouped), "-1,234,567");
+        assert_eq!(render_text(int(123), None, Format::Table, &grouped), "123");
+        assert_eq!(render_text(int(1234), None, Format::Csv, &grouped), "1234");
+        let german = RenderSettings {
+            decimals: Some(2),
+            thousands: Some(Separators::for_locale("de_DE.UTF-8")),
+            ..Default::default()
+        };
+        assert_eq!(render_text(real(1234567.891), None, Format::Table, &german), "1.234.567,89");
+        assert_eq!(Separators::for_locale("C"), Separators { group: ',', decimal: '.' });
+    }
+
+    #[test]
+    fn test_render_vertical() {
+        let columns = [("id", DataType::Integer), ("description", DataType::Text), ("active", DataType::Boolean)]
+            .map(|(name, data_type)| Column { name: name.to_string(), r#type: data_type as i32 });
+        let text = |s: &str| Value { value: Some(value::Value::TextValue(s.to_string())) };
+        let null = Value { value: Some(value::Value::NullValue(true)) };
+        let rows = vec![vec![int(1), text("first line\nsecond"), int(1)], vec![int(2), null, int(0)]];
+        assert_eq!(
+            render_vertical(&columns, rows, &ColumnView::default(), &RenderSettings::default()),
+            "-[ RECORD 1 ]-----------\n\
+             id          | 1\n\
+             description | first line\n\
+             \x20           | second\n\
+             active      | true\n\
+             -[ RECORD 2 ]------\n\
+             id          | 2\n\
+             description | NULL\n\
+             active      | false\n"
+        );
+    }
+
+    #[test]
+    fn test_column_view() {
+        let columns: Vec<Column> = ["id", "name", "bio"]
+            .map(|name| Column { name: name.to_string(), r#type: DataType::Text as i32 })
+            .into();
+        let text = |s: &str| Value { value: Some(value::Value::TextValue(s.to_string())) };
+        let rows = vec![vec![int(1), text("Ann"), text("A very long biography")]];
+
+        let view = ColumnView { columns: vec!["BIO".to_string(), "id".to_string()], max_width: Some(6) };
+        let (selected, rows) = view.select(columns.clone(), rows).unwrap();
+        assert_eq!(selected.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), ["bio", "id"]);
+        assert_eq!(rows, [vec![text("A very long biography"), int(1)]]);
+        assert_eq!(view.fit("A very long biography".to_string()), "A ver…");
+        assert_eq!(view.fit("short".to_string()), "short");
+        assert_eq!(view.fit("two\nlines".to_string()), "two…");
+
+        let view = ColumnView { columns: vec!["nmae".to_string()], ..Default::default() };
+        assert_eq!(
+            view.select(columns, Vec::new()).unwrap_err(),
+            "The result has no column 'nmae' (did you mean 'name'?)"
+        );
+        assert!(view.select(Vec::new(), Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_highlight_cells() {
+        let cells = |rows: &[&[&str]]| -> Vec<Vec<String>> {
+            rows.iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect()
+        };
+        let before = cells(&[&["1", "10"], &["2", "20"]]);
+        let after = cells(&[&["1", "15"], &["2", "20"], &["3", "a\nb"]]);
+        let changed = changed_cells(&before, &after);
+        assert_eq!(changed, HashSet::from([(0, 1), (2, 0), (2, 1)]));
+        assert!(changed_cells(&after, &after).is_empty());
+
+        let table = "╭────┬─────╮\n\
+                     │ id │ n   │\n\
+                     ├────┼─────┤\n\
+                     │ 1  │ 15  │\n\
+                     │ 2  │ 20  │\n\
+                     │ 3  │ a   │\n\
+                     │    │ b   │\n\
+                     ╰────┴─────╯";
+        assert_eq!(
+            highlight_cells(table, &after, &changed),
+            "╭────┬─────╮\n\
+             │ id │ n   │\n\
+             ├────┼─────┤\n\
+             │ 1  │\x1b[7m 15  \x1b[0m│\n\
+             │ 2  │ 20  │\n\
+             │\x1b[7m 3  \x1b[0m│\x1b[7m a   \x1b[0m│\n\
+             │\x1b[7m    \x1b[0m│\x1b[7m b   \x1b[0m│\n\
+             ╰────┴─────╯\n"
+        );
+    }
+
+    #[test]
+    fn test_render_timestamps() {
+        let at = column(DataType::Timestamp);
+        let defaults = RenderSettings::default();
+        assert_eq!(render_text(int(1710504000), Some(&at), Format::Table, &defaults), "2024-03-15T12:00:00Z");
+        assert_eq!(render_json(int(1710504000), Some(&at), &defaults), json!("2024-03-15T12:00:00Z"));
+        let real = Value { value: Some(value::Value::RealValue(1710504000.25)) };
+        assert_eq!(render_text(real, Some(&at), Format::Csv, &defaults), "2024-03-15T12:00:00.250Z");
+        // Text is shown as stored
+        let text = Value { value: Some(value::Value::TextValue("2024-03-15 12:00:00".to_string())) };
+        assert_eq!(render_text(text, Some(&at), Format::Table, &defaults), "2024-03-15 12:00:00");
+        assert_eq!(render_text(int(1710504000), Some(&column(DataType::Integer)), Format::Table, &defaults), "1710504000");
+
+        let raw = RenderSettings { raw_timestamps: true, ..Default::default() };
+        assert_eq!(render_json(int(1710504000), Some(&at), &raw), json!(1710504000));
+        let local = RenderSettings { local_time: true, ..Default::default() };
+        let shown = render_text(int(1710504000), Some(&at), Format::Table, &local);
+        assert_eq!(DateTime::parse_from_rfc3339(&shown).unwrap().timestamp(), 1710504000);
+    }
+}
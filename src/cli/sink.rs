@@ -0,0 +1,356 @@
+//! Kafka consumer sink: `datasink sink kafka`
+//!
+//! Consumes JSON records from a topic, maps their fields to the columns of a
+//! table and writes them through the server's BatchInsert, one transaction per
+//! batch. Offsets are committed only after their batch has been inserted, so
+//! every record is written at least once; mapping `$partition` and `$offset`
+//! to a unique key with `--on-conflict ignore` makes redelivery harmless.
+
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::{BorrowedMessage, Message};
+use rdkafka::{Offset, TopicPartitionList};
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::client::{self, Client};
+use super::error::{CliError, ErrorKind};
+use super::output;
+use crate::proto::common::{value, Value};
+use crate::proto::crud::{BatchInsertRequest, InsertRow, OnConflict};
+
+/// How long to wait before retrying a batch the server could not take
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Settings for a Kafka sink
+#[derive(Debug, Clone)]
+pub struct KafkaSinkOptions {
+    /// Comma-separated bootstrap servers
+    pub brokers: String,
+    pub topic: String,
+    pub table: String,
+    pub group_id: String,
+    /// Column and the record path its value is read from; empty maps every
+    /// top-level field to the column of the same name
+    pub mapping: Vec<(String, String)>,
+    /// Most records written per transaction
+    pub batch_size: usize,
+    /// Longest a partial batch waits for more records
+    pub batch_timeout: Duration,
+    pub on_conflict: OnConflict,
+    /// Start from the oldest retained record when the group has no committed offset
+    pub from_beginning: bool,
+    pub database: Option<String>,
+}
+
+/// Where a column's value comes from
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    /// Dotted path into the JSON payload (`payload.temp`, `readings.0`)
+    Path(Vec<String>),
+    Key,
+    Partition,
+    Offset,
+    Timestamp,
+}
+
+impl Source {
+    fn parse(spec: &str) -> Result<Self, String> {
+        match spec.trim() {
+            "$key" => Ok(Source::Key),
+            "$partition" => Ok(Source::Partition),
+            "$offset" => Ok(Source::Offset),
+            "$timestamp" => Ok(Source::Timestamp),
+            other if other.starts_with('$') => Err(format!(
+                "Unknown record field '{}' (expected $key, $partition, $offset or $timestamp)",
+                other
+            )),
+            "" => Err("Empty record path".to_string()),
+            path => Ok(Source::Path(path.split('.').map(str::to_string).collect())),
+        }
+    }
+}
+
+/// Parse a `COLUMN=PATH` mapping for `datasink sink kafka`
+pub fn parse_mapping(spec: &str) -> Result<(String, String), String> {
+    let (column, path) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid mapping '{}': expected COLUMN=PATH", spec))?;
+    Source::parse(path)?;
+    let column = column.trim();
+    if column.is_empty() {
+        return Err(format!("Invalid mapping '{}': expected COLUMN=PATH", spec));
+    }
+    Ok((column.to_string(), path.trim().to_string()))
+}
+
+/// Metadata of the record being mapped
+struct RecordMeta<'a> {
+    key: Option<&'a [u8]>,
+    partition: i32,
+    offset: i64,
+    timestamp: Option<i64>,
+}
+
+/// Column values of one record. Paths missing from the payload leave their
+/// column out so it takes its default.
+fn map_record(
+    payload: &JsonValue,
+    meta: &RecordMeta,
+    mapping: &[(String, Source)],
+) -> Result<HashMap<String, Value>, String> {
+    if mapping.is_empty() {
+        let fields = payload.as_object().ok_or("expected a JSON object")?;
+        return Ok(fields.iter().map(|(name, field)| (name.clone(), json_to_value(field))).collect());
+    }
+
+    let mut values = HashMap::new();
+    for (column, source) in mapping {
+        let value = match source {
+            Source::Path(path) => match lookup(payload, path) {
+                Some(field) => json_to_value(field),
+                None => continue,
+            },
+            Source::Key => match meta.key {
+                Some(key) => text(String::from_utf8_lossy(key).into_owned()),
+                None => null(),
+            },
+            Source::Partition => int(meta.partition as i64),
+            Source::Offset => int(meta.offset),
+            // Kafka timestamps are milliseconds; columns hold Unix seconds
+            Source::Timestamp => match meta.timestamp {
+                Some(millis) => Value {
+                    value: Some(value::Value::TimestampValue(millis / 1000)),
+                },
+                None => null(),
+            },
+        };
+        values.insert(column.clone(), value);
+    }
+    Ok(values)
+}
+
+fn lookup<'a>(payload: &'a JsonValue, path: &[String]) -> Option<&'a JsonValue> {
+    path.iter().try_fold(payload, |current, segment| match current {
+        JsonValue::Object(fields) => fields.get(segment),
+        JsonValue::Array(items) => items.get(segment.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+/// Scalars keep their JSON type; objects and arrays are stored as JSON text
+pub fn json_to_value(field: &JsonValue) -> Value {
+    match field {
+        JsonValue::Null => null(),
+        JsonValue::Bool(b) => Value {
+            value: Some(value::Value::BoolValue(*b)),
+        },
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => int(i),
+            None => Value {
+                value: Some(value::Value::RealValue(n.as_f64().unwrap_or_default())),
+            },
+        },
+        JsonValue::String(s) => text(s.clone()),
+        nested => text(nested.to_string()),
+    }
+}
+
+fn int(i: i64) -> Value {
+    Value {
+        value: Some(value::Value::IntValue(i)),
+    }
+}
+
+fn text(s: String) -> Value {
+    Value {
+        value: Some(value::Value::TextValue(s)),
+    }
+}
+
+fn null() -> Value {
+    Value {
+        value: Some(value::Value::NullValue(true)),
+    }
+}
+
+/// Records read since the last commit
+#[derive(Default)]
+struct Batch {
+    rows: Vec<InsertRow>,
+    /// Next offset to commit for each partition
+    offsets: BTreeMap<i32, i64>,
+    /// Records that could not be mapped
+    skipped: usize,
+}
+
+impl Batch {
+    fn add(&mut self, message: &BorrowedMessage, mapping: &[(String, Source)]) {
+        self.offsets.insert(message.partition(), message.offset() + 1);
+
+        let meta = RecordMeta {
+            key: message.key(),
+            partition: message.partition(),
+            offset: message.offset(),
+            timestamp: message.timestamp().to_millis(),
+        };
+        let mapped = serde_json::from_slice::<JsonValue>(message.payload().unwrap_or_default())
+            .map_err(|e| format!("invalid JSON: {}", e))
+            .and_then(|payload| map_record(&payload, &meta, mapping));
+        match mapped {
+            Ok(values) => self.rows.push(InsertRow { values }),
+            Err(e) => {
+                tracing::warn!(partition = meta.partition, offset = meta.offset, error = %e, "Skipping record");
+                self.skipped += 1;
+            }
+        }
+    }
+}
+
+pub async fn run_kafka_sink(
+    server_address: String,
+    options: KafkaSinkOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if options.batch_size == 0 {
+        return Err(CliError::new(ErrorKind::InvalidInput, "--batch-size must be at least 1").into());
+    }
+    let mapping = options
+        .mapping
+        .iter()
+        .map(|(column, path)| Ok((column.clone(), Source::parse(path)?)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let mut client = client::connect(server_address).await?;
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &options.brokers)
+        .set("group.id", &options.group_id)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", if options.from_beginning { "earliest" } else { "latest" })
+        .create()
+        .map_err(|e| format!("Failed to create Kafka consumer: {}", e))?;
+    consumer
+        .subscribe(&[options.topic.as_str()])
+        .map_err(|e| format!("Failed to subscribe to '{}': {}", options.topic, e))?;
+
+    output::progress(&format!(
+        "Consuming '{}' from {} into '{}' (group '{}', batch size {})",
+        options.topic, options.brokers, options.table, options.group_id, options.batch_size
+    ));
+
+    let mut total_inserted = 0i64;
+    loop {
+        // Wait as long as it takes for the first record, then fill the batch
+        // until it is full or its timeout passes
+        let mut batch = Batch::default();
+        let first = consumer.recv().await?;
+        batch.add(&first, &mapping);
+        let deadline = Instant::now() + options.batch_timeout;
+        while batch.rows.len() + batch.skipped < options.batch_size {
+            match tokio::time::timeout_at(deadline, consumer.recv()).await {
+                Ok(message) => batch.add(&message?, &mapping),
+                Err(_) => break,
+            }
+        }
+
+        let (inserted, conflicts) = insert_batch(&mut client, &options, std::mem::take(&mut batch.rows)).await?;
+        total_inserted += inserted;
+
+        let mut offsets = TopicPartitionList::new();
+        for (partition, offset) in &batch.offsets {
+            offsets.add_partition_offset(&options.topic, *partition, Offset::Offset(*offset))?;
+        }
+        consumer
+            .commit(&offsets, CommitMode::Sync)
+            .map_err(|e| format!("Failed to commit offsets: {}", e))?;
+
+        tracing::info!(inserted, conflicts, skipped = batch.skipped, total_inserted, "Batch committed");
+    }
+}
+
+/// Insert one batch in a single transaction, retrying while the server is
+/// unreachable. Returns the inserted and conflicting row counts.
+async fn insert_batch(
+    client: &mut Client,
+    options: &KafkaSinkOptions,
+    rows: Vec<InsertRow>,
+) -> Result<(i64, i64), Box<dyn std::error::Error>> {
+    if rows.is_empty() {
+        return Ok((0, 0));
+    }
+    loop {
+        let request = BatchInsertRequest {
+            table_name: options.table.clone(),
+            rows: rows.clone(),
+            database: options.database.clone().unwrap_or_default(),
+            on_conflict: options.on_conflict as i32,
+        };
+        match client.batch_insert(request).await {
+            Ok(response) => {
+                let response = response.into_inner();
+                return Ok((response.inserted_count, response.skipped_count));
+            }
+            Err(status) if status.code() == tonic::Code::Unavailable => {
+                tracing::warn!(error = %status.message(), "Server unavailable, retrying batch");
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            // Offsets stay uncommitted, so the batch is consumed again on restart
+            Err(status) => return Err(format!("Batch insert failed: {}", status.message()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn meta() -> RecordMeta<'static> {
+        RecordMeta {
+            key: Some(b"sensor-1"),
+            partition: 2,
+            offset: 41,
+            timestamp: Some(1718445600123),
+        }
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!(parse_mapping("temp=payload.temp").unwrap(), ("temp".to_string(), "payload.temp".to_string()));
+        assert_eq!(Source::parse("readings.0").unwrap(), Source::Path(vec!["readings".into(), "0".into()]));
+        assert_eq!(Source::parse("$offset").unwrap(), Source::Offset);
+        assert!(parse_mapping("temp").is_err());
+        assert!(parse_mapping("temp=$headers").is_err());
+    }
+
+    #[test]
+    fn test_map_record() {
+        let payload = json!({"sensor": "t1", "data": {"temp": 21.5, "tags": ["a", "b"]}, "ok": true});
+
+        // Without a mapping every top-level field is a column
+        let values = map_record(&payload, &meta(), &[]).unwrap();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values["data"], text(r#"{"tags":["a","b"],"temp":21.5}"#.to_string()));
+        assert!(map_record(&json!([1, 2]), &meta(), &[]).is_err());
+
+        let mapping: Vec<(String, Source)> = [
+            ("temp", "data.temp"),
+            ("first_tag", "data.tags.0"),
+            ("missing", "data.humidity"),
+            ("device", "$key"),
+            ("kafka_offset", "$offset"),
+            ("received_at", "$timestamp"),
+        ]
+        .into_iter()
+        .map(|(column, path)| (column.to_string(), Source::parse(path).unwrap()))
+        .collect();
+        let values = map_record(&payload, &meta(), &mapping).unwrap();
+        assert_eq!(values["temp"].value, Some(value::Value::RealValue(21.5)));
+        assert_eq!(values["first_tag"], text("a".to_string()));
+        assert!(!values.contains_key("missing"));
+        assert_eq!(values["device"], text("sensor-1".to_string()));
+        assert_eq!(values["kafka_offset"], int(41));
+        assert_eq!(values["received_at"].value, Some(value::Value::TimestampValue(1718445600)));
+    }
+}
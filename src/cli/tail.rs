@@ -0,0 +1,193 @@
+//! `datasink tail`: print the rows added to a table as they arrive, like
+//! `tail -f`.
+//!
+//! The table is polled through the Query RPC: each poll selects the rows
+//! whose `--since-column` value is greater than that of the last row printed,
+//! in that column's order, so the column should only ever grow, like an
+//! INTEGER PRIMARY KEY or an insertion time. A row that arrives later with
+//! the same value as the last row printed is not shown, and rows where the
+//! column is NULL never are. The last `--lines` rows are printed first.
+//!
+//! Each poll's rows are printed as a table, as CSV under a single header, or
+//! with `-f json` (or `--json`) as one JSON object per line.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::StreamExt;
+
+use crate::cli::error::{CliError, ErrorKind};
+use crate::cli::{bulk, client, commands, output, render};
+use crate::db::error::UnknownColumn;
+use crate::proto::common::{Column, Value};
+use crate::proto::crud::{query_response, QueryRequest, QueryResponse};
+
+/// What `tail` follows and how it prints it
+#[derive(Debug, Clone)]
+pub struct TailOptions {
+    pub table: String,
+    /// Column whose growing values mark the new rows
+    pub since_column: String,
+    /// Rows already in the table to print first
+    pub lines: u32,
+    /// Time between polls
+    pub interval: Duration,
+    pub format: String,
+    pub database: String,
+}
+
+/// The SELECT for a poll of `table`: the rows after `since`, or with no
+/// `since` yet the last `lines` rows (at least one, to find where to start),
+/// or every row once the first poll found the table empty
+pub fn build_query(
+    table: &str,
+    column: &str,
+    since: Option<&Value>,
+    lines: Option<u32>,
+) -> (String, HashMap<String, Value>) {
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let (table, column) = (quote(table), quote(column));
+    match (since, lines) {
+        (Some(since), _) => (
+            format!("SELECT * FROM {} WHERE {} > :since ORDER BY {}", table, column, column),
+            HashMap::from([("since".to_string(), since.clone())]),
+        ),
+        (None, Some(lines)) => (
+            format!(
+                "SELECT * FROM (SELECT * FROM {} WHERE {} IS NOT NULL ORDER BY {} DESC LIMIT {}) ORDER BY {}",
+                table,
+                column,
+                column,
+                lines.max(1),
+                column
+            ),
+            HashMap::new(),
+        ),
+        (None, None) => (
+            format!("SELECT * FROM {} WHERE {} IS NOT NULL ORDER BY {}", table, column, column),
+            HashMap::new(),
+        ),
+    }
+}
+
+/// Poll the table every `options.interval` until interrupted, printing the
+/// rows that are new since the previous poll
+pub async fn run(server_address: String, options: TailOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let schema = bulk::fetch_schema(&mut client, &options.table, &options.database).await?;
+    if schema.is_empty() {
+        return Err(CliError::new(ErrorKind::NotFound, format!("Table '{}' not found", options.table)).into());
+    }
+    let names: Vec<String> = schema.into_iter().map(|column| column.name).collect();
+    let column = names.iter().find(|name| name.eq_ignore_ascii_case(&options.since_column)).ok_or_else(|| {
+        CliError::new(
+            ErrorKind::InvalidInput,
+            format!("Table '{}' has no column {}", options.table, UnknownColumn::new(&options.since_column, names.iter().map(String::as_str))),
+        )
+    })?;
+
+    let format = if output::enabled() { "json" } else { options.format.as_str() };
+    let mut since: Option<Value> = None;
+    let mut first_poll = true;
+    let mut header_printed = false;
+
+    loop {
+        let lines = first_poll.then_some(options.lines);
+        let (sql, parameters) = build_query(&options.table, column, since.as_ref(), lines);
+        let request = QueryRequest { sql, parameters, database: options.database.clone() };
+        let (columns, mut rows) = read_rows(client.query(request).await?.into_inner()).await?;
+
+        let position = columns.iter().position(|c| c.name.eq_ignore_ascii_case(column));
+        if let Some(last) = position.and_then(|i| rows.last().and_then(|row| row.get(i))) {
+            since = Some(last.clone());
+        }
+        // The first poll reads one row to start from even with --lines 0
+        if lines == Some(0) {
+            rows.clear();
+        }
+        first_poll = false;
+
+        if !rows.is_empty() {
+            match format {
+                "json" => {
+                    for row in rows {
+                        let mut json_row = serde_json::Map::new();
+                        for (col, value) in columns.iter().zip(row) {
+                            json_row.insert(col.name.clone(), render::json(value, Some(col)));
+                        }
+                        println!("{}", serde_json::Value::Object(json_row));
+                    }
+                }
+                "csv" => {
+                    if !header_printed {
+                        println!("{}", columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(","));
+                        header_printed = true;
+                    }
+                    for row in rows {
+                        let values: Vec<String> = columns
+                            .iter()
+                            .zip(row)
+                            .map(|(col, value)| render::text(value, Some(col), render::Format::Csv))
+                            .collect();
+                        println!("{}", values.join(","));
+                    }
+                }
+                _ => {
+                    let cells = commands::table_cells(&columns, rows, &render::ColumnView::default());
+                    commands::print_table(&columns, &cells, &Default::default());
+                }
+            }
+        }
+
+        tokio::time::sleep(options.interval).await;
+    }
+}
+
+/// The columns and rows of a Query response stream
+async fn read_rows(
+    mut stream: tonic::Streaming<QueryResponse>,
+) -> Result<(Vec<Column>, Vec<Vec<Value>>), Box<dyn std::error::Error>> {
+    let mut columns = Vec::new();
+    let mut rows = Vec::new();
+    while let Some(response) = stream.next().await {
+        match response? {
+            QueryResponse { response: Some(query_response::Response::ResultSet(result_set)) } => {
+                if !result_set.columns.is_empty() {
+                    columns = result_set.columns;
+                }
+                rows.extend(result_set.rows.into_iter().map(|row| row.values));
+            }
+            QueryResponse { response: Some(query_response::Response::Error(error)) } => {
+                return Err(CliError::query(&error).into());
+            }
+            _ => {}
+        }
+    }
+    Ok((columns, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::value;
+
+    #[test]
+    fn test_build_query() {
+        let (sql, parameters) = build_query("events", "id", None, Some(10));
+        assert_eq!(
+            sql,
+            "SELECT * FROM (SELECT * FROM \"events\" WHERE \"id\" IS NOT NULL ORDER BY \"id\" DESC LIMIT 10) ORDER BY \"id\""
+        );
+        assert!(parameters.is_empty());
+        assert!(build_query("events", "id", None, Some(0)).0.contains("LIMIT 1)"));
+        assert_eq!(
+            build_query("events", "id", None, None).0,
+            "SELECT * FROM \"events\" WHERE \"id\" IS NOT NULL ORDER BY \"id\""
+        );
+
+        let since = Value { value: Some(value::Value::IntValue(42)) };
+        let (sql, parameters) = build_query("my \"log\"", "created_at", Some(&since), None);
+        assert_eq!(sql, "SELECT * FROM \"my \"\"log\"\"\" WHERE \"created_at\" > :since ORDER BY \"created_at\"");
+        assert_eq!(parameters["since"], since);
+    }
+}
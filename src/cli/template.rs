@@ -0,0 +1,185 @@
+//! Query templates for `datasink query --var`.
+//!
+//! Each `{{name}}` in a query becomes a `:name` placeholder and the variable's
+//! value is sent as a bound parameter, so values are never spliced into the
+//! SQL. A list value (`--var ids=[1,2,3]`) expands to one placeholder per item
+//! (`:ids_1, :ids_2, :ids_3`) for use in `IN ({{ids}})`.
+
+use std::collections::HashMap;
+
+use serde_json::Value as JsonValue;
+
+use crate::cli::{commands, sink};
+use crate::proto::common::Value;
+
+/// A query with its template variables replaced by placeholders
+#[derive(Debug)]
+pub struct Rendered {
+    pub sql: String,
+    pub parameters: HashMap<String, Value>,
+}
+
+/// Replace every `{{name}}` outside comments with placeholders bound to the
+/// `NAME=VALUE` pairs in `vars`. Variables inside string literals or quoted
+/// identifiers are rejected, since a bound value cannot appear there.
+pub fn render(sql: &str, vars: &[(String, String)]) -> Result<Rendered, String> {
+    let values: HashMap<&str, Vec<Value>> = vars
+        .iter()
+        .map(|(name, value)| (name.as_str(), parse_var(value)))
+        .collect();
+    let lists: HashMap<&str, bool> = vars
+        .iter()
+        .map(|(name, value)| (name.as_str(), is_list(value)))
+        .collect();
+
+    let mut rendered = String::with_capacity(sql.len());
+    let mut parameters = HashMap::new();
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        let quoted = match c {
+            '\'' | '"' | '`' => Some(c),
+            '[' => Some(']'),
+            _ => None,
+        };
+        let skipped = if let Some(close) = quoted {
+            // Up to the closing quote; a doubled quote is an escaped quote
+            let mut end = rest.len();
+            let mut chars = rest.char_indices().skip(1).peekable();
+            while let Some((i, ch)) = chars.next() {
+                if ch == close {
+                    if close != ']' && chars.peek().map(|(_, next)| *next) == Some(close) {
+                        chars.next();
+                        continue;
+                    }
+                    end = i + 1;
+                    break;
+                }
+            }
+            let literal = &rest[..end];
+            if let Some(start) = literal.find("{{") {
+                let name = literal[start..].split("}}").next().unwrap_or_default();
+                return Err(format!(
+                    "Template variable {}}}}} is inside a quoted string; variables are bound as \
+                     parameters, so leave out the quotes",
+                    name
+                ));
+            }
+            end
+        } else if rest.starts_with("--") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            rest.find("*/").map(|i| i + 2).unwrap_or(rest.len())
+        } else if let Some(body) = rest.strip_prefix("{{") {
+            let end = body
+                .find("}}")
+                .ok_or_else(|| format!("Unclosed template variable in '{}'", rest))?;
+            let name = body[..end].trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Err(format!("Invalid template variable name '{}'", name));
+            }
+            let items = values
+                .get(name)
+                .ok_or_else(|| format!("No value for template variable '{}' (pass --var {}=VALUE)", name, name))?;
+            if lists[name] {
+                let placeholders: Vec<String> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let parameter = format!("{}_{}", name, i + 1);
+                        parameters.insert(parameter.clone(), item.clone());
+                        format!(":{}", parameter)
+                    })
+                    .collect();
+                rendered.push_str(&placeholders.join(", "));
+            } else {
+                parameters.insert(name.to_string(), items[0].clone());
+                rendered.push(':');
+                rendered.push_str(name);
+            }
+            rest = &body[end + 2..];
+            continue;
+        } else {
+            c.len_utf8()
+        };
+        rendered.push_str(&rest[..skipped]);
+        rest = &rest[skipped..];
+    }
+
+    Ok(Rendered { sql: rendered, parameters })
+}
+
+fn is_list(value: &str) -> bool {
+    let value = value.trim();
+    value.starts_with('[') && value.ends_with(']')
+}
+
+/// A variable's values: one for a scalar, one per item for a `[...]` list.
+/// Lists are read as JSON arrays when they parse as one, otherwise split on
+/// commas.
+fn parse_var(value: &str) -> Vec<Value> {
+    if !is_list(value) {
+        return vec![commands::parse_key_value(value)];
+    }
+    if let Ok(JsonValue::Array(items)) = serde_json::from_str::<JsonValue>(value) {
+        return items.iter().map(sink::json_to_value).collect();
+    }
+    let inner = value.trim();
+    let inner = &inner[1..inner.len() - 1];
+    if inner.trim().is_empty() {
+        return Vec::new();
+    }
+    inner.split(',').map(|item| commands::parse_key_value(item.trim())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::value;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_render_binds_variables() {
+        let rendered = render(
+            "SELECT * FROM users WHERE org = {{org}} AND age > {{ min_age }} OR owner = {{org}}",
+            &vars(&[("org", "acme'; DROP TABLE users; --"), ("min_age", "18")]),
+        )
+        .unwrap();
+        assert_eq!(rendered.sql, "SELECT * FROM users WHERE org = :org AND age > :min_age OR owner = :org");
+        assert_eq!(
+            rendered.parameters["org"].value,
+            Some(value::Value::TextValue("acme'; DROP TABLE users; --".to_string()))
+        );
+        assert_eq!(rendered.parameters["min_age"].value, Some(value::Value::IntValue(18)));
+
+        // Comments are left alone; quoted variables are refused
+        let rendered = render("SELECT 1 -- {{unused}}", &[]).unwrap();
+        assert_eq!(rendered.sql, "SELECT 1 -- {{unused}}");
+        assert!(render("SELECT * FROM t WHERE name = '{{name}}'", &vars(&[("name", "x")])).is_err());
+        assert!(render("SELECT {{missing}}", &[]).is_err());
+        assert!(render("SELECT {{bad name}}", &vars(&[("bad name", "x")])).is_err());
+    }
+
+    #[test]
+    fn test_render_expands_lists() {
+        let rendered = render(
+            "SELECT * FROM users WHERE id IN ({{ids}}) AND org IN ({{orgs}})",
+            &vars(&[("ids", "[1, 2, 3]"), ("orgs", r#"["a,b", "c"]"#)]),
+        )
+        .unwrap();
+        assert_eq!(
+            rendered.sql,
+            "SELECT * FROM users WHERE id IN (:ids_1, :ids_2, :ids_3) AND org IN (:orgs_1, :orgs_2)"
+        );
+        assert_eq!(rendered.parameters.len(), 5);
+        assert_eq!(rendered.parameters["ids_3"].value, Some(value::Value::IntValue(3)));
+        assert_eq!(rendered.parameters["orgs_1"].value, Some(value::Value::TextValue("a,b".to_string())));
+
+        let rendered = render("SELECT * FROM t WHERE code IN ({{codes}})", &vars(&[("codes", "[x, y]")])).unwrap();
+        assert_eq!(rendered.parameters["codes_2"].value, Some(value::Value::TextValue("y".to_string())));
+        let rendered = render("SELECT * FROM t WHERE id IN ({{ids}})", &vars(&[("ids", "[]")])).unwrap();
+        assert_eq!(rendered.sql, "SELECT * FROM t WHERE id IN ()");
+    }
+}
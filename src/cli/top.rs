@@ -0,0 +1,193 @@
+//! `datasink server top`: a live view of what the server is doing.
+//!
+//! Polls GetServerStatus and GetMetrics every interval and redraws the
+//! terminal with per-database request rates, active queries and pool
+//! connections, followed by the most recent slow queries. Rates are the
+//! change in each database's request counter between two polls. With --json
+//! each refresh prints one compact JSON line instead of redrawing.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tabled::builder::Builder as TableBuilder;
+use tabled::settings::Style;
+
+use crate::cli::{client, output};
+use crate::proto::admin::{Metric, MetricsRequest, ServerStatusRequest, ServerStatusResponse};
+
+/// Clear the screen and move the cursor home
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Longest SQL shown in the slow query table
+const MAX_SQL_WIDTH: usize = 80;
+
+/// Requests per second for each database from two readings of their
+/// request counters taken `elapsed` apart. Databases without an earlier
+/// reading, or whose counter went backwards (server restart), are left out.
+pub fn request_rates(
+    previous: &HashMap<String, i64>,
+    current: &HashMap<String, i64>,
+    elapsed: Duration,
+) -> HashMap<String, f64> {
+    let seconds = elapsed.as_secs_f64();
+    if seconds <= 0.0 {
+        return HashMap::new();
+    }
+    current
+        .iter()
+        .filter_map(|(name, requests)| {
+            let before = previous.get(name)?;
+            (requests >= before).then(|| (name.clone(), (requests - before) as f64 / seconds))
+        })
+        .collect()
+}
+
+fn render(status: &ServerStatusResponse, metrics: &[Metric], rates: &HashMap<String, f64>, interval: Duration) -> String {
+    let mut screen = format!(
+        "DataSink top - up {}s, {} database(s), refreshing every {}s (Ctrl-C to quit)\n",
+        status.uptime_seconds,
+        status.databases.len(),
+        interval.as_secs_f64()
+    );
+    let value = |name: &str| metrics.iter().find(|metric| metric.name == name).map(|metric| metric.value);
+    if let (Some(hits), Some(misses)) = (value("query_cache_hits_total"), value("query_cache_misses_total")) {
+        let lookups = hits + misses;
+        let ratio = if lookups > 0.0 { hits / lookups * 100.0 } else { 0.0 };
+        screen.push_str(&format!("Query cache: {} hits, {} misses ({:.1}% hit rate)\n", hits, misses, ratio));
+    }
+    screen.push('\n');
+
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Database", "QPS", "Active Queries", "Connections", "Idle", "Requests"]);
+    for db in &status.databases {
+        let qps = rates.get(&db.name).map(|rate| format!("{:.1}", rate)).unwrap_or_else(|| "-".to_string());
+        builder.push_record([
+            db.name.clone(),
+            qps,
+            db.active_queries.to_string(),
+            db.active_connections.to_string(),
+            db.idle_connections.to_string(),
+            db.requests_total.to_string(),
+        ]);
+    }
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    screen.push_str(&format!("{}\n\n", table));
+
+    if status.slow_queries.is_empty() {
+        screen.push_str("No slow queries\n");
+        return screen;
+    }
+    let mut builder = TableBuilder::default();
+    builder.push_record(["Finished", "Database", "Duration", "Request ID", "SQL", "Plan"]);
+    for query in &status.slow_queries {
+        let finished = chrono::DateTime::from_timestamp(query.finished_at, 0)
+            .map(|dt| dt.format("%H:%M:%S").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let mut sql = query.sql.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some((end, _)) = sql.char_indices().nth(MAX_SQL_WIDTH) {
+            sql.truncate(end);
+            sql.push_str("...");
+        }
+        builder.push_record([
+            finished,
+            query.database.clone(),
+            format!("{}ms", query.duration_ms),
+            query.request_id.clone(),
+            sql,
+            query.plan.join("\n"),
+        ]);
+    }
+    let mut table = builder.build();
+    table.with(Style::rounded());
+    screen.push_str(&format!("Slow queries (most recent first):\n{}\n", table));
+    screen
+}
+
+/// One refresh as a single-line JSON document
+fn snapshot_json(status: &ServerStatusResponse, rates: &HashMap<String, f64>) -> serde_json::Value {
+    let databases: Vec<serde_json::Value> = status
+        .databases
+        .iter()
+        .map(|db| {
+            serde_json::json!({
+                "name": db.name,
+                "qps": rates.get(&db.name),
+                "active_queries": db.active_queries,
+                "active_connections": db.active_connections,
+                "idle_connections": db.idle_connections,
+                "requests_total": db.requests_total,
+            })
+        })
+        .collect();
+    let slow_queries: Vec<serde_json::Value> = status
+        .slow_queries
+        .iter()
+        .map(|query| {
+            serde_json::json!({
+                "database": query.database,
+                "sql": query.sql,
+                "duration_ms": query.duration_ms,
+                "finished_at": query.finished_at,
+                "request_id": query.request_id,
+                "plan": query.plan,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "uptime_seconds": status.uptime_seconds,
+        "databases": databases,
+        "slow_queries": slow_queries,
+    })
+}
+
+/// Redraw the view every `interval` until interrupted, or `count` times
+pub async fn run(server_address: String, interval: Duration, count: Option<u64>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client::connect(server_address).await?;
+    let mut previous: Option<(Instant, HashMap<String, i64>)> = None;
+    let mut refreshes = 0;
+
+    loop {
+        let status = client.get_server_status(ServerStatusRequest {}).await?.into_inner();
+        let metrics = client.get_metrics(MetricsRequest {}).await?.into_inner().metrics;
+        let now = Instant::now();
+
+        let requests: HashMap<String, i64> =
+            status.databases.iter().map(|db| (db.name.clone(), db.requests_total)).collect();
+        let rates = match &previous {
+            Some((at, before)) => request_rates(before, &requests, now.duration_since(*at)),
+            None => HashMap::new(),
+        };
+        previous = Some((now, requests));
+
+        if output::enabled() {
+            println!("{}", snapshot_json(&status, &rates));
+        } else {
+            print!("{}{}", CLEAR_SCREEN, render(&status, &metrics, &rates, interval));
+        }
+
+        refreshes += 1;
+        if count.is_some_and(|count| refreshes >= count) {
+            return Ok(());
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_rates() {
+        let previous = HashMap::from([("default".to_string(), 100), ("shop".to_string(), 50)]);
+        let current = HashMap::from([
+            ("default".to_string(), 120),
+            ("shop".to_string(), 10),
+            ("new".to_string(), 5),
+        ]);
+        let rates = request_rates(&previous, &current, Duration::from_secs(2));
+        assert_eq!(rates, HashMap::from([("default".to_string(), 10.0)]));
+        assert!(request_rates(&previous, &current, Duration::ZERO).is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+//! `datasink tx`: run a document of writes in one server-side transaction.
+//!
+//! The document is a JSON array of operations or one operation per line
+//! (NDJSON):
+//!
+//! ```text
+//! {"op": "insert", "table": "accounts", "values": {"id": 3, "balance": 0}}
+//! {"op": "update", "table": "accounts", "values": {"balance": 50}, "where": "id = 1"}
+//! {"op": "delete", "table": "pending", "where": "account_id = 3"}
+//! ```
+//!
+//! Inserts take an optional `"on_conflict": "ignore"`. Updates and deletes
+//! must give `where`, so a missing clause cannot touch every row by accident.
+
+use serde_json::Value as JsonValue;
+
+use crate::cli::{bulk, client, output, read_input, sink};
+use crate::proto::crud::{
+    write_operation, DeleteRequest, InsertRequest, OnConflict, TransactionRequest, UpdateRequest, WriteOperation,
+};
+
+/// Read the operations from `path`, or stdin for `-` or no path
+pub fn read_operations(path: Option<&str>) -> Result<Vec<WriteOperation>, String> {
+    parse_operations(&read_input(path.unwrap_or("-"))?)
+}
+
+/// Parse a JSON array of operations, or a sequence of JSON operations such
+/// as NDJSON
+pub fn parse_operations(text: &str) -> Result<Vec<WriteOperation>, String> {
+    let operations = bulk::json_documents(text)?;
+    if operations.is_empty() {
+        return Err("No operations given".to_string());
+    }
+
+    operations
+        .iter()
+        .enumerate()
+        .map(|(i, operation)| parse_operation(operation).map_err(|e| format!("Operation {}: {}", i + 1, e)))
+        .collect()
+}
+
+fn parse_operation(operation: &JsonValue) -> Result<WriteOperation, String> {
+    let fields = operation.as_object().ok_or("expected a JSON object")?;
+    let string = |key: &str| -> Result<Option<String>, String> {
+        match fields.get(key) {
+            None => Ok(None),
+            Some(JsonValue::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(format!("'{}' must be a string", key)),
+        }
+    };
+    let op = string("op")?.ok_or("missing 'op' (insert, update or delete)")?;
+    let table_name = string("table")?.ok_or("missing 'table'")?;
+    let where_clause = || string("where")?.ok_or_else(|| format!("{} needs a 'where' clause", op));
+    let values = || -> Result<_, String> {
+        let values = fields
+            .get("values")
+            .and_then(JsonValue::as_object)
+            .ok_or_else(|| format!("{} needs a 'values' object", op))?;
+        Ok(values.iter().map(|(column, value)| (column.clone(), sink::json_to_value(value))).collect())
+    };
+
+    let operation = match op.as_str() {
+        "insert" => {
+            let on_conflict = match string("on_conflict")?.as_deref() {
+                None | Some("abort") => OnConflict::Abort,
+                Some("ignore") => OnConflict::Ignore,
+                Some(other) => return Err(format!("unknown on_conflict '{}' (expected abort or ignore)", other)),
+            };
+            write_operation::Operation::Insert(InsertRequest {
+                table_name,
+                values: values()?,
+                database: String::new(),
+                on_conflict: on_conflict as i32,
+            })
+        }
+        "update" => write_operation::Operation::Update(UpdateRequest {
+            table_name,
+            values: values()?,
+            where_clause: where_clause()?,
+            database: String::new(),
+        }),
+        "delete" => write_operation::Operation::Delete(DeleteRequest {
+            table_name,
+            where_clause: where_clause()?,
+            database: String::new(),
+        }),
+        other => return Err(format!("unknown op '{}' (expected insert, update or delete)", other)),
+    };
+    Ok(WriteOperation {
+        operation: Some(operation),
+    })
+}
+
+pub async fn run_transaction(
+    server_address: String,
+    path: Option<String>,
+    database: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let operations = read_operations(path.as_deref())?;
+    let descriptions: Vec<String> = operations.iter().map(describe).collect();
+
+    let mut client = client::connect(server_address).await?;
+    let request = TransactionRequest {
+        operations,
+        database: database.unwrap_or_default(),
+    };
+    let response = client.transaction(request).await?.into_inner();
+
+    if output::enabled() {
+        let results: Vec<serde_json::Value> = descriptions
+            .iter()
+            .zip(&response.results)
+            .map(|(description, result)| {
+                serde_json::json!({
+                    "operation": description,
+                    "affected_rows": result.affected_rows,
+                    "inserted_id": (result.inserted_id >= 0).then_some(result.inserted_id),
+                })
+            })
+            .collect();
+        output::print(&serde_json::json!({
+            "success": true,
+            "message": response.message,
+            "results": results,
+        }));
+        return Ok(());
+    }
+
+    for (description, result) in descriptions.iter().zip(&response.results) {
+        if result.inserted_id >= 0 {
+            println!("{}: inserted row {}", description, result.inserted_id);
+        } else {
+            println!("{}: {} rows", description, result.affected_rows);
+        }
+    }
+    println!("{}", response.message);
+
+    Ok(())
+}
+
+fn describe(operation: &WriteOperation) -> String {
+    match &operation.operation {
+        Some(write_operation::Operation::Insert(insert)) => format!("insert into {}", insert.table_name),
+        Some(write_operation::Operation::Update(update)) => format!("update {}", update.table_name),
+        Some(write_operation::Operation::Delete(delete)) => format!("delete from {}", delete.table_name),
+        None => "empty operation".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::common::value;
+
+    #[test]
+    fn test_parse_operations() {
+        let ndjson = r#"
+            {"op": "insert", "table": "accounts", "values": {"id": 3, "owner": "carol", "tags": ["a"]}, "on_conflict": "ignore"}
+            {"op": "update", "table": "accounts", "values": {"balance": 12.5}, "where": "id = 1"}
+            {"op": "delete", "table": "pending", "where": "account_id = 3"}
+        "#;
+        let operations = parse_operations(ndjson).unwrap();
+        assert_eq!(operations.len(), 3);
+        match &operations[0].operation {
+            Some(write_operation::Operation::Insert(insert)) => {
+                assert_eq!(insert.table_name, "accounts");
+                assert_eq!(insert.on_conflict, OnConflict::Ignore as i32);
+                assert_eq!(insert.values["id"].value, Some(value::Value::IntValue(3)));
+                assert_eq!(insert.values["tags"].value, Some(value::Value::TextValue(r#"["a"]"#.to_string())));
+            }
+            other => panic!("expected an insert, got {:?}", other),
+        }
+        assert!(matches!(
+            &operations[2].operation,
+            Some(write_operation::Operation::Delete(delete)) if delete.where_clause == "account_id = 3"
+        ));
+
+        // A JSON array holds the same operations
+        let array = r#"[{"op": "delete", "table": "pending", "where": "1 = 1"}]"#;
+        assert_eq!(parse_operations(array).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_operations_errors() {
+        let missing_where = r#"{"op": "delete", "table": "pending"}"#;
+        assert_eq!(parse_operations(missing_where).unwrap_err(), "Operation 1: delete needs a 'where' clause");
+        let unknown = "{\"op\": \"insert\", \"table\": \"t\", \"values\": {}}\n{\"op\": \"upsert\", \"table\": \"t\"}";
+        assert!(parse_operations(unknown).unwrap_err().starts_with("Operation 2: unknown op 'upsert'"));
+        assert!(parse_operations("[]").is_err());
+        assert!(parse_operations("{\"op\": ").is_err());
+    }
+}
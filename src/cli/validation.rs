@@ -1,12 +1,11 @@
-/// Database URL validation utilities
+//! Database URL validation utilities
 
 /// Validate and normalize a database URL
 pub fn validate_database_url(url: &str) -> Result<String, String> {
     // Check if it's already a proper URL with scheme
     if url.starts_with("sqlite://") || url.starts_with("postgres://") || url.starts_with("mysql://") {
         // Ensure SQLite URLs have proper format
-        if url.starts_with("sqlite://") {
-            let path = &url[9..]; // Skip "sqlite://"
+        if let Some(path) = url.strip_prefix("sqlite://") {
             if path.is_empty() {
                 return Err("SQLite URL must specify a database file path".to_string());
             }
@@ -31,6 +30,7 @@ pub fn validate_database_url(url: &str) -> Result<String, String> {
 }
 
 /// Check if a database URL is valid
+#[allow(dead_code)]
 pub fn is_valid_database_url(url: &str) -> bool {
     validate_database_url(url).is_ok()
 }
@@ -0,0 +1,161 @@
+//! gzip and zstd compression of data files.
+//!
+//! Files ending in `.gz` or `.zst` are decompressed as they are read by
+//! `import`, `insert --file`, `tx` and schema seed data, so dumps can be loaded without
+//! unpacking them first. `datasink export --compress` writes them. The
+//! compression extension is ignored when a file's format is taken from its
+//! name, so `users.csv.gz` is read as CSV.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression a file name implies by its extension
+    pub fn for_path(path: &str) -> Self {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".gz") {
+            Compression::Gzip
+        } else if lower.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// The file extension this compression uses, with its dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression '{}' (expected gzip, zstd or none)", s)),
+        }
+    }
+}
+
+/// A file name without its compression extension (`users.csv.gz` gives
+/// `users.csv`)
+pub fn strip_extension(path: &str) -> &str {
+    let extension = Compression::for_path(path).extension();
+    &path[..path.len() - extension.len()]
+}
+
+/// Open a file for reading, decompressing it if its extension says so
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    decoder(File::open(path)?, Compression::for_path(&path.to_string_lossy()))
+}
+
+/// Decompress what `inner` reads
+pub fn decoder(inner: impl Read + 'static, compression: Compression) -> io::Result<Box<dyn BufRead>> {
+    Ok(match compression {
+        Compression::None => Box::new(BufReader::new(inner)),
+        // MultiGzDecoder reads every member, as gunzip does with concatenated files
+        Compression::Gzip => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(inner))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::new(inner)?)),
+    })
+}
+
+/// Read a whole file as text, decompressing it if its extension says so
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// A writer compressing what is written to it. Call [`Encoder::finish`]
+/// once done: dropping it without finishing leaves the output truncated.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::None(inner),
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(inner, flate2::Compression::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(inner, 0)?),
+        })
+    }
+
+    /// Write the end of the compressed stream and flush it
+    pub fn finish(self) -> io::Result<W> {
+        let mut inner = match self {
+            Encoder::None(inner) => inner,
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(inner) => inner.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(inner) => inner.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_for_path() {
+        assert_eq!(Compression::for_path("users.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::for_path("events.NDJSON.ZST"), Compression::Zstd);
+        assert_eq!(Compression::for_path("users.csv"), Compression::None);
+        assert_eq!(strip_extension("users.csv.gz"), "users.csv");
+        assert_eq!(strip_extension("events.ndjson.zst"), "events.ndjson");
+        assert_eq!(strip_extension("users.json"), "users.json");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let text = "{\"id\": 1}\n{\"id\": 2}\n".repeat(100);
+        for name in ["rows.ndjson", "rows.ndjson.gz", "rows.ndjson.zst"] {
+            let path = dir.path().join(name);
+            let mut encoder = Encoder::new(File::create(&path).unwrap(), Compression::for_path(name)).unwrap();
+            encoder.write_all(text.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+            assert_eq!(read_to_string(&path).unwrap(), text, "{}", name);
+        }
+        let raw = std::fs::read(dir.path().join("rows.ndjson.gz")).unwrap();
+        assert_eq!(&raw[..2], &[0x1f, 0x8b]);
+    }
+}
@@ -0,0 +1,451 @@
+//! Index advisor.
+//!
+//! The server counts the queries each database runs, grouped by their text
+//! with literals replaced (see [`normalize_query`]). For the frequent ones
+//! whose plan scans a whole table, the columns they filter or join that
+//! table on become a candidate index: equality columns first, then one
+//! range column. A candidate is only suggested if SQLite's plan for the
+//! query actually uses it once it exists, which is checked by building the
+//! index in a transaction that is rolled back.
+
+use std::collections::HashMap;
+
+use super::error::Result;
+use super::Database;
+
+/// Most columns in a suggested index
+const MAX_INDEX_COLUMNS: usize = 4;
+
+/// A query shape and how often the database ran it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryPattern {
+    /// One query of this shape, as it was run
+    pub sql: String,
+    pub count: u64,
+}
+
+/// An index that would let frequent queries search a table instead of scanning it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSuggestion {
+    pub table: String,
+    pub columns: Vec<String>,
+    /// CREATE INDEX statement for the index
+    pub sql: String,
+    /// Queries run that scan the table and would use the index
+    pub query_count: u64,
+    /// The most frequent of those queries
+    pub example_sql: String,
+    /// EXPLAIN QUERY PLAN of the example query, without and with the index
+    pub plan_before: Vec<String>,
+    pub plan_after: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// Keyword or identifier, unquoted; `o.user_id` is one word
+    Word(String),
+    /// Number or string literal
+    Literal(String),
+    /// `?`, `?1`, `:name`, `@name` or `$name`
+    Param(String),
+    /// Operator or punctuation
+    Symbol(String),
+}
+
+/// Split SQL into tokens, dropping comments
+fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '-' && chars.get(i + 1) == Some(&'-') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' {
+            let (text, end) = quoted(&chars, i, '\'');
+            tokens.push(Token::Literal(format!("'{}'", text)));
+            i = end;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit)) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push(Token::Literal(chars[start..i].iter().collect()));
+        } else if matches!(c, '?' | ':' | '@' | '$') {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Param(chars[start..i].iter().collect()));
+        } else if c.is_alphabetic() || c == '_' || matches!(c, '"' | '`' | '[') {
+            // Dotted names ("main"."orders", o.user_id) are one word
+            let mut word = String::new();
+            loop {
+                match chars.get(i) {
+                    Some('"') | Some('`') => {
+                        let (text, end) = quoted(&chars, i, chars[i]);
+                        word.push_str(&text);
+                        i = end;
+                    }
+                    Some('[') => {
+                        let (text, end) = quoted(&chars, i, ']');
+                        word.push_str(&text);
+                        i = end;
+                    }
+                    _ => {
+                        while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                            word.push(chars[i]);
+                            i += 1;
+                        }
+                    }
+                }
+                if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(|n| !n.is_whitespace()) {
+                    word.push('.');
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Word(word));
+        } else if matches!(c, '=' | '<' | '>' | '!' | '|') {
+            let start = i;
+            while i < chars.len() && matches!(chars[i], '=' | '<' | '>' | '!' | '|') {
+                i += 1;
+            }
+            tokens.push(Token::Symbol(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(Token::Symbol(c.to_string()));
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Text of a quoted token starting at `start`, with doubled closing quotes
+/// unescaped, and the index after it
+fn quoted(chars: &[char], start: usize, close: char) -> (String, usize) {
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == close {
+            if chars.get(i + 1) == Some(&close) && close != ']' {
+                text.push(close);
+                i += 2;
+                continue;
+            }
+            return (text, i + 1);
+        }
+        text.push(chars[i]);
+        i += 1;
+    }
+    (text, i)
+}
+
+/// The query with literals replaced by `?` and whitespace and comments
+/// collapsed, so queries differing only in their values group together
+pub fn normalize_query(sql: &str) -> String {
+    let tokens: Vec<String> = tokenize(sql)
+        .into_iter()
+        .map(|token| match token {
+            Token::Literal(_) => "?".to_string(),
+            Token::Word(word) | Token::Param(word) | Token::Symbol(word) => word,
+        })
+        .collect();
+    tokens.join(" ")
+}
+
+/// Keywords that end a FROM, WHERE or ON clause
+fn is_clause_end(word: &str) -> bool {
+    matches!(
+        word.to_lowercase().as_str(),
+        "where" | "group" | "order" | "limit" | "having" | "join" | "inner" | "left" | "right" | "full"
+            | "cross" | "natural" | "on" | "using" | "union" | "except" | "intersect" | "window" | "returning"
+            | "set" | "select" | "values"
+    )
+}
+
+/// Words that can sit next to a comparison without being a column
+fn is_keyword(word: &str) -> bool {
+    matches!(
+        word.to_lowercase().as_str(),
+        "and" | "or" | "not" | "null" | "is" | "in" | "like" | "glob" | "between" | "exists" | "case"
+            | "when" | "then" | "else" | "end" | "true" | "false" | "current_timestamp" | "current_date"
+            | "current_time" | "select" | "distinct" | "all" | "any"
+    )
+}
+
+/// A column a query compares, as written (`o.user_id` or `status`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ColumnFilter {
+    qualifier: Option<String>,
+    column: String,
+    /// Compared with `=`, `IN` or `IS`, rather than a range
+    equality: bool,
+}
+
+/// Tables named after FROM, JOIN, UPDATE or DELETE FROM, with the name the
+/// query refers to each by (its alias, or its own name)
+fn table_references(tokens: &[Token]) -> Vec<(String, String)> {
+    let mut tables = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let Token::Word(keyword) = &tokens[i] else {
+            i += 1;
+            continue;
+        };
+        let keyword = keyword.to_lowercase();
+        if !matches!(keyword.as_str(), "from" | "join" | "update") {
+            i += 1;
+            continue;
+        }
+        i += 1;
+        while let Some(Token::Word(name)) = tokens.get(i) {
+            if is_clause_end(name) {
+                break;
+            }
+            let table = name.rsplit('.').next().unwrap_or(name).to_string();
+            i += 1;
+            if matches!(tokens.get(i), Some(Token::Word(w)) if w.eq_ignore_ascii_case("as")) {
+                i += 1;
+            }
+            let alias = match tokens.get(i) {
+                Some(Token::Word(alias)) if !is_clause_end(alias) => {
+                    i += 1;
+                    alias.clone()
+                }
+                _ => table.clone(),
+            };
+            tables.push((table, alias));
+            // Only FROM takes a comma-separated list
+            if keyword == "from" && tokens.get(i) == Some(&Token::Symbol(",".to_string())) {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+    }
+    tables
+}
+
+/// Columns compared in WHERE and ON clauses
+fn filtered_columns(tokens: &[Token]) -> Vec<ColumnFilter> {
+    let comparison = |token: Option<&Token>| -> Option<bool> {
+        match token? {
+            Token::Symbol(op) if matches!(op.as_str(), "=" | "==") => Some(true),
+            Token::Symbol(op) if matches!(op.as_str(), "<" | "<=" | ">" | ">=") => Some(false),
+            Token::Word(word) if word.eq_ignore_ascii_case("in") || word.eq_ignore_ascii_case("is") => Some(true),
+            Token::Word(word) if word.eq_ignore_ascii_case("between") => Some(false),
+            _ => None,
+        }
+    };
+
+    let mut columns: Vec<ColumnFilter> = Vec::new();
+    let mut in_filter = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let Token::Word(word) = token else { continue };
+        if word.eq_ignore_ascii_case("where") || word.eq_ignore_ascii_case("on") {
+            in_filter = true;
+            continue;
+        }
+        if is_clause_end(word) {
+            in_filter = false;
+            continue;
+        }
+        if !in_filter || is_keyword(word) || tokens.get(i + 1) == Some(&Token::Symbol("(".to_string())) {
+            continue;
+        }
+        let equality = comparison(tokens.get(i + 1)).or_else(|| comparison(i.checked_sub(1).and_then(|p| tokens.get(p))));
+        let Some(equality) = equality else { continue };
+        let (qualifier, column) = match word.rsplit_once('.') {
+            Some((qualifier, column)) => (Some(qualifier.rsplit('.').next().unwrap_or(qualifier).to_string()), column),
+            None => (None, word.as_str()),
+        };
+        let filter = ColumnFilter { qualifier, column: column.to_string(), equality };
+        if !columns.contains(&filter) {
+            columns.push(filter);
+        }
+    }
+    columns
+}
+
+/// Names (aliases or table names) the plan reads with a full table scan
+fn scanned(plan: &[String]) -> Vec<String> {
+    plan.iter()
+        .filter(|line| !line.contains(" USING "))
+        .filter_map(|line| line.strip_prefix("SCAN "))
+        .map(|rest| rest.split_whitespace().next().unwrap_or(rest).to_string())
+        .collect()
+}
+
+/// Quote an identifier for SQLite
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Index name and CREATE INDEX statement for columns of a table
+fn index_sql(table: &str, columns: &[String]) -> (String, String) {
+    let name = format!("idx_{}_{}", table, columns.join("_")).to_lowercase();
+    let columns: Vec<String> = columns.iter().map(|column| quote(column)).collect();
+    let sql = format!("CREATE INDEX IF NOT EXISTS {} ON {} ({})", quote(&name), quote(table), columns.join(", "));
+    (name, sql)
+}
+
+/// The candidate index for each table a query scans: its filtered
+/// columns, equality comparisons first, then one range comparison
+async fn candidates(db: &dyn Database, sql: &str, plan: &[String]) -> Result<Vec<(String, Vec<String>)>> {
+    let tokens = tokenize(sql);
+    let tables = table_references(&tokens);
+    let filters = filtered_columns(&tokens);
+    let mut candidates = Vec::new();
+    for name in scanned(plan) {
+        let Some((table, alias)) = tables.iter().find(|(table, alias)| *alias == name || *table == name) else {
+            continue;
+        };
+        let table_columns: Vec<String> = match db.table_schema(table).await {
+            Ok(columns) => columns.into_iter().map(|(column, _)| column.to_lowercase()).collect(),
+            // A CTE or view rather than a table
+            Err(_) => continue,
+        };
+        let on_table = |filter: &&ColumnFilter| {
+            let named = match &filter.qualifier {
+                Some(qualifier) => qualifier.eq_ignore_ascii_case(alias) || qualifier.eq_ignore_ascii_case(table),
+                // Unqualified columns belong to the table when only one table is read
+                None => tables.len() == 1,
+            };
+            named && table_columns.contains(&filter.column.to_lowercase())
+        };
+        let mut columns: Vec<String> = Vec::new();
+        for filter in filters.iter().filter(&on_table).filter(|f| f.equality) {
+            if !columns.contains(&filter.column) {
+                columns.push(filter.column.clone());
+            }
+        }
+        if let Some(range) = filters.iter().filter(&on_table).find(|f| !f.equality && !columns.contains(&f.column)) {
+            columns.push(range.column.clone());
+        }
+        columns.truncate(MAX_INDEX_COLUMNS);
+        if !columns.is_empty() {
+            candidates.push((table.clone(), columns));
+        }
+    }
+    Ok(candidates)
+}
+
+/// Suggest indexes for the given query patterns, most useful first. Queries
+/// the database cannot plan (e.g. ones naming dropped tables) are skipped.
+pub async fn advise(db: &dyn Database, patterns: &[QueryPattern]) -> Result<Vec<IndexSuggestion>> {
+    // (table, columns) -> (queries helped, most frequent pattern)
+    let mut grouped: HashMap<(String, Vec<String>), (u64, &QueryPattern)> = HashMap::new();
+    for pattern in patterns {
+        let Ok(plan) = db.query_plan(&pattern.sql).await else { continue };
+        for candidate in candidates(db, &pattern.sql, &plan).await? {
+            let entry = grouped.entry(candidate).or_insert((0, pattern));
+            entry.0 += pattern.count;
+            if pattern.count > entry.1.count {
+                entry.1 = pattern;
+            }
+        }
+    }
+
+    let mut suggestions = Vec::new();
+    for ((table, columns), (query_count, example)) in grouped {
+        let (name, sql) = index_sql(&table, &columns);
+        let plan_before = db.query_plan(&example.sql).await?;
+        let plan_after = db.query_plan_with_index(&example.sql, &sql).await?;
+        // SQLite may prefer the scan, e.g. when the first column has few distinct values
+        if !plan_after.iter().any(|line| line.contains(&format!("INDEX {}", name))) {
+            continue;
+        }
+        suggestions.push(IndexSuggestion {
+            table,
+            columns,
+            sql,
+            query_count,
+            example_sql: example.sql.clone(),
+            plan_before,
+            plan_after,
+        });
+    }
+    suggestions.sort_by(|a, b| b.query_count.cmp(&a.query_count).then_with(|| a.sql.cmp(&b.sql)));
+    Ok(suggestions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(qualifier: Option<&str>, column: &str, equality: bool) -> ColumnFilter {
+        ColumnFilter { qualifier: qualifier.map(str::to_string), column: column.to_string(), equality }
+    }
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(
+            normalize_query("SELECT *  FROM users\n WHERE id = 42 AND name = 'O''Brien' -- lookup"),
+            "SELECT * FROM users WHERE id = ? AND name = ?"
+        );
+        assert_eq!(normalize_query("select * from t where x=1.5"), normalize_query("select * from t where x = 7"));
+        assert_ne!(normalize_query("SELECT * FROM t WHERE a = 1"), normalize_query("SELECT * FROM t WHERE b = 1"));
+    }
+
+    #[test]
+    fn test_table_references() {
+        let tokens = tokenize("SELECT * FROM orders o JOIN \"users\" AS u ON u.id = o.user_id WHERE 1");
+        assert_eq!(
+            table_references(&tokens),
+            vec![("orders".to_string(), "o".to_string()), ("users".to_string(), "u".to_string())]
+        );
+        let tokens = tokenize("SELECT * FROM a, b x WHERE a.id = x.id");
+        assert_eq!(
+            table_references(&tokens),
+            vec![("a".to_string(), "a".to_string()), ("b".to_string(), "x".to_string())]
+        );
+        assert_eq!(table_references(&tokenize("UPDATE t SET a = 1 WHERE b = 2")), vec![("t".to_string(), "t".to_string())]);
+    }
+
+    #[test]
+    fn test_filtered_columns() {
+        let tokens = tokenize(
+            "SELECT * FROM orders o JOIN users u ON u.id = o.user_id \
+             WHERE o.status IN ('paid', 'sent') AND :min <= o.total AND lower(u.email) = ? ORDER BY o.created",
+        );
+        assert_eq!(
+            filtered_columns(&tokens),
+            vec![
+                column(Some("u"), "id", true),
+                column(Some("o"), "user_id", true),
+                column(Some("o"), "status", true),
+                column(Some("o"), "total", false),
+            ]
+        );
+        assert!(filtered_columns(&tokenize("SELECT a = 1 FROM t")).is_empty());
+    }
+
+    #[test]
+    fn test_scanned() {
+        let plan = vec![
+            "SCAN o".to_string(),
+            "SEARCH u USING INTEGER PRIMARY KEY (rowid=?)".to_string(),
+            "SCAN items USING COVERING INDEX items_name".to_string(),
+        ];
+        assert_eq!(scanned(&plan), vec!["o"]);
+    }
+
+    #[test]
+    fn test_index_sql() {
+        let (name, sql) = index_sql("Orders", &["status".to_string(), "total".to_string()]);
+        assert_eq!(name, "idx_orders_status_total");
+        assert_eq!(sql, "CREATE INDEX IF NOT EXISTS \"idx_orders_status_total\" ON \"Orders\" (\"status\", \"total\")");
+    }
+}
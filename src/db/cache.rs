@@ -0,0 +1,425 @@
+//! Opt-in LRU cache for SELECT results
+//!
+//! Entries are keyed by (database, SQL, parameters) and remember the tables the
+//! query depends on, so a write to any of those tables drops them. Views are
+//! resolved to the tables behind them, and a table whose triggers mention one
+//! of a query's tables counts as one the query depends on.
+
+use lru::LruCache;
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::traits::{DbValue, QueryResult};
+
+/// Results with more rows than this are streamed but never cached
+pub const DEFAULT_MAX_CACHED_ROWS: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    database: String,
+    sql: String,
+    params: String,
+}
+
+impl CacheKey {
+    pub fn new(database: &str, sql: &str, params: &HashMap<String, DbValue>) -> Self {
+        // Sort parameters so the key doesn't depend on HashMap iteration order
+        let sorted: BTreeMap<_, _> = params.iter().collect();
+        Self {
+            database: database.to_string(),
+            sql: sql.trim().to_string(),
+            params: format!("{:?}", sorted),
+        }
+    }
+}
+
+struct CacheEntry {
+    result: Arc<QueryResult>,
+    tables: Vec<String>,
+}
+
+struct CacheState {
+    entries: LruCache<CacheKey, CacheEntry>,
+    /// Bumped on every invalidation, so results computed before a write are not cached
+    generations: HashMap<String, u64>,
+}
+
+/// Snapshot of the cache counters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub invalidations: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+pub struct QueryCache {
+    state: Mutex<CacheState>,
+    capacity: usize,
+    max_rows: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+impl QueryCache {
+    /// Create a cache holding up to `capacity` results
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            state: Mutex::new(CacheState {
+                entries: LruCache::new(NonZeroUsize::new(capacity).unwrap()),
+                generations: HashMap::new(),
+            }),
+            capacity,
+            max_rows: DEFAULT_MAX_CACHED_ROWS,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Largest result, in rows, that will be cached
+    pub fn max_rows(&self) -> usize {
+        self.max_rows
+    }
+
+    /// Look up a cached result, counting the hit or miss
+    pub fn get(&self, key: &CacheKey) -> Option<Arc<QueryResult>> {
+        let mut state = self.state.lock().unwrap();
+        match state.entries.get(key) {
+            Some(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.result.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Current invalidation generation of a database; pass it back to `insert`
+    pub fn generation(&self, database: &str) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.generations.get(database).copied().unwrap_or(0)
+    }
+
+    /// Cache a result, dropped by writes to any of `tables` (see
+    /// [`dependent_tables`]), unless the database was written to since
+    /// `generation` was read or the result is too large
+    pub fn insert(&self, key: CacheKey, result: QueryResult, tables: Vec<String>, generation: u64) {
+        if result.rows.len() > self.max_rows {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.generations.get(&key.database).copied().unwrap_or(0) != generation {
+            return;
+        }
+
+        state.entries.put(key, CacheEntry { result: Arc::new(result), tables });
+    }
+
+    /// Drop cached results that read from the given table
+    pub fn invalidate_table(&self, database: &str, table: &str) {
+        let table = normalize_identifier(table);
+        self.invalidate_where(database, |entry| entry.tables.contains(&table));
+    }
+
+    /// Drop every cached result for a database
+    pub fn invalidate_database(&self, database: &str) {
+        self.invalidate_where(database, |_| true);
+    }
+
+    fn invalidate_where(&self, database: &str, matches: impl Fn(&CacheEntry) -> bool) {
+        let mut state = self.state.lock().unwrap();
+        *state.generations.entry(database.to_string()).or_insert(0) += 1;
+
+        let stale: Vec<CacheKey> = state
+            .entries
+            .iter()
+            .filter(|(key, entry)| key.database == database && matches(entry))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &stale {
+            state.entries.pop(key);
+        }
+        self.invalidations.fetch_add(stale.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> QueryCacheStats {
+        let state = self.state.lock().unwrap();
+        QueryCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            entries: state.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+pub(crate) fn tokenize(sql: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    for c in sql.chars() {
+        if c.is_whitespace() || matches!(c, '(' | ')' | ',' | ';') {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+pub(crate) fn normalize_identifier(name: &str) -> String {
+    // Drop a schema prefix ("main.users") and identifier quoting
+    let name = name.rsplit('.').next().unwrap_or(name);
+    name.trim_matches(|c| matches!(c, '"' | '`' | '[' | ']'))
+        .to_lowercase()
+}
+
+/// Whether a statement only reads data and may be served from the cache
+pub fn is_cacheable(sql: &str) -> bool {
+    let tokens: Vec<String> = tokenize(sql).iter().map(|t| t.to_lowercase()).collect();
+
+    // A second statement ("SELECT 1; DELETE FROM t") could write
+    if let Some(end) = tokens.iter().position(|t| t == ";") {
+        if end + 1 < tokens.len() && tokens[end + 1..].iter().any(|t| t != ";") {
+            return false;
+        }
+    }
+
+    match tokens.first().map(String::as_str) {
+        Some("select") => true,
+        // A CTE may wrap a write ("WITH ... DELETE FROM ...")
+        Some("with") => !tokens
+            .iter()
+            .any(|t| matches!(t.as_str(), "insert" | "update" | "delete" | "replace")),
+        _ => false,
+    }
+}
+
+/// Whether a statement has a LIMIT clause, in any of its SELECTs
+pub fn has_limit(sql: &str) -> bool {
+    tokenize(sql).iter().any(|token| token.eq_ignore_ascii_case("limit"))
+}
+
+/// Tables named after FROM or JOIN in a query (lowercased, without schema prefix)
+pub fn referenced_tables(sql: &str) -> Vec<String> {
+    let tokens = tokenize(sql);
+    let mut tables = Vec::new();
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let keyword = tokens[i].to_lowercase();
+        if keyword == "from" || keyword == "join" {
+            let mut j = i + 1;
+            loop {
+                // A subquery is handled when its own FROM is reached
+                if j >= tokens.len() || tokens[j] == "(" {
+                    break;
+                }
+                let table = normalize_identifier(&tokens[j]);
+                if !tables.contains(&table) {
+                    tables.push(table);
+                }
+                j += 1;
+
+                // Only FROM takes a comma-separated list: "FROM a x, b AS y"
+                if keyword != "from" {
+                    break;
+                }
+                if j < tokens.len() && tokens[j].eq_ignore_ascii_case("as") {
+                    j += 1;
+                }
+                if j < tokens.len() && tokens[j] != "," && !is_clause_keyword(&tokens[j]) {
+                    j += 1;
+                }
+                if j < tokens.len() && tokens[j] == "," {
+                    j += 1;
+                } else {
+                    break;
+                }
+            }
+            i = j;
+        } else {
+            i += 1;
+        }
+    }
+
+    tables
+}
+
+/// A view or trigger as listed in sqlite_master
+#[derive(Debug, Clone)]
+pub struct SchemaObject {
+    /// "view" or "trigger"
+    pub kind: String,
+    /// The view's own name, or the table a trigger fires on
+    pub table: String,
+    pub sql: String,
+}
+
+/// Tables a query's result depends on: those it reads from, the tables behind
+/// any views among them, and tables with a trigger mentioning any of those,
+/// since a write there may change them too (lowercased)
+pub fn dependent_tables(sql: &str, objects: &[SchemaObject]) -> Vec<String> {
+    let mut tables = referenced_tables(sql);
+    loop {
+        let mut added = Vec::new();
+        for object in objects {
+            let name = normalize_identifier(&object.table);
+            let found = match object.kind.as_str() {
+                "view" if tables.contains(&name) => referenced_tables(&object.sql),
+                "trigger" if !tables.contains(&name) && mentions_any(&object.sql, &tables) => vec![name],
+                _ => continue,
+            };
+            for table in found {
+                if !tables.contains(&table) && !added.contains(&table) {
+                    added.push(table);
+                }
+            }
+        }
+        if added.is_empty() {
+            return tables;
+        }
+        tables.extend(added);
+    }
+}
+
+fn mentions_any(sql: &str, tables: &[String]) -> bool {
+    tokenize(sql).iter().any(|token| tables.contains(&normalize_identifier(token)))
+}
+
+fn is_clause_keyword(token: &str) -> bool {
+    matches!(
+        token.to_lowercase().as_str(),
+        "where" | "group" | "order" | "limit" | "having" | "join" | "inner" | "left"
+            | "right" | "cross" | "natural" | "on" | "union" | "except" | "intersect"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::traits::ColumnType;
+
+    fn result(rows: usize) -> QueryResult {
+        QueryResult {
+            columns: vec![("id".to_string(), ColumnType::Integer)],
+            rows: (0..rows as i64).map(|i| vec![DbValue::Integer(i)]).collect(),
+        }
+    }
+
+    #[test]
+    fn test_referenced_tables() {
+        assert_eq!(referenced_tables("SELECT * FROM users WHERE id = 1"), vec!["users"]);
+        assert_eq!(
+            referenced_tables("select u.name, o.total from Users u join \"orders\" o on o.user_id = u.id"),
+            vec!["users", "orders"]
+        );
+        assert_eq!(referenced_tables("SELECT * FROM a AS x, main.b y WHERE x.id = y.id"), vec!["a", "b"]);
+        assert_eq!(
+            referenced_tables("SELECT * FROM (SELECT id FROM events) e"),
+            vec!["events"]
+        );
+    }
+
+    #[test]
+    fn test_is_cacheable() {
+        assert!(is_cacheable("  SELECT 1"));
+        assert!(is_cacheable("WITH t AS (SELECT 1) SELECT * FROM t"));
+        assert!(!is_cacheable("WITH t AS (SELECT 1) DELETE FROM users"));
+        assert!(!is_cacheable("INSERT INTO users VALUES (1)"));
+        assert!(!is_cacheable("PRAGMA table_info(users)"));
+        assert!(!is_cacheable("SELECT 1; DELETE FROM users"));
+        assert!(is_cacheable("SELECT 1;"));
+    }
+
+    #[test]
+    fn test_has_limit() {
+        assert!(has_limit("SELECT * FROM users LIMIT 10"));
+        assert!(has_limit("SELECT * FROM (SELECT * FROM users limit 5)"));
+        assert!(!has_limit("SELECT * FROM users"));
+        assert!(!has_limit("SELECT \"limit\" FROM quotas"));
+    }
+
+    #[test]
+    fn test_hits_misses_and_table_invalidation() {
+        let cache = QueryCache::new(10);
+        let users = CacheKey::new("default", "SELECT * FROM users", &HashMap::new());
+        let orders = CacheKey::new("default", "SELECT * FROM orders", &HashMap::new());
+
+        assert!(cache.get(&users).is_none());
+        cache.insert(users.clone(), result(2), vec!["users".to_string()], cache.generation("default"));
+        cache.insert(orders.clone(), result(1), vec!["orders".to_string()], cache.generation("default"));
+        assert_eq!(cache.get(&users).unwrap().rows.len(), 2);
+
+        // A write to users leaves the orders entry alone
+        cache.invalidate_table("default", "Users");
+        assert!(cache.get(&users).is_none());
+        assert!(cache.get(&orders).is_some());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.invalidations, stats.entries), (2, 2, 1, 1));
+    }
+
+    #[test]
+    fn test_insert_after_write_is_discarded() {
+        let cache = QueryCache::new(10);
+        let key = CacheKey::new("default", "SELECT * FROM users", &HashMap::new());
+
+        let generation = cache.generation("default");
+        cache.invalidate_table("default", "users");
+        cache.insert(key.clone(), result(1), vec!["users".to_string()], generation);
+        assert!(cache.get(&key).is_none());
+    }
+
+    fn object(kind: &str, table: &str, sql: &str) -> SchemaObject {
+        SchemaObject { kind: kind.to_string(), table: table.to_string(), sql: sql.to_string() }
+    }
+
+    #[test]
+    fn test_dependent_tables_resolves_views_and_triggers() {
+        let objects = vec![
+            object("view", "big_orders", "CREATE VIEW big_orders AS SELECT * FROM orders WHERE total > 100"),
+            object("view", "vip", "CREATE VIEW vip AS SELECT o.* FROM big_orders o JOIN Users u ON u.id = o.user_id"),
+            object("trigger", "payments", "CREATE TRIGGER pay AFTER INSERT ON payments BEGIN UPDATE orders SET paid = 1; END"),
+            object("trigger", "logins", "CREATE TRIGGER seen AFTER INSERT ON logins BEGIN INSERT INTO audit VALUES (1); END"),
+        ];
+
+        assert_eq!(
+            dependent_tables("SELECT * FROM vip", &objects),
+            vec!["vip", "big_orders", "users", "orders", "payments"]
+        );
+        assert_eq!(dependent_tables("SELECT * FROM audit", &objects), vec!["audit", "logins"]);
+        assert_eq!(dependent_tables("SELECT * FROM users", &objects), vec!["users"]);
+    }
+
+    #[test]
+    fn test_write_to_table_behind_view_drops_view_results() {
+        let cache = QueryCache::new(10);
+        let objects = vec![object("view", "big_orders", "CREATE VIEW big_orders AS SELECT * FROM orders WHERE total > 100")];
+        let sql = "SELECT * FROM big_orders";
+        let key = CacheKey::new("default", sql, &HashMap::new());
+
+        cache.insert(key.clone(), result(1), dependent_tables(sql, &objects), cache.generation("default"));
+        assert!(cache.get(&key).is_some());
+
+        cache.invalidate_table("default", "orders");
+        assert!(cache.get(&key).is_none());
+    }
+}
@@ -0,0 +1,293 @@
+//! Comparing a table's rows in two databases.
+//!
+//! Both copies are read ordered by the key columns and merged like a sorted
+//! merge join, so only the current row of each is held at a time. Keys are
+//! compared the way SQLite orders values under the BINARY collation (see
+//! [`compare_values`]), which the queries ask for explicitly. Rows are
+//! decrypted before they are compared, since encrypting the same value twice
+//! gives different bytes.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use futures::StreamExt;
+
+use super::encryption::{column_sources, ColumnCipher};
+use super::error::{DatabaseError, Result};
+use super::traits::{ColumnType, DbValue, StreamedQueryResult};
+use super::Database;
+
+/// How a row differs between the source and the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowChange {
+    /// Only the target has the key
+    Added,
+    /// Only the source has the key
+    Removed,
+    /// Both have the key with different values
+    Changed,
+}
+
+#[derive(Debug, Clone)]
+pub struct RowDiff {
+    pub change: RowChange,
+    /// Values of the key columns
+    pub key: Vec<DbValue>,
+    /// The row in the source, unless it was added
+    pub source: Option<Vec<DbValue>>,
+    /// The row in the target, unless it was removed
+    pub target: Option<Vec<DbValue>>,
+    /// Columns whose values differ, for a changed row
+    pub changed_columns: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableDiff {
+    pub key_columns: Vec<String>,
+    /// Columns compared: those both tables have, in the source's order
+    pub columns: Vec<(String, ColumnType)>,
+    pub added: u64,
+    pub removed: u64,
+    pub changed: u64,
+    pub unchanged: u64,
+    /// The first differing rows, at most the number asked for
+    pub rows: Vec<RowDiff>,
+}
+
+/// Compare `table_name` in `source` and `target`, keeping up to `max_rows`
+/// differing rows. Without `key_columns` the table's primary key is used.
+pub async fn diff_table(
+    source: &dyn Database,
+    target: &dyn Database,
+    table_name: &str,
+    key_columns: &[String],
+    where_clause: Option<&str>,
+    cipher: Option<&ColumnCipher>,
+    max_rows: usize,
+) -> Result<TableDiff> {
+    let key_columns = if key_columns.is_empty() { primary_key(source, table_name).await? } else { key_columns.to_vec() };
+    let target_columns: Vec<String> =
+        target.table_schema(table_name).await?.into_iter().map(|(name, _)| name).collect();
+    let columns: Vec<(String, ColumnType)> = source
+        .table_schema(table_name)
+        .await?
+        .into_iter()
+        .filter(|(name, _)| target_columns.contains(name))
+        .map(|(name, column_type)| (name, column_type.unwrap_or(ColumnType::Text)))
+        .collect();
+    let key_positions = key_columns
+        .iter()
+        .map(|key| {
+            columns.iter().position(|(name, _)| name == key).ok_or_else(|| {
+                DatabaseError::QueryError(format!("Key column '{}' is not in both copies of '{}'", key, table_name))
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let quote = |name: &str| format!("\"{}\"", name.replace('"', "\"\""));
+    let mut sql = format!(
+        "SELECT {} FROM {}",
+        columns.iter().map(|(name, _)| quote(name)).collect::<Vec<_>>().join(", "),
+        table_name
+    );
+    if let Some(where_clause) = where_clause {
+        sql.push_str(&format!(" WHERE {}", where_clause));
+    }
+    let order: Vec<String> = key_columns.iter().map(|key| format!("{} COLLATE BINARY", quote(key))).collect();
+    sql.push_str(&format!(" ORDER BY {}", order.join(", ")));
+
+    // Both copies have the same table name, so their ciphertext is bound to the same columns
+    let encrypted = match cipher {
+        Some(_) => vec![(table_name.to_string(), source.encrypted_columns(table_name).await?)],
+        None => Vec::new(),
+    };
+    let sources = column_sources(&columns, &encrypted);
+
+    let (_, mut source_rows) = source.query_stream(&sql, HashMap::new()).await?;
+    let (_, mut target_rows) = target.query_stream(&sql, HashMap::new()).await?;
+    let mut diff = TableDiff {
+        key_columns,
+        columns,
+        added: 0,
+        removed: 0,
+        changed: 0,
+        unchanged: 0,
+        rows: Vec::new(),
+    };
+    let key_of = |row: &[DbValue]| key_positions.iter().map(|&i| row[i].clone()).collect::<Vec<_>>();
+    let record = |diff: &mut TableDiff, change, source: Option<Vec<DbValue>>, target: Option<Vec<DbValue>>| {
+        let changed_columns = match (&source, &target) {
+            (Some(source), Some(target)) => diff
+                .columns
+                .iter()
+                .zip(source.iter().zip(target))
+                .filter(|(_, (a, b))| compare_values(a, b) != Ordering::Equal)
+                .map(|((name, _), _)| name.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        match change {
+            RowChange::Added => diff.added += 1,
+            RowChange::Removed => diff.removed += 1,
+            RowChange::Changed => diff.changed += 1,
+        }
+        if diff.rows.len() < max_rows {
+            let key = key_of(source.as_deref().or(target.as_deref()).unwrap_or_default());
+            diff.rows.push(RowDiff { change, key, source, target, changed_columns });
+        }
+    };
+
+    let mut left = next_row(&mut source_rows, cipher, &sources).await?;
+    let mut right = next_row(&mut target_rows, cipher, &sources).await?;
+    loop {
+        match (left.take(), right.take()) {
+            (None, None) => break,
+            (Some(row), None) => {
+                record(&mut diff, RowChange::Removed, Some(row), None);
+                left = next_row(&mut source_rows, cipher, &sources).await?;
+            }
+            (None, Some(row)) => {
+                record(&mut diff, RowChange::Added, None, Some(row));
+                right = next_row(&mut target_rows, cipher, &sources).await?;
+            }
+            (Some(a), Some(b)) => match compare_rows(&key_positions, &a, &b) {
+                Ordering::Less => {
+                    record(&mut diff, RowChange::Removed, Some(a), None);
+                    left = next_row(&mut source_rows, cipher, &sources).await?;
+                    right = Some(b);
+                }
+                Ordering::Greater => {
+                    record(&mut diff, RowChange::Added, None, Some(b));
+                    left = Some(a);
+                    right = next_row(&mut target_rows, cipher, &sources).await?;
+                }
+                Ordering::Equal => {
+                    let all: Vec<usize> = (0..a.len()).collect();
+                    if compare_rows(&all, &a, &b) == Ordering::Equal {
+                        diff.unchanged += 1;
+                    } else {
+                        record(&mut diff, RowChange::Changed, Some(a), Some(b));
+                    }
+                    left = next_row(&mut source_rows, cipher, &sources).await?;
+                    right = next_row(&mut target_rows, cipher, &sources).await?;
+                }
+            },
+        }
+    }
+    Ok(diff)
+}
+
+/// Primary key columns of a table, in key order
+async fn primary_key(db: &dyn Database, table_name: &str) -> Result<Vec<String>> {
+    let params = HashMap::from([("table".to_string(), DbValue::Text(table_name.to_string()))]);
+    let result = db.query("SELECT name FROM pragma_table_info(:table) WHERE pk > 0 ORDER BY pk", params).await?;
+    let keys: Vec<String> = result
+        .rows
+        .into_iter()
+        .filter_map(|row| match row.into_iter().next() {
+            Some(DbValue::Text(name)) => Some(name),
+            _ => None,
+        })
+        .collect();
+    if keys.is_empty() {
+        return Err(DatabaseError::QueryError(format!(
+            "Table '{}' has no primary key; name the key columns to compare rows by",
+            table_name
+        )));
+    }
+    Ok(keys)
+}
+
+async fn next_row(
+    rows: &mut StreamedQueryResult,
+    cipher: Option<&ColumnCipher>,
+    sources: &[Vec<(String, String)>],
+) -> Result<Option<Vec<DbValue>>> {
+    match rows.next().await.transpose()? {
+        Some(row) => match cipher {
+            Some(cipher) => cipher.decrypt_row(row, sources).map(Some),
+            None => Ok(Some(row)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Compare two rows by the values at `positions`, in order
+fn compare_rows(positions: &[usize], a: &[DbValue], b: &[DbValue]) -> Ordering {
+    positions
+        .iter()
+        .map(|&i| compare_values(&a[i], &b[i]))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Order values as SQLite does with the BINARY collation: NULL, then
+/// numbers by value, then text and blobs byte by byte
+pub fn compare_values(a: &DbValue, b: &DbValue) -> Ordering {
+    fn rank(value: &DbValue) -> u8 {
+        match value {
+            DbValue::Null => 0,
+            DbValue::Integer(_) | DbValue::Real(_) | DbValue::Boolean(_) | DbValue::Timestamp(_) => 1,
+            DbValue::Text(_) => 2,
+            DbValue::Blob(_) => 3,
+        }
+    }
+    fn integer(value: &DbValue) -> Option<i64> {
+        match value {
+            DbValue::Integer(v) | DbValue::Timestamp(v) => Some(*v),
+            DbValue::Boolean(v) => Some(*v as i64),
+            _ => None,
+        }
+    }
+
+    match (a, b) {
+        (DbValue::Text(a), DbValue::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (DbValue::Blob(a), DbValue::Blob(b)) => a.cmp(b),
+        _ if rank(a) != rank(b) => rank(a).cmp(&rank(b)),
+        (DbValue::Null, DbValue::Null) => Ordering::Equal,
+        _ => match (integer(a), integer(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => {
+                let real = |value: &DbValue| match value {
+                    DbValue::Real(v) => *v,
+                    other => integer(other).unwrap_or_default() as f64,
+                };
+                real(a).total_cmp(&real(b))
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_values_follows_sqlite_order() {
+        let ordered = [
+            DbValue::Null,
+            DbValue::Integer(-3),
+            DbValue::Real(1.5),
+            DbValue::Integer(2),
+            DbValue::Text("B".to_string()),
+            DbValue::Text("a".to_string()),
+            DbValue::Text("é".to_string()),
+            DbValue::Blob(vec![0]),
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare_values(&pair[0], &pair[1]), Ordering::Less, "{:?} < {:?}", pair[0], pair[1]);
+            assert_eq!(compare_values(&pair[1], &pair[0]), Ordering::Greater);
+        }
+        assert_eq!(compare_values(&DbValue::Integer(2), &DbValue::Real(2.0)), Ordering::Equal);
+        assert_eq!(compare_values(&DbValue::Boolean(true), &DbValue::Integer(1)), Ordering::Equal);
+        assert_eq!(compare_values(&DbValue::Null, &DbValue::Null), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_rows_by_positions() {
+        let a = vec![DbValue::Integer(1), DbValue::Text("x".to_string())];
+        let b = vec![DbValue::Integer(1), DbValue::Text("y".to_string())];
+        assert_eq!(compare_rows(&[0], &a, &b), Ordering::Equal);
+        assert_eq!(compare_rows(&[0, 1], &a, &b), Ordering::Less);
+    }
+}
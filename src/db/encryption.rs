@@ -0,0 +1,301 @@
+//! Column-level encryption with a server-held key
+//!
+//! Values of columns marked `encrypted` are stored as text of the form
+//! `enc:v2:<base64(nonce || AES-256-GCM ciphertext)>`. The plaintext keeps the
+//! value's type, so an encrypted integer decrypts back to an integer. The
+//! ciphertext is bound to its `table.column` (as associated data), so it does
+//! not decrypt if moved to another column or table. Which columns are
+//! encrypted is recorded in the database itself (see `ENCRYPTED_COLUMNS_TABLE`),
+//! so a server with the key knows what to protect, and only values read from
+//! those columns are decrypted.
+//!
+//! `enc:v1:` values, written before ciphertext was bound to its column, are
+//! still read.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::path::Path;
+
+use super::error::{DatabaseError, Result};
+use super::traits::{ColumnType, DbValue};
+
+/// Table recording which columns hold encrypted values
+pub const ENCRYPTED_COLUMNS_TABLE: &str = "__datasink_encrypted_columns";
+
+/// Environment variable holding a base64-encoded 256-bit key
+pub const ENCRYPTION_KEY_ENV: &str = "DATASINK_ENCRYPTION_KEY";
+
+const PREFIX: &str = "enc:v2:";
+/// Ciphertext not bound to its column
+const UNBOUND_PREFIX: &str = "enc:v1:";
+const KEY_LEN: usize = 32;
+
+/// Whether a stored value is ciphertext produced by `ColumnCipher::encrypt`
+pub fn is_encrypted(value: &DbValue) -> bool {
+    matches!(value, DbValue::Text(text) if text.starts_with(PREFIX) || text.starts_with(UNBOUND_PREFIX))
+}
+
+/// The associated data binding ciphertext to its column
+fn column_aad(table: &str, column: &str) -> String {
+    format!("{}.{}", table, column).to_lowercase()
+}
+
+/// For each column of a result, the encrypted `(table, column)`s its values
+/// may have been read from: those of the `tables` the result was read from,
+/// given with their encrypted columns, that have a column of the same name.
+/// Columns that are renamed (`ssn AS id_number`) or computed match nothing.
+pub fn column_sources(columns: &[(String, ColumnType)], tables: &[(String, Vec<String>)]) -> Vec<Vec<(String, String)>> {
+    columns
+        .iter()
+        .map(|(column, _)| {
+            tables
+                .iter()
+                .filter_map(|(table, encrypted)| {
+                    let encrypted = encrypted.iter().find(|name| name.eq_ignore_ascii_case(column))?;
+                    Some((table.clone(), encrypted.clone()))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub struct ColumnCipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl ColumnCipher {
+    /// Build a cipher from a base64-encoded 256-bit key
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        let bytes = BASE64
+            .decode(encoded.trim())
+            .map_err(|e| DatabaseError::EncryptionError(format!("Encryption key is not valid base64: {}", e)))?;
+        if bytes.len() != KEY_LEN {
+            return Err(DatabaseError::EncryptionError(format!(
+                "Encryption key must be {} bytes, got {}",
+                KEY_LEN,
+                bytes.len()
+            )));
+        }
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| DatabaseError::EncryptionError("Invalid encryption key".to_string()))?;
+        Ok(Self { key: LessSafeKey::new(key), rng: SystemRandom::new() })
+    }
+
+    /// Read a base64-encoded key from a file
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let encoded = std::fs::read_to_string(path).map_err(|e| {
+            DatabaseError::EncryptionError(format!("Failed to read key file {}: {}", path.display(), e))
+        })?;
+        Self::from_base64(&encoded)
+    }
+
+    /// The key from `DATASINK_ENCRYPTION_KEY`, if set
+    pub fn from_env() -> Result<Option<Self>> {
+        match std::env::var(ENCRYPTION_KEY_ENV) {
+            Ok(encoded) if !encoded.trim().is_empty() => Self::from_base64(&encoded).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// A new random key, base64-encoded
+    pub fn generate_key() -> Result<String> {
+        let mut key = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| DatabaseError::EncryptionError("Failed to generate a random key".to_string()))?;
+        Ok(BASE64.encode(key))
+    }
+
+    /// Encrypt a value written to `table.column`; NULL stays NULL. Values
+    /// that already look encrypted are refused: the server cannot check
+    /// ciphertext it did not produce.
+    pub fn encrypt(&self, value: &DbValue, table: &str, column: &str) -> Result<DbValue> {
+        if matches!(value, DbValue::Null) {
+            return Ok(DbValue::Null);
+        }
+        if is_encrypted(value) {
+            return Err(DatabaseError::EncryptionError(format!(
+                "The value for encrypted column '{}.{}' is already ciphertext; write the plaintext",
+                table, column
+            )));
+        }
+
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| DatabaseError::EncryptionError("Failed to generate a nonce".to_string()))?;
+
+        let mut data = encode_plaintext(value);
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(column_aad(table, column)), &mut data)
+            .map_err(|_| DatabaseError::EncryptionError("Encryption failed".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&data);
+        Ok(DbValue::Text(format!("{}{}", PREFIX, BASE64.encode(sealed))))
+    }
+
+    /// Decrypt a value read from `table.column`, produced by `encrypt`;
+    /// anything else is returned unchanged
+    pub fn decrypt(&self, value: DbValue, table: &str, column: &str) -> Result<DbValue> {
+        let DbValue::Text(text) = &value else {
+            return Ok(value);
+        };
+        let (encoded, aad) = if let Some(encoded) = text.strip_prefix(PREFIX) {
+            (encoded, column_aad(table, column))
+        } else if let Some(encoded) = text.strip_prefix(UNBOUND_PREFIX) {
+            (encoded, String::new())
+        } else {
+            return Ok(value);
+        };
+
+        let invalid = || DatabaseError::EncryptionError("Failed to decrypt value (wrong key or corrupted data)".to_string());
+        let sealed = BASE64.decode(encoded).map_err(|_| invalid())?;
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| invalid())?;
+
+        let mut data = ciphertext.to_vec();
+        let plaintext = self.key.open_in_place(nonce, Aad::from(aad), &mut data).map_err(|_| invalid())?;
+        decode_plaintext(plaintext).ok_or_else(invalid)
+    }
+
+    /// Decrypt the values of a row's encrypted columns, given for each column
+    /// the `(table, column)`s it may have been read from (see `column_sources`);
+    /// a value is decrypted as the first of them it was encrypted for
+    pub fn decrypt_row(&self, values: Vec<DbValue>, sources: &[Vec<(String, String)>]) -> Result<Vec<DbValue>> {
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let mut sources = sources.get(i).into_iter().flatten();
+                let Some((table, column)) = sources.next() else {
+                    return Ok(value);
+                };
+                sources.fold(self.decrypt(value.clone(), table, column), |result, (table, column)| {
+                    result.or_else(|_| self.decrypt(value.clone(), table, column))
+                })
+            })
+            .collect()
+    }
+}
+
+/// A type tag followed by the value's bytes
+fn encode_plaintext(value: &DbValue) -> Vec<u8> {
+    let (tag, mut bytes) = match value {
+        DbValue::Integer(v) => (b'i', v.to_le_bytes().to_vec()),
+        DbValue::Real(v) => (b'r', v.to_le_bytes().to_vec()),
+        DbValue::Text(v) => (b't', v.as_bytes().to_vec()),
+        DbValue::Blob(v) => (b'b', v.clone()),
+        DbValue::Boolean(v) => (b'?', vec![*v as u8]),
+        DbValue::Timestamp(v) => (b'@', v.to_le_bytes().to_vec()),
+        DbValue::Null => (b'0', Vec::new()),
+    };
+    bytes.insert(0, tag);
+    bytes
+}
+
+fn decode_plaintext(data: &[u8]) -> Option<DbValue> {
+    let (tag, bytes) = data.split_first()?;
+    Some(match tag {
+        b'i' => DbValue::Integer(i64::from_le_bytes(bytes.try_into().ok()?)),
+        b'r' => DbValue::Real(f64::from_le_bytes(bytes.try_into().ok()?)),
+        b't' => DbValue::Text(String::from_utf8(bytes.to_vec()).ok()?),
+        b'b' => DbValue::Blob(bytes.to_vec()),
+        b'?' => DbValue::Boolean(bytes.first()? != &0),
+        b'@' => DbValue::Timestamp(i64::from_le_bytes(bytes.try_into().ok()?)),
+        b'0' => DbValue::Null,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> ColumnCipher {
+        ColumnCipher::from_base64(&ColumnCipher::generate_key().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_keeps_types() {
+        let cipher = cipher();
+        for value in [
+            DbValue::Text("555-12-3456".to_string()),
+            DbValue::Integer(-42),
+            DbValue::Real(1.5),
+            DbValue::Blob(vec![0, 1, 2]),
+        ] {
+            let encrypted = cipher.encrypt(&value, "patients", "ssn").unwrap();
+            assert!(is_encrypted(&encrypted));
+            assert_eq!(format!("{:?}", cipher.decrypt(encrypted, "Patients", "SSN").unwrap()), format!("{:?}", value));
+        }
+        assert!(matches!(cipher.encrypt(&DbValue::Null, "patients", "ssn").unwrap(), DbValue::Null));
+    }
+
+    #[test]
+    fn test_nonces_differ_and_plaintext_passes_through() {
+        let cipher = cipher();
+        let value = DbValue::Text("secret".to_string());
+        let (a, b) = (cipher.encrypt(&value, "t", "c").unwrap(), cipher.encrypt(&value, "t", "c").unwrap());
+        assert_ne!(format!("{:?}", a), format!("{:?}", b));
+
+        let plain = cipher.decrypt(DbValue::Text("not encrypted".to_string()), "t", "c").unwrap();
+        assert!(matches!(plain, DbValue::Text(text) if text == "not encrypted"));
+    }
+
+    #[test]
+    fn test_wrong_key_fails() {
+        let encrypted = cipher().encrypt(&DbValue::Text("secret".to_string()), "t", "c").unwrap();
+        assert!(matches!(cipher().decrypt(encrypted, "t", "c"), Err(DatabaseError::EncryptionError(_))));
+        assert!(ColumnCipher::from_base64("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn test_ciphertext_is_bound_to_its_column() {
+        let cipher = cipher();
+        let encrypted = cipher.encrypt(&DbValue::Text("555-12-3456".to_string()), "patients", "ssn").unwrap();
+        assert!(cipher.decrypt(encrypted.clone(), "patients", "notes").is_err());
+        assert!(cipher.decrypt(encrypted.clone(), "staff", "ssn").is_err());
+
+        // Ciphertext cannot be written in place of a value
+        assert!(matches!(cipher.encrypt(&encrypted, "patients", "ssn"), Err(DatabaseError::EncryptionError(_))));
+    }
+
+    #[test]
+    fn test_decrypt_row_only_touches_encrypted_columns() {
+        let cipher = cipher();
+        let tables = vec![
+            ("patients".to_string(), vec!["ssn".to_string()]),
+            ("staff".to_string(), vec!["SSN".to_string(), "salary".to_string()]),
+        ];
+        let columns = ["name", "ssn", "salary"].map(|name| (name.to_string(), ColumnType::Text));
+        let sources = column_sources(&columns, &tables);
+        assert!(sources[0].is_empty());
+        assert_eq!(sources[1].len(), 2);
+
+        // Plain text that happens to look like ciphertext is left alone outside encrypted columns
+        let row = vec![
+            DbValue::Text("enc:v2:not ciphertext".to_string()),
+            cipher.encrypt(&DbValue::Text("555-12-3456".to_string()), "staff", "ssn").unwrap(),
+            cipher.encrypt(&DbValue::Integer(50_000), "staff", "salary").unwrap(),
+        ];
+        let decrypted = cipher.decrypt_row(row, &sources).unwrap();
+        assert_eq!(
+            format!("{:?}", decrypted),
+            format!(
+                "{:?}",
+                vec![
+                    DbValue::Text("enc:v2:not ciphertext".to_string()),
+                    DbValue::Text("555-12-3456".to_string()),
+                    DbValue::Integer(50_000),
+                ]
+            )
+        );
+    }
+}
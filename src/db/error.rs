@@ -23,8 +23,183 @@ pub enum DatabaseError {
     #[error("Database error: {0}")]
     DatabaseError(#[from] sqlx::Error),
 
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+
+    #[error("{}", unknown_columns_message(table, columns))]
+    UnknownColumns { table: String, columns: Vec<UnknownColumn> },
+
     #[error("Other error: {0}")]
     Other(String),
 }
 
+/// A column a write named that its table does not have
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownColumn {
+    pub name: String,
+    /// The table's columns closest in spelling, closest first
+    pub close_matches: Vec<String>,
+}
+
+impl UnknownColumn {
+    /// `name` with up to three of `columns` it may be a misspelling of
+    pub fn new<'a>(name: &str, columns: impl IntoIterator<Item = &'a str>) -> Self {
+        Self { name: name.to_string(), close_matches: close_matches(name, columns) }
+    }
+}
+
+impl std::fmt::Display for UnknownColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}'", self.name)?;
+        match self.close_matches.as_slice() {
+            [] => Ok(()),
+            [only] => write!(f, " (did you mean '{}'?)", only),
+            matches => write!(f, " (did you mean {}?)", matches.iter().map(|m| format!("'{}'", m)).collect::<Vec<_>>().join(" or ")),
+        }
+    }
+}
+
+fn unknown_columns_message(table: &str, columns: &[UnknownColumn]) -> String {
+    let names: Vec<String> = columns.iter().map(ToString::to_string).collect();
+    let noun = if columns.len() == 1 { "column" } else { "columns" };
+    format!("Table '{}' has no {} {}", table, noun, names.join(", "))
+}
+
+/// Up to three of `candidates` within a few edits of `name`, ignoring case,
+/// closest first
+pub fn close_matches<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let name = name.to_lowercase();
+    // One edit for short names, about one per three characters for longer ones
+    let limit = (name.chars().count() / 3).max(1);
+    let mut matches: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .collect();
+    matches.sort();
+    matches.into_iter().take(3).map(|(_, candidate)| candidate.to_string()).collect()
+}
+
+/// Edits (insertions, deletions, substitutions and swaps of adjacent
+/// characters) turning one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // distances[i][j] is the distance between a[..i] and b[..j]
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            let mut distance = (distances[i - 1][j] + 1).min(distances[i][j - 1] + 1).min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// SQLite's generic error code, used for SQL syntax errors and references to
+/// missing tables or columns
+const SQLITE_ERROR: &str = "1";
+
+/// Primary result codes of a database that another connection is using
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
+/// A violated constraint, as SQLite reports it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstraintFailure {
+    /// "UNIQUE", "PRIMARY KEY", "NOT NULL", "CHECK" or "FOREIGN KEY"
+    pub kind: &'static str,
+    /// Name (or expression) of a CHECK constraint
+    pub name: Option<String>,
+    /// Column(s) the constraint is on, as "table.column"; several columns
+    /// of a composite key are comma-separated
+    pub column: Option<String>,
+}
+
+impl DatabaseError {
+    /// Whether the database rejected a write for breaking a UNIQUE, NOT NULL,
+    /// CHECK or foreign key constraint
+    pub fn is_constraint_violation(&self) -> bool {
+        matches!(self, DatabaseError::DatabaseError(sqlx::Error::Database(e)) if e.kind() != sqlx::error::ErrorKind::Other)
+    }
+
+    /// Whether SQLite rejected the statement itself, e.g. a syntax error or an
+    /// unknown table or column
+    pub fn is_invalid_statement(&self) -> bool {
+        matches!(self, DatabaseError::DatabaseError(sqlx::Error::Database(e)) if e.code().as_deref() == Some(SQLITE_ERROR))
+    }
+
+    /// Whether the violated constraint is a UNIQUE or PRIMARY KEY one
+    pub fn is_unique_violation(&self) -> bool {
+        matches!(self, DatabaseError::DatabaseError(sqlx::Error::Database(e)) if e.is_unique_violation())
+    }
+
+    /// SQLite's extended result code, if SQLite raised the error
+    pub fn sqlite_code(&self) -> Option<i32> {
+        match self {
+            DatabaseError::DatabaseError(sqlx::Error::Database(e)) => e.code()?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request may succeed if sent again: the database was
+    /// busy or locked, or the connection to it failed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DatabaseError::ConnectionError(_) => true,
+            DatabaseError::DatabaseError(sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut) => true,
+            _ => matches!(self.sqlite_code().map(|code| code & 0xff), Some(SQLITE_BUSY | SQLITE_LOCKED)),
+        }
+    }
+
+    /// The constraint a write violated, from SQLite's extended code and its
+    /// "UNIQUE constraint failed: users.email" message
+    pub fn constraint_failure(&self) -> Option<ConstraintFailure> {
+        let DatabaseError::DatabaseError(sqlx::Error::Database(e)) = self else {
+            return None;
+        };
+        let kind = match self.sqlite_code()? {
+            2067 => "UNIQUE",
+            1555 => "PRIMARY KEY",
+            1299 => "NOT NULL",
+            275 => "CHECK",
+            787 => "FOREIGN KEY",
+            _ => return None,
+        };
+        let subject = e.message().split_once("constraint failed: ").map(|(_, s)| s.trim().to_string());
+        Some(match kind {
+            "CHECK" => ConstraintFailure { kind, name: subject, column: None },
+            _ => ConstraintFailure { kind, name: None, column: subject },
+        })
+    }
+}
+
 pub type Result<T> = std::result::Result<T, DatabaseError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_close_matches() {
+        let columns = ["id", "name", "email", "created_at", "updated_at"];
+        assert_eq!(close_matches("nmae", columns), ["name"]);
+        assert_eq!(close_matches("EMial", columns), ["email"]);
+        assert_eq!(close_matches("create_at", columns), ["created_at"]);
+        assert_eq!(close_matches("nam", ["nan", "name", "id"]), ["name", "nan"]);
+        assert!(close_matches("nickname", columns).is_empty());
+
+        let error = DatabaseError::UnknownColumns {
+            table: "users".to_string(),
+            columns: vec![UnknownColumn::new("nmae", columns), UnknownColumn::new("nickname", columns)],
+        };
+        assert_eq!(error.to_string(), "Table 'users' has no columns 'nmae' (did you mean 'name'?), 'nickname'");
+    }
+}
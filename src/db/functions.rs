@@ -0,0 +1,119 @@
+//! Scalar SQL functions implemented in Rust and registered on every connection
+//!
+//! - `REGEXP`: backs the `text REGEXP pattern` operator (Rust `regex` syntax)
+//! - `uuid4()`: a random version 4 UUID as text
+//! - `unix_now()`: the current time in Unix seconds
+
+use libsqlite3_sys as ffi;
+use regex::Regex;
+use std::ffi::{c_int, c_void, CString};
+use std::ptr::{self, NonNull};
+
+type ScalarFn = unsafe extern "C" fn(*mut ffi::sqlite3_context, c_int, *mut *mut ffi::sqlite3_value);
+
+struct ScalarFunction {
+    name: &'static str,
+    args: c_int,
+    /// Deterministic functions may be used in indexes and CHECK constraints
+    deterministic: bool,
+    func: ScalarFn,
+}
+
+const FUNCTIONS: &[ScalarFunction] = &[
+    ScalarFunction { name: "regexp", args: 2, deterministic: true, func: regexp },
+    ScalarFunction { name: "uuid4", args: 0, deterministic: false, func: uuid4 },
+    ScalarFunction { name: "unix_now", args: 0, deterministic: false, func: unix_now },
+];
+
+/// Register the custom functions on a raw connection handle
+pub(crate) fn register_functions(db: NonNull<ffi::sqlite3>) -> Result<(), String> {
+    for function in FUNCTIONS {
+        let name = CString::new(function.name).expect("function names contain no NUL bytes");
+        let mut flags = ffi::SQLITE_UTF8;
+        if function.deterministic {
+            flags |= ffi::SQLITE_DETERMINISTIC;
+        }
+
+        // SAFETY: `db` is an open connection held locked by the caller, and the
+        // callbacks only touch the context and arguments SQLite passes them
+        let rc = unsafe {
+            ffi::sqlite3_create_function_v2(
+                db.as_ptr(),
+                name.as_ptr(),
+                function.args,
+                flags,
+                ptr::null_mut(),
+                Some(function.func),
+                None,
+                None,
+                None,
+            )
+        };
+        if rc != ffi::SQLITE_OK {
+            return Err(format!("Failed to register SQL function {}: error code {}", function.name, rc));
+        }
+    }
+    Ok(())
+}
+
+/// UTF-8 text of an argument, or None for NULL
+unsafe fn value_text<'a>(value: *mut ffi::sqlite3_value) -> Option<&'a str> {
+    if ffi::sqlite3_value_type(value) == ffi::SQLITE_NULL {
+        return None;
+    }
+    let text = ffi::sqlite3_value_text(value);
+    if text.is_null() {
+        return Some("");
+    }
+    let len = ffi::sqlite3_value_bytes(value) as usize;
+    std::str::from_utf8(std::slice::from_raw_parts(text, len)).ok()
+}
+
+unsafe fn result_text(ctx: *mut ffi::sqlite3_context, text: &str) {
+    ffi::sqlite3_result_text(ctx, text.as_ptr() as *const _, text.len() as c_int, ffi::SQLITE_TRANSIENT());
+}
+
+unsafe fn result_error(ctx: *mut ffi::sqlite3_context, message: &str) {
+    ffi::sqlite3_result_error(ctx, message.as_ptr() as *const _, message.len() as c_int);
+}
+
+unsafe extern "C" fn drop_regex(regex: *mut c_void) {
+    drop(Box::from_raw(regex as *mut Regex));
+}
+
+/// `regexp(pattern, text)`: SQLite rewrites `text REGEXP pattern` into this call
+unsafe extern "C" fn regexp(ctx: *mut ffi::sqlite3_context, argc: c_int, argv: *mut *mut ffi::sqlite3_value) {
+    let args = std::slice::from_raw_parts(argv, argc as usize);
+    let (Some(pattern), Some(text)) = (value_text(args[0]), value_text(args[1])) else {
+        ffi::sqlite3_result_null(ctx);
+        return;
+    };
+
+    // The compiled pattern is kept as auxdata so a constant pattern is compiled once per statement
+    let cached = ffi::sqlite3_get_auxdata(ctx, 0) as *const Regex;
+    let matched = if let Some(regex) = cached.as_ref() {
+        regex.is_match(text)
+    } else {
+        match Regex::new(pattern) {
+            Ok(regex) => {
+                let matched = regex.is_match(text);
+                ffi::sqlite3_set_auxdata(ctx, 0, Box::into_raw(Box::new(regex)) as *mut c_void, Some(drop_regex));
+                matched
+            }
+            Err(e) => {
+                result_error(ctx, &format!("Invalid regular expression: {}", e));
+                return;
+            }
+        }
+    };
+
+    ffi::sqlite3_result_int(ctx, matched as c_int);
+}
+
+unsafe extern "C" fn uuid4(ctx: *mut ffi::sqlite3_context, _argc: c_int, _argv: *mut *mut ffi::sqlite3_value) {
+    result_text(ctx, &uuid::Uuid::new_v4().to_string());
+}
+
+unsafe extern "C" fn unix_now(ctx: *mut ffi::sqlite3_context, _argc: c_int, _argv: *mut *mut ffi::sqlite3_value) {
+    ffi::sqlite3_result_int64(ctx, chrono::Utc::now().timestamp());
+}
@@ -0,0 +1,512 @@
+//! System-versioned tables.
+//!
+//! A table with history keeps every version of its rows in `<table>__history`,
+//! maintained by triggers: each version has the columns of the table, the
+//! rowid it belongs to, the Unix time (with fractions of a second) it was
+//! current from and to, and whether an `insert` or `update` wrote it (`seed`
+//! for the rows recorded when history was turned on). The version still
+//! current has no `__valid_to`.
+//!
+//! The versions a statement wrote and ended at the same time form a
+//! [`Change`], which `datasink undo` reverts with compensating statements.
+//!
+//! Queries read a table as it was at some time with
+//! `FROM orders AS OF '2024-06-01 12:00:00'` (ISO-8601 text, Unix seconds or
+//! a named parameter). The clause is rewritten into a subquery over the
+//! history table before the statement reaches SQLite.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::error::{DatabaseError, Result};
+use super::traits::DbValue;
+
+/// Appended to a table's name for its history table and triggers
+pub const HISTORY_SUFFIX: &str = "__history";
+
+/// The current time as Unix seconds with milliseconds; constant within one statement
+const NOW: &str = "unixepoch('subsec')";
+
+/// The columns every history table starts with, ahead of the table's own
+pub const HISTORY_COLUMNS: [&str; 4] = ["__rowid", "__valid_from", "__valid_to", "__operation"];
+
+pub fn history_table_name(table_name: &str) -> String {
+    format!("{}{}", table_name, HISTORY_SUFFIX)
+}
+
+/// Statements creating a table's history table, the triggers that record
+/// versions and the first version of the rows already there. `columns` are
+/// the table's columns with their declared types.
+pub fn history_sql(table_name: &str, columns: &[(String, String)]) -> Vec<String> {
+    let history = history_table_name(table_name);
+    let column_defs: Vec<String> = columns.iter().map(|(name, sql_type)| column_def(name, sql_type)).collect();
+    let names: Vec<&str> = columns.iter().map(|(name, _)| name.as_str()).collect();
+
+    let mut statements = vec![
+        format!(
+            "CREATE TABLE {history} (__rowid INTEGER NOT NULL, __valid_from REAL NOT NULL, __valid_to REAL, \
+             __operation TEXT NOT NULL, {})",
+            column_defs.join(", ")
+        ),
+        format!("CREATE INDEX {history}_rowid ON {history} (__rowid, __valid_to)"),
+    ];
+    statements.extend(history_triggers_sql(table_name, &names));
+    statements.push(seed_sql(table_name, &names));
+    statements
+}
+
+/// Statement recording the table's current rows as new versions
+pub fn seed_sql(table_name: &str, columns: &[&str]) -> String {
+    format!(
+        "INSERT INTO {} (__rowid, __valid_from, __operation, {1}) SELECT rowid, {NOW}, 'seed', {1} FROM {table_name}",
+        history_table_name(table_name),
+        columns.join(", ")
+    )
+}
+
+/// Statement ending every current version, before the rows are recorded
+/// again (a rebuilt table may number its rows differently)
+pub fn close_all_sql(table_name: &str) -> String {
+    format!("UPDATE {} SET __valid_to = {NOW} WHERE __valid_to IS NULL", history_table_name(table_name))
+}
+
+/// The triggers recording a version of a row each time it is written
+pub fn history_triggers_sql(table_name: &str, columns: &[&str]) -> Vec<String> {
+    let history = history_table_name(table_name);
+    let new_values: Vec<String> = columns.iter().map(|c| format!("new.{}", c)).collect();
+    let close = |row: &str| format!("UPDATE {history} SET __valid_to = {NOW} WHERE __rowid = {row}.rowid AND __valid_to IS NULL;");
+    let add_new = |operation: &str| {
+        format!(
+            "INSERT INTO {history} (__rowid, __valid_from, __operation, {}) VALUES (new.rowid, {NOW}, '{operation}', {});",
+            columns.join(", "),
+            new_values.join(", ")
+        )
+    };
+
+    // INSERT OR REPLACE reuses the rowid without firing the delete trigger
+    vec![
+        format!("CREATE TRIGGER {history}_ai AFTER INSERT ON {table_name} BEGIN {} {} END", close("new"), add_new("insert")),
+        format!("CREATE TRIGGER {history}_ad AFTER DELETE ON {table_name} BEGIN {} END", close("old")),
+        format!("CREATE TRIGGER {history}_au AFTER UPDATE ON {table_name} BEGIN {} {} END", close("old"), add_new("update")),
+    ]
+}
+
+/// Statements removing a table's history triggers; the versions are kept
+pub fn drop_history_triggers_sql(table_name: &str) -> Vec<String> {
+    let history = history_table_name(table_name);
+    ["ai", "ad", "au"]
+        .iter()
+        .map(|suffix| format!("DROP TRIGGER IF EXISTS {history}_{suffix}"))
+        .collect()
+}
+
+/// Half the resolution of the recorded times, for matching a change's time
+const TIME_TOLERANCE: f64 = 0.0005;
+
+/// The rows one statement inserted, updated and deleted in a table with
+/// history, identified by the table and the time it ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub table: String,
+    /// Unix time with milliseconds
+    pub time: f64,
+    pub inserted: u64,
+    pub updated: u64,
+    pub deleted: u64,
+}
+
+impl Change {
+    /// `<table>@<time>`, which [`parse_change_id`] reads back
+    pub fn id(&self) -> String {
+        change_id(&self.table, self.time)
+    }
+}
+
+pub fn change_id(table_name: &str, time: f64) -> String {
+    format!("{}@{:.3}", table_name, time)
+}
+
+/// Table and time of a change id
+pub fn parse_change_id(id: &str) -> Result<(String, f64)> {
+    id.rsplit_once('@')
+        .and_then(|(table, time)| Some((table.to_string(), time.parse::<f64>().ok()?)))
+        .filter(|(table, _)| !table.is_empty())
+        .ok_or_else(|| DatabaseError::QueryError(format!("Invalid change id '{}' (expected <table>@<time>)", id)))
+}
+
+/// Query listing the most recent changes to a table, at most `?1`, as
+/// time, inserted, updated and deleted. A row version that started at a
+/// change's time is its new value and one that ended then its old value;
+/// rows recorded by a seed are not changes.
+pub fn changes_sql(table_name: &str) -> String {
+    let history = history_table_name(table_name);
+    format!(
+        "WITH events AS (\
+            SELECT __rowid AS r, __valid_from AS t, 1 AS new, 0 AS old, __operation = 'seed' AS seed FROM {history} \
+            UNION ALL SELECT __rowid, __valid_to, 0, 1, 0 FROM {history} WHERE __valid_to IS NOT NULL), \
+         rows AS (SELECT t, MAX(new) AS new, MAX(old) AS old, MAX(seed) AS seed FROM events GROUP BY t, r) \
+         SELECT t, SUM(new AND NOT old), SUM(new AND old), SUM(old AND NOT new) FROM rows \
+         WHERE NOT seed GROUP BY t ORDER BY t DESC LIMIT ?1"
+    )
+}
+
+/// Query for the versions of a table's rows a change at time `?1` ended
+/// (`ended`) or started, as the rowid followed by `columns`
+pub fn change_versions_sql(table_name: &str, columns: &[&str], ended: bool) -> String {
+    let condition = if ended {
+        format!("abs(__valid_to - ?1) < {TIME_TOLERANCE} AND __valid_from < ?1 - {TIME_TOLERANCE}")
+    } else {
+        format!(
+            "abs(__valid_from - ?1) < {TIME_TOLERANCE} AND __operation != 'seed' \
+             AND (__valid_to IS NULL OR __valid_to > ?1 + {TIME_TOLERANCE})"
+        )
+    };
+    format!(
+        "SELECT __rowid, {} FROM {} WHERE {} ORDER BY __rowid",
+        columns.join(", "),
+        history_table_name(table_name),
+        condition
+    )
+}
+
+/// Statements reverting a change: for each rowid, the values the change
+/// ended (`old`) and those it wrote (`new`). Every statement matches the
+/// row only while it still holds what the change wrote, so one that
+/// changes no row means the row was changed again since.
+pub fn undo_sql(
+    table_name: &str,
+    columns: &[&str],
+    old: &HashMap<i64, Vec<DbValue>>,
+    new: &HashMap<i64, Vec<DbValue>>,
+) -> Vec<String> {
+    let unchanged = |rowid: i64, values: &[DbValue]| {
+        let mut conditions = vec![format!("rowid = {}", rowid)];
+        conditions.extend(columns.iter().zip(values).map(|(column, value)| format!("{} IS {}", column, literal(value))));
+        conditions.join(" AND ")
+    };
+
+    let mut rowids: Vec<i64> = old.keys().chain(new.keys()).copied().collect();
+    rowids.sort_unstable();
+    rowids.dedup();
+    let mut statements = Vec::with_capacity(rowids.len());
+    for rowid in rowids {
+        let statement = match (old.get(&rowid), new.get(&rowid)) {
+            (Some(old), Some(new)) => {
+                let assignments: Vec<String> =
+                    columns.iter().zip(old).map(|(column, value)| format!("{} = {}", column, literal(value))).collect();
+                format!("UPDATE {} SET {} WHERE {}", table_name, assignments.join(", "), unchanged(rowid, new))
+            }
+            (None, Some(new)) => format!("DELETE FROM {} WHERE {}", table_name, unchanged(rowid, new)),
+            (Some(old), None) => {
+                let values: Vec<String> = old.iter().map(literal).collect();
+                format!(
+                    "INSERT INTO {} (rowid, {}) VALUES ({}, {})",
+                    table_name,
+                    columns.join(", "),
+                    rowid,
+                    values.join(", ")
+                )
+            }
+            (None, None) => continue,
+        };
+        statements.push(statement);
+    }
+    statements
+}
+
+/// A value as an SQL literal
+pub fn literal(value: &DbValue) -> String {
+    match value {
+        DbValue::Integer(v) | DbValue::Timestamp(v) => v.to_string(),
+        DbValue::Real(v) => format!("{:?}", v),
+        DbValue::Text(v) => format!("'{}'", v.replace('\'', "''")),
+        DbValue::Blob(v) => {
+            let hex: String = v.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("X'{}'", hex)
+        }
+        DbValue::Boolean(v) => (*v as i32).to_string(),
+        DbValue::Null => "NULL".to_string(),
+    }
+}
+
+/// A history table column: the declared type without the table's constraints
+pub fn column_def(name: &str, sql_type: &str) -> String {
+    if sql_type.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} {}", name, sql_type)
+    }
+}
+
+/// An `AS OF` clause in a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsOf {
+    /// Byte range of `table AS OF timestamp`
+    pub range: Range<usize>,
+    /// Table name without quoting or schema
+    pub table: String,
+    /// The timestamp as written: a literal or a named/numbered parameter
+    pub timestamp: String,
+    /// Whether an alias follows the clause
+    pub aliased: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Word,
+    Literal,
+    Param,
+    Symbol,
+}
+
+/// Tokens of a statement with their byte ranges, without comments
+fn tokens(sql: &str) -> Vec<(Kind, Range<usize>)> {
+    let chars: Vec<(usize, char)> = sql.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let offset = |i: usize| chars.get(i).map(|(pos, _)| *pos).unwrap_or(sql.len());
+    let is_word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '.');
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(c) = at(i) {
+        let start = i;
+        let kind = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '-' if at(i + 1) == Some('-') => {
+                while at(i).is_some_and(|c| c != '\n') {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if at(i + 1) == Some('*') => {
+                i += 2;
+                while at(i).is_some() && !(at(i) == Some('*') && at(i + 1) == Some('/')) {
+                    i += 1;
+                }
+                i += 2;
+                continue;
+            }
+            '\'' | '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                i += 1;
+                while let Some(next) = at(i) {
+                    i += 1;
+                    if next == close {
+                        // A doubled quote is an escaped quote
+                        if close != ']' && at(i) == Some(close) {
+                            i += 1;
+                            continue;
+                        }
+                        break;
+                    }
+                }
+                // "main"."orders" is one name
+                while at(i) == Some('.') {
+                    i += 1;
+                    while at(i).is_some_and(|c| is_word(c) || matches!(c, '"' | '`')) {
+                        i += 1;
+                    }
+                }
+                if c == '\'' { Kind::Literal } else { Kind::Word }
+            }
+            '?' | ':' | '@' | '$' if at(i + 1).is_some_and(|c| c.is_alphanumeric() || c == '_') || c == '?' => {
+                i += 1;
+                while at(i).is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    i += 1;
+                }
+                Kind::Param
+            }
+            c if c.is_ascii_digit() => {
+                while at(i).is_some_and(|c| c.is_ascii_alphanumeric() || c == '.') {
+                    i += 1;
+                }
+                Kind::Literal
+            }
+            c if is_word(c) => {
+                while at(i).is_some_and(|c| is_word(c) || matches!(c, '"' | '`')) {
+                    i += 1;
+                }
+                Kind::Word
+            }
+            _ => {
+                i += 1;
+                Kind::Symbol
+            }
+        };
+        tokens.push((kind, offset(start)..offset(i)));
+    }
+    tokens
+}
+
+/// Words that end a table reference rather than name its alias
+fn is_clause_keyword(word: &str) -> bool {
+    matches!(
+        word.to_lowercase().as_str(),
+        "where" | "group" | "order" | "limit" | "having" | "join" | "inner" | "left" | "right" | "full"
+            | "cross" | "natural" | "on" | "using" | "union" | "except" | "intersect" | "window"
+    )
+}
+
+/// The table name in a reference, without schema or quoting
+fn unquote(name: &str) -> String {
+    let name = name.rsplit('.').next().unwrap_or(name);
+    name.trim_matches(|c| matches!(c, '"' | '`' | '[' | ']')).replace("\"\"", "\"")
+}
+
+/// The `AS OF` clauses of a query, in order
+pub fn find_as_of(sql: &str) -> Result<Vec<AsOf>> {
+    let tokens = tokens(sql);
+    let text = |i: usize| tokens.get(i).map(|(_, range)| &sql[range.clone()]);
+    let is = |i: usize, word: &str| {
+        tokens.get(i).is_some_and(|(kind, range)| *kind == Kind::Word && sql[range.clone()].eq_ignore_ascii_case(word))
+    };
+
+    let mut clauses = Vec::new();
+    for i in 1..tokens.len() {
+        if !(is(i, "as") && is(i + 1, "of")) || tokens[i - 1].0 != Kind::Word {
+            continue;
+        }
+        let Some((kind, range)) = tokens.get(i + 2) else {
+            return Err(DatabaseError::QueryError("AS OF needs a timestamp".to_string()));
+        };
+        let timestamp = &sql[range.clone()];
+        match kind {
+            // A bare ? is numbered by position, and the timestamp is used twice
+            Kind::Param if timestamp == "?" => {
+                return Err(DatabaseError::QueryError(
+                    "AS OF takes a literal or a named or numbered parameter (?1, :name), not ?".to_string(),
+                ));
+            }
+            Kind::Literal | Kind::Param => {}
+            // "SELECT x AS of FROM t": "of" is an alias
+            _ => continue,
+        }
+        let aliased = match tokens.get(i + 3) {
+            Some((Kind::Word, _)) => !text(i + 3).is_some_and(is_clause_keyword),
+            _ => false,
+        };
+        clauses.push(AsOf {
+            range: tokens[i - 1].1.start..range.end,
+            table: unquote(&sql[tokens[i - 1].1.clone()]),
+            timestamp: timestamp.to_string(),
+            aliased,
+        });
+    }
+    Ok(clauses)
+}
+
+/// The query with each `AS OF` clause replaced by the versions of its table
+/// current at that time. `columns` holds the column names of every table
+/// named in the clauses.
+pub fn rewrite_as_of(sql: &str, clauses: &[AsOf], columns: &HashMap<String, Vec<String>>) -> String {
+    let mut rewritten = String::with_capacity(sql.len() * 2);
+    let mut position = 0;
+    for clause in clauses {
+        let names = columns.get(&clause.table).map(|c| c.join(", ")).unwrap_or_else(|| "*".to_string());
+        // Text is parsed as a date and time; numbers are Unix seconds
+        let time = format!(
+            "(CASE typeof({0}) WHEN 'text' THEN unixepoch({0}, 'subsec') ELSE {0} END)",
+            clause.timestamp
+        );
+        rewritten.push_str(&sql[position..clause.range.start]);
+        rewritten.push_str(&format!(
+            "(SELECT {} FROM {} WHERE __valid_from <= {time} AND (__valid_to IS NULL OR __valid_to > {time}))",
+            names,
+            history_table_name(&clause.table),
+        ));
+        if !clause.aliased {
+            rewritten.push_str(&format!(" AS \"{}\"", clause.table.replace('"', "\"\"")));
+        }
+        position = clause.range.end;
+    }
+    rewritten.push_str(&sql[position..]);
+    rewritten
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_as_of() {
+        let sql = "SELECT * FROM orders AS OF '2024-06-01 12:00:00' o JOIN users AS OF :at ON o.user_id = users.id";
+        let clauses = find_as_of(sql).unwrap();
+        assert_eq!(clauses.len(), 2);
+        assert_eq!(&sql[clauses[0].range.clone()], "orders AS OF '2024-06-01 12:00:00'");
+        assert_eq!(clauses[0].timestamp, "'2024-06-01 12:00:00'");
+        assert!(clauses[0].aliased);
+        assert_eq!(clauses[1].table, "users");
+        assert_eq!(clauses[1].timestamp, ":at");
+        assert!(!clauses[1].aliased);
+
+        let clauses = find_as_of("SELECT * FROM main.\"Orders\" as of 1717200000 WHERE id = 1").unwrap();
+        assert_eq!(clauses[0].table, "Orders");
+        assert!(!clauses[0].aliased);
+
+        // An alias named "of", and AS OF inside literals and comments
+        assert!(find_as_of("SELECT total AS of FROM orders").unwrap().is_empty());
+        assert!(find_as_of("SELECT 'orders AS OF 1' -- t AS OF 2\nFROM t").unwrap().is_empty());
+        assert!(find_as_of("SELECT * FROM orders AS OF ?").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_as_of() {
+        let sql = "SELECT * FROM orders AS OF 1717200000 WHERE total > 10";
+        let clauses = find_as_of(sql).unwrap();
+        let columns = HashMap::from([("orders".to_string(), vec!["id".to_string(), "total".to_string()])]);
+        let time = "(CASE typeof(1717200000) WHEN 'text' THEN unixepoch(1717200000, 'subsec') ELSE 1717200000 END)";
+        assert_eq!(
+            rewrite_as_of(sql, &clauses, &columns),
+            format!(
+                "SELECT * FROM (SELECT id, total FROM orders__history WHERE __valid_from <= {time} \
+                 AND (__valid_to IS NULL OR __valid_to > {time})) AS \"orders\" WHERE total > 10"
+            )
+        );
+    }
+
+    #[test]
+    fn test_change_ids() {
+        let change = Change { table: "prices".to_string(), time: 1717243200.1234, inserted: 1, updated: 0, deleted: 0 };
+        assert_eq!(change.id(), "prices@1717243200.123");
+        assert_eq!(parse_change_id("prices@1717243200.123").unwrap(), ("prices".to_string(), 1717243200.123));
+        assert!(parse_change_id("prices").is_err());
+        assert!(parse_change_id("@12").is_err());
+    }
+
+    #[test]
+    fn test_undo_sql() {
+        let old = HashMap::from([
+            (1, vec![DbValue::Text("it's".to_string()), DbValue::Real(2.5)]),
+            (2, vec![DbValue::Text("gone".to_string()), DbValue::Null]),
+        ]);
+        let new = HashMap::from([
+            (1, vec![DbValue::Text("tea".to_string()), DbValue::Real(3.0)]),
+            (3, vec![DbValue::Text("new".to_string()), DbValue::Blob(vec![0xCA, 0xFE])]),
+        ]);
+        assert_eq!(
+            undo_sql("prices", &["item", "amount"], &old, &new),
+            [
+                "UPDATE prices SET item = 'it''s', amount = 2.5 WHERE rowid = 1 AND item IS 'tea' AND amount IS 3.0",
+                "INSERT INTO prices (rowid, item, amount) VALUES (2, 'gone', NULL)",
+                "DELETE FROM prices WHERE rowid = 3 AND item IS 'new' AND amount IS X'CAFE'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_sql() {
+        let columns = vec![("id".to_string(), "INTEGER".to_string()), ("note".to_string(), String::new())];
+        let statements = history_sql("notes", &columns);
+        assert_eq!(
+            statements[0],
+            "CREATE TABLE notes__history (__rowid INTEGER NOT NULL, __valid_from REAL NOT NULL, __valid_to REAL, \
+             __operation TEXT NOT NULL, id INTEGER, note)"
+        );
+        assert!(statements[2].starts_with("CREATE TRIGGER notes__history_ai AFTER INSERT ON notes"));
+        assert!(statements.last().unwrap().ends_with("SELECT rowid, unixepoch('subsec'), 'seed', id, note FROM notes"));
+        assert_eq!(drop_history_triggers_sql("notes")[0], "DROP TRIGGER IF EXISTS notes__history_ai");
+    }
+}
@@ -1,9 +1,17 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 
-use super::{Database, SqliteDatabase, DatabaseError};
+use super::traits::{ConnectionStats, DiskUsage};
+use super::{secrets, Database, DatabaseError, DatabaseOptions, QueryCache, SqliteDatabase};
+
+/// How often the view refresher looks for materialized views that are due
+const VIEW_REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Clone)]
 pub struct DatabaseInfo {
@@ -11,40 +19,296 @@ pub struct DatabaseInfo {
     pub url: String,
     pub connected: bool,
     pub connection_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub options: DatabaseOptions,
+    /// Whether this database is saved to the registry file and restored on restart
+    pub persistent: bool,
+    /// Problems found when the database was opened; a database with any is
+    /// degraded and may be serving corrupt data
+    pub problems: Vec<String>,
 }
 
 pub struct DatabaseManager {
     databases: Arc<RwLock<HashMap<String, DatabaseConnection>>>,
+    registry_path: Option<PathBuf>,
+    /// Rows deleted by the TTL reaper, per database
+    ttl_purged: std::sync::Mutex<HashMap<String, u64>>,
+    /// Registry entries that failed to restore or were shadowed by a startup
+    /// database; saved back unchanged so they are not lost
+    unrestored: std::sync::Mutex<Vec<RegistryEntry>>,
+    /// Held while the registry file is written, so concurrent saves do not
+    /// share its temporary file and the last one written is the newest
+    registry_write: tokio::sync::Mutex<()>,
+}
+
+/// A database entry as stored in the registry file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RegistryEntry {
+    name: String,
+    url: String,
+    #[serde(default)]
+    options: DatabaseOptions,
 }
 
 struct DatabaseConnection {
     info: DatabaseInfo,
-    db: Arc<RwLock<Box<dyn Database>>>,
+    /// None while the connection pool is closed because the database was idle
+    db: Option<SharedDatabase>,
+    /// Pools of the read replicas in `info.options`, open while `db` is
+    replicas: Vec<SharedDatabase>,
+    /// Turn of the replica the next read goes to
+    next_replica: AtomicUsize,
+    last_used: std::sync::Mutex<Instant>,
     _handle: JoinHandle<()>,
 }
 
+impl DatabaseConnection {
+    fn touch(&self) {
+        if let Ok(mut last_used) = self.last_used.lock() {
+            *last_used = Instant::now();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used
+            .lock()
+            .map(|last_used| last_used.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+type SharedDatabase = Arc<RwLock<Box<dyn Database>>>;
+
+/// Open a connection pool for a database, resolving the secret references
+/// in its URL
+async fn open_database(url: &str, options: &DatabaseOptions) -> Result<SharedDatabase, DatabaseError> {
+    let db = SqliteDatabase::connect_with_options(&secrets::resolve(url)?, options).await?;
+    Ok(Arc::new(RwLock::new(Box::new(db) as Box<dyn Database>)))
+}
+
+/// Open a pool for each read replica of a database. Replicas are opened
+/// read-only unless their URL says otherwise, and their journal mode is
+/// left to the process that keeps them up to date.
+async fn open_replicas(options: &DatabaseOptions) -> Result<Vec<SharedDatabase>, DatabaseError> {
+    let mut replica_options = options.clone();
+    replica_options.pragmas.retain(|pragma, _| !pragma.eq_ignore_ascii_case("journal_mode"));
+    let mut replicas = Vec::new();
+    for url in &options.replicas {
+        let url = if url.starts_with("sqlite://") && !url.contains('?') { format!("{}?mode=ro", url) } else { url.clone() };
+        let replica = open_database(&url, &replica_options)
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(format!("Replica {}: {}", secrets::redact_url(&url), e)))?;
+        replicas.push(replica);
+    }
+    Ok(replicas)
+}
+
+/// Whether a SQLite URL names a database with no backing file, whose data is
+/// lost once its pool closes
+fn is_in_memory(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("sqlite://").or_else(|| url.strip_prefix("sqlite:")) else {
+        return false;
+    };
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    path.is_empty() || path == ":memory:" || query.split('&').any(|option| option == "mode=memory")
+}
+
+/// Journal files of a SQLite URL's database that hold data. SQLite removes
+/// them when the last connection closes cleanly, so finding one before the
+/// database is opened means a process stopped mid-write (or still has the
+/// database open).
+fn leftover_journals(url: &str) -> Vec<PathBuf> {
+    let Some(path) = url.strip_prefix("sqlite://").or_else(|| url.strip_prefix("sqlite:")) else {
+        return Vec::new();
+    };
+    if is_in_memory(url) {
+        return Vec::new();
+    }
+    let path = path.split('?').next().unwrap_or_default();
+    ["-wal", "-journal"]
+        .iter()
+        .map(|suffix| PathBuf::from(format!("{}{}", path, suffix)))
+        .filter(|journal| journal.metadata().is_ok_and(|m| m.len() > 0))
+        .collect()
+}
+
+/// Finish recovery of a database that was not closed cleanly and run a quick
+/// check of it, returning the problems found. Opening it already rolled back
+/// a hot rollback journal; a leftover WAL is checkpointed into the database.
+async fn verify_database(name: &str, db: &SharedDatabase, leftovers: &[PathBuf]) -> Vec<String> {
+    let db = db.read().await;
+    if !leftovers.is_empty() {
+        let files: Vec<String> = leftovers.iter().map(|path| path.display().to_string()).collect();
+        tracing::warn!("Database '{}' was not closed cleanly, recovering from {}", name, files.join(", "));
+        if leftovers.iter().any(|path| path.to_string_lossy().ends_with("-wal")) {
+            if let Err(e) = db.execute("PRAGMA wal_checkpoint(TRUNCATE)").await {
+                tracing::error!("Recovery of database '{}' failed: {}", name, e);
+                return vec![format!("Recovery failed: {}", e)];
+            }
+        }
+    }
+
+    let problems = db.quick_check().await.unwrap_or_else(|e| vec![format!("quick_check failed: {}", e)]);
+    if problems.is_empty() {
+        if !leftovers.is_empty() {
+            tracing::info!("Database '{}' recovered and passed quick_check", name);
+        }
+    } else {
+        tracing::error!("Database '{}' is degraded: {}", name, problems.join("; "));
+    }
+    problems
+}
+
 impl DatabaseManager {
     pub fn new() -> Self {
         Self {
             databases: Arc::new(RwLock::new(HashMap::new())),
+            registry_path: None,
+            ttl_purged: Default::default(),
+            unrestored: Default::default(),
+            registry_write: Default::default(),
+        }
+    }
+
+    /// Create a manager that persists dynamically added databases to a registry file
+    pub fn with_registry(path: impl Into<PathBuf>) -> Self {
+        Self {
+            databases: Arc::new(RwLock::new(HashMap::new())),
+            registry_path: Some(path.into()),
+            ttl_purged: Default::default(),
+            unrestored: Default::default(),
+            registry_write: Default::default(),
         }
     }
 
+    /// Reconnect the databases recorded in the registry file.
+    ///
+    /// Entries whose name is already registered are skipped, so databases
+    /// configured at startup take precedence. Returns the number restored.
+    pub async fn load_registry(&self) -> Result<usize, DatabaseError> {
+        let Some(path) = &self.registry_path else {
+            return Ok(0);
+        };
+        if !path.exists() {
+            return Ok(0);
+        }
+
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| DatabaseError::Other(format!("Failed to read registry {}: {}", path.display(), e)))?;
+        let entries: Vec<RegistryEntry> = serde_json::from_str(&content)
+            .map_err(|e| DatabaseError::Other(format!("Invalid registry {}: {}", path.display(), e)))?;
+
+        let mut restored = 0;
+        let mut unrestored = Vec::new();
+        for entry in entries {
+            if self.databases.read().await.contains_key(&entry.name) {
+                unrestored.push(entry);
+                continue;
+            }
+            match self.connect_database(entry.name.clone(), entry.url.clone(), entry.options.clone(), true).await {
+                Ok(_) => restored += 1,
+                Err(e) => {
+                    tracing::warn!("Failed to restore database '{}': {}", entry.name, e);
+                    unrestored.push(entry);
+                }
+            }
+        }
+        if let Ok(mut kept) = self.unrestored.lock() {
+            *kept = unrestored;
+        }
+
+        Ok(restored)
+    }
+
+    /// Write all persistent databases to the registry file, along with the
+    /// entries that were not restored
+    async fn save_registry(&self) -> Result<(), DatabaseError> {
+        let Some(path) = &self.registry_path else {
+            return Ok(());
+        };
+        // Taken before reading the entries, so a save that waited writes
+        // what changed meanwhile
+        let _writing = self.registry_write.lock().await;
+
+        let mut entries: Vec<RegistryEntry> = {
+            let databases = self.databases.read().await;
+            databases
+                .values()
+                .filter(|conn| conn.info.persistent)
+                .map(|conn| RegistryEntry {
+                    name: conn.info.name.clone(),
+                    url: conn.info.url.clone(),
+                    options: conn.info.options.clone(),
+                })
+                .collect()
+        };
+        if let Ok(unrestored) = self.unrestored.lock() {
+            // A database added since under the same name replaces the entry
+            let kept: Vec<RegistryEntry> =
+                unrestored.iter().filter(|entry| entries.iter().all(|e| e.name != entry.name)).cloned().collect();
+            entries.extend(kept);
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let content = serde_json::to_string_pretty(&entries)
+            .map_err(|e| DatabaseError::Other(e.to_string()))?;
+        write_atomically(path, &content)
+            .await
+            .map_err(|e| DatabaseError::Other(format!("Failed to write registry {}: {}", path.display(), e)))
+    }
+
     /// Add or connect to a database
     pub async fn add_database(&self, name: String, url: String) -> Result<(), DatabaseError> {
-        let mut databases = self.databases.write().await;
-        
+        self.add_database_with_options(name, url, DatabaseOptions::default()).await
+    }
+
+    /// Add or connect to a database using the given connection options
+    pub async fn add_database_with_options(
+        &self,
+        name: String,
+        url: String,
+        options: DatabaseOptions,
+    ) -> Result<(), DatabaseError> {
+        self.connect_database(name, url, options, false).await
+    }
+
+    /// Add a database and record it in the registry file so it survives restarts
+    pub async fn add_persistent_database(
+        &self,
+        name: String,
+        url: String,
+        options: DatabaseOptions,
+    ) -> Result<(), DatabaseError> {
+        self.connect_database(name, url, options, true).await?;
+        self.save_registry().await
+    }
+
+    async fn connect_database(
+        &self,
+        name: String,
+        url: String,
+        options: DatabaseOptions,
+        persistent: bool,
+    ) -> Result<(), DatabaseError> {
         // Don't add if already exists
+        if self.databases.read().await.contains_key(&name) {
+            return Ok(());
+        }
+
+        // Open and check the database without holding the lock, which would
+        // stall requests for every other database meanwhile
+        let leftovers = leftover_journals(&secrets::resolve(&url)?);
+        let db_arc = open_database(&url, &options).await?;
+        let replicas = open_replicas(&options).await?;
+        let problems = verify_database(&name, &db_arc, &leftovers).await;
+
+        let mut databases = self.databases.write().await;
+        // Another request may have added it while it was being opened
         if databases.contains_key(&name) {
             return Ok(());
         }
 
-        // Create database connection
-        let db = SqliteDatabase::connect(&url).await?;
-        let db_arc = Arc::new(RwLock::new(Box::new(db) as Box<dyn Database>));
-        
         // Create a background task for the database connection
-        let _db_clone = db_arc.clone();
         let handle = tokio::spawn(async move {
             // Keep connection alive and handle any background tasks
             loop {
@@ -58,11 +322,17 @@ impl DatabaseManager {
             url: url.clone(),
             connected: true,
             connection_time: Some(chrono::Utc::now()),
+            options,
+            persistent,
+            problems,
         };
 
         let connection = DatabaseConnection {
             info,
-            db: db_arc,
+            db: Some(db_arc),
+            replicas,
+            next_replica: AtomicUsize::new(0),
+            last_used: std::sync::Mutex::new(Instant::now()),
             _handle: handle,
         };
 
@@ -70,14 +340,74 @@ impl DatabaseManager {
         Ok(())
     }
 
-    /// Get a database connection by name
-    pub async fn get_database(&self, name: &str) -> Option<Arc<RwLock<Box<dyn Database>>>> {
+    /// Get a database connection by name, reconnecting if it was closed while idle
+    pub async fn get_database(&self, name: &str) -> Option<SharedDatabase> {
+        {
+            let databases = self.databases.read().await;
+            let conn = databases.get(name)?;
+            conn.touch();
+            if let Some(db) = &conn.db {
+                return Some(db.clone());
+            }
+        }
+
+        self.reconnect(name).await
+    }
+
+    /// The replica the next read of a database goes to, taking turns; None if
+    /// the database has no replicas or its pools are closed
+    pub async fn get_replica(&self, name: &str) -> Option<SharedDatabase> {
         let databases = self.databases.read().await;
-        databases.get(name).map(|conn| conn.db.clone())
+        let conn = databases.get(name)?;
+        if conn.replicas.is_empty() {
+            return None;
+        }
+        let turn = conn.next_replica.fetch_add(1, Ordering::Relaxed);
+        Some(conn.replicas[turn % conn.replicas.len()].clone())
+    }
+
+    /// Reopen the pool of a database that was evicted for inactivity
+    async fn reconnect(&self, name: &str) -> Option<SharedDatabase> {
+        let (url, options) = {
+            let databases = self.databases.read().await;
+            let conn = databases.get(name)?;
+            if let Some(db) = &conn.db {
+                return Some(db.clone());
+            }
+            (conn.info.url.clone(), conn.info.options.clone())
+        };
+
+        // Opened without the lock, so other databases' requests carry on meanwhile
+        let opened = match open_database(&url, &options).await {
+            Ok(db) => open_replicas(&options).await.map(|replicas| (db, replicas)),
+            Err(e) => Err(e),
+        };
+
+        let mut databases = self.databases.write().await;
+        let conn = databases.get_mut(name)?;
+        // Another request may have reconnected it meanwhile
+        if let Some(db) = &conn.db {
+            return Some(db.clone());
+        }
+        match opened {
+            Ok((db, replicas)) => {
+                tracing::info!("Reconnected idle database '{}'", name);
+                conn.db = Some(db.clone());
+                conn.replicas = replicas;
+                conn.info.connected = true;
+                conn.info.connection_time = Some(chrono::Utc::now());
+                conn.touch();
+                Some(db)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to reconnect database '{}': {}", name, e);
+                None
+            }
+        }
     }
-    
+
     /// Get a database by name or return the default if name is None/empty
-    pub async fn get_database_or_default(&self, name: Option<&str>) -> Option<Arc<RwLock<Box<dyn Database>>>> {
+    pub async fn get_database_or_default(&self, name: Option<&str>) -> Option<SharedDatabase> {
         match name {
             Some(n) if !n.is_empty() => self.get_database(n).await,
             _ => self.get_default_database().await,
@@ -85,16 +415,239 @@ impl DatabaseManager {
     }
 
     /// Get the default database (first one added or "default")
-    pub async fn get_default_database(&self) -> Option<Arc<RwLock<Box<dyn Database>>>> {
+    pub async fn get_default_database(&self) -> Option<SharedDatabase> {
+        let name = self.resolve_name(None).await?;
+        self.get_database(&name).await
+    }
+
+    /// The name a request for `name` resolves to, following the same default
+    /// rules as `get_database_or_default`
+    pub async fn resolve_name(&self, name: Option<&str>) -> Option<String> {
         let databases = self.databases.read().await;
-        
-        // Try "default" first
-        if let Some(conn) = databases.get("default") {
-            return Some(conn.db.clone());
+        match name {
+            Some(n) if !n.is_empty() => databases.contains_key(n).then(|| n.to_string()),
+            // Try "default" first, otherwise use the first database
+            _ if databases.contains_key("default") => Some("default".to_string()),
+            _ => databases.keys().next().cloned(),
         }
-        
-        // Otherwise return the first database
-        databases.values().next().map(|conn| conn.db.clone())
+    }
+
+    /// Close the pools of databases that have not been used for `idle_timeout`.
+    ///
+    /// Evicted databases stay registered and are reopened on next access.
+    /// In-memory databases are never evicted, since closing their pool would
+    /// discard their data. Returns the names of the databases that were closed.
+    pub async fn evict_idle(&self, idle_timeout: Duration) -> Vec<String> {
+        let mut databases = self.databases.write().await;
+        let mut evicted = Vec::new();
+
+        for (name, conn) in databases.iter_mut() {
+            if conn.db.is_some() && conn.idle_for() >= idle_timeout && !is_in_memory(&conn.info.url) {
+                // In-flight requests keep their own handle; the pool closes once they finish
+                conn.db = None;
+                conn.replicas.clear();
+                conn.info.connected = false;
+                evicted.push(name.clone());
+            }
+        }
+
+        evicted
+    }
+
+    /// Spawn a background task that periodically evicts idle databases
+    pub fn start_idle_eviction(self: &Arc<Self>, idle_timeout: Duration) -> JoinHandle<()> {
+        let manager = Arc::clone(self);
+        let check_interval = (idle_timeout / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(check_interval).await;
+                for name in manager.evict_idle(idle_timeout).await {
+                    tracing::info!("Closed idle database '{}'", name);
+                }
+            }
+        })
+    }
+
+    /// Delete expired rows from every connected database, returning
+    /// `(database, table, rows deleted)` for each table that had expired rows
+    pub async fn purge_expired(&self) -> Vec<(String, String, u64)> {
+        // Idle databases are purged once they are reopened
+        let connected: Vec<(String, SharedDatabase)> = {
+            let databases = self.databases.read().await;
+            databases
+                .iter()
+                .filter_map(|(name, conn)| conn.db.clone().map(|db| (name.clone(), db)))
+                .collect()
+        };
+
+        let mut purged = Vec::new();
+        for (name, db) in connected {
+            match db.read().await.purge_expired().await {
+                Ok(tables) => {
+                    let total: u64 = tables.iter().map(|(_, deleted)| deleted).sum();
+                    if total > 0 {
+                        if let Ok(mut counts) = self.ttl_purged.lock() {
+                            *counts.entry(name.clone()).or_default() += total;
+                        }
+                    }
+                    purged.extend(tables.into_iter().map(|(table, deleted)| (name.clone(), table, deleted)));
+                }
+                Err(e) => tracing::warn!(database = %name, error = %e, "Failed to purge expired rows"),
+            }
+        }
+        purged
+    }
+
+    /// Drop partitions past their table's retention in every connected
+    /// database, returning `(database, table, partition)` for each one dropped
+    pub async fn drop_expired_partitions(&self) -> Vec<(String, String, String)> {
+        let connected: Vec<(String, SharedDatabase)> = {
+            let databases = self.databases.read().await;
+            databases
+                .iter()
+                .filter_map(|(name, conn)| conn.db.clone().map(|db| (name.clone(), db)))
+                .collect()
+        };
+
+        let mut dropped = Vec::new();
+        for (name, db) in connected {
+            match db.read().await.drop_expired_partitions().await {
+                Ok(partitions) => dropped.extend(
+                    partitions.into_iter().map(|(table, partition)| (name.clone(), table, partition)),
+                ),
+                Err(e) => tracing::warn!(database = %name, error = %e, "Failed to drop expired partitions"),
+            }
+        }
+        dropped
+    }
+
+    /// Rows deleted by the TTL reaper since startup, per database
+    pub fn ttl_purged(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self
+            .ttl_purged
+            .lock()
+            .map(|counts| counts.iter().map(|(name, count)| (name.clone(), *count)).collect())
+            .unwrap_or_default();
+        counts.sort();
+        counts
+    }
+
+    /// Spawn a background task that deletes expired rows and partitions past
+    /// their retention every `interval`, dropping cached results of the tables
+    /// it changed
+    pub fn start_ttl_reaper(self: &Arc<Self>, interval: Duration, cache: Option<Arc<QueryCache>>) -> JoinHandle<()> {
+        let manager = Arc::clone(self);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                for (database, table, deleted) in manager.purge_expired().await {
+                    tracing::info!(database = %database, table = %table, deleted_rows = deleted, "Purged expired rows");
+                    if let Some(cache) = &cache {
+                        cache.invalidate_table(&database, &table);
+                    }
+                }
+                for (database, table, partition) in manager.drop_expired_partitions().await {
+                    tracing::info!(database = %database, table = %table, partition = %partition, "Dropped expired partition");
+                    if let Some(cache) = &cache {
+                        cache.invalidate_table(&database, &table);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Refresh the materialized views whose interval has elapsed in every
+    /// connected database, returning `(database, view, rows)` for each refreshed view
+    pub async fn refresh_due_views(&self) -> Vec<(String, String, u64)> {
+        // Idle databases catch up once they are reopened
+        let connected: Vec<(String, SharedDatabase)> = {
+            let databases = self.databases.read().await;
+            databases
+                .iter()
+                .filter_map(|(name, conn)| conn.db.clone().map(|db| (name.clone(), db)))
+                .collect()
+        };
+
+        let mut refreshed = Vec::new();
+        for (name, db) in connected {
+            match db.read().await.refresh_due_materialized_views().await {
+                Ok(views) => {
+                    for (view, result) in views {
+                        match result {
+                            Ok(rows) => refreshed.push((name.clone(), view, rows)),
+                            Err(e) => tracing::warn!(database = %name, view = %view, error = %e, "Failed to refresh materialized view"),
+                        }
+                    }
+                }
+                Err(e) => tracing::warn!(database = %name, error = %e, "Failed to check materialized views"),
+            }
+        }
+        refreshed
+    }
+
+    /// Spawn a background task that refreshes materialized views when their
+    /// interval elapses, dropping cached results that read from them
+    pub fn start_view_refresher(self: &Arc<Self>, cache: Option<Arc<QueryCache>>) -> JoinHandle<()> {
+        let manager = Arc::clone(self);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(VIEW_REFRESH_CHECK_INTERVAL).await;
+                for (database, view, rows) in manager.refresh_due_views().await {
+                    tracing::info!(database = %database, view = %view, rows, "Refreshed materialized view");
+                    if let Some(cache) = &cache {
+                        cache.invalidate_table(&database, &view);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Set a PRAGMA on every connection of a database. The setting joins the
+    /// database's options and its pool is reopened, so that connections opened
+    /// later apply it too; requests already running finish on the old pool.
+    /// Persistent databases keep the setting across restarts.
+    pub async fn set_pragma(&self, name: &str, pragma: &str, value: &str) -> Result<(), DatabaseError> {
+        let not_found = || DatabaseError::Other(format!("Database '{}' not found", name));
+        let persistent = loop {
+            let (url, current) = {
+                let databases = self.databases.read().await;
+                let conn = databases.get(name).ok_or_else(not_found)?;
+                (conn.info.url.clone(), conn.info.options.clone())
+            };
+
+            // The new pools are opened without the lock, then swapped in
+            let mut options = current.clone();
+            options.pragmas.insert(pragma.to_string(), value.to_string());
+            let db = open_database(&url, &options).await?;
+            let replicas = open_replicas(&options).await?;
+
+            let mut databases = self.databases.write().await;
+            let conn = databases.get_mut(name).ok_or_else(not_found)?;
+            // Another SET_PRAGMA changed the options meanwhile; build on its result
+            if conn.info.options != current {
+                continue;
+            }
+            conn.db = Some(db);
+            conn.replicas = replicas;
+            conn.info.options = options;
+            conn.info.connected = true;
+            conn.info.connection_time = Some(chrono::Utc::now());
+            conn.touch();
+            break conn.info.persistent;
+        };
+
+        if persistent {
+            self.save_registry().await?;
+        }
+        Ok(())
+    }
+
+    /// Status of one database
+    pub async fn database_info(&self, name: &str) -> Option<DatabaseInfo> {
+        self.databases.read().await.get(name).map(|conn| conn.info.clone())
     }
 
     /// List all databases and their status
@@ -103,10 +656,67 @@ impl DatabaseManager {
         databases.values().map(|conn| conn.info.clone()).collect()
     }
 
-    /// Remove a database connection
+    /// Pool connection counts of every connected database, keyed by name
+    pub async fn connection_stats(&self) -> HashMap<String, ConnectionStats> {
+        let connected: Vec<(String, SharedDatabase)> = {
+            let databases = self.databases.read().await;
+            databases
+                .iter()
+                .filter_map(|(name, conn)| conn.db.clone().map(|db| (name.clone(), db)))
+                .collect()
+        };
+
+        let mut stats = HashMap::new();
+        for (name, db) in connected {
+            if let Some(db_stats) = db.read().await.connection_stats() {
+                stats.insert(name, db_stats);
+            }
+        }
+        stats
+    }
+
+    /// Disk usage of every connected database, keyed by name
+    pub async fn disk_usage(&self) -> HashMap<String, DiskUsage> {
+        let connected: Vec<(String, SharedDatabase)> = {
+            let databases = self.databases.read().await;
+            databases
+                .iter()
+                .filter_map(|(name, conn)| conn.db.clone().map(|db| (name.clone(), db)))
+                .collect()
+        };
+
+        let mut usage = HashMap::new();
+        for (name, db) in connected {
+            match db.read().await.disk_usage().await {
+                Ok(db_usage) => {
+                    usage.insert(name, db_usage);
+                }
+                Err(e) => tracing::warn!(database = %name, error = %e, "Failed to read disk usage"),
+            }
+        }
+        usage
+    }
+
+    /// Remove a database connection, or a registry entry that was not
+    /// restored; false if the name has neither
     pub async fn remove_database(&self, name: &str) -> bool {
-        let mut databases = self.databases.write().await;
-        databases.remove(name).is_some()
+        let removed = {
+            let mut databases = self.databases.write().await;
+            databases.remove(name)
+        };
+        // Removing a name also forgets a registry entry it shadowed
+        let forgotten = self.unrestored.lock().is_ok_and(|mut unrestored| {
+            let before = unrestored.len();
+            unrestored.retain(|entry| entry.name != name);
+            unrestored.len() < before
+        });
+
+        if removed.as_ref().is_some_and(|conn| conn.info.persistent) || forgotten {
+            if let Err(e) = self.save_registry().await {
+                tracing::warn!("{}", e);
+            }
+        }
+        removed.is_some() || forgotten
     }
 
     /// Get database count
@@ -120,4 +730,16 @@ impl Default for DatabaseManager {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Write a file via a temporary sibling and rename, so a crash never leaves it half-written
+async fn write_atomically(path: &Path, content: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+    }
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, content).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
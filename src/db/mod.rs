@@ -1,9 +1,24 @@
+pub mod advisor;
+pub mod cache;
+pub mod diff;
+pub mod encryption;
 pub mod error;
+pub mod functions;
+pub mod history;
 pub mod sqlite;
 pub mod traits;
 pub mod manager;
+pub mod options;
+pub mod params;
+pub mod partition;
+pub mod scheduler;
+pub mod secrets;
+pub mod sql_log;
 
+pub use cache::QueryCache;
+pub use encryption::ColumnCipher;
 pub use error::DatabaseError;
 pub use sqlite::SqliteDatabase;
 pub use traits::Database;
 pub use manager::{DatabaseManager, DatabaseInfo};
+pub use options::{DatabaseOptions, SqliteExtension};
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Connection-level options applied when a database is opened
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseOptions {
+    /// PRAGMA settings applied to every pooled connection (e.g. journal_mode = "WAL")
+    #[serde(default)]
+    pub pragmas: BTreeMap<String, String>,
+
+    /// Prepared statements kept per pooled connection (sqlx default: 100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement_cache_capacity: Option<usize>,
+
+    /// SQLite extensions loaded into every pooled connection, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<SqliteExtension>,
+
+    /// Enforce REFERENCES constraints on every pooled connection (default: on)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foreign_keys: Option<bool>,
+
+    /// URLs of read replicas: copies of the database that another process
+    /// keeps up to date (e.g. Litestream or LiteFS). Reads are spread over
+    /// them; writes always go to the database itself.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replicas: Vec<String>,
+
+    /// Most rows a query returns; further rows are dropped and the result is
+    /// marked truncated (overrides the server's `max_query_rows`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_query_rows: Option<usize>,
+
+    /// Rows returned by a SELECT without a LIMIT clause (overrides the
+    /// server's `default_query_limit`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_query_limit: Option<usize>,
+}
+
+/// A loadable SQLite extension: either just a path (`"/usr/lib/spellfix.so"`)
+/// or a table with an explicit entry point
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SqliteExtension {
+    Path(String),
+    WithEntryPoint {
+        path: String,
+        entry_point: Option<String>,
+    },
+}
+
+impl SqliteExtension {
+    pub fn path(&self) -> &str {
+        match self {
+            SqliteExtension::Path(path) => path,
+            SqliteExtension::WithEntryPoint { path, .. } => path,
+        }
+    }
+
+    /// Entry point symbol; None lets SQLite derive it from the file name
+    pub fn entry_point(&self) -> Option<&str> {
+        match self {
+            SqliteExtension::Path(_) => None,
+            SqliteExtension::WithEntryPoint { entry_point, .. } => entry_point.as_deref(),
+        }
+    }
+}
+
+impl DatabaseOptions {
+    /// Layer `overrides` on top of these options; values in `overrides` win
+    pub fn merged_with(&self, overrides: &DatabaseOptions) -> DatabaseOptions {
+        let mut pragmas = self.pragmas.clone();
+        pragmas.extend(overrides.pragmas.clone());
+        // Extensions accumulate; one listed at both levels is loaded once
+        let mut extensions = self.extensions.clone();
+        for extension in &overrides.extensions {
+            extensions.retain(|e| e.path() != extension.path());
+            extensions.push(extension.clone());
+        }
+        DatabaseOptions {
+            pragmas,
+            statement_cache_capacity: overrides
+                .statement_cache_capacity
+                .or(self.statement_cache_capacity),
+            extensions,
+            foreign_keys: overrides.foreign_keys.or(self.foreign_keys),
+            // Replicas belong to one database, so they are replaced rather than combined
+            replicas: if overrides.replicas.is_empty() { self.replicas.clone() } else { overrides.replicas.clone() },
+            max_query_rows: overrides.max_query_rows.or(self.max_query_rows),
+            default_query_limit: overrides.default_query_limit.or(self.default_query_limit),
+        }
+    }
+}
+
+/// PRAGMAs that can be read and changed on a running database
+/// (`datasink server pragma`), with the values each accepts; an empty list
+/// accepts any integer
+const RUNTIME_PRAGMAS: &[(&str, &[&str])] = &[
+    ("journal_mode", &["DELETE", "TRUNCATE", "PERSIST", "MEMORY", "WAL", "OFF"]),
+    ("synchronous", &["OFF", "NORMAL", "FULL", "EXTRA", "0", "1", "2", "3"]),
+    ("temp_store", &["DEFAULT", "FILE", "MEMORY", "0", "1", "2"]),
+    ("cache_size", &[]),
+    ("mmap_size", &[]),
+    ("wal_autocheckpoint", &[]),
+];
+
+/// The lowercase name of a PRAGMA that can be read or changed at runtime
+pub fn runtime_pragma(name: &str) -> Result<&'static str, String> {
+    let name = name.trim().to_lowercase();
+    RUNTIME_PRAGMAS
+        .iter()
+        .map(|(pragma, _)| *pragma)
+        .find(|pragma| *pragma == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = RUNTIME_PRAGMAS.iter().map(|(pragma, _)| *pragma).collect();
+            format!("PRAGMA '{}' cannot be changed at runtime (allowed: {})", name, names.join(", "))
+        })
+}
+
+/// `value` checked against the values `pragma` accepts, uppercased
+pub fn runtime_pragma_value(pragma: &str, value: &str) -> Result<String, String> {
+    let value = value.trim();
+    let upper = value.to_uppercase();
+    let allowed = RUNTIME_PRAGMAS.iter().find(|(name, _)| *name == pragma).map_or(&[][..], |(_, values)| *values);
+    let valid = if allowed.is_empty() { value.parse::<i64>().is_ok() } else { allowed.contains(&upper.as_str()) };
+    if valid {
+        Ok(upper)
+    } else if allowed.is_empty() {
+        Err(format!("PRAGMA {} needs an integer value, not '{}'", pragma, value))
+    } else {
+        Err(format!("'{}' is not a valid value for PRAGMA {} (expected one of: {})", value, pragma, allowed.join(", ")))
+    }
+}
@@ -0,0 +1,192 @@
+//! Binding named query parameters.
+//!
+//! SQLite numbers every placeholder (`?`, `?NNN`, `:name`, `@name`, `$name`)
+//! but sqlx only accepts the numbered forms, so named placeholders are
+//! rewritten to `?NNN` and their values bound in that order.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use super::traits::DbValue;
+
+/// A placeholder in a statement: its byte range, its name (None for `?` and
+/// `?NNN`) and the number SQLite gives it
+struct Placeholder {
+    start: usize,
+    end: usize,
+    name: Option<String>,
+    index: usize,
+}
+
+fn scan(sql: &str) -> Vec<Placeholder> {
+    let chars: Vec<(usize, char)> = sql.char_indices().collect();
+    let at = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let offset = |i: usize| chars.get(i).map(|(pos, _)| *pos).unwrap_or(sql.len());
+
+    let mut found: Vec<Placeholder> = Vec::new();
+    let mut count = 0;
+    let mut i = 0;
+    while let Some(c) = at(i) {
+        match c {
+            // String literals and quoted identifiers
+            '\'' | '"' | '`' => {
+                i += 1;
+                while let Some(next) = at(i) {
+                    if next == c {
+                        // A doubled quote is an escaped quote
+                        if at(i + 1) == Some(c) {
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            '[' => {
+                while at(i).is_some_and(|c| c != ']') {
+                    i += 1;
+                }
+                i += 1;
+            }
+            '-' if at(i + 1) == Some('-') => {
+                while at(i).is_some_and(|c| c != '\n') {
+                    i += 1;
+                }
+            }
+            '/' if at(i + 1) == Some('*') => {
+                i += 2;
+                while at(i).is_some() && !(at(i) == Some('*') && at(i + 1) == Some('/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            '?' => {
+                let start = i;
+                i += 1;
+                while at(i).is_some_and(|c| c.is_ascii_digit()) {
+                    i += 1;
+                }
+                let index = if i > start + 1 {
+                    sql[offset(start + 1)..offset(i)].parse().unwrap_or(0)
+                } else {
+                    count + 1
+                };
+                count = count.max(index);
+                found.push(Placeholder { start: offset(start), end: offset(i), name: None, index });
+            }
+            ':' | '@' | '$' => {
+                let start = i;
+                i += 1;
+                while at(i).is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    i += 1;
+                }
+                if i > start + 1 {
+                    let name = sql[offset(start + 1)..offset(i)].to_string();
+                    let index = match found.iter().find(|p| p.name.as_deref() == Some(name.as_str())) {
+                        Some(earlier) => earlier.index,
+                        None => {
+                            count += 1;
+                            count
+                        }
+                    };
+                    found.push(Placeholder { start: offset(start), end: offset(i), name: Some(name), index });
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    found
+}
+
+/// Parameter names in the order SQLite numbers them. Anonymous `?` and
+/// `?NNN` placeholders are named by their number ("1", "2", ...).
+pub fn placeholders(sql: &str) -> Vec<String> {
+    let found = scan(sql);
+    let count = found.iter().map(|p| p.index).max().unwrap_or(0);
+    (1..=count)
+        .map(|index| {
+            found
+                .iter()
+                .find(|p| p.index == index)
+                .and_then(|p| p.name.clone())
+                .unwrap_or_else(|| index.to_string())
+        })
+        .collect()
+}
+
+/// The statement with named placeholders rewritten to `?NNN`, and the
+/// parameter values in binding order. Values are matched to placeholders by
+/// name (with or without its `:`, `@` or `$` prefix); if any placeholder has
+/// no value of that name, the values are bound in the order given.
+pub fn bind_order<'a, 'q>(sql: &'q str, params: &'a HashMap<String, DbValue>) -> (Cow<'q, str>, Vec<&'a DbValue>) {
+    if params.is_empty() {
+        return (Cow::Borrowed(sql), Vec::new());
+    }
+
+    let lookup = |name: &str| {
+        params.get(name).or_else(|| {
+            params
+                .iter()
+                .find(|(key, _)| key.strip_prefix([':', '@', '$']) == Some(name))
+                .map(|(_, value)| value)
+        })
+    };
+    let names = placeholders(sql);
+    let by_name: Option<Vec<&DbValue>> = names.iter().map(|name| lookup(name)).collect();
+    let values = match by_name {
+        Some(values) if !names.is_empty() => values,
+        _ => return (Cow::Borrowed(sql), params.values().collect()),
+    };
+
+    let found = scan(sql);
+    if found.iter().all(|p| p.name.is_none()) {
+        return (Cow::Borrowed(sql), values);
+    }
+    let mut rewritten = String::with_capacity(sql.len());
+    let mut last = 0;
+    for placeholder in found.iter().filter(|p| p.name.is_some()) {
+        rewritten.push_str(&sql[last..placeholder.start]);
+        rewritten.push_str(&format!("?{}", placeholder.index));
+        last = placeholder.end;
+    }
+    rewritten.push_str(&sql[last..]);
+    (Cow::Owned(rewritten), values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            placeholders("SELECT * FROM t WHERE a = :a AND b = @b OR a = :a AND c = $c"),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(placeholders("SELECT ?, ?3, ?"), vec!["1", "2", "3", "4"]);
+        assert_eq!(
+            placeholders("SELECT ':no', \"@no\", [$no] -- :no\n, /* ?no */ :yes FROM t"),
+            vec!["yes"]
+        );
+        assert_eq!(placeholders("SELECT 'it''s :x', :y"), vec!["y"]);
+    }
+
+    #[test]
+    fn test_bind_order() {
+        let params = HashMap::from([
+            ("org".to_string(), DbValue::Text("acme".to_string())),
+            (":min".to_string(), DbValue::Integer(3)),
+        ]);
+        let (sql, values) = bind_order("SELECT * FROM t WHERE n > :min AND (org = :org OR :min = 0)", &params);
+        assert_eq!(sql, "SELECT * FROM t WHERE n > ?1 AND (org = ?2 OR ?1 = 0)");
+        assert!(matches!(values[..], [DbValue::Integer(3), DbValue::Text(ref org)] if org == "acme"));
+
+        // Keys that name no placeholder keep the order they were given in
+        let params = HashMap::from([("pattern".to_string(), DbValue::Text("a%".to_string()))]);
+        let (sql, values) = bind_order("SELECT name FROM t WHERE name LIKE ?1", &params);
+        assert_eq!(sql, "SELECT name FROM t WHERE name LIKE ?1");
+        assert_eq!(values.len(), 1);
+    }
+}
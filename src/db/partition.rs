@@ -0,0 +1,196 @@
+//! Time-based partitioning.
+//!
+//! Rows written to a partitioned table are stored in one physical table per
+//! period (`events_2024_06` for June 2024 under monthly partitioning). The
+//! logical name is a view over all partitions, and partitions older than the
+//! retention window are dropped by the server's reaper.
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, NaiveDateTime, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::{DatabaseError, Result};
+use super::traits::DbValue;
+
+/// Span of time covered by one partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionPeriod {
+    Day,
+    Month,
+    Year,
+}
+
+impl PartitionPeriod {
+    pub const ALL: [PartitionPeriod; 3] = [PartitionPeriod::Day, PartitionPeriod::Month, PartitionPeriod::Year];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PartitionPeriod::Day => "day",
+            PartitionPeriod::Month => "month",
+            PartitionPeriod::Year => "year",
+        }
+    }
+
+    /// Suffix of the partition holding rows from `time`
+    pub fn suffix(self, time: DateTime<Utc>) -> String {
+        match self {
+            PartitionPeriod::Day => time.format("%Y_%m_%d").to_string(),
+            PartitionPeriod::Month => time.format("%Y_%m").to_string(),
+            PartitionPeriod::Year => time.format("%Y").to_string(),
+        }
+    }
+
+    /// First day of the period a partition suffix names
+    pub fn parse_suffix(self, suffix: &str) -> Option<NaiveDate> {
+        let parts: Vec<&str> = suffix.split('_').collect();
+        let number = |i: usize| parts.get(i)?.parse::<u32>().ok();
+        match (self, parts.len()) {
+            (PartitionPeriod::Day, 3) => NaiveDate::from_ymd_opt(number(0)? as i32, number(1)?, number(2)?),
+            (PartitionPeriod::Month, 2) => NaiveDate::from_ymd_opt(number(0)? as i32, number(1)?, 1),
+            (PartitionPeriod::Year, 1) => NaiveDate::from_ymd_opt(number(0)? as i32, 1, 1),
+            _ => None,
+        }
+    }
+
+    /// GLOB pattern matching the names of a table's partitions
+    pub fn glob(self, table_name: &str) -> String {
+        let suffix = match self {
+            PartitionPeriod::Day => "[0-9][0-9][0-9][0-9]_[0-9][0-9]_[0-9][0-9]",
+            PartitionPeriod::Month => "[0-9][0-9][0-9][0-9]_[0-9][0-9]",
+            PartitionPeriod::Year => "[0-9][0-9][0-9][0-9]",
+        };
+        format!("{}_{}", table_name, suffix)
+    }
+
+    /// Whether `name` is one of `table_name`'s partitions: whether it
+    /// matches `glob(table_name)`
+    pub fn matches(self, table_name: &str, name: &str) -> bool {
+        let Some(suffix) = name.strip_prefix(table_name).and_then(|rest| rest.strip_prefix('_')) else {
+            return false;
+        };
+        let widths: &[usize] = match self {
+            PartitionPeriod::Day => &[4, 2, 2],
+            PartitionPeriod::Month => &[4, 2],
+            PartitionPeriod::Year => &[4],
+        };
+        let parts: Vec<&str> = suffix.split('_').collect();
+        parts.len() == widths.len()
+            && parts.iter().zip(widths).all(|(part, width)| part.len() == *width && part.bytes().all(|b| b.is_ascii_digit()))
+    }
+
+    /// First day of the period `periods_back` periods before the one holding `time`
+    pub fn start_before(self, time: DateTime<Utc>, periods_back: u32) -> NaiveDate {
+        let date = time.date_naive();
+        match self {
+            PartitionPeriod::Day => date - chrono::Days::new(periods_back as u64),
+            PartitionPeriod::Month => {
+                let first = date.with_day(1).unwrap_or(date);
+                first.checked_sub_months(Months::new(periods_back)).unwrap_or(NaiveDate::MIN)
+            }
+            PartitionPeriod::Year => {
+                NaiveDate::from_ymd_opt(date.year() - periods_back as i32, 1, 1).unwrap_or(NaiveDate::MIN)
+            }
+        }
+    }
+}
+
+impl FromStr for PartitionPeriod {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "day" | "daily" => Ok(PartitionPeriod::Day),
+            "month" | "monthly" => Ok(PartitionPeriod::Month),
+            "year" | "yearly" => Ok(PartitionPeriod::Year),
+            _ => Err(DatabaseError::QueryError(format!(
+                "Unknown partition period '{}' (expected day, month or year)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PartitionPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a table is split into partitions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partitioning {
+    /// Column holding each row's time (Unix seconds or an ISO-8601 timestamp)
+    pub column: String,
+    pub period: PartitionPeriod,
+    /// Number of periods to keep, including the current one (default: keep all)
+    pub retention: Option<u32>,
+}
+
+/// Name of the partition of `table_name` with the given suffix
+pub fn partition_name(table_name: &str, suffix: &str) -> String {
+    format!("{}_{}", table_name, suffix)
+}
+
+/// Time a partition column value refers to; NULL has none
+pub fn value_time(value: &DbValue) -> Result<Option<DateTime<Utc>>> {
+    let time = match value {
+        DbValue::Null => return Ok(None),
+        DbValue::Integer(secs) | DbValue::Timestamp(secs) => DateTime::from_timestamp(*secs, 0),
+        DbValue::Real(secs) => DateTime::from_timestamp(*secs as i64, 0),
+        DbValue::Text(text) => DateTime::parse_from_rfc3339(text)
+            .map(|t| t.with_timezone(&Utc))
+            .ok()
+            .or_else(|| {
+                NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+                    .ok()
+                    .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+                    .map(|t| t.and_utc())
+            }),
+        _ => None,
+    };
+    time.map(Some).ok_or_else(|| {
+        DatabaseError::QueryError(format!("Partition column value {:?} is not a timestamp", value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_period_suffixes() {
+        let time = at("2024-06-15T10:00:00Z");
+        assert_eq!(PartitionPeriod::Day.suffix(time), "2024_06_15");
+        assert_eq!(PartitionPeriod::Month.suffix(time), "2024_06");
+        assert_eq!(PartitionPeriod::Year.suffix(time), "2024");
+
+        assert_eq!(PartitionPeriod::Month.parse_suffix("2024_06"), NaiveDate::from_ymd_opt(2024, 6, 1));
+        assert_eq!(PartitionPeriod::Month.parse_suffix("2024_06_15"), None);
+        assert_eq!(PartitionPeriod::Month.start_before(time, 7), NaiveDate::from_ymd_opt(2023, 11, 1).unwrap());
+        assert_eq!(PartitionPeriod::Day.start_before(time, 15), NaiveDate::from_ymd_opt(2024, 5, 31).unwrap());
+        assert!("weekly".parse::<PartitionPeriod>().is_err());
+    }
+
+    #[test]
+    fn test_partition_names_match_glob() {
+        assert!(PartitionPeriod::Month.matches("events", "events_2024_06"));
+        assert!(PartitionPeriod::Day.matches("events", "events_2024_06_15"));
+        assert!(!PartitionPeriod::Month.matches("events", "events_2024_06_15"));
+        assert!(!PartitionPeriod::Year.matches("events", "events_archive"));
+        assert!(!PartitionPeriod::Year.matches("events", "events2024"));
+    }
+
+    #[test]
+    fn test_value_time() {
+        let expected = Some(at("2024-06-15T10:00:00Z"));
+        assert_eq!(value_time(&DbValue::Integer(1718445600)).unwrap(), expected);
+        assert_eq!(value_time(&DbValue::Text("2024-06-15 10:00:00".to_string())).unwrap(), expected);
+        assert_eq!(value_time(&DbValue::Text("2024-06-15T12:00:00+02:00".to_string())).unwrap(), expected);
+        assert_eq!(value_time(&DbValue::Null).unwrap(), None);
+        assert!(value_time(&DbValue::Text("yesterday".to_string())).is_err());
+    }
+}
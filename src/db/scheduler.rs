@@ -0,0 +1,319 @@
+//! Recurring SQL jobs.
+//!
+//! A job runs a SQL statement against a database on a cron schedule. Jobs
+//! come from the config file or are added at runtime; runtime jobs are saved
+//! in the target database so they survive restarts. Every run is recorded in
+//! the target database's job history.
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use super::error::{DatabaseError, Result};
+use super::traits::{Job, JobRun};
+use super::{DatabaseManager, QueryCache};
+
+/// How often the scheduler checks for due jobs
+const TICK: Duration = Duration::from_secs(1);
+
+/// Parse a cron expression. Standard five-field expressions
+/// (`min hour day month weekday`) are accepted as well as the six- and
+/// seven-field forms with seconds (and years).
+pub fn parse_schedule(expression: &str) -> Result<cron::Schedule> {
+    let expression = expression.trim();
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_string()
+    };
+    cron::Schedule::from_str(&expression)
+        .map_err(|e| DatabaseError::QueryError(format!("Invalid schedule '{}': {}", expression, e)))
+}
+
+struct ScheduledJob {
+    job: Job,
+    schedule: cron::Schedule,
+    next_run: Option<DateTime<Utc>>,
+    running: Arc<AtomicBool>,
+}
+
+impl ScheduledJob {
+    fn new(job: Job) -> Result<Self> {
+        let schedule = parse_schedule(&job.schedule)?;
+        let next_run = schedule.upcoming(Utc).next();
+        Ok(Self {
+            job,
+            schedule,
+            next_run,
+            running: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+/// Runs jobs when they are due
+pub struct JobScheduler {
+    manager: Arc<DatabaseManager>,
+    cache: Option<Arc<QueryCache>>,
+    jobs: Mutex<BTreeMap<String, ScheduledJob>>,
+}
+
+impl JobScheduler {
+    /// A scheduler whose jobs drop the cached results of the database they run against
+    pub fn new(manager: Arc<DatabaseManager>, cache: Option<Arc<QueryCache>>) -> Self {
+        Self {
+            manager,
+            cache,
+            jobs: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register the config file's jobs and the jobs saved in every registered
+    /// database, returning how many were loaded. A saved job with the same
+    /// name as a config job is ignored.
+    pub async fn load(&self, config_jobs: Vec<Job>) -> Result<usize> {
+        let mut loaded = Vec::new();
+        for mut job in config_jobs {
+            job.database = self.resolve_database(&job.database).await?;
+            job.from_config = true;
+            loaded.push(ScheduledJob::new(job)?);
+        }
+
+        for info in self.manager.list_databases().await {
+            let Some(db) = self.manager.get_database(&info.name).await else {
+                continue;
+            };
+            let saved = db.read().await.jobs().await?;
+            for mut job in saved {
+                if loaded.iter().any(|s| s.job.name == job.name) {
+                    tracing::warn!(job = %job.name, database = %info.name, "Saved job shadowed by a config job");
+                    continue;
+                }
+                job.database = info.name.clone();
+                match ScheduledJob::new(job) {
+                    Ok(scheduled) => loaded.push(scheduled),
+                    Err(e) => tracing::warn!(database = %info.name, error = %e, "Skipping saved job"),
+                }
+            }
+        }
+
+        let count = loaded.len();
+        let mut jobs = self.jobs.lock().unwrap();
+        for scheduled in loaded {
+            jobs.insert(scheduled.job.name.clone(), scheduled);
+        }
+        Ok(count)
+    }
+
+    /// Register a job and save it in its database. Returns the job's next run.
+    pub async fn add_job(&self, mut job: Job) -> Result<Option<DateTime<Utc>>> {
+        if job.name.trim().is_empty() {
+            return Err(DatabaseError::QueryError("Job name cannot be empty".to_string()));
+        }
+        job.database = self.resolve_database(&job.database).await?;
+        job.from_config = false;
+        let scheduled = ScheduledJob::new(job)?;
+
+        if self.jobs.lock().unwrap().contains_key(&scheduled.job.name) {
+            return Err(DatabaseError::QueryError(format!("Job '{}' already exists", scheduled.job.name)));
+        }
+
+        let db = self.database(&scheduled.job.database).await?;
+        db.read().await.save_job(&scheduled.job).await?;
+
+        let next_run = scheduled.next_run;
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains_key(&scheduled.job.name) {
+            return Err(DatabaseError::QueryError(format!("Job '{}' already exists", scheduled.job.name)));
+        }
+        jobs.insert(scheduled.job.name.clone(), scheduled);
+        Ok(next_run)
+    }
+
+    /// Unregister a runtime job and delete it from its database. Returns
+    /// whether the job existed.
+    pub async fn remove_job(&self, name: &str) -> Result<bool> {
+        let database = match self.jobs.lock().unwrap().get(name) {
+            None => return Ok(false),
+            Some(scheduled) if scheduled.job.from_config => {
+                return Err(DatabaseError::QueryError(format!(
+                    "Job '{}' is defined in the config file and cannot be removed at runtime",
+                    name
+                )))
+            }
+            Some(scheduled) => scheduled.job.database.clone(),
+        };
+
+        let db = self.database(&database).await?;
+        db.read().await.delete_job(name).await?;
+        Ok(self.jobs.lock().unwrap().remove(name).is_some())
+    }
+
+    /// Every registered job with its next run, ordered by name
+    pub fn jobs(&self) -> Vec<(Job, Option<DateTime<Utc>>)> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|scheduled| (scheduled.job.clone(), scheduled.next_run))
+            .collect()
+    }
+
+    /// Recent runs, most recent first: those of `job_name` if given, otherwise
+    /// every run recorded in `database`
+    pub async fn runs(&self, job_name: Option<&str>, database: &str, limit: u32) -> Result<Vec<JobRun>> {
+        let job_database = job_name.and_then(|name| {
+            self.jobs.lock().unwrap().get(name).map(|scheduled| scheduled.job.database.clone())
+        });
+        let database = match job_database {
+            Some(database) => database,
+            None => self.resolve_database(database).await?,
+        };
+        let db = self.database(&database).await?;
+        let runs = db.read().await.job_runs(job_name, limit).await?;
+        Ok(runs)
+    }
+
+    /// Spawn the background task that runs due jobs
+    pub fn start(self: &Arc<Self>) -> JoinHandle<()> {
+        let scheduler = Arc::clone(self);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(TICK).await;
+                for (job, running) in scheduler.take_due(Utc::now()) {
+                    let scheduler = Arc::clone(&scheduler);
+                    tokio::spawn(async move {
+                        scheduler.run_job(&job).await;
+                        running.store(false, Ordering::Release);
+                    });
+                }
+            }
+        })
+    }
+
+    /// Jobs whose next run has passed, advancing their schedules. A job still
+    /// running from its previous slot is skipped for this one.
+    fn take_due(&self, now: DateTime<Utc>) -> Vec<(Job, Arc<AtomicBool>)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut due = Vec::new();
+        for scheduled in jobs.values_mut() {
+            if scheduled.next_run.is_none_or(|next| next > now) {
+                continue;
+            }
+            scheduled.next_run = scheduled.schedule.after(&now).next();
+            if scheduled.running.swap(true, Ordering::AcqRel) {
+                tracing::warn!(job = %scheduled.job.name, "Skipping run: previous run still in progress");
+                continue;
+            }
+            due.push((scheduled.job.clone(), scheduled.running.clone()));
+        }
+        due
+    }
+
+    async fn run_job(&self, job: &Job) {
+        let db = match self.database(&job.database).await {
+            Ok(db) => db,
+            Err(e) => {
+                tracing::warn!(job = %job.name, error = %e, "Job skipped");
+                return;
+            }
+        };
+        let db = db.read().await;
+
+        let started_at = Utc::now().timestamp();
+        let start = Instant::now();
+        let result = db.execute(&job.sql).await;
+        let duration_ms = start.elapsed().as_millis() as i64;
+
+        // The statement may have written to any table
+        if let Some(cache) = &self.cache {
+            cache.invalidate_database(&job.database);
+        }
+
+        let run = match result {
+            Ok(rows_affected) => {
+                tracing::info!(job = %job.name, database = %job.database, rows_affected, duration_ms, "Job completed");
+                JobRun {
+                    job_name: job.name.clone(),
+                    started_at,
+                    duration_ms,
+                    success: true,
+                    rows_affected,
+                    message: String::new(),
+                }
+            }
+            Err(e) => {
+                tracing::warn!(job = %job.name, database = %job.database, error = %e, "Job failed");
+                JobRun {
+                    job_name: job.name.clone(),
+                    started_at,
+                    duration_ms,
+                    success: false,
+                    rows_affected: 0,
+                    message: e.to_string(),
+                }
+            }
+        };
+        if let Err(e) = db.record_job_run(&run).await {
+            tracing::warn!(job = %job.name, error = %e, "Failed to record job run");
+        }
+    }
+
+    async fn resolve_database(&self, name: &str) -> Result<String> {
+        self.manager
+            .resolve_name(Some(name))
+            .await
+            .ok_or_else(|| DatabaseError::ConnectionError(format!("Database '{}' not found", name)))
+    }
+
+    async fn database(&self, name: &str) -> Result<Arc<tokio::sync::RwLock<Box<dyn super::Database>>>> {
+        self.manager
+            .get_database(name)
+            .await
+            .ok_or_else(|| DatabaseError::ConnectionError(format!("Database '{}' not found", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_schedule() {
+        let five_field = parse_schedule("*/5 * * * *").unwrap();
+        let start = DateTime::parse_from_rfc3339("2024-06-01T10:02:30Z").unwrap().with_timezone(&Utc);
+        let next: Vec<_> = five_field.after(&start).take(2).map(|t| t.to_rfc3339()).collect();
+        assert_eq!(next, vec!["2024-06-01T10:05:00+00:00", "2024-06-01T10:10:00+00:00"]);
+
+        assert!(parse_schedule("30 0 3 * * *").is_ok());
+        assert!(parse_schedule("every minute").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_take_due_advances_schedule() {
+        let scheduler = JobScheduler::new(Arc::new(DatabaseManager::new()), None);
+        let job = Job {
+            name: "cleanup".to_string(),
+            schedule: "* * * * *".to_string(),
+            sql: "DELETE FROM events".to_string(),
+            database: "default".to_string(),
+            from_config: false,
+        };
+        scheduler.jobs.lock().unwrap().insert(job.name.clone(), ScheduledJob::new(job).unwrap());
+
+        let now = Utc::now();
+        assert!(scheduler.take_due(now).is_empty());
+
+        let later = now + chrono::Duration::minutes(1);
+        let due = scheduler.take_due(later);
+        assert_eq!(due.len(), 1);
+        // Still running, so the next slot is skipped
+        assert!(scheduler.take_due(later + chrono::Duration::minutes(1)).is_empty());
+        due[0].1.store(false, Ordering::Release);
+        assert_eq!(scheduler.take_due(later + chrono::Duration::minutes(2)).len(), 1);
+    }
+}
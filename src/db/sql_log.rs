@@ -0,0 +1,130 @@
+//! Records the statements a request executes, for clients that ask to see
+//! them (`datasink --show-sql`).
+//!
+//! Recording happens only inside `capture`; every statement passed to
+//! `SqliteDatabase::prepare` is logged with a summary of the values bound to
+//! it, so type and quoting problems can be diagnosed from the client.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::future::Future;
+
+use super::traits::DbValue;
+
+/// Longest text value shown in a parameter summary
+const MAX_TEXT_PREVIEW: usize = 40;
+
+tokio::task_local! {
+    static STATEMENTS: RefCell<Vec<ExecutedStatement>>;
+}
+
+/// A statement and summaries of its bound values, in `?1..?n` order
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutedStatement {
+    pub sql: String,
+    pub parameters: Vec<String>,
+}
+
+impl fmt::Display for ExecutedStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.sql)?;
+        if !self.parameters.is_empty() {
+            let parameters: Vec<String> =
+                self.parameters.iter().enumerate().map(|(i, value)| format!("?{} = {}", i + 1, value)).collect();
+            write!(f, "  -- {}", parameters.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `future`, returning the statements it executed when `enabled`
+pub async fn capture<F: Future>(enabled: bool, future: F) -> (F::Output, Vec<ExecutedStatement>) {
+    if !enabled {
+        return (future.await, Vec::new());
+    }
+    STATEMENTS
+        .scope(RefCell::new(Vec::new()), async {
+            let output = future.await;
+            (output, STATEMENTS.with(|statements| statements.take()))
+        })
+        .await
+}
+
+/// Log a statement about to run, if the current request is being captured
+pub(crate) fn record(sql: &str) {
+    let _ = STATEMENTS.try_with(|statements| {
+        statements.borrow_mut().push(ExecutedStatement {
+            sql: sql.to_string(),
+            parameters: Vec::new(),
+        })
+    });
+}
+
+/// Log a value bound to the statement recorded last
+pub(crate) fn record_parameter(value: &DbValue) {
+    let _ = STATEMENTS.try_with(|statements| {
+        if let Some(statement) = statements.borrow_mut().last_mut() {
+            statement.parameters.push(describe(value));
+        }
+    });
+}
+
+/// "INTEGER 42", "TEXT 'it''s'" (quoted as SQL would), "BLOB (16 bytes)"
+fn describe(value: &DbValue) -> String {
+    match value {
+        DbValue::Integer(i) => format!("INTEGER {}", i),
+        DbValue::Real(r) => format!("REAL {}", r),
+        DbValue::Text(s) => {
+            let mut preview: String = s.chars().take(MAX_TEXT_PREVIEW).collect();
+            if preview.len() < s.len() {
+                preview = format!("{}... ({} chars)", preview, s.chars().count());
+            }
+            format!("TEXT '{}'", preview.replace('\'', "''"))
+        }
+        DbValue::Blob(b) => format!("BLOB ({} bytes)", b.len()),
+        DbValue::Boolean(b) => format!("BOOLEAN {}", b),
+        DbValue::Timestamp(t) => format!("TIMESTAMP {}", t),
+        DbValue::Null => "NULL".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_capture_records_statements_and_parameters() {
+        let (output, statements) = capture(true, async {
+            record("INSERT INTO users (id, name) VALUES (?1, ?2)");
+            record_parameter(&DbValue::Integer(7));
+            record_parameter(&DbValue::Text("O'Brien".to_string()));
+            record("DELETE FROM users WHERE id = 3");
+            42
+        })
+        .await;
+
+        assert_eq!(output, 42);
+        assert_eq!(
+            statements.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            vec![
+                "INSERT INTO users (id, name) VALUES (?1, ?2)  -- ?1 = INTEGER 7, ?2 = TEXT 'O''Brien'".to_string(),
+                "DELETE FROM users WHERE id = 3".to_string(),
+            ]
+        );
+
+        // Outside a capture, and when disabled, nothing is kept
+        record("SELECT 1");
+        let (_, statements) = capture(false, async { record("SELECT 1") }).await;
+        assert!(statements.is_empty());
+    }
+
+    #[test]
+    fn test_describe_truncates_long_text() {
+        let text = "x".repeat(MAX_TEXT_PREVIEW + 10);
+        assert_eq!(
+            describe(&DbValue::Text(text)),
+            format!("TEXT '{}... ({} chars)'", "x".repeat(MAX_TEXT_PREVIEW), MAX_TEXT_PREVIEW + 10)
+        );
+        assert_eq!(describe(&DbValue::Blob(vec![0; 16])), "BLOB (16 bytes)");
+    }
+}
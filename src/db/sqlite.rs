@@ -1,37 +1,436 @@
 use async_trait::async_trait;
 use futures::stream;
-use sqlx::{sqlite::SqlitePool, Row, Sqlite, Column};
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use sqlx::{sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions}, Row, Sqlite, Column, ConnectOptions, Connection, TypeInfo};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 
 use crate::db::{
-    error::{DatabaseError, Result},
-    traits::{ColumnDef, ColumnType, Database, DbValue, QueryResult, StreamedQueryResult},
+    encryption::ENCRYPTED_COLUMNS_TABLE,
+    error::{DatabaseError, Result, UnknownColumn},
+    functions,
+    history::{self, Change},
+    options::DatabaseOptions,
+    params,
+    partition::{self, PartitionPeriod, Partitioning},
+    sql_log,
+    traits::{AppliedSchema, BoundingBox, ColumnDef, ConnectionStats, ColumnType, Database, DbValue, DedupeOutcome, KeepRow, Descriptions, DiskUsage, ForeignKeyViolation, InsertCounts, Job, JobRun, MaterializedView, OnConflict, QueryResult, SavedQuery, SearchMatch, SearchResult, StorageInfo, StreamedQueryResult, TableStats, WriteOp, WriteOutcome},
 };
 
+/// FTS5 table backing the search index of `table_name`
+fn search_index_name(table_name: &str) -> String {
+    format!("{}_fts", table_name)
+}
+
+/// R*Tree table backing the spatial index of `table_name`
+fn spatial_index_name(table_name: &str) -> String {
+    format!("{}_rtree", table_name)
+}
+
+/// A `CREATE TABLE` statement as SQLite stores it, creating `new_name`
+/// instead; `None` if the statement does not start that way
+fn rename_created_table(sql: &str, new_name: &str) -> Option<String> {
+    const PREFIX: &str = "CREATE TABLE ";
+    let rest = sql.get(..PREFIX.len()).filter(|p| p.eq_ignore_ascii_case(PREFIX)).map(|_| sql[PREFIX.len()..].trim_start())?;
+
+    // Skip the name, quoted or bare, and the schema before it if there is one
+    let mut end = 0;
+    loop {
+        let name = &rest[end..];
+        let len = match name.chars().next()? {
+            quote @ ('"' | '`' | '[' | '\'') => {
+                let close = if quote == '[' { ']' } else { quote };
+                let mut i = 1;
+                loop {
+                    let at = i + name[i..].find(close)?;
+                    // A doubled quote is an escaped quote
+                    if close != ']' && name[at + 1..].starts_with(close) {
+                        i = at + 2;
+                        continue;
+                    }
+                    break at + 1;
+                }
+            }
+            _ => name.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$')).unwrap_or(name.len()),
+        };
+        if len == 0 {
+            return None;
+        }
+        end += len;
+        if !rest[end..].starts_with('.') {
+            break;
+        }
+        end += 1;
+    }
+    Some(format!("CREATE TABLE \"{}\" {}", new_name.replace('"', "\"\""), rest[end..].trim_start()))
+}
+
+/// Tables with a TTL and the column holding each row's expiry time
+const TTL_COLUMNS_TABLE: &str = "__datasink_ttl_columns";
+
+/// Jobs added at runtime that run against this database
+const JOBS_TABLE: &str = "__datasink_jobs";
+
+/// History of job runs against this database
+const JOB_RUNS_TABLE: &str = "__datasink_job_runs";
+
+/// Named queries saved for reuse (`datasink saved`)
+const SAVED_QUERIES_TABLE: &str = "__datasink_saved_queries";
+
+/// Materialized views: the backing table name, its query and refresh schedule
+const MATERIALIZED_VIEWS_TABLE: &str = "__datasink_materialized_views";
+
+/// Partitioned tables: the partition column, period, retention and the
+/// column definitions new partitions are created with
+const PARTITIONED_TABLES_TABLE: &str = "__datasink_partitioned_tables";
+
+/// Schema files applied with `create-from-schema`: name, version and checksum
+pub const APPLIED_SCHEMA_TABLE: &str = "__datasink_schema";
+
+/// Descriptions of tables and columns from schema files; `column_name` is
+/// empty for the table's own description
+pub const DESCRIPTIONS_TABLE: &str = "__datasink_descriptions";
+
+/// sqlx's default number of prepared statements cached per connection
+const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 100;
+
+/// A sample seeks rowids only when the table's rowids span more than this many
+/// times the rows asked for, and gives up after as many seeks; smaller tables
+/// are shuffled whole
+const SAMPLE_SCAN_FACTOR: i64 = 4;
+
+type SqliteQuery<'q> = sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>;
+
+/// What inserting one row did
+enum RowWrite {
+    /// Inserted as a new row with this rowid
+    Inserted(i64),
+    /// Replaced or updated a conflicting row; the rowid is known for a replace
+    Replaced(Option<i64>),
+    /// Skipped on conflict
+    Skipped,
+}
+
+impl RowWrite {
+    /// Add the write to a batch's counts
+    fn count(self, counts: &mut InsertCounts) {
+        match self {
+            RowWrite::Inserted(id) => {
+                counts.inserted += 1;
+                counts.first_id.get_or_insert(id);
+                counts.last_id = Some(id);
+            }
+            RowWrite::Replaced(_) => counts.replaced += 1,
+            RowWrite::Skipped => {}
+        }
+    }
+}
+
 pub struct SqliteDatabase {
     pool: SqlitePool,
 }
 
 impl SqliteDatabase {
+    /// Connect with additional connection options (pragmas, etc.)
+    pub async fn connect_with_options(
+        connection_string: &str,
+        options: &DatabaseOptions,
+    ) -> Result<Self> {
+        let mut connect_options = SqliteConnectOptions::from_str(connection_string)
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        for (name, value) in &options.pragmas {
+            connect_options = connect_options.pragma(name.clone(), value.clone());
+        }
+
+        let statement_cache_capacity = options
+            .statement_cache_capacity
+            .unwrap_or(DEFAULT_STATEMENT_CACHE_CAPACITY);
+        connect_options = connect_options.statement_cache_capacity(statement_cache_capacity);
+
+        if let Some(enabled) = options.foreign_keys {
+            connect_options = connect_options.foreign_keys(enabled);
+        }
+
+        for extension in &options.extensions {
+            let path = extension.path().to_string();
+            connect_options = match extension.entry_point() {
+                Some(entry_point) => connect_options.extension_with_entrypoint(path, entry_point.to_string()),
+                None => connect_options.extension(path),
+            };
+        }
+
+        let pool = Self::pool_options()
+            .connect_with(connect_options)
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(SqliteDatabase { pool })
+    }
+
+    /// Options for a pool whose connections have DataSink's SQL functions
+    fn pool_options() -> SqlitePoolOptions {
+        SqlitePoolOptions::new().after_connect(|conn, _meta| {
+            Box::pin(async move {
+                let mut handle = conn.lock_handle().await?;
+                functions::register_functions(handle.as_raw_handle())
+                    .map_err(|e| sqlx::Error::Configuration(e.into()))
+            })
+        })
+    }
+
+    /// Close every pooled connection, waiting for those in use to be returned
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    /// Build a query for `sql`, recording it in the SQL log. sqlx keeps each
+    /// connection's prepared statements keyed by their SQL text.
+    fn prepare<'q>(&self, sql: &'q str) -> SqliteQuery<'q> {
+        sql_log::record(sql);
+        sqlx::query(sql)
+    }
+
+    /// Column/value pairs in a stable order, so the same set of columns always
+    /// produces the same SQL text and hits the statement cache
+    fn sorted_entries(values: &HashMap<String, DbValue>) -> Vec<(&String, &DbValue)> {
+        let mut entries: Vec<_> = values.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
+    /// INSERT statement for the given columns, numbered `?1..?n` in column
+    /// order. Under `Update` it is a plain INSERT; see [`Self::upsert_sql`].
+    /// `Ignore` uses ON CONFLICT DO NOTHING rather than OR IGNORE, which would
+    /// also skip rows violating NOT NULL or CHECK constraints.
+    fn insert_sql(table_name: &str, columns: &[&String], on_conflict: OnConflict) -> String {
+        let (verb, clause) = match on_conflict {
+            OnConflict::Abort | OnConflict::Update => ("INSERT", ""),
+            OnConflict::Ignore => ("INSERT", " ON CONFLICT DO NOTHING"),
+            OnConflict::Replace => ("INSERT OR REPLACE", ""),
+        };
+        let placeholders: Vec<String> = (0..columns.len()).map(|i| format!("?{}", i + 1)).collect();
+
+        format!(
+            "{} INTO {} ({}) VALUES ({}){}",
+            verb,
+            table_name,
+            columns
+                .iter()
+                .map(|c| c.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            placeholders.join(", "),
+            clause
+        )
+    }
+
+    /// INSERT that updates the conflicting row with the new values instead.
+    /// Primary key columns are left out of the update, so a conflict on
+    /// another UNIQUE column does not rekey the existing row.
+    fn upsert_sql(table_name: &str, columns: &[&String], keys: &[String]) -> String {
+        let assignments: Vec<String> = columns
+            .iter()
+            .filter(|column| !keys.iter().any(|key| key.eq_ignore_ascii_case(column)))
+            .map(|column| format!("{} = excluded.{}", column, column))
+            .collect();
+        let action = if assignments.is_empty() {
+            "DO NOTHING".to_string()
+        } else {
+            format!("DO UPDATE SET {}", assignments.join(", "))
+        };
+        format!("{} ON CONFLICT {}", Self::insert_sql(table_name, columns, OnConflict::Update), action)
+    }
+
+    /// Fail with [`DatabaseError::UnknownColumns`] when `names` includes
+    /// columns `table_name` does not have, naming all of them with their
+    /// close matches rather than leaving SQLite to report the first. A table
+    /// that does not exist is left for the write itself to report.
+    async fn check_write_columns<'a>(
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        names: impl IntoIterator<Item = &'a String>,
+    ) -> Result<()> {
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_xinfo(?)")
+            .bind(table_name)
+            .fetch_all(&mut *conn)
+            .await?;
+        if columns.is_empty() {
+            return Ok(());
+        }
+
+        let mut unknown: Vec<UnknownColumn> = Vec::new();
+        for name in names {
+            // Keys may be quoted as they are written into the SQL as given
+            let bare = name.trim_matches(|c| matches!(c, '"' | '`' | '[' | ']'));
+            let known = columns.iter().any(|column| column.eq_ignore_ascii_case(bare));
+            if !known && !unknown.iter().any(|column| column.name == *name) {
+                unknown.push(UnknownColumn::new(name, columns.iter().map(String::as_str)));
+            }
+        }
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort_by(|a, b| a.name.cmp(&b.name));
+        Err(DatabaseError::UnknownColumns { table: table_name.to_string(), columns: unknown })
+    }
+
+    /// Insert one row on `conn`. Under `Replace` and `Update` the row is first
+    /// inserted with ON CONFLICT DO NOTHING, so a conflict can be told apart from a new row;
+    /// only then is the conflicting row replaced or updated.
+    async fn write_row(
+        &self,
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        values: &HashMap<String, DbValue>,
+        on_conflict: OnConflict,
+    ) -> Result<RowWrite> {
+        let entries = Self::sorted_entries(values);
+        let columns: Vec<&String> = entries.iter().map(|(column, _)| *column).collect();
+        let first = if on_conflict == OnConflict::Abort { OnConflict::Abort } else { OnConflict::Ignore };
+        let result = self.execute_insert(conn, &Self::insert_sql(table_name, &columns, first), &entries).await?;
+        if result.rows_affected() > 0 {
+            return Ok(RowWrite::Inserted(result.last_insert_rowid()));
+        }
+        match on_conflict {
+            OnConflict::Replace => {
+                let sql = Self::insert_sql(table_name, &columns, on_conflict);
+                let result = self.execute_insert(conn, &sql, &entries).await?;
+                Ok(RowWrite::Replaced(Some(result.last_insert_rowid())))
+            }
+            OnConflict::Update => {
+                let keys: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?) WHERE pk > 0")
+                    .bind(table_name)
+                    .fetch_all(&mut *conn)
+                    .await?;
+                let result = self.execute_insert(conn, &Self::upsert_sql(table_name, &columns, &keys), &entries).await?;
+                // last_insert_rowid() is not set by an upsert's UPDATE
+                Ok(if result.rows_affected() > 0 { RowWrite::Replaced(None) } else { RowWrite::Skipped })
+            }
+            OnConflict::Abort | OnConflict::Ignore => Ok(RowWrite::Skipped),
+        }
+    }
+
+    async fn execute_insert(
+        &self,
+        conn: &mut SqliteConnection,
+        sql: &str,
+        entries: &[(&String, &DbValue)],
+    ) -> Result<sqlx::sqlite::SqliteQueryResult> {
+        let mut query = self.prepare(sql);
+        for (_, value) in entries {
+            query = Self::bind_value(query, value);
+        }
+        Ok(query.execute(&mut *conn).await?)
+    }
+
     fn column_type_to_sql(col_type: &ColumnType) -> &'static str {
         match col_type {
             ColumnType::Integer => "INTEGER",
             ColumnType::Real => "REAL",
             ColumnType::Text => "TEXT",
             ColumnType::Blob => "BLOB",
-            ColumnType::Boolean => "INTEGER",
-            ColumnType::Timestamp => "INTEGER",
+            // Declared by name so results can tell these columns apart from
+            // INTEGER ones; SQLite still stores 0/1 and Unix seconds
+            ColumnType::Boolean => "BOOLEAN",
+            ColumnType::Timestamp => "TIMESTAMP",
+        }
+    }
+
+    /// The DEFAULT clause value for a column default written as a plain
+    /// value: quoted as a literal of the column's type, `CURRENT_TIMESTAMP`
+    /// (Unix seconds for TIMESTAMP columns), `NULL`, or a parenthesized
+    /// expression passed through as is
+    pub fn default_sql(column: &str, default: &str, col_type: &ColumnType) -> Result<String> {
+        let default = default.trim();
+        let invalid = || {
+            DatabaseError::QueryError(format!(
+                "Invalid default {} for {} column '{}'",
+                default,
+                Self::column_type_to_sql(col_type),
+                column
+            ))
+        };
+        if default.starts_with('(') && default.ends_with(')') {
+            return Ok(default.to_string());
+        }
+        let keyword = default.to_uppercase();
+        match (keyword.as_str(), col_type) {
+            ("NULL", _) => return Ok(keyword),
+            ("CURRENT_TIMESTAMP", ColumnType::Timestamp) => return Ok("(unixepoch())".to_string()),
+            ("CURRENT_TIMESTAMP" | "CURRENT_DATE" | "CURRENT_TIME", ColumnType::Text) => return Ok(keyword),
+            _ => {}
         }
+
+        let sql = match col_type {
+            ColumnType::Integer | ColumnType::Timestamp => default.parse::<i64>().map_err(|_| invalid())?.to_string(),
+            ColumnType::Real => default.parse::<f64>().map_err(|_| invalid())?.to_string(),
+            ColumnType::Boolean => match keyword.as_str() {
+                "TRUE" | "1" => "1".to_string(),
+                "FALSE" | "0" => "0".to_string(),
+                _ => return Err(invalid()),
+            },
+            ColumnType::Text => {
+                // One layer of quotes around the text is optional
+                let text = ['\'', '"']
+                    .iter()
+                    .find_map(|&q| default.strip_prefix(q).and_then(|s| s.strip_suffix(q)))
+                    .map(|s| if default.starts_with('\'') { s.replace("''", "'") } else { s.to_string() })
+                    .unwrap_or_else(|| default.to_string());
+                format!("'{}'", text.replace('\'', "''"))
+            }
+            ColumnType::Blob => {
+                let hex = regex::Regex::new(r"^[xX]'([0-9a-fA-F]{2})*'$").unwrap();
+                if !hex.is_match(default) {
+                    return Err(invalid());
+                }
+                default.to_string()
+            }
+        };
+        Ok(sql)
+    }
+
+    /// CHECK constraint limiting a column to a list of text values
+    pub fn allowed_values_sql(column: &str, values: &[String]) -> String {
+        let quoted: Vec<String> = values.iter().map(|value| format!("'{}'", value.replace('\'', "''"))).collect();
+        format!("CHECK ({} IN ({}))", column, quoted.join(", "))
+    }
+
+    /// Allowed values of each column limited by `allowed_values_sql`, read
+    /// back from a table's CREATE TABLE statement
+    pub fn parse_allowed_values(create_sql: &str) -> HashMap<String, Vec<String>> {
+        let check = regex::Regex::new(r"CHECK \((\w+) IN \(('(?:[^']|'')*'(?:, '(?:[^']|'')*')*)\)\)").unwrap();
+        let value = regex::Regex::new(r"'((?:[^']|'')*)'").unwrap();
+        check
+            .captures_iter(create_sql)
+            .map(|captures| {
+                let values = value.captures_iter(&captures[2]).map(|v| v[1].replace("''", "'")).collect();
+                (captures[1].to_string(), values)
+            })
+            .collect()
     }
 
-    fn build_create_table_sql(table_name: &str, columns: &[ColumnDef]) -> String {
-        let mut sql = format!("CREATE TABLE {} (", table_name);
+    /// CREATE TABLE with the columns followed by a UNIQUE constraint per entry of `unique`
+    pub fn build_create_table_sql(table_name: &str, columns: &[ColumnDef], unique: &[Vec<String>]) -> String {
+        let mut defs = Self::column_defs_sql(columns);
+        for set in unique {
+            defs.push_str(&format!(", UNIQUE ({})", set.join(", ")));
+        }
+        format!("CREATE TABLE {} ({})", table_name, defs)
+    }
 
+    /// Comma-separated column definitions for CREATE TABLE
+    pub fn column_defs_sql(columns: &[ColumnDef]) -> String {
         let column_defs: Vec<String> = columns
             .iter()
             .map(|col| {
                 let mut def = format!("{} {}", col.name, Self::column_type_to_sql(&col.col_type));
 
+                if let Some(collation) = col.collation {
+                    def.push_str(&format!(" COLLATE {}", collation.as_str()));
+                }
+
                 if col.primary_key {
                     def.push_str(" PRIMARY KEY");
                 }
@@ -44,256 +443,484 @@ impl SqliteDatabase {
                 if let Some(default) = &col.default_value {
                     def.push_str(&format!(" DEFAULT {}", default));
                 }
+                if let Some(generated) = &col.generated {
+                    let storage = if generated.stored { "STORED" } else { "VIRTUAL" };
+                    def.push_str(&format!(" GENERATED ALWAYS AS ({}) {}", generated.expression, storage));
+                }
+                if let Some(values) = &col.allowed_values {
+                    def.push_str(&format!(" {}", Self::allowed_values_sql(&col.name, values)));
+                }
 
                 def
             })
             .collect();
 
-        sql.push_str(&column_defs.join(", "));
-        sql.push(')');
+        column_defs.join(", ")
+    }
 
-        sql
+    /// Map a declared SQL column type to the closest ColumnType
+    fn declared_type_to_column_type(sql_type: &str) -> ColumnType {
+        let sql_type = sql_type.to_uppercase();
+        if sql_type.contains("BOOL") {
+            ColumnType::Boolean
+        } else if sql_type.contains("TIMESTAMP") || sql_type.contains("DATE") {
+            ColumnType::Timestamp
+        } else if sql_type.contains("INT") {
+            ColumnType::Integer
+        } else if sql_type.contains("REAL") || sql_type.contains("FLOA") || sql_type.contains("DOUB") {
+            ColumnType::Real
+        } else if sql_type.contains("BLOB") {
+            ColumnType::Blob
+        } else {
+            ColumnType::Text
+        }
     }
 
-    fn bind_value<'q>(
-        query: sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
-        value: &'q DbValue,
-    ) -> sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
-        match value {
-            DbValue::Integer(v) => query.bind(v),
-            DbValue::Real(v) => query.bind(v),
-            DbValue::Text(v) => query.bind(v),
-            DbValue::Blob(v) => query.bind(v),
-            DbValue::Boolean(v) => query.bind(*v as i32),
-            DbValue::Timestamp(v) => query.bind(v),
-            DbValue::Null => query.bind(None::<i32>),
+    /// Columns of a table as (name, declared type, primary key position; 0 if not part of the key),
+    /// including generated columns
+    async fn table_columns(&self, table_name: &str) -> Result<Vec<(String, String, i64)>> {
+        let rows = sqlx::query(&format!("PRAGMA table_xinfo({})", table_name))
+            .fetch_all(&self.pool)
+            .await?;
+        if rows.is_empty() {
+            return Err(DatabaseError::TableNotFound(table_name.to_string()));
         }
+
+        // hidden is 1 for the hidden columns of virtual tables, which SELECT * leaves out
+        Ok(rows
+            .iter()
+            .filter(|row| row.get::<i64, _>("hidden") != 1)
+            .map(|row| (row.get::<String, _>("name"), row.get::<String, _>("type"), row.get::<i64, _>("pk")))
+            .collect())
     }
 
-    async fn row_to_values(row: &sqlx::sqlite::SqliteRow) -> Result<Vec<DbValue>> {
-        use sqlx::Row;
-        use sqlx::ValueRef;
-        
-        let mut values = Vec::new();
-        let column_count = row.len();
+    /// Replace a materialized view's backing table with the current result of
+    /// its query, returning the new row count
+    async fn fill_materialized_view(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        name: &str,
+        query: &str,
+    ) -> Result<u64> {
+        let sql = format!("DROP TABLE IF EXISTS {}", name);
+        self.prepare(&sql).execute(&mut **tx).await?;
+        let sql = format!("CREATE TABLE {} AS {}", name, query);
+        self.prepare(&sql).execute(&mut **tx).await?;
 
-        for i in 0..column_count {
-            // First check if the value is NULL
-            let raw_value = row.try_get_raw(i)?;
-            if raw_value.is_null() {
-                values.push(DbValue::Null);
-                continue;
-            }
-            
-            // Try different types in order
-            let value = if let Ok(v) = row.try_get::<i64, _>(i) {
-                // Check if this might be a boolean (0 or 1)
-                // We need column type info to determine this properly
-                // For now, we'll treat all integers as integers
-                DbValue::Integer(v)
-            } else if let Ok(v) = row.try_get::<f64, _>(i) {
-                DbValue::Real(v)
-            } else if let Ok(v) = row.try_get::<String, _>(i) {
-                DbValue::Text(v)
-            } else if let Ok(v) = row.try_get::<Vec<u8>, _>(i) {
-                DbValue::Blob(v)
-            } else {
-                DbValue::Null
+        let sql = format!("UPDATE {} SET last_refreshed = unixepoch(), last_attempted = unixepoch() WHERE table_name = ?1", MATERIALIZED_VIEWS_TABLE);
+        sqlx::query(&sql).bind(name).execute(&mut **tx).await?;
+
+        let sql = format!("SELECT COUNT(*) FROM {}", name);
+        let rows: i64 = sqlx::query_scalar(&sql).fetch_one(&mut **tx).await?;
+        Ok(rows as u64)
+    }
+
+    /// How a table is partitioned and the column definitions its partitions
+    /// are created with, if it is partitioned
+    async fn partition_spec(&self, table_name: &str) -> Result<Option<(Partitioning, String)>> {
+        if !self.table_exists(PARTITIONED_TABLES_TABLE).await? {
+            return Ok(None);
+        }
+        let sql = format!(
+            "SELECT column_name, period, retention, column_defs FROM {} WHERE table_name = ?1",
+            PARTITIONED_TABLES_TABLE
+        );
+        let row: Option<(String, String, Option<i64>, String)> =
+            sqlx::query_as(&sql).bind(table_name).fetch_optional(&self.pool).await?;
+        row.map(|(column, period, retention, column_defs)| {
+            let partitioning = Partitioning {
+                column,
+                period: period.parse()?,
+                retention: retention.map(|r| r as u32),
             };
+            Ok((partitioning, column_defs))
+        })
+        .transpose()
+    }
 
-            values.push(value);
+    /// Names of a table's partitions, oldest first
+    async fn partition_names(
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        period: PartitionPeriod,
+    ) -> Result<Vec<String>> {
+        let names = sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name GLOB ?1 ORDER BY name")
+            .bind(period.glob(table_name))
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(names)
+    }
+
+    /// Point a partitioned table's view at its current partitions
+    async fn rebuild_partition_view(conn: &mut SqliteConnection, table_name: &str, period: PartitionPeriod) -> Result<()> {
+        let partitions = Self::partition_names(conn, table_name, period).await?;
+        sqlx::query(&format!("DROP VIEW IF EXISTS {}", table_name)).execute(&mut *conn).await?;
+        if partitions.is_empty() {
+            return Ok(());
         }
 
-        Ok(values)
+        sqlx::query(&Self::partition_view_sql(table_name, &partitions)).execute(&mut *conn).await?;
+        Ok(())
     }
-}
 
-#[async_trait]
-impl Database for SqliteDatabase {
-    async fn connect(connection_string: &str) -> Result<Self> {
-        let pool = SqlitePool::connect(connection_string)
-            .await
-            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+    /// The view a partitioned table is read through, over its partitions
+    pub fn partition_view_sql(table_name: &str, partitions: &[String]) -> String {
+        let selects: Vec<String> = partitions.iter().map(|p| format!("SELECT * FROM {}", p)).collect();
+        format!("CREATE VIEW {} AS {}", table_name, selects.join(" UNION ALL "))
+    }
 
-        Ok(SqliteDatabase { pool })
+    /// A plain index over columns of a table
+    pub fn create_index_sql(name: &str, table_name: &str, columns: &[String]) -> String {
+        format!("CREATE INDEX {} ON {} ({})", name, table_name, columns.join(", "))
     }
 
-    async fn create_table(&self, table_name: &str, columns: Vec<ColumnDef>) -> Result<()> {
-        let sql = Self::build_create_table_sql(table_name, &columns);
+    /// Statements creating an FTS5 search index over columns of a table, with
+    /// the triggers that keep it in sync, and indexing the existing rows
+    pub fn search_index_sql(table_name: &str, columns: &[String]) -> Vec<String> {
+        let index = search_index_name(table_name);
+        let column_list = columns.join(", ");
+        let new_values: Vec<String> = columns.iter().map(|c| format!("new.{}", c)).collect();
+        let old_values: Vec<String> = columns.iter().map(|c| format!("old.{}", c)).collect();
+        let add_new = format!(
+            "INSERT INTO {index} (rowid, {column_list}) VALUES (new.rowid, {});",
+            new_values.join(", ")
+        );
+        let remove_old = format!(
+            "INSERT INTO {index} ({index}, rowid, {column_list}) VALUES ('delete', old.rowid, {});",
+            old_values.join(", ")
+        );
 
-        sqlx::query(&sql)
-            .execute(&self.pool)
-            .await
-            .map_err(|e| match e {
-                sqlx::Error::Database(db_err) if db_err.message().contains("already exists") => {
-                    DatabaseError::TableAlreadyExists(table_name.to_string())
-                }
-                _ => DatabaseError::from(e),
-            })?;
+        // External-content index: FTS5 stores only the index and reads text from the table
+        vec![
+            format!("CREATE VIRTUAL TABLE {index} USING fts5({column_list}, content='{table_name}')"),
+            format!("CREATE TRIGGER {index}_ai AFTER INSERT ON {table_name} BEGIN {add_new} END"),
+            format!("CREATE TRIGGER {index}_ad AFTER DELETE ON {table_name} BEGIN {remove_old} END"),
+            format!("CREATE TRIGGER {index}_au AFTER UPDATE ON {table_name} BEGIN {remove_old} {add_new} END"),
+            // Index the rows that are already there
+            format!("INSERT INTO {index} ({index}) VALUES ('rebuild')"),
+        ]
+    }
 
-        Ok(())
+    /// Statements giving a table created with `columns` a history table
+    /// (see `db::history`)
+    pub fn history_sql(table_name: &str, columns: &[ColumnDef]) -> Vec<String> {
+        history::history_sql(table_name, &Self::history_columns(columns))
     }
 
-    async fn drop_table(&self, table_name: &str) -> Result<()> {
-        let sql = format!("DROP TABLE IF EXISTS {}", table_name);
+    /// Names and declared types of columns, as a history table has them
+    pub fn history_columns(columns: &[ColumnDef]) -> Vec<(String, String)> {
+        columns
+            .iter()
+            .map(|c| (c.name.clone(), Self::column_type_to_sql(&c.col_type).to_string()))
+            .collect()
+    }
 
-        sqlx::query(&sql).execute(&self.pool).await?;
+    /// The search index, spatial index and history table of `table_name` that
+    /// exist and were created for it, judged by their definitions: a table that
+    /// only has one of their names is not one of them
+    async fn companion_tables(&self, table_name: &str) -> Result<Vec<String>> {
+        let mut companions = Vec::new();
+        let search_index = search_index_name(table_name);
+        let marker = format!("content='{}'", table_name.to_lowercase());
+        if self.virtual_table_sql(&search_index, "fts5").await?.is_some_and(|sql| sql.contains(&marker)) {
+            companions.push(search_index);
+        }
+        let spatial_index = spatial_index_name(table_name);
+        if self.virtual_table_sql(&spatial_index, "rtree").await?.is_some() {
+            companions.push(spatial_index);
+        }
 
-        Ok(())
+        let history = history::history_table_name(table_name);
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?1) ORDER BY cid")
+            .bind(&history)
+            .fetch_all(&self.pool)
+            .await?;
+        if columns.len() > history::HISTORY_COLUMNS.len() && columns.iter().zip(history::HISTORY_COLUMNS).all(|(a, b)| a == b) {
+            companions.push(history);
+        }
+        Ok(companions)
     }
 
-    async fn insert(&self, table_name: &str, values: HashMap<String, DbValue>) -> Result<i64> {
-        if values.is_empty() {
-            return Err(DatabaseError::QueryError("No values provided".to_string()));
-        }
+    /// The lowercased definition of `name` if it is a virtual table using `module`
+    async fn virtual_table_sql(&self, name: &str, module: &str) -> Result<Option<String>> {
+        let sql: Option<String> = sqlx::query_scalar("SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1 COLLATE NOCASE")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        let using = format!("using {}(", module);
+        Ok(sql
+            .map(|sql| sql.to_lowercase())
+            .filter(|sql| sql.starts_with("create virtual table") && sql.contains(&using)))
+    }
 
-        // Special handling for notes table - track history
-        if table_name == "notes" {
-            // Start a transaction to ensure atomicity
-            let mut tx = self.pool.begin().await?;
-            
-            // Perform the insert
-            let columns: Vec<&String> = values.keys().collect();
-            let placeholders: Vec<String> = (0..columns.len()).map(|i| format!("?{}", i + 1)).collect();
+    /// Statements removing a table's search index and its triggers
+    pub fn drop_search_index_sql(table_name: &str) -> Vec<String> {
+        Self::drop_synced_index_sql(&search_index_name(table_name))
+    }
 
-            let sql = format!(
-                "INSERT INTO {} ({}) VALUES ({})",
-                table_name,
-                columns
-                    .iter()
-                    .map(|c| c.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", "),
-                placeholders.join(", ")
-            );
+    /// Statements removing a table's spatial index and its triggers
+    pub fn drop_spatial_index_sql(table_name: &str) -> Vec<String> {
+        Self::drop_synced_index_sql(&spatial_index_name(table_name))
+    }
 
-            let mut query = sqlx::query(&sql);
-            for (_, value) in values.iter() {
-                query = Self::bind_value(query, value);
+    /// The triggers are gone already if their table was dropped
+    fn drop_synced_index_sql(index: &str) -> Vec<String> {
+        let mut statements: Vec<String> = ["ai", "ad", "au"]
+            .iter()
+            .map(|suffix| format!("DROP TRIGGER IF EXISTS {index}_{suffix}"))
+            .collect();
+        statements.push(format!("DROP TABLE {index}"));
+        statements
+    }
+
+    /// Statements creating an R*Tree spatial index over 2 point columns (x, y)
+    /// or 4 box columns (min_x, min_y, max_x, max_y) of a table, with the
+    /// triggers that keep it in sync, and indexing the existing rows
+    pub fn spatial_index_sql(table_name: &str, columns: &[String]) -> Result<Vec<String>> {
+        // A point is stored as a zero-size box
+        let (min_x, min_y, max_x, max_y) = match columns {
+            [x, y] => (x, y, x, y),
+            [min_x, min_y, max_x, max_y] => (min_x, min_y, max_x, max_y),
+            _ => {
+                return Err(DatabaseError::QueryError(
+                    "A spatial index needs 2 columns (x, y) or 4 (min_x, min_y, max_x, max_y)".to_string(),
+                ))
             }
+        };
+        let index = spatial_index_name(table_name);
 
-            let result = query.execute(&mut *tx).await?;
-            let inserted_id = result.last_insert_rowid();
-            
-            // Now insert into history
-            let history_sql = format!(
-                "INSERT INTO notes_history (id, title, description, created_at, created_by, status, priority, url, last_updated, updated_by, operation)
-                 SELECT id, title, description, created_at, created_by, status, priority, url, strftime('%s', 'now'), NULL, 'INSERT'
-                 FROM notes WHERE id = {}", 
-                inserted_id
-            );
-            sqlx::query(&history_sql).execute(&mut *tx).await?;
-            
-            // Commit the transaction
-            tx.commit().await?;
-            
-            Ok(inserted_id)
-        } else {
-            // Regular insert for other tables
-            let columns: Vec<&String> = values.keys().collect();
-            let placeholders: Vec<String> = (0..columns.len()).map(|i| format!("?{}", i + 1)).collect();
+        // Rows with a NULL coordinate are left out of the index
+        let row_values = |row: &str, from: &str| {
+            format!(
+                "SELECT {row}.rowid, {row}.{min_x}, {row}.{max_x}, {row}.{min_y}, {row}.{max_y}{from} \
+                 WHERE {row}.{min_x} IS NOT NULL AND {row}.{max_x} IS NOT NULL \
+                 AND {row}.{min_y} IS NOT NULL AND {row}.{max_y} IS NOT NULL"
+            )
+        };
+        let add_new = format!("INSERT INTO {index} {};", row_values("new", ""));
+        let remove_old = format!("DELETE FROM {index} WHERE id = old.rowid;");
 
-            let sql = format!(
-                "INSERT INTO {} ({}) VALUES ({})",
-                table_name,
-                columns
-                    .iter()
-                    .map(|c| c.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", "),
-                placeholders.join(", ")
-            );
+        Ok(vec![
+            format!("CREATE VIRTUAL TABLE {index} USING rtree(id, min_x, max_x, min_y, max_y)"),
+            format!("CREATE TRIGGER {index}_ai AFTER INSERT ON {table_name} BEGIN {add_new} END"),
+            format!("CREATE TRIGGER {index}_ad AFTER DELETE ON {table_name} BEGIN {remove_old} END"),
+            format!("CREATE TRIGGER {index}_au AFTER UPDATE ON {table_name} BEGIN {remove_old} {add_new} END"),
+            // Index the rows that are already there
+            format!("INSERT INTO {index} {}", row_values(table_name, &format!(" FROM {table_name}"))),
+        ])
+    }
 
-            let mut query = sqlx::query(&sql);
-            for (_, value) in values.iter() {
-                query = Self::bind_value(query, value);
+    /// The partition holding rows from `time`, created if it does not exist yet
+    async fn ensure_partition(
+        &self,
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        partitioning: &Partitioning,
+        column_defs: &str,
+        time: DateTime<Utc>,
+    ) -> Result<String> {
+        let name = partition::partition_name(table_name, &partitioning.period.suffix(time));
+        let exists: Option<i64> = sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE name = ?1")
+            .bind(&name)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if exists.is_none() {
+            let sql = format!("CREATE TABLE {} ({})", name, column_defs);
+            sqlx::query(&sql).execute(&mut *conn).await?;
+            Self::rebuild_partition_view(conn, table_name, partitioning.period).await?;
+        }
+        Ok(name)
+    }
+
+    /// Insert rows of a partitioned table into the partitions for their time.
+    /// Rows without a time go to the current partition. Returns the number of
+    /// rows written and the rowid of the last one.
+    async fn insert_partitioned(
+        &self,
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        (partitioning, column_defs): (Partitioning, String),
+        rows: Vec<HashMap<String, DbValue>>,
+        on_conflict: OnConflict,
+    ) -> Result<(InsertCounts, Option<i64>)> {
+        let now = Utc::now();
+        let mut counts = InsertCounts::default();
+        let mut last_id = None;
+
+        for row in rows {
+            if row.is_empty() {
+                continue;
             }
 
-            let result = query.execute(&self.pool).await?;
-            Ok(result.last_insert_rowid())
+            let time = match row.get(&partitioning.column) {
+                Some(value) => partition::value_time(value)?,
+                None => None,
+            };
+            let partition = self
+                .ensure_partition(conn, table_name, &partitioning, &column_defs, time.unwrap_or(now))
+                .await?;
+
+            // A conflict is only seen within the row's own partition
+            let write = self.write_row(conn, &partition, &row, on_conflict).await?;
+            match write {
+                RowWrite::Inserted(id) => last_id = Some(id),
+                RowWrite::Replaced(id) => last_id = id.or(last_id),
+                RowWrite::Skipped => {}
+            }
+            write.count(&mut counts);
         }
+
+        Ok((counts, last_id))
     }
 
-    async fn update(
+    /// Run a statement built for each partition of a table in one transaction,
+    /// binding `values` every time, and return the total rows changed
+    async fn execute_on_partitions(
         &self,
+        conn: &mut SqliteConnection,
         table_name: &str,
-        values: HashMap<String, DbValue>,
-        where_clause: &str,
+        period: PartitionPeriod,
+        statement: impl Fn(&str) -> String,
+        values: &[&DbValue],
     ) -> Result<u64> {
+        let mut changed = 0;
+        for partition in Self::partition_names(conn, table_name, period).await? {
+            let sql = statement(&partition);
+            let mut query = self.prepare(&sql);
+            for value in values {
+                query = Self::bind_value(query, value);
+            }
+            changed += query.execute(&mut *conn).await?.rows_affected();
+        }
+        Ok(changed)
+    }
+
+    /// Insert a row on `conn`, returning its rowid, or None if it was skipped
+    /// under `OnConflict::Ignore`. Inserts into `notes` are recorded in
+    /// `notes_history`.
+    async fn insert_on(
+        &self,
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        values: HashMap<String, DbValue>,
+        on_conflict: OnConflict,
+        partitioning: Option<(Partitioning, String)>,
+    ) -> Result<Option<i64>> {
         if values.is_empty() {
             return Err(DatabaseError::QueryError("No values provided".to_string()));
         }
 
-        // Special handling for notes table - track history
-        if table_name == "notes" {
-            // Start a transaction to ensure atomicity
-            let mut tx = self.pool.begin().await?;
-            
-            // First, capture the current state of notes that will be updated
+        if let Some(spec) = partitioning {
+            let (_, last_id) = self.insert_partitioned(conn, table_name, spec, vec![values], on_conflict).await?;
+            return Ok(last_id);
+        }
+        Self::check_write_columns(conn, table_name, values.keys()).await?;
+
+        let inserted_id = match self.write_row(conn, table_name, &values, on_conflict).await? {
+            RowWrite::Inserted(id) => id,
+            RowWrite::Replaced(id) => return Ok(id),
+            // Skipped on conflict: there is nothing to record
+            RowWrite::Skipped => return Ok(None),
+        };
+
+        // Special handling for notes table - track history
+        if table_name == "notes" {
             let history_sql = format!(
                 "INSERT INTO notes_history (id, title, description, created_at, created_by, status, priority, url, last_updated, updated_by, operation)
-                 SELECT id, title, description, created_at, created_by, status, priority, url, strftime('%s', 'now'), NULL, 'UPDATE'
-                 FROM notes WHERE {}", 
-                where_clause
+                 SELECT id, title, description, created_at, created_by, status, priority, url, strftime('%s', 'now'), NULL, 'INSERT'
+                 FROM notes WHERE id = {}", 
+                inserted_id
             );
-            sqlx::query(&history_sql).execute(&mut *tx).await?;
-            
-            // Now perform the update
-            let set_clauses: Vec<String> = values
-                .keys()
-                .enumerate()
-                .map(|(i, col)| format!("{} = ?{}", col, i + 1))
-                .collect();
+            self.prepare(&history_sql).execute(&mut *conn).await?;
+        }
 
-            let sql = format!(
-                "UPDATE {} SET {} WHERE {}",
-                table_name,
-                set_clauses.join(", "),
-                where_clause
-            );
+        Ok(Some(inserted_id))
+    }
 
-            let mut query = sqlx::query(&sql);
-            for (_, value) in values.iter() {
-                query = Self::bind_value(query, value);
-            }
+    /// Update rows on `conn`, returning how many changed. The previous state
+    /// of updated `notes` rows is recorded in `notes_history`.
+    async fn update_on(
+        &self,
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        values: HashMap<String, DbValue>,
+        where_clause: &str,
+        partitioning: Option<(Partitioning, String)>,
+    ) -> Result<u64> {
+        if values.is_empty() {
+            return Err(DatabaseError::QueryError("No values provided".to_string()));
+        }
 
-            let result = query.execute(&mut *tx).await?;
-            
-            // Commit the transaction
-            tx.commit().await?;
-            
-            Ok(result.rows_affected())
-        } else {
-            // Regular update for other tables
-            let set_clauses: Vec<String> = values
-                .keys()
-                .enumerate()
-                .map(|(i, col)| format!("{} = ?{}", col, i + 1))
-                .collect();
+        if partitioning.is_none() {
+            Self::check_write_columns(conn, table_name, values.keys()).await?;
+        }
 
-            let sql = format!(
-                "UPDATE {} SET {} WHERE {}",
-                table_name,
-                set_clauses.join(", "),
+        let entries = Self::sorted_entries(&values);
+        let set_clauses: Vec<String> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (col, _))| format!("{} = ?{}", col, i + 1))
+            .collect();
+        let set_clauses = set_clauses.join(", ");
+
+        if let Some((partitioning, _)) = partitioning {
+            let values: Vec<&DbValue> = entries.iter().map(|(_, value)| *value).collect();
+            return self
+                .execute_on_partitions(
+                    conn,
+                    table_name,
+                    partitioning.period,
+                    |partition| format!("UPDATE {} SET {} WHERE {}", partition, set_clauses, where_clause),
+                    &values,
+                )
+                .await;
+        }
+
+        // Special handling for notes table - track history
+        if table_name == "notes" {
+            // First, capture the current state of notes that will be updated
+            let history_sql = format!(
+                "INSERT INTO notes_history (id, title, description, created_at, created_by, status, priority, url, last_updated, updated_by, operation)
+                 SELECT id, title, description, created_at, created_by, status, priority, url, strftime('%s', 'now'), NULL, 'UPDATE'
+                 FROM notes WHERE {}", 
                 where_clause
             );
+            self.prepare(&history_sql).execute(&mut *conn).await?;
+        }
 
-            let mut query = sqlx::query(&sql);
-            for (_, value) in values.iter() {
-                query = Self::bind_value(query, value);
-            }
-
-            let result = query.execute(&self.pool).await?;
-            Ok(result.rows_affected())
+        let sql = format!("UPDATE {} SET {} WHERE {}", table_name, set_clauses, where_clause);
+        let mut query = self.prepare(&sql);
+        for (_, value) in entries.iter() {
+            query = Self::bind_value(query, value);
         }
+
+        let result = query.execute(&mut *conn).await?;
+        Ok(result.rows_affected())
     }
 
-    async fn delete(&self, table_name: &str, where_clause: &str) -> Result<u64> {
+    /// Delete rows on `conn`, returning how many were deleted. Deleted `notes`
+    /// rows and their tags are archived first.
+    async fn delete_on(
+        &self,
+        conn: &mut SqliteConnection,
+        table_name: &str,
+        where_clause: &str,
+        partitioning: Option<(Partitioning, String)>,
+    ) -> Result<u64> {
+        if let Some((partitioning, _)) = partitioning {
+            return self
+                .execute_on_partitions(
+                    conn,
+                    table_name,
+                    partitioning.period,
+                    |partition| format!("DELETE FROM {} WHERE {}", partition, where_clause),
+                    &[],
+                )
+                .await;
+        }
+
         // Special handling for notes table - archive before deletion
         if table_name == "notes" {
-            // Start a transaction to ensure atomicity
-            let mut tx = self.pool.begin().await?;
-            
             // Archive the notes to be deleted
             let archive_sql = format!(
                 "INSERT INTO notes_archive (id, title, description, created_at, created_by, status, priority, url, deleted_at, deleted_by)
@@ -301,7 +928,7 @@ impl Database for SqliteDatabase {
                  FROM notes WHERE {}", 
                 where_clause
             );
-            sqlx::query(&archive_sql).execute(&mut *tx).await?;
+            self.prepare(&archive_sql).execute(&mut *conn).await?;
             
             // Archive the note_tags relationships
             let archive_tags_sql = format!(
@@ -312,7 +939,7 @@ impl Database for SqliteDatabase {
                  WHERE {}", 
                 where_clause
             );
-            sqlx::query(&archive_tags_sql).execute(&mut *tx).await?;
+            self.prepare(&archive_tags_sql).execute(&mut *conn).await?;
             
             // Delete the note_tags relationships
             let delete_tags_sql = format!(
@@ -320,28 +947,300 @@ impl Database for SqliteDatabase {
                  WHERE note_id IN (SELECT id FROM notes WHERE {})", 
                 where_clause
             );
-            sqlx::query(&delete_tags_sql).execute(&mut *tx).await?;
-            
-            // Now delete the notes
-            let delete_sql = format!("DELETE FROM {} WHERE {}", table_name, where_clause);
-            let result = sqlx::query(&delete_sql).execute(&mut *tx).await?;
+            self.prepare(&delete_tags_sql).execute(&mut *conn).await?;
+        }
+
+        let sql = format!("DELETE FROM {} WHERE {}", table_name, where_clause);
+        let result = self.prepare(&sql).execute(&mut *conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn table_exists(&self, table_name: &str) -> Result<bool> {
+        let found: Option<i64> = sqlx::query_scalar("SELECT 1 FROM sqlite_master WHERE name = ?1")
+            .bind(table_name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(found.is_some())
+    }
+
+    /// Fail with a QueryError unless the table has history
+    async fn check_history(&self, table_name: &str) -> Result<()> {
+        if !self.table_exists(&history::history_table_name(table_name)).await? {
+            return Err(DatabaseError::QueryError(format!("Table '{}' has no history", table_name)));
+        }
+        Ok(())
+    }
+
+    /// The statement with its `AS OF` clauses rewritten to read history tables
+    async fn resolve_as_of<'a>(&self, sql: &'a str) -> Result<Cow<'a, str>> {
+        let clauses = history::find_as_of(sql)?;
+        if clauses.is_empty() {
+            return Ok(Cow::Borrowed(sql));
+        }
+        let mut columns = HashMap::new();
+        for clause in &clauses {
+            if !self.table_exists(&history::history_table_name(&clause.table)).await? {
+                return Err(DatabaseError::QueryError(format!(
+                    "Table '{}' has no history to query AS OF",
+                    clause.table
+                )));
+            }
+            let names = self.table_columns(&clause.table).await?.into_iter().map(|(name, _, _)| name).collect();
+            columns.insert(clause.table.clone(), names);
+        }
+        Ok(Cow::Owned(history::rewrite_as_of(sql, &clauses, &columns)))
+    }
+
+    /// Fail with a QueryError unless every column exists in the table
+    async fn check_columns(&self, table_name: &str, columns: &[String]) -> Result<()> {
+        if columns.is_empty() {
+            return Err(DatabaseError::QueryError("No columns to index".to_string()));
+        }
+        let table_columns = self.table_columns(table_name).await?;
+        if let Some(missing) = columns.iter().find(|c| !table_columns.iter().any(|(name, _, _)| name == *c)) {
+            return Err(DatabaseError::QueryError(format!(
+                "Column '{}' not found in table '{}'",
+                missing, table_name
+            )));
+        }
+        Ok(())
+    }
+
+    fn bind_value<'q>(query: SqliteQuery<'q>, value: &'q DbValue) -> SqliteQuery<'q> {
+        sql_log::record_parameter(value);
+        match value {
+            DbValue::Integer(v) => query.bind(v),
+            DbValue::Real(v) => query.bind(v),
+            DbValue::Text(v) => query.bind(v),
+            DbValue::Blob(v) => query.bind(v),
+            DbValue::Boolean(v) => query.bind(*v as i32),
+            DbValue::Timestamp(v) => query.bind(v),
+            DbValue::Null => query.bind(None::<i32>),
+        }
+    }
+
+    async fn row_to_values(row: &sqlx::sqlite::SqliteRow) -> Result<Vec<DbValue>> {
+        use sqlx::Row;
+        use sqlx::ValueRef;
+        
+        let mut values = Vec::new();
+        let column_count = row.len();
+
+        for i in 0..column_count {
+            // First check if the value is NULL
+            let raw_value = row.try_get_raw(i)?;
+            if raw_value.is_null() {
+                values.push(DbValue::Null);
+                continue;
+            }
             
-            // Commit the transaction
+            // Try different types in order
+            let value = if let Ok(v) = row.try_get::<i64, _>(i) {
+                // Check if this might be a boolean (0 or 1)
+                // We need column type info to determine this properly
+                // For now, we'll treat all integers as integers
+                DbValue::Integer(v)
+            } else if let Ok(v) = row.try_get::<f64, _>(i) {
+                DbValue::Real(v)
+            } else if let Ok(v) = row.try_get::<String, _>(i) {
+                DbValue::Text(v)
+            } else if let Ok(v) = row.try_get::<Vec<u8>, _>(i) {
+                DbValue::Blob(v)
+            } else {
+                DbValue::Null
+            };
+
+            values.push(value);
+        }
+
+        Ok(values)
+    }
+}
+
+#[async_trait]
+impl Database for SqliteDatabase {
+    async fn connect(connection_string: &str) -> Result<Self> {
+        Self::connect_with_options(connection_string, &DatabaseOptions::default()).await
+    }
+
+    async fn create_table(&self, table_name: &str, columns: Vec<ColumnDef>) -> Result<()> {
+        self.create_table_with_unique(table_name, columns, &[]).await
+    }
+
+    async fn create_table_with_unique(
+        &self,
+        table_name: &str,
+        columns: Vec<ColumnDef>,
+        unique: &[Vec<String>],
+    ) -> Result<()> {
+        if unique.iter().any(|set| set.is_empty()) {
+            return Err(DatabaseError::QueryError("A UNIQUE constraint needs at least one column".to_string()));
+        }
+        let sql = Self::build_create_table_sql(table_name, &columns, unique);
+
+        self.prepare(&sql)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(db_err) if db_err.message().contains("already exists") => {
+                    DatabaseError::TableAlreadyExists(table_name.to_string())
+                }
+                _ => DatabaseError::from(e),
+            })?;
+
+        Ok(())
+    }
+
+    async fn drop_table(&self, table_name: &str) -> Result<()> {
+        if let Some((partitioning, _)) = self.partition_spec(table_name).await? {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query(&format!("DROP VIEW IF EXISTS {}", table_name)).execute(&mut *tx).await?;
+            for partition in Self::partition_names(&mut tx, table_name, partitioning.period).await? {
+                sqlx::query(&format!("DROP TABLE {}", partition)).execute(&mut *tx).await?;
+            }
+            let sql = format!("DELETE FROM {} WHERE table_name = ?1", PARTITIONED_TABLES_TABLE);
+            sqlx::query(&sql).bind(table_name).execute(&mut *tx).await?;
             tx.commit().await?;
-            
-            Ok(result.rows_affected())
-        } else {
-            // Regular delete for other tables
-            let sql = format!("DELETE FROM {} WHERE {}", table_name, where_clause);
-            let result = sqlx::query(&sql).execute(&self.pool).await?;
-            Ok(result.rows_affected())
+            return Ok(());
+        }
+
+        let companions = self.companion_tables(table_name).await?;
+        let sql = format!("DROP TABLE IF EXISTS {}", table_name);
+
+        self.prepare(&sql).execute(&self.pool).await?;
+
+        // The sync triggers go with the table; the indexes and history have to be dropped explicitly
+        for companion in companions {
+            let sql = format!("DROP TABLE IF EXISTS {}", companion);
+            self.prepare(&sql).execute(&self.pool).await?;
+        }
+
+        for metadata in [ENCRYPTED_COLUMNS_TABLE, TTL_COLUMNS_TABLE, MATERIALIZED_VIEWS_TABLE] {
+            if self.table_exists(metadata).await? {
+                let sql = format!("DELETE FROM {} WHERE table_name = ?1", metadata);
+                self.prepare(&sql).bind(table_name).execute(&self.pool).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn truncate_table(&self, table_name: &str) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let sql = format!("DELETE FROM {}", table_name);
+        let result = self.prepare(&sql).execute(&mut *tx).await.map_err(|e| match e {
+            sqlx::Error::Database(db_err) if db_err.message().contains("no such table") => {
+                DatabaseError::TableNotFound(table_name.to_string())
+            }
+            _ => DatabaseError::from(e),
+        })?;
+
+        // sqlite_sequence only exists once some table uses AUTOINCREMENT
+        let has_sequence: Option<i64> = sqlx::query_scalar(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'sqlite_sequence'",
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if has_sequence.is_some() {
+            sqlx::query("DELETE FROM sqlite_sequence WHERE name = ?1")
+                .bind(table_name)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert(&self, table_name: &str, values: HashMap<String, DbValue>) -> Result<i64> {
+        let inserted_id = self.insert_with_conflict(table_name, values, OnConflict::Abort).await?;
+        // A conflict under Abort is an error, so a row was always inserted
+        Ok(inserted_id.unwrap_or(-1))
+    }
+
+    async fn insert_with_conflict(
+        &self,
+        table_name: &str,
+        values: HashMap<String, DbValue>,
+        on_conflict: OnConflict,
+    ) -> Result<Option<i64>> {
+        let partitioning = self.partition_spec(table_name).await?;
+        let mut tx = self.pool.begin().await?;
+        let inserted_id = self.insert_on(&mut tx, table_name, values, on_conflict, partitioning).await?;
+        tx.commit().await?;
+        Ok(inserted_id)
+    }
+
+    async fn update(
+        &self,
+        table_name: &str,
+        values: HashMap<String, DbValue>,
+        where_clause: &str,
+    ) -> Result<u64> {
+        let partitioning = self.partition_spec(table_name).await?;
+        let mut tx = self.pool.begin().await?;
+        let affected = self.update_on(&mut tx, table_name, values, where_clause, partitioning).await?;
+        tx.commit().await?;
+        Ok(affected)
+    }
+
+    async fn delete(&self, table_name: &str, where_clause: &str) -> Result<u64> {
+        let partitioning = self.partition_spec(table_name).await?;
+        let mut tx = self.pool.begin().await?;
+        let affected = self.delete_on(&mut tx, table_name, where_clause, partitioning).await?;
+        tx.commit().await?;
+        Ok(affected)
+    }
+
+    async fn execute_writes(&self, writes: Vec<WriteOp>) -> Result<Vec<WriteOutcome>> {
+        // Look up partitioning before the transaction takes the write lock
+        let mut partitionings = HashMap::new();
+        for write in &writes {
+            let table_name = write.table_name();
+            if !partitionings.contains_key(table_name) {
+                partitionings.insert(table_name.to_string(), self.partition_spec(table_name).await?);
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let mut outcomes = Vec::with_capacity(writes.len());
+        for (i, write) in writes.into_iter().enumerate() {
+            let description = format!("{} {}", write.kind(), write.table_name());
+            let partitioning = partitionings.get(write.table_name()).cloned().flatten();
+            let outcome = match write {
+                WriteOp::Insert { table_name, values, on_conflict } => self
+                    .insert_on(&mut tx, &table_name, values, on_conflict, partitioning)
+                    .await
+                    .map(|inserted_id| WriteOutcome {
+                        rows_affected: inserted_id.is_some() as u64,
+                        inserted_id,
+                    }),
+                WriteOp::Update { table_name, values, where_clause } => self
+                    .update_on(&mut tx, &table_name, values, &where_clause, partitioning)
+                    .await
+                    .map(|rows_affected| WriteOutcome { rows_affected, inserted_id: None }),
+                WriteOp::Delete { table_name, where_clause } => self
+                    .delete_on(&mut tx, &table_name, &where_clause, partitioning)
+                    .await
+                    .map(|rows_affected| WriteOutcome { rows_affected, inserted_id: None }),
+            };
+            // Dropping the transaction rolls back the writes before this one
+            let outcome = outcome.map_err(|e| {
+                DatabaseError::TransactionError(format!("Operation {} ({}) failed: {}", i + 1, description, e))
+            })?;
+            outcomes.push(outcome);
         }
+        tx.commit().await?;
+        Ok(outcomes)
     }
 
     async fn query(&self, sql: &str, params: HashMap<String, DbValue>) -> Result<QueryResult> {
-        let mut query = sqlx::query(sql);
+        let sql = self.resolve_as_of(sql).await?;
+        let (sql, values) = params::bind_order(&sql, &params);
+        let mut query = self.prepare(&sql);
 
-        for (_, value) in params.iter() {
+        for value in values {
             query = Self::bind_value(query, value);
         }
 
@@ -354,15 +1253,19 @@ impl Database for SqliteDatabase {
             });
         }
 
-        // Get column information from the first row
+        // A table column has its declared type; an expression the type of
+        // its value in the first row
         let first_row = &rows[0];
         let columns = first_row.columns()
             .iter()
             .map(|col| {
-                let col_name = col.name().to_string();
-                // Try to determine column type - default to Text for simplicity
-                // In a more robust implementation, we'd query sqlite_master or use type info
-                (col_name, ColumnType::Text)
+                let type_name = if col.type_info().is_null() {
+                    use sqlx::ValueRef;
+                    first_row.try_get_raw(col.ordinal()).map(|value| value.type_info().name().to_string()).unwrap_or_default()
+                } else {
+                    col.type_info().name().to_string()
+                };
+                (col.name().to_string(), Self::declared_type_to_column_type(&type_name))
             })
             .collect();
 
@@ -382,20 +1285,39 @@ impl Database for SqliteDatabase {
         sql: &str,
         params: HashMap<String, DbValue>,
     ) -> Result<(Vec<(String, ColumnType)>, StreamedQueryResult)> {
+        // INSERT ... SELECT may read a table AS OF a past time
+        let sql = &*self.resolve_as_of(sql).await?;
+
         // Check if this is a non-SELECT query (INSERT, UPDATE, DELETE)
         let trimmed_sql = sql.trim().to_uppercase();
-        if trimmed_sql.starts_with("INSERT") || trimmed_sql.starts_with("UPDATE") || trimmed_sql.starts_with("DELETE") {
+        let statement = ["INSERT", "UPDATE", "DELETE"].into_iter().find(|kind| trimmed_sql.starts_with(kind));
+        if let Some(statement) = statement {
             // Execute the non-SELECT query
-            let mut query = sqlx::query(sql);
-            for (_, value) in params.iter() {
+            let (sql, values) = params::bind_order(sql, &params);
+            let mut query = self.prepare(&sql);
+            for value in values {
                 query = Self::bind_value(query, value);
             }
             let result = query.execute(&self.pool).await?;
             let affected_rows = result.rows_affected();
-            
-            // Return a synthetic result set with the affected rows count
-            let columns = vec![("affected_rows".to_string(), ColumnType::Integer)];
-            let rows = vec![vec![DbValue::Integer(affected_rows as i64)]];
+            // The connection's last rowid is stale unless this statement inserted a row
+            let last_insert_rowid = match statement {
+                "INSERT" if affected_rows > 0 => DbValue::Integer(result.last_insert_rowid()),
+                _ => DbValue::Null,
+            };
+
+            // Return a synthetic result set with the affected rows count, the
+            // inserted rowid and the kind of statement
+            let columns = vec![
+                ("affected_rows".to_string(), ColumnType::Integer),
+                ("last_insert_rowid".to_string(), ColumnType::Integer),
+                ("statement".to_string(), ColumnType::Text),
+            ];
+            let rows = vec![vec![
+                DbValue::Integer(affected_rows as i64),
+                last_insert_rowid,
+                DbValue::Text(statement.to_string()),
+            ]];
             
             let stream = Box::pin(stream::iter(
                 rows.into_iter()
@@ -422,48 +1344,1263 @@ impl Database for SqliteDatabase {
         Ok((columns, stream))
     }
 
-    async fn batch_insert(
-        &self,
-        table_name: &str,
-        rows: Vec<HashMap<String, DbValue>>,
-    ) -> Result<u64> {
-        if rows.is_empty() {
-            return Ok(0);
+    async fn table_schema(&self, table_name: &str) -> Result<Vec<(String, Option<ColumnType>)>> {
+        Ok(self
+            .table_columns(table_name)
+            .await?
+            .into_iter()
+            .map(|(name, declared_type, _)| {
+                let col_type = (!declared_type.is_empty()).then(|| Self::declared_type_to_column_type(&declared_type));
+                (name, col_type)
+            })
+            .collect())
+    }
+
+    async fn get_row(&self, table_name: &str, key: Vec<DbValue>) -> Result<Option<QueryResult>> {
+        let columns = self.table_columns(table_name).await?;
+
+        let mut key_columns: Vec<(i64, &str)> = columns
+            .iter()
+            .filter(|(_, _, pk)| *pk > 0)
+            .map(|(name, _, pk)| (*pk, name.as_str()))
+            .collect();
+        key_columns.sort();
+        let mut key_columns: Vec<&str> = key_columns.into_iter().map(|(_, name)| name).collect();
+        if key_columns.is_empty() {
+            key_columns.push("rowid");
         }
 
-        let mut tx = self.pool.begin().await?;
-        let mut count = 0;
+        if key.len() != key_columns.len() {
+            return Err(DatabaseError::QueryError(format!(
+                "Table '{}' is keyed by ({}): expected {} key value(s), got {}",
+                table_name,
+                key_columns.join(", "),
+                key_columns.len(),
+                key.len()
+            )));
+        }
 
-        for row in rows {
-            if row.is_empty() {
-                continue;
-            }
+        let conditions: Vec<String> = key_columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| format!("{} = ?{}", column, i + 1))
+            .collect();
+        let sql = format!("SELECT * FROM {} WHERE {} LIMIT 1", table_name, conditions.join(" AND "));
 
-            let columns: Vec<&String> = row.keys().collect();
-            let placeholders: Vec<String> =
-                (0..columns.len()).map(|i| format!("?{}", i + 1)).collect();
+        let mut query = self.prepare(&sql);
+        for value in key.iter() {
+            query = Self::bind_value(query, value);
+        }
 
-            let sql = format!(
-                "INSERT INTO {} ({}) VALUES ({})",
-                table_name,
-                columns
+        match query.fetch_optional(&self.pool).await? {
+            Some(row) => Ok(Some(QueryResult {
+                columns: columns
                     .iter()
-                    .map(|c| c.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", "),
-                placeholders.join(", ")
-            );
+                    .map(|(name, sql_type, _)| (name.clone(), Self::declared_type_to_column_type(sql_type)))
+                    .collect(),
+                rows: vec![Self::row_to_values(&row).await?],
+            })),
+            None => Ok(None),
+        }
+    }
 
-            let mut query = sqlx::query(&sql);
-            for (_, value) in row.iter() {
-                query = Self::bind_value(query, value);
-            }
+    async fn create_search_index(&self, table_name: &str, columns: &[String]) -> Result<u64> {
+        self.check_columns(table_name, columns).await?;
 
-            query.execute(&mut *tx).await?;
-            count += 1;
+        let index = search_index_name(table_name);
+        if self.table_exists(&index).await? {
+            return Err(DatabaseError::TableAlreadyExists(index));
         }
+        let statements = Self::search_index_sql(table_name, columns);
 
+        let mut tx = self.pool.begin().await?;
+        for sql in &statements {
+            sqlx::query(sql).execute(&mut *tx).await?;
+        }
+        let indexed: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table_name))
+            .fetch_one(&mut *tx)
+            .await?;
         tx.commit().await?;
-        Ok(count)
+
+        Ok(indexed as u64)
+    }
+
+    async fn search(&self, table_name: &str, query: &str, limit: u32) -> Result<SearchResult> {
+        let index = search_index_name(table_name);
+        if !self.table_exists(&index).await? {
+            return Err(DatabaseError::QueryError(format!(
+                "Table '{}' has no search index",
+                table_name
+            )));
+        }
+        let columns = self.table_columns(table_name).await?;
+
+        let sql = format!(
+            "SELECT -bm25({index}), snippet({index}, -1, '[', ']', '...', 12), {table_name}.* \
+             FROM {index} JOIN {table_name} ON {table_name}.rowid = {index}.rowid \
+             WHERE {index} MATCH ?1 ORDER BY bm25({index}) LIMIT ?2"
+        );
+        let rows = self.prepare(&sql).bind(query).bind(limit).fetch_all(&self.pool).await.map_err(|e| match e {
+            // The SQL is ours, so a plain SQLITE_ERROR means the MATCH expression is malformed
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some("1") => {
+                DatabaseError::QueryError(format!("Invalid search query: {}", db_err.message()))
+            }
+            _ => DatabaseError::from(e),
+        })?;
+
+        let mut matches = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            let mut values = Self::row_to_values(row).await?;
+            let rest = values.split_off(2);
+            let score = match values[0] {
+                DbValue::Real(score) => score,
+                DbValue::Integer(score) => score as f64,
+                _ => 0.0,
+            };
+            let snippet = match &values[1] {
+                DbValue::Text(snippet) => snippet.clone(),
+                _ => String::new(),
+            };
+            matches.push(SearchMatch { score, snippet, values: rest });
+        }
+
+        Ok(SearchResult {
+            columns: columns
+                .iter()
+                .map(|(name, sql_type, _)| (name.clone(), Self::declared_type_to_column_type(sql_type)))
+                .collect(),
+            matches,
+        })
+    }
+
+    async fn create_spatial_index(&self, table_name: &str, columns: &[String]) -> Result<u64> {
+        let statements = Self::spatial_index_sql(table_name, columns)?;
+        self.check_columns(table_name, columns).await?;
+
+        let index = spatial_index_name(table_name);
+        if self.table_exists(&index).await? {
+            return Err(DatabaseError::TableAlreadyExists(index));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for sql in &statements {
+            sqlx::query(sql).execute(&mut *tx).await?;
+        }
+        let indexed: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", index))
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(indexed as u64)
+    }
+
+    async fn spatial_query(&self, table_name: &str, bbox: BoundingBox, limit: u32) -> Result<QueryResult> {
+        let index = spatial_index_name(table_name);
+        if !self.table_exists(&index).await? {
+            return Err(DatabaseError::QueryError(format!(
+                "Table '{}' has no spatial index",
+                table_name
+            )));
+        }
+        let columns = self.table_columns(table_name).await?;
+
+        // Boxes intersect unless one lies entirely to a side of the other
+        let sql = format!(
+            "SELECT {table_name}.* FROM {index} JOIN {table_name} ON {table_name}.rowid = {index}.id \
+             WHERE {index}.max_x >= ?1 AND {index}.min_x <= ?2 AND {index}.max_y >= ?3 AND {index}.min_y <= ?4 \
+             LIMIT ?5"
+        );
+        let rows = self
+            .prepare(&sql)
+            .bind(bbox.min_x)
+            .bind(bbox.max_x)
+            .bind(bbox.min_y)
+            .bind(bbox.max_y)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut result_rows = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            result_rows.push(Self::row_to_values(row).await?);
+        }
+
+        Ok(QueryResult {
+            columns: columns
+                .iter()
+                .map(|(name, sql_type, _)| (name.clone(), Self::declared_type_to_column_type(sql_type)))
+                .collect(),
+            rows: result_rows,
+        })
+    }
+
+    async fn dedupe(&self, table_name: &str, columns: &[String], keep: &KeepRow, dry_run: bool) -> Result<DedupeOutcome> {
+        if columns.is_empty() {
+            return Err(DatabaseError::QueryError("No columns to find duplicates by".to_string()));
+        }
+        let mut checked = columns.to_vec();
+        if let KeepRow::Newest(column) | KeepRow::Oldest(column) = keep {
+            checked.push(column.clone());
+        }
+        self.check_columns(table_name, &checked).await?;
+        let kind: Option<(String, bool)> = sqlx::query_as("SELECT type, wr FROM pragma_table_list(?1)")
+            .bind(table_name)
+            .fetch_optional(&self.pool)
+            .await?;
+        if !matches!(kind, Some((kind, without_rowid)) if kind == "table" && !without_rowid) {
+            return Err(DatabaseError::QueryError(format!(
+                "Table '{}' has no rowids to tell duplicates apart",
+                table_name
+            )));
+        }
+
+        let group = columns.iter().map(|c| format!("\"{}\"", c.replace('"', "\"\""))).collect::<Vec<_>>().join(", ");
+        // The row kept is numbered 1 in its group
+        let order = match keep {
+            KeepRow::First => "rowid".to_string(),
+            KeepRow::Last => "rowid DESC".to_string(),
+            KeepRow::Newest(column) => format!("\"{}\" DESC, rowid DESC", column.replace('"', "\"\"")),
+            KeepRow::Oldest(column) => format!("\"{}\", rowid", column.replace('"', "\"\"")),
+        };
+        let duplicates = format!(
+            "SELECT rowid FROM (SELECT rowid, row_number() OVER (PARTITION BY {group} ORDER BY {order}) AS n FROM {table_name}) WHERE n > 1"
+        );
+
+        let mut tx = self.pool.begin().await?;
+        let groups: i64 = sqlx::query_scalar(&format!(
+            "SELECT count(*) FROM (SELECT 1 FROM {table_name} GROUP BY {group} HAVING count(*) > 1)"
+        ))
+        .fetch_one(&mut *tx)
+        .await?;
+        let removed = if dry_run {
+            sqlx::query_scalar::<_, i64>(&format!("SELECT count(*) FROM ({duplicates})")).fetch_one(&mut *tx).await? as u64
+        } else {
+            let sql = format!("DELETE FROM {table_name} WHERE rowid IN ({duplicates})");
+            self.prepare(&sql).execute(&mut *tx).await?.rows_affected()
+        };
+        tx.commit().await?;
+        Ok(DedupeOutcome { groups: groups as u64, removed })
+    }
+
+    async fn table_definition(&self, table_name: &str, as_name: &str) -> Result<Vec<String>> {
+        let objects: Vec<(String, String)> = sqlx::query_as(
+            "SELECT type, sql FROM sqlite_master WHERE tbl_name = ?1 AND type IN ('table', 'index') AND sql IS NOT NULL \
+             ORDER BY type = 'index', rowid",
+        )
+        .bind(table_name)
+        .fetch_all(&self.pool)
+        .await?;
+        let create_table = match objects.first() {
+            Some((kind, sql)) if kind == "table" => rename_created_table(sql, as_name).ok_or_else(|| {
+                DatabaseError::QueryError(format!("Table '{}' has a definition that cannot be copied", table_name))
+            })?,
+            _ => return Err(DatabaseError::TableNotFound(table_name.to_string())),
+        };
+
+        let mut statements = vec![create_table];
+        // Index names are unique per database, so a renamed copy gets none
+        if as_name == table_name {
+            statements.extend(objects.into_iter().skip(1).map(|(_, sql)| sql));
+        }
+        Ok(statements)
+    }
+
+    async fn sample(&self, table_name: &str, count: u32) -> Result<QueryResult> {
+        let columns = self.table_columns(table_name).await?;
+        let kind: Option<(String, bool)> = sqlx::query_as("SELECT type, wr FROM pragma_table_list(?1)")
+            .bind(table_name)
+            .fetch_optional(&self.pool)
+            .await?;
+        let has_rowid = matches!(kind, Some((kind, without_rowid)) if kind == "table" && !without_rowid);
+
+        let mut sampled = BTreeMap::new();
+        if has_rowid && count > 0 {
+            let (min, max): (Option<i64>, Option<i64>) =
+                sqlx::query_as(&format!("SELECT min(rowid), max(rowid) FROM {}", table_name)).fetch_one(&self.pool).await?;
+            let span = match (min, max) {
+                (Some(min), Some(max)) => max.saturating_sub(min).saturating_add(1),
+                _ => 0,
+            };
+            // Seek to random rowids, each an index lookup; rows after a gap in
+            // the rowids are a little more likely to be picked
+            if span > SAMPLE_SCAN_FACTOR * count as i64 {
+                let sql = format!(
+                    "SELECT rowid, * FROM {} WHERE rowid >= ?1 + (random() & 9223372036854775807) % ?2 ORDER BY rowid LIMIT 1",
+                    table_name
+                );
+                for _ in 0..SAMPLE_SCAN_FACTOR * count as i64 {
+                    if sampled.len() == count as usize {
+                        break;
+                    }
+                    if let Some(row) = self.prepare(&sql).bind(min).bind(span).fetch_optional(&self.pool).await? {
+                        let mut values = Self::row_to_values(&row).await?;
+                        if let DbValue::Integer(rowid) = values.remove(0) {
+                            sampled.insert(rowid, values);
+                        }
+                    }
+                }
+            }
+        }
+
+        let rows = if sampled.len() == count as usize {
+            sampled.into_values().collect()
+        } else {
+            // Small, sparse or rowid-less tables are shuffled whole
+            let sql = format!(
+                "SELECT * FROM (SELECT rowid AS sample_order, * FROM {} ORDER BY random() LIMIT ?1) ORDER BY sample_order",
+                table_name
+            );
+            let sql = if has_rowid { sql } else { format!("SELECT * FROM {} ORDER BY random() LIMIT ?1", table_name) };
+            let mut rows = Vec::new();
+            for row in self.prepare(&sql).bind(count).fetch_all(&self.pool).await? {
+                let mut values = Self::row_to_values(&row).await?;
+                if has_rowid {
+                    values.remove(0);
+                }
+                rows.push(values);
+            }
+            rows
+        };
+
+        Ok(QueryResult {
+            columns: columns
+                .iter()
+                .map(|(name, sql_type, _)| (name.clone(), Self::declared_type_to_column_type(sql_type)))
+                .collect(),
+            rows,
+        })
+    }
+
+    async fn enable_history(&self, table_name: &str) -> Result<u64> {
+        if self.partition_spec(table_name).await?.is_some() {
+            return Err(DatabaseError::QueryError("History is not supported on partitioned tables".to_string()));
+        }
+        let columns: Vec<(String, String)> = self
+            .table_columns(table_name)
+            .await?
+            .into_iter()
+            .map(|(name, sql_type, _)| (name, sql_type))
+            .collect();
+        let history_table = history::history_table_name(table_name);
+        if self.table_exists(&history_table).await? {
+            return Err(DatabaseError::TableAlreadyExists(history_table));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for sql in history::history_sql(table_name, &columns) {
+            sqlx::query(&sql).execute(&mut *tx).await?;
+        }
+        let rows: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table_name))
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(rows as u64)
+    }
+
+    async fn history_changes(&self, table_name: Option<&str>, limit: u32) -> Result<Vec<Change>> {
+        let tables = match table_name {
+            Some(table_name) => {
+                self.check_history(table_name).await?;
+                vec![table_name.to_string()]
+            }
+            None => {
+                let pattern = format!("%{}", history::HISTORY_SUFFIX.replace('_', "\\_"));
+                let names: Vec<String> =
+                    sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE ?1 ESCAPE '\\'")
+                        .bind(pattern)
+                        .fetch_all(&self.pool)
+                        .await?;
+                names.iter().filter_map(|name| name.strip_suffix(history::HISTORY_SUFFIX)).map(String::from).collect()
+            }
+        };
+
+        let mut changes = Vec::new();
+        for table in tables {
+            let rows: Vec<(f64, i64, i64, i64)> = sqlx::query_as(&history::changes_sql(&table))
+                .bind(limit as i64)
+                .fetch_all(&self.pool)
+                .await?;
+            changes.extend(rows.into_iter().map(|(time, inserted, updated, deleted)| Change {
+                table: table.clone(),
+                time,
+                inserted: inserted as u64,
+                updated: updated as u64,
+                deleted: deleted as u64,
+            }));
+        }
+        changes.sort_by(|a, b| b.time.total_cmp(&a.time));
+        changes.truncate(limit as usize);
+        Ok(changes)
+    }
+
+    async fn undo_changes(&self, changes: &[(String, f64)], dry_run: bool) -> Result<(Vec<Change>, Vec<String>)> {
+        let mut changes = changes.to_vec();
+        changes.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let mut undone = Vec::new();
+
+        // Every statement is worked out before any runs, since running them records new versions
+        let mut statements = Vec::new();
+        for (table, time) in &changes {
+            self.check_history(table).await?;
+            let rows = sqlx::query(&format!("PRAGMA table_xinfo({})", table)).fetch_all(&self.pool).await?;
+            // Generated columns cannot be written
+            let columns: Vec<String> =
+                rows.iter().filter(|row| row.get::<i64, _>("hidden") == 0).map(|row| row.get("name")).collect();
+            let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+
+            let mut versions = [HashMap::new(), HashMap::new()];
+            for (ended, versions) in [true, false].into_iter().zip(versions.iter_mut()) {
+                let sql = history::change_versions_sql(table, &columns, ended);
+                for row in sqlx::query(&sql).bind(time).fetch_all(&self.pool).await? {
+                    let mut values = Self::row_to_values(&row).await?;
+                    let DbValue::Integer(rowid) = values.remove(0) else { continue };
+                    versions.insert(rowid, values);
+                }
+            }
+            let [old, new] = versions;
+            let id = history::change_id(table, *time);
+            if old.is_empty() && new.is_empty() {
+                return Err(DatabaseError::QueryError(format!("No change {} found", id)));
+            }
+            statements.extend(history::undo_sql(table, &columns, &old, &new).into_iter().map(|sql| (id.clone(), sql)));
+            let updated = old.keys().filter(|rowid| new.contains_key(rowid)).count();
+            undone.push(Change {
+                table: table.clone(),
+                time: *time,
+                inserted: (new.len() - updated) as u64,
+                updated: updated as u64,
+                deleted: (old.len() - updated) as u64,
+            });
+        }
+        let statements_only = || -> Vec<String> { statements.iter().map(|(_, sql)| sql.clone()).collect() };
+        if dry_run {
+            return Ok((undone, statements_only()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for (id, sql) in &statements {
+            let changed = match self.prepare(sql).execute(&mut *tx).await.map_err(DatabaseError::from) {
+                Ok(result) => result.rows_affected() == 1,
+                // The rowid of a deleted row has been taken by a new one
+                Err(e) if e.is_constraint_violation() => false,
+                Err(e) => return Err(e),
+            };
+            if !changed {
+                tx.rollback().await?;
+                return Err(DatabaseError::TransactionError(format!(
+                    "Cannot undo {}: a row it wrote has been changed since (undo the later changes first): {}",
+                    id, sql
+                )));
+            }
+        }
+        tx.commit().await?;
+        Ok((undone, statements_only()))
+    }
+
+    async fn encrypted_columns(&self, table_name: &str) -> Result<Vec<String>> {
+        if !self.table_exists(ENCRYPTED_COLUMNS_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT column_name FROM {} WHERE table_name = ?1 COLLATE NOCASE ORDER BY column_name",
+            ENCRYPTED_COLUMNS_TABLE
+        );
+        Ok(sqlx::query_scalar(&sql).bind(table_name).fetch_all(&self.pool).await?)
+    }
+
+    async fn set_encrypted_columns(&self, table_name: &str, columns: &[String]) -> Result<()> {
+        let table_columns = self.table_columns(table_name).await?;
+        for column in columns {
+            match table_columns.iter().find(|(name, _, _)| name == column) {
+                None => {
+                    return Err(DatabaseError::QueryError(format!(
+                        "Column '{}' not found in table '{}'",
+                        column, table_name
+                    )))
+                }
+                // Ciphertext is randomized, so equal values never compare equal
+                Some((_, _, pk)) if *pk > 0 => {
+                    return Err(DatabaseError::QueryError(format!(
+                        "Primary key column '{}' cannot be encrypted",
+                        column
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (table_name TEXT NOT NULL, column_name TEXT NOT NULL, \
+             PRIMARY KEY (table_name, column_name))",
+            ENCRYPTED_COLUMNS_TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!("DELETE FROM {} WHERE table_name = ?1", ENCRYPTED_COLUMNS_TABLE))
+            .bind(table_name)
+            .execute(&mut *tx)
+            .await?;
+        for column in columns {
+            sqlx::query(&format!(
+                "INSERT INTO {} (table_name, column_name) VALUES (?1, ?2)",
+                ENCRYPTED_COLUMNS_TABLE
+            ))
+            .bind(table_name)
+            .bind(column)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn ttl_column(&self, table_name: &str) -> Result<Option<String>> {
+        if !self.table_exists(TTL_COLUMNS_TABLE).await? {
+            return Ok(None);
+        }
+        let sql = format!("SELECT column_name FROM {} WHERE table_name = ?1", TTL_COLUMNS_TABLE);
+        Ok(sqlx::query_scalar(&sql).bind(table_name).fetch_optional(&self.pool).await?)
+    }
+
+    async fn set_ttl_column(&self, table_name: &str, column: Option<&str>) -> Result<()> {
+        let Some(column) = column else {
+            if self.table_exists(TTL_COLUMNS_TABLE).await? {
+                let sql = format!("DELETE FROM {} WHERE table_name = ?1", TTL_COLUMNS_TABLE);
+                sqlx::query(&sql).bind(table_name).execute(&self.pool).await?;
+            }
+            return Ok(());
+        };
+        let table_columns = self.table_columns(table_name).await?;
+        if !table_columns.iter().any(|(name, _, _)| name == column) {
+            return Err(DatabaseError::QueryError(format!(
+                "Column '{}' not found in table '{}'",
+                column, table_name
+            )));
+        }
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (table_name TEXT PRIMARY KEY, column_name TEXT NOT NULL)",
+            TTL_COLUMNS_TABLE
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {} (table_name, column_name) VALUES (?1, ?2)",
+            TTL_COLUMNS_TABLE
+        ))
+        .bind(table_name)
+        .bind(column)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn purge_expired(&self) -> Result<Vec<(String, u64)>> {
+        if !self.table_exists(TTL_COLUMNS_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        let sql = format!("SELECT table_name, column_name FROM {} ORDER BY table_name", TTL_COLUMNS_TABLE);
+        let ttls: Vec<(String, String)> = sqlx::query_as(&sql).fetch_all(&self.pool).await?;
+
+        let mut purged = Vec::new();
+        for (table, column) in ttls {
+            // Text values are parsed as timestamps; NULL never expires
+            let sql = format!(
+                "DELETE FROM {0} WHERE (CASE typeof({1}) WHEN 'text' THEN unixepoch({1}) ELSE {1} END) <= unixepoch()",
+                table, column
+            );
+            let deleted = self.prepare(&sql).execute(&self.pool).await?.rows_affected();
+            if deleted > 0 {
+                purged.push((table, deleted));
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn execute(&self, sql: &str) -> Result<u64> {
+        Ok(self.prepare(sql).execute(&self.pool).await?.rows_affected())
+    }
+
+    async fn jobs(&self) -> Result<Vec<Job>> {
+        if !self.table_exists(JOBS_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        let sql = format!("SELECT name, schedule, sql FROM {} ORDER BY name", JOBS_TABLE);
+        let rows: Vec<(String, String, String)> = sqlx::query_as(&sql).fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, schedule, sql)| Job {
+                name,
+                schedule,
+                sql,
+                // The caller knows which database this is
+                database: String::new(),
+                from_config: false,
+            })
+            .collect())
+    }
+
+    async fn save_job(&self, job: &Job) -> Result<()> {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (name TEXT PRIMARY KEY, schedule TEXT NOT NULL, sql TEXT NOT NULL, \
+             created_at INTEGER NOT NULL DEFAULT (unixepoch()))",
+            JOBS_TABLE
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {} (name, schedule, sql) VALUES (?1, ?2, ?3)",
+            JOBS_TABLE
+        ))
+        .bind(&job.name)
+        .bind(&job.schedule)
+        .bind(&job.sql)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_job(&self, name: &str) -> Result<bool> {
+        if !self.table_exists(JOBS_TABLE).await? {
+            return Ok(false);
+        }
+        let sql = format!("DELETE FROM {} WHERE name = ?1", JOBS_TABLE);
+        Ok(sqlx::query(&sql).bind(name).execute(&self.pool).await?.rows_affected() > 0)
+    }
+
+    async fn record_job_run(&self, run: &JobRun) -> Result<()> {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, job_name TEXT NOT NULL, \
+             started_at INTEGER NOT NULL, duration_ms INTEGER NOT NULL, success INTEGER NOT NULL, \
+             rows_affected INTEGER NOT NULL, message TEXT NOT NULL)",
+            JOB_RUNS_TABLE
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT INTO {} (job_name, started_at, duration_ms, success, rows_affected, message) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            JOB_RUNS_TABLE
+        ))
+        .bind(&run.job_name)
+        .bind(run.started_at)
+        .bind(run.duration_ms)
+        .bind(run.success)
+        .bind(run.rows_affected as i64)
+        .bind(&run.message)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn job_runs(&self, job_name: Option<&str>, limit: u32) -> Result<Vec<JobRun>> {
+        if !self.table_exists(JOB_RUNS_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT job_name, started_at, duration_ms, success, rows_affected, message FROM {} \
+             WHERE ?1 IS NULL OR job_name = ?1 ORDER BY id DESC LIMIT ?2",
+            JOB_RUNS_TABLE
+        );
+        let rows: Vec<(String, i64, i64, bool, i64, String)> = sqlx::query_as(&sql)
+            .bind(job_name)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(job_name, started_at, duration_ms, success, rows_affected, message)| JobRun {
+                job_name,
+                started_at,
+                duration_ms,
+                success,
+                rows_affected: rows_affected as u64,
+                message,
+            })
+            .collect())
+    }
+
+    async fn saved_queries(&self) -> Result<Vec<SavedQuery>> {
+        if !self.table_exists(SAVED_QUERIES_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        let sql = format!("SELECT name, sql, description FROM {} ORDER BY name", SAVED_QUERIES_TABLE);
+        let rows: Vec<(String, String, String)> = sqlx::query_as(&sql).fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, sql, description)| SavedQuery { name, sql, description })
+            .collect())
+    }
+
+    async fn saved_query(&self, name: &str) -> Result<Option<SavedQuery>> {
+        if !self.table_exists(SAVED_QUERIES_TABLE).await? {
+            return Ok(None);
+        }
+        let sql = format!("SELECT name, sql, description FROM {} WHERE name = ?1", SAVED_QUERIES_TABLE);
+        let row: Option<(String, String, String)> = sqlx::query_as(&sql).bind(name).fetch_optional(&self.pool).await?;
+        Ok(row.map(|(name, sql, description)| SavedQuery { name, sql, description }))
+    }
+
+    async fn save_query(&self, query: &SavedQuery) -> Result<()> {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (name TEXT PRIMARY KEY, sql TEXT NOT NULL, \
+             description TEXT NOT NULL DEFAULT '', created_at INTEGER NOT NULL DEFAULT (unixepoch()))",
+            SAVED_QUERIES_TABLE
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {} (name, sql, description) VALUES (?1, ?2, ?3)",
+            SAVED_QUERIES_TABLE
+        ))
+        .bind(&query.name)
+        .bind(&query.sql)
+        .bind(&query.description)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_saved_query(&self, name: &str) -> Result<bool> {
+        if !self.table_exists(SAVED_QUERIES_TABLE).await? {
+            return Ok(false);
+        }
+        let sql = format!("DELETE FROM {} WHERE name = ?1", SAVED_QUERIES_TABLE);
+        Ok(sqlx::query(&sql).bind(name).execute(&self.pool).await?.rows_affected() > 0)
+    }
+
+    async fn applied_schema(&self) -> Result<Option<AppliedSchema>> {
+        if !self.table_exists(APPLIED_SCHEMA_TABLE).await? {
+            return Ok(None);
+        }
+        let sql = format!(
+            "SELECT name, version, checksum, applied_at FROM {} ORDER BY id DESC LIMIT 1",
+            APPLIED_SCHEMA_TABLE
+        );
+        let row: Option<(String, String, String, i64)> = sqlx::query_as(&sql).fetch_optional(&self.pool).await?;
+        Ok(row.map(|(name, version, checksum, applied_at)| AppliedSchema { name, version, checksum, applied_at }))
+    }
+
+    async fn record_applied_schema(&self, name: &str, version: &str, checksum: &str) -> Result<()> {
+        // Every application is kept, so the table doubles as a history
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, name TEXT NOT NULL, version TEXT NOT NULL, \
+             checksum TEXT NOT NULL, applied_at INTEGER NOT NULL DEFAULT (unixepoch()))",
+            APPLIED_SCHEMA_TABLE
+        ))
+        .execute(&self.pool)
+        .await?;
+        sqlx::query(&format!("INSERT INTO {} (name, version, checksum) VALUES (?1, ?2, ?3)", APPLIED_SCHEMA_TABLE))
+            .bind(name)
+            .bind(version)
+            .bind(checksum)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn descriptions(&self, table_name: &str) -> Result<Descriptions> {
+        let mut descriptions = Descriptions::default();
+        if !self.table_exists(DESCRIPTIONS_TABLE).await? {
+            return Ok(descriptions);
+        }
+        let sql = format!("SELECT column_name, description FROM {} WHERE table_name = ?1", DESCRIPTIONS_TABLE);
+        let rows: Vec<(String, String)> = sqlx::query_as(&sql).bind(table_name).fetch_all(&self.pool).await?;
+        for (column, description) in rows {
+            if column.is_empty() {
+                descriptions.table = Some(description);
+            } else {
+                descriptions.columns.insert(column, description);
+            }
+        }
+        Ok(descriptions)
+    }
+
+    async fn set_descriptions(&self, table_name: &str, descriptions: &Descriptions) -> Result<()> {
+        let table_columns = self.table_columns(table_name).await?;
+        if let Some(column) = descriptions.columns.keys().find(|c| !table_columns.iter().any(|(name, _, _)| name == *c)) {
+            return Err(DatabaseError::QueryError(format!(
+                "Column '{}' not found in table '{}'",
+                column, table_name
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (table_name TEXT NOT NULL, column_name TEXT NOT NULL, \
+             description TEXT NOT NULL, PRIMARY KEY (table_name, column_name))",
+            DESCRIPTIONS_TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!("DELETE FROM {} WHERE table_name = ?1", DESCRIPTIONS_TABLE))
+            .bind(table_name)
+            .execute(&mut *tx)
+            .await?;
+        let entries = descriptions.table.iter().map(|d| ("", d)).chain(descriptions.columns.iter().map(|(c, d)| (c.as_str(), d)));
+        for (column, description) in entries {
+            sqlx::query(&format!(
+                "INSERT INTO {} (table_name, column_name, description) VALUES (?1, ?2, ?3)",
+                DESCRIPTIONS_TABLE
+            ))
+            .bind(table_name)
+            .bind(column)
+            .bind(description)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn create_partitioned_table(
+        &self,
+        table_name: &str,
+        columns: Vec<ColumnDef>,
+        partitioning: &Partitioning,
+    ) -> Result<()> {
+        if self.table_exists(table_name).await? {
+            return Err(DatabaseError::TableAlreadyExists(table_name.to_string()));
+        }
+        if !columns.iter().any(|c| c.name == partitioning.column) {
+            return Err(DatabaseError::QueryError(format!(
+                "Partition column '{}' not found in table '{}'",
+                partitioning.column, table_name
+            )));
+        }
+        if partitioning.retention == Some(0) {
+            return Err(DatabaseError::QueryError("Partition retention must be at least 1".to_string()));
+        }
+
+        let column_defs = Self::column_defs_sql(&columns);
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (table_name TEXT PRIMARY KEY, column_name TEXT NOT NULL, \
+             period TEXT NOT NULL, retention INTEGER, column_defs TEXT NOT NULL)",
+            PARTITIONED_TABLES_TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT INTO {} (table_name, column_name, period, retention, column_defs) VALUES (?1, ?2, ?3, ?4, ?5)",
+            PARTITIONED_TABLES_TABLE
+        ))
+        .bind(table_name)
+        .bind(&partitioning.column)
+        .bind(partitioning.period.as_str())
+        .bind(partitioning.retention.map(i64::from))
+        .bind(&column_defs)
+        .execute(&mut *tx)
+        .await?;
+        // The view needs at least one partition to select from
+        self.ensure_partition(&mut tx, table_name, partitioning, &column_defs, Utc::now()).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn partitioning(&self, table_name: &str) -> Result<Option<Partitioning>> {
+        Ok(self.partition_spec(table_name).await?.map(|(partitioning, _)| partitioning))
+    }
+
+    async fn partitions(&self, table_name: &str) -> Result<Vec<String>> {
+        let Some((partitioning, _)) = self.partition_spec(table_name).await? else {
+            return Ok(Vec::new());
+        };
+        let mut conn = self.pool.acquire().await?;
+        Self::partition_names(&mut conn, table_name, partitioning.period).await
+    }
+
+    async fn drop_expired_partitions(&self) -> Result<Vec<(String, String)>> {
+        if !self.table_exists(PARTITIONED_TABLES_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT table_name FROM {} WHERE retention IS NOT NULL ORDER BY table_name",
+            PARTITIONED_TABLES_TABLE
+        );
+        let tables: Vec<String> = sqlx::query_scalar(&sql).fetch_all(&self.pool).await?;
+
+        let now = Utc::now();
+        let mut dropped = Vec::new();
+        for table in tables {
+            let Some((partitioning, column_defs)) = self.partition_spec(&table).await? else {
+                continue;
+            };
+            let Some(retention) = partitioning.retention else {
+                continue;
+            };
+            let period = partitioning.period;
+            let cutoff = period.start_before(now, retention.saturating_sub(1));
+            let prefix = format!("{}_", table);
+
+            let mut tx = self.pool.begin().await?;
+            let expired: Vec<String> = Self::partition_names(&mut tx, &table, period)
+                .await?
+                .into_iter()
+                .filter(|name| {
+                    name.strip_prefix(&prefix)
+                        .and_then(|suffix| period.parse_suffix(suffix))
+                        .is_some_and(|start| start < cutoff)
+                })
+                .collect();
+            if expired.is_empty() {
+                continue;
+            }
+
+            for partition in &expired {
+                sqlx::query(&format!("DROP TABLE {}", partition)).execute(&mut *tx).await?;
+            }
+            // Keep the current partition so the view always has one to select from
+            self.ensure_partition(&mut tx, &table, &partitioning, &column_defs, now).await?;
+            Self::rebuild_partition_view(&mut tx, &table, period).await?;
+            tx.commit().await?;
+
+            dropped.extend(expired.into_iter().map(|partition| (table.clone(), partition)));
+        }
+        Ok(dropped)
+    }
+
+    async fn materialized_views(&self) -> Result<Vec<MaterializedView>> {
+        if !self.table_exists(MATERIALIZED_VIEWS_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT table_name, query, refresh_interval_seconds, last_refreshed FROM {} ORDER BY table_name",
+            MATERIALIZED_VIEWS_TABLE
+        );
+        let rows: Vec<(String, String, i64, Option<i64>)> = sqlx::query_as(&sql).fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, query, refresh_interval_seconds, last_refreshed)| MaterializedView {
+                name,
+                query,
+                refresh_interval_seconds: refresh_interval_seconds as u64,
+                last_refreshed,
+            })
+            .collect())
+    }
+
+    async fn create_materialized_view(&self, view: &MaterializedView) -> Result<u64> {
+        let defined = self
+            .materialized_views()
+            .await?
+            .iter()
+            .any(|existing| existing.name == view.name);
+        if !defined && self.table_exists(&view.name).await? {
+            return Err(DatabaseError::TableAlreadyExists(view.name.clone()));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (table_name TEXT PRIMARY KEY, query TEXT NOT NULL, \
+             refresh_interval_seconds INTEGER NOT NULL, last_refreshed INTEGER, last_attempted INTEGER)",
+            MATERIALIZED_VIEWS_TABLE
+        ))
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(&format!(
+            "INSERT OR REPLACE INTO {} (table_name, query, refresh_interval_seconds) VALUES (?1, ?2, ?3)",
+            MATERIALIZED_VIEWS_TABLE
+        ))
+        .bind(&view.name)
+        .bind(&view.query)
+        .bind(view.refresh_interval_seconds as i64)
+        .execute(&mut *tx)
+        .await?;
+        let rows = self.fill_materialized_view(&mut tx, &view.name, &view.query).await?;
+        tx.commit().await?;
+        Ok(rows)
+    }
+
+    async fn refresh_materialized_view(&self, name: &str) -> Result<u64> {
+        let view = self
+            .materialized_views()
+            .await?
+            .into_iter()
+            .find(|view| view.name == name)
+            .ok_or_else(|| DatabaseError::TableNotFound(name.to_string()))?;
+
+        // Readers keep seeing the previous contents until the rebuild commits
+        let mut tx = self.pool.begin().await?;
+        let rows = self.fill_materialized_view(&mut tx, &view.name, &view.query).await?;
+        tx.commit().await?;
+        Ok(rows)
+    }
+
+    async fn refresh_due_materialized_views(&self) -> Result<Vec<(String, Result<u64>)>> {
+        if !self.table_exists(MATERIALIZED_VIEWS_TABLE).await? {
+            return Ok(Vec::new());
+        }
+        // A failed refresh is retried after another interval rather than on every check
+        let sql = format!(
+            "UPDATE {} SET last_attempted = unixepoch() WHERE refresh_interval_seconds > 0 \
+             AND COALESCE(last_attempted, 0) + refresh_interval_seconds <= unixepoch() \
+             RETURNING table_name",
+            MATERIALIZED_VIEWS_TABLE
+        );
+        let mut due: Vec<String> = sqlx::query_scalar(&sql).fetch_all(&self.pool).await?;
+        due.sort();
+
+        let mut refreshed = Vec::new();
+        for name in due {
+            let result = self.refresh_materialized_view(&name).await;
+            refreshed.push((name, result));
+        }
+        Ok(refreshed)
+    }
+
+    async fn batch_insert(
+        &self,
+        table_name: &str,
+        rows: Vec<HashMap<String, DbValue>>,
+    ) -> Result<u64> {
+        Ok(self.batch_insert_with_conflict(table_name, rows, OnConflict::Abort).await?.inserted)
+    }
+
+    async fn batch_insert_with_conflict(
+        &self,
+        table_name: &str,
+        rows: Vec<HashMap<String, DbValue>>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertCounts> {
+        if rows.is_empty() {
+            return Ok(InsertCounts::default());
+        }
+
+        if let Some(spec) = self.partition_spec(table_name).await? {
+            let mut tx = self.pool.begin().await?;
+            let (counts, _) = self.insert_partitioned(&mut tx, table_name, spec, rows, on_conflict).await?;
+            tx.commit().await?;
+            return Ok(counts);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let mut counts = InsertCounts::default();
+        Self::check_write_columns(&mut tx, table_name, rows.iter().flat_map(HashMap::keys)).await?;
+
+        for row in rows {
+            if row.is_empty() {
+                continue;
+            }
+            self.write_row(&mut tx, table_name, &row, on_conflict).await?.count(&mut counts);
+        }
+
+        tx.commit().await?;
+        Ok(counts)
+    }
+
+    async fn disk_usage(&self) -> Result<DiskUsage> {
+        let page_count: i64 = sqlx::query_scalar("PRAGMA page_count").fetch_one(&self.pool).await?;
+        let page_size: i64 = sqlx::query_scalar("PRAGMA page_size").fetch_one(&self.pool).await?;
+        let free_pages: i64 = sqlx::query_scalar("PRAGMA freelist_count").fetch_one(&self.pool).await?;
+        let file: Option<String> = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+            .fetch_optional(&self.pool)
+            .await?;
+
+        // In-memory and temporary databases have no file name
+        let file_size = |path: &str| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        let (file_bytes, wal_bytes) = match file.filter(|f| !f.is_empty()) {
+            Some(path) => (file_size(&path), file_size(&format!("{}-wal", path))),
+            None => (0, 0),
+        };
+
+        Ok(DiskUsage {
+            file_bytes,
+            page_count: page_count as u64,
+            page_size: page_size as u64,
+            free_pages: free_pages as u64,
+            wal_bytes,
+        })
+    }
+
+    async fn open_session(&self) -> Result<Box<dyn Database>> {
+        // A pool of one connection that is never recycled, so its TEMP schema
+        // lasts as long as the session. In-memory databases use a shared
+        // cache, so the new connection sees the same data.
+        let pool = Self::pool_options()
+            .max_connections(1)
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with((*self.pool.connect_options()).clone())
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+        Ok(Box::new(SqliteDatabase { pool }))
+    }
+
+    async fn storage_info(&self) -> Result<StorageInfo> {
+        let file: Option<String> = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+            .fetch_optional(&self.pool)
+            .await?;
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&self.pool).await?;
+        let table_count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name NOT GLOB 'sqlite_*' AND name NOT GLOB '__datasink_*'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(StorageInfo {
+            backend: "sqlite".to_string(),
+            file_path: file.filter(|f| !f.is_empty()),
+            journal_mode,
+            table_count: table_count as u64,
+        })
+    }
+
+    async fn table_stats(&self) -> Result<Vec<TableStats>> {
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT GLOB 'sqlite_*' AND name NOT GLOB '__datasink_*' ORDER BY name",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        if tables.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // One statement for every count: a multi-row VALUES clause is not
+        // subject to SQLite's limit on compound SELECTs
+        let counts = tables
+            .iter()
+            .map(|table| format!("((SELECT COUNT(*) FROM \"{}\"))", table.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let counts: Vec<i64> = sqlx::query_scalar(&format!("VALUES {}", counts)).fetch_all(&self.pool).await?;
+
+        // dbstat is missing from SQLite builds without SQLITE_ENABLE_DBSTAT_VTAB
+        let sizes: HashMap<String, i64> = sqlx::query_as(
+            "SELECT COALESCE(m.tbl_name, s.name), SUM(s.pgsize) FROM dbstat s \
+             LEFT JOIN sqlite_master m ON m.name = s.name GROUP BY 1",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().collect())
+        .unwrap_or_default();
+
+        Ok(tables
+            .into_iter()
+            .zip(counts)
+            .map(|(name, rows)| {
+                let size_bytes = sizes.get(&name).copied().unwrap_or(0) as u64;
+                TableStats { name, rows: rows as u64, size_bytes }
+            })
+            .collect())
+    }
+
+    async fn copy_to(&self, path: &std::path::Path, schema_only: bool) -> Result<()> {
+        if !schema_only {
+            // A consistent snapshot, even while other connections write
+            sqlx::query("VACUUM INTO ?1").bind(path.display().to_string()).execute(&self.pool).await?;
+            return Ok(());
+        }
+
+        let source: Option<String> = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+            .fetch_optional(&self.pool)
+            .await?;
+        let source = source
+            .filter(|f| !f.is_empty())
+            .ok_or_else(|| DatabaseError::Other("A schema-only copy needs a database file".to_string()))?;
+        // Virtual tables create their own shadow tables
+        let shadow_tables: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM pragma_table_list WHERE schema = 'main' AND type = 'shadow'")
+                .fetch_all(&self.pool)
+                .await?;
+        let objects: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT type, name, sql FROM sqlite_master WHERE sql IS NOT NULL AND name NOT GLOB 'sqlite_*' ORDER BY rowid",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut conn = SqliteConnectOptions::new().filename(path).create_if_missing(true).connect().await?;
+        let mut tx = conn.begin().await?;
+        for (_, name, sql) in &objects {
+            if !shadow_tables.contains(name) {
+                sqlx::query(sql).execute(&mut *tx).await?;
+            }
+        }
+        tx.commit().await?;
+
+        sqlx::query("ATTACH DATABASE ?1 AS source").bind(source).execute(&mut conn).await?;
+        let metadata_tables = objects
+            .iter()
+            .filter(|(kind, name, _)| kind == "table" && name.starts_with("__datasink_") && name != JOB_RUNS_TABLE);
+        for (_, name, _) in metadata_tables {
+            sqlx::query(&format!("INSERT INTO main.\"{0}\" SELECT * FROM source.\"{0}\"", name)).execute(&mut conn).await?;
+        }
+        sqlx::query("DETACH DATABASE source").execute(&mut conn).await?;
+        conn.close().await?;
+        Ok(())
+    }
+
+    async fn pragma(&self, name: &str) -> Result<String> {
+        let row = sqlx::query(&format!("PRAGMA {}", name)).fetch_one(&self.pool).await?;
+        Ok(match row.try_get::<i64, _>(0) {
+            Ok(value) => value.to_string(),
+            Err(_) => row.try_get::<String, _>(0)?,
+        })
+    }
+
+    async fn foreign_keys_enabled(&self) -> Result<bool> {
+        let enabled: i64 = sqlx::query_scalar("PRAGMA foreign_keys").fetch_one(&self.pool).await?;
+        Ok(enabled != 0)
+    }
+
+    async fn query_plan(&self, sql: &str) -> Result<Vec<String>> {
+        let sql = self.resolve_as_of(sql).await?;
+        let rows = sqlx::query(&format!("EXPLAIN QUERY PLAN {}", sql)).fetch_all(&self.pool).await?;
+        rows.iter().map(|row| Ok(row.try_get::<String, _>("detail")?)).collect()
+    }
+
+    async fn query_plan_with_index(&self, sql: &str, create_index_sql: &str) -> Result<Vec<String>> {
+        let sql = self.resolve_as_of(sql).await?;
+        // The index only exists inside the transaction, which is rolled back
+        // whether or not the plan could be read
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(create_index_sql).execute(&mut *tx).await?;
+        let rows = sqlx::query(&format!("EXPLAIN QUERY PLAN {}", sql)).fetch_all(&mut *tx).await;
+        tx.rollback().await?;
+        rows?.iter().map(|row| Ok(row.try_get::<String, _>("detail")?)).collect()
+    }
+
+    async fn quick_check(&self) -> Result<Vec<String>> {
+        // A single "ok" row means no problems; otherwise one row per problem
+        let rows: Vec<String> = sqlx::query_scalar("PRAGMA quick_check(20)").fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().filter(|row| row != "ok").collect())
+    }
+
+    async fn foreign_key_violations(&self, table_name: Option<&str>, limit: u32) -> Result<Vec<ForeignKeyViolation>> {
+        if let Some(table_name) = table_name {
+            if !self.table_exists(table_name).await? {
+                return Err(DatabaseError::TableNotFound(table_name.to_string()));
+            }
+        }
+
+        // pragma_foreign_key_check checks every table when its argument is NULL
+        let rows = sqlx::query(
+            "SELECT \"table\", rowid, parent, fkid FROM pragma_foreign_key_check(?1) LIMIT ?2",
+        )
+        .bind(table_name)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ForeignKeyViolation {
+                table: row.get(0),
+                rowid: row.get(1),
+                parent: row.get(2),
+                constraint_index: row.get(3),
+            })
+            .collect())
+    }
+
+    fn connection_stats(&self) -> Option<ConnectionStats> {
+        Some(ConnectionStats {
+            open: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.pool.options().get_max_connections(),
+        })
     }
 }
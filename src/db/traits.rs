@@ -1,9 +1,12 @@
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::pin::Pin;
+use std::str::FromStr;
 use tokio_stream::Stream;
 
-use crate::db::error::Result;
+use crate::db::error::{DatabaseError, Result};
+use crate::db::history::Change;
+use crate::db::partition::Partitioning;
 
 #[derive(Debug, Clone)]
 pub enum ColumnType {
@@ -23,6 +26,60 @@ pub struct ColumnDef {
     pub primary_key: bool,
     pub unique: bool,
     pub default_value: Option<String>,
+    /// Compute the column from other columns of the row instead of storing
+    /// values written to it
+    pub generated: Option<GeneratedColumn>,
+    /// How text values compare, sort and match UNIQUE constraints
+    /// (SQLite's default is BINARY)
+    pub collation: Option<Collation>,
+    /// Limit the column to these text values (a CHECK constraint)
+    pub allowed_values: Option<Vec<String>>,
+}
+
+/// SQLite's built-in collating sequences
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collation {
+    /// Byte by byte
+    Binary,
+    /// Ignoring the case of ASCII letters
+    NoCase,
+    /// Ignoring trailing spaces
+    RTrim,
+}
+
+impl Collation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Collation::Binary => "BINARY",
+            Collation::NoCase => "NOCASE",
+            Collation::RTrim => "RTRIM",
+        }
+    }
+}
+
+impl FromStr for Collation {
+    type Err = DatabaseError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_uppercase().as_str() {
+            "BINARY" => Ok(Collation::Binary),
+            "NOCASE" => Ok(Collation::NoCase),
+            "RTRIM" => Ok(Collation::RTrim),
+            _ => Err(DatabaseError::QueryError(format!(
+                "Unknown collation '{}' (expected BINARY, NOCASE or RTRIM)",
+                s
+            ))),
+        }
+    }
+}
+
+/// `GENERATED ALWAYS AS (expression)`: a column computed from the row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedColumn {
+    pub expression: String,
+    /// Store the value when the row is written (STORED) rather than computing
+    /// it on every read (VIRTUAL)
+    pub stored: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -42,6 +99,279 @@ pub struct QueryResult {
     pub rows: Vec<Vec<DbValue>>,
 }
 
+/// One full-text search hit
+#[derive(Debug)]
+pub struct SearchMatch {
+    /// Relevance (negated bm25), higher is a better match
+    pub score: f64,
+    /// Matching text with the query terms wrapped in [brackets]
+    pub snippet: String,
+    /// The matching row, in table column order
+    pub values: Vec<DbValue>,
+}
+
+#[derive(Debug)]
+pub struct SearchResult {
+    pub columns: Vec<(String, ColumnType)>,
+    pub matches: Vec<SearchMatch>,
+}
+
+/// An axis-aligned rectangle, e.g. longitude/latitude bounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+/// What an insert does when the row violates a UNIQUE or PRIMARY KEY constraint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Fail the statement (and the surrounding batch)
+    #[default]
+    Abort,
+    /// Skip the conflicting row and keep going
+    Ignore,
+    /// Delete the conflicting rows and insert the new one (INSERT OR REPLACE)
+    Replace,
+    /// Update the conflicting row with the new row's values (upsert)
+    Update,
+}
+
+/// Rows written by `Database::batch_insert_with_conflict`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InsertCounts {
+    /// Rows inserted without a conflict
+    pub inserted: u64,
+    /// Conflicting rows replaced under `OnConflict::Replace` or updated
+    /// under `OnConflict::Update`
+    pub replaced: u64,
+    /// Rowid of the first row inserted without a conflict
+    pub first_id: Option<i64>,
+    /// Rowid of the last row inserted without a conflict
+    pub last_id: Option<i64>,
+}
+
+/// Which row of a group of duplicates `Database::dedupe` keeps
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KeepRow {
+    /// The row inserted first (lowest rowid)
+    #[default]
+    First,
+    /// The row inserted last (highest rowid)
+    Last,
+    /// The row with the greatest value in a column, e.g. `updated_at`
+    Newest(String),
+    /// The row with the smallest value in a column
+    Oldest(String),
+}
+
+impl FromStr for KeepRow {
+    type Err = DatabaseError;
+
+    /// `first`, `last`, `newest:COLUMN` or `oldest:COLUMN`
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s.eq_ignore_ascii_case("first") => Ok(KeepRow::First),
+            None if s.eq_ignore_ascii_case("last") => Ok(KeepRow::Last),
+            Some((which, column)) if !column.is_empty() && which.eq_ignore_ascii_case("newest") => {
+                Ok(KeepRow::Newest(column.to_string()))
+            }
+            Some((which, column)) if !column.is_empty() && which.eq_ignore_ascii_case("oldest") => {
+                Ok(KeepRow::Oldest(column.to_string()))
+            }
+            _ => Err(DatabaseError::QueryError(format!(
+                "Unknown row to keep '{}' (expected first, last, newest:COLUMN or oldest:COLUMN)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Duplicates found, and removed unless it was a dry run, by `Database::dedupe`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupeOutcome {
+    /// Groups of rows with equal values
+    pub groups: u64,
+    /// Rows beyond the one kept per group
+    pub removed: u64,
+}
+
+/// One write in a transaction run by `Database::execute_writes`
+#[derive(Debug, Clone)]
+pub enum WriteOp {
+    Insert {
+        table_name: String,
+        values: HashMap<String, DbValue>,
+        on_conflict: OnConflict,
+    },
+    Update {
+        table_name: String,
+        values: HashMap<String, DbValue>,
+        where_clause: String,
+    },
+    Delete {
+        table_name: String,
+        where_clause: String,
+    },
+}
+
+impl WriteOp {
+    pub fn table_name(&self) -> &str {
+        match self {
+            WriteOp::Insert { table_name, .. } | WriteOp::Update { table_name, .. } | WriteOp::Delete { table_name, .. } => {
+                table_name
+            }
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WriteOp::Insert { .. } => "insert into",
+            WriteOp::Update { .. } => "update",
+            WriteOp::Delete { .. } => "delete from",
+        }
+    }
+}
+
+/// What one write in a transaction did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOutcome {
+    pub rows_affected: u64,
+    /// Rowid of an inserted row; None for updates, deletes and skipped inserts
+    pub inserted_id: Option<i64>,
+}
+
+/// Connections held by a database's pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Open connections, busy or idle
+    pub open: u32,
+    /// Open connections not currently running a statement
+    pub idle: u32,
+    /// Most connections the pool will open
+    pub max: u32,
+}
+
+/// On-disk footprint of a database
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Size of the database file (0 for in-memory databases)
+    pub file_bytes: u64,
+    pub page_count: u64,
+    pub page_size: u64,
+    /// Pages on the freelist, reclaimable with VACUUM
+    pub free_pages: u64,
+    /// Size of the write-ahead log, if one exists
+    pub wal_bytes: u64,
+}
+
+/// How and where a database is stored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageInfo {
+    /// Storage engine, e.g. "sqlite"
+    pub backend: String,
+    /// Database file; `None` for in-memory databases
+    pub file_path: Option<String>,
+    /// Journal mode: delete, wal, memory, ...
+    pub journal_mode: String,
+    /// User tables, not counting SQLite's and DataSink's own
+    pub table_count: u64,
+}
+
+/// Row count and estimated size of a table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableStats {
+    pub name: String,
+    pub rows: u64,
+    /// Bytes of the pages holding the table and its indexes; 0 if unknown
+    pub size_bytes: u64,
+}
+
+/// A row whose REFERENCES constraint names a parent row that does not exist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    /// Rowid of the offending row (None for WITHOUT ROWID tables)
+    pub rowid: Option<i64>,
+    /// Table the constraint references
+    pub parent: String,
+    /// Position of the constraint in the table's foreign key list
+    pub constraint_index: i64,
+}
+
+/// A SQL statement run on a cron schedule (see `db::scheduler`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub name: String,
+    /// Cron expression, evaluated in UTC
+    pub schedule: String,
+    pub sql: String,
+    /// Database the job runs against
+    pub database: String,
+    /// Defined in the config file rather than added at runtime
+    pub from_config: bool,
+}
+
+/// The outcome of one execution of a job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobRun {
+    pub job_name: String,
+    /// Unix time the run started
+    pub started_at: i64,
+    pub duration_ms: i64,
+    pub success: bool,
+    pub rows_affected: u64,
+    /// Error message of a failed run
+    pub message: String,
+}
+
+/// A named query stored in a database so it can be shared and rerun
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedQuery {
+    pub name: String,
+    /// SQL with `:name` placeholders for its parameters
+    pub sql: String,
+    pub description: String,
+}
+
+/// A table holding the result of a SELECT, rebuilt on refresh
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaterializedView {
+    pub name: String,
+    pub query: String,
+    /// Seconds between automatic refreshes; 0 refreshes only on demand
+    pub refresh_interval_seconds: u64,
+    /// Unix time of the last refresh
+    pub last_refreshed: Option<i64>,
+}
+
+/// A schema file applied to a database with `create-from-schema`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedSchema {
+    pub name: String,
+    pub version: String,
+    /// SHA-256 of the schema file contents, hex encoded
+    pub checksum: String,
+    /// Unix time it was applied
+    pub applied_at: i64,
+}
+
+/// Documentation of a table and its columns, recorded from a schema file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Descriptions {
+    pub table: Option<String>,
+    /// Column name to description
+    pub columns: HashMap<String, String>,
+}
+
+impl Descriptions {
+    pub fn is_empty(&self) -> bool {
+        self.table.is_none() && self.columns.is_empty()
+    }
+}
+
 pub type StreamedQueryResult = Pin<Box<dyn Stream<Item = Result<Vec<DbValue>>> + Send>>;
 
 #[async_trait]
@@ -52,10 +382,32 @@ pub trait Database: Send + Sync {
 
     async fn create_table(&self, table_name: &str, columns: Vec<ColumnDef>) -> Result<()>;
 
+    /// Create a table with table-level UNIQUE constraints, each over one or
+    /// more of its columns
+    async fn create_table_with_unique(
+        &self,
+        table_name: &str,
+        columns: Vec<ColumnDef>,
+        unique: &[Vec<String>],
+    ) -> Result<()>;
+
     async fn drop_table(&self, table_name: &str) -> Result<()>;
 
+    /// Delete every row of a table and reset its AUTOINCREMENT counter.
+    /// Returns the number of rows removed.
+    async fn truncate_table(&self, table_name: &str) -> Result<u64>;
+
     async fn insert(&self, table_name: &str, values: HashMap<String, DbValue>) -> Result<i64>;
 
+    /// Insert a row, returning its rowid, or None if it was skipped under
+    /// `OnConflict::Ignore` or updated in place under `OnConflict::Update`
+    async fn insert_with_conflict(
+        &self,
+        table_name: &str,
+        values: HashMap<String, DbValue>,
+        on_conflict: OnConflict,
+    ) -> Result<Option<i64>>;
+
     async fn update(
         &self,
         table_name: &str,
@@ -65,6 +417,11 @@ pub trait Database: Send + Sync {
 
     async fn delete(&self, table_name: &str, where_clause: &str) -> Result<u64>;
 
+    /// Run writes in order in one transaction, returning what each did. If
+    /// any write fails, none of them take effect and the error is a
+    /// `TransactionError` naming the failed write.
+    async fn execute_writes(&self, writes: Vec<WriteOp>) -> Result<Vec<WriteOutcome>>;
+
     async fn query(&self, sql: &str, params: HashMap<String, DbValue>) -> Result<QueryResult>;
 
     async fn query_stream(
@@ -73,9 +430,211 @@ pub trait Database: Send + Sync {
         params: HashMap<String, DbValue>,
     ) -> Result<(Vec<(String, ColumnType)>, StreamedQueryResult)>;
 
+    /// Columns of a table or view with the type each was declared with
+    /// (`None` for columns declared without a type)
+    async fn table_schema(&self, table_name: &str) -> Result<Vec<(String, Option<ColumnType>)>>;
+
+    /// Fetch the row whose primary key equals `key` (values in key column order)
+    async fn get_row(&self, table_name: &str, key: Vec<DbValue>) -> Result<Option<QueryResult>>;
+
+    /// Create a full-text index over text columns of a table, kept in sync
+    /// with the table by triggers. Returns the number of rows indexed.
+    async fn create_search_index(&self, table_name: &str, columns: &[String]) -> Result<u64>;
+
+    /// Full-text search a table's index, best matches first
+    async fn search(&self, table_name: &str, query: &str, limit: u32) -> Result<SearchResult>;
+
+    /// Create an R*Tree index over coordinate columns of a table, kept in sync
+    /// with the table by triggers. `columns` is either `[x, y]` for points or
+    /// `[min_x, min_y, max_x, max_y]` for boxes. Returns the number of rows indexed.
+    async fn create_spatial_index(&self, table_name: &str, columns: &[String]) -> Result<u64>;
+
+    /// Rows whose point or box intersects `bbox`, using the table's spatial index
+    async fn spatial_query(&self, table_name: &str, bbox: BoundingBox, limit: u32) -> Result<QueryResult>;
+
+    /// Delete all but one row of each group of rows with equal values in
+    /// `columns` (NULLs count as equal), in one transaction; with `dry_run`
+    /// the duplicates are only counted
+    async fn dedupe(&self, table_name: &str, columns: &[String], keep: &KeepRow, dry_run: bool) -> Result<DedupeOutcome>;
+
+    /// Statements creating a table named `as_name` with the columns and
+    /// constraints of `table_name`, and its indexes if the name is the same
+    async fn table_definition(&self, table_name: &str, as_name: &str) -> Result<Vec<String>>;
+
+    /// Up to `count` rows picked at random, in rowid order, without reading
+    /// the whole of a large table
+    async fn sample(&self, table_name: &str, count: u32) -> Result<QueryResult>;
+
+    /// Keep every version of a table's rows in `<table>__history`, recorded by
+    /// triggers, so queries can read the table `AS OF` a past time (see
+    /// `db::history`). Returns the number of rows in the first version.
+    async fn enable_history(&self, table_name: &str) -> Result<u64>;
+
+    /// The most recent changes recorded in history tables (only `table_name`'s
+    /// if given), most recent first
+    async fn history_changes(&self, table_name: Option<&str>, limit: u32) -> Result<Vec<Change>>;
+
+    /// Revert changes, given as table and time, most recent first in one
+    /// transaction, returning the changes and the compensating statements;
+    /// with `dry_run` they are only returned. Fails without changing anything
+    /// if a row a change wrote has been changed again since.
+    async fn undo_changes(&self, changes: &[(String, f64)], dry_run: bool) -> Result<(Vec<Change>, Vec<String>)>;
+
+    /// Columns of a table whose values are stored encrypted
+    async fn encrypted_columns(&self, table_name: &str) -> Result<Vec<String>>;
+
+    /// Record which columns of a table hold encrypted values
+    async fn set_encrypted_columns(&self, table_name: &str, columns: &[String]) -> Result<()>;
+
+    /// Column holding the expiry time of a table's rows, if the table has a TTL
+    async fn ttl_column(&self, table_name: &str) -> Result<Option<String>>;
+
+    /// Expire a table's rows once the time in `column` (Unix seconds or an
+    /// ISO-8601 timestamp) has passed; `None` removes the TTL
+    async fn set_ttl_column(&self, table_name: &str, column: Option<&str>) -> Result<()>;
+
+    /// Delete expired rows from every table with a TTL, returning the rows
+    /// deleted per table (tables with nothing expired are omitted)
+    async fn purge_expired(&self) -> Result<Vec<(String, u64)>>;
+
+    /// Run a statement, discarding any rows it returns, and report how many rows it changed
+    async fn execute(&self, sql: &str) -> Result<u64>;
+
+    /// Jobs saved in this database
+    async fn jobs(&self) -> Result<Vec<Job>>;
+
+    /// Save a job in this database, replacing one with the same name
+    async fn save_job(&self, job: &Job) -> Result<()>;
+
+    /// Remove a saved job, returning whether it existed
+    async fn delete_job(&self, name: &str) -> Result<bool>;
+
+    /// Append a run to the job history
+    async fn record_job_run(&self, run: &JobRun) -> Result<()>;
+
+    /// Most recent runs first, optionally only those of one job
+    async fn job_runs(&self, job_name: Option<&str>, limit: u32) -> Result<Vec<JobRun>>;
+
+    /// Saved queries, ordered by name
+    async fn saved_queries(&self) -> Result<Vec<SavedQuery>>;
+
+    /// The saved query with this name, if any
+    async fn saved_query(&self, name: &str) -> Result<Option<SavedQuery>>;
+
+    /// Save a query, replacing one with the same name
+    async fn save_query(&self, query: &SavedQuery) -> Result<()>;
+
+    /// Remove a saved query, returning whether it existed
+    async fn delete_saved_query(&self, name: &str) -> Result<bool>;
+
+    /// Create a table whose rows are stored in one table per period, created
+    /// as rows arrive. The table name itself becomes a view over every partition.
+    async fn create_partitioned_table(
+        &self,
+        table_name: &str,
+        columns: Vec<ColumnDef>,
+        partitioning: &Partitioning,
+    ) -> Result<()>;
+
+    /// How a table is partitioned, if it is
+    async fn partitioning(&self, table_name: &str) -> Result<Option<Partitioning>>;
+
+    /// Physical partitions of a partitioned table, oldest first (empty if the
+    /// table is not partitioned)
+    async fn partitions(&self, table_name: &str) -> Result<Vec<String>>;
+
+    /// Drop partitions that have fallen out of their table's retention window,
+    /// returning `(table, partition)` for each one dropped
+    async fn drop_expired_partitions(&self) -> Result<Vec<(String, String)>>;
+
+    /// Materialized views defined in this database
+    async fn materialized_views(&self) -> Result<Vec<MaterializedView>>;
+
+    /// Define a materialized view (replacing an existing definition) and fill
+    /// its backing table. Returns the number of rows in the table.
+    async fn create_materialized_view(&self, view: &MaterializedView) -> Result<u64>;
+
+    /// Rebuild a materialized view's backing table from its query, returning
+    /// the number of rows in the table
+    async fn refresh_materialized_view(&self, name: &str) -> Result<u64>;
+
+    /// Refresh every view whose refresh interval has elapsed, returning each
+    /// view's outcome
+    async fn refresh_due_materialized_views(&self) -> Result<Vec<(String, Result<u64>)>>;
+
+    /// The schema file applied to this database most recently, if any
+    async fn applied_schema(&self) -> Result<Option<AppliedSchema>>;
+
+    /// Record that a schema file was applied to this database
+    async fn record_applied_schema(&self, name: &str, version: &str, checksum: &str) -> Result<()>;
+
+    /// Descriptions recorded for a table and its columns
+    async fn descriptions(&self, table_name: &str) -> Result<Descriptions>;
+
+    /// Record descriptions of a table and its columns, replacing those
+    /// recorded before
+    async fn set_descriptions(&self, table_name: &str, descriptions: &Descriptions) -> Result<()>;
+
     async fn batch_insert(
         &self,
         table_name: &str,
         rows: Vec<HashMap<String, DbValue>>,
     ) -> Result<u64>;
+
+    /// Insert rows in one transaction, returning how many were inserted and
+    /// how many replaced conflicting rows (rows skipped under
+    /// `OnConflict::Ignore` are in neither count)
+    async fn batch_insert_with_conflict(
+        &self,
+        table_name: &str,
+        rows: Vec<HashMap<String, DbValue>>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertCounts>;
+
+    /// File, page and WAL sizes of the database
+    async fn disk_usage(&self) -> Result<DiskUsage>;
+
+    /// A handle on a connection of its own to the same database, for a
+    /// client session: TEMP tables created through it are only visible
+    /// through it, and are dropped when it is dropped
+    async fn open_session(&self) -> Result<Box<dyn Database>>;
+
+    /// Backend, file, journal mode and table count of the database
+    async fn storage_info(&self) -> Result<StorageInfo>;
+
+    /// Row count and size of every user table, by name
+    async fn table_stats(&self) -> Result<Vec<TableStats>>;
+
+    /// Current value of a PRAGMA, as text
+    async fn pragma(&self, name: &str) -> Result<String>;
+
+    /// Write a copy of the database to a new file at `path`. With
+    /// `schema_only` the copy has the tables, indexes, views and triggers
+    /// and DataSink's settings for them, but no rows and no job history.
+    async fn copy_to(&self, path: &std::path::Path, schema_only: bool) -> Result<()>;
+
+    /// Whether connections to this database enforce foreign key constraints
+    async fn foreign_keys_enabled(&self) -> Result<bool>;
+
+    /// EXPLAIN QUERY PLAN of a statement, one line per step (e.g.
+    /// "SCAN orders", "SEARCH users USING INTEGER PRIMARY KEY (rowid=?)")
+    async fn query_plan(&self, sql: &str) -> Result<Vec<String>>;
+
+    /// The plan a statement would get if an index existed: the index is
+    /// built in a transaction that is rolled back
+    async fn query_plan_with_index(&self, sql: &str, create_index_sql: &str) -> Result<Vec<String>>;
+
+    /// Problems a quick structural check of the database finds, e.g. corrupt
+    /// pages or out-of-order indexes; empty if it is intact
+    async fn quick_check(&self) -> Result<Vec<String>>;
+
+    /// Up to `limit` rows violating a foreign key constraint, in `table_name`
+    /// only if given. Rows inserted while enforcement was off can violate
+    /// constraints that are enforced now.
+    async fn foreign_key_violations(&self, table_name: Option<&str>, limit: u32) -> Result<Vec<ForeignKeyViolation>>;
+
+    /// Pool connection counts, if the backend pools connections
+    fn connection_stats(&self) -> Option<ConnectionStats> {
+        None
+    }
 }
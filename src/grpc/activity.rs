@@ -0,0 +1,325 @@
+//! Live request activity per database.
+//!
+//! Counts requests, queries, rows written and errors, and tracks in-flight
+//! queries and the last write, for each database, and remembers the
+//! most recent queries that ran longer than the slow query threshold, with
+//! the query plan captured when they finished. Exposed
+//! through GetMetrics and GetServerStatus for `datasink server top`. Queries
+//! are also counted by shape for the index advisor (AdviseIndexes).
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::db::advisor::{normalize_query, QueryPattern};
+
+/// Slow queries kept for GetServerStatus, most recent last
+const MAX_SLOW_QUERIES: usize = 20;
+
+/// Longest SQL text kept for a slow query
+const MAX_SLOW_QUERY_SQL: usize = 500;
+
+/// Query shapes counted per database; queries of further shapes are not counted
+const MAX_QUERY_PATTERNS: usize = 200;
+
+/// Default duration above which a query is reported as slow
+pub const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_millis(1000);
+
+/// A query that took longer than the slow query threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowQuery {
+    pub database: String,
+    pub sql: String,
+    pub duration_ms: u64,
+    /// Unix time the query finished
+    pub finished_at: i64,
+    pub request_id: String,
+    /// EXPLAIN QUERY PLAN lines, captured when the query finished; empty if
+    /// the plan could not be read
+    pub plan: Vec<String>,
+}
+
+/// Request counters of one database
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatabaseActivity {
+    /// Requests handled since the server started
+    pub requests: u64,
+    /// Queries whose results are still being produced or streamed
+    pub active_queries: u64,
+    /// Queries served since the server started
+    pub queries: u64,
+    /// Rows inserted, updated or deleted since the server started
+    pub rows_written: u64,
+    /// Unix time of the most recent write, if any since the server started
+    pub last_write: Option<i64>,
+    /// Requests that failed with a database error since the server started
+    pub errors: u64,
+}
+
+#[derive(Debug)]
+pub struct Activity {
+    slow_query_threshold: Duration,
+    databases: Mutex<HashMap<String, DatabaseActivity>>,
+    slow_queries: Mutex<VecDeque<SlowQuery>>,
+    /// Per database, queries run keyed by their normalized text
+    patterns: Mutex<HashMap<String, HashMap<String, QueryPattern>>>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOW_QUERY_THRESHOLD)
+    }
+}
+
+impl Activity {
+    pub fn new(slow_query_threshold: Duration) -> Self {
+        Self {
+            slow_query_threshold,
+            databases: Mutex::new(HashMap::new()),
+            slow_queries: Mutex::new(VecDeque::new()),
+            patterns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record_request(&self, database: &str) {
+        let mut databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+        databases.entry(database.to_string()).or_default().requests += 1;
+    }
+
+    pub fn record_query(&self, database: &str) {
+        let mut databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+        databases.entry(database.to_string()).or_default().queries += 1;
+    }
+
+    /// Count rows written; a write of no rows (such as a truncate or an
+    /// update matching nothing) still counts as the last write
+    pub fn record_write(&self, database: &str, rows: u64) {
+        let mut databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+        let activity = databases.entry(database.to_string()).or_default();
+        activity.rows_written += rows;
+        activity.last_write = Some(chrono::Utc::now().timestamp());
+    }
+
+    pub fn record_error(&self, database: &str) {
+        let mut databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+        databases.entry(database.to_string()).or_default().errors += 1;
+    }
+
+    /// Count a query as active until the returned guard is dropped, then
+    /// record it if it was slow
+    pub fn start_query(self: &Arc<Self>, database: &str, sql: &str, request_id: &str) -> ActiveQuery {
+        let mut databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+        databases.entry(database.to_string()).or_default().active_queries += 1;
+        ActiveQuery {
+            activity: self.clone(),
+            database: database.to_string(),
+            sql: sql.to_string(),
+            request_id: request_id.to_string(),
+            started: Instant::now(),
+            duration: None,
+            plan: Vec::new(),
+        }
+    }
+
+    /// Counters of every database that has seen a request, by name
+    pub fn databases(&self) -> Vec<(String, DatabaseActivity)> {
+        let databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+        let mut activity: Vec<_> = databases.iter().map(|(name, activity)| (name.clone(), *activity)).collect();
+        activity.sort_by(|a, b| a.0.cmp(&b.0));
+        activity
+    }
+
+    /// Recent slow queries, most recent first
+    pub fn slow_queries(&self) -> Vec<SlowQuery> {
+        let slow_queries = self.slow_queries.lock().unwrap_or_else(|e| e.into_inner());
+        slow_queries.iter().rev().cloned().collect()
+    }
+
+    /// Queries a database has run since the server started, grouped by
+    /// shape, most frequent first
+    pub fn query_patterns(&self, database: &str) -> Vec<QueryPattern> {
+        let patterns = self.patterns.lock().unwrap_or_else(|e| e.into_inner());
+        let mut patterns: Vec<QueryPattern> = patterns.get(database).map(|p| p.values().cloned().collect()).unwrap_or_default();
+        patterns.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.sql.cmp(&b.sql)));
+        patterns
+    }
+
+    fn record_pattern(&self, database: &str, sql: &str) {
+        let mut patterns = self.patterns.lock().unwrap_or_else(|e| e.into_inner());
+        let patterns = patterns.entry(database.to_string()).or_default();
+        let key = normalize_query(sql);
+        if let Some(pattern) = patterns.get_mut(&key) {
+            pattern.count += 1;
+        } else if patterns.len() < MAX_QUERY_PATTERNS {
+            patterns.insert(key, QueryPattern { sql: sql.to_string(), count: 1 });
+        }
+    }
+
+    fn finish_query(&self, query: &ActiveQuery) {
+        {
+            let mut databases = self.databases.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(activity) = databases.get_mut(&query.database) {
+                activity.active_queries = activity.active_queries.saturating_sub(1);
+            }
+        }
+        self.record_pattern(&query.database, &query.sql);
+
+        let elapsed = query.duration.unwrap_or_else(|| query.started.elapsed());
+        if elapsed < self.slow_query_threshold {
+            return;
+        }
+        let mut sql = query.sql.clone();
+        if let Some((end, _)) = sql.char_indices().nth(MAX_SLOW_QUERY_SQL) {
+            sql.truncate(end);
+            sql.push_str("...");
+        }
+        let mut slow_queries = self.slow_queries.lock().unwrap_or_else(|e| e.into_inner());
+        if slow_queries.len() == MAX_SLOW_QUERIES {
+            slow_queries.pop_front();
+        }
+        slow_queries.push_back(SlowQuery {
+            database: query.database.clone(),
+            sql,
+            duration_ms: elapsed.as_millis() as u64,
+            finished_at: chrono::Utc::now().timestamp(),
+            request_id: query.request_id.clone(),
+            plan: query.plan.clone(),
+        });
+    }
+}
+
+/// A running query; see `Activity::start_query`
+#[derive(Debug)]
+pub struct ActiveQuery {
+    activity: Arc<Activity>,
+    database: String,
+    sql: String,
+    request_id: String,
+    started: Instant,
+    /// Set by `stop`; otherwise the query is timed until it is dropped
+    duration: Option<Duration>,
+    plan: Vec<String>,
+}
+
+impl ActiveQuery {
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// Stop timing the query, so work done before it is dropped (such as
+    /// capturing its plan) does not count; returns whether it was slow
+    pub fn stop(&mut self) -> bool {
+        let duration = *self.duration.get_or_insert_with(|| self.started.elapsed());
+        duration >= self.activity.slow_query_threshold
+    }
+
+    /// Plan to keep with the slow query record
+    pub fn set_plan(&mut self, plan: Vec<String>) {
+        self.plan = plan;
+    }
+}
+
+impl Drop for ActiveQuery {
+    fn drop(&mut self) {
+        self.activity.finish_query(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_query_counters() {
+        let activity = Arc::new(Activity::new(Duration::from_secs(60)));
+        activity.record_request("default");
+        activity.record_request("default");
+        activity.record_request("shop");
+
+        let first = activity.start_query("default", "SELECT 1", "req-1");
+        let second = activity.start_query("default", "SELECT 2", "req-2");
+        assert_eq!(
+            activity.databases(),
+            vec![
+                ("default".to_string(), DatabaseActivity { requests: 2, active_queries: 2, ..Default::default() }),
+                ("shop".to_string(), DatabaseActivity { requests: 1, ..Default::default() }),
+            ]
+        );
+
+        drop(first);
+        drop(second);
+        assert_eq!(activity.databases()[0].1.active_queries, 0);
+        assert!(activity.slow_queries().is_empty());
+    }
+
+    #[test]
+    fn test_query_write_and_error_counters() {
+        let activity = Activity::default();
+        activity.record_query("shop");
+        activity.record_query("shop");
+        activity.record_write("shop", 3);
+        activity.record_write("shop", 0);
+        activity.record_error("shop");
+
+        let databases = activity.databases();
+        let (name, shop) = &databases[0];
+        assert_eq!(name, "shop");
+        assert_eq!((shop.queries, shop.rows_written, shop.errors), (2, 3, 1));
+        assert!(shop.last_write.is_some_and(|time| time > 0));
+
+        activity.record_request("default");
+        assert_eq!(activity.databases()[0].1.last_write, None);
+    }
+
+    #[test]
+    fn test_slow_queries_are_kept_most_recent_first() {
+        let activity = Arc::new(Activity::new(Duration::ZERO));
+        for i in 0..MAX_SLOW_QUERIES + 5 {
+            drop(activity.start_query("default", &format!("SELECT {}", i), "req"));
+        }
+        drop(activity.start_query("default", &"x".repeat(MAX_SLOW_QUERY_SQL + 10), "req-long"));
+
+        let slow = activity.slow_queries();
+        assert_eq!(slow.len(), MAX_SLOW_QUERIES);
+        assert_eq!(slow[0].request_id, "req-long");
+        assert_eq!(slow[0].sql.len(), MAX_SLOW_QUERY_SQL + 3);
+        assert_eq!(slow[1].sql, format!("SELECT {}", MAX_SLOW_QUERIES + 4));
+    }
+
+    #[test]
+    fn test_slow_query_plan() {
+        let activity = Arc::new(Activity::new(Duration::from_millis(20)));
+        let mut query = activity.start_query("default", "SELECT * FROM events", "req-1");
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(query.stop());
+        query.set_plan(vec!["SCAN events".to_string()]);
+        // Time spent after stop() is not counted
+        std::thread::sleep(Duration::from_millis(100));
+        drop(query);
+
+        let slow = activity.slow_queries();
+        assert_eq!(slow[0].plan, vec!["SCAN events".to_string()]);
+        assert!(slow[0].duration_ms < 100);
+
+        let mut fast = activity.start_query("default", "SELECT 1", "req-2");
+        assert!(!fast.stop());
+        drop(fast);
+        assert_eq!(activity.slow_queries().len(), 1);
+    }
+
+    #[test]
+    fn test_query_patterns() {
+        let activity = Arc::new(Activity::new(Duration::from_secs(60)));
+        for id in 0..3 {
+            drop(activity.start_query("shop", &format!("SELECT * FROM orders WHERE id = {}", id), "req"));
+        }
+        drop(activity.start_query("shop", "SELECT * FROM users WHERE email = 'a@example.com'", "req"));
+        drop(activity.start_query("default", "SELECT 1", "req"));
+
+        let patterns = activity.query_patterns("shop");
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[0], QueryPattern { sql: "SELECT * FROM orders WHERE id = 0".to_string(), count: 3 });
+        assert_eq!(patterns[1].count, 1);
+        assert!(activity.query_patterns("missing").is_empty());
+    }
+}
@@ -1,5 +1,11 @@
-use crate::db::traits::{ColumnDef, ColumnType, DbValue};
-use crate::proto::common::{ColumnDefinition, DataType, Value as ProtoValue, value};
+use crate::db::history::Change;
+use crate::db::traits::{ColumnDef, ColumnType, DbValue, GeneratedColumn, OnConflict};
+use crate::db::{DatabaseError, SqliteDatabase};
+use crate::proto::admin::HistoryChange;
+use crate::proto::common::{
+    ColumnDefinition, DataType, Error as ProtoError, UnknownColumn as ProtoUnknownColumn, Value as ProtoValue, value,
+};
+use crate::proto::crud::OnConflict as ProtoOnConflict;
 use std::collections::HashMap;
 
 pub fn proto_to_column_type(data_type: DataType) -> ColumnType {
@@ -13,6 +19,16 @@ pub fn proto_to_column_type(data_type: DataType) -> ColumnType {
     }
 }
 
+/// Unknown values fall back to ABORT, the proto3 default
+pub fn proto_to_on_conflict(on_conflict: i32) -> OnConflict {
+    match ProtoOnConflict::try_from(on_conflict) {
+        Ok(ProtoOnConflict::Ignore) => OnConflict::Ignore,
+        Ok(ProtoOnConflict::Replace) => OnConflict::Replace,
+        Ok(ProtoOnConflict::Update) => OnConflict::Update,
+        _ => OnConflict::Abort,
+    }
+}
+
 pub fn column_type_to_proto(col_type: &ColumnType) -> DataType {
     match col_type {
         ColumnType::Integer => DataType::Integer,
@@ -24,18 +40,69 @@ pub fn column_type_to_proto(col_type: &ColumnType) -> DataType {
     }
 }
 
-pub fn proto_to_column_def(def: ColumnDefinition) -> ColumnDef {
-    ColumnDef {
+pub fn proto_to_column_def(def: ColumnDefinition) -> Result<ColumnDef, DatabaseError> {
+    let col_type = proto_to_column_type(DataType::try_from(def.r#type).unwrap_or(DataType::Text));
+    Ok(ColumnDef {
+        default_value: if def.default_value.is_empty() {
+            None
+        } else {
+            Some(SqliteDatabase::default_sql(&def.name, &def.default_value, &col_type)?)
+        },
         name: def.name,
-        col_type: proto_to_column_type(DataType::try_from(def.r#type).unwrap_or(DataType::Text)),
+        col_type,
         nullable: def.nullable,
         primary_key: def.primary_key,
         unique: def.unique,
-        default_value: if def.default_value.is_empty() {
+        generated: if def.generated.is_empty() {
             None
         } else {
-            Some(def.default_value)
+            Some(GeneratedColumn {
+                expression: def.generated,
+                stored: def.generated_stored,
+            })
         },
+        collation: if def.collation.is_empty() {
+            None
+        } else {
+            Some(def.collation.parse()?)
+        },
+        allowed_values: if def.allowed_values.is_empty() {
+            None
+        } else {
+            Some(def.allowed_values)
+        },
+    })
+}
+
+/// The Error message describing a failed database operation: `code` is
+/// "CONSTRAINT_VIOLATION", "UNKNOWN_COLUMN" or "QUERY_ERROR", with the
+/// constraint, column, unknown columns, SQLite result code and retryability
+/// filled in when known
+pub fn db_error_to_proto(err: &DatabaseError, message: String) -> ProtoError {
+    let failure = err.constraint_failure();
+    let unknown_columns = match err {
+        DatabaseError::UnknownColumns { columns, .. } => columns
+            .iter()
+            .map(|column| ProtoUnknownColumn { name: column.name.clone(), close_matches: column.close_matches.clone() })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let code = if err.is_constraint_violation() {
+        "CONSTRAINT_VIOLATION"
+    } else if !unknown_columns.is_empty() {
+        "UNKNOWN_COLUMN"
+    } else {
+        "QUERY_ERROR"
+    };
+    ProtoError {
+        code: code.to_string(),
+        message,
+        constraint: failure.as_ref().map(|f| f.kind.to_string()).unwrap_or_default(),
+        constraint_name: failure.as_ref().and_then(|f| f.name.clone()).unwrap_or_default(),
+        column: failure.and_then(|f| f.column).unwrap_or_default(),
+        sqlite_code: err.sqlite_code().unwrap_or_default(),
+        retryable: err.is_retryable(),
+        unknown_columns,
     }
 }
 
@@ -79,9 +146,21 @@ pub fn db_values_to_proto_values(values: Vec<DbValue>) -> Vec<ProtoValue> {
     values.into_iter().map(db_value_to_proto).collect()
 }
 
+pub fn change_to_proto(change: &Change) -> HistoryChange {
+    HistoryChange {
+        id: change.id(),
+        table_name: change.table.clone(),
+        time: change.time,
+        inserted: change.inserted,
+        updated: change.updated,
+        deleted: change.deleted,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::traits::Collation;
 
     #[test]
     fn test_proto_to_column_type() {
@@ -112,9 +191,13 @@ mod tests {
             primary_key: true,
             unique: false,
             default_value: "0".to_string(),
+            generated: String::new(),
+            generated_stored: false,
+            collation: String::new(),
+            allowed_values: vec![],
         };
 
-        let db_def = proto_to_column_def(proto_def);
+        let db_def = proto_to_column_def(proto_def).unwrap();
         assert_eq!(db_def.name, "test_col");
         assert!(matches!(db_def.col_type, ColumnType::Integer));
         assert!(!db_def.nullable);
@@ -132,13 +215,42 @@ mod tests {
             primary_key: false,
             unique: true,
             default_value: "".to_string(),
+            generated: "price * quantity".to_string(),
+            generated_stored: true,
+            collation: "nocase".to_string(),
+            allowed_values: vec![],
         };
 
-        let db_def = proto_to_column_def(proto_def);
+        let db_def = proto_to_column_def(proto_def).unwrap();
         assert_eq!(db_def.default_value, None);
+        assert_eq!(
+            db_def.generated,
+            Some(GeneratedColumn { expression: "price * quantity".to_string(), stored: true })
+        );
+        assert_eq!(db_def.collation, Some(Collation::NoCase));
+
+        let proto_def = ColumnDefinition { collation: "accent_insensitive".to_string(), ..Default::default() };
+        assert!(proto_to_column_def(proto_def).is_err());
+
+        let proto_def = ColumnDefinition {
+            r#type: DataType::Integer as i32,
+            default_value: "zero".to_string(),
+            ..Default::default()
+        };
+        assert!(proto_to_column_def(proto_def).is_err());
+    }
+
+    #[test]
+    fn test_db_error_to_proto() {
+        let error = db_error_to_proto(&DatabaseError::ConnectionError("refused".to_string()), "refused".to_string());
+        assert_eq!(error.code, "QUERY_ERROR");
+        assert_eq!(error.message, "refused");
+        assert!(error.retryable);
+        assert_eq!((error.constraint.as_str(), error.sqlite_code), ("", 0));
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_proto_to_db_value() {
         // Test integer
         let proto_int = ProtoValue {
@@ -180,6 +292,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_db_value_to_proto() {
         // Test integer
         let db_int = DbValue::Integer(42);
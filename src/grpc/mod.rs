@@ -1,4 +1,9 @@
+pub mod activity;
 pub mod conversions;
+pub mod policy;
+pub mod request_id;
 pub mod service;
+pub mod session;
 
+pub use request_id::RequestIdLayer;
 pub use service::DataSinkService;
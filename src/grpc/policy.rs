@@ -0,0 +1,737 @@
+//! Row-level security for authenticated identities.
+//!
+//! Identities are configured with a bearer token and, per table, a mandatory
+//! filter such as `tenant_id = :tenant`. An interceptor resolves the caller's
+//! identity from the `authorization` metadata; handlers then AND the filter
+//! into Update and Delete conditions and rewrite SELECTs so restricted tables
+//! are read through a filtered CTE of the same name. Requests that cannot be
+//! restricted this way (writes through raw SQL, GetRow, Search, ...) are
+//! refused for restricted tables. Table names given in requests must be bare
+//! identifiers, so a quoted or qualified spelling cannot dodge the filter.
+//!
+//! An identity can also be limited to some of the server's databases; its
+//! requests for any other database are refused.
+//!
+//! A separate admin token, when configured, guards admin-plane operations
+//! such as adding databases and dropping tables; regular identities cannot
+//! call them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::config::IdentityConfig;
+use crate::db::cache::{is_cacheable, normalize_identifier};
+use crate::db::history::{find_as_of, history_table_name};
+use crate::db::partition::PartitionPeriod;
+use crate::db::traits::MaterializedView;
+use crate::grpc::request_id::with_request_id;
+
+/// Metadata key carrying the caller's bearer token
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// Why a request was refused by a row policy
+#[derive(Debug)]
+pub enum PolicyError {
+    /// The caller's WHERE clause cannot be combined safely with the filter
+    InvalidWhereClause(String),
+    /// The request would bypass the filter
+    Denied(String),
+}
+
+impl From<PolicyError> for Status {
+    fn from(err: PolicyError) -> Self {
+        with_request_id(match err {
+            PolicyError::InvalidWhereClause(reason) => {
+                Status::invalid_argument(format!("Invalid where clause: {}", reason))
+            }
+            PolicyError::Denied(message) => Status::permission_denied(message),
+        })
+    }
+}
+
+/// Whether a request carries the admin token. Only inserted into requests
+/// when the server has an admin token; without one every caller has access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminAccess {
+    Granted,
+    Denied,
+}
+
+impl AdminAccess {
+    /// Refuse an admin-plane operation to a caller without the admin token
+    pub fn check<T>(request: &Request<T>, operation: &str) -> Result<(), PolicyError> {
+        match request.extensions().get::<AdminAccess>() {
+            Some(AdminAccess::Denied) => {
+                Err(PolicyError::Denied(format!("{} requires the admin token", operation)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An authenticated caller and the row filters that apply to it
+#[derive(Debug)]
+pub struct Identity {
+    pub name: String,
+    /// Rendered filters keyed by lowercased table name
+    filters: HashMap<String, String>,
+    /// Databases the identity may use; `None` for all of them
+    databases: Option<Vec<String>>,
+}
+
+impl Identity {
+    /// The filter applied to a table, if the table is restricted
+    pub fn filter(&self, table: &str) -> Option<&str> {
+        self.filters.get(&normalize_identifier(table)).map(String::as_str)
+    }
+
+    /// The restricted table whose rows `name` holds, and its filter: the
+    /// table itself, or its FTS5 and R*Tree index tables, history table or
+    /// partitions, which hold copies of its rows without a filter of their own
+    fn restricted_source(&self, name: &str) -> Option<(&String, &String)> {
+        let name = normalize_identifier(name);
+        self.filters.iter().find(|(table, _)| {
+            name == **table
+                || name.starts_with(&format!("{}_fts", table))
+                || name.starts_with(&format!("{}_rtree", table))
+                || name.starts_with(&history_table_name(table))
+                || PartitionPeriod::ALL.iter().any(|period| period.matches(table, &name))
+        })
+    }
+
+    /// Refuse naming a table that holds copies of a restricted table's rows
+    fn check_not_copy(&self, name: &str) -> Result<(), PolicyError> {
+        match self.restricted_source(name) {
+            Some((table, _)) if *table != normalize_identifier(name) => Err(self.denied(
+                table,
+                &format!("'{}' holds copies of its rows and cannot be queried", normalize_identifier(name)),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuse a table name other than a bare identifier when the identity has
+    /// row policies: the name reaches the SQL as written, so `"orders"`,
+    /// `main.orders` or `[orders]` would name a restricted table the filter
+    /// lookup does not match
+    fn check_table_name(&self, table: &str) -> Result<(), PolicyError> {
+        let bare = !table.is_empty() && table.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if self.filters.is_empty() || bare {
+            return Ok(());
+        }
+        Err(PolicyError::Denied(format!(
+            "Identity '{}' has row policies: table '{}' must be named without quotes or a schema",
+            self.name, table
+        )))
+    }
+
+    /// Combine a caller's WHERE clause with the table's filter
+    pub fn restrict_where(&self, table: &str, where_clause: &str) -> Result<String, PolicyError> {
+        self.check_table_name(table)?;
+        self.check_not_copy(table)?;
+        let Some(filter) = self.filter(table) else {
+            return Ok(where_clause.to_string());
+        };
+        // An unbalanced clause ("1) OR (1") could escape the parentheses
+        check_clause(where_clause).map_err(PolicyError::InvalidWhereClause)?;
+        Ok(format!("({}) AND ({})", where_clause, filter))
+    }
+
+    /// Refuse an operation that cannot apply the table's filter
+    pub fn check_unrestricted(&self, table: &str, operation: &str) -> Result<(), PolicyError> {
+        self.check_table_name(table)?;
+        self.check_not_copy(table)?;
+        match self.filter(table) {
+            Some(_) => Err(self.denied(table, &format!("{} is not available", operation))),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuse an operation that runs SQL the filters cannot be applied to
+    pub fn check_no_filters(&self, operation: &str) -> Result<(), PolicyError> {
+        if self.filters.is_empty() {
+            return Ok(());
+        }
+        Err(PolicyError::Denied(format!(
+            "Identity '{}' has row policies: {} is not available",
+            self.name, operation
+        )))
+    }
+
+    /// Whether the identity has row filters
+    pub fn has_filters(&self) -> bool {
+        !self.filters.is_empty()
+    }
+
+    /// Refuse a statement or table name that reads a materialized view filled
+    /// from a restricted table, directly or through another view: the view's
+    /// backing table holds every row of the source
+    pub fn check_materialized_views(&self, sql: &str, views: &[MaterializedView]) -> Result<(), PolicyError> {
+        if self.filters.is_empty() {
+            return Ok(());
+        }
+        // Views over restricted tables, with the restricted table each one reads
+        let mut restricted: Vec<(String, String)> = Vec::new();
+        loop {
+            let before = restricted.len();
+            for view in views {
+                let name = normalize_identifier(&view.name);
+                if restricted.iter().any(|(v, _)| *v == name) {
+                    continue;
+                }
+                let source = lex(&view.query).into_iter().find_map(|token| match token {
+                    Token::Name(n) => self
+                        .restricted_source(&n)
+                        .map(|(table, _)| table.clone())
+                        .or_else(|| restricted.iter().find(|(v, _)| *v == n).map(|(_, table)| table.clone())),
+                    _ => None,
+                });
+                if let Some(source) = source {
+                    restricted.push((name, source));
+                }
+            }
+            if restricted.len() == before {
+                break;
+            }
+        }
+
+        for token in lex(sql) {
+            if let Token::Name(name) = token {
+                if let Some((view, table)) = restricted.iter().find(|(v, _)| *v == name) {
+                    return Err(self.denied(table, &format!("materialized view '{}' holds copies of its rows", view)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuse a request for a database outside the identity's scope
+    pub fn check_database(&self, database: &str) -> Result<(), PolicyError> {
+        match &self.databases {
+            Some(databases) if !databases.iter().any(|d| d == database) => Err(PolicyError::Denied(format!(
+                "Identity '{}' has no access to database '{}'",
+                self.name, database
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuse an operation that is not tied to one database
+    pub fn check_all_databases(&self, operation: &str) -> Result<(), PolicyError> {
+        match &self.databases {
+            Some(_) => Err(PolicyError::Denied(format!(
+                "Identity '{}' is limited to some databases: {} is not available",
+                self.name, operation
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the identity may use a database
+    pub fn can_access(&self, database: &str) -> bool {
+        self.check_database(database).is_ok()
+    }
+
+    /// Rewrite a query so restricted tables only expose the caller's rows.
+    ///
+    /// `SELECT * FROM orders` becomes
+    /// `WITH "orders" AS (SELECT * FROM main."orders" WHERE (filter)) SELECT * FROM orders`;
+    /// SQLite resolves unqualified names to the CTE before the table.
+    pub fn restrict_query(&self, sql: &str) -> Result<String, PolicyError> {
+        let mut restricted: Vec<(String, &str)> = Vec::new();
+        let tokens = lex(sql);
+        for (i, token) in tokens.iter().enumerate() {
+            let Token::Name(name) = token else {
+                continue;
+            };
+            let Some((table, filter)) = self.restricted_source(name) else {
+                continue;
+            };
+            self.check_not_copy(name)?;
+            // "main.orders" would bypass the CTE
+            if i > 0 && tokens[i - 1] == Token::Dot {
+                return Err(self.denied(table, "schema-qualified references are not allowed"));
+            }
+            if !restricted.iter().any(|(t, _)| t == table) {
+                restricted.push((table.clone(), filter.as_str()));
+            }
+        }
+
+        if restricted.is_empty() {
+            return Ok(sql.to_string());
+        }
+        // AS OF reads the history table in place of the filtered table
+        let as_of = find_as_of(sql).unwrap_or_default();
+        if let Some(clause) = as_of.iter().find(|c| restricted.iter().any(|(t, _)| c.table.eq_ignore_ascii_case(t))) {
+            return Err(self.denied(&clause.table, "it cannot be queried AS OF a past time"));
+        }
+        if !is_cacheable(sql) {
+            return Err(self.denied(&restricted[0].0, "only SELECT statements may reference it"));
+        }
+
+        let ctes: Vec<String> = restricted
+            .iter()
+            .map(|(table, filter)| format!("\"{0}\" AS (SELECT * FROM main.\"{0}\" WHERE ({1}))", table, filter))
+            .collect();
+        let ctes = ctes.join(", ");
+
+        // Merge into an existing WITH [RECURSIVE] clause
+        let trimmed = sql.trim_start();
+        let mut words = trimmed.splitn(3, char::is_whitespace);
+        let first = words.next().unwrap_or_default();
+        if first.eq_ignore_ascii_case("with") {
+            let rest = trimmed[first.len()..].trim_start();
+            let second = rest.split(char::is_whitespace).next().unwrap_or_default();
+            if second.eq_ignore_ascii_case("recursive") {
+                let rest = rest[second.len()..].trim_start();
+                return Ok(format!("WITH RECURSIVE {}, {}", ctes, rest));
+            }
+            return Ok(format!("WITH {}, {}", ctes, rest));
+        }
+        Ok(format!("WITH {} {}", ctes, trimmed))
+    }
+
+    fn denied(&self, table: &str, reason: &str) -> PolicyError {
+        PolicyError::Denied(format!(
+            "Table '{}' has a row policy for identity '{}': {}",
+            table, self.name, reason
+        ))
+    }
+}
+
+/// A token of a statement as `restrict_query` sees it
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    /// An identifier or keyword, unquoted and lowercased
+    Name(String),
+    Dot,
+    Other,
+}
+
+/// Split a statement into tokens the way SQLite does, skipping comments and
+/// string literals, so that no spelling of a table reference goes unseen
+/// ("main. orders", "main/**/.orders", "[orders]")
+fn lex(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let at = |i: usize| chars.get(i).copied();
+    let is_word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '$');
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(c) = at(i) {
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '-' if at(i + 1) == Some('-') => {
+                while at(i).is_some_and(|c| c != '\n') {
+                    i += 1;
+                }
+            }
+            '/' if at(i + 1) == Some('*') => {
+                i += 2;
+                while at(i).is_some() && !(at(i) == Some('*') && at(i + 1) == Some('/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            '\'' | '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                let mut text = String::new();
+                i += 1;
+                while let Some(next) = at(i) {
+                    i += 1;
+                    if next == close {
+                        // A doubled quote is an escaped quote
+                        if close != ']' && at(i) == Some(close) {
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    text.push(next);
+                }
+                tokens.push(if c == '\'' { Token::Other } else { Token::Name(text.to_lowercase()) });
+            }
+            '.' => {
+                tokens.push(Token::Dot);
+                i += 1;
+            }
+            // Numbers and parameters (":orders" is not a table)
+            c if c.is_ascii_digit() || (matches!(c, '?' | ':' | '@' | '$')) => {
+                i += 1;
+                while at(i).is_some_and(|c| is_word(c) || (c == '.' && chars[i - 1].is_ascii_digit())) {
+                    i += 1;
+                }
+                tokens.push(Token::Other);
+            }
+            c if is_word(c) => {
+                let start = i;
+                while at(i).is_some_and(is_word) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect::<String>().to_lowercase()));
+            }
+            _ => {
+                tokens.push(Token::Other);
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// Identities keyed by bearer token
+#[derive(Debug, Default)]
+pub struct RowPolicies {
+    identities: HashMap<String, Arc<Identity>>,
+    /// Token for admin-plane operations and the unrestricted identity it grants
+    admin: Option<(String, Arc<Identity>)>,
+}
+
+impl RowPolicies {
+    /// Build the policies from configuration, rendering each filter's parameters
+    pub fn from_config(identities: &[IdentityConfig]) -> Result<Self, String> {
+        let mut policies = Self::default();
+        for config in identities {
+            if config.token.is_empty() {
+                return Err(format!("Identity '{}' has an empty token", config.name));
+            }
+            let mut filters = HashMap::new();
+            for (table, filter) in &config.filters {
+                let rendered = render_filter(filter, &config.params)
+                    .map_err(|e| format!("Invalid filter for '{}' of identity '{}': {}", table, config.name, e))?;
+                filters.insert(table.to_lowercase(), rendered);
+            }
+            if config.databases.as_ref().is_some_and(Vec::is_empty) {
+                return Err(format!("Identity '{}' has an empty list of databases", config.name));
+            }
+            let identity = Arc::new(Identity {
+                name: config.name.clone(),
+                filters,
+                databases: config.databases.clone(),
+            });
+            if policies.identities.insert(config.token.clone(), identity).is_some() {
+                return Err(format!("Identity '{}' reuses another identity's token", config.name));
+            }
+        }
+        Ok(policies)
+    }
+
+    /// Require `token` for admin-plane operations. It must differ from every
+    /// identity's token.
+    pub fn with_admin_token(mut self, token: &str) -> Result<Self, String> {
+        if token.is_empty() {
+            return Err("The admin token is empty".to_string());
+        }
+        if let Some(identity) = self.identities.get(token) {
+            return Err(format!("The admin token is also the token of identity '{}'", identity.name));
+        }
+        let identity = Arc::new(Identity { name: "admin".to_string(), filters: HashMap::new(), databases: None });
+        self.admin = Some((token.to_string(), identity));
+        Ok(self)
+    }
+
+    /// Whether no identities are configured, leaving the data plane open
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+
+    /// The identity a bearer token belongs to; the admin token's identity has no filters
+    pub fn resolve(&self, token: &str) -> Option<Arc<Identity>> {
+        match &self.admin {
+            Some((admin_token, identity)) if admin_token == token => Some(identity.clone()),
+            _ => self.identities.get(token).cloned(),
+        }
+    }
+
+    /// The admin access a bearer token gives, if an admin token is configured
+    pub fn admin_access(&self, token: Option<&str>) -> Option<AdminAccess> {
+        let (admin_token, _) = self.admin.as_ref()?;
+        Some(if token == Some(admin_token.as_str()) { AdminAccess::Granted } else { AdminAccess::Denied })
+    }
+}
+
+/// Interceptor that authenticates requests and attaches their [`Identity`]
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    policies: Arc<RowPolicies>,
+}
+
+impl AuthInterceptor {
+    pub fn new(policies: Arc<RowPolicies>) -> Self {
+        Self { policies }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .map(str::to_string);
+        if let Some(access) = self.policies.admin_access(token.as_deref()) {
+            request.extensions_mut().insert(access);
+        }
+
+        // Without configured identities the data plane is open
+        if self.policies.is_empty() {
+            return Ok(request);
+        }
+
+        let identity = token
+            .as_deref()
+            .and_then(|token| self.policies.resolve(token))
+            .ok_or_else(|| with_request_id(Status::unauthenticated("Missing or invalid bearer token")))?;
+
+        tracing::debug!(identity = %identity.name, "Request authenticated");
+        request.extensions_mut().insert(identity);
+        Ok(request)
+    }
+}
+
+/// Replace `:name` placeholders outside string literals with SQL literals
+fn render_filter(filter: &str, params: &BTreeMap<String, toml::Value>) -> Result<String, String> {
+    check_clause(filter)?;
+
+    let mut rendered = String::new();
+    let mut chars = filter.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            in_string = !in_string;
+        }
+        if c != ':' || in_string || !chars.peek().is_some_and(|n| n.is_ascii_alphabetic() || *n == '_') {
+            rendered.push(c);
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(n) = chars.peek().filter(|n| n.is_ascii_alphanumeric() || **n == '_') {
+            name.push(*n);
+            chars.next();
+        }
+        let value = params.get(&name).ok_or_else(|| format!("unknown parameter ':{}'", name))?;
+        rendered.push_str(&match value {
+            toml::Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Float(f) => f.to_string(),
+            toml::Value::Boolean(b) => (*b as i32).to_string(),
+            _ => return Err(format!("parameter ':{}' must be a string, number or boolean", name)),
+        });
+    }
+    Ok(rendered)
+}
+
+/// Check that a SQL condition is a single self-contained expression: balanced
+/// parentheses and quotes, no statement separators and no comments
+fn check_clause(clause: &str) -> Result<(), String> {
+    let mut depth = 0i32;
+    let mut quote: Option<char> = None;
+    let mut chars = clause.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                let closing = if q == '[' { ']' } else { q };
+                if c == closing {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '\'' | '"' | '`' | '[' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth < 0 {
+                        return Err("unbalanced parentheses".to_string());
+                    }
+                }
+                ';' => return Err("multiple statements are not allowed".to_string()),
+                '-' if chars.peek() == Some(&'-') => return Err("comments are not allowed".to_string()),
+                '/' if chars.peek() == Some(&'*') => return Err("comments are not allowed".to_string()),
+                _ => {}
+            },
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_string());
+    }
+    if depth != 0 {
+        return Err("unbalanced parentheses".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> Arc<Identity> {
+        let config = IdentityConfig {
+            name: "acme".to_string(),
+            token: "secret".to_string(),
+            params: BTreeMap::from([("tenant".to_string(), toml::Value::String("ac'me".to_string()))]),
+            filters: BTreeMap::from([("Orders".to_string(), "tenant_id = :tenant AND note != ':x'".to_string())]),
+            databases: None,
+        };
+        RowPolicies::from_config(&[config]).unwrap().resolve("secret").unwrap()
+    }
+
+    #[test]
+    fn test_render_filter() {
+        let identity = identity();
+        assert_eq!(identity.filter("ORDERS"), Some("tenant_id = 'ac''me' AND note != ':x'"));
+        assert_eq!(identity.filter("customers"), None);
+
+        let params = BTreeMap::new();
+        assert!(render_filter("tenant_id = :missing", &params).unwrap_err().contains("unknown parameter"));
+    }
+
+    #[test]
+    fn test_restrict_where() {
+        let identity = identity();
+        assert_eq!(
+            identity.restrict_where("orders", "id = 1").unwrap(),
+            "(id = 1) AND (tenant_id = 'ac''me' AND note != ':x')"
+        );
+        assert_eq!(identity.restrict_where("customers", "1) OR (1").unwrap(), "1) OR (1");
+
+        assert!(identity.restrict_where("orders", "1) OR (1").is_err());
+        assert!(identity.restrict_where("orders", "1 --").is_err());
+        assert!(identity.restrict_where("orders", "name = ')'").is_ok());
+
+        // Quoted or qualified names would reach the SQL without the filter
+        assert_eq!(identity.filter("main.\"Orders\""), identity.filter("orders"));
+        for table in ["\"orders\"", "main.orders", "[orders]", " orders", "orders/**/"] {
+            assert!(matches!(identity.restrict_where(table, "1"), Err(PolicyError::Denied(_))), "{}", table);
+            assert!(matches!(identity.check_unrestricted(table, "GetRow"), Err(PolicyError::Denied(_))), "{}", table);
+        }
+        assert!(identity.check_unrestricted("customers", "GetRow").is_ok());
+
+        // Partitions and index tables hold the rows without the filter
+        for table in ["orders_2024", "orders_2024_06", "orders_2024_06_01", "orders_fts", "orders__history"] {
+            assert!(matches!(identity.restrict_where(table, "1"), Err(PolicyError::Denied(_))), "{}", table);
+            assert!(matches!(identity.check_unrestricted(table, "Sample"), Err(PolicyError::Denied(_))), "{}", table);
+        }
+        assert!(identity.check_unrestricted("orders_archive", "Sample").is_ok());
+        assert!(identity.check_unrestricted("orders_2024_6", "Sample").is_ok());
+        assert!(matches!(identity.check_unrestricted("orders", "GetRow"), Err(PolicyError::Denied(_))));
+    }
+
+    #[test]
+    fn test_restrict_query() {
+        let identity = identity();
+        assert_eq!(identity.restrict_query("SELECT * FROM customers").unwrap(), "SELECT * FROM customers");
+        assert_eq!(
+            identity.restrict_query("SELECT * FROM orders o JOIN customers c ON o.cid = c.id").unwrap(),
+            "WITH \"orders\" AS (SELECT * FROM main.\"orders\" WHERE (tenant_id = 'ac''me' AND note != ':x')) \
+             SELECT * FROM orders o JOIN customers c ON o.cid = c.id"
+        );
+        assert!(identity
+            .restrict_query("with recursive n(x) AS (SELECT 1) SELECT * FROM n, orders")
+            .unwrap()
+            .starts_with("WITH RECURSIVE \"orders\" AS (SELECT * FROM main.\"orders\" WHERE (tenant_id"));
+
+        let denied = |sql: &str| matches!(identity.restrict_query(sql), Err(PolicyError::Denied(_)));
+        assert!(denied("DELETE FROM orders"));
+        assert!(denied("SELECT * FROM main.orders"));
+        assert!(denied("SELECT * FROM main. orders"));
+        assert!(denied("SELECT * FROM main .orders"));
+        assert!(denied("SELECT * FROM main/**/.orders"));
+        assert!(denied("SELECT * FROM \"main\".\"ORDERS\""));
+        assert!(denied("SELECT * FROM main.--\n[orders]"));
+        assert!(denied("SELECT * FROM orders_fts"));
+        assert!(denied("SELECT * FROM orders__history"));
+        assert!(denied("SELECT * FROM orders_2024_06"));
+        assert!(denied("SELECT o.* FROM customers c JOIN Orders_2024_06_01 o ON o.cid = c.id"));
+        assert!(denied("SELECT * FROM orders AS OF '2024-06-01'"));
+
+        // Comments and quoting do not hide the table from the filter
+        let restricted = |sql: &str| identity.restrict_query(sql).unwrap().starts_with("WITH \"orders\" AS");
+        assert!(restricted("SELECT * FROM orders/**/"));
+        assert!(restricted("SELECT * FROM [Orders] WHERE orders.id = 1"));
+        assert!(restricted("SELECT * FROM \"orders\""));
+        assert!(!restricted("SELECT 'orders', :orders FROM customers -- orders"));
+    }
+
+    #[test]
+    fn test_materialized_views_over_restricted_tables() {
+        let identity = identity();
+        let view = |name: &str, query: &str| MaterializedView {
+            name: name.to_string(),
+            query: query.to_string(),
+            refresh_interval_seconds: 0,
+            last_refreshed: None,
+        };
+        let views = [
+            view("daily_totals", "SELECT day, SUM(total) FROM Orders GROUP BY day"),
+            view("weekly_totals", "SELECT week, SUM(total) FROM daily_totals GROUP BY week"),
+            view("customer_names", "SELECT name FROM customers"),
+        ];
+
+        let denied = |sql: &str| matches!(identity.check_materialized_views(sql, &views), Err(PolicyError::Denied(_)));
+        assert!(denied("SELECT * FROM daily_totals"));
+        assert!(denied("SELECT * FROM weekly_totals"));
+        assert!(denied("daily_totals"));
+        assert!(!denied("SELECT * FROM customer_names"));
+        assert!(!denied("SELECT 'daily_totals' FROM customers"));
+    }
+
+    #[test]
+    fn test_admin_token() {
+        let config = IdentityConfig {
+            name: "acme".to_string(),
+            token: "secret".to_string(),
+            params: BTreeMap::new(),
+            filters: BTreeMap::new(),
+            databases: None,
+        };
+        let policies = RowPolicies::from_config(&[config]).unwrap();
+        assert!(policies.admin_access(Some("secret")).is_none());
+        assert!(policies.with_admin_token("secret").unwrap_err().contains("acme"));
+
+        let policies = RowPolicies::default().with_admin_token("root").unwrap();
+        assert!(policies.is_empty());
+        assert_eq!(policies.admin_access(Some("root")), Some(AdminAccess::Granted));
+        assert_eq!(policies.admin_access(Some("secret")), Some(AdminAccess::Denied));
+        assert_eq!(policies.admin_access(None), Some(AdminAccess::Denied));
+        assert!(policies.resolve("root").unwrap().filter("orders").is_none());
+
+        let mut request = Request::new(());
+        request.extensions_mut().insert(AdminAccess::Denied);
+        assert!(matches!(AdminAccess::check(&request, "AddDatabase"), Err(PolicyError::Denied(_))));
+        assert!(AdminAccess::check(&Request::new(()), "AddDatabase").is_ok());
+    }
+
+    #[test]
+    fn test_database_scope() {
+        let config = |name: &str, token: &str, databases: Option<Vec<&str>>| IdentityConfig {
+            name: name.to_string(),
+            token: token.to_string(),
+            params: BTreeMap::new(),
+            filters: BTreeMap::new(),
+            databases: databases.map(|d| d.into_iter().map(String::from).collect()),
+        };
+        let policies = RowPolicies::from_config(&[
+            config("reporting", "r", Some(vec!["analytics", "default"])),
+            config("ops", "o", None),
+        ])
+        .unwrap()
+        .with_admin_token("root")
+        .unwrap();
+
+        let reporting = policies.resolve("r").unwrap();
+        assert!(reporting.check_database("analytics").is_ok());
+        assert!(reporting.can_access("default"));
+        assert!(matches!(reporting.check_database("billing"), Err(PolicyError::Denied(_))));
+        assert!(reporting.check_all_databases("AddJob").is_err());
+        assert!(policies.resolve("o").unwrap().can_access("billing"));
+        assert!(policies.resolve("root").unwrap().can_access("billing"));
+
+        assert!(RowPolicies::from_config(&[config("none", "n", Some(vec![]))]).unwrap_err().contains("empty"));
+    }
+}
@@ -0,0 +1,192 @@
+//! Request-ID propagation for every RPC.
+//!
+//! Each request carries an `x-request-id`: the client's value is accepted when
+//! present, otherwise one is generated. The ID is attached to the tracing span
+//! the handler runs in, appended to error messages, and echoed back in the
+//! response headers so client and server logs can be correlated.
+//!
+//! The database an RPC resolves to is remembered for the rest of the call, so
+//! the errors and writes of its handler can be counted towards it.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tonic::codegen::http::{self, HeaderValue};
+use tonic::Status;
+use tower::{Layer, Service};
+use tracing::Instrument;
+
+/// Metadata key carrying the request ID
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request ID that is accepted as-is
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+    static REQUEST_DATABASE: RefCell<Option<String>>;
+}
+
+/// The request ID of the RPC currently being handled, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Remember the database the RPC currently being handled resolved to
+pub fn set_request_database(name: &str) {
+    let _ = REQUEST_DATABASE.try_with(|database| *database.borrow_mut() = Some(name.to_string()));
+}
+
+/// The database the RPC currently being handled resolved to, once it has
+pub fn current_request_database() -> Option<String> {
+    REQUEST_DATABASE.try_with(|database| database.borrow().clone()).ok().flatten()
+}
+
+/// Append the current request ID to an error status so clients can quote it,
+/// keeping the status's details and metadata
+pub fn with_request_id(status: Status) -> Status {
+    match current_request_id() {
+        Some(id) => Status::with_details_and_metadata(
+            status.code(),
+            format!("{} (request_id: {})", status.message(), id),
+            status.details().to_vec().into(),
+            status.metadata().clone(),
+        ),
+        None => status,
+    }
+}
+
+/// Use the client's request ID if it is reasonable, otherwise generate one
+fn resolve_request_id(headers: &http::HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Tower layer that assigns request IDs (see module docs)
+#[derive(Debug, Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RequestIdService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let request_id = resolve_request_id(request.headers());
+        let header_value = HeaderValue::from_str(&request_id)
+            .unwrap_or_else(|_| HeaderValue::from_static("invalid"));
+
+        // Make the ID visible to handlers through the request metadata
+        request.headers_mut().insert(REQUEST_ID_HEADER, header_value.clone());
+
+        let span = tracing::info_span!(
+            "rpc",
+            method = %request.uri().path(),
+            request_id = %request_id,
+            database = tracing::field::Empty,
+        );
+
+        // The service that was polled ready must be the one that is called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(
+            REQUEST_ID
+                .scope(
+                    request_id,
+                    REQUEST_DATABASE.scope(RefCell::new(None), async move {
+                        let mut response = inner.call(request).await?;
+                        response.headers_mut().insert(REQUEST_ID_HEADER, header_value);
+                        Ok(response)
+                    }),
+                )
+                .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_request_id_accepts_client_value() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("client-abc-123"));
+        assert_eq!(resolve_request_id(&headers), "client-abc-123");
+    }
+
+    #[test]
+    fn test_resolve_request_id_generates_when_missing_or_oversized() {
+        let generated = resolve_request_id(&http::HeaderMap::new());
+        assert!(uuid::Uuid::parse_str(&generated).is_ok());
+
+        let mut headers = http::HeaderMap::new();
+        let long_id = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(&long_id).unwrap());
+        assert_ne!(resolve_request_id(&headers), long_id);
+    }
+
+    #[tokio::test]
+    async fn test_with_request_id_appends_current_id() {
+        let status = REQUEST_ID
+            .scope("req-1".to_string(), async { with_request_id(Status::not_found("missing")) })
+            .await;
+        assert_eq!(status.message(), "missing (request_id: req-1)");
+
+        // Details and metadata, such as executed statements, are kept
+        let mut original = Status::with_details(tonic::Code::Aborted, "conflict", vec![1, 2, 3].into());
+        original.metadata_mut().insert("x-retry-after", "5".parse().unwrap());
+        let status = REQUEST_ID.scope("req-2".to_string(), async { with_request_id(original) }).await;
+        assert_eq!(status.details(), &[1, 2, 3]);
+        assert_eq!(status.metadata().get("x-retry-after").unwrap(), "5");
+
+        // Outside an RPC the status is left untouched
+        assert_eq!(with_request_id(Status::not_found("missing")).message(), "missing");
+    }
+
+    #[tokio::test]
+    async fn test_request_database() {
+        let database = REQUEST_DATABASE
+            .scope(RefCell::new(None), async {
+                assert_eq!(current_request_database(), None);
+                set_request_database("shop");
+                current_request_database()
+            })
+            .await;
+        assert_eq!(database.as_deref(), Some("shop"));
+
+        // Outside an RPC there is nothing to remember it in
+        set_request_database("shop");
+        assert_eq!(current_request_database(), None);
+    }
+}
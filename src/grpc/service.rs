@@ -1,35 +1,218 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, RwLock};
 use tokio_stream::{Stream, StreamExt};
+use tonic::metadata::MetadataValue;
 use tonic::{Request, Response, Status};
+use tracing::{debug, info};
+use prost::Message;
 
-use crate::db::{Database, DatabaseError, DatabaseManager};
+use crate::db::advisor;
+use crate::db::history;
+use crate::db::cache::{dependent_tables, has_limit, is_cacheable, referenced_tables, CacheKey, SchemaObject};
+use crate::db::diff::{self, RowChange};
+use crate::db::encryption::column_sources;
+use crate::db::scheduler::JobScheduler;
+use crate::db::params;
+use crate::db::secrets;
+use crate::db::sql_log::{self, ExecutedStatement};
+use crate::db::traits::{BoundingBox, ColumnType, DbValue, DiskUsage, Job, KeepRow, OnConflict, QueryResult, SavedQuery, StreamedQueryResult, WriteOp};
+use crate::db::options::{runtime_pragma, runtime_pragma_value};
+use crate::db::{ColumnCipher, Database, DatabaseError, DatabaseManager, DatabaseOptions, QueryCache, SqliteDatabase};
+use crate::grpc::activity::Activity;
 use crate::grpc::conversions::*;
+use crate::grpc::policy::{AdminAccess, Identity, PolicyError};
+use crate::grpc::request_id::{current_request_database, current_request_id, set_request_database, with_request_id};
+use crate::grpc::session::{Sessions, SESSION_IDLE_TIMEOUT};
 use crate::proto::data_sink_server::DataSink;
 use crate::proto::admin::{
     CreateTableRequest, CreateTableResponse, DropTableRequest, DropTableResponse,
-    ServerStatusRequest, ServerStatusResponse, DatabaseStatus,
-    AddDatabaseRequest, AddDatabaseResponse,
+    TruncateTableRequest, TruncateTableResponse, CreateSearchIndexRequest, CreateSearchIndexResponse,
+    CreateSpatialIndexRequest, CreateSpatialIndexResponse,
+    ServerStatusRequest, ServerStatusResponse, DatabaseStatus, DatabaseInfoRequest, DatabaseInfoResponse,
+    DatabaseStatsRequest, DatabaseStatsResponse, TableStats as ProtoTableStats,
+    GetPragmaRequest, GetPragmaResponse, SetPragmaRequest, SetPragmaResponse, DiskUsage as ProtoDiskUsage, SlowQuery as ProtoSlowQuery,
+    AdviseIndexesRequest, AdviseIndexesResponse, IndexSuggestion,
+    ListChangesRequest, ListChangesResponse, UndoChangesRequest, UndoChangesResponse,
+    AddDatabaseRequest, AddDatabaseResponse, RemoveDatabaseRequest, RemoveDatabaseResponse, ProvisionDatabaseRequest, ProvisionDatabaseResponse, provision_database_request,
+    CloneDatabaseRequest, CloneDatabaseResponse, CopyTableRequest, CopyTableResponse,
+    DiffTableRequest, DiffTableResponse, RowChange as ProtoRowChange, RowDifference, Metric, MetricsRequest, MetricsResponse, PingRequest, PingResponse, ShutdownRequest, ShutdownResponse,
+    RefreshMaterializedViewRequest, RefreshMaterializedViewResponse,
+    CheckForeignKeysRequest, CheckForeignKeysResponse, ForeignKeyViolation as ProtoForeignKeyViolation,
+    SaveQueryRequest, SaveQueryResponse, RemoveSavedQueryRequest, RemoveSavedQueryResponse,
+    ListSavedQueriesRequest, ListSavedQueriesResponse, SavedQuery as ProtoSavedQuery,
+    AddJobRequest, AddJobResponse, RemoveJobRequest, RemoveJobResponse, ListJobsRequest, ListJobsResponse,
+    ListJobRunsRequest, ListJobRunsResponse, Job as ProtoJob, JobRun as ProtoJobRun,
 };
 use crate::proto::crud::{
     BatchInsertRequest, BatchInsertResponse, DeleteRequest, DeleteResponse,
-    InsertRequest, InsertResponse, QueryRequest, QueryResponse, ResultSet,
-    UpdateRequest, UpdateResponse, query_response,
+    GetRowRequest, GetRowResponse, InsertRequest, InsertResponse, QueryRequest, QueryResponse, QuerySummary, ResultSet,
+    SearchMatch, SearchRequest, SearchResponse, SpatialQueryRequest, SpatialQueryResponse,
+    SampleRequest, SampleResponse, OpenSessionRequest, OpenSessionResponse, CloseSessionRequest, CloseSessionResponse, DedupeKeep, DedupeRequest, DedupeResponse, UpdateRequest, UpdateResponse, RunSavedQueryRequest, TransactionRequest, TransactionResponse, WriteResult,
+    query_response, write_operation,
 };
-use crate::proto::common::{Column as ProtoColumn, Error, Row};
+use crate::proto::common::{Column as ProtoColumn, Row};
+use crate::schema::{apply, parser, Schema};
 
 pub struct DataSinkService {
     db_manager: Arc<DatabaseManager>,
     start_time: Instant,
+    query_cache: Option<Arc<QueryCache>>,
+    cipher: Option<Arc<ColumnCipher>>,
+    scheduler: Option<Arc<JobScheduler>>,
+    activity: Arc<Activity>,
+    query_batch_rows: usize,
+    max_query_rows: Option<usize>,
+    default_query_limit: Option<usize>,
+    /// Where ProvisionDatabase creates database files
+    data_dir: PathBuf,
+    /// Schema templates ProvisionDatabase applies by name
+    schema_dir: Option<PathBuf>,
+    /// Client sessions with a connection of their own (OpenSession)
+    sessions: Sessions,
+    /// Notified when a client asks the server to stop (Shutdown)
+    shutdown: Arc<Notify>,
 }
 
+/// Request metadata asking Insert, Update, Delete and CreateTable to report
+/// the statements they executed
+pub const SHOW_SQL_HEADER: &str = "x-datasink-show-sql";
+
+/// Request metadata choosing where a read goes: "strong" reads from the
+/// database itself, "eventual" (the default) may read from one of its replicas
+pub const CONSISTENCY_HEADER: &str = "x-datasink-consistency";
+
+/// Response (or error) metadata carrying one executed statement per entry
+pub const EXECUTED_SQL_HEADER: &str = "x-datasink-sql-bin";
+
+/// Rows sent per streamed QueryResponse unless configured otherwise
+pub const DEFAULT_QUERY_BATCH_ROWS: usize = 500;
+
 impl DataSinkService {
     pub fn new_with_manager(db_manager: Arc<DatabaseManager>) -> Self {
         Self {
             db_manager,
             start_time: Instant::now(),
+            query_cache: None,
+            cipher: None,
+            scheduler: None,
+            activity: Arc::new(Activity::default()),
+            query_batch_rows: DEFAULT_QUERY_BATCH_ROWS,
+            max_query_rows: None,
+            default_query_limit: None,
+            data_dir: PathBuf::from("."),
+            schema_dir: None,
+            sessions: Sessions::default(),
+            shutdown: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Completes when a client has asked the server to stop
+    pub fn shutdown_signal(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let shutdown = self.shutdown.clone();
+        async move { shutdown.notified().await }
+    }
+
+    /// Serve repeated SELECTs from the given result cache
+    pub fn with_query_cache(mut self, cache: Arc<QueryCache>) -> Self {
+        self.query_cache = Some(cache);
+        self
+    }
+
+    /// Encrypt and decrypt the values of columns marked as encrypted with this key
+    pub fn with_cipher(mut self, cipher: Arc<ColumnCipher>) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Manage scheduled jobs through this scheduler
+    pub fn with_scheduler(mut self, scheduler: Arc<JobScheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Report queries that take at least this long as slow
+    pub fn with_slow_query_threshold(mut self, threshold: Duration) -> Self {
+        self.activity = Arc::new(Activity::new(threshold));
+        self
+    }
+
+    /// Send query results in messages of up to this many rows
+    pub fn with_query_batch_rows(mut self, rows: usize) -> Self {
+        self.query_batch_rows = rows.max(1);
+        self
+    }
+
+    /// Return at most this many rows per query, marking longer results truncated
+    pub fn with_max_query_rows(mut self, rows: usize) -> Self {
+        self.max_query_rows = Some(rows);
+        self
+    }
+
+    /// Return at most this many rows for a SELECT without a LIMIT clause
+    pub fn with_default_query_limit(mut self, rows: usize) -> Self {
+        self.default_query_limit = Some(rows);
+        self
+    }
+
+    /// Create provisioned databases' files in this directory
+    pub fn with_data_dir(mut self, dir: PathBuf) -> Self {
+        self.data_dir = dir;
+        self
+    }
+
+    /// Let ProvisionDatabase apply the `<name>.toml` schemas in this directory
+    pub fn with_schema_dir(mut self, dir: PathBuf) -> Self {
+        self.schema_dir = Some(dir);
+        self
+    }
+
+    /// The file for a database created by ProvisionDatabase or CloneDatabase,
+    /// checking that neither the name nor the file is taken
+    async fn new_database_path(&self, name: &str) -> Result<PathBuf, Status> {
+        if !is_plain_name(name) {
+            return Err(Status::invalid_argument(format!(
+                "Invalid database name '{}': use letters, digits, '_' and '-'",
+                name
+            )));
+        }
+        if self.db_manager.database_info(name).await.is_some() {
+            return Err(Status::already_exists(format!("Database '{}' already exists", name)));
+        }
+        let path = self.data_dir.join(format!("{}.db", name));
+        if path.exists() {
+            return Err(Status::already_exists(format!("Database file {} already exists", path.display())));
+        }
+        std::fs::create_dir_all(&self.data_dir)
+            .map_err(|e| Status::internal(format!("Failed to create {}: {}", self.data_dir.display(), e)))?;
+        Ok(path)
+    }
+
+    /// The schema a ProvisionDatabase request names or uploads, and its checksum
+    async fn provision_schema(&self, schema: Option<provision_database_request::Schema>) -> Result<(Schema, String), Status> {
+        match schema {
+            Some(provision_database_request::Schema::Template(template)) => {
+                let dir = self.schema_dir.as_ref().ok_or_else(|| {
+                    Status::failed_precondition("The server has no schema_dir; upload the schema instead")
+                })?;
+                if !is_plain_name(&template) {
+                    return Err(Status::invalid_argument(format!("Invalid schema template name '{}'", template)));
+                }
+                let path = dir.join(format!("{}.toml", template));
+                if !path.exists() {
+                    return Err(Status::not_found(format!("Schema template '{}' not found", template)));
+                }
+                parser::load_schema_with_checksum(&path)
+                    .await
+                    .map_err(|e| Status::invalid_argument(format!("Schema template '{}': {}", template, e)))
+            }
+            Some(provision_database_request::Schema::SchemaToml(content)) => {
+                parser::parse_schema(&content).map_err(|e| Status::invalid_argument(format!("Invalid schema: {}", e)))
+            }
+            None => Err(Status::invalid_argument("A schema template or schema_toml is required")),
         }
     }
 
@@ -43,19 +226,236 @@ impl DataSinkService {
         Self::new_with_manager(manager)
     }
 
-    async fn get_database(&self, database_name: Option<&str>) -> Result<Arc<RwLock<Box<dyn Database>>>, Status> {
+    /// Look up a request's database, counting the request towards its activity
+    async fn get_database(
+        &self,
+        identity: Option<&Identity>,
+        database_name: Option<&str>,
+    ) -> Result<Arc<RwLock<Box<dyn Database>>>, Status> {
+        let db = self.lookup_database(identity, database_name).await?;
+        if let Some(name) = self.db_manager.resolve_name(database_name).await {
+            self.activity.record_request(&name);
+            set_request_database(&name);
+        }
+        Ok(db)
+    }
+
+    /// Look up the database a read goes to: one of its replicas, if it has
+    /// any and the request allows it. Also returns whether it is a replica.
+    async fn get_read_database(
+        &self,
+        identity: Option<&Identity>,
+        database_name: Option<&str>,
+        consistency: Consistency,
+    ) -> Result<(Arc<RwLock<Box<dyn Database>>>, bool), Status> {
+        let db = self.get_database(identity, database_name).await?;
+        if consistency == Consistency::Strong {
+            return Ok((db, false));
+        }
+        let name = self.db_manager.resolve_name(database_name).await.unwrap_or_default();
+        match self.db_manager.get_replica(&name).await {
+            Some(replica) => Ok((replica, true)),
+            None => Ok((db, false)),
+        }
+    }
+
+    /// Look up a request's database, refusing databases outside the identity's scope
+    async fn lookup_database(
+        &self,
+        identity: Option<&Identity>,
+        database_name: Option<&str>,
+    ) -> Result<Arc<RwLock<Box<dyn Database>>>, Status> {
+        tracing::Span::current().record("database", database_name.unwrap_or("default"));
+
+        // Checked before the lookup so a scoped caller cannot probe for other databases
+        if let Some(identity) = identity {
+            let name = match database_name {
+                Some(name) if !name.is_empty() => Some(name.to_string()),
+                _ => self.db_manager.resolve_name(None).await,
+            };
+            identity.check_database(name.as_deref().unwrap_or_default())?;
+        }
+
         self.db_manager
             .get_database_or_default(database_name)
             .await
             .ok_or_else(|| {
-                match database_name {
+                with_request_id(match database_name {
                     Some(name) => Status::not_found(format!("Database '{}' not found", name)),
                     None => Status::unavailable("No database connections available"),
+                })
+            })
+    }
+
+    /// Most rows a query may return: the database's max_query_rows (or the
+    /// server's), and for a SELECT without a LIMIT clause at most the default
+    /// limit
+    async fn row_limit(&self, database: &str, sql: &str) -> Option<usize> {
+        let options = self.db_manager.database_info(database).await.map(|info| info.options).unwrap_or_default();
+        let max_rows = options.max_query_rows.or(self.max_query_rows);
+        let default_limit = options
+            .default_query_limit
+            .or(self.default_query_limit)
+            .filter(|_| is_cacheable(sql) && !has_limit(sql));
+        match (max_rows, default_limit) {
+            (Some(max_rows), Some(default_limit)) => Some(max_rows.min(default_limit)),
+            (max_rows, default_limit) => max_rows.or(default_limit),
+        }
+    }
+
+    /// Drop cached query results after a write: those reading from `table`,
+    /// or every result for the database after a schema change (`None`)
+    async fn invalidate_cache(&self, database: &str, table: Option<&str>) {
+        if let Some(cache) = &self.query_cache {
+            if let Some(name) = self.db_manager.resolve_name(Some(database)).await {
+                match table {
+                    Some(table) => cache.invalidate_table(&name, table),
+                    None => cache.invalidate_database(&name),
+                }
+            }
+        }
+    }
+
+    /// Encrypt the values written to a table's encrypted columns
+    async fn encrypt_rows(
+        &self,
+        db: &dyn Database,
+        table: &str,
+        rows: &mut [HashMap<String, DbValue>],
+    ) -> Result<(), Status> {
+        let encrypted = db.encrypted_columns(table).await.map_err(|e| self.db_error_to_status(e))?;
+        if encrypted.is_empty() {
+            return Ok(());
+        }
+
+        for row in rows.iter_mut() {
+            for (column, value) in row.iter_mut() {
+                // SQLite column names are case-insensitive
+                if matches!(value, DbValue::Null) || !encrypted.iter().any(|c| c.eq_ignore_ascii_case(column)) {
+                    continue;
                 }
+                let cipher = self.cipher.as_ref().ok_or_else(|| {
+                    with_request_id(Status::failed_precondition(format!(
+                        "Column '{}' of '{}' is encrypted, but the server has no encryption key",
+                        column, table
+                    )))
+                })?;
+                *value = cipher.encrypt(value, table, column).map_err(|e| self.db_error_to_status(e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The encrypted columns of those of `tables` that have any, for
+    /// `column_sources`; nothing without a key, since nothing is decrypted then
+    async fn encrypted_tables(&self, db: &dyn Database, tables: &[String]) -> Result<Vec<(String, Vec<String>)>, Status> {
+        let mut encrypted = Vec::new();
+        if self.cipher.is_none() {
+            return Ok(encrypted);
+        }
+        for table in tables {
+            let columns = db.encrypted_columns(table).await.map_err(|e| self.db_error_to_status(e))?;
+            if !columns.is_empty() {
+                encrypted.push((table.clone(), columns));
+            }
+        }
+        Ok(encrypted)
+    }
+
+    /// Refuse reading a materialized view filled from a table the identity's
+    /// filters restrict; `sql` is a statement or a table name
+    async fn check_materialized_views(&self, identity: Option<&Identity>, db: &dyn Database, sql: &str) -> Result<(), Status> {
+        let Some(identity) = identity.filter(|identity| identity.has_filters()) else {
+            return Ok(());
+        };
+        let views = db.materialized_views().await.map_err(|e| self.db_error_to_status(e))?;
+        Ok(identity.check_materialized_views(sql, &views)?)
+    }
+
+    /// The database's views and triggers, which decide the tables a cached
+    /// result depends on (see `dependent_tables`)
+    async fn schema_objects(&self, db: &dyn Database) -> Result<Vec<SchemaObject>, DatabaseError> {
+        let result = db
+            .query("SELECT type, tbl_name, sql FROM sqlite_master WHERE type IN ('view', 'trigger')", HashMap::new())
+            .await?;
+        Ok(result
+            .rows
+            .into_iter()
+            .filter_map(|row| match row.as_slice() {
+                [DbValue::Text(kind), DbValue::Text(table), DbValue::Text(sql)] => Some(SchemaObject {
+                    kind: kind.clone(),
+                    table: table.clone(),
+                    sql: sql.clone(),
+                }),
+                _ => None,
             })
+            .collect())
+    }
+
+    /// Decrypt a row being returned, given where each of its columns was read
+    /// from; without a key, ciphertext is returned as stored
+    fn decrypt_row(&self, values: Vec<DbValue>, sources: &[Vec<(String, String)>]) -> Result<Vec<DbValue>, DatabaseError> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt_row(values, sources),
+            None => Ok(values),
+        }
     }
 
-    fn db_error_to_status(err: DatabaseError) -> Status {
+    /// Jobs run arbitrary SQL, so callers with row policies cannot manage them
+    fn check_job_access<T>(request: &Request<T>, operation: &str) -> Result<(), PolicyError> {
+        match Self::identity(request) {
+            // Jobs may run against any database
+            Some(identity) => {
+                identity.check_no_filters(operation)?;
+                identity.check_all_databases(operation)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn scheduler(&self) -> Result<&JobScheduler, DatabaseError> {
+        self.scheduler
+            .as_deref()
+            .ok_or_else(|| DatabaseError::ConnectionError("Job scheduler is not running".to_string()))
+    }
+
+    /// The authenticated caller, when identities are configured
+    fn identity<T>(request: &Request<T>) -> Option<Arc<Identity>> {
+        request.extensions().get::<Arc<Identity>>().cloned()
+    }
+
+    /// The status for a failed database operation, carrying an encoded
+    /// common.Error with structured details (see `db_error_to_proto`).
+    /// Counts the error towards the request's database.
+    fn db_error_to_status(&self, err: DatabaseError) -> Status {
+        tracing::warn!(error = %err, "Request failed");
+        if let Some(database) = current_request_database() {
+            self.activity.record_error(&database);
+        }
+        let details = db_error_to_proto(&err, err.to_string()).encode_to_vec();
+        let status = Self::db_error_status(err);
+        with_request_id(Status::with_details(status.code(), status.message(), details.into()))
+    }
+
+    /// Count rows written by the current request towards its database
+    fn record_write(&self, rows: u64) {
+        if let Some(database) = current_request_database() {
+            self.activity.record_write(&database, rows);
+        }
+    }
+
+    fn db_error_status(err: DatabaseError) -> Status {
+        // A duplicate key is AlreadyExists, other constraints FailedPrecondition
+        if err.is_unique_violation() {
+            return Status::already_exists(err.to_string());
+        }
+        if err.is_constraint_violation() {
+            return Status::failed_precondition(err.to_string());
+        }
+        if err.is_invalid_statement() {
+            return Status::invalid_argument(err.to_string());
+        }
+
         match err {
             DatabaseError::TableAlreadyExists(table) => {
                 Status::already_exists(format!("Table '{}' already exists", table))
@@ -64,224 +464,1291 @@ impl DataSinkService {
                 Status::not_found(format!("Table '{}' not found", table))
             }
             DatabaseError::QueryError(msg) => Status::invalid_argument(msg),
+            DatabaseError::UnknownColumns { .. } => Status::invalid_argument(err.to_string()),
+            DatabaseError::TransactionError(msg) => Status::aborted(msg),
             DatabaseError::ConnectionError(msg) => Status::unavailable(msg),
+            DatabaseError::EncryptionError(msg) => Status::failed_precondition(msg),
             _ => Status::internal(err.to_string()),
         }
     }
 }
 
+/// Where a read may be served from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Consistency {
+    /// From the database itself, seeing every write made through it
+    Strong,
+    /// From a replica, which may lag behind
+    Eventual,
+}
+
+/// The consistency a request asks for in its metadata
+#[allow(clippy::result_large_err)]
+fn consistency<T>(request: &Request<T>) -> Result<Consistency, Status> {
+    let Some(value) = request.metadata().get(CONSISTENCY_HEADER) else {
+        return Ok(Consistency::Eventual);
+    };
+    match value.to_str().map(str::to_ascii_lowercase).as_deref() {
+        Ok("strong") => Ok(Consistency::Strong),
+        Ok("eventual") => Ok(Consistency::Eventual),
+        _ => Err(with_request_id(Status::invalid_argument(format!(
+            "{} must be 'strong' or 'eventual'",
+            CONSISTENCY_HEADER
+        )))),
+    }
+}
+
+/// Whether the client asked for the statements a request executes
+fn wants_sql<T>(request: &Request<T>) -> bool {
+    request.metadata().contains_key(SHOW_SQL_HEADER)
+}
+
+/// Report executed statements in the metadata of a response or error
+fn attach_sql<T>(result: &mut Result<Response<T>, Status>, statements: &[ExecutedStatement]) {
+    let metadata = match result {
+        Ok(response) => response.metadata_mut(),
+        Err(status) => status.metadata_mut(),
+    };
+    for statement in statements {
+        metadata.append_bin(EXECUTED_SQL_HEADER, MetadataValue::from_bytes(statement.to_string().as_bytes()));
+    }
+}
+
 #[tonic::async_trait]
 impl DataSink for DataSinkService {
     async fn create_table(
         &self,
         request: Request<CreateTableRequest>,
     ) -> Result<Response<CreateTableResponse>, Status> {
+        let show_sql = wants_sql(&request);
+        let identity = Self::identity(&request);
         let req = request.into_inner();
 
-        let columns: Vec<_> = req.columns.into_iter().map(proto_to_column_def).collect();
+        let columns = req
+            .columns
+            .into_iter()
+            .map(proto_to_column_def)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| self.db_error_to_status(e))?;
 
-        let db_arc = self.get_database(if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
         let db = db_arc.read().await;
-        match db.create_table(&req.table_name, columns).await {
-            Ok(_) => Ok(Response::new(CreateTableResponse {
-                success: true,
-                message: format!("Table '{}' created successfully", req.table_name),
-            })),
-            Err(e) => Err(Self::db_error_to_status(e)),
-        }
+        let unique: Vec<Vec<String>> = req.unique.into_iter().map(|constraint| constraint.columns).collect();
+        let (result, statements) =
+            sql_log::capture(show_sql, db.create_table_with_unique(&req.table_name, columns, &unique)).await;
+        let mut result = match result {
+            Ok(_) => {
+                // Schema changes also affect queries against sqlite_master
+                self.invalidate_cache(&req.database, None).await;
+                Ok(Response::new(CreateTableResponse {
+                    success: true,
+                    message: format!("Table '{}' created successfully", req.table_name),
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        };
+        attach_sql(&mut result, &statements);
+        result
     }
 
     async fn drop_table(
         &self,
         request: Request<DropTableRequest>,
     ) -> Result<Response<DropTableResponse>, Status> {
+        AdminAccess::check(&request, "DropTable")?;
+        let identity = Self::identity(&request);
         let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.table_name, "DropTable")?;
+        }
 
-        let db_arc = self.get_database(if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
         let db = db_arc.read().await;
         match db.drop_table(&req.table_name).await {
-            Ok(_) => Ok(Response::new(DropTableResponse {
-                success: true,
-                message: format!("Table '{}' dropped successfully", req.table_name),
-            })),
-            Err(e) => Err(Self::db_error_to_status(e)),
+            Ok(_) => {
+                self.invalidate_cache(&req.database, None).await;
+                Ok(Response::new(DropTableResponse {
+                    success: true,
+                    message: format!("Table '{}' dropped successfully", req.table_name),
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn truncate_table(
+        &self,
+        request: Request<TruncateTableRequest>,
+    ) -> Result<Response<TruncateTableResponse>, Status> {
+        AdminAccess::check(&request, "TruncateTable")?;
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.table_name, "TruncateTable")?;
+        }
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        match db.truncate_table(&req.table_name).await {
+            Ok(deleted) => {
+                info!(table = %req.table_name, deleted_rows = deleted, "Truncate completed");
+                self.record_write(deleted);
+                self.invalidate_cache(&req.database, Some(&req.table_name)).await;
+                Ok(Response::new(TruncateTableResponse {
+                    success: true,
+                    message: format!("Table '{}' truncated ({} rows deleted)", req.table_name, deleted),
+                    deleted_rows: deleted as i64,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn create_search_index(
+        &self,
+        request: Request<CreateSearchIndexRequest>,
+    ) -> Result<Response<CreateSearchIndexResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        match db.create_search_index(&req.table_name, &req.columns).await {
+            Ok(indexed) => {
+                info!(table = %req.table_name, indexed_rows = indexed, "Search index created");
+                // The index is a new table that cached schema queries don't know about
+                self.invalidate_cache(&req.database, None).await;
+                Ok(Response::new(CreateSearchIndexResponse {
+                    success: true,
+                    message: format!("Search index on '{}' created ({} rows indexed)", req.table_name, indexed),
+                    indexed_rows: indexed as i64,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn create_spatial_index(
+        &self,
+        request: Request<CreateSpatialIndexRequest>,
+    ) -> Result<Response<CreateSpatialIndexResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        match db.create_spatial_index(&req.table_name, &req.columns).await {
+            Ok(indexed) => {
+                info!(table = %req.table_name, indexed_rows = indexed, "Spatial index created");
+                self.invalidate_cache(&req.database, None).await;
+                Ok(Response::new(CreateSpatialIndexResponse {
+                    success: true,
+                    message: format!("Spatial index on '{}' created ({} rows indexed)", req.table_name, indexed),
+                    indexed_rows: indexed as i64,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn refresh_materialized_view(
+        &self,
+        request: Request<RefreshMaterializedViewRequest>,
+    ) -> Result<Response<RefreshMaterializedViewResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.name, "RefreshMaterializedView")?;
+        }
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        match db.refresh_materialized_view(&req.name).await {
+            Ok(rows) => {
+                info!(view = %req.name, rows, "Materialized view refreshed");
+                self.invalidate_cache(&req.database, Some(&req.name)).await;
+                Ok(Response::new(RefreshMaterializedViewResponse {
+                    success: true,
+                    message: format!("Materialized view '{}' refreshed ({} rows)", req.name, rows),
+                    row_count: rows as i64,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
         }
     }
 
+    async fn check_foreign_keys(
+        &self,
+        request: Request<CheckForeignKeysRequest>,
+    ) -> Result<Response<CheckForeignKeysResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            if req.table_name.is_empty() {
+                identity.check_no_filters("CheckForeignKeys")?;
+            } else {
+                identity.check_unrestricted(&req.table_name, "CheckForeignKeys")?;
+            }
+        }
+
+        let limit = if req.limit == 0 { 100 } else { req.limit };
+        let table_name = if req.table_name.is_empty() { None } else { Some(req.table_name.as_str()) };
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        let enforced = db.foreign_keys_enabled().await.map_err(|e| self.db_error_to_status(e))?;
+        // One extra row tells whether the list was cut short
+        let mut violations = db
+            .foreign_key_violations(table_name, limit.saturating_add(1))
+            .await
+            .map_err(|e| self.db_error_to_status(e))?;
+        let truncated = violations.len() > limit as usize;
+        violations.truncate(limit as usize);
+
+        Ok(Response::new(CheckForeignKeysResponse {
+            enforced,
+            violations: violations
+                .into_iter()
+                .map(|v| ProtoForeignKeyViolation {
+                    table_name: v.table,
+                    rowid: v.rowid.unwrap_or(0),
+                    parent_table: v.parent,
+                    constraint_index: v.constraint_index,
+                })
+                .collect(),
+            truncated,
+        }))
+    }
+
     async fn insert(
         &self,
         request: Request<InsertRequest>,
     ) -> Result<Response<InsertResponse>, Status> {
+        let show_sql = wants_sql(&request);
+        let identity = Self::identity(&request);
         let req = request.into_inner();
 
-        let values = proto_values_to_db_values(req.values);
+        let mut values = [proto_values_to_db_values(req.values)];
 
-        let db_arc = self.get_database(if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
         let db = db_arc.read().await;
-        match db.insert(&req.table_name, values).await {
-            Ok(id) => Ok(Response::new(InsertResponse {
-                success: true,
-                message: "Insert successful".to_string(),
-                inserted_id: id,
-            })),
-            Err(e) => Err(Self::db_error_to_status(e)),
-        }
+        self.encrypt_rows(&**db, &req.table_name, &mut values).await?;
+        let [values] = values;
+        let on_conflict = proto_to_on_conflict(req.on_conflict);
+        let (result, statements) =
+            sql_log::capture(show_sql, db.insert_with_conflict(&req.table_name, values, on_conflict)).await;
+        let mut result = match result {
+            Ok(Some(id)) => {
+                info!(table = %req.table_name, inserted_id = id, "Insert completed");
+                self.record_write(1);
+                self.invalidate_cache(&req.database, Some(&req.table_name)).await;
+                Ok(Response::new(InsertResponse {
+                    success: true,
+                    message: "Insert successful".to_string(),
+                    inserted_id: id,
+                    inserted: true,
+                }))
+            }
+            // Under UPDATE the conflicting row was updated in place
+            Ok(None) if on_conflict == OnConflict::Update => {
+                info!(table = %req.table_name, "Insert updated the conflicting row");
+                self.record_write(1);
+                self.invalidate_cache(&req.database, Some(&req.table_name)).await;
+                Ok(Response::new(InsertResponse {
+                    success: true,
+                    message: "Row already exists; updated it".to_string(),
+                    inserted_id: -1,
+                    inserted: false,
+                }))
+            }
+            Ok(None) => {
+                info!(table = %req.table_name, "Insert skipped on conflict");
+                Ok(Response::new(InsertResponse {
+                    success: true,
+                    message: "Row already exists; insert skipped".to_string(),
+                    inserted_id: -1,
+                    inserted: false,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        };
+        attach_sql(&mut result, &statements);
+        result
     }
 
     async fn update(
         &self,
         request: Request<UpdateRequest>,
     ) -> Result<Response<UpdateResponse>, Status> {
+        let identity = Self::identity(&request);
+        let show_sql = wants_sql(&request);
         let req = request.into_inner();
+        let where_clause = match &identity {
+            Some(identity) => identity.restrict_where(&req.table_name, &req.where_clause)?,
+            None => req.where_clause.clone(),
+        };
 
-        let values = proto_values_to_db_values(req.values);
+        let mut values = [proto_values_to_db_values(req.values)];
 
-        let db_arc = self.get_database(if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
         let db = db_arc.read().await;
-        match db.update(&req.table_name, values, &req.where_clause).await {
-            Ok(affected) => Ok(Response::new(UpdateResponse {
-                success: true,
-                message: format!("{} rows updated", affected),
-                affected_rows: affected as i64,
-            })),
-            Err(e) => Err(Self::db_error_to_status(e)),
-        }
+        self.check_materialized_views(identity.as_deref(), &**db, &req.table_name).await?;
+        self.encrypt_rows(&**db, &req.table_name, &mut values).await?;
+        let [values] = values;
+        let (result, statements) = sql_log::capture(show_sql, db.update(&req.table_name, values, &where_clause)).await;
+        let mut result = match result {
+            Ok(affected) => {
+                info!(table = %req.table_name, affected_rows = affected, "Update completed");
+                self.record_write(affected);
+                self.invalidate_cache(&req.database, Some(&req.table_name)).await;
+                Ok(Response::new(UpdateResponse {
+                    success: true,
+                    message: format!("{} rows updated", affected),
+                    affected_rows: affected as i64,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        };
+        attach_sql(&mut result, &statements);
+        result
     }
 
     async fn delete(
         &self,
         request: Request<DeleteRequest>,
     ) -> Result<Response<DeleteResponse>, Status> {
+        let identity = Self::identity(&request);
+        let show_sql = wants_sql(&request);
         let req = request.into_inner();
+        let where_clause = match &identity {
+            Some(identity) => identity.restrict_where(&req.table_name, &req.where_clause)?,
+            None => req.where_clause.clone(),
+        };
 
-        let db_arc = self.get_database(if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
         let db = db_arc.read().await;
-        match db.delete(&req.table_name, &req.where_clause).await {
-            Ok(affected) => Ok(Response::new(DeleteResponse {
-                success: true,
-                message: format!("{} rows deleted", affected),
-                affected_rows: affected as i64,
-            })),
-            Err(e) => Err(Self::db_error_to_status(e)),
-        }
+        self.check_materialized_views(identity.as_deref(), &**db, &req.table_name).await?;
+        let (result, statements) = sql_log::capture(show_sql, db.delete(&req.table_name, &where_clause)).await;
+        let mut result = match result {
+            Ok(affected) => {
+                info!(table = %req.table_name, affected_rows = affected, "Delete completed");
+                self.record_write(affected);
+                self.invalidate_cache(&req.database, Some(&req.table_name)).await;
+                Ok(Response::new(DeleteResponse {
+                    success: true,
+                    message: format!("{} rows deleted", affected),
+                    affected_rows: affected as i64,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        };
+        attach_sql(&mut result, &statements);
+        result
     }
 
     type QueryStream = Pin<Box<dyn Stream<Item = Result<QueryResponse, Status>> + Send>>;
+    type RunSavedQueryStream = Self::QueryStream;
 
     async fn query(
         &self,
         request: Request<QueryRequest>,
     ) -> Result<Response<Self::QueryStream>, Status> {
-        let req = request.into_inner();
+        let started = Instant::now();
+        let identity = Self::identity(&request);
+        let consistency = consistency(&request)?;
+        let session = self.sessions.for_request(&request, identity.as_ref().map(|i| i.name.as_str()))?;
+        let mut req = request.into_inner();
+        if let Some(identity) = &identity {
+            req.sql = identity.restrict_query(&req.sql)?;
+        }
 
         let params = proto_values_to_db_values(req.parameters);
 
-        let db_arc = self.get_database(if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        // A session's queries run on its own connection to its database
+        let (db_arc, from_replica) = if let Some(session) = &session {
+            if req.database.is_empty() {
+                req.database = session.database.clone();
+            } else if self.db_manager.resolve_name(Some(&req.database)).await.as_deref() != Some(&session.database) {
+                return Err(with_request_id(Status::invalid_argument(format!(
+                    "The session belongs to database '{}'",
+                    session.database
+                ))));
+            }
+            self.activity.record_request(&session.database);
+            set_request_database(&session.database);
+            (session.db.clone(), false)
+        } else {
+            // Only statements that cannot write may go to a replica
+            let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+            if is_cacheable(&req.sql) {
+                self.get_read_database(identity.as_deref(), database, consistency).await?
+            } else {
+                (self.get_database(identity.as_deref(), database).await?, false)
+            }
+        };
+        // Checked before the cache, which may hold the view's rows
+        self.check_materialized_views(identity.as_deref(), &**db_arc.read().await, &req.sql).await?;
+        // The stream is polled after this handler returns, outside the request scope
+        let request_id = current_request_id().unwrap_or_default();
+        let database = self.db_manager.resolve_name(Some(&req.database)).await.unwrap_or_default();
+        let row_limit = self.row_limit(&database, &req.sql).await;
+        self.activity.record_query(&database);
+
+        // Repeated SELECTs are served from the cache. Any other statement may
+        // write, so it drops the database's cached results once it finishes.
+        let mut cache_fill = None;
+        let mut invalidate_guard = None;
+        if let Some(cache) = &self.query_cache {
+            let database = database.clone();
+            // A session's TEMP tables can shadow the tables cached results read
+            if is_cacheable(&req.sql) && session.is_none() {
+                let key = CacheKey::new(&database, &req.sql, &params);
+                if let Some(result) = cache.get(&key) {
+                    return Ok(Response::new(cached_result_stream(
+                        result,
+                        self.query_batch_rows,
+                        row_limit,
+                        started,
+                    )));
+                }
+                // A replica may lag behind writes that already invalidated the cache
+                if !from_replica {
+                    let generation = cache.generation(&database);
+                    cache_fill = Some((cache.clone(), key, Vec::new(), generation));
+                }
+            } else if !is_cacheable(&req.sql) {
+                invalidate_guard = Some(InvalidateOnDrop { cache: cache.clone(), database });
+            }
+        }
+
+        let mut active_query = self.activity.start_query(&database, &req.sql, &request_id);
+
+        let plan_db = db_arc.clone();
         let db = db_arc.read().await;
+        let cipher = self.cipher.clone();
+        let encrypted = self.encrypted_tables(&**db, &referenced_tables(&req.sql)).await?;
+        if let Some((_, _, tables, _)) = &mut cache_fill {
+            match self.schema_objects(&**db).await {
+                Ok(objects) => *tables = dependent_tables(&req.sql, &objects),
+                // Without them the result cannot be invalidated reliably
+                Err(e) => {
+                    debug!(request_id = %request_id, "Not caching query result: {}", e);
+                    cache_fill = None;
+                }
+            }
+        }
+        let batch_rows = self.query_batch_rows;
+        let max_rows = row_limit.unwrap_or(usize::MAX);
+        let activity = self.activity.clone();
         match db.query_stream(&req.sql, params).await {
             Ok((columns, mut stream)) => {
-                let proto_columns: Vec<ProtoColumn> = columns
-                    .into_iter()
-                    .map(|(name, col_type)| ProtoColumn {
-                        name,
-                        r#type: column_type_to_proto(&col_type) as i32,
-                    })
-                    .collect();
+                let proto_columns = columns_to_proto(&columns);
+                let sources = column_sources(&columns, &encrypted);
+                // A write statement's single row carries the rows it affected
+                let write = !is_cacheable(&req.sql) && columns.first().is_some_and(|(name, _)| name == "affected_rows");
 
                 let response_stream = Box::pin(async_stream::stream! {
+                    let _invalidate_guard = invalidate_guard;
+
                     // Send column information in the first response
                     yield Ok(QueryResponse {
                         response: Some(query_response::Response::ResultSet(ResultSet {
-                            columns: proto_columns.clone(),
+                            columns: proto_columns,
                             rows: vec![],
                         })),
                     });
 
-                    // Stream rows
+                    // Stream rows in batches, keeping a copy for the cache unless the result grows too large
+                    let mut cached_rows = cache_fill.as_ref().map(|_| Vec::new());
+                    let mut batch = Vec::with_capacity(batch_rows);
+                    let (mut rows_returned, mut truncated, mut failed) = (0, false, false);
                     while let Some(result) = stream.next().await {
+                        // Decrypted before caching, so cache hits need no key work
+                        let result = match (&cipher, result) {
+                            (Some(cipher), Ok(values)) => cipher.decrypt_row(values, &sources),
+                            (_, result) => result,
+                        };
                         match result {
+                            Ok(_) if rows_returned == max_rows => {
+                                // A truncated result is never cached
+                                cached_rows = None;
+                                truncated = true;
+                                break;
+                            }
                             Ok(values) => {
-                                let proto_values = db_values_to_proto_values(values);
-                                yield Ok(QueryResponse {
-                                    response: Some(query_response::Response::ResultSet(ResultSet {
-                                        columns: vec![],
-                                        rows: vec![Row { values: proto_values }],
-                                    })),
-                                });
+                                rows_returned += 1;
+                                if let (true, Some(DbValue::Integer(affected))) = (write, values.first()) {
+                                    activity.record_write(&database, *affected as u64);
+                                }
+                                if let Some((cache, _, _, _)) = &cache_fill {
+                                    match &mut cached_rows {
+                                        Some(rows) if rows.len() < cache.max_rows() => rows.push(values.clone()),
+                                        _ => cached_rows = None,
+                                    }
+                                }
+                                batch.push(values);
+                                if batch.len() >= batch_rows {
+                                    yield Ok(rows_response(std::mem::take(&mut batch)));
+                                }
                             }
                             Err(e) => {
+                                activity.record_error(&database);
+                                cached_rows = None;
+                                failed = true;
+                                // Rows read before the error still reach the client
+                                if !batch.is_empty() {
+                                    yield Ok(rows_response(std::mem::take(&mut batch)));
+                                }
                                 yield Ok(QueryResponse {
-                                    response: Some(query_response::Response::Error(Error {
-                                        code: "QUERY_ERROR".to_string(),
-                                        message: e.to_string(),
-                                    })),
+                                    response: Some(query_response::Response::Error(db_error_to_proto(
+                                        &e,
+                                        format!("{} (request_id: {})", e, request_id),
+                                    ))),
                                 });
                                 break;
                             }
                         }
                     }
+                    if !batch.is_empty() {
+                        yield Ok(rows_response(batch));
+                    }
+                    if !failed {
+                        yield Ok(summary_response(rows_returned, started, truncated, false));
+                    }
+
+                    if let (Some((cache, key, tables, generation)), Some(rows)) = (cache_fill, cached_rows) {
+                        cache.insert(key, QueryResult { columns, rows }, tables, generation);
+                    }
+
+                    // Keep the plan the query ran with in its slow query record. The
+                    // row stream goes first so its connection is free for EXPLAIN.
+                    drop(stream);
+                    if active_query.stop() {
+                        match plan_db.read().await.query_plan(active_query.sql()).await {
+                            Ok(plan) => active_query.set_plan(plan),
+                            Err(e) => debug!(request_id = %request_id, "Could not capture plan of slow query: {}", e),
+                        }
+                    }
                 });
 
                 Ok(Response::new(response_stream))
             }
-            Err(e) => Err(Self::db_error_to_status(e)),
+            Err(e) => Err(self.db_error_to_status(e)),
         }
     }
 
-    async fn batch_insert(
+    async fn run_saved_query(
         &self,
-        request: Request<BatchInsertRequest>,
-    ) -> Result<Response<BatchInsertResponse>, Status> {
-        let req = request.into_inner();
+        request: Request<RunSavedQueryRequest>,
+    ) -> Result<Response<Self::RunSavedQueryStream>, Status> {
+        let identity = Self::identity(&request);
+        let (metadata, extensions, req) = request.into_parts();
 
-        let rows: Vec<_> = req
-            .rows
+        // Counted as a request by the Query call below
+        let db_arc = self.lookup_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let saved = db_arc
+            .read()
+            .await
+            .saved_query(&req.name)
+            .await
+            .map_err(|e| self.db_error_to_status(e))?
+            .ok_or_else(|| with_request_id(Status::not_found(format!("Saved query '{}' not found", req.name))))?;
+
+        // Parameters may be given with or without their placeholder prefix
+        let parameters: HashMap<_, _> = req
+            .parameters
             .into_iter()
-            .map(|row| proto_values_to_db_values(row.values))
+            .map(|(name, value)| (name.trim_start_matches([':', '@', '$']).to_string(), value))
             .collect();
-
-        let db_arc = self.get_database(if req.database.is_empty() { None } else { Some(&req.database) }).await?;
-        let db = db_arc.read().await;
-        match db.batch_insert(&req.table_name, rows).await {
-            Ok(count) => Ok(Response::new(BatchInsertResponse {
-                success: true,
-                message: format!("{} rows inserted", count),
-                inserted_count: count as i64,
-            })),
-            Err(e) => Err(Self::db_error_to_status(e)),
+        let missing: Vec<String> = params::placeholders(&saved.sql)
+            .into_iter()
+            .filter(|name| !parameters.contains_key(name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(with_request_id(Status::invalid_argument(format!(
+                "Saved query '{}' needs values for: {}",
+                req.name,
+                missing.join(", ")
+            ))));
         }
+
+        // Run it as a Query from the same client, so its identity's policies apply
+        let query = QueryRequest {
+            sql: saved.sql,
+            parameters,
+            database: req.database,
+        };
+        self.query(Request::from_parts(metadata, extensions, query)).await
     }
 
-    async fn get_server_status(
+    async fn get_row(
         &self,
-        _request: Request<ServerStatusRequest>,
-    ) -> Result<Response<ServerStatusResponse>, Status> {
-        let databases = self.db_manager.list_databases().await;
-        
-        let db_statuses: Vec<DatabaseStatus> = databases
-            .into_iter()
-            .map(|db_info| DatabaseStatus {
-                name: db_info.name,
-                url: db_info.url,
-                connected: db_info.connected,
-                connection_time: db_info.connection_time
-                    .map(|t| t.timestamp())
-                    .unwrap_or(0),
-                active_connections: 1, // For now, assume 1 connection per database
-            })
-            .collect();
+        request: Request<GetRowRequest>,
+    ) -> Result<Response<GetRowResponse>, Status> {
+        let identity = Self::identity(&request);
+        let consistency = consistency(&request)?;
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.table_name, "GetRow")?;
+        }
 
-        Ok(Response::new(ServerStatusResponse {
-            server_running: true,
-            uptime_seconds: self.start_time.elapsed().as_secs() as i64,
-            databases: db_statuses,
-        }))
+        let key: Vec<DbValue> = req.key.into_iter().map(proto_to_db_value).collect();
+
+        let (db_arc, _) = self.get_read_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }, consistency).await?;
+        let db = db_arc.read().await;
+        self.check_materialized_views(identity.as_deref(), &**db, &req.table_name).await?;
+        let encrypted = self.encrypted_tables(&**db, std::slice::from_ref(&req.table_name)).await?;
+        match db.get_row(&req.table_name, key.clone()).await {
+            Ok(Some(result)) => {
+                let sources = column_sources(&result.columns, &encrypted);
+                Ok(Response::new(GetRowResponse {
+                    columns: columns_to_proto(&result.columns),
+                    row: match result.rows.into_iter().next() {
+                        Some(values) => Some(Row { values: db_values_to_proto_values(self.decrypt_row(values, &sources).map_err(|e| self.db_error_to_status(e))?) }),
+                        None => None,
+                    },
+                }))
+            }
+            Ok(None) => Err(with_request_id(Status::not_found(format!(
+                "No row in '{}' with key {}",
+                req.table_name,
+                format_key(&key)
+            )))),
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
     }
 
-    async fn add_database(
+    async fn search(
         &self,
-        request: Request<AddDatabaseRequest>,
-    ) -> Result<Response<AddDatabaseResponse>, Status> {
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        let identity = Self::identity(&request);
+        let consistency = consistency(&request)?;
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.table_name, "Search")?;
+        }
+        let limit = if req.limit == 0 { 20 } else { req.limit };
+
+        let (db_arc, _) = self.get_read_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }, consistency).await?;
+        let db = db_arc.read().await;
+        self.check_materialized_views(identity.as_deref(), &**db, &req.table_name).await?;
+        let encrypted = self.encrypted_tables(&**db, std::slice::from_ref(&req.table_name)).await?;
+        match db.search(&req.table_name, &req.query, limit).await {
+            Ok(result) => {
+                let sources = column_sources(&result.columns, &encrypted);
+                Ok(Response::new(SearchResponse {
+                    columns: columns_to_proto(&result.columns),
+                    matches: result
+                        .matches
+                        .into_iter()
+                        .map(|m| {
+                            Ok(SearchMatch {
+                                score: m.score,
+                                snippet: m.snippet,
+                                row: Some(Row { values: db_values_to_proto_values(self.decrypt_row(m.values, &sources)?) }),
+                            })
+                        })
+                        .collect::<Result<_, DatabaseError>>()
+                        .map_err(|e| self.db_error_to_status(e))?,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn spatial_query(
+        &self,
+        request: Request<SpatialQueryRequest>,
+    ) -> Result<Response<SpatialQueryResponse>, Status> {
+        let identity = Self::identity(&request);
+        let consistency = consistency(&request)?;
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.table_name, "SpatialQuery")?;
+        }
+        let bbox = req
+            .bbox
+            .ok_or_else(|| with_request_id(Status::invalid_argument("A bounding box is required")))?;
+        if bbox.min_x > bbox.max_x || bbox.min_y > bbox.max_y {
+            return Err(with_request_id(Status::invalid_argument(
+                "Bounding box minimums must not exceed its maximums",
+            )));
+        }
+        let bbox = BoundingBox { min_x: bbox.min_x, min_y: bbox.min_y, max_x: bbox.max_x, max_y: bbox.max_y };
+        let limit = if req.limit == 0 { 1000 } else { req.limit };
+
+        let (db_arc, _) = self.get_read_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }, consistency).await?;
+        let db = db_arc.read().await;
+        self.check_materialized_views(identity.as_deref(), &**db, &req.table_name).await?;
+        let encrypted = self.encrypted_tables(&**db, std::slice::from_ref(&req.table_name)).await?;
+        match db.spatial_query(&req.table_name, bbox, limit).await {
+            Ok(result) => {
+                let sources = column_sources(&result.columns, &encrypted);
+                Ok(Response::new(SpatialQueryResponse {
+                    columns: columns_to_proto(&result.columns),
+                    rows: result
+                        .rows
+                        .into_iter()
+                        .map(|values| Ok(Row { values: db_values_to_proto_values(self.decrypt_row(values, &sources)?) }))
+                        .collect::<Result<_, DatabaseError>>()
+                        .map_err(|e| self.db_error_to_status(e))?,
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn sample(
+        &self,
+        request: Request<SampleRequest>,
+    ) -> Result<Response<SampleResponse>, Status> {
+        let identity = Self::identity(&request);
+        let consistency = consistency(&request)?;
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.table_name, "Sample")?;
+        }
+        let count = if req.count == 0 { 100 } else { req.count };
+
+        let (db_arc, _) = self.get_read_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }, consistency).await?;
+        let db = db_arc.read().await;
+        self.check_materialized_views(identity.as_deref(), &**db, &req.table_name).await?;
+        let encrypted = self.encrypted_tables(&**db, std::slice::from_ref(&req.table_name)).await?;
+        let result = db.sample(&req.table_name, count).await.map_err(|e| self.db_error_to_status(e))?;
+        let sources = column_sources(&result.columns, &encrypted);
+        Ok(Response::new(SampleResponse {
+            columns: columns_to_proto(&result.columns),
+            rows: result
+                .rows
+                .into_iter()
+                .map(|values| Ok(Row { values: db_values_to_proto_values(self.decrypt_row(values, &sources)?) }))
+                .collect::<Result<_, DatabaseError>>()
+                .map_err(|e| self.db_error_to_status(e))?,
+        }))
+    }
+
+    async fn dedupe(
+        &self,
+        request: Request<DedupeRequest>,
+    ) -> Result<Response<DedupeResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        if let Some(identity) = &identity {
+            identity.check_unrestricted(&req.table_name, "Dedupe")?;
+        }
+        if req.columns.is_empty() {
+            return Err(with_request_id(Status::invalid_argument("Name the columns that make rows duplicates")));
+        }
+        let keep = match (DedupeKeep::try_from(req.keep), req.keep_column.is_empty()) {
+            (Ok(DedupeKeep::First), _) => KeepRow::First,
+            (Ok(DedupeKeep::Last), _) => KeepRow::Last,
+            (Ok(DedupeKeep::Newest), false) => KeepRow::Newest(req.keep_column.clone()),
+            (Ok(DedupeKeep::Oldest), false) => KeepRow::Oldest(req.keep_column.clone()),
+            (Ok(_), true) => {
+                return Err(with_request_id(Status::invalid_argument("NEWEST and OLDEST need a keep_column")));
+            }
+            (Err(_), _) => return Err(with_request_id(Status::invalid_argument("Unknown row to keep"))),
+        };
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        // Encrypting a value twice gives different bytes, so equal values don't compare equal
+        let encrypted = db.encrypted_columns(&req.table_name).await.map_err(|e| self.db_error_to_status(e))?;
+        if let Some(column) = req.columns.iter().find(|c| encrypted.iter().any(|e| e.eq_ignore_ascii_case(c))) {
+            return Err(with_request_id(Status::invalid_argument(format!(
+                "Column '{}' is encrypted and cannot be compared",
+                column
+            ))));
+        }
+        let outcome = db
+            .dedupe(&req.table_name, &req.columns, &keep, req.dry_run)
+            .await
+            .map_err(|e| self.db_error_to_status(e))?;
+        if !req.dry_run {
+            self.record_write(outcome.removed as u64);
+        }
+        if !req.dry_run && outcome.removed > 0 {
+            info!(table = %req.table_name, groups = outcome.groups, removed_rows = outcome.removed, "Dedupe completed");
+            self.invalidate_cache(&req.database, Some(&req.table_name)).await;
+        }
+        Ok(Response::new(DedupeResponse {
+            groups: outcome.groups,
+            rows_removed: outcome.removed,
+            applied: !req.dry_run,
+        }))
+    }
+
+    async fn batch_insert(
+        &self,
+        request: Request<BatchInsertRequest>,
+    ) -> Result<Response<BatchInsertResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+
+        let mut rows: Vec<_> = req
+            .rows
+            .into_iter()
+            .map(|row| proto_values_to_db_values(row.values))
+            .collect();
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        self.encrypt_rows(&**db, &req.table_name, &mut rows).await?;
+        let total = rows.iter().filter(|row| !row.is_empty()).count() as u64;
+        let on_conflict = proto_to_on_conflict(req.on_conflict);
+        match db.batch_insert_with_conflict(&req.table_name, rows, on_conflict).await {
+            Ok(counts) => {
+                let skipped = total.saturating_sub(counts.inserted + counts.replaced);
+                info!(
+                    table = %req.table_name,
+                    inserted_count = counts.inserted,
+                    replaced_count = counts.replaced,
+                    skipped_count = skipped,
+                    "Batch insert completed"
+                );
+                self.record_write(counts.inserted + counts.replaced);
+                if counts.inserted + counts.replaced > 0 {
+                    self.invalidate_cache(&req.database, Some(&req.table_name)).await;
+                }
+                let mut message = format!("{} rows inserted", counts.inserted);
+                if counts.replaced > 0 {
+                    let verb = if on_conflict == OnConflict::Update { "updated" } else { "replaced" };
+                    message.push_str(&format!(", {} {}", counts.replaced, verb));
+                }
+                if skipped > 0 {
+                    message.push_str(&format!(", {} skipped", skipped));
+                }
+                Ok(Response::new(BatchInsertResponse {
+                    success: true,
+                    message,
+                    inserted_count: counts.inserted as i64,
+                    skipped_count: skipped as i64,
+                    replaced_count: counts.replaced as i64,
+                    first_inserted_id: counts.first_id.unwrap_or_default(),
+                    last_inserted_id: counts.last_id.unwrap_or_default(),
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn transaction(
+        &self,
+        request: Request<TransactionRequest>,
+    ) -> Result<Response<TransactionResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        if req.operations.is_empty() {
+            return Err(with_request_id(Status::invalid_argument("A transaction needs at least one operation")));
+        }
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+
+        // Apply the same encryption and row filters as the single-row RPCs
+        let mut writes = Vec::with_capacity(req.operations.len());
+        for (i, operation) in req.operations.into_iter().enumerate() {
+            let restrict = |table: &str, where_clause: String| match &identity {
+                Some(identity) => identity.restrict_where(table, &where_clause),
+                None => Ok(where_clause),
+            };
+            let write = match operation.operation {
+                Some(write_operation::Operation::Insert(insert)) => {
+                    let mut values = [proto_values_to_db_values(insert.values)];
+                    self.encrypt_rows(&**db, &insert.table_name, &mut values).await?;
+                    let [values] = values;
+                    WriteOp::Insert {
+                        on_conflict: proto_to_on_conflict(insert.on_conflict),
+                        table_name: insert.table_name,
+                        values,
+                    }
+                }
+                Some(write_operation::Operation::Update(update)) => {
+                    let where_clause = restrict(&update.table_name, update.where_clause)?;
+                    let mut values = [proto_values_to_db_values(update.values)];
+                    self.encrypt_rows(&**db, &update.table_name, &mut values).await?;
+                    let [values] = values;
+                    WriteOp::Update {
+                        table_name: update.table_name,
+                        values,
+                        where_clause,
+                    }
+                }
+                Some(write_operation::Operation::Delete(delete)) => WriteOp::Delete {
+                    where_clause: restrict(&delete.table_name, delete.where_clause)?,
+                    table_name: delete.table_name,
+                },
+                None => {
+                    return Err(with_request_id(Status::invalid_argument(format!(
+                        "Operation {} has no insert, update or delete",
+                        i + 1
+                    ))))
+                }
+            };
+            writes.push(write);
+        }
+
+        let mut tables: Vec<String> = writes.iter().map(|write| write.table_name().to_string()).collect();
+        tables.sort();
+        tables.dedup();
+        match db.execute_writes(writes).await {
+            Ok(outcomes) => {
+                info!(operations = outcomes.len(), "Transaction committed");
+                self.record_write(outcomes.iter().map(|outcome| outcome.rows_affected).sum());
+                for table in &tables {
+                    self.invalidate_cache(&req.database, Some(table)).await;
+                }
+                Ok(Response::new(TransactionResponse {
+                    success: true,
+                    message: format!("{} operations committed", outcomes.len()),
+                    results: outcomes
+                        .into_iter()
+                        .map(|outcome| WriteResult {
+                            affected_rows: outcome.rows_affected as i64,
+                            inserted_id: outcome.inserted_id.unwrap_or(-1),
+                        })
+                        .collect(),
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn open_session(
+        &self,
+        request: Request<OpenSessionRequest>,
+    ) -> Result<Response<OpenSessionResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+        let db_arc = self.get_database(identity.as_deref(), database).await?;
+        let name = self.db_manager.resolve_name(database).await.unwrap_or_default();
+
+        let connection = db_arc.read().await.open_session().await.map_err(|e| self.db_error_to_status(e))?;
+        let session_id = self.sessions.open(name.clone(), connection, identity.map(|i| i.name.clone()))?;
+        info!(database = %name, "Session opened");
+        Ok(Response::new(OpenSessionResponse {
+            session_id,
+            database: name,
+            idle_timeout_seconds: SESSION_IDLE_TIMEOUT.as_secs(),
+        }))
+    }
+
+    async fn close_session(
+        &self,
+        request: Request<CloseSessionRequest>,
+    ) -> Result<Response<CloseSessionResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        let closed = self.sessions.close(&req.session_id, identity.as_ref().map(|i| i.name.as_str()));
+        if closed {
+            info!("Session closed");
+        }
+        Ok(Response::new(CloseSessionResponse { closed }))
+    }
+
+    async fn get_server_status(
+        &self,
+        request: Request<ServerStatusRequest>,
+    ) -> Result<Response<ServerStatusResponse>, Status> {
+        // A caller limited to some databases only sees those
+        let identity = Self::identity(&request);
+        let visible = |database: &str| identity.as_ref().is_none_or(|identity| identity.can_access(database));
+        let mut databases = self.db_manager.list_databases().await;
+        databases.retain(|db_info| visible(&db_info.name));
+        let mut disk_usage = self.db_manager.disk_usage().await;
+        let mut connections = self.db_manager.connection_stats().await;
+        let mut activity: HashMap<_, _> = self.activity.databases().into_iter().collect();
+
+        let db_statuses: Vec<DatabaseStatus> = databases
+            .into_iter()
+            .map(|db_info| {
+                let connections = connections.remove(&db_info.name).unwrap_or_default();
+                let activity = activity.remove(&db_info.name).unwrap_or_default();
+                DatabaseStatus {
+                disk_usage: disk_usage.remove(&db_info.name).map(disk_usage_to_proto),
+                name: db_info.name,
+                url: secrets::redact_url(&db_info.url),
+                connected: db_info.connected,
+                connection_time: db_info.connection_time
+                    .map(|t| t.timestamp())
+                    .unwrap_or(0),
+                active_connections: connections.open as i32,
+                idle_connections: connections.idle as i32,
+                max_connections: connections.max as i32,
+                requests_total: activity.requests as i64,
+                active_queries: activity.active_queries as i64,
+                problems: db_info.problems,
+                queries_total: activity.queries as i64,
+                rows_written_total: activity.rows_written as i64,
+                last_write_time: activity.last_write.unwrap_or(0),
+                errors_total: activity.errors as i64,
+                }
+            })
+            .collect();
+
+        Ok(Response::new(ServerStatusResponse {
+            server_running: true,
+            uptime_seconds: self.start_time.elapsed().as_secs() as i64,
+            databases: db_statuses,
+            slow_queries: self
+                .activity
+                .slow_queries()
+                .into_iter()
+                .filter(|query| visible(&query.database))
+                .map(|query| ProtoSlowQuery {
+                    database: query.database,
+                    sql: query.sql,
+                    duration_ms: query.duration_ms as i64,
+                    finished_at: query.finished_at,
+                    request_id: query.request_id,
+                    plan: query.plan,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_database_info(
+        &self,
+        request: Request<DatabaseInfoRequest>,
+    ) -> Result<Response<DatabaseInfoResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+
+        // Reconnects a database evicted for inactivity, so its info is current
+        let db_arc = self.lookup_database(identity.as_deref(), database).await?;
+        let name = self.db_manager.resolve_name(database).await.unwrap_or_default();
+        let info = self
+            .db_manager
+            .database_info(&name)
+            .await
+            .ok_or_else(|| with_request_id(Status::not_found(format!("Database '{}' not found", name))))?;
+
+        let db = db_arc.read().await;
+        let storage = db.storage_info().await.map_err(|e| self.db_error_to_status(e))?;
+        let disk_usage = db.disk_usage().await.map_err(|e| self.db_error_to_status(e))?;
+        let (schema_name, schema_version) = db
+            .applied_schema()
+            .await
+            .map_err(|e| self.db_error_to_status(e))?
+            .map(|schema| (schema.name, schema.version))
+            .unwrap_or_default();
+        let connection_time = info.connection_time.map(|t| t.timestamp()).unwrap_or(0);
+
+        Ok(Response::new(DatabaseInfoResponse {
+            name: info.name,
+            backend: storage.backend,
+            url: secrets::redact_url(&info.url),
+            file_path: storage.file_path.unwrap_or_default(),
+            disk_usage: Some(disk_usage_to_proto(disk_usage)),
+            journal_mode: storage.journal_mode,
+            schema_name,
+            schema_version,
+            table_count: storage.table_count as i64,
+            connection_time,
+            connection_uptime_seconds: info
+                .connection_time
+                .map(|t| (chrono::Utc::now() - t).num_seconds().max(0))
+                .unwrap_or(0),
+            replicas: info.options.replicas.iter().map(|url| secrets::redact_url(url)).collect(),
+            problems: info.problems,
+        }))
+    }
+
+    async fn get_database_stats(
+        &self,
+        request: Request<DatabaseStatsRequest>,
+    ) -> Result<Response<DatabaseStatsResponse>, Status> {
+        // Row counts and sizes cover rows a restricted identity cannot read
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("GetDatabaseStats")?;
+        }
+        let req = request.into_inner();
+        let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+        let db_arc = self.lookup_database(identity.as_deref(), database).await?;
+
+        let db = db_arc.read().await;
+        let tables = db.table_stats().await.map_err(|e| self.db_error_to_status(e))?;
+        let disk_usage = db.disk_usage().await.map_err(|e| self.db_error_to_status(e))?;
+
+        Ok(Response::new(DatabaseStatsResponse {
+            total_rows: tables.iter().map(|table| table.rows as i64).sum(),
+            total_size_bytes: tables.iter().map(|table| table.size_bytes as i64).sum(),
+            tables: tables
+                .into_iter()
+                .map(|table| ProtoTableStats {
+                    name: table.name,
+                    rows: table.rows as i64,
+                    size_bytes: table.size_bytes as i64,
+                })
+                .collect(),
+            disk_usage: Some(disk_usage_to_proto(disk_usage)),
+        }))
+    }
+
+    async fn get_pragma(&self, request: Request<GetPragmaRequest>) -> Result<Response<GetPragmaResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        let pragma = runtime_pragma(&req.name).map_err(|e| with_request_id(Status::invalid_argument(e)))?;
+        let db_arc = self.lookup_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let value = db_arc.read().await.pragma(pragma).await.map_err(|e| self.db_error_to_status(e))?;
+        Ok(Response::new(GetPragmaResponse { name: pragma.to_string(), value }))
+    }
+
+    async fn set_pragma(&self, request: Request<SetPragmaRequest>) -> Result<Response<SetPragmaResponse>, Status> {
+        AdminAccess::check(&request, "SetPragma")?;
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("SetPragma")?;
+        }
+        let req = request.into_inner();
+        let invalid = |e: String| with_request_id(Status::invalid_argument(e));
+        let pragma = runtime_pragma(&req.name).map_err(invalid)?;
+        let value = runtime_pragma_value(pragma, &req.value).map_err(invalid)?;
+
+        let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+        let db_arc = self.lookup_database(identity.as_deref(), database).await?;
+        let name = self.db_manager.resolve_name(database).await.unwrap_or_default();
+        // Reopening the pool of an in-memory database would start an empty one
+        let storage = db_arc.read().await.storage_info().await.map_err(|e| self.db_error_to_status(e))?;
+        if storage.file_path.is_none() {
+            return Err(with_request_id(Status::failed_precondition(format!(
+                "Database '{}' is in memory; its PRAGMAs can only be set when it is added",
+                name
+            ))));
+        }
+        drop(db_arc);
+
+        self.db_manager.set_pragma(&name, pragma, &value).await.map_err(|e| self.db_error_to_status(e))?;
+        info!(database = %name, pragma, value = %value, "PRAGMA changed");
+
+        let db_arc = self.lookup_database(identity.as_deref(), Some(&name)).await?;
+        let value = db_arc.read().await.pragma(pragma).await.map_err(|e| self.db_error_to_status(e))?;
+        Ok(Response::new(SetPragmaResponse { name: pragma.to_string(), value }))
+    }
+
+    async fn advise_indexes(
+        &self,
+        request: Request<AdviseIndexesRequest>,
+    ) -> Result<Response<AdviseIndexesResponse>, Status> {
+        // The collected queries may read rows a restricted identity cannot
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("AdviseIndexes")?;
+        }
+        if request.get_ref().apply {
+            AdminAccess::check(&request, "AdviseIndexes with apply")?;
+        }
+        let req = request.into_inner();
+
+        let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+        let db_arc = self.lookup_database(identity.as_deref(), database).await?;
+        let name = self.db_manager.resolve_name(database).await.unwrap_or_default();
+        let patterns: Vec<_> = self
+            .activity
+            .query_patterns(&name)
+            .into_iter()
+            .filter(|pattern| pattern.count >= req.min_count.max(1) as u64)
+            .collect();
+
+        let db = db_arc.read().await;
+        let suggestions = advisor::advise(db.as_ref(), &patterns).await.map_err(|e| self.db_error_to_status(e))?;
+        let mut response = AdviseIndexesResponse { suggestions: Vec::new(), patterns_analyzed: patterns.len() as i64 };
+        for suggestion in suggestions {
+            if req.apply {
+                db.execute(&suggestion.sql).await.map_err(|e| self.db_error_to_status(e))?;
+                info!(database = %name, index = %suggestion.sql, "Index created by advisor");
+            }
+            response.suggestions.push(IndexSuggestion {
+                table_name: suggestion.table,
+                columns: suggestion.columns,
+                sql: suggestion.sql,
+                query_count: suggestion.query_count as i64,
+                example_sql: suggestion.example_sql,
+                plan_before: suggestion.plan_before,
+                plan_after: suggestion.plan_after,
+                applied: req.apply,
+            });
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn list_changes(
+        &self,
+        request: Request<ListChangesRequest>,
+    ) -> Result<Response<ListChangesResponse>, Status> {
+        // Past versions may hold rows a restricted identity cannot read
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("ListChanges")?;
+        }
+        let req = request.into_inner();
+
+        let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+        let table = if req.table_name.is_empty() { None } else { Some(req.table_name.as_str()) };
+        let limit = if req.limit == 0 { 10 } else { req.limit };
+        let db_arc = self.lookup_database(identity.as_deref(), database).await?;
+        let changes = db_arc.read().await.history_changes(table, limit).await.map_err(|e| self.db_error_to_status(e))?;
+        Ok(Response::new(ListChangesResponse { changes: changes.iter().map(change_to_proto).collect() }))
+    }
+
+    async fn undo_changes(
+        &self,
+        request: Request<UndoChangesRequest>,
+    ) -> Result<Response<UndoChangesResponse>, Status> {
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("UndoChanges")?;
+        }
+        if !request.get_ref().dry_run {
+            AdminAccess::check(&request, "UndoChanges")?;
+        }
+        let req = request.into_inner();
+        if (req.last == 0) == req.change_ids.is_empty() {
+            return Err(with_request_id(Status::invalid_argument("Give either last or change_ids")));
+        }
+
+        let database = if req.database.is_empty() { None } else { Some(req.database.as_str()) };
+        let db_arc = self.lookup_database(identity.as_deref(), database).await?;
+        let db = db_arc.read().await;
+        let targets = if req.last > 0 {
+            let table = if req.table_name.is_empty() { None } else { Some(req.table_name.as_str()) };
+            let changes = db.history_changes(table, req.last).await.map_err(|e| self.db_error_to_status(e))?;
+            if changes.is_empty() {
+                return Err(with_request_id(Status::not_found("No changes to undo")));
+            }
+            changes.into_iter().map(|change| (change.table, change.time)).collect()
+        } else {
+            req.change_ids
+                .iter()
+                .map(|id| history::parse_change_id(id))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| self.db_error_to_status(e))?
+        };
+
+        let (changes, statements) = db.undo_changes(&targets, req.dry_run).await.map_err(|e| self.db_error_to_status(e))?;
+        drop(db);
+        if !req.dry_run {
+            self.invalidate_cache(&req.database, None).await;
+            for change in &changes {
+                info!(database = %req.database, change = %change.id(), "Change undone");
+            }
+        }
+        Ok(Response::new(UndoChangesResponse {
+            changes: changes.iter().map(change_to_proto).collect(),
+            statements,
+            applied: !req.dry_run,
+        }))
+    }
+
+    async fn add_database(
+        &self,
+        request: Request<AddDatabaseRequest>,
+    ) -> Result<Response<AddDatabaseResponse>, Status> {
+        AdminAccess::check(&request, "AddDatabase")?;
         let req = request.into_inner();
         
         // Validate database name
@@ -299,7 +1766,8 @@ impl DataSink for DataSinkService {
             req.url.clone()
         };
         
-        match self.db_manager.add_database(req.name.clone(), db_url).await {
+        let options = DatabaseOptions { replicas: req.replicas, ..DatabaseOptions::default() };
+        match self.db_manager.add_persistent_database(req.name.clone(), db_url, options).await {
             Ok(_) => Ok(Response::new(AddDatabaseResponse {
                 success: true,
                 message: format!("Database '{}' added successfully", req.name),
@@ -310,4 +1778,870 @@ impl DataSink for DataSinkService {
             })),
         }
     }
+
+    async fn remove_database(
+        &self,
+        request: Request<RemoveDatabaseRequest>,
+    ) -> Result<Response<RemoveDatabaseResponse>, Status> {
+        AdminAccess::check(&request, "RemoveDatabase")?;
+        let req = request.into_inner();
+
+        if !self.db_manager.remove_database(&req.name).await {
+            return Err(with_request_id(Status::not_found(format!("Database '{}' not found", req.name))));
+        }
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate_database(&req.name);
+        }
+        info!(database = %req.name, "Database removed");
+        Ok(Response::new(RemoveDatabaseResponse {
+            success: true,
+            message: format!("Database '{}' removed", req.name),
+        }))
+    }
+
+    async fn provision_database(
+        &self,
+        request: Request<ProvisionDatabaseRequest>,
+    ) -> Result<Response<ProvisionDatabaseResponse>, Status> {
+        AdminAccess::check(&request, "ProvisionDatabase")?;
+        let req = request.into_inner();
+        let path = self.new_database_path(&req.name).await.map_err(with_request_id)?;
+        let (schema, checksum) = self.provision_schema(req.schema).await.map_err(with_request_id)?;
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+
+        // Any failure leaves no file behind, so the request can be retried
+        let remove_files = || remove_database_files(&path);
+        let db = SqliteDatabase::connect(&url).await.map_err(|e| self.db_error_to_status(e))?;
+        let mut warnings = Vec::new();
+        let mut report = |event: apply::Event| {
+            if let apply::Event::Warning(warning) = event {
+                warnings.push(warning);
+            }
+        };
+        let applied = apply::apply(&db, &schema, &checksum, self.cipher.as_deref(), &mut report).await;
+        db.close().await;
+        if let Err(e) = applied {
+            remove_files();
+            return Err(with_request_id(Status::invalid_argument(format!("Failed to apply schema: {}", e))));
+        }
+        if !warnings.is_empty() {
+            remove_files();
+            return Err(with_request_id(Status::invalid_argument(format!(
+                "Failed to apply schema: {}",
+                warnings.join("; ")
+            ))));
+        }
+
+        if let Err(e) = self.db_manager.add_persistent_database(req.name.clone(), url, DatabaseOptions::default()).await {
+            remove_files();
+            return Err(self.db_error_to_status(e));
+        }
+        info!(database = %req.name, schema = %schema.database.name, version = %schema.database.version, "Database provisioned");
+
+        Ok(Response::new(ProvisionDatabaseResponse {
+            name: req.name,
+            file_path: path.display().to_string(),
+            schema_name: schema.database.name,
+            schema_version: schema.database.version,
+        }))
+    }
+
+    async fn clone_database(
+        &self,
+        request: Request<CloneDatabaseRequest>,
+    ) -> Result<Response<CloneDatabaseResponse>, Status> {
+        AdminAccess::check(&request, "CloneDatabase")?;
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+        let source = if req.source.is_empty() { None } else { Some(req.source.as_str()) };
+        let db_arc = self.lookup_database(identity.as_deref(), source).await?;
+        let source_name = self.db_manager.resolve_name(source).await.unwrap_or_default();
+        let options = self.db_manager.database_info(&source_name).await.map(|info| info.options).unwrap_or_default();
+        if req.schema_only {
+            // The schema is replayed from the source file
+            let storage = db_arc.read().await.storage_info().await.map_err(|e| self.db_error_to_status(e))?;
+            if storage.file_path.is_none() {
+                return Err(with_request_id(Status::failed_precondition(format!(
+                    "Database '{}' is in memory; only a full copy can be made",
+                    source_name
+                ))));
+            }
+        }
+        let path = self.new_database_path(&req.name).await.map_err(with_request_id)?;
+
+        if let Err(e) = db_arc.read().await.copy_to(&path, req.schema_only).await {
+            remove_database_files(&path);
+            return Err(self.db_error_to_status(e));
+        }
+        // The copy is opened with the source's connection options
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        if let Err(e) = self.db_manager.add_persistent_database(req.name.clone(), url, options).await {
+            remove_database_files(&path);
+            return Err(self.db_error_to_status(e));
+        }
+        info!(source = %source_name, database = %req.name, schema_only = req.schema_only, "Database cloned");
+
+        Ok(Response::new(CloneDatabaseResponse {
+            name: req.name,
+            file_path: path.display().to_string(),
+            file_bytes: std::fs::metadata(&path).map(|m| m.len() as i64).unwrap_or(0),
+        }))
+    }
+
+    async fn copy_table(
+        &self,
+        request: Request<CopyTableRequest>,
+    ) -> Result<Response<CopyTableResponse>, Status> {
+        // Rows are read and written without row filters
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("CopyTable")?;
+        }
+        let req = request.into_inner();
+        if req.source_table.is_empty() {
+            return Err(with_request_id(Status::invalid_argument("A source table is required")));
+        }
+        let target_table = if req.target_table.is_empty() { req.source_table.clone() } else { req.target_table.clone() };
+        let batch_size = if req.batch_size == 0 { 1000 } else { req.batch_size as usize };
+
+        let source = if req.source_database.is_empty() { None } else { Some(req.source_database.as_str()) };
+        let target = if req.target_database.is_empty() { None } else { Some(req.target_database.as_str()) };
+        let source_arc = self.lookup_database(identity.as_deref(), source).await?;
+        let target_arc = self.lookup_database(identity.as_deref(), target).await?;
+        let same_database = Arc::ptr_eq(&source_arc, &target_arc);
+        if same_database && req.source_table == target_table {
+            return Err(with_request_id(Status::invalid_argument("A table cannot be copied onto itself")));
+        }
+        let source_db = source_arc.read().await;
+        // Locking the same database twice could wait on a queued writer forever
+        let target_guard = if same_database { None } else { Some(target_arc.read().await) };
+        let target_db = target_guard.as_deref().map_or(source_db.as_ref(), |db| db.as_ref());
+
+        let created = req.create;
+        if created {
+            if target_db.table_schema(&target_table).await.is_ok() {
+                return Err(with_request_id(Status::already_exists(format!("Table '{}' already exists", target_table))));
+            }
+            let statements =
+                source_db.table_definition(&req.source_table, &target_table).await.map_err(|e| self.db_error_to_status(e))?;
+            for sql in statements {
+                target_db.execute(&sql).await.map_err(|e| self.db_error_to_status(e))?;
+            }
+            let encrypted = source_db.encrypted_columns(&req.source_table).await.map_err(|e| self.db_error_to_status(e))?;
+            if !encrypted.is_empty() {
+                target_db.set_encrypted_columns(&target_table, &encrypted).await.map_err(|e| self.db_error_to_status(e))?;
+            }
+        }
+
+        // Columns of the source that the target also has, so the tables may differ
+        let target_columns: Vec<String> = target_db
+            .table_schema(&target_table)
+            .await
+            .map_err(|e| self.db_error_to_status(e))?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let columns: Vec<String> = source_db
+            .table_schema(&req.source_table)
+            .await
+            .map_err(|e| self.db_error_to_status(e))?
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| target_columns.contains(name))
+            .collect();
+        if columns.is_empty() {
+            return Err(with_request_id(Status::invalid_argument(format!(
+                "Tables '{}' and '{}' have no columns in common",
+                req.source_table, target_table
+            ))));
+        }
+        // Ciphertext is bound to its table and column, so unless both tables
+        // have the same name and encrypted columns, values are re-encrypted
+        let source_encrypted = source_db.encrypted_columns(&req.source_table).await.map_err(|e| self.db_error_to_status(e))?;
+        let target_encrypted = target_db.encrypted_columns(&target_table).await.map_err(|e| self.db_error_to_status(e))?;
+        let unchanged = req.source_table.eq_ignore_ascii_case(&target_table) && source_encrypted == target_encrypted;
+        let plain = source_encrypted.is_empty() && target_encrypted.is_empty();
+        let reencrypt = !(unchanged || plain);
+        let cipher = match (reencrypt, &self.cipher) {
+            (false, _) => None,
+            (true, Some(cipher)) => Some(cipher.clone()),
+            (true, None) => {
+                return Err(with_request_id(Status::failed_precondition(format!(
+                    "Copying encrypted columns of '{}' needs the server's encryption key",
+                    req.source_table
+                ))))
+            }
+        };
+        let reencrypt_row = |values: &mut Vec<DbValue>| -> Result<(), DatabaseError> {
+            let Some(cipher) = &cipher else {
+                return Ok(());
+            };
+            let is_in = |list: &[String], column: &str| list.iter().any(|c| c.eq_ignore_ascii_case(column));
+            for (column, value) in columns.iter().zip(values.iter_mut()) {
+                if is_in(&source_encrypted, column) {
+                    *value = cipher.decrypt(std::mem::replace(value, DbValue::Null), &req.source_table, column)?;
+                }
+                if is_in(&target_encrypted, column) {
+                    *value = cipher.encrypt(value, &target_table, column)?;
+                }
+            }
+            Ok(())
+        };
+
+        let column_list = columns.iter().map(|name| format!("\"{}\"", name.replace('"', "\"\""))).collect::<Vec<_>>().join(", ");
+        let mut select = format!("SELECT {} FROM {}", column_list, req.source_table);
+        if !req.where_clause.is_empty() {
+            select.push_str(&format!(" WHERE {}", req.where_clause));
+        }
+
+        let mut rows_copied = 0;
+        if same_database && !reencrypt {
+            let sql = format!("INSERT INTO {} ({}) {}", target_table, column_list, select);
+            rows_copied = target_db.execute(&sql).await.map_err(|e| self.db_error_to_status(e))?;
+        } else {
+            let mut rows: StreamedQueryResult = if same_database {
+                // Streaming would hold a read open on the database the rows are written to
+                let result = source_db.query(&select, HashMap::new()).await.map_err(|e| self.db_error_to_status(e))?;
+                Box::pin(futures::stream::iter(result.rows.into_iter().map(Ok)))
+            } else {
+                source_db.query_stream(&select, HashMap::new()).await.map_err(|e| self.db_error_to_status(e))?.1
+            };
+            let mut batch = Vec::with_capacity(batch_size);
+            loop {
+                let row = rows.next().await.transpose().map_err(|e| self.db_error_to_status(e))?;
+                let done = row.is_none();
+                if let Some(mut values) = row {
+                    reencrypt_row(&mut values).map_err(|e| self.db_error_to_status(e))?;
+                    batch.push(columns.iter().cloned().zip(values).collect::<HashMap<_, _>>());
+                }
+                if batch.len() == batch_size || (done && !batch.is_empty()) {
+                    match target_db.batch_insert(&target_table, std::mem::take(&mut batch)).await {
+                        Ok(inserted) => rows_copied += inserted,
+                        Err(e) => {
+                            tracing::warn!(rows_copied, "Table copy stopped part way");
+                            return Err(self.db_error_to_status(e));
+                        }
+                    }
+                }
+                if done {
+                    break;
+                }
+            }
+        }
+        drop(target_guard);
+        drop(source_db);
+
+        self.invalidate_cache(&req.target_database, Some(&target_table)).await;
+        if let Some(name) = self.db_manager.resolve_name(target).await {
+            self.activity.record_write(&name, rows_copied);
+        }
+        info!(
+            source = %req.source_database,
+            target = %req.target_database,
+            table = %target_table,
+            rows = rows_copied,
+            created,
+            "Table copied"
+        );
+        Ok(Response::new(CopyTableResponse { rows_copied, created }))
+    }
+
+    async fn diff_table(
+        &self,
+        request: Request<DiffTableRequest>,
+    ) -> Result<Response<DiffTableResponse>, Status> {
+        // Rows are read without row filters
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("DiffTable")?;
+        }
+        let req = request.into_inner();
+        if req.table_name.is_empty() {
+            return Err(with_request_id(Status::invalid_argument("A table name is required")));
+        }
+        let limit = if !req.include_rows { 0 } else if req.limit == 0 { 1000 } else { req.limit as usize };
+
+        let source = if req.source_database.is_empty() { None } else { Some(req.source_database.as_str()) };
+        let target = if req.target_database.is_empty() { None } else { Some(req.target_database.as_str()) };
+        let source_arc = self.lookup_database(identity.as_deref(), source).await?;
+        let target_arc = self.lookup_database(identity.as_deref(), target).await?;
+        if Arc::ptr_eq(&source_arc, &target_arc) {
+            return Err(with_request_id(Status::invalid_argument("A database cannot be compared with itself")));
+        }
+        let source_db = source_arc.read().await;
+        let target_db = target_arc.read().await;
+        let where_clause = if req.where_clause.is_empty() { None } else { Some(req.where_clause.as_str()) };
+        let result = diff::diff_table(
+            source_db.as_ref(),
+            target_db.as_ref(),
+            &req.table_name,
+            &req.key_columns,
+            where_clause,
+            self.cipher.as_deref(),
+            limit,
+        )
+        .await
+        .map_err(|e| self.db_error_to_status(e))?;
+
+        let differing = result.added + result.removed + result.changed;
+        let rows = result
+            .rows
+            .into_iter()
+            .map(|row| RowDifference {
+                change: match row.change {
+                    RowChange::Added => ProtoRowChange::Added,
+                    RowChange::Removed => ProtoRowChange::Removed,
+                    RowChange::Changed => ProtoRowChange::Changed,
+                } as i32,
+                key: db_values_to_proto_values(row.key),
+                source_row: row.source.map(|values| Row { values: db_values_to_proto_values(values) }),
+                target_row: row.target.map(|values| Row { values: db_values_to_proto_values(values) }),
+                changed_columns: row.changed_columns,
+            })
+            .collect::<Vec<_>>();
+        Ok(Response::new(DiffTableResponse {
+            key_columns: result.key_columns,
+            columns: columns_to_proto(&result.columns),
+            added: result.added,
+            removed: result.removed,
+            changed: result.changed,
+            unchanged: result.unchanged,
+            truncated: req.include_rows && (rows.len() as u64) < differing,
+            rows,
+        }))
+    }
+
+    async fn get_metrics(
+        &self,
+        _request: Request<MetricsRequest>,
+    ) -> Result<Response<MetricsResponse>, Status> {
+        let mut metrics = vec![
+            metric("uptime_seconds", self.start_time.elapsed().as_secs() as f64),
+            metric("databases", self.db_manager.database_count().await as f64),
+        ];
+
+        if let Some(cache) = &self.query_cache {
+            let stats = cache.stats();
+            metrics.extend([
+                metric("query_cache_hits_total", stats.hits as f64),
+                metric("query_cache_misses_total", stats.misses as f64),
+                metric("query_cache_invalidations_total", stats.invalidations as f64),
+                metric("query_cache_entries", stats.entries as f64),
+                metric("query_cache_capacity", stats.capacity as f64),
+            ]);
+        }
+
+        for (database, purged) in self.db_manager.ttl_purged() {
+            metrics.push(database_metric("ttl_purged_rows_total", &database, purged as f64));
+        }
+
+        for (database, activity) in self.activity.databases() {
+            metrics.extend([
+                database_metric("requests_total", &database, activity.requests as f64),
+                database_metric("active_queries", &database, activity.active_queries as f64),
+                database_metric("queries_total", &database, activity.queries as f64),
+                database_metric("rows_written_total", &database, activity.rows_written as f64),
+                database_metric("errors_total", &database, activity.errors as f64),
+            ]);
+        }
+
+        for (database, connections) in self.db_manager.connection_stats().await {
+            metrics.push(database_metric("pool_connections", &database, connections.open as f64));
+        }
+
+        Ok(Response::new(MetricsResponse { metrics }))
+    }
+
+    async fn ping(
+        &self,
+        _request: Request<PingRequest>,
+    ) -> Result<Response<PingResponse>, Status> {
+        Ok(Response::new(PingResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_seconds: self.start_time.elapsed().as_secs() as i64,
+            server_time_ms: chrono::Utc::now().timestamp_millis(),
+        }))
+    }
+
+    async fn shutdown(
+        &self,
+        request: Request<ShutdownRequest>,
+    ) -> Result<Response<ShutdownResponse>, Status> {
+        AdminAccess::check(&request, "Shutdown")?;
+        info!("Shutdown requested");
+        // The server finishes the requests in progress, this one included
+        self.shutdown.notify_one();
+        Ok(Response::new(ShutdownResponse {
+            message: "Server is shutting down".to_string(),
+        }))
+    }
+
+    async fn add_job(
+        &self,
+        request: Request<AddJobRequest>,
+    ) -> Result<Response<AddJobResponse>, Status> {
+        AdminAccess::check(&request, "AddJob")?;
+        Self::check_job_access(&request, "AddJob")?;
+        let scheduler = self.scheduler().map_err(|e| self.db_error_to_status(e))?;
+        let req = request.into_inner();
+
+        let job = Job {
+            name: req.name,
+            schedule: req.schedule,
+            sql: req.sql,
+            database: req.database,
+            from_config: false,
+        };
+        let name = job.name.clone();
+        match scheduler.add_job(job).await {
+            Ok(next_run) => {
+                info!(job = %name, "Job added");
+                Ok(Response::new(AddJobResponse {
+                    success: true,
+                    message: format!("Job '{}' added", name),
+                    next_run: next_run.map(|t| t.timestamp()).unwrap_or(0),
+                }))
+            }
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn remove_job(
+        &self,
+        request: Request<RemoveJobRequest>,
+    ) -> Result<Response<RemoveJobResponse>, Status> {
+        AdminAccess::check(&request, "RemoveJob")?;
+        Self::check_job_access(&request, "RemoveJob")?;
+        let scheduler = self.scheduler().map_err(|e| self.db_error_to_status(e))?;
+        let req = request.into_inner();
+
+        match scheduler.remove_job(&req.name).await {
+            Ok(true) => {
+                info!(job = %req.name, "Job removed");
+                Ok(Response::new(RemoveJobResponse {
+                    success: true,
+                    message: format!("Job '{}' removed", req.name),
+                }))
+            }
+            Ok(false) => Err(with_request_id(Status::not_found(format!("Job '{}' not found", req.name)))),
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn list_jobs(
+        &self,
+        request: Request<ListJobsRequest>,
+    ) -> Result<Response<ListJobsResponse>, Status> {
+        Self::check_job_access(&request, "ListJobs")?;
+        let scheduler = self.scheduler().map_err(|e| self.db_error_to_status(e))?;
+
+        let jobs = scheduler
+            .jobs()
+            .into_iter()
+            .map(|(job, next_run)| ProtoJob {
+                name: job.name,
+                schedule: job.schedule,
+                sql: job.sql,
+                database: job.database,
+                next_run: next_run.map(|t| t.timestamp()).unwrap_or(0),
+                from_config: job.from_config,
+            })
+            .collect();
+        Ok(Response::new(ListJobsResponse { jobs }))
+    }
+
+    async fn save_query(
+        &self,
+        request: Request<SaveQueryRequest>,
+    ) -> Result<Response<SaveQueryResponse>, Status> {
+        // Other clients run saved queries, so only unrestricted identities may write them
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("SaveQuery")?;
+        }
+        let req = request.into_inner();
+        if req.name.trim().is_empty() || req.sql.trim().is_empty() {
+            return Err(with_request_id(Status::invalid_argument("A saved query needs a name and SQL")));
+        }
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        if !req.replace && db.saved_query(&req.name).await.map_err(|e| self.db_error_to_status(e))?.is_some() {
+            return Err(with_request_id(Status::already_exists(format!(
+                "Saved query '{}' already exists",
+                req.name
+            ))));
+        }
+
+        let query = SavedQuery {
+            name: req.name,
+            sql: req.sql,
+            description: req.description,
+        };
+        db.save_query(&query).await.map_err(|e| self.db_error_to_status(e))?;
+        info!(query = %query.name, "Query saved");
+        Ok(Response::new(SaveQueryResponse {
+            success: true,
+            message: format!("Query '{}' saved", query.name),
+        }))
+    }
+
+    async fn remove_saved_query(
+        &self,
+        request: Request<RemoveSavedQueryRequest>,
+    ) -> Result<Response<RemoveSavedQueryResponse>, Status> {
+        let identity = Self::identity(&request);
+        if let Some(identity) = &identity {
+            identity.check_no_filters("RemoveSavedQuery")?;
+        }
+        let req = request.into_inner();
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let db = db_arc.read().await;
+        match db.delete_saved_query(&req.name).await {
+            Ok(true) => {
+                info!(query = %req.name, "Saved query removed");
+                Ok(Response::new(RemoveSavedQueryResponse {
+                    success: true,
+                    message: format!("Query '{}' removed", req.name),
+                }))
+            }
+            Ok(false) => Err(with_request_id(Status::not_found(format!("Saved query '{}' not found", req.name)))),
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+
+    async fn list_saved_queries(
+        &self,
+        request: Request<ListSavedQueriesRequest>,
+    ) -> Result<Response<ListSavedQueriesResponse>, Status> {
+        let identity = Self::identity(&request);
+        let req = request.into_inner();
+
+        let db_arc = self.get_database(identity.as_deref(), if req.database.is_empty() { None } else { Some(&req.database) }).await?;
+        let queries = db_arc.read().await.saved_queries().await.map_err(|e| self.db_error_to_status(e))?;
+        Ok(Response::new(ListSavedQueriesResponse {
+            queries: queries
+                .into_iter()
+                .map(|query| ProtoSavedQuery {
+                    parameters: params::placeholders(&query.sql),
+                    name: query.name,
+                    sql: query.sql,
+                    description: query.description,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn list_job_runs(
+        &self,
+        request: Request<ListJobRunsRequest>,
+    ) -> Result<Response<ListJobRunsResponse>, Status> {
+        Self::check_job_access(&request, "ListJobRuns")?;
+        let scheduler = self.scheduler().map_err(|e| self.db_error_to_status(e))?;
+        let req = request.into_inner();
+        let limit = if req.limit == 0 { 20 } else { req.limit };
+        let job_name = if req.job_name.is_empty() { None } else { Some(req.job_name.as_str()) };
+
+        match scheduler.runs(job_name, &req.database, limit).await {
+            Ok(runs) => Ok(Response::new(ListJobRunsResponse {
+                runs: runs
+                    .into_iter()
+                    .map(|run| ProtoJobRun {
+                        job_name: run.job_name,
+                        started_at: run.started_at,
+                        duration_ms: run.duration_ms,
+                        success: run.success,
+                        rows_affected: run.rows_affected as i64,
+                        message: run.message,
+                    })
+                    .collect(),
+            })),
+            Err(e) => Err(self.db_error_to_status(e)),
+        }
+    }
+}
+
+fn metric(name: &str, value: f64) -> Metric {
+    Metric {
+        name: name.to_string(),
+        value,
+        labels: Default::default(),
+    }
+}
+
+fn database_metric(name: &str, database: &str, value: f64) -> Metric {
+    let mut metric = metric(name, value);
+    metric.labels.insert("database".to_string(), database.to_string());
+    metric
+}
+
+/// Render key values for messages, e.g. `(42, 'alice')`
+fn format_key(key: &[DbValue]) -> String {
+    let parts: Vec<String> = key
+        .iter()
+        .map(|value| match value {
+            DbValue::Integer(v) | DbValue::Timestamp(v) => v.to_string(),
+            DbValue::Real(v) => v.to_string(),
+            DbValue::Text(v) => format!("'{}'", v),
+            DbValue::Blob(v) => format!("<{} bytes>", v.len()),
+            DbValue::Boolean(v) => v.to_string(),
+            DbValue::Null => "NULL".to_string(),
+        })
+        .collect();
+    format!("({})", parts.join(", "))
+}
+
+fn columns_to_proto(columns: &[(String, ColumnType)]) -> Vec<ProtoColumn> {
+    columns
+        .iter()
+        .map(|(name, col_type)| ProtoColumn {
+            name: name.clone(),
+            r#type: column_type_to_proto(col_type) as i32,
+        })
+        .collect()
+}
+
+fn rows_response(rows: Vec<Vec<DbValue>>) -> QueryResponse {
+    QueryResponse {
+        response: Some(query_response::Response::ResultSet(ResultSet {
+            columns: vec![],
+            rows: rows.into_iter().map(|values| Row { values: db_values_to_proto_values(values) }).collect(),
+        })),
+    }
+}
+
+/// Delete a database file and the journal files SQLite keeps beside it
+fn remove_database_files(path: &std::path::Path) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+/// Whether a name is safe to use as a file name: letters, digits, '_' and '-'
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn disk_usage_to_proto(usage: DiskUsage) -> ProtoDiskUsage {
+    ProtoDiskUsage {
+        file_bytes: usage.file_bytes as i64,
+        page_count: usage.page_count as i64,
+        page_size: usage.page_size as i64,
+        free_pages: usage.free_pages as i64,
+        wal_bytes: usage.wal_bytes as i64,
+    }
+}
+
+/// The message ending a query stream that completed
+fn summary_response(rows_returned: usize, started: Instant, truncated: bool, cached: bool) -> QueryResponse {
+    QueryResponse {
+        response: Some(query_response::Response::Summary(QuerySummary {
+            rows_returned: rows_returned as u64,
+            execution_time_us: started.elapsed().as_micros() as u64,
+            truncated,
+            cached,
+        })),
+    }
+}
+
+/// Replay a cached result in the same shape as a live query stream
+fn cached_result_stream(
+    result: Arc<QueryResult>,
+    batch_rows: usize,
+    max_rows: Option<usize>,
+    started: Instant,
+) -> <DataSinkService as DataSink>::QueryStream {
+    Box::pin(async_stream::stream! {
+        yield Ok(QueryResponse {
+            response: Some(query_response::Response::ResultSet(ResultSet {
+                columns: columns_to_proto(&result.columns),
+                rows: vec![],
+            })),
+        });
+        let rows = &result.rows[..result.rows.len().min(max_rows.unwrap_or(usize::MAX))];
+        for batch in rows.chunks(batch_rows) {
+            yield Ok(rows_response(batch.to_vec()));
+        }
+        yield Ok(summary_response(rows.len(), started, rows.len() < result.rows.len(), true));
+    })
+}
+
+/// Drops a database's cached results when a possibly-writing query stream ends
+struct InvalidateOnDrop {
+    cache: Arc<QueryCache>,
+    database: String,
+}
+
+impl Drop for InvalidateOnDrop {
+    fn drop(&mut self) {
+        self.cache.invalidate_database(&self.database);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    /// A manager whose default database has `events` with ids 1 to `rows`
+    async fn manager_with_events(temp_file: &NamedTempFile, rows: i64) -> Arc<DatabaseManager> {
+        let url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+        let manager = Arc::new(DatabaseManager::new());
+        manager.add_database("default".to_string(), url).await.unwrap();
+        let db = manager.get_database("default").await.unwrap();
+        let db = db.read().await;
+        db.execute("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT)").await.unwrap();
+        for id in 1..=rows {
+            db.execute(&format!("INSERT INTO events (id, name) VALUES ({}, 'e{}')", id, id)).await.unwrap();
+        }
+        drop(db);
+        manager
+    }
+
+    async fn run_query(service: &DataSinkService, sql: &str) -> Vec<QueryResponse> {
+        let request = Request::new(QueryRequest { sql: sql.to_string(), parameters: HashMap::new(), database: String::new() });
+        let stream = service.query(request).await.unwrap().into_inner();
+        stream.map(|response| response.unwrap()).collect().await
+    }
+
+    /// Rows per result set message, and the summary if one was sent
+    fn shape(responses: Vec<QueryResponse>) -> (Vec<usize>, Option<QuerySummary>) {
+        let mut batches = Vec::new();
+        let mut summary = None;
+        for response in responses {
+            match response.response {
+                Some(query_response::Response::ResultSet(result_set)) => batches.push(result_set.rows.len()),
+                Some(query_response::Response::Summary(s)) => summary = Some(s),
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+        (batches, summary)
+    }
+
+    #[tokio::test]
+    async fn test_query_rows_are_sent_in_batches() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let manager = manager_with_events(&temp_file, 5).await;
+        let service = DataSinkService::new_with_manager(manager)
+            .with_query_batch_rows(2)
+            .with_query_cache(Arc::new(QueryCache::new(10)));
+
+        // Columns first, then full batches and the remainder; a cache hit has the same shape
+        for _ in 0..2 {
+            let (batches, _) = shape(run_query(&service, "SELECT * FROM events ORDER BY id").await);
+            assert_eq!(batches, vec![0, 2, 2, 1]);
+        }
+        assert_eq!(service.query_cache.as_ref().unwrap().stats().hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ping_reports_version_and_clock() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let service = DataSinkService::new_with_manager(manager_with_events(&temp_file, 0).await);
+
+        let before = chrono::Utc::now().timestamp_millis();
+        let response = service.ping(Request::new(PingRequest {})).await.unwrap().into_inner();
+        assert_eq!(response.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(response.uptime_seconds, 0);
+        assert!(response.server_time_ms >= before && response.server_time_ms <= chrono::Utc::now().timestamp_millis());
+    }
+
+    #[tokio::test]
+    async fn test_query_summary_reports_truncation_and_cache_hits() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let manager = manager_with_events(&temp_file, 5).await;
+        let service = DataSinkService::new_with_manager(manager)
+            .with_max_query_rows(3)
+            .with_query_cache(Arc::new(QueryCache::new(10)));
+
+        let (batches, summary) = shape(run_query(&service, "SELECT * FROM events").await);
+        let summary = summary.unwrap();
+        assert_eq!(batches.iter().sum::<usize>(), 3);
+        assert_eq!((summary.rows_returned, summary.truncated, summary.cached), (3, true, false));
+
+        // A result within the limit is cached and the repeat says so
+        for cached in [false, true] {
+            let (_, summary) = shape(run_query(&service, "SELECT * FROM events WHERE id <= 2").await);
+            let summary = summary.unwrap();
+            assert_eq!((summary.rows_returned, summary.truncated, summary.cached), (2, false, cached));
+        }
+    }
+
+    /// A request made by an identity whose `events` rows are filtered to `id <= 2`
+    fn filtered_request<T>(message: T) -> Request<T> {
+        let config = crate::config::IdentityConfig {
+            name: "tenant".to_string(),
+            token: "secret".to_string(),
+            params: Default::default(),
+            filters: [("events".to_string(), "id <= 2".to_string())].into(),
+            databases: None,
+        };
+        let identity = crate::grpc::policy::RowPolicies::from_config(&[config]).unwrap().resolve("secret").unwrap();
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(identity);
+        request
+    }
+
+    #[tokio::test]
+    async fn test_partitions_of_filtered_tables_are_denied() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let manager = manager_with_events(&temp_file, 3).await;
+        let db = manager.get_database("default").await.unwrap();
+        db.read().await.execute("CREATE TABLE events_2024_06 AS SELECT * FROM events").await.unwrap();
+        let service = DataSinkService::new_with_manager(manager);
+
+        let query = |sql: &str| QueryRequest { sql: sql.to_string(), parameters: HashMap::new(), database: String::new() };
+        let stream = service.query(filtered_request(query("SELECT * FROM events"))).await.unwrap().into_inner();
+        let responses: Vec<QueryResponse> = stream.map(|response| response.unwrap()).collect().await;
+        assert_eq!(shape(responses).0.iter().sum::<usize>(), 2);
+
+        let status = service.query(filtered_request(query("SELECT * FROM events_2024_06"))).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let sample = SampleRequest { table_name: "events_2024_06".to_string(), count: 10, database: String::new() };
+        let status = service.sample(filtered_request(sample)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let delete = DeleteRequest { table_name: "events_2024_06".to_string(), where_clause: "1".to_string(), database: String::new() };
+        let status = service.delete(filtered_request(delete)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_materialized_views_over_filtered_tables_are_denied() {
+        use crate::db::traits::MaterializedView;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let manager = manager_with_events(&temp_file, 3).await;
+        let db = manager.get_database("default").await.unwrap();
+        let view = MaterializedView {
+            name: "event_names".to_string(),
+            query: "SELECT id, name FROM events".to_string(),
+            refresh_interval_seconds: 0,
+            last_refreshed: None,
+        };
+        db.read().await.create_materialized_view(&view).await.unwrap();
+        let service = DataSinkService::new_with_manager(manager).with_query_cache(Arc::new(QueryCache::new(10)));
+
+        // Cached by an unfiltered caller first, so the cache cannot serve it either
+        let sql = "SELECT * FROM event_names";
+        assert_eq!(shape(run_query(&service, sql).await).0.iter().sum::<usize>(), 3);
+        let query = QueryRequest { sql: sql.to_string(), parameters: HashMap::new(), database: String::new() };
+        let status = service.query(filtered_request(query)).await.err().unwrap();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let get_row = GetRowRequest { table_name: "event_names".to_string(), key: vec![], database: String::new() };
+        let status = service.get_row(filtered_request(get_row)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_database_stats_denied_to_filtered_identities() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let service = DataSinkService::new_with_manager(manager_with_events(&temp_file, 3).await);
+
+        let stats = service.get_database_stats(Request::new(DatabaseStatsRequest::default())).await.unwrap().into_inner();
+        assert_eq!(stats.total_rows, 3);
+        let status = service.get_database_stats(filtered_request(DatabaseStatsRequest::default())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    }
 }
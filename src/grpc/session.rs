@@ -0,0 +1,149 @@
+//! Client sessions with a dedicated database connection.
+//!
+//! OpenSession gives a client an ID it sends in the `x-datasink-session`
+//! metadata of its queries. They all run on one connection kept for the
+//! session, so TEMP tables and other connection state (temp triggers and
+//! views, `PRAGMA temp_store`, ...) created by one query are visible to the
+//! next and to no other client. Sessions that go unused for the idle timeout
+//! are closed, dropping their TEMP tables.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::RwLock;
+use tonic::{Request, Status};
+
+use crate::db::Database;
+use crate::grpc::request_id::with_request_id;
+
+/// Request metadata naming the session a query runs in
+pub const SESSION_HEADER: &str = "x-datasink-session";
+
+/// Close sessions unused for this long
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Open sessions allowed at once; each holds a connection
+const MAX_SESSIONS: usize = 64;
+
+/// Why a session could not be opened or used
+#[derive(Debug)]
+pub enum SessionError {
+    /// No open session has the ID, or it belongs to another identity
+    NotFound(String),
+    /// `MAX_SESSIONS` are open
+    TooMany,
+    /// The session metadata is not valid text
+    InvalidHeader,
+}
+
+impl From<SessionError> for Status {
+    fn from(err: SessionError) -> Self {
+        with_request_id(match err {
+            SessionError::NotFound(id) => Status::not_found(format!("Session '{}' not found or expired", id)),
+            SessionError::TooMany => {
+                Status::resource_exhausted(format!("Too many open sessions ({}); close one first", MAX_SESSIONS))
+            }
+            SessionError::InvalidHeader => Status::invalid_argument(format!("{} is not valid text", SESSION_HEADER)),
+        })
+    }
+}
+
+/// An open session
+#[derive(Clone)]
+pub struct Session {
+    /// Database the session's connection belongs to
+    pub database: String,
+    /// The session's own connection to it
+    pub db: Arc<RwLock<Box<dyn Database>>>,
+    /// Identity that opened the session; only it may use the session
+    owner: Option<String>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl Sessions {
+    /// Register a session on `db` for `owner`, returning its ID
+    pub fn open(&self, database: String, db: Box<dyn Database>, owner: Option<String>) -> Result<String, SessionError> {
+        let mut sessions = self.lock();
+        Self::expire(&mut sessions);
+        if sessions.len() >= MAX_SESSIONS {
+            return Err(SessionError::TooMany);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        let session = Session { database, db: Arc::new(RwLock::new(db)), owner, last_used: Instant::now() };
+        sessions.insert(id.clone(), session);
+        Ok(id)
+    }
+
+    /// The session a request names in its metadata, if any
+    pub fn for_request<T>(&self, request: &Request<T>, owner: Option<&str>) -> Result<Option<Session>, SessionError> {
+        let Some(value) = request.metadata().get(SESSION_HEADER) else {
+            return Ok(None);
+        };
+        let id = value.to_str().map_err(|_| SessionError::InvalidHeader)?;
+        self.get(id.trim(), owner).map(Some)
+    }
+
+    /// Look up a session, marking it used
+    pub fn get(&self, id: &str, owner: Option<&str>) -> Result<Session, SessionError> {
+        let mut sessions = self.lock();
+        Self::expire(&mut sessions);
+        match sessions.get_mut(id) {
+            Some(session) if session.owner.as_deref() == owner => {
+                session.last_used = Instant::now();
+                Ok(session.clone())
+            }
+            // Another identity's session is reported like an unknown one
+            _ => Err(SessionError::NotFound(id.to_string())),
+        }
+    }
+
+    /// Close a session, dropping its connection once in-flight queries finish.
+    /// Returns whether it was open.
+    pub fn close(&self, id: &str, owner: Option<&str>) -> bool {
+        let mut sessions = self.lock();
+        match sessions.get(id) {
+            Some(session) if session.owner.as_deref() == owner => sessions.remove(id).is_some(),
+            _ => false,
+        }
+    }
+
+    fn expire(sessions: &mut HashMap<String, Session>) {
+        sessions.retain(|_, session| session.last_used.elapsed() < SESSION_IDLE_TIMEOUT);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::SqliteDatabase;
+
+    #[tokio::test]
+    async fn test_sessions_belong_to_their_owner() {
+        let db = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+        let sessions = Sessions::default();
+        let id = sessions.open("default".to_string(), Box::new(db), Some("acme".to_string())).unwrap();
+
+        assert_eq!(sessions.get(&id, Some("acme")).unwrap().database, "default");
+        assert!(matches!(sessions.get(&id, Some("other")), Err(SessionError::NotFound(_))));
+        assert!(sessions.get(&id, None).is_err());
+
+        let mut request = Request::new(());
+        assert!(sessions.for_request(&request, Some("acme")).unwrap().is_none());
+        request.metadata_mut().insert(SESSION_HEADER, id.parse().unwrap());
+        assert!(sessions.for_request(&request, Some("acme")).unwrap().is_some());
+
+        assert!(!sessions.close(&id, Some("other")));
+        assert!(sessions.close(&id, Some("acme")));
+        assert!(sessions.get(&id, Some("acme")).is_err());
+    }
+}
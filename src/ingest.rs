@@ -0,0 +1,333 @@
+//! HTTP JSON ingestion.
+//!
+//! `POST /ingest/<database>/<table>` accepts a JSON object or an array of
+//! objects, converts each field to the type of its column and inserts the
+//! rows through the service's BatchInsert, so authentication, row policies,
+//! column encryption and cache invalidation apply just as they do over gRPC.
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tonic::{Code, Request, Status};
+
+use crate::db::partition;
+use crate::db::traits::{ColumnType, DbValue};
+use crate::db::{DatabaseError, DatabaseManager};
+use crate::grpc::conversions::db_value_to_proto;
+use crate::grpc::policy::RowPolicies;
+use crate::grpc::DataSinkService;
+use crate::proto::crud::{BatchInsertRequest, InsertRow, OnConflict};
+use crate::proto::data_sink_server::DataSink;
+
+struct IngestState {
+    service: Arc<DataSinkService>,
+    manager: Arc<DatabaseManager>,
+    policies: Arc<RowPolicies>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct IngestParams {
+    /// "ignore" skips rows that conflict with existing ones
+    on_conflict: Option<String>,
+}
+
+/// A failed ingestion, returned as `{"error": "..."}`
+#[derive(Debug)]
+struct IngestError {
+    status: StatusCode,
+    message: String,
+}
+
+impl IngestError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+}
+
+impl IntoResponse for IngestError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+impl From<Status> for IngestError {
+    fn from(status: Status) -> Self {
+        let code = match status.code() {
+            Code::InvalidArgument => StatusCode::BAD_REQUEST,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::AlreadyExists => StatusCode::CONFLICT,
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::FailedPrecondition => StatusCode::PRECONDITION_FAILED,
+            Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(code, status.message())
+    }
+}
+
+impl From<DatabaseError> for IngestError {
+    fn from(err: DatabaseError) -> Self {
+        match err {
+            DatabaseError::TableNotFound(table) => {
+                Self::new(StatusCode::NOT_FOUND, format!("Table '{}' not found", table))
+            }
+            DatabaseError::QueryError(msg) => Self::bad_request(msg),
+            _ => Self::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        }
+    }
+}
+
+/// Routes of the ingestion endpoint
+pub fn router(service: Arc<DataSinkService>, manager: Arc<DatabaseManager>, policies: Arc<RowPolicies>) -> Router {
+    Router::new()
+        .route("/ingest/:database/:table", post(ingest))
+        .with_state(Arc::new(IngestState { service, manager, policies }))
+}
+
+/// Serve the ingestion endpoint on `addr`
+pub async fn serve(addr: SocketAddr, router: Router) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    axum::Server::try_bind(&addr)?.serve(router.into_make_service()).await?;
+    Ok(())
+}
+
+async fn ingest(
+    State(state): State<Arc<IngestState>>,
+    Path((database, table)): Path<(String, String)>,
+    Query(params): Query<IngestParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<JsonValue>, IngestError> {
+    // Same bearer tokens as gRPC clients
+    let identity = if state.policies.is_empty() {
+        None
+    } else {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        let identity = token
+            .and_then(|token| state.policies.resolve(token))
+            .ok_or_else(|| IngestError::new(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token"))?;
+        Some(identity)
+    };
+
+    let body: JsonValue = serde_json::from_slice(&body)
+        .map_err(|e| IngestError::bad_request(format!("Invalid JSON: {}", e)))?;
+    let objects = match body {
+        JsonValue::Array(items) => items,
+        object @ JsonValue::Object(_) => vec![object],
+        _ => return Err(IngestError::bad_request("Expected a JSON object or an array of objects")),
+    };
+
+    let db = state
+        .manager
+        .get_database(&database)
+        .await
+        .ok_or_else(|| IngestError::new(StatusCode::NOT_FOUND, format!("Database '{}' not found", database)))?;
+    let schema = db.read().await.table_schema(&table).await?;
+    let columns: HashMap<String, (String, Option<ColumnType>)> = schema
+        .into_iter()
+        .map(|(name, col_type)| (name.to_lowercase(), (name, col_type)))
+        .collect();
+
+    let rows = objects
+        .into_iter()
+        .enumerate()
+        .map(|(i, object)| {
+            coerce_row(object, &columns)
+                .map(|values| InsertRow { values })
+                .map_err(|e| IngestError::bad_request(format!("Row {}: {}", i, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let on_conflict = match params.on_conflict.as_deref().map(str::to_lowercase).as_deref() {
+        None | Some("abort") => OnConflict::Abort,
+        Some("ignore") => OnConflict::Ignore,
+        Some(other) => return Err(IngestError::bad_request(format!("Unknown on_conflict '{}'", other))),
+    };
+
+    let mut request = Request::new(BatchInsertRequest {
+        table_name: table,
+        rows,
+        database,
+        on_conflict: on_conflict as i32,
+    });
+    if let Some(identity) = identity {
+        request.extensions_mut().insert(identity);
+    }
+    let response = state.service.batch_insert(request).await?.into_inner();
+
+    Ok(Json(json!({
+        "inserted": response.inserted_count,
+        "skipped": response.skipped_count,
+    })))
+}
+
+/// Convert a JSON object to column values, matching column names case-insensitively
+fn coerce_row(
+    object: JsonValue,
+    columns: &HashMap<String, (String, Option<ColumnType>)>,
+) -> Result<HashMap<String, crate::proto::common::Value>, String> {
+    let JsonValue::Object(fields) = object else {
+        return Err("expected a JSON object".to_string());
+    };
+
+    let mut values = HashMap::new();
+    for (field, value) in fields {
+        let (column, col_type) = columns
+            .get(&field.to_lowercase())
+            .ok_or_else(|| format!("unknown column '{}'", field))?;
+        let value = coerce_value(value, col_type.as_ref()).map_err(|e| format!("column '{}': {}", column, e))?;
+        values.insert(column.clone(), db_value_to_proto(value));
+    }
+    Ok(values)
+}
+
+/// Convert a JSON value to the type of its column. Columns without a
+/// declared type take the value as JSON typed it.
+fn coerce_value(value: JsonValue, col_type: Option<&ColumnType>) -> Result<DbValue, String> {
+    let invalid = |value: &JsonValue, expected: &str| format!("cannot convert {} to {}", value, expected);
+
+    let converted = match (col_type, value) {
+        (_, JsonValue::Null) => DbValue::Null,
+        (Some(ColumnType::Integer), value) => match &value {
+            JsonValue::Number(n) => n
+                .as_i64()
+                .or_else(|| n.as_f64().filter(|f| f.fract() == 0.0).map(|f| f as i64))
+                .map(DbValue::Integer),
+            JsonValue::String(s) => s.trim().parse().ok().map(DbValue::Integer),
+            JsonValue::Bool(b) => Some(DbValue::Integer(*b as i64)),
+            _ => None,
+        }
+        .ok_or_else(|| invalid(&value, "INTEGER"))?,
+        (Some(ColumnType::Real), value) => match &value {
+            JsonValue::Number(n) => n.as_f64().map(DbValue::Real),
+            JsonValue::String(s) => s.trim().parse().ok().map(DbValue::Real),
+            _ => None,
+        }
+        .ok_or_else(|| invalid(&value, "REAL"))?,
+        (Some(ColumnType::Boolean), value) => match &value {
+            JsonValue::Bool(b) => Some(DbValue::Boolean(*b)),
+            JsonValue::Number(n) => match n.as_i64() {
+                Some(0) => Some(DbValue::Boolean(false)),
+                Some(1) => Some(DbValue::Boolean(true)),
+                _ => None,
+            },
+            JsonValue::String(s) => match s.trim().to_lowercase().as_str() {
+                "true" | "1" => Some(DbValue::Boolean(true)),
+                "false" | "0" => Some(DbValue::Boolean(false)),
+                _ => None,
+            },
+            _ => None,
+        }
+        .ok_or_else(|| invalid(&value, "BOOLEAN"))?,
+        (Some(ColumnType::Timestamp), value) => {
+            let time = match &value {
+                JsonValue::Number(n) => n.as_i64().map(DbValue::Integer),
+                JsonValue::String(s) => Some(DbValue::Text(s.clone())),
+                _ => None,
+            };
+            match time.map(|t| partition::value_time(&t)) {
+                Some(Ok(Some(time))) => DbValue::Timestamp(time.timestamp()),
+                _ => return Err(invalid(&value, "TIMESTAMP")),
+            }
+        }
+        (Some(ColumnType::Blob), JsonValue::String(s)) => base64::engine::general_purpose::STANDARD
+            .decode(s.as_bytes())
+            .map(DbValue::Blob)
+            .map_err(|_| "expected base64 for BLOB".to_string())?,
+        (Some(ColumnType::Blob), value) => return Err(invalid(&value, "BLOB (base64 string)")),
+        (Some(ColumnType::Text), JsonValue::String(s)) => DbValue::Text(s),
+        // Nested values are stored as their JSON text
+        (Some(ColumnType::Text), value) => DbValue::Text(value.to_string()),
+        (None, JsonValue::Number(n)) => match n.as_i64() {
+            Some(i) => DbValue::Integer(i),
+            None => DbValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        (None, JsonValue::String(s)) => DbValue::Text(s),
+        (None, JsonValue::Bool(b)) => DbValue::Boolean(b),
+        (None, value) => DbValue::Text(value.to_string()),
+    };
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, HttpBody};
+    use axum::http::Request as HttpRequest;
+    use tempfile::NamedTempFile;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_coerce_value() {
+        assert!(matches!(coerce_value(json!("42"), Some(&ColumnType::Integer)), Ok(DbValue::Integer(42))));
+        assert!(matches!(coerce_value(json!(3.0), Some(&ColumnType::Integer)), Ok(DbValue::Integer(3))));
+        assert!(coerce_value(json!(3.5), Some(&ColumnType::Integer)).is_err());
+        assert!(matches!(coerce_value(json!("1.5"), Some(&ColumnType::Real)), Ok(DbValue::Real(r)) if r == 1.5));
+        assert!(matches!(coerce_value(json!(1), Some(&ColumnType::Boolean)), Ok(DbValue::Boolean(true))));
+        assert!(matches!(
+            coerce_value(json!("2024-06-15T10:00:00Z"), Some(&ColumnType::Timestamp)),
+            Ok(DbValue::Timestamp(1718445600))
+        ));
+        assert!(matches!(coerce_value(json!(7), Some(&ColumnType::Text)), Ok(DbValue::Text(s)) if s == "7"));
+        assert!(matches!(coerce_value(json!({"a": 1}), Some(&ColumnType::Text)), Ok(DbValue::Text(s)) if s == r#"{"a":1}"#));
+        assert!(matches!(coerce_value(json!(null), Some(&ColumnType::Integer)), Ok(DbValue::Null)));
+        assert!(matches!(coerce_value(json!(7), None), Ok(DbValue::Integer(7))));
+    }
+
+    #[tokio::test]
+    async fn test_ingest_request() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+        let manager = Arc::new(DatabaseManager::new());
+        manager.add_database("default".to_string(), url).await.unwrap();
+        let db = manager.get_database("default").await.unwrap();
+        db.read()
+            .await
+            .execute("CREATE TABLE readings (id INTEGER PRIMARY KEY, sensor TEXT, value REAL)")
+            .await
+            .unwrap();
+
+        let service = Arc::new(DataSinkService::new_with_manager(manager.clone()));
+        let app = router(service, manager, Arc::new(RowPolicies::default()));
+        let post = |uri: &str, body: &str| {
+            HttpRequest::post(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let body = r#"[{"sensor": "t1", "value": "21.5"}, {"SENSOR": "t2", "value": 19}]"#;
+        let response = app.clone().oneshot(post("/ingest/default/readings", body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(serde_json::from_slice::<JsonValue>(&bytes).unwrap(), json!({"inserted": 2, "skipped": 0}));
+
+        let response = app.clone().oneshot(post("/ingest/default/readings", r#"{"humidity": 40}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app.clone().oneshot(post("/ingest/default/missing", "{}")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app.oneshot(post("/ingest/default/readings", "not json")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,6 +1,8 @@
+pub mod compression;
 pub mod db;
 pub mod schema;
 
+#[allow(clippy::enum_variant_names)]
 pub mod proto {
     pub mod common {
         tonic::include_proto!("datasink.common");
@@ -0,0 +1,170 @@
+//! Logging setup: text or JSON output, to stdout or a size-rotated log file
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::Level;
+
+/// Output format for log lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable text (default)
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregation systems
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {} (expected text or json)", s)),
+        }
+    }
+}
+
+/// Resolved logging settings
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    pub level: Level,
+    pub format: LogFormat,
+    /// Write logs to this file instead of stdout
+    pub file: Option<PathBuf>,
+    /// Rotate the log file once it grows past this many bytes
+    pub max_size_bytes: u64,
+    /// Number of rotated files to keep (file.1 .. file.N)
+    pub max_files: usize,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: Level::INFO,
+            format: LogFormat::Text,
+            file: None,
+            max_size_bytes: 100 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+/// Install the global tracing subscriber
+pub fn init(settings: &LogSettings) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = tracing_subscriber::fmt().with_max_level(settings.level);
+
+    match (&settings.file, settings.format) {
+        (None, LogFormat::Text) => builder.init(),
+        (None, LogFormat::Json) => builder.json().with_current_span(true).with_span_list(false).init(),
+        (Some(path), format) => {
+            let writer = Mutex::new(RotatingFile::open(path, settings.max_size_bytes, settings.max_files)?);
+            let builder = builder.with_writer(writer).with_ansi(false);
+            match format {
+                LogFormat::Text => builder.init(),
+                LogFormat::Json => builder.json().with_current_span(true).with_span_list(false).init(),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A log file that is rotated (file -> file.1 -> file.2 ...) when it exceeds a size limit
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files: max_files.max(1),
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), index))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        // Shift file.N-1 -> file.N, ..., file.1 -> file.2; the oldest is overwritten
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, self.rotated_path(1))?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("TEXT".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_rotating_file_rotates_by_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("server.log");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+        file.write_all(b"first-line\n").unwrap();
+        file.write_all(b"second-line\n").unwrap();
+        file.write_all(b"third-line\n").unwrap();
+        file.write_all(b"fourth-line\n").unwrap();
+        file.flush().unwrap();
+
+        // Only max_files rotated files are kept alongside the active one
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth-line\n");
+        assert_eq!(std::fs::read_to_string(temp_dir.path().join("server.log.1")).unwrap(), "third-line\n");
+        assert_eq!(std::fs::read_to_string(temp_dir.path().join("server.log.2")).unwrap(), "second-line\n");
+        assert!(!temp_dir.path().join("server.log.3").exists());
+    }
+}
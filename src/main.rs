@@ -1,7 +1,12 @@
 mod cli;
+pub mod compression;
+mod config;
 pub mod db;
 mod grpc;
+mod ingest;
+mod logging;
 pub mod schema;
+#[allow(clippy::enum_variant_names)]
 mod proto {
     pub mod common {
         tonic::include_proto!("datasink.common");
@@ -17,47 +22,186 @@ mod proto {
 
 use clap::Parser;
 use tracing::Level;
-use tracing_subscriber;
 
-use crate::cli::{commands, Cli, Commands, ServerCommands, SchemaCommands};
+use crate::cli::error::{self, CliError, ErrorKind};
+use crate::cli::{bench, bulk, client, commands, doctor, export, generate, import, output, render, sink, tail, top, tx, Cli, Commands, JobCommands, SavedCommands, ServerCommands, SessionCommands, SchemaCommands, SinkCommands};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
     let cli = Cli::parse();
+    if cli.json {
+        output::enable();
+    }
+
+    if let Err(e) = run(cli).await {
+        // With --json the error is a document on stdout like any other result
+        if output::enabled() {
+            output::print(&output::error_document(e.as_ref()));
+        } else {
+            eprintln!("Error: {}", error::message(e.as_ref()));
+        }
+        std::process::exit(error::kind_of(e.as_ref()).exit_code());
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
 
-    // Initialize logging
-    let log_level = if cli.verbose {
-        Level::DEBUG
-    } else {
-        Level::INFO
+    // Load the server config file up front so its logging settings apply
+    let server_config = match &cli.command {
+        Commands::Server {
+            command: ServerCommands::Start { config: Some(path), .. },
+        } => match config::ServerConfig::load(std::path::Path::new(path)) {
+            Ok(config) => Some(config),
+            Err(e) => return Err(CliError::new(ErrorKind::InvalidInput, e.to_string()).into()),
+        },
+        _ => None,
     };
-    tracing_subscriber::fmt().with_max_level(log_level).init();
 
-    // Get database URL from CLI or environment with consistency checking
-    let database_url = match cli.resolve_database_url() {
-        Ok(url) => url,
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
+    // Initialize logging: config file first, then command line flags
+    let mut log_settings = logging::LogSettings::default();
+    if let Some(config) = &server_config {
+        config.apply_logging(&mut log_settings);
+    }
+    if let Commands::Server {
+        command: ServerCommands::Start { log_format, log_file, log_max_size, .. },
+    } = &cli.command
+    {
+        if let Some(format) = log_format {
+            log_settings.format = format
+                .parse()
+                .map_err(|e: String| CliError::new(ErrorKind::InvalidInput, e))?;
         }
-    };
+        if let Some(file) = log_file {
+            log_settings.file = Some(std::path::PathBuf::from(file));
+        }
+        if let Some(max_size) = log_max_size {
+            log_settings.max_size_bytes = max_size * 1024 * 1024;
+        }
+    }
+    if cli.verbose {
+        log_settings.level = Level::DEBUG;
+    }
+    logging::init(&log_settings)?;
+
+    // Get database URL from CLI or environment with consistency checking
+    let database_url = cli
+        .resolve_database_url()
+        .map_err(|e| CliError::new(ErrorKind::InvalidInput, e))?;
+
+    let explicit_database = cli.has_explicit_database();
+
+    client::configure(client::ConnectionSettings {
+        connect_timeout: cli.connect_timeout.map(std::time::Duration::from_secs_f64),
+        request_timeout: cli.request_timeout.map(std::time::Duration::from_secs_f64),
+        retries: cli.retries,
+        keepalive_interval: cli.keepalive_interval.map(std::time::Duration::from_secs),
+        keepalive_timeout: cli.keepalive_timeout.map(std::time::Duration::from_secs),
+        tcp_nodelay: cli.tcp_nodelay,
+        show_sql: cli.show_sql,
+        consistency: cli.consistency.clone(),
+    });
+    render::configure(render::RenderSettings {
+        null_as: cli.null_as.clone(),
+        raw_timestamps: cli.raw_timestamps,
+        local_time: cli.local_time,
+        decimals: cli.decimals,
+        thousands: cli.thousands.then(render::Separators::from_env),
+    });
 
     match cli.command {
         Commands::Server { command } => match command {
-            ServerCommands::Start { bind_address } => {
-                commands::start_server(database_url, bind_address).await?;
+            ServerCommands::Start {
+                bind_address,
+                databases,
+                state_file,
+                no_persist,
+                idle_timeout,
+                query_cache,
+                ingest_addr,
+                concurrency_limit,
+                max_concurrent_streams,
+                ..
+            } => {
+                let mut server_config = server_config.unwrap_or_default();
+                let section = &mut server_config.server;
+                section.ingest_address = ingest_addr.or(section.ingest_address.take());
+                section.keepalive_interval_seconds = cli.keepalive_interval.or(section.keepalive_interval_seconds);
+                section.keepalive_timeout_seconds = cli.keepalive_timeout.or(section.keepalive_timeout_seconds);
+                section.tcp_nodelay = cli.tcp_nodelay.or(section.tcp_nodelay);
+                section.concurrency_limit_per_connection =
+                    concurrency_limit.or(section.concurrency_limit_per_connection);
+                section.max_concurrent_streams = max_concurrent_streams.or(section.max_concurrent_streams);
+                // A database given on the command line replaces the config's "default" entry
+                let default_url = if explicit_database || !server_config.databases.contains_key("default") {
+                    Some(database_url)
+                } else {
+                    None
+                };
+                let registry_path = if no_persist || server_config.server.persist_databases == Some(false) {
+                    None
+                } else {
+                    Some(
+                        state_file
+                            .map(std::path::PathBuf::from)
+                            .or_else(|| server_config.server.state_file.clone())
+                            .unwrap_or_else(|| std::path::PathBuf::from("datasink.registry.json")),
+                    )
+                };
+                commands::start_server(
+                    default_url,
+                    databases,
+                    bind_address,
+                    registry_path,
+                    idle_timeout,
+                    query_cache,
+                    server_config,
+                )
+                .await?;
             }
             ServerCommands::Stop => {
                 commands::stop_server(cli.server_address).await?;
             }
-            ServerCommands::Status => {
-                commands::server_status(cli.server_address).await?;
+            ServerCommands::Status { format, detailed } => {
+                commands::server_status(cli.server_address, detailed, &format).await?;
+            }
+            ServerCommands::Info { database } => {
+                commands::database_info(cli.server_address, database).await?;
+            }
+            ServerCommands::Pragma { setting, database } => {
+                commands::pragma(cli.server_address, setting, database).await?;
+            }
+            ServerCommands::GenerateKey => {
+                commands::generate_key()?;
             }
-            ServerCommands::AddDatabase { name, url } => {
-                commands::add_database(cli.server_address, name, url).await?;
+            ServerCommands::Metrics => {
+                commands::server_metrics(cli.server_address).await?;
             }
-            ServerCommands::CreateTable { name, columns } => {
-                commands::create_table(cli.server_address, name, columns, None).await?;
+            ServerCommands::Top { interval, count } => {
+                top::run(cli.server_address, std::time::Duration::from_secs_f64(interval), count).await?;
+            }
+            ServerCommands::AddDatabase { name, url, replicas } => {
+                commands::add_database(cli.server_address, name, url, replicas).await?;
+            }
+            ServerCommands::RemoveDatabase { name } => {
+                commands::remove_database(cli.server_address, name).await?;
+            }
+            ServerCommands::Provision { name, template, schema } => {
+                commands::provision_database(cli.server_address, name, template, schema).await?;
+            }
+            ServerCommands::Clone { source, new_name, schema_only } => {
+                commands::clone_database(cli.server_address, source, new_name, schema_only).await?;
+            }
+            ServerCommands::CreateTable { name, columns, unique } => {
+                commands::create_table(cli.server_address, name, columns, unique, None).await?;
+            }
+            ServerCommands::CreateSearchIndex { table, columns, database } => {
+                commands::create_search_index(cli.server_address, table, columns, database).await?;
+            }
+            ServerCommands::CreateSpatialIndex { table, columns, database } => {
+                commands::create_spatial_index(cli.server_address, table, columns, database).await?;
+            }
+            ServerCommands::RefreshView { name, database } => {
+                commands::refresh_view(cli.server_address, name, database).await?;
             }
             ServerCommands::CreateDatabase { name } => {
                 commands::create_database(name).await?;
@@ -66,19 +210,108 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 commands::create_from_schema(schema_file, database_name).await?;
             }
         },
-        Commands::Query { sql, format, database } => {
-            commands::query(cli.server_address, sql, format, database).await?;
+        Commands::Query { sql, vars, format, stats, columns, max_col_width, watch, count, database } => {
+            let view = render::ColumnView { columns, max_width: max_col_width.map(|width| width as usize) };
+            let watch = watch.map(|seconds| commands::Watch { interval: std::time::Duration::from_secs_f64(seconds), count });
+            commands::query(cli.server_address, sql, vars, format, stats, view, watch, database).await?;
+        }
+        Commands::Get { table, key, format, database } => {
+            commands::get_row(cli.server_address, table, key, format, database).await?;
+        }
+        Commands::Insert {
+            table,
+            data,
+            file,
+            batch_size,
+            jobs,
+            on_conflict,
+            returning,
+            format,
+            coerce,
+            database,
+        } => match (data, file) {
+            (Some(data), None) if data != "-" => {
+                let options = commands::InsertOptions { on_conflict, returning, format, coerce };
+                commands::insert(cli.server_address, table, data, options, database).await?;
+            }
+            // `-` reads rows from stdin the same way as `--file -`
+            (data, file) => {
+                if returning.is_some() {
+                    let message = "--returning works with a single row given as an argument";
+                    return Err(CliError::new(ErrorKind::InvalidInput, message).into());
+                }
+                let options = bulk::BulkInsertOptions {
+                    table,
+                    path: file.or(data).unwrap_or_else(|| "-".to_string()),
+                    batch_size: batch_size as usize,
+                    jobs: jobs as usize,
+                    on_conflict: if on_conflict == "ignore" {
+                        proto::crud::OnConflict::Ignore
+                    } else {
+                        proto::crud::OnConflict::Abort
+                    },
+                    coerce,
+                    database: database.unwrap_or_default(),
+                };
+                bulk::insert_file(cli.server_address, options).await?;
+            }
+        },
+        Commands::Export {
+            table,
+            output,
+            format,
+            compress,
+            columns,
+            exclude,
+            filters,
+            where_clause,
+            database,
+        } => {
+            let format = format.unwrap_or_else(|| {
+                output.as_deref().map(export::ExportFormat::for_path).unwrap_or(export::ExportFormat::Ndjson)
+            });
+            let compression = compress.unwrap_or_else(|| {
+                output.as_deref().map(compression::Compression::for_path).unwrap_or(compression::Compression::None)
+            });
+            let options = export::ExportOptions {
+                table,
+                path: output,
+                format,
+                compression,
+                columns,
+                exclude,
+                filters,
+                where_clause,
+                database: database.unwrap_or_default(),
+            };
+            export::run_export(cli.server_address, options).await?;
+        }
+        Commands::Import { table, file, batch_size, on_conflict, resume, restart, mapping, create, coerce, database } => {
+            let options = import::ImportOptions {
+                table,
+                path: file,
+                batch_size: batch_size as usize,
+                on_conflict,
+                resume,
+                restart,
+                mapping,
+                create,
+                coerce,
+                database: database.unwrap_or_default(),
+            };
+            import::run_import(cli.server_address, options).await?;
         }
-        Commands::Insert { table, data, database } => {
-            commands::insert(cli.server_address, table, data, database).await?;
+        Commands::Tx { file, database } => {
+            tx::run_transaction(cli.server_address, file, database).await?;
         }
         Commands::Update {
             table,
             data,
             where_clause,
+            coerce,
             database,
         } => {
-            commands::update(cli.server_address, table, data, where_clause, database).await?;
+            commands::update(cli.server_address, table, data, where_clause, coerce, database).await?;
         }
         Commands::Delete {
             table,
@@ -87,20 +320,177 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         } => {
             commands::delete(cli.server_address, table, where_clause, database).await?;
         }
+        Commands::Search { table, query, limit, format, database } => {
+            commands::search(cli.server_address, table, query, limit, format, database).await?;
+        }
+        Commands::Within { table, bbox, limit, format, database } => {
+            commands::within(cli.server_address, table, bbox, limit, format, database).await?;
+        }
+        Commands::Sample { table, count, format, database } => {
+            commands::sample(cli.server_address, table, count, format, database).await?;
+        }
+        Commands::Tail { table, since_column, lines, interval, format, database } => {
+            let options = tail::TailOptions {
+                table,
+                since_column,
+                lines,
+                interval: std::time::Duration::from_secs_f64(interval),
+                format,
+                database: database.unwrap_or_default(),
+            };
+            tail::run(cli.server_address, options).await?;
+        }
+        Commands::Ping { count, interval } => {
+            commands::ping(cli.server_address, count, std::time::Duration::from_secs_f64(interval)).await?;
+        }
+        Commands::Doctor { database, schema } => {
+            if !doctor::run(cli.server_address, database, schema).await? {
+                std::process::exit(1);
+            }
+        }
+        Commands::Advise { database, apply, min_count } => {
+            commands::advise_indexes(cli.server_address, database, apply, min_count).await?;
+        }
+        Commands::Undo { database, last, id, table, list, dry_run } => {
+            if list || (last.is_none() && id.is_empty()) {
+                commands::list_changes(cli.server_address, database, table, last.unwrap_or(10)).await?;
+            } else {
+                commands::undo_changes(cli.server_address, database, last.unwrap_or(0), id, table, dry_run).await?;
+            }
+        }
+        Commands::CopyTable { source, target, where_clause, create, batch_size } => {
+            commands::copy_table(cli.server_address, source, target, where_clause, create, batch_size).await?;
+        }
+        Commands::Diff { source, target, table, key, where_clause, rows, limit } => {
+            commands::diff_table(cli.server_address, source, target, table, key, where_clause, rows, limit).await?;
+        }
+        Commands::Dedupe { table, by, keep, dry_run, database } => {
+            commands::dedupe(cli.server_address, table, by, keep, dry_run, database).await?;
+        }
+        Commands::Truncate { table, database, force } => {
+            commands::truncate(cli.server_address, table, database, force).await?;
+        }
+        Commands::Bench {
+            table,
+            rows,
+            concurrency,
+            batch_size,
+            queries,
+            database,
+        } => {
+            let options = bench::BenchOptions {
+                table,
+                rows,
+                concurrency,
+                batch_size,
+                queries,
+                database,
+            };
+            bench::run_bench(cli.server_address, options).await?;
+        }
+        Commands::Generate {
+            table,
+            rows,
+            overrides,
+            seed,
+            database,
+        } => {
+            generate::generate_data(cli.server_address, table, rows, overrides, seed, database).await?;
+        }
         Commands::Schema { command } => match command {
-            SchemaCommands::ListTables { database } => {
-                commands::list_tables(cli.server_address, database).await?;
+            SchemaCommands::ListTables { like, with_counts, database } => {
+                commands::list_tables(cli.server_address, like, with_counts, database).await?;
             }
             SchemaCommands::Describe { tables, database } => {
-                commands::describe_tables(cli.server_address, tables, database).await?;
+                commands::describe_tables(cli.server_address, tables, cli.verbose, database).await?;
             }
-            SchemaCommands::Stats { database, detailed } => {
-                commands::show_stats(cli.server_address, detailed, database).await?;
+            SchemaCommands::Stats { database, detailed, table, columns } => {
+                commands::show_stats(cli.server_address, detailed, table, columns, database).await?;
             }
             SchemaCommands::Show { database, format } => {
                 commands::show_schema(cli.server_address, format, database).await?;
             }
+            SchemaCommands::ForeignKeys { table, limit, database } => {
+                commands::check_foreign_keys(cli.server_address, table, limit, database).await?;
+            }
+            SchemaCommands::Version { database } => {
+                commands::schema_version(cli.server_address, database).await?;
+            }
+            SchemaCommands::Ddl { schema_file, dialect: _ } => {
+                commands::schema_ddl(schema_file).await?;
+            }
+            SchemaCommands::Plan { old_schema, new_schema, output, reverse } => {
+                commands::schema_plan(old_schema, new_schema, output, reverse).await?;
+            }
+        }
+        Commands::Jobs { command } => match command {
+            JobCommands::List => {
+                commands::list_jobs(cli.server_address).await?;
+            }
+            JobCommands::Add { name, schedule, sql, database } => {
+                commands::add_job(cli.server_address, name, schedule, sql, database).await?;
+            }
+            JobCommands::Runs { job, limit, database } => {
+                commands::list_job_runs(cli.server_address, job, limit, database).await?;
+            }
+            JobCommands::Remove { name } => {
+                commands::remove_job(cli.server_address, name).await?;
+            }
         }
+        Commands::Session { command } => match command {
+            SessionCommands::Open { database } => {
+                commands::open_session(cli.server_address, database).await?;
+            }
+            SessionCommands::Close { id } => {
+                commands::close_session(cli.server_address, id).await?;
+            }
+        },
+        Commands::Saved { command } => match command {
+            SavedCommands::Add { name, sql, description, replace, database } => {
+                commands::save_query(cli.server_address, name, sql, description, replace, database).await?;
+            }
+            SavedCommands::List { database } => {
+                commands::list_saved_queries(cli.server_address, database).await?;
+            }
+            SavedCommands::Run { name, params, format, database } => {
+                commands::run_saved_query(cli.server_address, name, params, format, database).await?;
+            }
+            SavedCommands::Remove { name, database } => {
+                commands::remove_saved_query(cli.server_address, name, database).await?;
+            }
+        },
+        Commands::Sink { command } => match command {
+            SinkCommands::Kafka {
+                brokers,
+                topic,
+                table,
+                group_id,
+                mapping,
+                batch_size,
+                batch_timeout,
+                on_conflict,
+                from_beginning,
+                database,
+            } => {
+                let options = sink::KafkaSinkOptions {
+                    brokers,
+                    topic,
+                    table,
+                    group_id,
+                    mapping,
+                    batch_size,
+                    batch_timeout: std::time::Duration::from_millis(batch_timeout),
+                    on_conflict: if on_conflict == "ignore" {
+                        proto::crud::OnConflict::Ignore
+                    } else {
+                        proto::crud::OnConflict::Abort
+                    },
+                    from_beginning,
+                    database,
+                };
+                sink::run_kafka_sink(cli.server_address, options).await?;
+            }
+        },
     }
 
     Ok(())
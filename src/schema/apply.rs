@@ -0,0 +1,194 @@
+//! Creating a schema's objects and seed data in a database, shared by
+//! `datasink server create-from-schema` and the ProvisionDatabase RPC.
+//!
+//! Objects are created in the order `schema ddl` prints them, with seed data
+//! loaded after the search and spatial indexes (so their triggers index it)
+//! and before materialized views (so they are filled from it). A table,
+//! index or view that cannot be created is reported as a warning and the
+//! rest of the schema is still applied.
+
+use super::{parser, Schema};
+use crate::db::partition::Partitioning;
+use crate::db::traits::{Descriptions, MaterializedView};
+use crate::db::{ColumnCipher, Database, DatabaseError, SqliteDatabase};
+
+/// Progress reported while a schema is applied
+pub enum Event {
+    /// A step starting or finishing, e.g. "Creating table: users"
+    Progress(String),
+    /// A step that failed without stopping the rest
+    Warning(String),
+}
+
+/// Apply `schema` to `db` and record it as applied with `checksum`. Seed data
+/// for encrypted columns is encrypted with `cipher`. Errors in seed data and
+/// a missing key stop the rest of the schema from being applied.
+pub async fn apply(
+    db: &dyn Database,
+    schema: &Schema,
+    checksum: &str,
+    cipher: Option<&ColumnCipher>,
+    report: &mut (dyn FnMut(Event) + Send),
+) -> Result<(), String> {
+    for table in &schema.tables {
+        report(Event::Progress(format!("Creating table: {}", table.name)));
+
+        let db_columns = table
+            .columns
+            .iter()
+            .map(parser::column_def_to_db)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let created = match &table.partition {
+            Some(_) if !table.unique.is_empty() => Err(DatabaseError::QueryError(
+                "UNIQUE constraints are not supported on partitioned tables".to_string(),
+            )),
+            Some(def) => match def.period.parse() {
+                Ok(period) => {
+                    let partitioning = Partitioning {
+                        column: def.column.clone(),
+                        period,
+                        retention: def.retention,
+                    };
+                    db.create_partitioned_table(&table.name, db_columns, &partitioning).await
+                }
+                Err(e) => Err(e),
+            },
+            None => db.create_table_with_unique(&table.name, db_columns, &table.unique).await,
+        };
+        if let Err(e) = created {
+            report(Event::Warning(format!("Failed to create table {}: {}", table.name, e)));
+        }
+
+        // Before the seed data is loaded, so it is the first version of its rows
+        if table.history {
+            if let Err(e) = db.enable_history(&table.name).await {
+                report(Event::Warning(format!("Failed to enable history on {}: {}", table.name, e)));
+            }
+        }
+
+        let descriptions = Descriptions {
+            table: table.description.clone(),
+            columns: table.columns.iter()
+                .filter_map(|c| Some((c.name.clone(), c.description.clone()?)))
+                .collect(),
+        };
+        if !descriptions.is_empty() {
+            if let Err(e) = db.set_descriptions(&table.name, &descriptions).await {
+                report(Event::Warning(format!("Failed to record descriptions of {}: {}", table.name, e)));
+            }
+        }
+
+        if let Some(column) = &table.ttl_column {
+            if let Err(e) = db.set_ttl_column(&table.name, Some(column)).await {
+                report(Event::Warning(format!("Failed to set TTL column on {}: {}", table.name, e)));
+            }
+        }
+
+        let encrypted: Vec<String> = table.columns.iter()
+            .filter(|c| c.encrypted)
+            .map(|c| c.name.clone())
+            .collect();
+        if !encrypted.is_empty() {
+            if let Err(e) = db.set_encrypted_columns(&table.name, &encrypted).await {
+                report(Event::Warning(format!("Failed to mark encrypted columns on {}: {}", table.name, e)));
+            }
+        }
+    }
+
+    for index in &schema.indexes {
+        report(Event::Progress(format!(
+            "Creating index: {} on {} ({})",
+            index.name,
+            index.table,
+            index.columns.join(", ")
+        )));
+        let sql = SqliteDatabase::create_index_sql(&index.name, &index.table, &index.columns);
+        if let Err(e) = db.execute(&sql).await {
+            report(Event::Warning(format!("Failed to create index {}: {}", index.name, e)));
+        }
+    }
+
+    // Create search indexes before loading data so the triggers index it
+    for index in &schema.search_indexes {
+        report(Event::Progress(format!("Creating search index: {} ({})", index.table, index.columns.join(", "))));
+        if let Err(e) = db.create_search_index(&index.table, &index.columns).await {
+            report(Event::Warning(format!("Failed to create search index on {}: {}", index.table, e)));
+        }
+    }
+    for index in &schema.spatial_indexes {
+        report(Event::Progress(format!("Creating spatial index: {} ({})", index.table, index.columns.join(", "))));
+        if let Err(e) = db.create_spatial_index(&index.table, &index.columns).await {
+            report(Event::Warning(format!("Failed to create spatial index on {}: {}", index.table, e)));
+        }
+    }
+
+    // Insert initial data
+    for (table_name, data) in &schema.data {
+        let table_def = schema.tables.iter()
+            .find(|t| t.name == *table_name)
+            .ok_or_else(|| format!("Table {} not found in schema", table_name))?;
+
+        let rows = data.rows(table_def)?;
+        if rows.is_empty() {
+            continue;
+        }
+
+        report(Event::Progress(format!("Inserting data into table: {}", table_name)));
+
+        // Seed data for encrypted columns is stored encrypted as well
+        let encrypted: Vec<&str> = table_def.columns.iter()
+            .filter(|c| c.encrypted)
+            .map(|c| c.name.as_str())
+            .collect();
+        let cipher = match cipher {
+            _ if encrypted.is_empty() => None,
+            Some(cipher) => Some(cipher),
+            None => {
+                return Err(format!(
+                    "Table {} has encrypted columns; set {} to load its data",
+                    table_name,
+                    crate::db::encryption::ENCRYPTION_KEY_ENV
+                ))
+            }
+        };
+
+        let mut db_rows = Vec::new();
+        for row_data in &rows {
+            let mut values = parser::prepare_insert_data_db(table_def, row_data).map_err(|e| e.to_string())?;
+            if let Some(cipher) = cipher {
+                for column in &encrypted {
+                    if let Some(value) = values.get_mut(*column) {
+                        *value = cipher.encrypt(value, table_name, column).map_err(|e| e.to_string())?;
+                    }
+                }
+            }
+            db_rows.push(values);
+        }
+
+        match db.batch_insert(table_name, db_rows).await {
+            Ok(count) => report(Event::Progress(format!("  Inserted {} rows", count))),
+            Err(e) => report(Event::Warning(format!("Failed to insert data into {}: {}", table_name, e))),
+        }
+    }
+
+    // Materialized views are filled once their source tables have data
+    for view in &schema.materialized_views {
+        report(Event::Progress(format!("Creating materialized view: {}", view.name)));
+        let db_view = MaterializedView {
+            name: view.name.clone(),
+            query: view.query.clone(),
+            refresh_interval_seconds: view.refresh_interval_seconds,
+            last_refreshed: None,
+        };
+        match db.create_materialized_view(&db_view).await {
+            Ok(rows) => report(Event::Progress(format!("  {} rows", rows))),
+            Err(e) => report(Event::Warning(format!("Failed to create materialized view {}: {}", view.name, e))),
+        }
+    }
+
+    db.record_applied_schema(&schema.database.name, &schema.database.version, checksum)
+        .await
+        .map_err(|e| e.to_string())
+}
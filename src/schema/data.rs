@@ -0,0 +1,274 @@
+//! Seed rows for a table, written inline in the schema file or kept in a
+//! separate CSV or NDJSON file:
+//!
+//! ```toml
+//! [data.users]
+//! file = "seed/users.csv"
+//! ```
+//!
+//! Files are read a line at a time. CSV files start with a header row naming
+//! the columns; an empty field leaves the column out of the row (NULL or its
+//! default). Text fields, and JSON values of another type, are converted to
+//! the column's type, so `42` in a CSV file becomes an INTEGER.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use super::{ColumnDef, TableDef};
+use crate::compression;
+
+/// A seed row: column name to value
+pub type Row = HashMap<String, toml::Value>;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TableData {
+    /// `[[data.users]]` tables in the schema file
+    Rows(Vec<Row>),
+    /// A `.csv`, `.ndjson` or `.jsonl` file, relative to the schema file
+    /// that names it; `.gz` and `.zst` files are decompressed
+    File { file: PathBuf },
+}
+
+impl TableData {
+    /// The table's rows, reading them from its file if it has one
+    pub fn rows(&self, table: &TableDef) -> Result<Vec<Row>, String> {
+        match self {
+            TableData::Rows(rows) => Ok(rows.clone()),
+            TableData::File { file } => read_file(file, table),
+        }
+    }
+}
+
+/// Rows of a CSV or NDJSON file (optionally `.gz` or `.zst` compressed),
+/// converted to the table's column types
+pub fn read_file(path: &Path, table: &TableDef) -> Result<Vec<Row>, String> {
+    let error = |e: String| format!("{}: {}", path.display(), e);
+    let reader = compression::open(path).map_err(|e| error(e.to_string()))?;
+    let name = path.to_string_lossy();
+    let extension = Path::new(compression::strip_extension(&name)).extension().and_then(|ext| ext.to_str());
+    match extension.map(str::to_lowercase).as_deref() {
+        Some("csv") => read_csv(reader, table).map_err(error),
+        Some("ndjson") | Some("jsonl") => read_ndjson(reader, table).map_err(error),
+        _ => Err(error("data files must be .csv, .ndjson or .jsonl (optionally .gz or .zst)".to_string())),
+    }
+}
+
+fn read_csv(reader: impl BufRead, table: &TableDef) -> Result<Vec<Row>, String> {
+    let mut lines = reader.lines();
+    let header = next_csv_record(&mut lines)?.ok_or("missing header row")?;
+    let columns = header.iter().map(|name| column(table, name.trim())).collect::<Result<Vec<_>, _>>()?;
+
+    let mut rows = Vec::new();
+    while let Some(record) = next_csv_record(&mut lines)? {
+        let number = rows.len() + 1;
+        if record.len() == 1 && record[0].is_empty() {
+            continue;
+        }
+        if record.len() != columns.len() {
+            return Err(format!("row {}: expected {} fields, found {}", number, columns.len(), record.len()));
+        }
+        let mut row = Row::new();
+        for (column, field) in columns.iter().zip(record) {
+            if !field.is_empty() {
+                let value = coerce_text(&field, column).map_err(|e| format!("row {}: {}", number, e))?;
+                row.insert(column.name.clone(), value);
+            }
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn read_ndjson(reader: impl BufRead, table: &TableDef) -> Result<Vec<Row>, String> {
+    let mut rows = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |e: String| format!("line {}: {}", i + 1, e);
+        let fields = match serde_json::from_str(&line).map_err(|e| error(e.to_string()))? {
+            JsonValue::Object(fields) => fields,
+            _ => return Err(error("expected a JSON object".to_string())),
+        };
+        let mut row = Row::new();
+        for (name, value) in fields {
+            let column = column(table, &name).map_err(error)?;
+            if let Some(value) = coerce_json(value, column).map_err(error)? {
+                row.insert(column.name.clone(), value);
+            }
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn column<'a>(table: &'a TableDef, name: &str) -> Result<&'a ColumnDef, String> {
+    table
+        .columns
+        .iter()
+        .find(|column| column.name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("table '{}' has no column '{}'", table.name, name))
+}
+
+/// The next CSV record, joining lines while a quoted field is open;
+/// `None` at the end of the input
+pub fn next_csv_record(lines: &mut impl Iterator<Item = std::io::Result<String>>) -> Result<Option<Vec<String>>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut first_line = true;
+    loop {
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| e.to_string())?,
+            None if first_line => return Ok(None),
+            None => return Err("unterminated quoted field".to_string()),
+        };
+        if !first_line {
+            field.push('\n');
+        }
+        first_line = false;
+
+        let mut chars = line.strip_suffix('\r').unwrap_or(&line).chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(std::mem::take(&mut field)),
+                c => field.push(c),
+            }
+        }
+        if !quoted {
+            fields.push(field);
+            return Ok(Some(fields));
+        }
+    }
+}
+
+/// A text field as a value of the column's type
+fn coerce_text(text: &str, column: &ColumnDef) -> Result<toml::Value, String> {
+    let invalid = || format!("'{}' is not a valid {} for column '{}'", text, column.col_type, column.name);
+    Ok(match column.col_type.to_uppercase().as_str() {
+        "INTEGER" => toml::Value::Integer(text.trim().parse().map_err(|_| invalid())?),
+        "TIMESTAMP" if text == "CURRENT_TIMESTAMP" => toml::Value::String(text.to_string()),
+        "TIMESTAMP" => toml::Value::Integer(text.trim().parse().map_err(|_| invalid())?),
+        "REAL" => toml::Value::Float(text.trim().parse().map_err(|_| invalid())?),
+        "BOOLEAN" => match text.trim().to_lowercase().as_str() {
+            "true" | "1" => toml::Value::Boolean(true),
+            "false" | "0" => toml::Value::Boolean(false),
+            _ => return Err(invalid()),
+        },
+        _ => toml::Value::String(text.to_string()),
+    })
+}
+
+/// A JSON value as a value of the column's type; `None` for null
+fn coerce_json(value: JsonValue, column: &ColumnDef) -> Result<Option<toml::Value>, String> {
+    let col_type = column.col_type.to_uppercase();
+    Ok(Some(match value {
+        JsonValue::Null => return Ok(None),
+        JsonValue::String(s) => coerce_text(&s, column)?,
+        JsonValue::Bool(b) if col_type == "BOOLEAN" => toml::Value::Boolean(b),
+        JsonValue::Number(n) if col_type == "REAL" => {
+            toml::Value::Float(n.as_f64().ok_or_else(|| format!("{} is out of range", n))?)
+        }
+        JsonValue::Number(n) if col_type == "INTEGER" || col_type == "TIMESTAMP" => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => return Err(format!("{} is not a valid {} for column '{}'", n, column.col_type, column.name)),
+        },
+        // Objects and arrays are stored as JSON text
+        value @ (JsonValue::Object(_) | JsonValue::Array(_)) if col_type == "TEXT" => toml::Value::String(value.to_string()),
+        value => coerce_text(&value.to_string(), column)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = r#"
+name = "users"
+
+[[columns]]
+name = "id"
+type = "INTEGER"
+primary_key = true
+
+[[columns]]
+name = "name"
+type = "TEXT"
+
+[[columns]]
+name = "score"
+type = "REAL"
+nullable = true
+
+[[columns]]
+name = "active"
+type = "BOOLEAN"
+nullable = true
+"#;
+
+    #[test]
+    fn test_read_csv() {
+        let table: TableDef = toml::from_str(TABLE).unwrap();
+        let csv = "id,Name,score,active\r\n1,\"Smith, Jo\",1.5,true\n\n2,\"Say \"\"hi\"\"\nthere\",,0\n";
+        let rows = read_csv(csv.as_bytes(), &table).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["id"], toml::Value::Integer(1));
+        assert_eq!(rows[0]["name"], toml::Value::String("Smith, Jo".to_string()));
+        assert_eq!(rows[0]["score"], toml::Value::Float(1.5));
+        assert_eq!(rows[1]["name"], toml::Value::String("Say \"hi\"\nthere".to_string()));
+        assert!(!rows[1].contains_key("score"));
+        assert_eq!(rows[1]["active"], toml::Value::Boolean(false));
+
+        let err = read_csv("id,name\nx,Jo\n".as_bytes(), &table).unwrap_err();
+        assert_eq!(err, "row 1: 'x' is not a valid INTEGER for column 'id'");
+        let err = read_csv("id,nickname\n".as_bytes(), &table).unwrap_err();
+        assert_eq!(err, "table 'users' has no column 'nickname'");
+        assert!(read_csv("id,name\n1\n".as_bytes(), &table).is_err());
+        assert!(read_csv("id,name\n1,\"open\n".as_bytes(), &table).is_err());
+    }
+
+    #[test]
+    fn test_read_ndjson() {
+        let table: TableDef = toml::from_str(TABLE).unwrap();
+        let ndjson = "{\"id\": 1, \"name\": \"Jo\", \"score\": 2, \"active\": true}\n\n{\"id\": \"2\", \"name\": \"Al\", \"score\": null}\n";
+        let rows = read_ndjson(ndjson.as_bytes(), &table).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["score"], toml::Value::Float(2.0));
+        assert_eq!(rows[0]["active"], toml::Value::Boolean(true));
+        assert_eq!(rows[1]["id"], toml::Value::Integer(2));
+        assert!(!rows[1].contains_key("score"));
+
+        let err = read_ndjson("{\"id\": 1.5}\n".as_bytes(), &table).unwrap_err();
+        assert_eq!(err, "line 1: 1.5 is not a valid INTEGER for column 'id'");
+        assert!(read_ndjson("[1, 2]\n".as_bytes(), &table).is_err());
+    }
+
+    #[test]
+    fn test_read_compressed_file() {
+        use std::io::Write;
+
+        let table: TableDef = toml::from_str(TABLE).unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("users.csv.gz");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut encoder = compression::Encoder::new(file, compression::Compression::Gzip).unwrap();
+        encoder.write_all(b"id,name\n1,Jo\n2,Al\n").unwrap();
+        encoder.finish().unwrap();
+
+        let rows = read_file(&path, &table).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["name"], toml::Value::String("Al".to_string()));
+        assert!(read_file(&dir.path().join("users.txt.gz"), &table).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! The SQL a schema file produces, generated without a database
+//! (`datasink schema ddl`).
+//!
+//! Statements come out in the order `create-from-schema` runs them: tables
+//! (each followed by its history table), indexes, search and spatial
+//! indexes, then materialized views. Seed data
+//! and DataSink's own metadata tables (TTL, encrypted columns, partitions)
+//! are left out.
+
+use chrono::{DateTime, Utc};
+
+use super::{parser, Schema};
+use crate::db::partition::{self, PartitionPeriod};
+use crate::db::SqliteDatabase;
+
+/// Statements creating the schema's objects, without trailing semicolons.
+/// A partitioned table is created as its partition for `now` and the view
+/// over it.
+pub fn statements(schema: &Schema, now: DateTime<Utc>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut statements = Vec::new();
+
+    for table in &schema.tables {
+        let columns = table.columns.iter().map(parser::column_def_to_db).collect::<Result<Vec<_>, _>>()?;
+        match &table.partition {
+            Some(_) if !table.unique.is_empty() => {
+                return Err(format!("Table '{}': UNIQUE constraints are not supported on partitioned tables", table.name).into());
+            }
+            Some(_) if table.history => {
+                return Err(format!("Table '{}': history is not supported on partitioned tables", table.name).into());
+            }
+            Some(def) => {
+                let period: PartitionPeriod = def.period.parse()?;
+                let partition = partition::partition_name(&table.name, &period.suffix(now));
+                statements.push(SqliteDatabase::build_create_table_sql(&partition, &columns, &[]));
+                statements.push(SqliteDatabase::partition_view_sql(&table.name, &[partition]));
+            }
+            None => statements.push(SqliteDatabase::build_create_table_sql(&table.name, &columns, &table.unique)),
+        }
+        if table.history {
+            statements.extend(SqliteDatabase::history_sql(&table.name, &columns));
+        }
+    }
+
+    for index in &schema.indexes {
+        statements.push(SqliteDatabase::create_index_sql(&index.name, &index.table, &index.columns));
+    }
+    for index in &schema.search_indexes {
+        statements.extend(SqliteDatabase::search_index_sql(&index.table, &index.columns));
+    }
+    for index in &schema.spatial_indexes {
+        statements.extend(SqliteDatabase::spatial_index_sql(&index.table, &index.columns)?);
+    }
+    for view in &schema.materialized_views {
+        statements.push(format!("CREATE TABLE {} AS {}", view.name, view.query));
+    }
+
+    Ok(statements)
+}
+
+/// The statements as an SQL script
+pub fn script(schema: &Schema, statements: &[String]) -> String {
+    let mut script = format!(
+        "-- Schema '{}' version {}\n",
+        schema.database.name, schema.database.version
+    );
+    for statement in statements {
+        script.push_str(&format!("\n{};\n", statement));
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const SCHEMA: &str = r#"
+[database]
+name = "shop"
+description = "Test shop"
+version = "2.1.0"
+
+[[tables]]
+name = "products"
+unique = [["id", "name"]]
+
+[[tables.columns]]
+name = "id"
+type = "INTEGER"
+primary_key = true
+
+[[tables.columns]]
+name = "name"
+type = "TEXT"
+nullable = false
+unique = true
+collation = "nocase"
+
+[[tables.columns]]
+name = "slug"
+type = "TEXT"
+nullable = true
+generated = "lower(name)"
+
+[[tables]]
+name = "events"
+partition = { column = "at", period = "month" }
+
+[[tables.columns]]
+name = "at"
+type = "TIMESTAMP"
+nullable = true
+
+[[indexes]]
+table = "products"
+name = "idx_products_name"
+columns = ["name"]
+
+[[search_indexes]]
+table = "products"
+columns = ["name"]
+
+[[materialized_views]]
+name = "product_count"
+query = "SELECT COUNT(*) AS n FROM products"
+"#;
+
+    #[test]
+    fn test_statements() {
+        let schema: Schema = toml::from_str(SCHEMA).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 3, 15, 0, 0, 0).unwrap();
+        let statements = statements(&schema, now).unwrap();
+
+        assert_eq!(statements[0], "CREATE TABLE products (id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE NOT NULL UNIQUE, slug TEXT GENERATED ALWAYS AS (lower(name)) VIRTUAL, UNIQUE (id, name))");
+        assert_eq!(statements[1], "CREATE TABLE events_2024_03 (at TIMESTAMP)");
+        assert_eq!(statements[2], "CREATE VIEW events AS SELECT * FROM events_2024_03");
+        assert_eq!(statements[3], "CREATE INDEX idx_products_name ON products (name)");
+        assert!(statements[4].starts_with("CREATE VIRTUAL TABLE products_fts USING fts5(name"));
+        assert_eq!(statements.len(), 10);
+        assert_eq!(statements[9], "CREATE TABLE product_count AS SELECT COUNT(*) AS n FROM products");
+
+        let script = script(&schema, &statements);
+        assert!(script.starts_with("-- Schema 'shop' version 2.1.0\n\nCREATE TABLE products"));
+        assert!(script.ends_with("FROM products;\n"));
+    }
+
+    #[test]
+    fn test_statements_reject_bad_definitions() {
+        let schema: Schema = toml::from_str(&SCHEMA.replace("\"TEXT\"", "\"STRING\"")).unwrap();
+        assert!(statements(&schema, Utc::now()).is_err());
+        let schema: Schema = toml::from_str(&SCHEMA.replace("\"month\"", "\"fortnight\"")).unwrap();
+        assert!(statements(&schema, Utc::now()).is_err());
+        let schema: Schema = toml::from_str(&SCHEMA.replace("\"nocase\"", "\"german\"")).unwrap();
+        assert!(statements(&schema, Utc::now()).is_err());
+        let schema: Schema = toml::from_str(&SCHEMA.replace("period = \"month\" }", "period = \"month\" }\nunique = [[\"at\"]]")).unwrap();
+        assert!(statements(&schema, Utc::now()).is_err());
+    }
+}
@@ -1,19 +1,41 @@
+pub mod apply;
+pub mod data;
+pub mod ddl;
 pub mod parser;
+pub mod plan;
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Serialize)]
+pub use data::TableData;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Schema {
+    /// Required in the schema file that is loaded; ignored in included files
+    #[serde(default)]
     pub database: DatabaseInfo,
+    /// Other schema files whose definitions are merged in before this file's,
+    /// relative to this file
+    #[serde(default)]
+    pub includes: Vec<String>,
+    /// Named groups of columns that tables add with `include_columns`
+    #[serde(default)]
+    pub column_sets: HashMap<String, Vec<ColumnDef>>,
+    #[serde(default)]
     pub tables: Vec<TableDef>,
     #[serde(default)]
-    pub data: HashMap<String, Vec<HashMap<String, toml::Value>>>,
+    pub data: HashMap<String, TableData>,
     #[serde(default)]
     pub indexes: Vec<IndexDef>,
+    #[serde(default)]
+    pub search_indexes: Vec<SearchIndexDef>,
+    #[serde(default)]
+    pub spatial_indexes: Vec<SpatialIndexDef>,
+    #[serde(default)]
+    pub materialized_views: Vec<MaterializedViewDef>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct DatabaseInfo {
     pub name: String,
     pub description: String,
@@ -24,14 +46,43 @@ pub struct DatabaseInfo {
 pub struct TableDef {
     pub name: String,
     pub description: Option<String>,
+    #[serde(default)]
     pub columns: Vec<ColumnDef>,
+    /// Column sets whose columns are added after `columns`, in order
+    #[serde(default)]
+    pub include_columns: Vec<String>,
+    /// Column combinations whose values must be unique together,
+    /// e.g. `[["org_id", "email"]]`
+    #[serde(default)]
+    pub unique: Vec<Vec<String>>,
+    /// Column holding each row's expiry time (Unix seconds or ISO-8601);
+    /// the server deletes rows once it has passed
+    pub ttl_column: Option<String>,
+    /// Store rows in one table per period of this column's time
+    pub partition: Option<PartitionDef>,
+    /// Keep every version of the rows in `<name>__history` so queries can
+    /// read the table `AS OF` a past time
+    #[serde(default)]
+    pub history: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Time-based partitioning of a table
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct PartitionDef {
+    /// Column holding each row's time (Unix seconds or ISO-8601)
+    pub column: String,
+    /// "day", "month" or "year"
+    pub period: String,
+    /// Number of periods to keep, including the current one (default: all)
+    pub retention: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ColumnDef {
     pub name: String,
     #[serde(rename = "type")]
     pub col_type: String,
+    pub description: Option<String>,
     #[serde(default)]
     pub nullable: bool,
     #[serde(default)]
@@ -41,15 +92,54 @@ pub struct ColumnDef {
     #[serde(default)]
     pub auto_increment: bool,
     pub default: Option<String>,
+    /// Expression computing the column from other columns of the row
+    /// (e.g. "price * quantity"); such columns cannot be written
+    pub generated: Option<String>,
+    /// Store generated values when rows are written rather than computing
+    /// them on read
+    #[serde(default)]
+    pub stored: bool,
+    /// "NOCASE", "BINARY" or "RTRIM"
+    pub collation: Option<String>,
+    /// Text values the column is limited to, e.g. ["low", "medium", "high"]
+    pub values: Option<Vec<String>>,
     pub foreign_key: Option<ForeignKeyDef>,
+    /// Store values encrypted with the server's key (see `db::encryption`)
+    #[serde(default)]
+    pub encrypted: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ForeignKeyDef {
     pub table: String,
     pub column: String,
 }
 
+/// A full-text (FTS5) index over text columns of a table
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SearchIndexDef {
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+/// An R*Tree index over coordinate columns: `[x, y]` for points or
+/// `[min_x, min_y, max_x, max_y]` for boxes
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SpatialIndexDef {
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+/// A table kept filled with the result of `query`, rebuilt every
+/// `refresh_interval_seconds` (0 or absent: only on demand)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MaterializedViewDef {
+    pub name: String,
+    pub query: String,
+    #[serde(default)]
+    pub refresh_interval_seconds: u64,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct IndexDef {
     pub table: String,
@@ -1,13 +1,176 @@
-use super::{ColumnDef, Schema};
-use crate::db::{traits::ColumnDef as DbColumnDef, traits::ColumnType, traits::DbValue};
+use super::{ColumnDef, Schema, TableData};
+use crate::db::{SqliteDatabase, traits::ColumnDef as DbColumnDef, traits::ColumnType, traits::DbValue, traits::GeneratedColumn as DbGeneratedColumn};
 use crate::proto::common::{value, Value};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// A schema file read while resolving includes
+struct SchemaFile {
+    path: PathBuf,
+    canonical: PathBuf,
+    content: String,
+    schema: Schema,
+}
 
 pub async fn load_schema(path: &Path) -> Result<Schema, Box<dyn std::error::Error>> {
-    let content = tokio::fs::read_to_string(path).await?;
-    let schema: Schema = toml::from_str(&content)?;
-    Ok(schema)
+    Ok(load_schema_with_checksum(path).await?.0)
+}
+
+/// Load a schema file and the files it includes, merged into one schema,
+/// along with the checksum recorded when it is applied
+pub async fn load_schema_with_checksum(path: &Path) -> Result<(Schema, String), Box<dyn std::error::Error>> {
+    let path = path.to_path_buf();
+    let files = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        read_schema_files(&path, &mut Vec::new(), &mut files).map(|_| files)
+    })
+    .await??;
+
+    let content: String = files.iter().map(|file| file.content.as_str()).collect();
+    let mut schema = merge_schema_files(files)?;
+    expand_column_sets(&mut schema)?;
+    Ok((schema, checksum(&content)))
+}
+
+/// Parse a schema sent as text rather than loaded from a file, along with
+/// its checksum. With no location to resolve paths against, it cannot
+/// include other files or read seed data from files.
+pub fn parse_schema(content: &str) -> Result<(Schema, String), String> {
+    let schema: Schema = toml::from_str(content).map_err(|e| e.to_string())?;
+    if !schema.includes.is_empty() {
+        return Err("An uploaded schema cannot include other files".to_string());
+    }
+    if let Some(table) = schema.data.iter().find(|(_, data)| matches!(data, TableData::File { .. })).map(|(table, _)| table) {
+        return Err(format!("Data for table '{}' is read from a file, which an uploaded schema cannot do", table));
+    }
+
+    let path = PathBuf::from("(uploaded schema)");
+    let file = SchemaFile { canonical: path.clone(), path, content: content.to_string(), schema };
+    let mut schema = merge_schema_files(vec![file])?;
+    expand_column_sets(&mut schema)?;
+    Ok((schema, checksum(content)))
+}
+
+/// Read `path` into `files`, after the files it includes (depth first).
+/// Include paths are relative to the including file; a file reached twice
+/// is read once.
+fn read_schema_files(path: &Path, stack: &mut Vec<PathBuf>, files: &mut Vec<SchemaFile>) -> Result<(), String> {
+    let canonical = std::fs::canonicalize(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if let Some(start) = stack.iter().position(|p| *p == canonical) {
+        let cycle: Vec<String> = stack[start..].iter().chain([&canonical]).map(|p| p.display().to_string()).collect();
+        return Err(format!("Schema include cycle: {}", cycle.join(" -> ")));
+    }
+    if files.iter().any(|file| file.canonical == canonical) {
+        return Ok(());
+    }
+
+    let content = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let schema: Schema = toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    stack.push(canonical.clone());
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for include in &schema.includes {
+        read_schema_files(&dir.join(include), stack, files)?;
+    }
+    stack.pop();
+
+    files.push(SchemaFile { path: path.to_path_buf(), canonical, content, schema });
+    Ok(())
+}
+
+/// Combine schema files in order; the last is the file that was loaded,
+/// whose `[database]` section describes the result
+fn merge_schema_files(mut files: Vec<SchemaFile>) -> Result<Schema, String> {
+    let root = files.last_mut().ok_or("No schema file")?;
+    if root.schema.database.name.is_empty() {
+        return Err(format!("{}: missing [database] section", root.path.display()));
+    }
+    let mut merged = Schema { database: std::mem::take(&mut root.schema.database), ..Default::default() };
+
+    // Where each named object was first defined, to report duplicates
+    let mut defined: HashMap<(&'static str, String), PathBuf> = HashMap::new();
+    let mut define = |kind: &'static str, name: &str, path: &Path| match defined.get(&(kind, name.to_lowercase())) {
+        Some(first) => Err(format!(
+            "{} '{}' is defined more than once ({}, {})",
+            kind,
+            name,
+            first.display(),
+            path.display()
+        )),
+        None => {
+            defined.insert((kind, name.to_lowercase()), path.to_path_buf());
+            Ok(())
+        }
+    };
+
+    for SchemaFile { path, schema, .. } in files {
+        for (name, columns) in schema.column_sets {
+            define("Column set", &name, &path)?;
+            merged.column_sets.insert(name, columns);
+        }
+        for table in schema.tables {
+            define("Table", &table.name, &path)?;
+            merged.tables.push(table);
+        }
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for (table, data) in schema.data {
+            define("Data for table", &table, &path)?;
+            let data = match data {
+                TableData::File { file } => TableData::File { file: dir.join(file) },
+                rows => rows,
+            };
+            merged.data.insert(table, data);
+        }
+        for index in schema.indexes {
+            define("Index", &index.name, &path)?;
+            merged.indexes.push(index);
+        }
+        for index in schema.search_indexes {
+            define("Search index on", &index.table, &path)?;
+            merged.search_indexes.push(index);
+        }
+        for index in schema.spatial_indexes {
+            define("Spatial index on", &index.table, &path)?;
+            merged.spatial_indexes.push(index);
+        }
+        for view in schema.materialized_views {
+            define("Materialized view", &view.name, &path)?;
+            merged.materialized_views.push(view);
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Add the columns of each table's `include_columns` sets to the table
+fn expand_column_sets(schema: &mut Schema) -> Result<(), String> {
+    for table in &mut schema.tables {
+        for set_name in std::mem::take(&mut table.include_columns) {
+            let set = schema
+                .column_sets
+                .get(&set_name)
+                .ok_or_else(|| format!("Table '{}' includes unknown column set '{}'", table.name, set_name))?;
+            for column in set {
+                if table.columns.iter().any(|c| c.name.eq_ignore_ascii_case(&column.name)) {
+                    return Err(format!(
+                        "Column '{}' of set '{}' is already defined in table '{}'",
+                        column.name, set_name, table.name
+                    ));
+                }
+                table.columns.push(column.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 of schema file contents, hex encoded
+pub fn checksum(content: &str) -> String {
+    ring::digest::digest(&ring::digest::SHA256, content.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
 
 pub fn column_def_to_db(col: &ColumnDef) -> Result<DbColumnDef, Box<dyn std::error::Error>> {
@@ -30,11 +193,17 @@ pub fn column_def_to_db(col: &ColumnDef) -> Result<DbColumnDef, Box<dyn std::err
 
     Ok(DbColumnDef {
         name: col.name.clone(),
-        col_type,
         nullable,
         primary_key: col.primary_key,
         unique: col.unique,
-        default_value: col.default.clone(),
+        default_value: col.default.as_deref().map(|d| SqliteDatabase::default_sql(&col.name, d, &col_type)).transpose()?,
+        col_type,
+        generated: col.generated.as_ref().map(|expression| DbGeneratedColumn {
+            expression: expression.clone(),
+            stored: col.stored,
+        }),
+        collation: col.collation.as_deref().map(str::parse).transpose()?,
+        allowed_values: col.values.clone(),
     })
 }
 
@@ -78,6 +247,21 @@ pub fn toml_value_to_proto(
     Ok(proto_value)
 }
 
+/// Fail unless a value is one of the column's `values`, if it has any
+fn check_allowed_value(col: &ColumnDef, value: &toml::Value) -> Result<(), Box<dyn std::error::Error>> {
+    match (&col.values, value) {
+        (None, _) => Ok(()),
+        (Some(values), toml::Value::String(s)) if values.contains(s) => Ok(()),
+        (Some(values), _) => Err(format!(
+            "{} is not an allowed value for column '{}' (expected one of: {})",
+            value,
+            col.name,
+            values.join(", ")
+        )
+        .into()),
+    }
+}
+
 pub fn prepare_insert_data(
     table_def: &super::TableDef,
     row_data: &HashMap<String, toml::Value>,
@@ -85,12 +269,13 @@ pub fn prepare_insert_data(
     let mut values = HashMap::new();
 
     for col in &table_def.columns {
-        // Skip auto-increment columns
-        if col.auto_increment {
+        // Skip auto-increment and generated columns
+        if col.auto_increment || col.generated.is_some() {
             continue;
         }
 
         if let Some(value) = row_data.get(&col.name) {
+            check_allowed_value(col, value)?;
             let proto_value = toml_value_to_proto(value, &col.col_type)?;
             values.insert(col.name.clone(), proto_value);
         } else if let Some(default) = &col.default {
@@ -178,12 +363,13 @@ pub fn prepare_insert_data_db(
     let mut values = HashMap::new();
 
     for col in &table_def.columns {
-        // Skip auto-increment columns
-        if col.auto_increment {
+        // Skip auto-increment and generated columns
+        if col.auto_increment || col.generated.is_some() {
             continue;
         }
 
         if let Some(value) = row_data.get(&col.name) {
+            check_allowed_value(col, value)?;
             let db_value = toml_value_to_db(value, &col.col_type)?;
             values.insert(col.name.clone(), db_value);
         } else if let Some(default) = &col.default {
@@ -266,7 +452,6 @@ mod tests {
     use super::*;
     use crate::schema::TableDef;
     use tempfile::NamedTempFile;
-    use tokio;
 
     #[tokio::test]
     async fn test_load_schema() {
@@ -301,6 +486,158 @@ nullable = false
         assert_eq!(schema.tables.len(), 1);
         assert_eq!(schema.tables[0].name, "users");
         assert_eq!(schema.tables[0].columns.len(), 2);
+
+        let (_, checksum) = load_schema_with_checksum(temp_file.path()).await.unwrap();
+        assert_eq!(checksum.len(), 64);
+        assert_eq!(checksum, super::checksum(schema_content));
+        assert_ne!(checksum, super::checksum(&schema_content.replace("1.0.0", "1.0.1")));
+    }
+
+    const BASE_SCHEMA: &str = r#"
+[[tables]]
+name = "users"
+
+[[tables.columns]]
+name = "id"
+type = "INTEGER"
+primary_key = true
+
+[[indexes]]
+table = "users"
+name = "idx_users_id"
+columns = ["id"]
+
+[[data.users]]
+id = 1
+"#;
+
+    const MAIN_SCHEMA: &str = r#"
+includes = ["common/base.schema"]
+
+[database]
+name = "app"
+description = "Split schema"
+version = "1.0.0"
+
+[[tables]]
+name = "posts"
+
+[[tables.columns]]
+name = "user_id"
+type = "INTEGER"
+"#;
+
+    fn write_schema_files(dir: &Path, files: &[(&str, &str)]) {
+        for (name, content) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_schema_includes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        write_schema_files(dir.path(), &[("common/base.schema", BASE_SCHEMA), ("main.schema", MAIN_SCHEMA)]);
+
+        let (schema, checksum) = load_schema_with_checksum(&dir.path().join("main.schema")).await.unwrap();
+        assert_eq!(schema.database.name, "app");
+        // Included definitions come first
+        let tables: Vec<&str> = schema.tables.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(tables, vec!["users", "posts"]);
+        assert_eq!(schema.indexes.len(), 1);
+        assert!(matches!(&schema.data["users"], TableData::Rows(rows) if rows.len() == 1));
+        // Editing an included file changes the checksum
+        assert_eq!(checksum, super::checksum(&format!("{}{}", BASE_SCHEMA, MAIN_SCHEMA)));
+
+        // An included file cannot be loaded on its own
+        let err = load_schema(&dir.path().join("common/base.schema")).await.unwrap_err();
+        assert!(err.to_string().contains("missing [database] section"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_load_schema_include_errors() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let duplicate = MAIN_SCHEMA.replace("\"posts\"", "\"Users\"");
+        write_schema_files(dir.path(), &[("common/base.schema", BASE_SCHEMA), ("main.schema", &duplicate)]);
+        let err = load_schema(&dir.path().join("main.schema")).await.unwrap_err();
+        assert!(err.to_string().contains("Table 'Users' is defined more than once"), "{}", err);
+
+        let cyclic = format!("includes = [\"../main.schema\"]\n{}", BASE_SCHEMA);
+        write_schema_files(dir.path(), &[("common/base.schema", &cyclic), ("main.schema", MAIN_SCHEMA)]);
+        let err = load_schema(&dir.path().join("main.schema")).await.unwrap_err();
+        assert!(err.to_string().starts_with("Schema include cycle:"), "{}", err);
+
+        let missing = MAIN_SCHEMA.replace("common/base.schema", "common/nope.schema");
+        write_schema_files(dir.path(), &[("main.schema", &missing)]);
+        let err = load_schema(&dir.path().join("main.schema")).await.unwrap_err();
+        assert!(err.to_string().contains("nope.schema"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_schema() {
+        let content = format!("{}\n{}", MAIN_SCHEMA.replace("includes = [\"common/base.schema\"]", ""), BASE_SCHEMA);
+        let (schema, checksum) = parse_schema(&content).unwrap();
+        assert_eq!(schema.database.name, "app");
+        assert_eq!(schema.tables.len(), 2);
+        assert_eq!(checksum, super::checksum(&content));
+
+        let err = parse_schema(MAIN_SCHEMA).unwrap_err();
+        assert_eq!(err, "An uploaded schema cannot include other files");
+        let err = parse_schema(&format!("{}\n[data.posts]\nfile = \"/etc/posts.csv\"\n", content)).unwrap_err();
+        assert!(err.contains("Data for table 'posts' is read from a file"), "{}", err);
+        let err = parse_schema(BASE_SCHEMA).unwrap_err();
+        assert!(err.contains("missing [database] section"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_load_schema_column_sets() {
+        let schema_content = r#"
+[database]
+name = "app"
+description = "Column sets"
+version = "1.0.0"
+
+[[column_sets.audit]]
+name = "created_at"
+type = "TIMESTAMP"
+default = "CURRENT_TIMESTAMP"
+
+[[column_sets.audit]]
+name = "created_by"
+type = "TEXT"
+nullable = true
+
+[[tables]]
+name = "users"
+include_columns = ["audit"]
+
+[[tables.columns]]
+name = "id"
+type = "INTEGER"
+primary_key = true
+
+[[tables]]
+name = "tags"
+include_columns = ["audit"]
+"#;
+        let dir = tempfile::TempDir::new().unwrap();
+        write_schema_files(dir.path(), &[("app.schema", schema_content)]);
+        let schema = load_schema(&dir.path().join("app.schema")).await.unwrap();
+        let columns = |table: usize| schema.tables[table].columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(columns(0), vec!["id", "created_at", "created_by"]);
+        assert_eq!(columns(1), vec!["created_at", "created_by"]);
+        assert_eq!(schema.tables[1].columns[0].default.as_deref(), Some("CURRENT_TIMESTAMP"));
+
+        let unknown = schema_content.replace("include_columns = [\"audit\"]\n\n[[tables.columns]]", "include_columns = [\"audti\"]\n\n[[tables.columns]]");
+        write_schema_files(dir.path(), &[("app.schema", &unknown)]);
+        let err = load_schema(&dir.path().join("app.schema")).await.unwrap_err();
+        assert_eq!(err.to_string(), "Table 'users' includes unknown column set 'audti'");
+
+        let clash = schema_content.replace("name = \"id\"", "name = \"Created_By\"");
+        write_schema_files(dir.path(), &[("app.schema", &clash)]);
+        let err = load_schema(&dir.path().join("app.schema")).await.unwrap_err();
+        assert_eq!(err.to_string(), "Column 'created_by' of set 'audit' is already defined in table 'users'");
     }
 
     #[test]
@@ -308,12 +645,18 @@ nullable = false
         let col = ColumnDef {
             name: "test_col".to_string(),
             col_type: "INTEGER".to_string(),
+            description: None,
             nullable: true,
             primary_key: false,
             unique: false,
             auto_increment: false,
             default: Some("0".to_string()),
+            generated: None,
+            stored: false,
+            collation: None,
+            values: None,
             foreign_key: None,
+            encrypted: false,
         };
 
         let db_col = column_def_to_db(&col).unwrap();
@@ -330,12 +673,18 @@ nullable = false
         let col = ColumnDef {
             name: "id".to_string(),
             col_type: "INTEGER".to_string(),
+            description: None,
             nullable: true, // This should be overridden
             primary_key: true,
             unique: false,
             auto_increment: true,
             default: None,
+            generated: None,
+            stored: false,
+            collation: None,
+            values: None,
             foreign_key: None,
+            encrypted: false,
         };
 
         let db_col = column_def_to_db(&col).unwrap();
@@ -348,12 +697,18 @@ nullable = false
         let col = ColumnDef {
             name: "test_col".to_string(),
             col_type: "UNKNOWN".to_string(),
+            description: None,
             nullable: true,
             primary_key: false,
             unique: false,
             auto_increment: false,
             default: None,
+            generated: None,
+            stored: false,
+            collation: None,
+            values: None,
             foreign_key: None,
+            encrypted: false,
         };
 
         let result = column_def_to_db(&col);
@@ -362,6 +717,7 @@ nullable = false
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_toml_value_to_proto() {
         // Test integer conversion
         let int_val = toml::Value::Integer(42);
@@ -394,6 +750,7 @@ nullable = false
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_toml_value_to_db() {
         // Test integer conversion
         let int_val = toml::Value::Integer(42);
@@ -423,36 +780,59 @@ nullable = false
         let table_def = TableDef {
             name: "test_table".to_string(),
             description: None,
+            ttl_column: None,
+            partition: None,
+            history: false,
+            include_columns: vec![],
+            unique: vec![],
             columns: vec![
                 ColumnDef {
                     name: "id".to_string(),
                     col_type: "INTEGER".to_string(),
+                    description: None,
                     nullable: false,
                     primary_key: true,
                     unique: false,
                     auto_increment: true,
                     default: None,
+                    generated: None,
+                    stored: false,
+                    collation: None,
+                    values: None,
                     foreign_key: None,
+                    encrypted: false,
                 },
                 ColumnDef {
                     name: "name".to_string(),
                     col_type: "TEXT".to_string(),
+                    description: None,
                     nullable: false,
                     primary_key: false,
                     unique: false,
                     auto_increment: false,
                     default: None,
+                    generated: None,
+                    stored: false,
+                    collation: None,
+                    values: None,
                     foreign_key: None,
+                    encrypted: false,
                 },
                 ColumnDef {
                     name: "active".to_string(),
                     col_type: "BOOLEAN".to_string(),
+                    description: None,
                     nullable: true,
                     primary_key: false,
                     unique: false,
                     auto_increment: false,
                     default: Some("true".to_string()),
+                    generated: None,
+                    stored: false,
+                    collation: None,
+                    values: None,
                     foreign_key: None,
+                    encrypted: false,
                 },
             ],
         };
@@ -477,16 +857,27 @@ nullable = false
         let table_def = TableDef {
             name: "test_table".to_string(),
             description: None,
+            ttl_column: None,
+            partition: None,
+            history: false,
+            include_columns: vec![],
+            unique: vec![],
             columns: vec![
                 ColumnDef {
                     name: "name".to_string(),
                     col_type: "TEXT".to_string(),
+                    description: None,
                     nullable: false,
                     primary_key: false,
                     unique: false,
                     auto_increment: false,
                     default: None,
+                    generated: None,
+                    stored: false,
+                    collation: None,
+                    values: None,
                     foreign_key: None,
+                    encrypted: false,
                 },
             ],
         };
@@ -497,4 +888,34 @@ nullable = false
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("Missing required field"));
     }
+
+    #[test]
+    fn test_prepare_insert_data_db_allowed_values() {
+        let table_def: TableDef = toml::from_str(
+            r#"
+name = "tasks"
+
+[[columns]]
+name = "priority"
+type = "TEXT"
+values = ["low", "medium", "high"]
+"#,
+        )
+        .unwrap();
+        let row = |value: toml::Value| HashMap::from([("priority".to_string(), value)]);
+
+        assert!(prepare_insert_data_db(&table_def, &row(toml::Value::String("high".to_string()))).is_ok());
+        let err = prepare_insert_data_db(&table_def, &row(toml::Value::String("urgent".to_string()))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "\"urgent\" is not an allowed value for column 'priority' (expected one of: low, medium, high)"
+        );
+        assert!(prepare_insert_data(&table_def, &row(toml::Value::Integer(1))).is_err());
+
+        let db_col = column_def_to_db(&table_def.columns[0]).unwrap();
+        assert_eq!(
+            crate::db::SqliteDatabase::build_create_table_sql("tasks", &[db_col], &[]),
+            "CREATE TABLE tasks (priority TEXT NOT NULL CHECK (priority IN ('low', 'medium', 'high')))"
+        );
+    }
 }
\ No newline at end of file
@@ -0,0 +1,450 @@
+//! Migration SQL between two versions of a schema file
+//! (`datasink schema plan`).
+//!
+//! Tables, indexes, search and spatial indexes and materialized views are
+//! matched by name. SQLite's ALTER TABLE can only add and drop plain columns,
+//! so a table that changes in any other way is rebuilt: created under a new
+//! name, filled with the columns both versions have, and renamed over the old
+//! table. A renamed column looks like one dropped and one added.
+//!
+//! A table with history gets its triggers recreated whenever it changes, and
+//! its history table gains the added columns; every row then starts a new
+//! version. Turning history off drops the history table.
+//!
+//! The reverse statements are the plan from the new version back to the old
+//! one, so they cannot bring back dropped rows or columns. As with `schema
+//! ddl`, seed data and DataSink's own metadata tables are left out, and
+//! partitioned tables are reported rather than migrated.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use super::{parser, Schema, TableDef};
+use crate::db::history;
+use crate::db::traits::ColumnDef;
+use crate::db::SqliteDatabase;
+
+/// Statements migrating a database between two versions of a schema
+pub struct Plan {
+    /// From the old version to the new one
+    pub forward: Vec<String>,
+    /// From the new version back to the old one
+    pub reverse: Vec<String>,
+    /// Data the forward statements delete and changes they leave out
+    pub warnings: Vec<String>,
+    /// The same for the reverse statements
+    pub reverse_warnings: Vec<String>,
+}
+
+/// A table of a schema with its columns as created
+struct Table<'a> {
+    def: &'a TableDef,
+    columns: Vec<ColumnDef>,
+    sql: String,
+}
+
+impl<'a> Table<'a> {
+    fn has_column(&self, name: &str) -> bool {
+        self.columns.iter().any(|c| c.name == name)
+    }
+}
+
+/// The statements taking a database from `old` to `new` and back
+pub fn plan(old: &Schema, new: &Schema) -> Result<Plan, Box<dyn Error>> {
+    let (forward, warnings) = statements(old, new)?;
+    let (reverse, reverse_warnings) = statements(new, old)?;
+    Ok(Plan { forward, reverse, warnings, reverse_warnings })
+}
+
+/// Statements as an SQL script migrating `from` to `to`
+pub fn script(from: &Schema, to: &Schema, statements: &[String], warnings: &[String]) -> String {
+    let mut script = format!(
+        "-- Migrate schema '{}' from version {} to {}\n",
+        to.database.name, from.database.version, to.database.version
+    );
+    for warning in warnings {
+        script.push_str(&format!("-- Warning: {}\n", warning));
+    }
+    if statements.is_empty() {
+        script.push_str("-- No changes\n");
+    }
+    for statement in statements {
+        script.push_str(&format!("\n{};\n", statement));
+    }
+    script
+}
+
+fn tables(schema: &Schema) -> Result<HashMap<&str, Table<'_>>, Box<dyn Error>> {
+    let mut tables = HashMap::new();
+    for def in &schema.tables {
+        let columns = def.columns.iter().map(parser::column_def_to_db).collect::<Result<Vec<_>, _>>()?;
+        let sql = SqliteDatabase::build_create_table_sql(&def.name, &columns, &def.unique);
+        tables.insert(def.name.as_str(), Table { def, columns, sql });
+    }
+    Ok(tables)
+}
+
+fn column_sql(column: &ColumnDef) -> String {
+    SqliteDatabase::column_defs_sql(std::slice::from_ref(column))
+}
+
+/// Statements migrating `from` to `to`, and warnings about them
+fn statements(from: &Schema, to: &Schema) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    let from_tables = tables(from)?;
+    let to_tables = tables(to)?;
+    let mut warnings = Vec::new();
+
+    // Tables both versions have and the statements changing them
+    let mut changes = Vec::new();
+    let mut changed = HashSet::new();
+    let mut rebuilt = HashSet::new();
+    for def in &to.tables {
+        let Some(old) = from_tables.get(def.name.as_str()) else { continue };
+        let new = &to_tables[def.name.as_str()];
+        if old.sql == new.sql && old.def.partition == new.def.partition {
+            continue;
+        }
+        if old.def.partition.is_some() || new.def.partition.is_some() {
+            warnings.push(format!("Table '{}' is partitioned; migrate it by hand", def.name));
+            continue;
+        }
+        for column in old.columns.iter().filter(|c| !new.has_column(&c.name)) {
+            warnings.push(format!("Dropping column '{}.{}' deletes its values", def.name, column.name));
+        }
+        changed.insert(def.name.as_str());
+        match alter_statements(old, new, from) {
+            Some(statements) => changes.extend(statements),
+            None => {
+                changes.extend(rebuild_statements(old, new));
+                rebuilt.insert(def.name.as_str());
+            }
+        }
+    }
+    let dropped: Vec<&TableDef> = from.tables.iter().filter(|t| !to_tables.contains_key(t.name.as_str())).collect();
+    let added: Vec<&TableDef> = to.tables.iter().filter(|t| !from_tables.contains_key(t.name.as_str())).collect();
+    let gone = |table: &str| rebuilt.contains(table) || !to_tables.contains_key(table);
+
+    let mut statements = Vec::new();
+
+    // Remove what is dropped or changed, starting with what depends on tables
+    for view in &from.materialized_views {
+        if to.materialized_views.iter().all(|v| v.name != view.name || v.query != view.query) {
+            statements.push(format!("DROP TABLE {}", view.name));
+        }
+    }
+    for index in &from.indexes {
+        let kept = to.indexes.iter().any(|i| i.name == index.name && i.table == index.table && i.columns == index.columns);
+        // An index goes with its table
+        if !kept && !gone(&index.table) {
+            statements.push(format!("DROP INDEX {}", index.name));
+        }
+    }
+    // The index tables outlive the tables they index, and a rebuilt table loses the triggers
+    for index in &from.search_indexes {
+        if gone(&index.table) || to.search_indexes.iter().all(|i| i.table != index.table || i.columns != index.columns) {
+            statements.extend(SqliteDatabase::drop_search_index_sql(&index.table));
+        }
+    }
+    for index in &from.spatial_indexes {
+        if gone(&index.table) || to.spatial_indexes.iter().all(|i| i.table != index.table || i.columns != index.columns) {
+            statements.extend(SqliteDatabase::drop_spatial_index_sql(&index.table));
+        }
+    }
+    // The triggers name the table's columns, so they go before any column does
+    for def in from.tables.iter().filter(|t| t.history && t.partition.is_none()) {
+        let kept = to.tables.iter().any(|t| t.name == def.name && t.history);
+        if kept && changed.contains(def.name.as_str()) {
+            statements.extend(history::drop_history_triggers_sql(&def.name));
+        } else if !kept {
+            statements.extend(history::drop_history_triggers_sql(&def.name));
+            statements.push(format!("DROP TABLE {}", history::history_table_name(&def.name)));
+            if to_tables.contains_key(def.name.as_str()) {
+                warnings.push(format!("Turning off history of '{}' deletes its past versions", def.name));
+            }
+        }
+    }
+    for def in dropped.iter().rev() {
+        if def.partition.is_some() {
+            warnings.push(format!("Table '{}' is partitioned; drop it by hand", def.name));
+            continue;
+        }
+        statements.push(format!("DROP TABLE {}", def.name));
+        warnings.push(format!("Dropping table '{}' deletes its rows", def.name));
+    }
+
+    // Then create and change tables, and add back what depends on them
+    for def in &added {
+        if def.partition.is_some() {
+            warnings.push(format!("Table '{}' is partitioned; create it by hand", def.name));
+            continue;
+        }
+        statements.push(to_tables[def.name.as_str()].sql.clone());
+    }
+    statements.extend(changes);
+    for def in to.tables.iter().filter(|t| t.history && t.partition.is_none()) {
+        let new = &to_tables[def.name.as_str()];
+        match from_tables.get(def.name.as_str()).filter(|old| old.def.history) {
+            None => statements.extend(SqliteDatabase::history_sql(&def.name, &new.columns)),
+            Some(old) if changed.contains(def.name.as_str()) => {
+                // Dropped columns stay in the history table with their past values
+                let added: Vec<ColumnDef> = new.columns.iter().filter(|c| !old.has_column(&c.name)).cloned().collect();
+                for (name, sql_type) in SqliteDatabase::history_columns(&added) {
+                    statements.push(format!(
+                        "ALTER TABLE {} ADD COLUMN {}",
+                        history::history_table_name(&def.name),
+                        history::column_def(&name, &sql_type)
+                    ));
+                }
+                let names: Vec<&str> = new.columns.iter().map(|c| c.name.as_str()).collect();
+                statements.push(history::close_all_sql(&def.name));
+                statements.push(history::seed_sql(&def.name, &names));
+                statements.extend(history::history_triggers_sql(&def.name, &names));
+            }
+            Some(_) => {}
+        }
+    }
+    for index in &to.indexes {
+        let existed = from.indexes.iter().any(|i| i.name == index.name && i.table == index.table && i.columns == index.columns);
+        if !existed || rebuilt.contains(index.table.as_str()) {
+            statements.push(SqliteDatabase::create_index_sql(&index.name, &index.table, &index.columns));
+        }
+    }
+    for index in &to.search_indexes {
+        let existed = from.search_indexes.iter().any(|i| i.table == index.table && i.columns == index.columns);
+        if !existed || rebuilt.contains(index.table.as_str()) {
+            statements.extend(SqliteDatabase::search_index_sql(&index.table, &index.columns));
+        }
+    }
+    for index in &to.spatial_indexes {
+        let existed = from.spatial_indexes.iter().any(|i| i.table == index.table && i.columns == index.columns);
+        if !existed || rebuilt.contains(index.table.as_str()) {
+            statements.extend(SqliteDatabase::spatial_index_sql(&index.table, &index.columns)?);
+        }
+    }
+    for view in &to.materialized_views {
+        if from.materialized_views.iter().all(|v| v.name != view.name || v.query != view.query) {
+            statements.push(format!("CREATE TABLE {} AS {}", view.name, view.query));
+        }
+    }
+
+    if statements.is_empty() {
+        return Ok((statements, warnings));
+    }
+    statements.insert(0, "BEGIN".to_string());
+    statements.push("COMMIT".to_string());
+    // Dropping the old table of a rebuild must not cascade to tables referencing it
+    if !rebuilt.is_empty() {
+        statements.insert(0, "PRAGMA foreign_keys = OFF".to_string());
+        statements.push("PRAGMA foreign_keys = ON".to_string());
+    }
+    Ok((statements, warnings))
+}
+
+/// ALTER TABLE statements for a change that only drops columns and adds
+/// them at the end, if SQLite can make it that way
+fn alter_statements(old: &Table, new: &Table, from: &Schema) -> Option<Vec<String>> {
+    if old.def.unique != new.def.unique {
+        return None;
+    }
+    let kept: Vec<&ColumnDef> = old.columns.iter().filter(|c| new.has_column(&c.name)).collect();
+    let unchanged = kept.iter().zip(&new.columns).all(|(o, n)| o.name == n.name && column_sql(o) == column_sql(n));
+    if !unchanged {
+        return None;
+    }
+
+    let mut statements = Vec::new();
+    for column in old.columns.iter().filter(|c| !new.has_column(&c.name)) {
+        if !can_drop(column, old, from) {
+            return None;
+        }
+        statements.push(format!("ALTER TABLE {} DROP COLUMN {}", new.def.name, column.name));
+    }
+    for column in &new.columns[kept.len()..] {
+        if !can_add(column) {
+            return None;
+        }
+        statements.push(format!("ALTER TABLE {} ADD COLUMN {}", new.def.name, column_sql(column)));
+    }
+    Some(statements)
+}
+
+/// Whether ALTER TABLE ADD COLUMN accepts the column
+fn can_add(column: &ColumnDef) -> bool {
+    // Existing rows get the default, which must be a constant
+    let constant_default = match &column.default_value {
+        Some(default) => !default.starts_with('(') && !default.to_uppercase().starts_with("CURRENT_"),
+        None => column.nullable || column.generated.is_some(),
+    };
+    !column.primary_key && !column.unique && !column.generated.as_ref().is_some_and(|g| g.stored) && constant_default
+}
+
+/// Whether ALTER TABLE DROP COLUMN accepts the column: it cannot be part of
+/// a key, constraint or index, or be used by a generated column
+fn can_drop(column: &ColumnDef, table: &Table, from: &Schema) -> bool {
+    let name = &column.name;
+    let on_table = |t: &str| t == table.def.name;
+    !column.primary_key
+        && !column.unique
+        && !table.def.unique.iter().any(|set| set.contains(name))
+        && !table.columns.iter().any(|c| c.generated.as_ref().is_some_and(|g| g.expression.contains(name.as_str())))
+        && !from.indexes.iter().any(|i| on_table(&i.table) && i.columns.contains(name))
+        && !from.search_indexes.iter().any(|i| on_table(&i.table) && i.columns.contains(name))
+        && !from.spatial_indexes.iter().any(|i| on_table(&i.table) && i.columns.contains(name))
+}
+
+/// SQLite's table rebuild: the new table under a temporary name, filled from
+/// the columns both versions have, then renamed over the old one
+fn rebuild_statements(old: &Table, new: &Table) -> Vec<String> {
+    let name = &new.def.name;
+    let temp = format!("{}__new", name);
+    let columns: Vec<&str> = new
+        .columns
+        .iter()
+        .filter(|c| c.generated.is_none() && old.has_column(&c.name))
+        .map(|c| c.name.as_str())
+        .collect();
+    let columns = columns.join(", ");
+    vec![
+        SqliteDatabase::build_create_table_sql(&temp, &new.columns, &new.def.unique),
+        format!("INSERT INTO {temp} ({columns}) SELECT {columns} FROM {name}"),
+        format!("DROP TABLE {name}"),
+        format!("ALTER TABLE {temp} RENAME TO {name}"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = r#"
+[database]
+name = "blog"
+description = "Test blog"
+version = "1.0.0"
+
+[[tables]]
+name = "users"
+
+[[tables.columns]]
+name = "id"
+type = "INTEGER"
+primary_key = true
+
+[[tables.columns]]
+name = "name"
+type = "TEXT"
+
+[[tables.columns]]
+name = "nickname"
+type = "TEXT"
+nullable = true
+
+[[tables]]
+name = "posts"
+
+[[tables.columns]]
+name = "id"
+type = "INTEGER"
+primary_key = true
+
+[[tables.columns]]
+name = "title"
+type = "TEXT"
+nullable = true
+
+[[indexes]]
+table = "posts"
+name = "idx_posts_title"
+columns = ["title"]
+
+[[search_indexes]]
+table = "posts"
+columns = ["title"]
+"#;
+
+    fn new_schema() -> Schema {
+        let new = OLD
+            .replace("version = \"1.0.0\"", "version = \"1.1.0\"")
+            .replace("name = \"nickname\"", "name = \"email\"")
+            .replace("name = \"title\"\ntype = \"TEXT\"\nnullable = true", "name = \"title\"\ntype = \"TEXT\"");
+        let new = format!("{}\n[[tables]]\nname = \"tags\"\n\n[[tables.columns]]\nname = \"name\"\ntype = \"TEXT\"\n", new);
+        toml::from_str(&new).unwrap()
+    }
+
+    #[test]
+    fn test_plan() {
+        let old: Schema = toml::from_str(OLD).unwrap();
+        let new = new_schema();
+        let plan = plan(&old, &new).unwrap();
+
+        assert_eq!(plan.forward[..3], ["PRAGMA foreign_keys = OFF", "BEGIN", "DROP TRIGGER IF EXISTS posts_fts_ai"]);
+        assert_eq!(plan.forward[5], "DROP TABLE posts_fts");
+        assert_eq!(plan.forward[6..13], [
+            "CREATE TABLE tags (name TEXT NOT NULL)",
+            "ALTER TABLE users DROP COLUMN nickname",
+            "ALTER TABLE users ADD COLUMN email TEXT",
+            "CREATE TABLE posts__new (id INTEGER PRIMARY KEY, title TEXT NOT NULL)",
+            "INSERT INTO posts__new (id, title) SELECT id, title FROM posts",
+            "DROP TABLE posts",
+            "ALTER TABLE posts__new RENAME TO posts",
+        ]);
+        assert_eq!(plan.forward[13], "CREATE INDEX idx_posts_title ON posts (title)");
+        assert!(plan.forward[14].starts_with("CREATE VIRTUAL TABLE posts_fts USING fts5(title"));
+        assert_eq!(plan.forward[plan.forward.len() - 2..], ["COMMIT", "PRAGMA foreign_keys = ON"]);
+        assert_eq!(plan.warnings, ["Dropping column 'users.nickname' deletes its values"]);
+
+        assert!(plan.reverse.contains(&"DROP TABLE tags".to_string()));
+        assert!(plan.reverse.contains(&"ALTER TABLE users ADD COLUMN nickname TEXT".to_string()));
+        assert_eq!(plan.reverse_warnings, ["Dropping column 'users.email' deletes its values", "Dropping table 'tags' deletes its rows"]);
+
+        let script = script(&old, &new, &plan.forward, &plan.warnings);
+        assert!(script.starts_with("-- Migrate schema 'blog' from version 1.0.0 to 1.1.0\n-- Warning: Dropping column"));
+        assert!(script.ends_with("\nPRAGMA foreign_keys = ON;\n"));
+    }
+
+    #[test]
+    fn test_plan_unchanged_and_constrained_columns() {
+        let old: Schema = toml::from_str(OLD).unwrap();
+        let same: Schema = toml::from_str(OLD).unwrap();
+        let plan = plan(&old, &same).unwrap();
+        assert!(plan.forward.is_empty() && plan.reverse.is_empty());
+        assert!(script(&old, &same, &plan.forward, &[]).ends_with("-- No changes\n"));
+
+        // ALTER TABLE cannot change a column's constraints, so the table is rebuilt
+        let new: Schema = toml::from_str(&OLD.replace("name = \"nickname\"\ntype = \"TEXT\"\nnullable = true", "name = \"nickname\"\ntype = \"TEXT\"")).unwrap();
+        let plan = super::plan(&old, &new).unwrap();
+        assert!(plan.forward.contains(&"ALTER TABLE users__new RENAME TO users".to_string()));
+        assert!(plan.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_plan_history() {
+        let with_history = |mut schema: Schema| {
+            schema.tables[0].history = true;
+            schema
+        };
+        let old: Schema = toml::from_str(OLD).unwrap();
+        let plan = plan(&old, &with_history(toml::from_str(OLD).unwrap())).unwrap();
+        assert_eq!(
+            plan.forward[1],
+            "CREATE TABLE users__history (__rowid INTEGER NOT NULL, __valid_from REAL NOT NULL, __valid_to REAL, \
+             __operation TEXT NOT NULL, id INTEGER, name TEXT, nickname TEXT)"
+        );
+        assert_eq!(plan.reverse[1..5], [
+            "DROP TRIGGER IF EXISTS users__history_ai",
+            "DROP TRIGGER IF EXISTS users__history_ad",
+            "DROP TRIGGER IF EXISTS users__history_au",
+            "DROP TABLE users__history",
+        ]);
+        assert_eq!(plan.reverse_warnings, ["Turning off history of 'users' deletes its past versions"]);
+
+        // The triggers name the dropped column, so they are replaced around the change
+        let plan = super::plan(&with_history(old), &with_history(new_schema())).unwrap();
+        let position = |sql: &str| plan.forward.iter().position(|s| s == sql).unwrap();
+        assert!(position("DROP TRIGGER IF EXISTS users__history_au") < position("ALTER TABLE users DROP COLUMN nickname"));
+        let added = position("ALTER TABLE users__history ADD COLUMN email TEXT");
+        assert_eq!(plan.forward[added + 1], "UPDATE users__history SET __valid_to = unixepoch('subsec') WHERE __valid_to IS NULL");
+        assert!(plan.forward[added + 2].starts_with("INSERT INTO users__history (__rowid, __valid_from, __operation, id, name, email) SELECT"));
+        assert!(plan.forward[added + 3].starts_with("CREATE TRIGGER users__history_ai"));
+    }
+}
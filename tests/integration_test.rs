@@ -1,7 +1,6 @@
-use datasink::db::{traits::*, SqliteDatabase, Database};
+use datasink::db::{diff, history, traits::*, Database, DatabaseError, DatabaseManager, DatabaseOptions, SqliteDatabase, SqliteExtension};
 use std::collections::HashMap;
 use tempfile::NamedTempFile;
-use tokio;
 
 #[tokio::test]
 async fn test_sqlite_database_operations() {
@@ -20,6 +19,9 @@ async fn test_sqlite_database_operations() {
             primary_key: true,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "name".to_string(),
@@ -28,6 +30,9 @@ async fn test_sqlite_database_operations() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "active".to_string(),
@@ -36,6 +41,9 @@ async fn test_sqlite_database_operations() {
             primary_key: false,
             unique: false,
             default_value: Some("true".to_string()),
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
     ];
     
@@ -91,6 +99,9 @@ async fn test_batch_insert() {
             primary_key: true,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "value".to_string(),
@@ -99,6 +110,9 @@ async fn test_batch_insert() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
     ];
     
@@ -138,6 +152,9 @@ async fn test_null_values() {
             primary_key: true,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "optional_text".to_string(),
@@ -146,6 +163,9 @@ async fn test_null_values() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
     ];
     
@@ -164,6 +184,7 @@ async fn test_null_values() {
 }
 
 #[tokio::test]
+#[allow(clippy::approx_constant)]
 async fn test_data_types() {
     let temp_file = NamedTempFile::new().unwrap();
     let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
@@ -179,6 +200,9 @@ async fn test_data_types() {
             primary_key: true,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "int_val".to_string(),
@@ -187,6 +211,9 @@ async fn test_data_types() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "real_val".to_string(),
@@ -195,6 +222,9 @@ async fn test_data_types() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "text_val".to_string(),
@@ -203,6 +233,9 @@ async fn test_data_types() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "blob_val".to_string(),
@@ -211,6 +244,9 @@ async fn test_data_types() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "bool_val".to_string(),
@@ -219,6 +255,9 @@ async fn test_data_types() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
         ColumnDef {
             name: "timestamp_val".to_string(),
@@ -227,6 +266,9 @@ async fn test_data_types() {
             primary_key: false,
             unique: false,
             default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
         },
     ];
     
@@ -264,5 +306,1901 @@ async fn test_data_types() {
     assert!(matches!(row[5], DbValue::Integer(1)));
     // SQLite stores timestamps as integers
     assert!(matches!(row[6], DbValue::Integer(1640995200)));
+
+    // Column types come from the declared types, so BOOLEAN and TIMESTAMP
+    // columns can be shown as booleans and dates
+    let types: Vec<&ColumnType> = results.columns.iter().map(|(_, col_type)| col_type).collect();
+    assert!(matches!(
+        types[..],
+        [ColumnType::Integer, ColumnType::Integer, ColumnType::Real, ColumnType::Text, ColumnType::Blob, ColumnType::Boolean, ColumnType::Timestamp]
+    ));
+    db.execute("CREATE TABLE declared (name VARCHAR(20), seen DATETIME, flag BOOL)").await.unwrap();
+    db.execute("INSERT INTO declared VALUES ('a', 0, 1)").await.unwrap();
+    let results = db.query("SELECT name, seen, flag, flag + 1 AS n FROM declared", HashMap::new()).await.unwrap();
+    let types: Vec<&ColumnType> = results.columns.iter().map(|(_, col_type)| col_type).collect();
+    assert!(matches!(
+        types[..],
+        [ColumnType::Text, ColumnType::Timestamp, ColumnType::Boolean, ColumnType::Integer]
+    ), "{:?}", types);
+}
+
+
+#[tokio::test]
+async fn test_database_registry_persistence() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let registry_path = temp_dir.path().join("registry.json");
+    let db_url = format!("sqlite://{}?mode=rwc", temp_dir.path().join("dynamic.db").display());
+
+    // Add a database at runtime and a non-persistent startup database
+    let manager = DatabaseManager::with_registry(&registry_path);
+    manager
+        .add_database_with_options("startup".to_string(), db_url.clone(), DatabaseOptions::default())
+        .await
+        .unwrap();
+    manager
+        .add_persistent_database("dynamic".to_string(), db_url.clone(), DatabaseOptions::default())
+        .await
+        .unwrap();
+    assert!(registry_path.exists());
+
+    // A fresh manager restores only the persistent database
+    let restarted = DatabaseManager::with_registry(&registry_path);
+    assert_eq!(restarted.load_registry().await.unwrap(), 1);
+    assert!(restarted.get_database("dynamic").await.is_some());
+    assert!(restarted.get_database("startup").await.is_none());
+
+    // Removing it drops it from the registry
+    assert!(restarted.remove_database("dynamic").await);
+    let empty = DatabaseManager::with_registry(&registry_path);
+    assert_eq!(empty.load_registry().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_registry_keeps_entries_that_were_not_restored() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let registry_path = temp_dir.path().join("registry.json");
+    let db_url = format!("sqlite://{}?mode=rwc", temp_dir.path().join("data.db").display());
+    let missing_url = format!("sqlite://{}", temp_dir.path().join("gone").join("missing.db").display());
+    let entries = serde_json::json!([
+        { "name": "missing", "url": missing_url },
+        { "name": "shadowed", "url": db_url },
+    ]);
+    std::fs::write(&registry_path, entries.to_string()).unwrap();
+
+    // One entry fails to open and the other is shadowed by a startup database
+    let manager = DatabaseManager::with_registry(&registry_path);
+    manager.add_database("shadowed".to_string(), "sqlite::memory:".to_string()).await.unwrap();
+    assert_eq!(manager.load_registry().await.unwrap(), 0);
+
+    // Saving for another change keeps both
+    manager
+        .add_persistent_database("added".to_string(), db_url.clone(), DatabaseOptions::default())
+        .await
+        .unwrap();
+    let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&registry_path).unwrap()).unwrap();
+    let names: Vec<&str> = saved.as_array().unwrap().iter().map(|entry| entry["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["added", "missing", "shadowed"]);
+    assert_eq!(saved[1]["url"], missing_url);
+
+    // Concurrent saves each leave a complete file with every entry
+    let adds = ["one", "two", "three", "four"]
+        .map(|name| manager.add_persistent_database(name.to_string(), db_url.clone(), DatabaseOptions::default()));
+    for result in futures::future::join_all(adds).await {
+        result.unwrap();
+    }
+    let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&registry_path).unwrap()).unwrap();
+    assert_eq!(saved.as_array().unwrap().len(), 7);
+
+    // Removing a name forgets its entry
+    assert!(manager.remove_database("missing").await);
+    let saved = std::fs::read_to_string(&registry_path).unwrap();
+    assert!(!saved.contains("missing.db"));
+}
+
+#[tokio::test]
+async fn test_concurrent_adds_of_one_database() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+
+    // Both open the database; the second to finish keeps the first's entry
+    let manager = DatabaseManager::new();
+    let (first, second) = tokio::join!(
+        manager.add_database("shared".to_string(), db_url.clone()),
+        manager.add_database("shared".to_string(), db_url.clone()),
+    );
+    first.unwrap();
+    second.unwrap();
+    assert_eq!(manager.list_databases().await.len(), 1);
+    let db = manager.get_database("shared").await.unwrap();
+    db.read().await.execute("CREATE TABLE notes (body TEXT)").await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idle_database_eviction_and_reconnect() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+
+    let manager = DatabaseManager::new();
+    manager.add_database("idle".to_string(), db_url).await.unwrap();
+    {
+        let db = manager.get_database("idle").await.unwrap();
+        let db = db.read().await;
+        let columns = vec![ColumnDef {
+            name: "name".to_string(),
+            col_type: ColumnType::Text,
+            nullable: false,
+            primary_key: false,
+            unique: false,
+            default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
+        }];
+        db.create_table("items", columns).await.unwrap();
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), DbValue::Text("kept".to_string()));
+        db.insert("items", values).await.unwrap();
+    }
+
+    // Recently used databases stay open
+    assert!(manager.evict_idle(std::time::Duration::from_secs(3600)).await.is_empty());
+
+    assert_eq!(manager.evict_idle(std::time::Duration::ZERO).await, vec!["idle".to_string()]);
+    assert!(!manager.list_databases().await[0].connected);
+
+    // The next access reconnects transparently
+    let db = manager.get_database("idle").await.unwrap();
+    let db = db.read().await;
+    let result = db.query("SELECT name FROM items", HashMap::new()).await.unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert!(manager.list_databases().await[0].connected);
+    drop(db);
+
+    // Requests racing to reconnect share one pool
+    manager.evict_idle(std::time::Duration::ZERO).await;
+    let (first, second) = tokio::join!(manager.get_database("idle"), manager.get_database("idle"));
+    assert!(std::sync::Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+}
+
+#[tokio::test]
+async fn test_in_memory_database_survives_idle_eviction() {
+    let manager = DatabaseManager::new();
+    manager.add_database("scratch".to_string(), "sqlite::memory:".to_string()).await.unwrap();
+    {
+        let db = manager.get_database("scratch").await.unwrap();
+        let db = db.read().await;
+        db.execute("CREATE TABLE notes (body TEXT)").await.unwrap();
+        db.execute("INSERT INTO notes VALUES ('kept')").await.unwrap();
+    }
+
+    // Closing its pool would discard the data, so it is never evicted
+    assert!(manager.evict_idle(std::time::Duration::ZERO).await.is_empty());
+    assert!(manager.list_databases().await[0].connected);
+
+    let db = manager.get_database("scratch").await.unwrap();
+    let db = db.read().await;
+    let result = db.query("SELECT body FROM notes", HashMap::new()).await.unwrap();
+    assert_eq!(result.rows.len(), 1);
+}
+
+#[tokio::test]
+async fn test_truncate_table_resets_autoincrement() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query(
+        "CREATE TABLE events (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT)",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+    for name in ["a", "b", "c"] {
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), DbValue::Text(name.to_string()));
+        db.insert("events", values).await.unwrap();
+    }
+
+    assert_eq!(db.truncate_table("events").await.unwrap(), 3);
+    let result = db.query("SELECT COUNT(*) FROM events", HashMap::new()).await.unwrap();
+    assert!(matches!(result.rows[0][0], DbValue::Integer(0)));
+
+    // Ids start over after the sequence reset
+    let mut values = HashMap::new();
+    values.insert("name".to_string(), DbValue::Text("d".to_string()));
+    assert_eq!(db.insert("events", values).await.unwrap(), 1);
+
+    assert!(matches!(
+        db.truncate_table("missing").await,
+        Err(DatabaseError::TableNotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_get_row_by_primary_key() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)", HashMap::new()).await.unwrap();
+    db.query(
+        "CREATE TABLE memberships (org TEXT, user_id INTEGER, role TEXT, PRIMARY KEY (org, user_id))",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+    db.query("INSERT INTO users (id, name) VALUES (42, 'Alice')", HashMap::new()).await.unwrap();
+    db.query("INSERT INTO memberships VALUES ('acme', 42, 'admin')", HashMap::new()).await.unwrap();
+
+    let row = db.get_row("users", vec![DbValue::Integer(42)]).await.unwrap().unwrap();
+    assert_eq!(row.columns.len(), 2);
+    assert!(matches!(&row.rows[0][1], DbValue::Text(name) if name == "Alice"));
+    assert!(db.get_row("users", vec![DbValue::Integer(7)]).await.unwrap().is_none());
+
+    // Composite keys are matched in key column order
+    let key = vec![DbValue::Text("acme".to_string()), DbValue::Integer(42)];
+    let row = db.get_row("memberships", key).await.unwrap().unwrap();
+    assert!(matches!(&row.rows[0][2], DbValue::Text(role) if role == "admin"));
+    assert!(matches!(
+        db.get_row("memberships", vec![DbValue::Integer(42)]).await,
+        Err(DatabaseError::QueryError(_))
+    ));
+    assert!(matches!(
+        db.get_row("missing", vec![DbValue::Integer(1)]).await,
+        Err(DatabaseError::TableNotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_insert_or_ignore_skips_conflicts() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT)", HashMap::new()).await.unwrap();
+
+    let row = |id: i64| HashMap::from([
+        ("id".to_string(), DbValue::Integer(id)),
+        ("name".to_string(), DbValue::Text(format!("event-{}", id))),
+    ]);
+
+    assert_eq!(db.insert_with_conflict("events", row(1), OnConflict::Ignore).await.unwrap(), Some(1));
+    assert_eq!(db.insert_with_conflict("events", row(1), OnConflict::Ignore).await.unwrap(), None);
+    assert!(db.insert_with_conflict("events", row(1), OnConflict::Abort).await.is_err());
+
+    // Duplicates are skipped without failing the rest of the batch
+    let inserted = db
+        .batch_insert_with_conflict("events", vec![row(1), row(2), row(3)], OnConflict::Ignore)
+        .await
+        .unwrap();
+    assert_eq!(inserted, InsertCounts { inserted: 2, replaced: 0, first_id: Some(2), last_id: Some(3) });
+    assert!(db.batch_insert("events", vec![row(4), row(3)]).await.is_err());
+
+    let results = db.query("SELECT COUNT(*) FROM events", HashMap::new()).await.unwrap();
+    assert!(matches!(results.rows[0][0], DbValue::Integer(3)));
+}
+
+#[tokio::test]
+async fn test_insert_ignore_still_fails_on_not_null_and_check() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query(
+        "CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT NOT NULL, size INTEGER CHECK (size >= 0))",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+
+    // Only UNIQUE and PRIMARY KEY conflicts are ignored
+    let missing_name = HashMap::from([("id".to_string(), DbValue::Integer(1)), ("name".to_string(), DbValue::Null)]);
+    assert!(db.insert_with_conflict("events", missing_name.clone(), OnConflict::Ignore).await.is_err());
+    assert!(db.batch_insert_with_conflict("events", vec![missing_name], OnConflict::Ignore).await.is_err());
+
+    let negative = HashMap::from([
+        ("id".to_string(), DbValue::Integer(2)),
+        ("name".to_string(), DbValue::Text("e".to_string())),
+        ("size".to_string(), DbValue::Integer(-1)),
+    ]);
+    assert!(db.insert_with_conflict("events", negative, OnConflict::Ignore).await.is_err());
+
+    let results = db.query("SELECT COUNT(*) FROM events", HashMap::new()).await.unwrap();
+    assert!(matches!(results.rows[0][0], DbValue::Integer(0)));
+}
+
+#[tokio::test]
+async fn test_insert_replace_and_update_conflicts() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT UNIQUE, name TEXT, visits INTEGER DEFAULT 0)",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+    let user = |id: i64, email: &str, name: &str| HashMap::from([
+        ("id".to_string(), DbValue::Integer(id)),
+        ("email".to_string(), DbValue::Text(email.to_string())),
+        ("name".to_string(), DbValue::Text(name.to_string())),
+    ]);
+    db.query("INSERT INTO users (id, email, name, visits) VALUES (1, 'a@x', 'Ann', 5)", HashMap::new()).await.unwrap();
+
+    // Update keeps the columns the new row does not set
+    let counts = db
+        .batch_insert_with_conflict("users", vec![user(1, "a@x", "Anne"), user(2, "b@x", "Bob")], OnConflict::Update)
+        .await
+        .unwrap();
+    assert_eq!(counts, InsertCounts { inserted: 1, replaced: 1, first_id: Some(2), last_id: Some(2) });
+    let row = db.query("SELECT name, visits FROM users WHERE id = 1", HashMap::new()).await.unwrap().rows.remove(0);
+    assert!(matches!(&row[0], DbValue::Text(name) if name == "Anne"));
+    assert!(matches!(row[1], DbValue::Integer(5)));
+
+    // Replace deletes the conflicting row, so its other columns take their defaults
+    let counts = db
+        .batch_insert_with_conflict("users", vec![user(1, "a@x", "Ann B"), user(3, "c@x", "Cy")], OnConflict::Replace)
+        .await
+        .unwrap();
+    assert_eq!(counts, InsertCounts { inserted: 1, replaced: 1, first_id: Some(3), last_id: Some(3) });
+    let row = db.query("SELECT name, visits FROM users WHERE id = 1", HashMap::new()).await.unwrap().rows.remove(0);
+    assert!(matches!(&row[0], DbValue::Text(name) if name == "Ann B"));
+    assert!(matches!(row[1], DbValue::Integer(0)));
+
+    // A conflict on a unique column other than the key is handled the same way
+    assert_eq!(db.insert_with_conflict("users", user(9, "b@x", "Bobby"), OnConflict::Update).await.unwrap(), None);
+    let results = db.query("SELECT id, name FROM users WHERE email = 'b@x'", HashMap::new()).await.unwrap();
+    assert!(matches!(results.rows[0][0], DbValue::Integer(2)));
+    assert!(matches!(&results.rows[0][1], DbValue::Text(name) if name == "Bobby"));
+
+    let results = db.query("SELECT COUNT(*) FROM users", HashMap::new()).await.unwrap();
+    assert!(matches!(results.rows[0][0], DbValue::Integer(3)));
+}
+
+#[tokio::test]
+async fn test_search_index_tracks_writes() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE posts (id INTEGER PRIMARY KEY, title TEXT, body TEXT)", HashMap::new()).await.unwrap();
+    db.query(
+        "INSERT INTO posts (title, body) VALUES ('Async Rust', 'Futures and tokio'), ('Gardening', 'Tomatoes')",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+
+    // Existing rows are indexed when the index is created
+    let columns = vec!["title".to_string(), "body".to_string()];
+    assert_eq!(db.create_search_index("posts", &columns).await.unwrap(), 2);
+    let result = db.search("posts", "tokio", 10).await.unwrap();
+    assert_eq!(result.matches.len(), 1);
+    assert_eq!(result.columns.len(), 3);
+    assert!(matches!(result.matches[0].values[0], DbValue::Integer(1)));
+    assert!(result.matches[0].snippet.contains("[tokio]"));
+
+    // Later inserts, updates and deletes are picked up by the triggers
+    db.query("INSERT INTO posts (title, body) VALUES ('More tokio', 'Tokio tokio')", HashMap::new()).await.unwrap();
+    db.query("UPDATE posts SET body = 'Threads' WHERE id = 1", HashMap::new()).await.unwrap();
+    let result = db.search("posts", "tokio", 10).await.unwrap();
+    assert_eq!(result.matches.len(), 1);
+    assert!(matches!(result.matches[0].values[0], DbValue::Integer(3)));
+
+    db.delete("posts", "id = 3").await.unwrap();
+    assert!(db.search("posts", "tokio", 10).await.unwrap().matches.is_empty());
+
+    assert!(matches!(
+        db.create_search_index("posts", &columns).await,
+        Err(DatabaseError::TableAlreadyExists(_))
+    ));
+    assert!(matches!(
+        db.create_search_index("posts", &["missing".to_string()]).await,
+        Err(DatabaseError::QueryError(_))
+    ));
+    assert!(matches!(db.search("posts", "\"unclosed", 10).await, Err(DatabaseError::QueryError(_))));
+}
+
+#[tokio::test]
+async fn test_drop_table_keeps_tables_that_only_share_a_companion_name() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    for sql in [
+        "CREATE TABLE posts (id INTEGER PRIMARY KEY, title TEXT)",
+        "CREATE TABLE posts_fts (id INTEGER PRIMARY KEY, term TEXT)",
+        "CREATE TABLE posts_rtree (id INTEGER PRIMARY KEY, area REAL)",
+        "CREATE TABLE posts__history (id INTEGER PRIMARY KEY, note TEXT)",
+        "CREATE TABLE places (id INTEGER PRIMARY KEY, lat REAL, lon REAL, name TEXT)",
+    ] {
+        db.query(sql, HashMap::new()).await.unwrap();
+    }
+    let table_names = "SELECT name FROM sqlite_master WHERE type = 'table'";
+    let names = |result: QueryResult| -> Vec<String> {
+        result.rows.into_iter().filter_map(|row| match &row[0] {
+            DbValue::Text(name) => Some(name.clone()),
+            _ => None,
+        }).collect()
+    };
+
+    db.drop_table("posts").await.unwrap();
+    let tables = names(db.query(table_names, HashMap::new()).await.unwrap());
+    for table in ["posts_fts", "posts_rtree", "posts__history"] {
+        assert!(tables.iter().any(|t| t == table), "{} was dropped", table);
+    }
+
+    // The indexes and history created for a table go with it
+    db.create_search_index("places", &["name".to_string()]).await.unwrap();
+    db.create_spatial_index("places", &["lat".to_string(), "lon".to_string()]).await.unwrap();
+    db.enable_history("places").await.unwrap();
+    db.drop_table("places").await.unwrap();
+    let tables = names(db.query(table_names, HashMap::new()).await.unwrap());
+    assert!(!tables.iter().any(|t| t.starts_with("places")), "left behind: {:?}", tables);
+}
+
+#[tokio::test]
+async fn test_spatial_index_bounding_box_queries() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE places (id INTEGER PRIMARY KEY, name TEXT, lon REAL, lat REAL)", HashMap::new())
+        .await
+        .unwrap();
+    db.query(
+        "INSERT INTO places (name, lon, lat) VALUES ('Manhattan', -73.97, 40.78), ('Paris', 2.35, 48.86), ('Nowhere', NULL, NULL)",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+
+    // Rows without coordinates are not indexed
+    let columns = vec!["lon".to_string(), "lat".to_string()];
+    assert_eq!(db.create_spatial_index("places", &columns).await.unwrap(), 2);
+
+    let new_york = BoundingBox { min_x: -74.3, min_y: 40.5, max_x: -73.7, max_y: 40.9 };
+    let result = db.spatial_query("places", new_york, 100).await.unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert!(matches!(&result.rows[0][1], DbValue::Text(name) if name == "Manhattan"));
+
+    // Moving a row updates the index
+    db.query("UPDATE places SET lon = -74.0, lat = 40.7 WHERE name = 'Paris'", HashMap::new()).await.unwrap();
+    db.query("UPDATE places SET lon = -73.8, lat = 40.6 WHERE name = 'Nowhere'", HashMap::new()).await.unwrap();
+    assert_eq!(db.spatial_query("places", new_york, 100).await.unwrap().rows.len(), 3);
+    assert_eq!(db.spatial_query("places", new_york, 2).await.unwrap().rows.len(), 2);
+
+    db.delete("places", "name = 'Manhattan'").await.unwrap();
+    assert_eq!(db.spatial_query("places", new_york, 100).await.unwrap().rows.len(), 2);
+
+    assert!(matches!(
+        db.create_spatial_index("places", &["lon".to_string()]).await,
+        Err(DatabaseError::QueryError(_))
+    ));
+    assert!(matches!(
+        db.spatial_query("missing", new_york, 10).await,
+        Err(DatabaseError::QueryError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_session_temp_tables() {
+    let db = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, total REAL)").await.unwrap();
+    db.execute("INSERT INTO orders VALUES (1, 50), (2, 150), (3, 250)").await.unwrap();
+
+    // Sessions of an in-memory database see its data
+    let session = db.open_session().await.unwrap();
+    session.execute("CREATE TEMP TABLE big AS SELECT * FROM orders WHERE total > 100").await.unwrap();
+    let count = |result: QueryResult| match result.rows[0][0] {
+        DbValue::Integer(n) => n,
+        ref other => panic!("{:?}", other),
+    };
+    assert_eq!(count(session.query("SELECT count(*) FROM big", HashMap::new()).await.unwrap()), 2);
+
+    // Neither the shared pool nor another session sees the TEMP table
+    assert!(db.query("SELECT count(*) FROM big", HashMap::new()).await.is_err());
+    let other = db.open_session().await.unwrap();
+    assert!(other.query("SELECT count(*) FROM big", HashMap::new()).await.is_err());
+
+    // Writes through a session reach the database
+    session.execute("DELETE FROM orders WHERE id IN (SELECT id FROM big)").await.unwrap();
+    assert_eq!(count(db.query("SELECT count(*) FROM orders", HashMap::new()).await.unwrap()), 1);
+}
+
+#[tokio::test]
+async fn test_sample_rows() {
+    let db = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    db.execute("CREATE TABLE events (id INTEGER PRIMARY KEY, kind TEXT)").await.unwrap();
+    db.execute(
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000) \
+         INSERT INTO events SELECT i, 'kind' || (i % 7) FROM n",
+    )
+    .await
+    .unwrap();
+
+    let ids = |result: &QueryResult| -> Vec<i64> {
+        result.rows.iter().map(|row| match row[0] { DbValue::Integer(id) => id, ref other => panic!("{:?}", other) }).collect()
+    };
+    let sample = db.sample("events", 50).await.unwrap();
+    assert_eq!(sample.columns.len(), 2);
+    let sampled = ids(&sample);
+    assert_eq!(sampled.len(), 50);
+    // Distinct and in table order
+    assert!(sampled.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_ne!(sampled, ids(&db.sample("events", 50).await.unwrap()));
+
+    // Asking for more rows than a table has returns all of them
+    db.execute("DELETE FROM events WHERE id % 1000 != 0").await.unwrap();
+    assert_eq!(ids(&db.sample("events", 100).await.unwrap()), vec![1000, 2000, 3000, 4000, 5000]);
+
+    db.execute("CREATE TABLE tags (name TEXT PRIMARY KEY) WITHOUT ROWID").await.unwrap();
+    db.execute("INSERT INTO tags VALUES ('a'), ('b'), ('c')").await.unwrap();
+    assert_eq!(db.sample("tags", 2).await.unwrap().rows.len(), 2);
+
+    assert!(matches!(db.sample("missing", 10).await, Err(DatabaseError::TableNotFound(_))));
+}
+
+#[tokio::test]
+async fn test_dedupe_rows() {
+    let db = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT, name TEXT, updated_at INTEGER)").await.unwrap();
+    db.execute(
+        "INSERT INTO users VALUES (1, 'a@x', 'old', 10), (2, 'a@x', 'new', 30), (3, 'a@x', 'mid', 20), \
+         (4, 'b@x', 'only', 10), (5, NULL, 'n1', 10), (6, NULL, 'n2', 20)",
+    )
+    .await
+    .unwrap();
+    let by = vec!["email".to_string()];
+    let ids = |result: QueryResult| -> Vec<i64> {
+        result.rows.iter().map(|row| match row[0] { DbValue::Integer(id) => id, ref other => panic!("{:?}", other) }).collect()
+    };
+
+    // A dry run counts without deleting
+    let outcome = db.dedupe("users", &by, &KeepRow::Newest("updated_at".to_string()), true).await.unwrap();
+    assert_eq!(outcome, DedupeOutcome { groups: 2, removed: 3 });
+    assert_eq!(ids(db.query("SELECT id FROM users", HashMap::new()).await.unwrap()).len(), 6);
+
+    // NULLs group together
+    let outcome = db.dedupe("users", &by, &KeepRow::Newest("updated_at".to_string()), false).await.unwrap();
+    assert_eq!(outcome, DedupeOutcome { groups: 2, removed: 3 });
+    assert_eq!(ids(db.query("SELECT id FROM users ORDER BY id", HashMap::new()).await.unwrap()), vec![2, 4, 6]);
+    assert_eq!(db.dedupe("users", &by, &KeepRow::First, false).await.unwrap(), DedupeOutcome::default());
+
+    db.execute("INSERT INTO users VALUES (7, 'b@x', 'again', 5), (8, 'b@x', 'third', 1)").await.unwrap();
+    assert_eq!(db.dedupe("users", &by, &KeepRow::Last, false).await.unwrap().removed, 2);
+    assert_eq!(ids(db.query("SELECT id FROM users ORDER BY id", HashMap::new()).await.unwrap()), vec![2, 6, 8]);
+
+    assert!(db.dedupe("users", &["nope".to_string()], &KeepRow::First, false).await.is_err());
+    assert!(db.dedupe("users", &by, &KeepRow::Oldest("nope".to_string()), false).await.is_err());
+    assert!(db.dedupe("users", &[], &KeepRow::First, false).await.is_err());
+    db.execute("CREATE TABLE tags (name TEXT PRIMARY KEY, label TEXT) WITHOUT ROWID").await.unwrap();
+    assert!(db.dedupe("tags", &["label".to_string()], &KeepRow::First, false).await.is_err());
+}
+
+#[tokio::test]
+async fn test_missing_extension_fails_to_connect() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let options = DatabaseOptions {
+        extensions: vec![SqliteExtension::Path("/nonexistent/libmissing_ext".to_string())],
+        ..DatabaseOptions::default()
+    };
+
+    let result = SqliteDatabase::connect_with_options(&db_url, &options).await;
+    assert!(matches!(result, Err(DatabaseError::ConnectionError(_))));
+}
+
+#[tokio::test]
+async fn test_custom_sql_functions() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE users (email TEXT)", HashMap::new()).await.unwrap();
+    db.query(
+        "INSERT INTO users VALUES ('alice@example.com'), ('bob@test.org'), (NULL)",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+
+    let result = db
+        .query("SELECT email FROM users WHERE email REGEXP '@example\\.(com|net)$'", HashMap::new())
+        .await
+        .unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert!(matches!(&result.rows[0][0], DbValue::Text(email) if email == "alice@example.com"));
+
+    let result = db.query("SELECT uuid4(), uuid4(), unix_now()", HashMap::new()).await.unwrap();
+    let (DbValue::Text(a), DbValue::Text(b)) = (&result.rows[0][0], &result.rows[0][1]) else {
+        panic!("uuid4() should return text");
+    };
+    assert_eq!(a.len(), 36);
+    assert_ne!(a, b);
+    assert!(matches!(result.rows[0][2], DbValue::Integer(now) if now > 1_600_000_000));
+
+    let invalid = db.query("SELECT 'x' REGEXP '('", HashMap::new()).await;
+    assert!(invalid.unwrap_err().to_string().contains("Invalid regular expression"));
+}
+
+#[tokio::test]
+async fn test_encrypted_columns_metadata() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE patients (id INTEGER PRIMARY KEY, name TEXT, ssn TEXT)", HashMap::new()).await.unwrap();
+    assert!(db.encrypted_columns("patients").await.unwrap().is_empty());
+
+    db.set_encrypted_columns("patients", &["ssn".to_string()]).await.unwrap();
+    assert_eq!(db.encrypted_columns("patients").await.unwrap(), vec!["ssn".to_string()]);
+
+    // Primary keys and unknown columns are rejected
+    let result = db.set_encrypted_columns("patients", &["id".to_string()]).await;
+    assert!(matches!(result, Err(DatabaseError::QueryError(_))));
+    let result = db.set_encrypted_columns("patients", &["missing".to_string()]).await;
+    assert!(matches!(result, Err(DatabaseError::QueryError(_))));
+
+    db.drop_table("patients").await.unwrap();
+    assert!(db.encrypted_columns("patients").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_disk_usage() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE events (id INTEGER PRIMARY KEY, payload TEXT)", HashMap::new()).await.unwrap();
+    db.query(
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 500) \
+         INSERT INTO events (payload) SELECT hex(randomblob(64)) FROM n",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+
+    let usage = db.disk_usage().await.unwrap();
+    assert!(usage.page_count > 1);
+    assert!(usage.page_size >= 512);
+    assert!(usage.file_bytes >= usage.page_size);
+}
+
+#[tokio::test]
+async fn test_ttl_purges_expired_rows() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE events (id INTEGER PRIMARY KEY, expires_at)", HashMap::new()).await.unwrap();
+    db.query(
+        "INSERT INTO events (expires_at) VALUES \
+         (unixepoch() - 10), (unixepoch() + 3600), ('2000-01-01 00:00:00'), (NULL)",
+        HashMap::new(),
+    )
+    .await
+    .unwrap();
+
+    // Nothing is purged until the table has a TTL
+    assert!(db.purge_expired().await.unwrap().is_empty());
+    assert!(matches!(
+        db.set_ttl_column("events", Some("missing")).await,
+        Err(DatabaseError::QueryError(_))
+    ));
+
+    db.set_ttl_column("events", Some("expires_at")).await.unwrap();
+    assert_eq!(db.ttl_column("events").await.unwrap().as_deref(), Some("expires_at"));
+    assert_eq!(db.purge_expired().await.unwrap(), vec![("events".to_string(), 2)]);
+    assert!(db.purge_expired().await.unwrap().is_empty());
+
+    let result = db.query("SELECT COUNT(*) FROM events", HashMap::new()).await.unwrap();
+    assert!(matches!(result.rows[0][0], DbValue::Integer(2)));
+
+    db.set_ttl_column("events", None).await.unwrap();
+    assert_eq!(db.ttl_column("events").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_job_storage_and_history() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE sessions (id INTEGER PRIMARY KEY, expired INTEGER)", HashMap::new()).await.unwrap();
+    db.query("INSERT INTO sessions (expired) VALUES (1), (1), (0)", HashMap::new()).await.unwrap();
+
+    let job = Job {
+        name: "cleanup".to_string(),
+        schedule: "0 3 * * *".to_string(),
+        sql: "DELETE FROM sessions WHERE expired = 1".to_string(),
+        database: "default".to_string(),
+        from_config: false,
+    };
+    assert!(db.jobs().await.unwrap().is_empty());
+    db.save_job(&job).await.unwrap();
+    let saved = db.jobs().await.unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].sql, job.sql);
+
+    assert_eq!(db.execute(&job.sql).await.unwrap(), 2);
+    assert!(db.execute("DELETE FROM missing").await.is_err());
+
+    for (started_at, success) in [(100, true), (200, false)] {
+        db.record_job_run(&JobRun {
+            job_name: job.name.clone(),
+            started_at,
+            duration_ms: 5,
+            success,
+            rows_affected: if success { 2 } else { 0 },
+            message: String::new(),
+        })
+        .await
+        .unwrap();
+    }
+    let runs = db.job_runs(Some("cleanup"), 10).await.unwrap();
+    assert_eq!(runs.iter().map(|r| r.started_at).collect::<Vec<_>>(), vec![200, 100]);
+    assert_eq!(db.job_runs(None, 1).await.unwrap().len(), 1);
+    assert!(db.job_runs(Some("other"), 10).await.unwrap().is_empty());
+
+    assert!(db.delete_job("cleanup").await.unwrap());
+    assert!(!db.delete_job("cleanup").await.unwrap());
+    assert!(db.jobs().await.unwrap().is_empty());
 }
 
+#[tokio::test]
+async fn test_materialized_view_refresh() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.query("CREATE TABLE sales (id INTEGER PRIMARY KEY, region TEXT, amount REAL)", HashMap::new()).await.unwrap();
+    db.query("INSERT INTO sales (region, amount) VALUES ('east', 10), ('east', 5), ('west', 7)", HashMap::new())
+        .await
+        .unwrap();
+
+    let view = MaterializedView {
+        name: "sales_by_region".to_string(),
+        query: "SELECT region, SUM(amount) AS total FROM sales GROUP BY region".to_string(),
+        refresh_interval_seconds: 0,
+        last_refreshed: None,
+    };
+    assert_eq!(db.create_materialized_view(&view).await.unwrap(), 2);
+    let views = db.materialized_views().await.unwrap();
+    assert_eq!(views.len(), 1);
+    assert!(views[0].last_refreshed.is_some());
+
+    // The backing table only changes on refresh
+    db.query("INSERT INTO sales (region, amount) VALUES ('north', 3)", HashMap::new()).await.unwrap();
+    let result = db.query("SELECT COUNT(*) FROM sales_by_region", HashMap::new()).await.unwrap();
+    assert!(matches!(result.rows[0][0], DbValue::Integer(2)));
+    assert_eq!(db.refresh_materialized_view("sales_by_region").await.unwrap(), 3);
+
+    // On-demand views are never due; a regular table cannot become a view
+    assert!(db.refresh_due_materialized_views().await.unwrap().is_empty());
+    assert!(matches!(
+        db.refresh_materialized_view("sales").await,
+        Err(DatabaseError::TableNotFound(_))
+    ));
+    let clash = MaterializedView { name: "sales".to_string(), ..view.clone() };
+    assert!(matches!(
+        db.create_materialized_view(&clash).await,
+        Err(DatabaseError::TableAlreadyExists(_))
+    ));
+
+    db.drop_table("sales_by_region").await.unwrap();
+    assert!(db.materialized_views().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_partitioned_table() {
+    use datasink::db::partition::{PartitionPeriod, Partitioning};
+
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    let columns = vec![
+        ColumnDef {
+            name: "ts".to_string(),
+            col_type: ColumnType::Integer,
+            nullable: true,
+            primary_key: false,
+            unique: false,
+            default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
+        },
+        ColumnDef {
+            name: "kind".to_string(),
+            col_type: ColumnType::Text,
+            nullable: true,
+            primary_key: false,
+            unique: false,
+            default_value: None,
+            generated: None,
+            collation: None,
+            allowed_values: None,
+        },
+    ];
+    let partitioning = Partitioning {
+        column: "ts".to_string(),
+        period: PartitionPeriod::Month,
+        retention: Some(2),
+    };
+    db.create_partitioned_table("events", columns, &partitioning).await.unwrap();
+    assert_eq!(db.partitioning("events").await.unwrap(), Some(partitioning));
+    assert_eq!(db.partitions("events").await.unwrap().len(), 1);
+
+    let row = |ts: Option<i64>, kind: &str| {
+        let mut values = HashMap::new();
+        if let Some(ts) = ts {
+            values.insert("ts".to_string(), DbValue::Integer(ts));
+        }
+        values.insert("kind".to_string(), DbValue::Text(kind.to_string()));
+        values
+    };
+    // 2024-01-15 and 2024-02-10; the row without a time lands in the current month
+    let rows = vec![row(Some(1705312800), "a"), row(Some(1707562800), "b"), row(None, "c")];
+    assert_eq!(db.batch_insert("events", rows).await.unwrap(), 3);
+
+    let partitions = db.partitions("events").await.unwrap();
+    assert_eq!(partitions.len(), 3);
+    assert_eq!(&partitions[..2], ["events_2024_01", "events_2024_02"]);
+
+    let count = |sql: &'static str| {
+        let db = &db;
+        async move {
+            match db.query(sql, HashMap::new()).await.unwrap().rows[0][0] {
+                DbValue::Integer(n) => n,
+                ref other => panic!("unexpected count {:?}", other),
+            }
+        }
+    };
+    assert_eq!(count("SELECT COUNT(*) FROM events").await, 3);
+
+    let mut values = HashMap::new();
+    values.insert("kind".to_string(), DbValue::Text("old".to_string()));
+    assert_eq!(db.update("events", values, "ts < 1710000000").await.unwrap(), 2);
+    assert_eq!(count("SELECT COUNT(*) FROM events WHERE kind = 'old'").await, 2);
+    assert_eq!(db.delete("events", "kind = 'c'").await.unwrap(), 1);
+
+    // Only the current and previous month are retained
+    let dropped = db.drop_expired_partitions().await.unwrap();
+    assert_eq!(dropped.len(), 2);
+    assert_eq!(count("SELECT COUNT(*) FROM events").await, 0);
+    assert_eq!(db.partitions("events").await.unwrap().len(), 1);
+
+    db.drop_table("events").await.unwrap();
+    assert!(db.partitions("events").await.unwrap().is_empty());
+    assert!(db.query("SELECT * FROM events", HashMap::new()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_foreign_key_enforcement_and_violations() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+
+    // Rows written without enforcement can break constraints
+    let options = DatabaseOptions {
+        foreign_keys: Some(false),
+        ..DatabaseOptions::default()
+    };
+    let db = SqliteDatabase::connect_with_options(&db_url, &options).await.unwrap();
+    assert!(!db.foreign_keys_enabled().await.unwrap());
+
+    db.execute("CREATE TABLE customers (id INTEGER PRIMARY KEY, name TEXT)").await.unwrap();
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, customer_id INTEGER REFERENCES customers(id))")
+        .await
+        .unwrap();
+    db.execute("INSERT INTO customers (id, name) VALUES (1, 'Alice')").await.unwrap();
+    db.execute("INSERT INTO orders (id, customer_id) VALUES (10, 1), (11, 2), (12, 3)").await.unwrap();
+
+    let violations = db.foreign_key_violations(None, 100).await.unwrap();
+    assert_eq!(violations.len(), 2);
+    assert_eq!(violations[0].table, "orders");
+    assert_eq!(violations[0].parent, "customers");
+    assert_eq!(violations[0].rowid, Some(11));
+    assert_eq!(db.foreign_key_violations(Some("orders"), 1).await.unwrap().len(), 1);
+    assert!(db.foreign_key_violations(Some("customers"), 100).await.unwrap().is_empty());
+    assert!(matches!(
+        db.foreign_key_violations(Some("missing"), 100).await,
+        Err(DatabaseError::TableNotFound(_))
+    ));
+    drop(db);
+
+    // Enforcement is on by default
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    assert!(db.foreign_keys_enabled().await.unwrap());
+    assert!(db.execute("INSERT INTO orders (id, customer_id) VALUES (13, 99)").await.is_err());
+}
+
+#[tokio::test]
+async fn test_saved_queries() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    assert!(db.saved_queries().await.unwrap().is_empty());
+    assert!(db.saved_query("big_orders").await.unwrap().is_none());
+    assert!(!db.delete_saved_query("big_orders").await.unwrap());
+
+    let mut query = SavedQuery {
+        name: "big_orders".to_string(),
+        sql: "SELECT id FROM orders WHERE total > :min".to_string(),
+        description: String::new(),
+    };
+    db.save_query(&query).await.unwrap();
+    query.sql = "SELECT id FROM orders WHERE customer = :customer AND total >= :min ORDER BY id".to_string();
+    query.description = "Orders over a threshold".to_string();
+    db.save_query(&query).await.unwrap();
+
+    let saved = db.saved_queries().await.unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].description, "Orders over a threshold");
+    let saved = db.saved_query("big_orders").await.unwrap().unwrap();
+    assert_eq!(saved.sql, query.sql);
+
+    // Named parameters bind by name whatever order they arrive in
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, customer TEXT, total REAL)").await.unwrap();
+    db.execute("INSERT INTO orders VALUES (1, 'a', 50), (2, 'b', 500), (3, 'a', 300), (4, 'a', 100)")
+        .await
+        .unwrap();
+    let params = HashMap::from([
+        ("min".to_string(), DbValue::Integer(100)),
+        ("customer".to_string(), DbValue::Text("a".to_string())),
+    ]);
+    let result = db.query(&saved.sql, params).await.unwrap();
+    let ids: Vec<i64> = result
+        .rows
+        .iter()
+        .map(|row| match row[0] {
+            DbValue::Integer(id) => id,
+            _ => panic!("expected integer id"),
+        })
+        .collect();
+    assert_eq!(ids, vec![3, 4]);
+
+    assert!(db.delete_saved_query("big_orders").await.unwrap());
+    assert!(db.saved_queries().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_generated_columns() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    let column = |name: &str, col_type: ColumnType, generated: Option<GeneratedColumn>| ColumnDef {
+        name: name.to_string(),
+        col_type,
+        nullable: true,
+        primary_key: name == "id",
+        unique: false,
+        default_value: None,
+        generated,
+        collation: None,
+        allowed_values: None,
+    };
+    let columns = vec![
+        column("id", ColumnType::Integer, None),
+        column("price", ColumnType::Real, None),
+        column("quantity", ColumnType::Integer, None),
+        column(
+            "total",
+            ColumnType::Real,
+            Some(GeneratedColumn { expression: "price * quantity".to_string(), stored: false }),
+        ),
+        column(
+            "label",
+            ColumnType::Text,
+            Some(GeneratedColumn { expression: "'#' || id".to_string(), stored: true }),
+        ),
+    ];
+    assert!(SqliteDatabase::build_create_table_sql("items", &columns, &[]).ends_with(
+        "total REAL GENERATED ALWAYS AS (price * quantity) VIRTUAL, label TEXT GENERATED ALWAYS AS ('#' || id) STORED)"
+    ));
+    db.create_table("items", columns).await.unwrap();
+
+    db.insert(
+        "items",
+        HashMap::from([
+            ("id".to_string(), DbValue::Integer(1)),
+            ("price".to_string(), DbValue::Real(2.5)),
+            ("quantity".to_string(), DbValue::Integer(4)),
+        ]),
+    )
+    .await
+    .unwrap();
+
+    // Generated columns are read like any other column but cannot be written
+    let schema = db.table_schema("items").await.unwrap();
+    assert_eq!(schema.len(), 5);
+    let row = db.get_row("items", vec![DbValue::Integer(1)]).await.unwrap().unwrap();
+    assert_eq!(row.columns.len(), 5);
+    assert!(matches!(row.rows[0][3], DbValue::Real(total) if total == 10.0));
+    assert!(matches!(&row.rows[0][4], DbValue::Text(label) if label == "#1"));
+    assert!(db.insert("items", HashMap::from([("total".to_string(), DbValue::Real(1.0))])).await.is_err());
+}
+
+#[tokio::test]
+async fn test_column_collation() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    let columns = vec![ColumnDef {
+        name: "email".to_string(),
+        col_type: ColumnType::Text,
+        nullable: false,
+        primary_key: false,
+        unique: true,
+        default_value: None,
+        generated: None,
+        collation: Some("nocase".parse().unwrap()),
+        allowed_values: None,
+    }];
+    assert_eq!(
+        SqliteDatabase::build_create_table_sql("users", &columns, &[]),
+        "CREATE TABLE users (email TEXT COLLATE NOCASE NOT NULL UNIQUE)"
+    );
+    db.create_table("users", columns).await.unwrap();
+
+    let email = |email: &str| HashMap::from([("email".to_string(), DbValue::Text(email.to_string()))]);
+    db.insert("users", email("Alice@Example.com")).await.unwrap();
+    // Case-insensitive keys collide and match
+    assert!(db.insert("users", email("alice@example.com")).await.unwrap_err().is_unique_violation());
+    let result = db.query("SELECT email FROM users WHERE email = 'ALICE@EXAMPLE.COM'", HashMap::new()).await.unwrap();
+    assert_eq!(result.rows.len(), 1);
+}
+
+#[tokio::test]
+async fn test_column_defaults() {
+    let cases = [
+        ("CURRENT_TIMESTAMP", ColumnType::Timestamp, "(unixepoch())"),
+        ("current_timestamp", ColumnType::Text, "CURRENT_TIMESTAMP"),
+        ("'open'", ColumnType::Text, "'open'"),
+        ("it's", ColumnType::Text, "'it''s'"),
+        ("\"done\"", ColumnType::Text, "'done'"),
+        ("42", ColumnType::Integer, "42"),
+        ("1.5", ColumnType::Real, "1.5"),
+        ("false", ColumnType::Boolean, "0"),
+        ("NULL", ColumnType::Integer, "NULL"),
+        ("(random() % 10)", ColumnType::Integer, "(random() % 10)"),
+        ("x'00ff'", ColumnType::Blob, "x'00ff'"),
+    ];
+    for (default, col_type, sql) in cases {
+        assert_eq!(SqliteDatabase::default_sql("c", default, &col_type).unwrap(), sql, "{}", default);
+    }
+    for (default, col_type) in [("abc", ColumnType::Integer), ("yes", ColumnType::Boolean), ("CURRENT_TIMESTAMP", ColumnType::Real)] {
+        assert!(SqliteDatabase::default_sql("c", default, &col_type).is_err(), "{}", default);
+    }
+
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    let column = |name: &str, col_type: ColumnType, default: &str| ColumnDef {
+        name: name.to_string(),
+        default_value: Some(SqliteDatabase::default_sql(name, default, &col_type).unwrap()),
+        col_type,
+        nullable: true,
+        primary_key: false,
+        unique: false,
+        generated: None,
+        collation: None,
+        allowed_values: None,
+    };
+    let columns = vec![
+        column("id", ColumnType::Integer, "1"),
+        column("status", ColumnType::Text, "'open'"),
+        column("created_at", ColumnType::Timestamp, "CURRENT_TIMESTAMP"),
+    ];
+    db.create_table("tickets", columns).await.unwrap();
+    db.execute("INSERT INTO tickets DEFAULT VALUES").await.unwrap();
+    let result = db
+        .query("SELECT status, created_at > 1700000000 AS recent FROM tickets", HashMap::new())
+        .await
+        .unwrap();
+    assert!(matches!(&result.rows[0][0], DbValue::Text(s) if s == "open"));
+    assert!(matches!(result.rows[0][1], DbValue::Integer(1)));
+}
+
+#[tokio::test]
+async fn test_descriptions() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT)").await.unwrap();
+    assert!(db.descriptions("users").await.unwrap().is_empty());
+
+    let descriptions = Descriptions {
+        table: Some("People who can sign in".to_string()),
+        columns: HashMap::from([("email".to_string(), "Login address".to_string())]),
+    };
+    db.set_descriptions("users", &descriptions).await.unwrap();
+    assert_eq!(db.descriptions("users").await.unwrap(), descriptions);
+
+    // Recording again replaces what was there
+    let columns_only = Descriptions { table: None, columns: descriptions.columns.clone() };
+    db.set_descriptions("users", &columns_only).await.unwrap();
+    assert_eq!(db.descriptions("users").await.unwrap(), columns_only);
+
+    let unknown = Descriptions { table: None, columns: HashMap::from([("name".to_string(), "x".to_string())]) };
+    assert!(db.set_descriptions("users", &unknown).await.is_err());
+    assert!(db.set_descriptions("nope", &descriptions).await.is_err());
+}
+
+#[tokio::test]
+async fn test_allowed_values() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    let values = vec!["low".to_string(), "medium".to_string(), "it's".to_string()];
+    let columns = vec![ColumnDef {
+        name: "priority".to_string(),
+        col_type: ColumnType::Text,
+        nullable: true,
+        primary_key: false,
+        unique: false,
+        default_value: None,
+        generated: None,
+        collation: None,
+        allowed_values: Some(values.clone()),
+    }];
+    let sql = SqliteDatabase::build_create_table_sql("tasks", &columns, &[]);
+    assert_eq!(sql, "CREATE TABLE tasks (priority TEXT CHECK (priority IN ('low', 'medium', 'it''s')))");
+    assert_eq!(SqliteDatabase::parse_allowed_values(&sql), HashMap::from([("priority".to_string(), values)]));
+    db.create_table("tasks", columns).await.unwrap();
+
+    let priority = |value: DbValue| HashMap::from([("priority".to_string(), value)]);
+    db.insert("tasks", priority(DbValue::Text("it's".to_string()))).await.unwrap();
+    db.insert("tasks", priority(DbValue::Null)).await.unwrap();
+    let error = db.insert("tasks", priority(DbValue::Text("urgent".to_string()))).await.unwrap_err();
+    assert!(error.is_constraint_violation());
+}
+
+#[tokio::test]
+async fn test_composite_unique() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    let column = |name: &str, col_type: ColumnType| ColumnDef {
+        name: name.to_string(),
+        col_type,
+        nullable: false,
+        primary_key: false,
+        unique: false,
+        default_value: None,
+        generated: None,
+        collation: None,
+        allowed_values: None,
+    };
+    let columns = vec![column("org_id", ColumnType::Integer), column("email", ColumnType::Text)];
+    let unique = vec![vec!["org_id".to_string(), "email".to_string()]];
+    assert!(db.create_table_with_unique("members", columns.clone(), &[vec![]]).await.is_err());
+    db.create_table_with_unique("members", columns, &unique).await.unwrap();
+
+    let member = |org_id: i64, email: &str| {
+        HashMap::from([
+            ("org_id".to_string(), DbValue::Integer(org_id)),
+            ("email".to_string(), DbValue::Text(email.to_string())),
+        ])
+    };
+    db.insert("members", member(1, "a@example.com")).await.unwrap();
+    // Only the combination has to be unique
+    db.insert("members", member(2, "a@example.com")).await.unwrap();
+    db.insert("members", member(1, "b@example.com")).await.unwrap();
+    assert!(db.insert("members", member(1, "a@example.com")).await.unwrap_err().is_unique_violation());
+}
+
+#[tokio::test]
+async fn test_applied_schema() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    assert!(db.applied_schema().await.unwrap().is_none());
+
+    db.record_applied_schema("shop", "1.0.0", "aaaa").await.unwrap();
+    db.record_applied_schema("shop", "1.1.0", "bbbb").await.unwrap();
+
+    // The latest application wins
+    let applied = db.applied_schema().await.unwrap().unwrap();
+    assert_eq!((applied.name.as_str(), applied.version.as_str(), applied.checksum.as_str()), ("shop", "1.1.0", "bbbb"));
+    assert!(applied.applied_at > 0);
+}
+
+#[tokio::test]
+async fn test_execute_writes_is_atomic() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.execute("CREATE TABLE accounts (id INTEGER PRIMARY KEY, balance INTEGER NOT NULL)").await.unwrap();
+    db.execute("INSERT INTO accounts VALUES (1, 100), (2, 0)").await.unwrap();
+
+    let balance = |amount: i64| HashMap::from([("balance".to_string(), DbValue::Integer(amount))]);
+    let insert = |id: i64, on_conflict: OnConflict| WriteOp::Insert {
+        table_name: "accounts".to_string(),
+        values: HashMap::from([
+            ("id".to_string(), DbValue::Integer(id)),
+            ("balance".to_string(), DbValue::Integer(0)),
+        ]),
+        on_conflict,
+    };
+    let balances = || async {
+        let result = db.query("SELECT balance FROM accounts ORDER BY id", HashMap::new()).await.unwrap();
+        result
+            .rows
+            .into_iter()
+            .map(|row| match row[0] {
+                DbValue::Integer(balance) => balance,
+                _ => panic!("expected integer balance"),
+            })
+            .collect::<Vec<i64>>()
+    };
+
+    let outcomes = db
+        .execute_writes(vec![
+            WriteOp::Update {
+                table_name: "accounts".to_string(),
+                values: balance(60),
+                where_clause: "id = 1".to_string(),
+            },
+            WriteOp::Update {
+                table_name: "accounts".to_string(),
+                values: balance(40),
+                where_clause: "id = 2".to_string(),
+            },
+            insert(3, OnConflict::Abort),
+            insert(3, OnConflict::Ignore),
+            WriteOp::Delete {
+                table_name: "accounts".to_string(),
+                where_clause: "id = 3".to_string(),
+            },
+        ])
+        .await
+        .unwrap();
+    assert_eq!(outcomes.len(), 5);
+    assert_eq!(outcomes[0].rows_affected, 1);
+    assert_eq!(outcomes[2].inserted_id, Some(3));
+    assert_eq!(outcomes[3], WriteOutcome { rows_affected: 0, inserted_id: None });
+    assert_eq!(outcomes[4].rows_affected, 1);
+    assert_eq!(balances().await, vec![60, 40]);
+
+    // A failing write rolls back the ones before it
+    let err = db
+        .execute_writes(vec![
+            WriteOp::Update {
+                table_name: "accounts".to_string(),
+                values: balance(0),
+                where_clause: "id = 1".to_string(),
+            },
+            insert(2, OnConflict::Abort),
+        ])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, DatabaseError::TransactionError(ref msg) if msg.starts_with("Operation 2 (insert into accounts)")));
+    assert_eq!(balances().await, vec![60, 40]);
+}
+
+#[tokio::test]
+async fn test_error_classification() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT NOT NULL UNIQUE)").await.unwrap();
+    db.execute("INSERT INTO users (id, email) VALUES (1, 'a@example.com')").await.unwrap();
+
+    let duplicate = db.execute("INSERT INTO users (id, email) VALUES (2, 'a@example.com')").await.unwrap_err();
+    assert!(duplicate.is_constraint_violation() && duplicate.is_unique_violation());
+
+    let missing = db.execute("INSERT INTO users (id) VALUES (3)").await.unwrap_err();
+    assert!(missing.is_constraint_violation() && !missing.is_unique_violation());
+
+    let syntax = db.execute("SELEC 1").await.unwrap_err();
+    assert!(syntax.is_invalid_statement() && !syntax.is_constraint_violation());
+    assert!(db.execute("SELECT * FROM nope").await.unwrap_err().is_invalid_statement());
+}
+
+#[tokio::test]
+async fn test_constraint_failure_details() {
+    use datasink::db::error::ConstraintFailure;
+
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    db.execute(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT NOT NULL UNIQUE, \
+         age INTEGER CONSTRAINT adult CHECK (age >= 18))",
+    )
+    .await
+    .unwrap();
+    db.execute("INSERT INTO users (id, email) VALUES (1, 'a@example.com')").await.unwrap();
+
+    let failure = |kind, name: Option<&str>, column: Option<&str>| {
+        Some(ConstraintFailure { kind, name: name.map(String::from), column: column.map(String::from) })
+    };
+    let cases = [
+        ("INSERT INTO users (id, email) VALUES (2, 'a@example.com')", failure("UNIQUE", None, Some("users.email")), 2067),
+        ("INSERT INTO users (id, email) VALUES (1, 'b@example.com')", failure("PRIMARY KEY", None, Some("users.id")), 1555),
+        ("INSERT INTO users (id) VALUES (3)", failure("NOT NULL", None, Some("users.email")), 1299),
+        ("INSERT INTO users (email, age) VALUES ('c@example.com', 5)", failure("CHECK", Some("adult"), None), 275),
+    ];
+    for (sql, expected, code) in cases {
+        let error = db.execute(sql).await.unwrap_err();
+        assert_eq!(error.constraint_failure(), expected, "{}", sql);
+        assert_eq!(error.sqlite_code(), Some(code), "{}", sql);
+        assert!(!error.is_retryable());
+    }
+
+    let syntax = db.execute("SELEC 1").await.unwrap_err();
+    assert_eq!(syntax.constraint_failure(), None);
+    assert_eq!(syntax.sqlite_code(), Some(1));
+    assert!(DatabaseError::ConnectionError("refused".to_string()).is_retryable());
+}
+
+#[tokio::test]
+async fn test_unknown_columns_rejected() {
+    use datasink::db::error::UnknownColumn;
+
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT)").await.unwrap();
+
+    let values = |pairs: &[&str]| -> HashMap<String, DbValue> {
+        pairs.iter().map(|name| (name.to_string(), DbValue::Text("x".to_string()))).collect()
+    };
+    let unknown = |name: &str, matches: &[&str]| UnknownColumn {
+        name: name.to_string(),
+        close_matches: matches.iter().map(|m| m.to_string()).collect(),
+    };
+
+    let error = db.insert("users", values(&["nmae", "emial", "nickname"])).await.unwrap_err();
+    assert_eq!(error.to_string(), "Table 'users' has no columns 'emial' (did you mean 'email'?), 'nickname', 'nmae' (did you mean 'name'?)");
+    match error {
+        DatabaseError::UnknownColumns { table, columns } => {
+            assert_eq!(table, "users");
+            assert_eq!(columns, [unknown("emial", &["email"]), unknown("nickname", &[]), unknown("nmae", &["name"])]);
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+
+    let error = db.update("users", values(&["NAME", "emal"]), "id = 1").await.unwrap_err();
+    assert!(matches!(error, DatabaseError::UnknownColumns { ref columns, .. } if *columns == [unknown("emal", &["email"])]));
+
+    let rows = vec![values(&["name"]), values(&["name", "mail"])];
+    let error = db.batch_insert_with_conflict("users", rows, OnConflict::Abort).await.unwrap_err();
+    assert!(matches!(error, DatabaseError::UnknownColumns { ref columns, .. } if *columns == [unknown("mail", &["email"])]));
+    // Nothing from the batch was written
+    assert!(db.query("SELECT * FROM users", HashMap::new()).await.unwrap().rows.is_empty());
+
+    // Names match SQLite's case-insensitively, quoted or not
+    assert!(db.insert("users", values(&["Name", "\"email\""])).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_query_stream_write_result() {
+    use futures::StreamExt;
+
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    db.execute("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)").await.unwrap();
+
+    let run = |sql: &'static str| {
+        let db = &db;
+        async move {
+            let (columns, stream) = db.query_stream(sql, HashMap::new()).await.unwrap();
+            let rows: Vec<_> = stream.map(|row| row.unwrap()).collect().await;
+            (columns.into_iter().map(|(name, _)| name).collect::<Vec<_>>(), rows)
+        }
+    };
+
+    let (columns, rows) = run("INSERT INTO notes (id, body) VALUES (7, 'a'), (8, 'b')").await;
+    assert_eq!(columns, vec!["affected_rows", "last_insert_rowid", "statement"]);
+    assert!(matches!(
+        rows[0].as_slice(),
+        [DbValue::Integer(2), DbValue::Integer(8), DbValue::Text(kind)] if kind == "INSERT"
+    ));
+
+    let (_, rows) = run("update notes SET body = 'c'").await;
+    assert!(matches!(
+        rows[0].as_slice(),
+        [DbValue::Integer(2), DbValue::Null, DbValue::Text(kind)] if kind == "UPDATE"
+    ));
+
+    let (_, rows) = run("INSERT OR IGNORE INTO notes (id, body) VALUES (7, 'dup')").await;
+    assert!(matches!(rows[0].as_slice(), [DbValue::Integer(0), DbValue::Null, _]));
+}
+
+#[tokio::test]
+async fn test_storage_info() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    db.execute("CREATE TABLE notes (id INTEGER PRIMARY KEY)").await.unwrap();
+    db.execute("CREATE TABLE tags (id INTEGER PRIMARY KEY AUTOINCREMENT)").await.unwrap();
+    db.record_applied_schema("notes", "1.0.0", "abc").await.unwrap();
+
+    let info = db.storage_info().await.unwrap();
+    assert_eq!(info.backend, "sqlite");
+    assert_eq!(info.file_path.as_deref(), Some(temp_file.path().to_str().unwrap()));
+    assert!(!info.journal_mode.is_empty());
+    // sqlite_sequence and the applied schema table are not counted
+    assert_eq!(info.table_count, 2);
+
+    let memory = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    assert_eq!(memory.storage_info().await.unwrap().file_path, None);
+}
+
+#[tokio::test]
+async fn test_table_stats() {
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    assert!(db.table_stats().await.unwrap().is_empty());
+
+    db.execute("CREATE TABLE \"order items\" (id INTEGER PRIMARY KEY AUTOINCREMENT, sku TEXT)").await.unwrap();
+    db.execute("CREATE TABLE notes (id INTEGER PRIMARY KEY)").await.unwrap();
+    db.execute("INSERT INTO \"order items\" (sku) VALUES ('a'), ('b'), ('c')").await.unwrap();
+    db.record_applied_schema("notes", "1.0.0", "abc").await.unwrap();
+
+    let stats = db.table_stats().await.unwrap();
+    // sqlite_sequence and DataSink's own tables are left out
+    let counts: Vec<(&str, u64)> = stats.iter().map(|table| (table.name.as_str(), table.rows)).collect();
+    assert_eq!(counts, [("notes", 0), ("order items", 3)]);
+}
+
+#[tokio::test]
+async fn test_set_pragma() {
+    use datasink::db::options::{runtime_pragma, runtime_pragma_value};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let registry_path = temp_dir.path().join("registry.json");
+    let db_url = format!("sqlite://{}?mode=rwc", temp_dir.path().join("live.db").display());
+    let manager = DatabaseManager::with_registry(&registry_path);
+    manager
+        .add_persistent_database("live".to_string(), db_url, DatabaseOptions::default())
+        .await
+        .unwrap();
+
+    manager.set_pragma("live", "synchronous", "OFF").await.unwrap();
+    manager.set_pragma("live", "journal_mode", "WAL").await.unwrap();
+    let db = manager.get_database("live").await.unwrap();
+    assert_eq!(db.read().await.pragma("synchronous").await.unwrap(), "0");
+    assert_eq!(db.read().await.pragma("journal_mode").await.unwrap(), "wal");
+
+    // Kept for the next start
+    let restarted = DatabaseManager::with_registry(&registry_path);
+    restarted.load_registry().await.unwrap();
+    let info = restarted.database_info("live").await.unwrap();
+    assert_eq!(info.options.pragmas.get("synchronous").map(String::as_str), Some("OFF"));
+    assert!(manager.set_pragma("missing", "synchronous", "OFF").await.is_err());
+
+    // Concurrent settings both survive
+    let (first, second) = tokio::join!(
+        manager.set_pragma("live", "cache_size", "-4000"),
+        manager.set_pragma("live", "temp_store", "MEMORY"),
+    );
+    first.unwrap();
+    second.unwrap();
+    let pragmas = manager.database_info("live").await.unwrap().options.pragmas;
+    assert_eq!(pragmas.get("cache_size").map(String::as_str), Some("-4000"));
+    assert_eq!(pragmas.get("temp_store").map(String::as_str), Some("MEMORY"));
+
+    assert_eq!(runtime_pragma(" Cache_Size ").unwrap(), "cache_size");
+    assert!(runtime_pragma("writable_schema").is_err());
+    assert_eq!(runtime_pragma_value("journal_mode", "wal").unwrap(), "WAL");
+    assert_eq!(runtime_pragma_value("cache_size", "-2000").unwrap(), "-2000");
+    assert!(runtime_pragma_value("journal_mode", "fast").is_err());
+    assert!(runtime_pragma_value("cache_size", "2000; DROP TABLE x").is_err());
+}
+
+#[tokio::test]
+async fn test_apply_schema() {
+    use datasink::schema::{apply, parser};
+
+    let temp_file = NamedTempFile::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_file.path().display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    let (schema, checksum) = parser::parse_schema(
+        r#"
+[database]
+name = "tenant"
+description = "Per-tenant"
+version = "2.0.0"
+
+[[tables]]
+name = "notes"
+
+[[tables.columns]]
+name = "id"
+type = "INTEGER"
+primary_key = true
+
+[[indexes]]
+table = "missing"
+name = "idx_missing"
+columns = ["id"]
+
+[[data.notes]]
+id = 1
+"#,
+    )
+    .unwrap();
+
+    let mut warnings = Vec::new();
+    let mut report = |event| {
+        if let apply::Event::Warning(warning) = event {
+            warnings.push(warning);
+        }
+    };
+    apply::apply(&db, &schema, &checksum, None, &mut report).await.unwrap();
+
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("Failed to create index idx_missing"), "{}", warnings[0]);
+    let rows = db.query("SELECT id FROM notes", HashMap::new()).await.unwrap().rows;
+    assert_eq!(rows.len(), 1);
+    let applied = db.applied_schema().await.unwrap().unwrap();
+    assert_eq!((applied.version.as_str(), applied.checksum), ("2.0.0", checksum));
+}
+
+#[tokio::test]
+async fn test_copy_database() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let db_url = format!("sqlite://{}?mode=rwc", temp_dir.path().join("source.db").display());
+    let db = SqliteDatabase::connect(&db_url).await.unwrap();
+    db.execute("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT, expires_at INTEGER)").await.unwrap();
+    db.create_search_index("notes", &["body".to_string()]).await.unwrap();
+    db.set_ttl_column("notes", Some("expires_at")).await.unwrap();
+    db.execute("INSERT INTO notes (id, body) VALUES (1, 'hello world')").await.unwrap();
+
+    async fn count(db: &SqliteDatabase, sql: &str) -> i64 {
+        match db.query(sql, HashMap::new()).await.unwrap().rows[0][0] {
+            DbValue::Integer(n) => n,
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    let full_path = temp_dir.path().join("full.db");
+    db.copy_to(&full_path, false).await.unwrap();
+    let full = SqliteDatabase::connect(&format!("sqlite://{}", full_path.display())).await.unwrap();
+    assert_eq!(count(&full, "SELECT COUNT(*) FROM notes_fts WHERE notes_fts MATCH 'hello'").await, 1);
+
+    let schema_path = temp_dir.path().join("schema.db");
+    db.copy_to(&schema_path, true).await.unwrap();
+    let copy = SqliteDatabase::connect(&format!("sqlite://{}", schema_path.display())).await.unwrap();
+    assert_eq!(copy.ttl_column("notes").await.unwrap().as_deref(), Some("expires_at"));
+    copy.execute("INSERT INTO notes (id, body) VALUES (5, 'fresh start')").await.unwrap();
+    assert_eq!(count(&copy, "SELECT COUNT(*) FROM notes").await, 1);
+    // The search index's triggers came along
+    assert_eq!(count(&copy, "SELECT COUNT(*) FROM notes_fts WHERE notes_fts MATCH 'fresh'").await, 1);
+
+    let memory = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    assert!(memory.copy_to(&temp_dir.path().join("memory.db"), true).await.is_err());
+}
+
+#[tokio::test]
+async fn test_read_replicas() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let primary_url = format!("sqlite://{}?mode=rwc", temp_dir.path().join("primary.db").display());
+    let replica_path = temp_dir.path().join("replica.db");
+
+    // The replica is written separately so reads show which database served them
+    let replica = SqliteDatabase::connect(&format!("sqlite://{}?mode=rwc", replica_path.display())).await.unwrap();
+    replica.execute("CREATE TABLE source (name TEXT)").await.unwrap();
+    replica.execute("INSERT INTO source VALUES ('replica')").await.unwrap();
+    drop(replica);
+
+    let manager = DatabaseManager::new();
+    let mut options = DatabaseOptions {
+        replicas: vec![format!("sqlite://{}", replica_path.display())],
+        ..Default::default()
+    };
+    options.pragmas.insert("journal_mode".to_string(), "WAL".to_string());
+    manager.add_persistent_database("app".to_string(), primary_url, options).await.unwrap();
+
+    let primary = manager.get_database("app").await.unwrap();
+    primary.read().await.execute("CREATE TABLE source (name TEXT)").await.unwrap();
+    primary.read().await.execute("INSERT INTO source VALUES ('primary')").await.unwrap();
+
+    let replica = manager.get_replica("app").await.unwrap();
+    let result = replica.read().await.query("SELECT name FROM source", HashMap::new()).await.unwrap();
+    assert!(matches!(&result.rows[0][0], DbValue::Text(name) if name == "replica"));
+
+    // Replicas are opened read-only
+    assert!(replica.read().await.execute("INSERT INTO source VALUES ('x')").await.is_err());
+    let info = manager.database_info("app").await.unwrap();
+    assert_eq!(info.options.replicas.len(), 1);
+
+    manager.add_database("plain".to_string(), "sqlite::memory:".to_string()).await.unwrap();
+    assert!(manager.get_replica("plain").await.is_none());
+}
+
+#[tokio::test]
+async fn test_url_secret_references() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    std::env::set_var("DATASINK_TEST_SECRET_DIR", temp_dir.path());
+
+    // References are resolved when connecting but kept in the recorded URL
+    let manager = DatabaseManager::new();
+    let url = "sqlite://${env:DATASINK_TEST_SECRET_DIR}/app.db?mode=rwc".to_string();
+    manager.add_database("app".to_string(), url.clone()).await.unwrap();
+    assert!(temp_dir.path().join("app.db").exists());
+    assert_eq!(manager.database_info("app").await.unwrap().url, url);
+
+    let missing = "sqlite://${env:DATASINK_TEST_MISSING_DIR}/app.db?mode=rwc".to_string();
+    assert!(manager.add_database("missing".to_string(), missing).await.is_err());
+}
+
+#[tokio::test]
+async fn test_startup_recovery_and_quick_check() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let crashed = temp_dir.path().join("crashed.db");
+
+    // Copy a database and its WAL while a connection still has it open, as
+    // a process killed mid-run leaves them
+    let live = temp_dir.path().join("live.db");
+    let db = SqliteDatabase::connect(&format!("sqlite://{}?mode=rwc", live.display())).await.unwrap();
+    db.execute("PRAGMA journal_mode = WAL").await.unwrap();
+    db.execute("CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT)").await.unwrap();
+    db.execute("INSERT INTO events (name) VALUES ('boot')").await.unwrap();
+    std::fs::copy(&live, &crashed).unwrap();
+    std::fs::copy(temp_dir.path().join("live.db-wal"), temp_dir.path().join("crashed.db-wal")).unwrap();
+    drop(db);
+
+    let manager = DatabaseManager::new();
+    manager
+        .add_database("crashed".to_string(), format!("sqlite://{}?mode=rwc", crashed.display()))
+        .await
+        .unwrap();
+    assert!(manager.database_info("crashed").await.unwrap().problems.is_empty());
+    let db = manager.get_database("crashed").await.unwrap();
+    let result = db.read().await.query("SELECT name FROM events", HashMap::new()).await.unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert_eq!(std::fs::metadata(temp_dir.path().join("crashed.db-wal")).unwrap().len(), 0);
+
+    // A damaged index is reported, and the database is still served
+    let damaged = temp_dir.path().join("damaged.db");
+    let db = SqliteDatabase::connect(&format!("sqlite://{}?mode=rwc", damaged.display())).await.unwrap();
+    db.execute("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT)").await.unwrap();
+    db.execute("CREATE INDEX items_name ON items (name)").await.unwrap();
+    db.execute("INSERT INTO items (name) VALUES ('a'), ('b'), ('c')").await.unwrap();
+    let root_page = db.query("SELECT rootpage FROM sqlite_master WHERE name = 'items_name'", HashMap::new()).await.unwrap();
+    let DbValue::Integer(root_page) = root_page.rows[0][0] else { panic!("rootpage is not an integer") };
+    drop(db);
+    let mut bytes = std::fs::read(&damaged).unwrap();
+    let page_size = 4096;
+    let offset = (root_page as usize - 1) * page_size;
+    bytes[offset..offset + page_size].fill(0xAB);
+    std::fs::write(&damaged, bytes).unwrap();
+
+    manager
+        .add_database("damaged".to_string(), format!("sqlite://{}?mode=rwc", damaged.display()))
+        .await
+        .unwrap();
+    assert!(!manager.database_info("damaged").await.unwrap().problems.is_empty());
+}
+
+#[tokio::test]
+async fn test_index_advisor() {
+    use datasink::db::advisor::{advise, QueryPattern};
+
+    let db = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT)").await.unwrap();
+    db.execute("CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER, status TEXT, total REAL)")
+        .await
+        .unwrap();
+    db.execute("CREATE INDEX idx_orders_user_id ON orders (user_id)").await.unwrap();
+
+    let patterns = vec![
+        QueryPattern { sql: "SELECT * FROM orders WHERE status = 'paid'".to_string(), count: 12 },
+        QueryPattern { sql: "SELECT * FROM orders WHERE status = 'refunded' AND total > 100".to_string(), count: 3 },
+        // Already indexed, and searched by primary key
+        QueryPattern { sql: "SELECT * FROM orders WHERE user_id = 7".to_string(), count: 40 },
+        QueryPattern { sql: "SELECT * FROM users WHERE id = 1".to_string(), count: 50 },
+    ];
+    let suggestions = advise(&db, &patterns).await.unwrap();
+
+    let status = suggestions
+        .iter()
+        .find(|s| s.table == "orders" && s.columns == ["status"])
+        .expect("an index on orders(status)");
+    assert_eq!(status.example_sql, "SELECT * FROM orders WHERE status = 'paid'");
+    assert!(status.query_count >= 12);
+    assert!(status.plan_before.iter().any(|line| line.contains("SCAN orders")));
+    assert!(status.plan_after.iter().any(|line| line.contains("USING INDEX")));
+    assert!(!suggestions.iter().any(|s| s.table == "users" || s.columns == ["user_id"]));
+
+    // The comparison does not leave the index behind
+    let indexes = db
+        .query("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'orders'", HashMap::new())
+        .await
+        .unwrap();
+    assert_eq!(indexes.rows.len(), 1);
+}
+
+#[tokio::test]
+async fn test_table_history_as_of() {
+    use futures::StreamExt;
+
+    let db = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    db.execute("CREATE TABLE prices (id INTEGER PRIMARY KEY, item TEXT, amount REAL)").await.unwrap();
+    db.execute("INSERT INTO prices (item, amount) VALUES ('tea', 2.5)").await.unwrap();
+    assert_eq!(db.enable_history("prices").await.unwrap(), 1);
+    assert!(matches!(db.enable_history("prices").await, Err(DatabaseError::TableAlreadyExists(_))));
+
+    let now = || chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+    let pause = || tokio::time::sleep(std::time::Duration::from_millis(20));
+    pause().await;
+    let before_changes = now();
+    pause().await;
+    db.execute("UPDATE prices SET amount = 3.0 WHERE item = 'tea'").await.unwrap();
+    db.execute("INSERT INTO prices (item, amount) VALUES ('coffee', 4.0)").await.unwrap();
+    pause().await;
+    let after_changes = now();
+    pause().await;
+    db.execute("DELETE FROM prices WHERE item = 'tea'").await.unwrap();
+
+    let amounts = |result: QueryResult| -> Vec<String> {
+        result.rows.iter().map(|row| format!("{:?}={:?}", row[0], row[1])).collect()
+    };
+    let as_of = |time: f64| {
+        let mut params = HashMap::new();
+        params.insert("at".to_string(), DbValue::Real(time));
+        db.query("SELECT item, amount FROM prices AS OF :at ORDER BY item", params)
+    };
+    assert_eq!(amounts(as_of(before_changes).await.unwrap()), vec!["Text(\"tea\")=Real(2.5)"]);
+    assert_eq!(
+        amounts(as_of(after_changes).await.unwrap()),
+        vec!["Text(\"coffee\")=Real(4.0)", "Text(\"tea\")=Real(3.0)"]
+    );
+    assert_eq!(amounts(as_of(now()).await.unwrap()), vec!["Text(\"coffee\")=Real(4.0)"]);
+
+    // ISO-8601 text, an alias, and a restore of a deleted row from its past version
+    let time = chrono::DateTime::from_timestamp_millis((after_changes * 1000.0) as i64).unwrap();
+    let sql = format!(
+        "SELECT p.amount FROM prices AS OF '{}' p WHERE p.item = 'tea'",
+        time.format("%Y-%m-%d %H:%M:%S%.3f")
+    );
+    assert!(matches!(db.query(&sql, HashMap::new()).await.unwrap().rows[0][0], DbValue::Real(a) if a == 3.0));
+    let sql = format!("INSERT INTO prices SELECT * FROM prices AS OF {} WHERE item = 'tea'", after_changes);
+    let (_, mut inserted) = db.query_stream(&sql, HashMap::new()).await.unwrap();
+    assert!(matches!(inserted.next().await.unwrap().unwrap()[0], DbValue::Integer(1)));
+    assert_eq!(db.query("SELECT * FROM prices", HashMap::new()).await.unwrap().rows.len(), 2);
+
+    let err = db.query("SELECT * FROM sqlite_master AS OF 0", HashMap::new()).await.unwrap_err();
+    assert!(err.to_string().contains("has no history"));
+}
+
+#[tokio::test]
+async fn test_undo_changes() {
+    let db = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    db.execute("CREATE TABLE prices (id INTEGER PRIMARY KEY, item TEXT UNIQUE, amount REAL)").await.unwrap();
+    db.execute("INSERT INTO prices (item, amount) VALUES ('tea', 2.5), ('milk', 1.0)").await.unwrap();
+    db.enable_history("prices").await.unwrap();
+    assert!(db.history_changes(None, 10).await.unwrap().is_empty());
+
+    let pause = || tokio::time::sleep(std::time::Duration::from_millis(10));
+    for sql in [
+        "INSERT INTO prices (item, amount) VALUES ('coffee', 4.0)",
+        "UPDATE prices SET amount = amount * 2",
+        "DELETE FROM prices WHERE item = 'milk'",
+    ] {
+        pause().await;
+        db.execute(sql).await.unwrap();
+    }
+    let prices = || async {
+        let result = db.query("SELECT item, amount FROM prices ORDER BY item", HashMap::new()).await.unwrap();
+        result.rows.iter().map(|row| format!("{:?}={:?}", row[0], row[1])).collect::<Vec<_>>()
+    };
+
+    let changes = db.history_changes(Some("prices"), 10).await.unwrap();
+    let counts: Vec<_> = changes.iter().map(|c| (c.inserted, c.updated, c.deleted)).collect();
+    assert_eq!(counts, vec![(0, 0, 1), (0, 3, 0), (1, 0, 0)]);
+
+    // A dry run only returns the statements
+    let last: Vec<_> = changes.iter().map(|c| (c.table.clone(), c.time)).collect();
+    let (_, statements) = db.undo_changes(&last[..1], true).await.unwrap();
+    assert_eq!(statements, vec!["INSERT INTO prices (rowid, id, item, amount) VALUES (2, 2, 'milk', 2.0)"]);
+    assert_eq!(prices().await.len(), 2);
+
+    // Undoing the delete and the update restores the rows as they were
+    let (undone, _) = db.undo_changes(&last[..2], false).await.unwrap();
+    assert_eq!(undone.len(), 2);
+    assert_eq!(prices().await, vec!["Text(\"coffee\")=Real(4.0)", "Text(\"milk\")=Real(1.0)", "Text(\"tea\")=Real(2.5)"]);
+
+    // The insert cannot be undone once its row has changed again
+    pause().await;
+    db.execute("UPDATE prices SET amount = 5.0 WHERE item = 'coffee'").await.unwrap();
+    let err = db.undo_changes(&last[2..], false).await.unwrap_err();
+    assert!(matches!(err, DatabaseError::TransactionError(_)), "{}", err);
+    assert_eq!(prices().await.len(), 3);
+
+    // Undos are recorded too, so they can be undone in turn
+    let latest = db.history_changes(None, 1).await.unwrap();
+    let (table, time) = history::parse_change_id(&latest[0].id()).unwrap();
+    db.undo_changes(&[(table, time)], false).await.unwrap();
+    assert_eq!(prices().await, vec!["Text(\"coffee\")=Real(4.0)", "Text(\"milk\")=Real(1.0)", "Text(\"tea\")=Real(2.5)"]);
+
+    let err = db.undo_changes(&[("prices".to_string(), 1.0)], false).await.unwrap_err();
+    assert!(err.to_string().contains("No change prices@1.000 found"), "{}", err);
+}
+
+#[tokio::test]
+async fn test_table_definition_for_copies() {
+    let source = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    source
+        .execute("CREATE TABLE \"order items\" (id INTEGER PRIMARY KEY, sku TEXT NOT NULL UNIQUE, qty INTEGER CHECK (qty > 0))")
+        .await
+        .unwrap();
+    source.execute("CREATE INDEX idx_order_items_qty ON \"order items\" (qty)").await.unwrap();
+
+    let same = source.table_definition("order items", "order items").await.unwrap();
+    assert_eq!(same.len(), 2);
+    assert!(same[1].starts_with("CREATE INDEX idx_order_items_qty"));
+
+    // A renamed copy keeps its constraints but not the index
+    let renamed = source.table_definition("order items", "items").await.unwrap();
+    assert_eq!(
+        renamed,
+        vec!["CREATE TABLE \"items\" (id INTEGER PRIMARY KEY, sku TEXT NOT NULL UNIQUE, qty INTEGER CHECK (qty > 0))"]
+    );
+    let target = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    for sql in &renamed {
+        target.execute(sql).await.unwrap();
+    }
+    let mut row = HashMap::new();
+    row.insert("sku".to_string(), DbValue::Text("a-1".to_string()));
+    row.insert("qty".to_string(), DbValue::Integer(0));
+    assert!(target.insert("items", row).await.unwrap_err().is_constraint_violation());
+
+    assert!(matches!(source.table_definition("missing", "missing").await, Err(DatabaseError::TableNotFound(_))));
+}
+
+#[tokio::test]
+async fn test_diff_table_between_databases() {
+    let source = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    let target = SqliteDatabase::connect("sqlite::memory:").await.unwrap();
+    for db in [&source, &target] {
+        db.execute("CREATE TABLE lines (sku TEXT COLLATE NOCASE, line INTEGER, qty INTEGER, PRIMARY KEY (sku, line))")
+            .await
+            .unwrap();
+        db.execute("INSERT INTO lines VALUES ('a', 1, 1), ('B', 1, 2), ('b', 2, 3), ('c', 1, 4)").await.unwrap();
+    }
+    target.execute("UPDATE lines SET qty = 30 WHERE sku = 'b' AND line = 2").await.unwrap();
+    target.execute("DELETE FROM lines WHERE sku = 'a'").await.unwrap();
+    target.execute("INSERT INTO lines VALUES ('d', 1, 5)").await.unwrap();
+
+    let result = diff::diff_table(&source, &target, "lines", &[], None, None, 10).await.unwrap();
+    assert_eq!(result.key_columns, vec!["sku", "line"]);
+    assert_eq!((result.added, result.removed, result.changed, result.unchanged), (1, 1, 1, 2));
+    // In key order, with 'B' before 'a' as bytes despite the NOCASE column
+    let changes: Vec<_> = result.rows.iter().map(|row| (row.change, format!("{:?}", row.key))).collect();
+    assert_eq!(
+        changes,
+        vec![
+            (diff::RowChange::Removed, "[Text(\"a\"), Integer(1)]".to_string()),
+            (diff::RowChange::Changed, "[Text(\"b\"), Integer(2)]".to_string()),
+            (diff::RowChange::Added, "[Text(\"d\"), Integer(1)]".to_string()),
+        ]
+    );
+    assert_eq!(result.rows[1].changed_columns, vec!["qty"]);
+
+    // A WHERE clause applies to both sides, so the changed row drops out of one; only the
+    // first differing rows are kept
+    let result = diff::diff_table(&source, &target, "lines", &["sku".to_string(), "line".to_string()], Some("qty < 5"), None, 1)
+        .await
+        .unwrap();
+    assert_eq!((result.added, result.removed, result.changed, result.rows.len()), (0, 2, 0, 1));
+
+    let err = diff::diff_table(&source, &target, "lines", &["id".to_string()], None, None, 10).await.unwrap_err();
+    assert!(err.to_string().contains("Key column 'id'"));
+}